@@ -0,0 +1,260 @@
+//! Cooperative task scheduler for UEFI applications.
+//!
+//! UEFI has no threads. Applications with several concurrent activities (UI, network download, disk write) can
+//! instead structure each activity as a task and let the [`Scheduler`] interleave them. Tasks are futures, or closures
+//! polled until they report completion, and yield control with [`yield_now`].
+//!
+//! Most UEFI completion sources (events, protocol completion tokens) are polled with `check_event` rather than waking
+//! tasks. When every task is waiting, the scheduler calls its idle hook, which is the place to pump those sources,
+//! and then polls every task again. The event and timer futures of `mu_rust_helpers` instead wake their task from
+//! the notification function of an event.
+//!
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, sync::Arc, task::Wake, vec::Vec};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+/// Scheduling priority of a task. Woken tasks with a higher priority are polled first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    /// Background work.
+    Low,
+    /// Default priority.
+    #[default]
+    Normal,
+    /// Latency-sensitive work, such as user interaction.
+    High,
+}
+
+struct TaskWaker {
+    woken: AtomicBool,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::SeqCst);
+    }
+}
+
+struct Task<'a> {
+    priority: Priority,
+    future: Pin<Box<dyn Future<Output = ()> + 'a>>,
+    waker: Arc<TaskWaker>,
+}
+
+/// Cooperative scheduler running tasks until all of them complete.
+///
+/// # Example
+/// ```
+/// use core::cell::RefCell;
+/// use executor::{yield_now, Priority, Scheduler};
+///
+/// let log = RefCell::new(Vec::new());
+/// let mut scheduler = Scheduler::new();
+/// scheduler.spawn(Priority::Normal, async {
+///     log.borrow_mut().push("download 1");
+///     yield_now().await;
+///     log.borrow_mut().push("download 2");
+/// });
+/// scheduler.spawn(Priority::Normal, async {
+///     log.borrow_mut().push("ui 1");
+///     yield_now().await;
+///     log.borrow_mut().push("ui 2");
+/// });
+/// scheduler.run();
+///
+/// assert_eq!(*log.borrow(), ["download 1", "ui 1", "download 2", "ui 2"]);
+/// ```
+pub struct Scheduler<'a> {
+    tasks: Vec<Task<'a>>,
+    idle: Option<Box<dyn FnMut() + 'a>>,
+}
+
+impl<'a> Scheduler<'a> {
+    /// Create a scheduler with no tasks.
+    pub fn new() -> Self {
+        Self { tasks: Vec::new(), idle: None }
+    }
+
+    /// Add a task running `future` to completion.
+    pub fn spawn(&mut self, priority: Priority, future: impl Future<Output = ()> + 'a) {
+        let waker = Arc::new(TaskWaker { woken: AtomicBool::new(true) });
+        let task = Task { priority, future: Box::pin(future), waker };
+        // Keep the tasks sorted by decreasing priority, in spawn order within a priority.
+        let index = self.tasks.partition_point(|other| other.priority >= priority);
+        self.tasks.insert(index, task);
+    }
+
+    /// Add a task calling `step` once per scheduling round until it returns `Poll::Ready`.
+    pub fn spawn_fn(&mut self, priority: Priority, mut step: impl FnMut() -> Poll<()> + 'a) {
+        self.spawn(priority, async move {
+            while step().is_pending() {
+                yield_now().await;
+            }
+        });
+    }
+
+    /// Set the function called when no task is ready to run, e.g. to pump `check_event` or wait for an event.
+    pub fn set_idle(&mut self, idle: impl FnMut() + 'a) {
+        self.idle = Some(Box::new(idle));
+    }
+
+    /// Return the number of tasks that have not completed.
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Return true if every task has completed.
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Poll every woken task once, in priority order. Returns true if any task was polled.
+    pub fn run_once(&mut self) -> bool {
+        let mut polled = false;
+        let mut index = 0;
+        while index < self.tasks.len() {
+            let task = &mut self.tasks[index];
+            if !task.waker.woken.swap(false, Ordering::SeqCst) {
+                index += 1;
+                continue;
+            }
+            polled = true;
+            let waker = Waker::from(task.waker.clone());
+            match task.future.as_mut().poll(&mut Context::from_waker(&waker)) {
+                Poll::Ready(()) => drop(self.tasks.remove(index)),
+                Poll::Pending => index += 1,
+            }
+        }
+        polled
+    }
+
+    /// Run every task to completion.
+    pub fn run(&mut self) {
+        while !self.tasks.is_empty() {
+            if self.run_once() {
+                continue;
+            }
+            if let Some(idle) = self.idle.as_mut() {
+                idle();
+            }
+            // Completion sources are usually polled rather than waking tasks, so poll every task after idling.
+            for task in &self.tasks {
+                task.waker.woken.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+impl Default for Scheduler<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Future that yields control to the scheduler once, letting other tasks run.
+pub struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.yielded {
+            return Poll::Ready(());
+        }
+        self.yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// Yield control to the scheduler, letting other tasks run before this one continues.
+pub fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::cell::{Cell, RefCell};
+
+    #[test]
+    fn test_tasks_interleave() {
+        let log = RefCell::new(Vec::new());
+        let mut scheduler = Scheduler::new();
+        for name in ["a", "b"] {
+            let log = &log;
+            scheduler.spawn(Priority::Normal, async move {
+                for step in 0..3 {
+                    log.borrow_mut().push((name, step));
+                    yield_now().await;
+                }
+            });
+        }
+        assert_eq!(scheduler.len(), 2);
+        scheduler.run();
+
+        assert!(scheduler.is_empty());
+        assert_eq!(*log.borrow(), [("a", 0), ("b", 0), ("a", 1), ("b", 1), ("a", 2), ("b", 2)]);
+    }
+
+    #[test]
+    fn test_priorities() {
+        let log = RefCell::new(Vec::new());
+        let mut scheduler = Scheduler::new();
+        scheduler.spawn(Priority::Low, async { log.borrow_mut().push("low") });
+        scheduler.spawn(Priority::Normal, async { log.borrow_mut().push("normal") });
+        scheduler.spawn(Priority::High, async { log.borrow_mut().push("high 1") });
+        scheduler.spawn(Priority::High, async { log.borrow_mut().push("high 2") });
+        scheduler.run();
+
+        assert_eq!(*log.borrow(), ["high 1", "high 2", "normal", "low"]);
+    }
+
+    #[test]
+    fn test_closure_tasks_and_idle() {
+        let remaining = Cell::new(3);
+        let idle_calls = Cell::new(0);
+        let signaled = Cell::new(false);
+        let mut scheduler = Scheduler::new();
+
+        scheduler.spawn_fn(Priority::Normal, || {
+            remaining.set(remaining.get() - 1);
+            if remaining.get() == 0 {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        });
+        // A future waiting on a polled completion source never wakes itself, so it relies on the idle hook.
+        scheduler.spawn(
+            Priority::Normal,
+            core::future::poll_fn(|_| match signaled.get() {
+                true => Poll::Ready(()),
+                false => Poll::Pending,
+            }),
+        );
+        scheduler.set_idle(|| {
+            idle_calls.set(idle_calls.get() + 1);
+            signaled.set(idle_calls.get() == 2);
+        });
+        scheduler.run();
+
+        assert_eq!(remaining.get(), 0);
+        assert_eq!(idle_calls.get(), 2);
+    }
+}
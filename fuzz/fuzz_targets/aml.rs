@@ -0,0 +1,3 @@
+#![no_main]
+
+libfuzzer_sys::fuzz_target!(|input: mu_rust_helpers::fuzz::AmlInput| mu_rust_helpers::fuzz::aml(input));
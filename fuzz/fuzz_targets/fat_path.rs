@@ -0,0 +1,3 @@
+#![no_main]
+
+libfuzzer_sys::fuzz_target!(|input: mu_rust_helpers::fuzz::FatPathInput| mu_rust_helpers::fuzz::fat_path(input));
@@ -0,0 +1,3 @@
+#![no_main]
+
+libfuzzer_sys::fuzz_target!(|record: mu_rust_helpers::user_auth::UserInfoRecord| mu_rust_helpers::fuzz::user_info_record_encode(record));
@@ -28,6 +28,20 @@ macro_rules! guid_to_uuid {
     };
 }
 
+/// Length of the string form of a GUID, e.g. `434F695C-EF26-4A12-9EBA-DDEF0097497C`.
+pub const GUID_STR_LEN: usize = 36;
+
+/// Format `guid` in `buffer` as an uppercase string, without allocating.
+pub fn to_str<'a>(guid: &efi::Guid, buffer: &'a mut [u8; GUID_STR_LEN]) -> &'a str {
+    guid_to_uuid!(guid).hyphenated().encode_upper(buffer)
+}
+
+/// Parse the string form of a GUID, in upper or lower case, without allocating.
+pub fn from_str(guid_str: &str) -> Option<efi::Guid> {
+    let uuid = uuid::Uuid::try_parse(guid_str).ok()?;
+    Some(efi::Guid::from_bytes(&uuid.to_bytes_le()))
+}
+
 const ZERO_GUID_STR: &str = "00000000-0000-0000-0000-000000000000";
 
 pub const ZERO: efi::Guid = guid!(ZERO_GUID_STR);
@@ -45,7 +59,7 @@ mod tests {
     use r_efi::efi;
     use uuid::uuid;
 
-    use crate::{CALLER_ID, ZERO, ZERO_GUID_STR};
+    use crate::{from_str, to_str, CALLER_ID, GUID_STR_LEN, ZERO, ZERO_GUID_STR};
 
     const MS_WHEA_RSC_DATA_TYPE_GUID_FROM_MACRO: efi::Guid = guid!("91DEEA05-8C0A-4DCD-B91E-F21CA0C68405");
     const ADVANCED_LOGGER_PROTOCOL_GUID_FROM_MACRO: efi::Guid = guid!("434F695C-EF26-4A12-9EBA-DDEF0097497C");
@@ -74,6 +88,18 @@ mod tests {
         println!("Print GUID as string: {}", guid_fmt!(ADVANCED_LOGGER_PROTOCOL_GUID_FROM_FIELDS));
     }
 
+    #[test]
+    fn test_guid_str_buffer() {
+        let mut buffer = [0; GUID_STR_LEN];
+        assert_eq!(
+            to_str(&ADVANCED_LOGGER_PROTOCOL_GUID_FROM_FIELDS, &mut buffer),
+            "434F695C-EF26-4A12-9EBA-DDEF0097497C"
+        );
+        assert_eq!(from_str("434f695c-ef26-4a12-9eba-ddef0097497c"), Some(ADVANCED_LOGGER_PROTOCOL_GUID_FROM_FIELDS));
+        assert_eq!(from_str(ZERO_GUID_STR), Some(ZERO));
+        assert_eq!(from_str("434F695C-EF26-4A12-9EBA"), None);
+    }
+
     #[test]
     fn test_guid_to_uuid_macro() {
         assert_eq!(
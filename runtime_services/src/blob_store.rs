@@ -0,0 +1,405 @@
+//! Blobs stored across multiple variables.
+//!
+//! Variable stores limit the size of a single variable, often to a few tens of KiB, while platforms regularly need to
+//! persist much larger blobs. [`BlobStore`] splits the data into numbered chunk variables and records their layout in
+//! a manifest variable along with a CRC32 of the data, which is checked when the blob is read back.
+//!
+//! The chunks of a blob named `Blob` are stored as `Blob0000`, `Blob0001` and so on, in the same namespace.
+//!
+use alloc::{vec, vec::Vec};
+use core::mem;
+
+use r_efi::efi;
+
+use crate::{read_variable, RuntimeServices, VariableAttributes};
+
+/// Chunk size used unless [`BlobStore::chunk_size`] is called.
+pub const DEFAULT_CHUNK_SIZE: usize = 0x4000;
+
+const MAX_CHUNKS: usize = 0x10000;
+
+/// Manifest variable describing a blob.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Manifest {
+    signature: u32,
+    revision: u32,
+    total_size: u32,
+    chunk_size: u32,
+    chunk_count: u32,
+    crc32: u32,
+}
+
+impl Manifest {
+    const SIGNATURE: u32 = u32::from_le_bytes(*b"MUBS");
+    const REVISION: u32 = 1;
+
+    fn to_bytes(self) -> [u8; mem::size_of::<Self>()] {
+        let fields = [self.signature, self.revision, self.total_size, self.chunk_size, self.chunk_count, self.crc32];
+        let mut bytes = [0; mem::size_of::<Self>()];
+        for (chunk, field) in bytes.chunks_exact_mut(4).zip(fields) {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, efi::Status> {
+        if bytes.len() != mem::size_of::<Self>() {
+            return Err(efi::Status::COMPROMISED_DATA);
+        }
+        let mut fields = bytes.chunks_exact(4).map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()));
+        let mut next = || fields.next().unwrap();
+        let manifest = Self {
+            signature: next(),
+            revision: next(),
+            total_size: next(),
+            chunk_size: next(),
+            chunk_count: next(),
+            crc32: next(),
+        };
+        if manifest.signature != Self::SIGNATURE || manifest.revision != Self::REVISION {
+            return Err(efi::Status::INCOMPATIBLE_VERSION);
+        }
+        let chunk_size = manifest.chunk_size as usize;
+        if chunk_size == 0 || (manifest.total_size as usize).div_ceil(chunk_size) != manifest.chunk_count as usize {
+            return Err(efi::Status::COMPROMISED_DATA);
+        }
+        Ok(manifest)
+    }
+}
+
+/// Blob stored across a manifest variable and numbered chunk variables.
+///
+/// # Example
+/// ```no_run
+/// use r_efi::efi;
+/// use runtime_services::{blob_store::BlobStore, StandardRuntimeServices, VariableAttributes};
+///
+/// const CRASH_DUMP_NAMESPACE: efi::Guid =
+///     efi::Guid::from_fields(0x2d7e6f41, 0x93c2, 0x4a1b, 0x8e, 0x5d, &[0x61, 0x0f, 0x3c, 0x9a, 0x72, 0xb4]);
+///
+/// fn save_crash_dump(runtime_services: &StandardRuntimeServices, dump: &[u8]) -> Result<(), efi::Status> {
+///     // "Dump" as a null-terminated UCS-2 string.
+///     let name = [0x44, 0x75, 0x6D, 0x70, 0x00];
+///     BlobStore::new(runtime_services, &name, &CRASH_DUMP_NAMESPACE)
+///         .attributes(VariableAttributes::NV_BS_RT)
+///         .write(dump)
+/// }
+/// ```
+pub struct BlobStore<'a, R: RuntimeServices> {
+    runtime_services: &'a R,
+    name: &'a [u16],
+    namespace: &'a efi::Guid,
+    attributes: VariableAttributes,
+    chunk_size: usize,
+}
+
+impl<'a, R: RuntimeServices> BlobStore<'a, R> {
+    /// Create a store for the blob `name` in the `namespace` vendor GUID, written as non-volatile boot service
+    /// variables.
+    ///
+    /// `name` must be a null-terminated UCS-2 string.
+    pub fn new(runtime_services: &'a R, name: &'a [u16], namespace: &'a efi::Guid) -> Self {
+        Self {
+            runtime_services,
+            name,
+            namespace,
+            attributes: VariableAttributes::NV_BS,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    /// Set the attributes of the variables written by [`Self::write`]. Empty attributes delete variables instead.
+    pub fn attributes(mut self, attributes: VariableAttributes) -> Self {
+        self.attributes = attributes;
+        self
+    }
+
+    /// Set the maximum size of each chunk variable written by [`Self::write`]. Zero is treated as one.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Write `data`, replacing the previous blob.
+    ///
+    /// The chunks are written before the manifest, so an interrupted write leaves the previous blob unreadable rather
+    /// than silently corrupted. Returns `efi::Status::INVALID_PARAMETER` if the name is not null-terminated and
+    /// `efi::Status::OUT_OF_RESOURCES` if `data` needs more than 65536 chunks.
+    pub fn write(&self, data: &[u8]) -> Result<(), efi::Status> {
+        let chunk_count = data.len().div_ceil(self.chunk_size);
+        if chunk_count > MAX_CHUNKS || data.len() > u32::MAX as usize || self.chunk_size > u32::MAX as usize {
+            return Err(efi::Status::OUT_OF_RESOURCES);
+        }
+        let previous_count = self.manifest().map(|manifest| manifest.chunk_count as usize);
+
+        for (index, chunk) in data.chunks(self.chunk_size).enumerate() {
+            let name = self.chunk_name(index)?;
+            self.runtime_services.set_variable_bytes(&name, self.namespace, self.attributes, chunk)?;
+        }
+        let manifest = Manifest {
+            signature: Manifest::SIGNATURE,
+            revision: Manifest::REVISION,
+            total_size: data.len() as u32,
+            chunk_size: self.chunk_size as u32,
+            chunk_count: chunk_count as u32,
+            crc32: crc32(data),
+        };
+        self.runtime_services.set_variable_bytes(self.name, self.namespace, self.attributes, &manifest.to_bytes())?;
+
+        // Chunks of a larger previous blob are no longer referenced. Without a readable manifest, e.g. after an
+        // interrupted write, the stale chunks are found by deleting them in order until one is missing.
+        match previous_count {
+            Ok(previous_count) => self.delete_chunks(chunk_count..previous_count),
+            Err(_) => self.delete_stale_chunks(chunk_count),
+        }
+    }
+
+    /// Read the blob back.
+    ///
+    /// Returns `efi::Status::NOT_FOUND` if there is no blob, `efi::Status::INCOMPATIBLE_VERSION` if the manifest was
+    /// written by an unknown revision, `efi::Status::COMPROMISED_DATA` if the chunks do not match the manifest and
+    /// `efi::Status::CRC_ERROR` if the data does not match its CRC32.
+    pub fn read(&self) -> Result<Vec<u8>, efi::Status> {
+        let manifest = self.manifest()?;
+        let mut data = Vec::with_capacity(manifest.total_size as usize);
+        for index in 0..manifest.chunk_count as usize {
+            let name = self.chunk_name(index)?;
+            let (chunk, _) = read_variable(self.runtime_services, &name, self.namespace).map_err(|status| {
+                if status == efi::Status::NOT_FOUND {
+                    efi::Status::COMPROMISED_DATA
+                } else {
+                    status
+                }
+            })?;
+            let is_last = index + 1 == manifest.chunk_count as usize;
+            if chunk.len() > manifest.chunk_size as usize || (!is_last && chunk.len() != manifest.chunk_size as usize) {
+                return Err(efi::Status::COMPROMISED_DATA);
+            }
+            data.extend_from_slice(&chunk);
+        }
+        if data.len() != manifest.total_size as usize {
+            return Err(efi::Status::COMPROMISED_DATA);
+        }
+        if crc32(&data) != manifest.crc32 {
+            return Err(efi::Status::CRC_ERROR);
+        }
+        Ok(data)
+    }
+
+    /// Delete the blob.
+    ///
+    /// The manifest is deleted first, so a partially deleted blob is never read back. Returns
+    /// `efi::Status::NOT_FOUND` if there is no blob.
+    pub fn delete(&self) -> Result<(), efi::Status> {
+        let manifest = self.manifest()?;
+        self.runtime_services.set_variable_bytes(self.name, self.namespace, self.attributes, &[])?;
+        self.delete_chunks(0..manifest.chunk_count as usize)
+    }
+
+    fn manifest(&self) -> Result<Manifest, efi::Status> {
+        let (bytes, _) = read_variable(self.runtime_services, self.name, self.namespace)?;
+        Manifest::from_bytes(&bytes)
+    }
+
+    fn delete_chunks(&self, chunks: core::ops::Range<usize>) -> Result<(), efi::Status> {
+        for index in chunks {
+            let name = self.chunk_name(index)?;
+            match self.runtime_services.set_variable_bytes(&name, self.namespace, self.attributes, &[]) {
+                Ok(()) | Err(efi::Status::NOT_FOUND) => (),
+                Err(status) => return Err(status),
+            }
+        }
+        Ok(())
+    }
+
+    fn delete_stale_chunks(&self, first: usize) -> Result<(), efi::Status> {
+        for index in first..MAX_CHUNKS {
+            let name = self.chunk_name(index)?;
+            match self.runtime_services.set_variable_bytes(&name, self.namespace, self.attributes, &[]) {
+                Ok(()) => (),
+                Err(efi::Status::NOT_FOUND) => break,
+                Err(status) => return Err(status),
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the null-terminated name of the chunk at `index`: the blob name followed by 4 hexadecimal digits.
+    fn chunk_name(&self, index: usize) -> Result<Vec<u16>, efi::Status> {
+        let Some((0, name)) = self.name.split_last() else {
+            return Err(efi::Status::INVALID_PARAMETER);
+        };
+        let mut chunk_name = vec![0; name.len() + 5];
+        chunk_name[..name.len()].copy_from_slice(name);
+        for (digit, c) in chunk_name[name.len()..name.len() + 4].iter_mut().enumerate() {
+            let nibble = (index >> (4 * (3 - digit))) & 0xf;
+            *c = u16::from(b"0123456789ABCDEF"[nibble]);
+        }
+        Ok(chunk_name)
+    }
+}
+
+/// CRC32 (IEEE 802.3), as computed by the CalculateCrc32 boot service, which is not available at runtime.
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ u32::from(byte), |crc, _| (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::{ffi::c_void, ptr, slice};
+    use std::{cell::RefCell, collections::BTreeMap};
+
+    use crate::{tests::mock_efi_runtime_services, StandardRuntimeServices};
+
+    const TEST_NAMESPACE: efi::Guid =
+        efi::Guid::from_fields(0x1f0e2d3c, 0x4b5a, 0x4968, 0x87, 0x76, &[0x65, 0x54, 0x43, 0x32, 0x21, 0x10]);
+
+    // "Blob" as a null-terminated UCS-2 string.
+    const TEST_NAME: [u16; 5] = [0x42, 0x6c, 0x6f, 0x62, 0x00];
+
+    std::thread_local! {
+        static STORE: RefCell<BTreeMap<Vec<u16>, Vec<u8>>> = const { RefCell::new(BTreeMap::new()) };
+    }
+
+    unsafe fn name_from_ptr(name: *const u16) -> Vec<u16> {
+        let len = (0..).position(|i| *name.add(i) == 0).unwrap();
+        slice::from_raw_parts(name, len + 1).to_vec()
+    }
+
+    extern "efiapi" fn get_variable(
+        name: *mut u16,
+        _namespace: *mut efi::Guid,
+        attributes: *mut u32,
+        data_size: *mut usize,
+        data: *mut c_void,
+    ) -> efi::Status {
+        let name = unsafe { name_from_ptr(name) };
+        STORE.with(|store| match store.borrow().get(&name) {
+            None => efi::Status::NOT_FOUND,
+            Some(value) if unsafe { *data_size } < value.len() => {
+                unsafe { *data_size = value.len() };
+                efi::Status::BUFFER_TOO_SMALL
+            }
+            Some(value) => {
+                unsafe {
+                    ptr::copy_nonoverlapping(value.as_ptr(), data as *mut u8, value.len());
+                    *data_size = value.len();
+                    if !attributes.is_null() {
+                        *attributes = 0;
+                    }
+                }
+                efi::Status::SUCCESS
+            }
+        })
+    }
+
+    extern "efiapi" fn set_variable(
+        name: *mut u16,
+        namespace: *mut efi::Guid,
+        attributes: u32,
+        data_size: usize,
+        data: *mut c_void,
+    ) -> efi::Status {
+        assert_eq!(unsafe { *namespace }, TEST_NAMESPACE);
+        let name = unsafe { name_from_ptr(name) };
+        STORE.with(|store| {
+            let mut store = store.borrow_mut();
+            // Like firmware, a write without data or without attributes deletes the variable.
+            if data_size == 0 || attributes == 0 {
+                return match store.remove(&name) {
+                    Some(_) => efi::Status::SUCCESS,
+                    None => efi::Status::NOT_FOUND,
+                };
+            }
+            let value = unsafe { slice::from_raw_parts(data as *const u8, data_size) }.to_vec();
+            store.insert(name, value);
+            efi::Status::SUCCESS
+        })
+    }
+
+    fn stored_names() -> Vec<String> {
+        STORE
+            .with(|store| store.borrow().keys().map(|name| String::from_utf16_lossy(&name[..name.len() - 1])).collect())
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn test_write_read_delete() {
+        let efi_runtime_services = efi::RuntimeServices { get_variable, set_variable, ..mock_efi_runtime_services() };
+        let runtime_services = StandardRuntimeServices::new(&efi_runtime_services);
+        let store = BlobStore::new(&runtime_services, &TEST_NAME, &TEST_NAMESPACE).chunk_size(16);
+        assert_eq!(store.read(), Err(efi::Status::NOT_FOUND));
+
+        let data: Vec<u8> = (0..40).collect();
+        store.write(&data).unwrap();
+        assert_eq!(stored_names(), ["Blob", "Blob0000", "Blob0001", "Blob0002"]);
+        assert_eq!(store.read().unwrap(), data);
+
+        // A smaller blob removes the chunks it no longer uses.
+        store.write(&data[..10]).unwrap();
+        assert_eq!(stored_names(), ["Blob", "Blob0000"]);
+        assert_eq!(store.read().unwrap(), &data[..10]);
+
+        store.delete().unwrap();
+        assert!(stored_names().is_empty());
+        assert_eq!(store.delete(), Err(efi::Status::NOT_FOUND));
+
+        store.write(&[]).unwrap();
+        assert_eq!(store.read().unwrap(), []);
+        store.delete().unwrap();
+
+        // Writing without attributes deletes rather than stores.
+        let volatile =
+            BlobStore::new(&runtime_services, &TEST_NAME, &TEST_NAMESPACE).attributes(VariableAttributes::empty());
+        store.write(&data).unwrap();
+        volatile.write(&data[..10]).unwrap();
+        assert!(stored_names().is_empty());
+    }
+
+    #[test]
+    fn test_stale_chunks_without_manifest() {
+        let efi_runtime_services = efi::RuntimeServices { get_variable, set_variable, ..mock_efi_runtime_services() };
+        let runtime_services = StandardRuntimeServices::new(&efi_runtime_services);
+        let store = BlobStore::new(&runtime_services, &TEST_NAME, &TEST_NAMESPACE).chunk_size(16);
+        let data: Vec<u8> = (0..40).collect();
+        store.write(&data).unwrap();
+
+        // An unreadable manifest, e.g. after an interrupted write, still leads to the stale chunks being deleted.
+        STORE.with(|store| store.borrow_mut().get_mut(&TEST_NAME.to_vec()).unwrap()[0] ^= 0xff);
+        store.write(&data[..10]).unwrap();
+        assert_eq!(stored_names(), ["Blob", "Blob0000"]);
+        assert_eq!(store.read().unwrap(), &data[..10]);
+        store.delete().unwrap();
+    }
+
+    #[test]
+    fn test_corruption() {
+        let efi_runtime_services = efi::RuntimeServices { get_variable, set_variable, ..mock_efi_runtime_services() };
+        let runtime_services = StandardRuntimeServices::new(&efi_runtime_services);
+        let store = BlobStore::new(&runtime_services, &TEST_NAME, &TEST_NAMESPACE).chunk_size(8);
+        store.write(b"abcdefghijklmnopqrst").unwrap();
+
+        let chunk_name = store.chunk_name(1).unwrap();
+        STORE.with(|store| store.borrow_mut().get_mut(&chunk_name).unwrap()[0] ^= 0xff);
+        assert_eq!(store.read(), Err(efi::Status::CRC_ERROR));
+
+        STORE.with(|store| store.borrow_mut().remove(&chunk_name));
+        assert_eq!(store.read(), Err(efi::Status::COMPROMISED_DATA));
+
+        STORE.with(|store| store.borrow_mut().get_mut(&TEST_NAME.to_vec()).unwrap()[4] = 2);
+        assert_eq!(store.read(), Err(efi::Status::INCOMPATIBLE_VERSION));
+
+        let unterminated = BlobStore::new(&runtime_services, &TEST_NAME[..4], &TEST_NAMESPACE);
+        assert_eq!(unterminated.write(b"data"), Err(efi::Status::INVALID_PARAMETER));
+    }
+}
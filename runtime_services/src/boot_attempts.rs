@@ -0,0 +1,204 @@
+//! Counting of failed boot attempts.
+//!
+//! Resilient firmware falls back to a recovery or alternate (A/B) boot path when the normal path keeps failing before
+//! the OS loader is reached, e.g. because of a hang caught by the watchdog. [`BootAttemptTracker`] keeps the number of
+//! unfinished attempts in a non-volatile variable: each boot increments it early with
+//! [`BootAttemptTracker::begin_attempt`], and decrements it with [`BootAttemptTracker::boot_succeeded`] when it reaches
+//! ReadyToBoot cleanly. A boot that fails in between leaves the count incremented, and once it exceeds the allowed
+//! number of attempts, [`BootAttemptTracker::should_enter_recovery`] returns true.
+//!
+//! `boot_succeeded` is meant to be called from a notification of the `EFI_EVENT_GROUP_READY_TO_BOOT` event group,
+//! which must be created with the boot services.
+//!
+use r_efi::efi;
+
+use crate::{RuntimeServices, VariableAttributes};
+
+/// Tracker of unfinished boot attempts, kept in a variable.
+///
+/// # Example
+/// ```no_run
+/// use r_efi::efi;
+/// use runtime_services::{boot_attempts::BootAttemptTracker, StandardRuntimeServices};
+///
+/// const PLATFORM_NAMESPACE: efi::Guid =
+///     efi::Guid::from_fields(0x8c4d1e92, 0x5b7a, 0x4f03, 0x9a, 0x61, &[0x2e, 0xd8, 0x47, 0x10, 0xbc, 0x35]);
+///
+/// fn select_boot_path(runtime_services: &StandardRuntimeServices) -> Result<bool, efi::Status> {
+///     // "BootAttempts" as a null-terminated UCS-2 string.
+///     let name = [0x42, 0x6F, 0x6F, 0x74, 0x41, 0x74, 0x74, 0x65, 0x6D, 0x70, 0x74, 0x73, 0x00];
+///     let tracker = BootAttemptTracker::new(runtime_services, &name, &PLATFORM_NAMESPACE, 3);
+///     tracker.begin_attempt()?;
+///     tracker.should_enter_recovery()
+/// }
+/// ```
+pub struct BootAttemptTracker<'a, R: RuntimeServices> {
+    runtime_services: &'a R,
+    name: &'a [u16],
+    namespace: &'a efi::Guid,
+    max_attempts: u32,
+    attributes: VariableAttributes,
+}
+
+impl<'a, R: RuntimeServices> BootAttemptTracker<'a, R> {
+    /// Create a tracker keeping its count in the variable `name` of the `namespace` vendor GUID, allowing
+    /// `max_attempts` unfinished attempts before recovery is requested.
+    ///
+    /// `name` must be a null-terminated UCS-2 string. The variable is written as [`VariableAttributes::NV_BS`] unless
+    /// [`Self::attributes`] is called.
+    pub fn new(runtime_services: &'a R, name: &'a [u16], namespace: &'a efi::Guid, max_attempts: u32) -> Self {
+        Self { runtime_services, name, namespace, max_attempts, attributes: VariableAttributes::NV_BS }
+    }
+
+    /// Set the attributes of the counter variable, e.g. to make it readable at runtime.
+    pub fn attributes(mut self, attributes: VariableAttributes) -> Self {
+        self.attributes = attributes;
+        self
+    }
+
+    /// Return the number of unfinished attempts, zero if the counter does not exist.
+    ///
+    /// Returns `efi::Status::BAD_BUFFER_SIZE` if the variable does not hold a counter.
+    pub fn attempts(&self) -> Result<u32, efi::Status> {
+        match self.runtime_services.get_variable::<u32>(self.name, self.namespace) {
+            Ok((attempts, _)) => Ok(attempts),
+            Err(efi::Status::NOT_FOUND) => Ok(0),
+            Err(status) => Err(status),
+        }
+    }
+
+    /// Record the start of a boot attempt, returning the number of unfinished attempts including this one.
+    pub fn begin_attempt(&self) -> Result<u32, efi::Status> {
+        let attempts = self.attempts()?.saturating_add(1);
+        self.store(attempts)?;
+        Ok(attempts)
+    }
+
+    /// Record that the current attempt reached ReadyToBoot, returning the number of unfinished attempts left.
+    ///
+    /// The count is decremented rather than cleared, so a platform that alternates between failing and succeeding
+    /// boots still ends up in recovery.
+    pub fn boot_succeeded(&self) -> Result<u32, efi::Status> {
+        let attempts = self.attempts()?.saturating_sub(1);
+        self.store(attempts)?;
+        Ok(attempts)
+    }
+
+    /// Return true if more than the allowed number of attempts are unfinished.
+    pub fn should_enter_recovery(&self) -> Result<bool, efi::Status> {
+        Ok(self.attempts()? > self.max_attempts)
+    }
+
+    /// Forget all attempts, e.g. once recovery completed.
+    pub fn reset(&self) -> Result<(), efi::Status> {
+        self.store(0)
+    }
+
+    /// Write `attempts`, deleting the variable when it drops to zero.
+    fn store(&self, attempts: u32) -> Result<(), efi::Status> {
+        if attempts == 0 {
+            return match self.runtime_services.set_variable_bytes(self.name, self.namespace, self.attributes, &[]) {
+                Ok(()) | Err(efi::Status::NOT_FOUND) => Ok(()),
+                Err(status) => Err(status),
+            };
+        }
+        self.runtime_services.set_variable(self.name, self.namespace, self.attributes, &attempts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::{ffi::c_void, ptr, slice};
+    use std::cell::RefCell;
+
+    use crate::{tests::mock_efi_runtime_services, StandardRuntimeServices};
+
+    const TEST_NAMESPACE: efi::Guid =
+        efi::Guid::from_fields(0x6a2b3c4d, 0x5e6f, 0x4a0b, 0x9c, 0x1d, &[0x2e, 0x3f, 0x40, 0x51, 0x62, 0x73]);
+
+    // "Tries" as a null-terminated UCS-2 string.
+    const TEST_NAME: [u16; 6] = [0x54, 0x72, 0x69, 0x65, 0x73, 0x00];
+
+    std::thread_local! {
+        static COUNTER: RefCell<Option<(u32, Vec<u8>)>> = const { RefCell::new(None) };
+    }
+
+    extern "efiapi" fn get_variable(
+        _name: *mut u16,
+        _namespace: *mut efi::Guid,
+        attributes: *mut u32,
+        data_size: *mut usize,
+        data: *mut c_void,
+    ) -> efi::Status {
+        COUNTER.with(|counter| match &*counter.borrow() {
+            None => efi::Status::NOT_FOUND,
+            Some((_, value)) if unsafe { *data_size } < value.len() => {
+                unsafe { *data_size = value.len() };
+                efi::Status::BUFFER_TOO_SMALL
+            }
+            Some((stored_attributes, value)) => {
+                unsafe {
+                    ptr::copy_nonoverlapping(value.as_ptr(), data as *mut u8, value.len());
+                    *data_size = value.len();
+                    *attributes = *stored_attributes;
+                }
+                efi::Status::SUCCESS
+            }
+        })
+    }
+
+    extern "efiapi" fn set_variable(
+        _name: *mut u16,
+        namespace: *mut efi::Guid,
+        attributes: u32,
+        data_size: usize,
+        data: *mut c_void,
+    ) -> efi::Status {
+        assert_eq!(unsafe { *namespace }, TEST_NAMESPACE);
+        COUNTER.with(|counter| {
+            let mut counter = counter.borrow_mut();
+            if data_size == 0 {
+                return if counter.take().is_some() { efi::Status::SUCCESS } else { efi::Status::NOT_FOUND };
+            }
+            *counter = Some((attributes, unsafe { slice::from_raw_parts(data as *const u8, data_size) }.to_vec()));
+            efi::Status::SUCCESS
+        })
+    }
+
+    #[test]
+    fn test_attempts() {
+        let efi_runtime_services = efi::RuntimeServices { get_variable, set_variable, ..mock_efi_runtime_services() };
+        let runtime_services = StandardRuntimeServices::new(&efi_runtime_services);
+        let tracker = BootAttemptTracker::new(&runtime_services, &TEST_NAME, &TEST_NAMESPACE, 2);
+        assert_eq!(tracker.attempts(), Ok(0));
+
+        // A clean boot leaves no trace.
+        assert_eq!(tracker.begin_attempt(), Ok(1));
+        assert_eq!(tracker.boot_succeeded(), Ok(0));
+        assert!(COUNTER.with(|counter| counter.borrow().is_none()));
+
+        // Failed boots accumulate until recovery is requested.
+        for attempts in 1..=2 {
+            assert_eq!(tracker.begin_attempt(), Ok(attempts));
+            assert_eq!(tracker.should_enter_recovery(), Ok(false));
+        }
+        assert_eq!(tracker.begin_attempt(), Ok(3));
+        assert_eq!(tracker.should_enter_recovery(), Ok(true));
+        assert_eq!(
+            COUNTER.with(|counter| counter.borrow().clone()),
+            Some((VariableAttributes::NV_BS.bits(), 3u32.to_le_bytes().to_vec()))
+        );
+
+        // Success only takes back the current attempt.
+        assert_eq!(tracker.boot_succeeded(), Ok(2));
+        tracker.reset().unwrap();
+        assert_eq!(tracker.attempts(), Ok(0));
+        tracker.reset().unwrap();
+        assert_eq!(tracker.boot_succeeded(), Ok(0));
+
+        COUNTER.with(|counter| *counter.borrow_mut() = Some((0, vec![1])));
+        assert_eq!(tracker.begin_attempt(), Err(efi::Status::BAD_BUFFER_SIZE));
+    }
+}
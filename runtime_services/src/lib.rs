@@ -0,0 +1,1035 @@
+//! UEFI runtime services support.
+//!
+//! [`StandardRuntimeServices`] wraps the firmware-provided `efi::RuntimeServices` table and implements
+//! [`RuntimeServices`], which exposes safe, typed wrappers over the raw services.
+//!
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+pub mod blob_store;
+pub mod boot_attempts;
+pub mod pool_box;
+pub mod reset_services;
+pub mod runtime_safe;
+pub mod string_table;
+pub mod time_services;
+pub mod variable_services;
+pub mod virtual_address;
+
+use alloc::vec::Vec;
+use core::{
+    ffi::c_void,
+    marker::PhantomData,
+    mem, ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use r_efi::efi;
+
+pub use pool_box::PoolBox;
+pub use reset_services::ResetType;
+pub use runtime_safe::RuntimeSafe;
+pub use time_services::{EfiTime, TimeCapabilities};
+pub use variable_services::{
+    FromVariable, ToVariable, VariableAttributes, VariableBuilder, VariableNames, GLOBAL_VARIABLE,
+};
+
+/// Wrapper around the firmware-provided `efi::RuntimeServices` table.
+///
+/// # Example
+/// ```no_run
+/// use r_efi::efi;
+/// use runtime_services::StandardRuntimeServices;
+///
+/// pub static RUNTIME_SERVICES: StandardRuntimeServices = StandardRuntimeServices::new_uninit();
+///
+/// pub extern "efiapi" fn efi_main(_image_handle: efi::Handle, system_table: *const efi::SystemTable) -> efi::Status {
+///     RUNTIME_SERVICES.initialize(unsafe { &*(*system_table).runtime_services });
+///     efi::Status::SUCCESS
+/// }
+/// ```
+#[derive(Debug)]
+pub struct StandardRuntimeServices<'a> {
+    efi_runtime_services: AtomicPtr<efi::RuntimeServices>,
+    _lifetime_marker: PhantomData<&'a efi::RuntimeServices>,
+}
+
+impl<'a> StandardRuntimeServices<'a> {
+    /// Create a new StandardRuntimeServices from the firmware-provided table.
+    pub const fn new(efi_runtime_services: &'a efi::RuntimeServices) -> Self {
+        // The table is only ever read through this pointer. The cast is needed because `AtomicPtr` stores `*mut T`.
+        let efi_runtime_services = efi_runtime_services as *const efi::RuntimeServices as *mut efi::RuntimeServices;
+        Self { efi_runtime_services: AtomicPtr::new(efi_runtime_services), _lifetime_marker: PhantomData }
+    }
+
+    /// Create a new StandardRuntimeServices that must be initialized with [`Self::initialize`] before use.
+    pub const fn new_uninit() -> Self {
+        Self { efi_runtime_services: AtomicPtr::new(ptr::null_mut()), _lifetime_marker: PhantomData }
+    }
+
+    /// Initialize the StandardRuntimeServices with the firmware-provided table.
+    ///
+    /// Calls made after the first successful initialization are ignored.
+    pub fn initialize(&'a self, efi_runtime_services: &'a efi::RuntimeServices) {
+        let efi_runtime_services = efi_runtime_services as *const efi::RuntimeServices as *mut efi::RuntimeServices;
+        let _ = self.efi_runtime_services.compare_exchange(
+            ptr::null_mut(),
+            efi_runtime_services,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        );
+    }
+
+    /// Return true if the StandardRuntimeServices has been initialized.
+    pub fn is_init(&self) -> bool {
+        !self.efi_runtime_services.load(Ordering::SeqCst).is_null()
+    }
+
+    /// Convert the pointer to the runtime services table to its virtual address.
+    ///
+    /// # Safety
+    /// Must only be called from an `EVT_SIGNAL_VIRTUAL_ADDRESS_CHANGE` notification, after every other conversion
+    /// made through this instance. [`virtual_address::VirtualAddressFixups`] takes care of this.
+    pub unsafe fn convert_to_virtual(&self) -> Result<(), efi::Status> {
+        let mut efi_runtime_services = self.efi_runtime_services.load(Ordering::SeqCst);
+        self.convert_pointer(&mut efi_runtime_services)?;
+        self.efi_runtime_services.store(efi_runtime_services, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Return the underlying `efi::RuntimeServices` table.
+    ///
+    /// # Panic
+    /// This function will panic if the StandardRuntimeServices has not been initialized.
+    pub fn as_efi_runtime_services(&self) -> &efi::RuntimeServices {
+        let efi_runtime_services = self.efi_runtime_services.load(Ordering::SeqCst);
+        // SAFETY: a non-null pointer always originates from a reference with lifetime 'a.
+        unsafe { efi_runtime_services.as_ref() }.expect("Runtime services is not initialized.")
+    }
+}
+
+/// Safe interface to the UEFI runtime services.
+///
+/// Implementors provide the raw services; the typed helpers are provided on top of them.
+pub trait RuntimeServices {
+    /// Read the variable `name` in the `namespace` vendor GUID into `data`.
+    ///
+    /// `name` must be a null-terminated UCS-2 string. On success, the variable attributes and the number of bytes
+    /// written to `data` are returned. On failure, the status is returned along with the data size reported by the
+    /// firmware, which is the required buffer size when the status is `efi::Status::BUFFER_TOO_SMALL`.
+    fn get_variable_into(
+        &self,
+        name: &[u16],
+        namespace: &efi::Guid,
+        data: &mut [u8],
+    ) -> Result<(VariableAttributes, usize), (efi::Status, usize)>;
+
+    /// Replace `name` and `namespace` with the name and vendor GUID of the variable that follows them.
+    ///
+    /// `name` must contain a null-terminated UCS-2 string; an empty string starts the enumeration. On failure, the
+    /// status is returned along with the name length in characters reported by the firmware, which is the required
+    /// buffer length when the status is `efi::Status::BUFFER_TOO_SMALL`. `efi::Status::NOT_FOUND` marks the end of the
+    /// enumeration.
+    fn get_next_variable_name_into(
+        &self,
+        name: &mut [u16],
+        namespace: &mut efi::Guid,
+    ) -> Result<(), (efi::Status, usize)>;
+
+    /// Write `data` to the variable `name` in the `namespace` vendor GUID with the given `attributes`.
+    ///
+    /// `name` must be a null-terminated UCS-2 string. Writing empty `data` deletes the variable, unless `attributes`
+    /// requests an append or authenticated write. Returns `efi::Status::INVALID_PARAMETER` without calling the
+    /// firmware if `attributes` is not valid, see [`VariableAttributes::validate`].
+    fn set_variable_bytes(
+        &self,
+        name: &[u16],
+        namespace: &efi::Guid,
+        attributes: VariableAttributes,
+        data: &[u8],
+    ) -> Result<(), efi::Status>;
+
+    /// Encode `value` and write it to the variable `name` in the `namespace` vendor GUID with the given `attributes`.
+    ///
+    /// See [`VariableBuilder`] for a more readable way to spell out the attributes.
+    fn set_variable<T: ToVariable + ?Sized>(
+        &self,
+        name: &[u16],
+        namespace: &efi::Guid,
+        attributes: VariableAttributes,
+        value: &T,
+    ) -> Result<(), efi::Status> {
+        self.set_variable_bytes(name, namespace, attributes, &value.to_variable())
+    }
+
+    /// Return the current time along with the capabilities of the real-time clock.
+    ///
+    /// Returns `efi::Status::DEVICE_ERROR` if the firmware reports a time that is not valid.
+    fn get_time_and_capabilities(&self) -> Result<(EfiTime, TimeCapabilities), efi::Status>;
+
+    /// Set the current time.
+    ///
+    /// Returns `efi::Status::INVALID_PARAMETER` without calling the firmware if `time` is not valid.
+    fn set_time(&self, time: &EfiTime) -> Result<(), efi::Status>;
+
+    /// Arm the wakeup alarm to fire at `time`, or disable it if `time` is `None`.
+    ///
+    /// Returns `efi::Status::INVALID_PARAMETER` without calling the firmware if `time` is not valid.
+    fn set_wakeup(&self, time: Option<&EfiTime>) -> Result<(), efi::Status>;
+
+    /// Return the state of the wakeup alarm.
+    ///
+    /// Returns `None` if the alarm is disabled, otherwise whether the alarm signal is pending and the alarm time.
+    fn wakeup_status(&self) -> Result<Option<(bool, EfiTime)>, efi::Status>;
+
+    /// Reset the platform.
+    ///
+    /// `status` describes the reason for the reset and `message` is an optional description, which is passed to the
+    /// firmware along with the vendor GUID of platform-specific resets as described in [`reset_services::reset_data`].
+    fn reset_system(&self, reset_type: ResetType, status: efi::Status, message: Option<&str>) -> !;
+
+    /// Power off the platform.
+    fn reset_shutdown(&self) -> ! {
+        self.reset_system(ResetType::Shutdown, efi::Status::SUCCESS, None)
+    }
+
+    /// Convert `pointer` from a physical to a virtual address. Null pointers are left unchanged.
+    ///
+    /// # Safety
+    /// Must only be called from an `EVT_SIGNAL_VIRTUAL_ADDRESS_CHANGE` notification.
+    unsafe fn convert_pointer<T>(&self, pointer: &mut *mut T) -> Result<(), efi::Status>;
+
+    /// Return the current time.
+    fn get_time(&self) -> Result<EfiTime, efi::Status> {
+        self.get_time_and_capabilities().map(|(time, _)| time)
+    }
+
+    /// Return the capabilities of the real-time clock.
+    fn time_capabilities(&self) -> Result<TimeCapabilities, efi::Status> {
+        self.get_time_and_capabilities().map(|(_, capabilities)| capabilities)
+    }
+
+    /// Read the variable `name` in the `namespace` vendor GUID and decode it as `T`.
+    ///
+    /// Returns the decoded value along with the variable attributes.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use r_efi::efi;
+    /// use runtime_services::{RuntimeServices, StandardRuntimeServices, GLOBAL_VARIABLE};
+    ///
+    /// fn boot_timeout(runtime_services: &StandardRuntimeServices) -> Result<u16, efi::Status> {
+    ///     // "Timeout" as a null-terminated UCS-2 string.
+    ///     let name = [0x54, 0x69, 0x6D, 0x65, 0x6F, 0x75, 0x74, 0x00];
+    ///     let (timeout, _attributes) = runtime_services.get_variable::<u16>(&name, &GLOBAL_VARIABLE)?;
+    ///     Ok(timeout)
+    /// }
+    /// ```
+    fn get_variable<T: FromVariable>(
+        &self,
+        name: &[u16],
+        namespace: &efi::Guid,
+    ) -> Result<(T, VariableAttributes), efi::Status> {
+        let (data, attributes) = read_variable(self, name, namespace)?;
+        Ok((T::from_variable(&data)?, attributes))
+    }
+
+    /// Read the raw contents of the variable `name` in the `namespace` vendor GUID into a buffer allocated from pool.
+    ///
+    /// The buffer is sized from the firmware-reported variable size and reallocated as needed, so this never fails with
+    /// `efi::Status::BUFFER_TOO_SMALL` unless the firmware reports inconsistent sizes. Pool is only available before
+    /// ExitBootServices; runtime callers read variables with [`Self::get_variable_into`] instead.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use r_efi::efi;
+    /// use runtime_services::{RuntimeServices, StandardRuntimeServices, GLOBAL_VARIABLE};
+    ///
+    /// fn boot_order_len(
+    ///     runtime_services: &StandardRuntimeServices,
+    ///     boot_services: &efi::BootServices,
+    /// ) -> Result<usize, efi::Status> {
+    ///     // "BootOrder" as a null-terminated UCS-2 string.
+    ///     let name = [0x42, 0x6F, 0x6F, 0x74, 0x4F, 0x72, 0x64, 0x65, 0x72, 0x00];
+    ///     let (boot_order, _attributes) = runtime_services.get_variable_bytes(boot_services, &name, &GLOBAL_VARIABLE)?;
+    ///     Ok(boot_order.len() / 2)
+    /// }
+    /// ```
+    fn get_variable_bytes(
+        &self,
+        boot_services: &efi::BootServices,
+        name: &[u16],
+        namespace: &efi::Guid,
+    ) -> Result<(PoolBox, VariableAttributes), efi::Status> {
+        let mut data = PoolBox::new_zeroed(boot_services, 0)?;
+        loop {
+            match self.get_variable_into(name, namespace, &mut data) {
+                Ok((attributes, data_size)) => {
+                    data.truncate(data_size);
+                    return Ok((data, attributes));
+                }
+                // The variable may be updated between calls, so keep reallocating until the buffer is large enough.
+                Err((efi::Status::BUFFER_TOO_SMALL, data_size)) if data_size > data.len() => {
+                    data = PoolBox::new_zeroed(boot_services, data_size)?;
+                }
+                Err((status, _)) => return Err(status),
+            }
+        }
+    }
+
+    /// Return an iterator over the names and vendor GUIDs of all variables.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use runtime_services::{RuntimeServices, StandardRuntimeServices};
+    ///
+    /// fn count_variables(runtime_services: &StandardRuntimeServices) -> usize {
+    ///     runtime_services.variables().filter_map(Result::ok).count()
+    /// }
+    /// ```
+    fn variables(&self) -> VariableNames<'_, Self>
+    where
+        Self: Sized,
+    {
+        VariableNames::new(self)
+    }
+}
+
+/// Read the raw contents of the variable `name` in the `namespace` vendor GUID into a buffer from the global allocator,
+/// for helpers that must also work after ExitBootServices.
+pub(crate) fn read_variable<R: RuntimeServices + ?Sized>(
+    runtime_services: &R,
+    name: &[u16],
+    namespace: &efi::Guid,
+) -> Result<(Vec<u8>, VariableAttributes), efi::Status> {
+    let mut data = Vec::new();
+    loop {
+        match runtime_services.get_variable_into(name, namespace, &mut data) {
+            Ok((attributes, data_size)) => {
+                data.truncate(data_size);
+                return Ok((data, attributes));
+            }
+            // The variable may be updated between calls, so keep resizing until the buffer is large enough.
+            Err((efi::Status::BUFFER_TOO_SMALL, data_size)) if data_size > data.len() => data.resize(data_size, 0),
+            Err((status, _)) => return Err(status),
+        }
+    }
+}
+
+impl RuntimeServices for StandardRuntimeServices<'_> {
+    fn get_time_and_capabilities(&self) -> Result<(EfiTime, TimeCapabilities), efi::Status> {
+        let mut time = efi::Time::default();
+        let mut capabilities = efi::TimeCapabilities { resolution: 0, accuracy: 0, sets_to_zero: efi::Boolean::FALSE };
+
+        let status = (self.as_efi_runtime_services().get_time)(&mut time, &mut capabilities);
+
+        if status.is_error() {
+            return Err(status);
+        }
+        let time = EfiTime::try_from(time).map_err(|_| efi::Status::DEVICE_ERROR)?;
+        Ok((time, capabilities.into()))
+    }
+
+    fn set_time(&self, time: &EfiTime) -> Result<(), efi::Status> {
+        let mut time = efi::Time::try_from(*time)?;
+
+        let status = (self.as_efi_runtime_services().set_time)(&mut time);
+
+        if status.is_error() {
+            Err(status)
+        } else {
+            Ok(())
+        }
+    }
+
+    unsafe fn convert_pointer<T>(&self, pointer: &mut *mut T) -> Result<(), efi::Status> {
+        let status = (self.as_efi_runtime_services().convert_pointer)(
+            efi::OPTIONAL_POINTER as usize,
+            pointer as *mut *mut T as *mut *mut c_void,
+        );
+
+        if status.is_error() {
+            Err(status)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn reset_system(&self, reset_type: ResetType, status: efi::Status, message: Option<&str>) -> ! {
+        let mut data = reset_services::reset_data(reset_type, message);
+        let data_ptr = if data.is_empty() { ptr::null_mut() } else { data.as_mut_ptr() as *mut c_void };
+
+        (self.as_efi_runtime_services().reset_system)(reset_type.into(), status, data.len(), data_ptr);
+
+        panic!("ResetSystem returned.");
+    }
+
+    fn set_wakeup(&self, time: Option<&EfiTime>) -> Result<(), efi::Status> {
+        let mut time = time.map(|time| efi::Time::try_from(*time)).transpose()?;
+        let time_ptr = time.as_mut().map_or(ptr::null_mut(), |time| time as *mut efi::Time);
+
+        let status = (self.as_efi_runtime_services().set_wakeup_time)(time.is_some().into(), time_ptr);
+
+        if status.is_error() {
+            Err(status)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn wakeup_status(&self) -> Result<Option<(bool, EfiTime)>, efi::Status> {
+        let mut enabled = efi::Boolean::FALSE;
+        let mut pending = efi::Boolean::FALSE;
+        let mut time = efi::Time::default();
+
+        let status = (self.as_efi_runtime_services().get_wakeup_time)(&mut enabled, &mut pending, &mut time);
+
+        if status.is_error() {
+            return Err(status);
+        }
+        if !bool::from(enabled) {
+            return Ok(None);
+        }
+        let time = EfiTime::try_from(time).map_err(|_| efi::Status::DEVICE_ERROR)?;
+        Ok(Some((pending.into(), time)))
+    }
+
+    fn get_variable_into(
+        &self,
+        name: &[u16],
+        namespace: &efi::Guid,
+        data: &mut [u8],
+    ) -> Result<(VariableAttributes, usize), (efi::Status, usize)> {
+        if name.last() != Some(&0) {
+            return Err((efi::Status::INVALID_PARAMETER, 0));
+        }
+
+        let mut attributes = 0;
+        let mut data_size = data.len();
+        let data_ptr = if data.is_empty() { ptr::null_mut() } else { data.as_mut_ptr() as *mut c_void };
+
+        let status = (self.as_efi_runtime_services().get_variable)(
+            name.as_ptr() as *mut u16,
+            namespace as *const efi::Guid as *mut efi::Guid,
+            &mut attributes,
+            &mut data_size,
+            data_ptr,
+        );
+
+        if status.is_error() {
+            Err((status, data_size))
+        } else {
+            Ok((VariableAttributes::from_bits_retain(attributes), data_size))
+        }
+    }
+
+    fn set_variable_bytes(
+        &self,
+        name: &[u16],
+        namespace: &efi::Guid,
+        attributes: VariableAttributes,
+        data: &[u8],
+    ) -> Result<(), efi::Status> {
+        if name.last() != Some(&0) {
+            return Err(efi::Status::INVALID_PARAMETER);
+        }
+        attributes.validate()?;
+
+        let data_ptr = if data.is_empty() { ptr::null_mut() } else { data.as_ptr() as *mut c_void };
+
+        let status = (self.as_efi_runtime_services().set_variable)(
+            name.as_ptr() as *mut u16,
+            namespace as *const efi::Guid as *mut efi::Guid,
+            attributes.bits(),
+            data.len(),
+            data_ptr,
+        );
+
+        if status.is_error() {
+            Err(status)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn get_next_variable_name_into(
+        &self,
+        name: &mut [u16],
+        namespace: &mut efi::Guid,
+    ) -> Result<(), (efi::Status, usize)> {
+        if !name.contains(&0) {
+            return Err((efi::Status::INVALID_PARAMETER, 0));
+        }
+
+        let mut name_size = mem::size_of_val(name);
+        let status =
+            (self.as_efi_runtime_services().get_next_variable_name)(&mut name_size, name.as_mut_ptr(), namespace);
+
+        if status.is_error() {
+            Err((status, name_size.div_ceil(mem::size_of::<u16>())))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::{cell::RefCell, slice};
+
+    const TEST_NAMESPACE: efi::Guid =
+        efi::Guid::from_fields(0x4c8a0b71, 0x5b1f, 0x4b3e, 0x9a, 0x0e, &[0x52, 0x7b, 0x06, 0x1d, 0x4f, 0x10]);
+
+    // "Test" as a null-terminated UCS-2 string.
+    const TEST_NAME: [u16; 5] = [0x54, 0x65, 0x73, 0x74, 0x00];
+    const TEST_DATA: [u8; 4] = [0x78, 0x56, 0x34, 0x12];
+    const TEST_ATTRIBUTES: VariableAttributes = VariableAttributes::BS_RT;
+
+    const TEST_TIME: EfiTime = EfiTime {
+        year: 2024,
+        month: 5,
+        day: 17,
+        hour: 8,
+        minute: 30,
+        second: 15,
+        nanosecond: 0,
+        time_zone: time_services::TimeZone::Unspecified,
+        daylight: time_services::Daylight::empty(),
+    };
+
+    extern "efiapi" fn get_time(time: *mut efi::Time, capabilities: *mut efi::TimeCapabilities) -> efi::Status {
+        unsafe {
+            *time = TEST_TIME.try_into().unwrap();
+            *capabilities = efi::TimeCapabilities { resolution: 1, accuracy: 50_000_000, sets_to_zero: true.into() };
+        }
+        efi::Status::SUCCESS
+    }
+
+    std::thread_local! {
+        static LAST_SET_TIME: RefCell<Option<EfiTime>> = const { RefCell::new(None) };
+    }
+
+    extern "efiapi" fn set_time(time: *mut efi::Time) -> efi::Status {
+        let time = EfiTime::try_from(unsafe { *time }).unwrap();
+        LAST_SET_TIME.with(|last| *last.borrow_mut() = Some(time));
+        efi::Status::SUCCESS
+    }
+
+    std::thread_local! {
+        static WAKEUP_TIME: RefCell<Option<EfiTime>> = const { RefCell::new(None) };
+    }
+
+    extern "efiapi" fn get_wakeup_time(
+        enabled: *mut efi::Boolean,
+        pending: *mut efi::Boolean,
+        time: *mut efi::Time,
+    ) -> efi::Status {
+        let wakeup_time = WAKEUP_TIME.with(|wakeup_time| *wakeup_time.borrow());
+        unsafe {
+            *enabled = wakeup_time.is_some().into();
+            *pending = efi::Boolean::FALSE;
+            *time = wakeup_time.map_or(efi::Time::default(), |time| time.try_into().unwrap());
+        }
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn set_wakeup_time(enable: efi::Boolean, time: *mut efi::Time) -> efi::Status {
+        let wakeup_time = match (bool::from(enable), time.is_null()) {
+            (true, false) => Some(EfiTime::try_from(unsafe { *time }).unwrap()),
+            (false, _) => None,
+            (true, true) => return efi::Status::INVALID_PARAMETER,
+        };
+        WAKEUP_TIME.with(|time| *time.borrow_mut() = wakeup_time);
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn set_virtual_address_map(
+        _: usize,
+        _: usize,
+        _: u32,
+        _: *mut efi::MemoryDescriptor,
+    ) -> efi::Status {
+        efi::Status::UNSUPPORTED
+    }
+
+    // Mock conversions relocate pointers by a fixed offset.
+    pub(crate) const VIRTUAL_ADDRESS_OFFSET: usize = 0x8000_0000;
+
+    extern "efiapi" fn convert_pointer(debug_disposition: usize, address: *mut *mut c_void) -> efi::Status {
+        unsafe {
+            match (*address).is_null() {
+                true if debug_disposition & efi::OPTIONAL_POINTER as usize != 0 => efi::Status::SUCCESS,
+                true => efi::Status::INVALID_PARAMETER,
+                false => {
+                    *address = (*address).byte_add(VIRTUAL_ADDRESS_OFFSET);
+                    efi::Status::SUCCESS
+                }
+            }
+        }
+    }
+
+    extern "efiapi" fn get_variable(
+        name: *mut u16,
+        namespace: *mut efi::Guid,
+        attributes: *mut u32,
+        data_size: *mut usize,
+        data: *mut c_void,
+    ) -> efi::Status {
+        let name = unsafe { slice::from_raw_parts(name, TEST_NAME.len()) };
+        if name != TEST_NAME || unsafe { *namespace } != TEST_NAMESPACE {
+            return efi::Status::NOT_FOUND;
+        }
+        if unsafe { *data_size } < TEST_DATA.len() {
+            unsafe { *data_size = TEST_DATA.len() };
+            return efi::Status::BUFFER_TOO_SMALL;
+        }
+        unsafe {
+            ptr::copy_nonoverlapping(TEST_DATA.as_ptr(), data as *mut u8, TEST_DATA.len());
+            *data_size = TEST_DATA.len();
+            *attributes = TEST_ATTRIBUTES.bits();
+        }
+        efi::Status::SUCCESS
+    }
+
+    // The mock variable store holds `TEST_NAME` followed by a name longer than the initial enumeration buffer.
+    fn long_name() -> Vec<u16> {
+        let mut name = vec![u16::from(b'L'); 100];
+        name.push(0);
+        name
+    }
+
+    extern "efiapi" fn get_next_variable_name(
+        name_size: *mut usize,
+        name: *mut u16,
+        namespace: *mut efi::Guid,
+    ) -> efi::Status {
+        let previous = unsafe {
+            let len = (0..).position(|i| *name.add(i) == 0).unwrap();
+            slice::from_raw_parts(name, len + 1).to_vec()
+        };
+        let (next_name, next_namespace) = match previous.as_slice() {
+            [0] => (TEST_NAME.to_vec(), TEST_NAMESPACE),
+            previous if previous == TEST_NAME => (long_name(), GLOBAL_VARIABLE),
+            _ => return efi::Status::NOT_FOUND,
+        };
+        let next_size = mem::size_of_val(next_name.as_slice());
+        if unsafe { *name_size } < next_size {
+            unsafe { *name_size = next_size };
+            return efi::Status::BUFFER_TOO_SMALL;
+        }
+        unsafe {
+            ptr::copy_nonoverlapping(next_name.as_ptr(), name, next_name.len());
+            *name_size = next_size;
+            *namespace = next_namespace;
+        }
+        efi::Status::SUCCESS
+    }
+
+    type SetVariableCall = (Vec<u16>, efi::Guid, u32, Vec<u8>);
+
+    std::thread_local! {
+        static LAST_SET_VARIABLE: RefCell<Option<SetVariableCall>> = const { RefCell::new(None) };
+    }
+
+    extern "efiapi" fn set_variable(
+        name: *mut u16,
+        namespace: *mut efi::Guid,
+        attributes: u32,
+        data_size: usize,
+        data: *mut c_void,
+    ) -> efi::Status {
+        let call = unsafe {
+            let len = (0..).position(|i| *name.add(i) == 0).unwrap();
+            let name = slice::from_raw_parts(name, len + 1).to_vec();
+            let data =
+                if data_size == 0 { Vec::new() } else { slice::from_raw_parts(data as *const u8, data_size).to_vec() };
+            (name, *namespace, attributes, data)
+        };
+        LAST_SET_VARIABLE.with(|last| *last.borrow_mut() = Some(call));
+        efi::Status::SUCCESS
+    }
+
+    fn take_last_set_variable() -> Option<SetVariableCall> {
+        LAST_SET_VARIABLE.with(|last| last.borrow_mut().take())
+    }
+
+    extern "efiapi" fn get_next_high_mono_count(_: *mut u32) -> efi::Status {
+        efi::Status::UNSUPPORTED
+    }
+
+    type ResetSystemCall = (efi::ResetType, efi::Status, Vec<u8>);
+
+    std::thread_local! {
+        static LAST_RESET_SYSTEM: RefCell<Option<ResetSystemCall>> = const { RefCell::new(None) };
+    }
+
+    // Returns instead of resetting, which lets the tests observe the arguments.
+    extern "efiapi" fn reset_system(
+        reset_type: efi::ResetType,
+        status: efi::Status,
+        data_size: usize,
+        data: *mut c_void,
+    ) {
+        let data = if data_size == 0 {
+            Vec::new()
+        } else {
+            unsafe { slice::from_raw_parts(data as *const u8, data_size) }.to_vec()
+        };
+        LAST_RESET_SYSTEM.with(|last| *last.borrow_mut() = Some((reset_type, status, data)));
+    }
+
+    extern "efiapi" fn update_capsule(
+        _: *mut *mut efi::CapsuleHeader,
+        _: usize,
+        _: efi::PhysicalAddress,
+    ) -> efi::Status {
+        efi::Status::UNSUPPORTED
+    }
+
+    extern "efiapi" fn query_capsule_capabilities(
+        _: *mut *mut efi::CapsuleHeader,
+        _: usize,
+        _: *mut u64,
+        _: *mut efi::ResetType,
+    ) -> efi::Status {
+        efi::Status::UNSUPPORTED
+    }
+
+    extern "efiapi" fn query_variable_info(_: u32, _: *mut u64, _: *mut u64, _: *mut u64) -> efi::Status {
+        efi::Status::UNSUPPORTED
+    }
+
+    pub(crate) fn mock_efi_runtime_services() -> efi::RuntimeServices {
+        efi::RuntimeServices {
+            hdr: efi::TableHeader {
+                signature: efi::RUNTIME_SERVICES_SIGNATURE,
+                revision: efi::RUNTIME_SERVICES_REVISION,
+                header_size: mem::size_of::<efi::RuntimeServices>() as u32,
+                crc32: 0,
+                reserved: 0,
+            },
+            get_time,
+            set_time,
+            get_wakeup_time,
+            set_wakeup_time,
+            set_virtual_address_map,
+            convert_pointer,
+            get_variable,
+            get_next_variable_name,
+            set_variable,
+            get_next_high_mono_count,
+            reset_system,
+            update_capsule,
+            query_capsule_capabilities,
+            query_variable_info,
+        }
+    }
+
+    std::thread_local! {
+        static LIVE_POOLS: core::cell::Cell<usize> = const { core::cell::Cell::new(0) };
+    }
+
+    /// Return the number of pool buffers allocated through [`mock_efi_boot_services`] and not freed yet.
+    pub(crate) fn live_pools() -> usize {
+        LIVE_POOLS.with(|pools| pools.get())
+    }
+
+    extern "efiapi" fn allocate_pool(pool_type: efi::MemoryType, size: usize, buffer: *mut *mut c_void) -> efi::Status {
+        assert_eq!(pool_type, efi::BOOT_SERVICES_DATA);
+        LIVE_POOLS.with(|pools| pools.set(pools.get() + 1));
+        unsafe { *buffer = Box::into_raw(vec![0xa5u8; size].into_boxed_slice()) as *mut c_void };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn free_pool(_buffer: *mut c_void) -> efi::Status {
+        // The buffers are leaked, since their size is not known here.
+        LIVE_POOLS.with(|pools| pools.set(pools.get() - 1));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn unimplemented_stub() -> efi::Status {
+        efi::Status::UNSUPPORTED
+    }
+
+    /// Return a pointer to a stub returning `efi::Status::UNSUPPORTED`, for services the tests do not use.
+    fn unimplemented_service<F: Copy>() -> F {
+        let stub: extern "efiapi" fn() -> efi::Status = unimplemented_stub;
+        // SAFETY: every boot service is an `efiapi` function pointer returning a pointer-sized value or nothing, and the
+        // stub ignores its arguments.
+        unsafe { mem::transmute_copy(&stub) }
+    }
+
+    /// Boot services table with pool allocation, counted by [`live_pools`].
+    pub(crate) fn mock_efi_boot_services() -> efi::BootServices {
+        efi::BootServices {
+            hdr: efi::TableHeader {
+                signature: efi::BOOT_SERVICES_SIGNATURE,
+                revision: efi::BOOT_SERVICES_REVISION,
+                header_size: mem::size_of::<efi::BootServices>() as u32,
+                crc32: 0,
+                reserved: 0,
+            },
+            raise_tpl: unimplemented_service(),
+            restore_tpl: unimplemented_service(),
+            allocate_pages: unimplemented_service(),
+            free_pages: unimplemented_service(),
+            get_memory_map: unimplemented_service(),
+            allocate_pool,
+            free_pool,
+            create_event: unimplemented_service(),
+            set_timer: unimplemented_service(),
+            wait_for_event: unimplemented_service(),
+            signal_event: unimplemented_service(),
+            close_event: unimplemented_service(),
+            check_event: unimplemented_service(),
+            install_protocol_interface: unimplemented_service(),
+            reinstall_protocol_interface: unimplemented_service(),
+            uninstall_protocol_interface: unimplemented_service(),
+            handle_protocol: unimplemented_service(),
+            reserved: ptr::null_mut(),
+            register_protocol_notify: unimplemented_service(),
+            locate_handle: unimplemented_service(),
+            locate_device_path: unimplemented_service(),
+            install_configuration_table: unimplemented_service(),
+            load_image: unimplemented_service(),
+            start_image: unimplemented_service(),
+            exit: unimplemented_service(),
+            unload_image: unimplemented_service(),
+            exit_boot_services: unimplemented_service(),
+            get_next_monotonic_count: unimplemented_service(),
+            stall: unimplemented_service(),
+            set_watchdog_timer: unimplemented_service(),
+            connect_controller: unimplemented_service(),
+            disconnect_controller: unimplemented_service(),
+            open_protocol: unimplemented_service(),
+            close_protocol: unimplemented_service(),
+            open_protocol_information: unimplemented_service(),
+            protocols_per_handle: unimplemented_service(),
+            locate_handle_buffer: unimplemented_service(),
+            locate_protocol: unimplemented_service(),
+            install_multiple_protocol_interfaces: unimplemented_service(),
+            uninstall_multiple_protocol_interfaces: unimplemented_service(),
+            calculate_crc32: unimplemented_service(),
+            copy_mem: unimplemented_service(),
+            set_mem: unimplemented_service(),
+            create_event_ex: unimplemented_service(),
+        }
+    }
+
+    #[test]
+    fn test_initialize() {
+        let efi_runtime_services = mock_efi_runtime_services();
+        let runtime_services = StandardRuntimeServices::new_uninit();
+        assert!(!runtime_services.is_init());
+        runtime_services.initialize(&efi_runtime_services);
+        assert!(runtime_services.is_init());
+        assert!(ptr::eq(runtime_services.as_efi_runtime_services(), &efi_runtime_services));
+    }
+
+    #[test]
+    #[should_panic = "Runtime services is not initialized."]
+    fn test_uninitialized_use_panics() {
+        let runtime_services = StandardRuntimeServices::new_uninit();
+        let _ = runtime_services.get_variable::<u32>(&TEST_NAME, &TEST_NAMESPACE);
+    }
+
+    #[test]
+    fn test_get_variable() {
+        let efi_runtime_services = mock_efi_runtime_services();
+        let runtime_services = StandardRuntimeServices::new(&efi_runtime_services);
+
+        assert_eq!(
+            runtime_services.get_variable::<u32>(&TEST_NAME, &TEST_NAMESPACE),
+            Ok((0x12345678, TEST_ATTRIBUTES))
+        );
+        assert_eq!(
+            runtime_services.get_variable::<[u8; 4]>(&TEST_NAME, &TEST_NAMESPACE),
+            Ok((TEST_DATA, TEST_ATTRIBUTES))
+        );
+        assert_eq!(
+            runtime_services.get_variable::<u16>(&TEST_NAME, &TEST_NAMESPACE),
+            Err(efi::Status::BAD_BUFFER_SIZE)
+        );
+        assert_eq!(runtime_services.get_variable::<u32>(&TEST_NAME, &GLOBAL_VARIABLE), Err(efi::Status::NOT_FOUND));
+        // The name must be null-terminated.
+        assert_eq!(
+            runtime_services.get_variable::<u32>(&TEST_NAME[..4], &TEST_NAMESPACE),
+            Err(efi::Status::INVALID_PARAMETER)
+        );
+    }
+
+    #[test]
+    fn test_get_variable_bytes() {
+        let efi_runtime_services = mock_efi_runtime_services();
+        let runtime_services = StandardRuntimeServices::new(&efi_runtime_services);
+
+        let boot_services = mock_efi_boot_services();
+
+        let (data, attributes) =
+            runtime_services.get_variable_bytes(&boot_services, &TEST_NAME, &TEST_NAMESPACE).unwrap();
+        assert_eq!(*data, TEST_DATA);
+        assert_eq!(attributes, TEST_ATTRIBUTES);
+        assert_eq!(live_pools(), 1);
+        drop(data);
+        assert_eq!(live_pools(), 0);
+        assert_eq!(
+            runtime_services.get_variable_bytes(&boot_services, &TEST_NAME, &GLOBAL_VARIABLE).unwrap_err(),
+            efi::Status::NOT_FOUND
+        );
+        assert_eq!(live_pools(), 0);
+
+        let mut data = [0u8; 2];
+        assert_eq!(
+            runtime_services.get_variable_into(&TEST_NAME, &TEST_NAMESPACE, &mut data),
+            Err((efi::Status::BUFFER_TOO_SMALL, TEST_DATA.len()))
+        );
+    }
+
+    #[test]
+    fn test_variables() {
+        let efi_runtime_services = mock_efi_runtime_services();
+        let runtime_services = StandardRuntimeServices::new(&efi_runtime_services);
+
+        let variables = runtime_services.variables().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(variables, vec![(TEST_NAME.to_vec(), TEST_NAMESPACE), (long_name(), GLOBAL_VARIABLE)]);
+
+        // The name buffer must hold a null-terminated string.
+        let mut name = [u16::from(b'T')];
+        let mut namespace = TEST_NAMESPACE;
+        assert_eq!(
+            runtime_services.get_next_variable_name_into(&mut name, &mut namespace),
+            Err((efi::Status::INVALID_PARAMETER, 0))
+        );
+    }
+
+    #[test]
+    fn test_set_variable() {
+        let efi_runtime_services = mock_efi_runtime_services();
+        let runtime_services = StandardRuntimeServices::new(&efi_runtime_services);
+        let attributes = VariableAttributes::BS_RT;
+
+        runtime_services.set_variable(&TEST_NAME, &TEST_NAMESPACE, attributes, &0x12345678u32).unwrap();
+        assert_eq!(
+            take_last_set_variable(),
+            Some((TEST_NAME.to_vec(), TEST_NAMESPACE, attributes.bits(), TEST_DATA.to_vec()))
+        );
+
+        // The name must be null-terminated.
+        assert_eq!(
+            runtime_services.set_variable_bytes(&TEST_NAME[..4], &TEST_NAMESPACE, attributes, &TEST_DATA),
+            Err(efi::Status::INVALID_PARAMETER)
+        );
+        // Runtime access requires boot services access.
+        assert_eq!(
+            runtime_services.set_variable_bytes(
+                &TEST_NAME,
+                &TEST_NAMESPACE,
+                VariableAttributes::RUNTIME_ACCESS,
+                &TEST_DATA
+            ),
+            Err(efi::Status::INVALID_PARAMETER)
+        );
+        assert_eq!(take_last_set_variable(), None);
+    }
+
+    #[test]
+    fn test_variable_builder() {
+        let efi_runtime_services = mock_efi_runtime_services();
+        let runtime_services = StandardRuntimeServices::new(&efi_runtime_services);
+
+        VariableBuilder::new(&runtime_services, &TEST_NAME, &TEST_NAMESPACE)
+            .non_volatile()
+            .bootservice_access()
+            .runtime_access()
+            .set(&TEST_DATA)
+            .unwrap();
+        assert_eq!(
+            take_last_set_variable(),
+            Some((
+                TEST_NAME.to_vec(),
+                TEST_NAMESPACE,
+                efi::VARIABLE_NON_VOLATILE | efi::VARIABLE_BOOTSERVICE_ACCESS | efi::VARIABLE_RUNTIME_ACCESS,
+                TEST_DATA.to_vec()
+            ))
+        );
+
+        // Deleting writes an empty payload and drops the append flag, which would otherwise make the write a no-op.
+        VariableBuilder::new(&runtime_services, &TEST_NAME, &TEST_NAMESPACE)
+            .bootservice_access()
+            .append_write()
+            .delete()
+            .unwrap();
+        assert_eq!(
+            take_last_set_variable(),
+            Some((TEST_NAME.to_vec(), TEST_NAMESPACE, efi::VARIABLE_BOOTSERVICE_ACCESS, Vec::new()))
+        );
+    }
+
+    #[test]
+    fn test_get_time() {
+        let efi_runtime_services = mock_efi_runtime_services();
+        let runtime_services = StandardRuntimeServices::new(&efi_runtime_services);
+
+        assert_eq!(runtime_services.get_time(), Ok(TEST_TIME));
+        assert_eq!(
+            runtime_services.time_capabilities(),
+            Ok(TimeCapabilities { resolution: 1, accuracy: 50_000_000, sets_to_zero: true })
+        );
+    }
+
+    #[test]
+    fn test_set_time() {
+        let efi_runtime_services = mock_efi_runtime_services();
+        let runtime_services = StandardRuntimeServices::new(&efi_runtime_services);
+
+        let time = EfiTime { time_zone: time_services::TimeZone::Offset(60), ..TEST_TIME };
+        runtime_services.set_time(&time).unwrap();
+        assert_eq!(LAST_SET_TIME.with(|last| last.borrow_mut().take()), Some(time));
+
+        // Invalid times are rejected before reaching the firmware.
+        assert_eq!(runtime_services.set_time(&EfiTime { month: 13, ..TEST_TIME }), Err(efi::Status::INVALID_PARAMETER));
+        assert_eq!(LAST_SET_TIME.with(|last| last.borrow_mut().take()), None);
+    }
+
+    #[test]
+    fn test_wakeup() {
+        let efi_runtime_services = mock_efi_runtime_services();
+        let runtime_services = StandardRuntimeServices::new(&efi_runtime_services);
+
+        assert_eq!(runtime_services.wakeup_status(), Ok(None));
+        runtime_services.set_wakeup(Some(&TEST_TIME)).unwrap();
+        assert_eq!(runtime_services.wakeup_status(), Ok(Some((false, TEST_TIME))));
+        runtime_services.set_wakeup(None).unwrap();
+        assert_eq!(runtime_services.wakeup_status(), Ok(None));
+
+        assert_eq!(
+            runtime_services.set_wakeup(Some(&EfiTime { hour: 24, ..TEST_TIME })),
+            Err(efi::Status::INVALID_PARAMETER)
+        );
+    }
+
+    #[test]
+    fn test_reset_system() {
+        let efi_runtime_services = mock_efi_runtime_services();
+        let runtime_services = StandardRuntimeServices::new(&efi_runtime_services);
+
+        let result = std::panic::catch_unwind(|| {
+            runtime_services.reset_system(ResetType::Warm, efi::Status::ABORTED, Some("Hi"));
+        });
+        assert!(result.is_err());
+        assert_eq!(
+            LAST_RESET_SYSTEM.with(|last| last.borrow_mut().take()),
+            Some((efi::RESET_WARM, efi::Status::ABORTED, vec![b'H', 0, b'i', 0, 0, 0]))
+        );
+
+        let result = std::panic::catch_unwind(|| runtime_services.reset_shutdown());
+        assert!(result.is_err());
+        assert_eq!(
+            LAST_RESET_SYSTEM.with(|last| last.borrow_mut().take()),
+            Some((efi::RESET_SHUTDOWN, efi::Status::SUCCESS, Vec::new()))
+        );
+    }
+}
@@ -0,0 +1,109 @@
+//! Byte buffers allocated from pool.
+//!
+//! [`PoolBox`] owns a buffer allocated with AllocatePool and frees it with FreePool when dropped. It keeps the FreePool
+//! service rather than the boot services table, but like any pool allocation it must be dropped before
+//! ExitBootServices.
+//!
+use core::{
+    ffi::c_void,
+    fmt,
+    ops::{Deref, DerefMut},
+    ptr::{self, NonNull},
+    slice,
+};
+
+use r_efi::efi;
+
+/// Bytes allocated from `efi::BOOT_SERVICES_DATA` pool, freed when dropped.
+pub struct PoolBox {
+    buffer: Option<NonNull<u8>>,
+    len: usize,
+    free_pool: efi::BootFreePool,
+}
+
+impl PoolBox {
+    /// Allocate `len` zeroed bytes from pool. No pool is allocated for an empty buffer.
+    ///
+    /// Returns the error of AllocatePool, or `efi::Status::OUT_OF_RESOURCES` if it succeeds without returning a buffer.
+    pub fn new_zeroed(boot_services: &efi::BootServices, len: usize) -> Result<Self, efi::Status> {
+        let free_pool = boot_services.free_pool;
+        if len == 0 {
+            return Ok(Self { buffer: None, len, free_pool });
+        }
+        let mut buffer = ptr::null_mut();
+        let status = (boot_services.allocate_pool)(efi::BOOT_SERVICES_DATA, len, &mut buffer);
+        if status.is_error() {
+            return Err(status);
+        }
+        let buffer = NonNull::new(buffer as *mut u8).ok_or(efi::Status::OUT_OF_RESOURCES)?;
+        // SAFETY: AllocatePool returned a buffer of `len` bytes.
+        unsafe { ptr::write_bytes(buffer.as_ptr(), 0, len) };
+        Ok(Self { buffer: Some(buffer), len, free_pool })
+    }
+
+    /// Shorten the buffer to `len` bytes, keeping the allocation. Has no effect if `len` is not shorter.
+    pub fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+}
+
+impl Deref for PoolBox {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self.buffer {
+            // SAFETY: the buffer holds at least `len` initialized bytes, owned by this box.
+            Some(buffer) => unsafe { slice::from_raw_parts(buffer.as_ptr(), self.len) },
+            None => &[],
+        }
+    }
+}
+
+impl DerefMut for PoolBox {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match self.buffer {
+            // SAFETY: the buffer holds at least `len` initialized bytes, owned by this box.
+            Some(buffer) => unsafe { slice::from_raw_parts_mut(buffer.as_ptr(), self.len) },
+            None => &mut [],
+        }
+    }
+}
+
+impl fmt::Debug for PoolBox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PoolBox").field(&&**self).finish()
+    }
+}
+
+impl Drop for PoolBox {
+    fn drop(&mut self) {
+        if let Some(buffer) = self.buffer {
+            (self.free_pool)(buffer.as_ptr() as *mut c_void);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tests::{live_pools, mock_efi_boot_services};
+
+    #[test]
+    fn test_pool_box() {
+        let boot_services = mock_efi_boot_services();
+        let mut data = PoolBox::new_zeroed(&boot_services, 4).unwrap();
+        assert_eq!(live_pools(), 1);
+        assert_eq!(*data, [0; 4]);
+        data.copy_from_slice(&[1, 2, 3, 4]);
+        data.truncate(2);
+        data.truncate(3);
+        assert_eq!(*data, [1, 2]);
+        drop(data);
+        assert_eq!(live_pools(), 0);
+
+        let empty = PoolBox::new_zeroed(&boot_services, 0).unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(live_pools(), 0);
+    }
+}
@@ -0,0 +1,91 @@
+//! Typed arguments for the UEFI ResetSystem service.
+//!
+//! ResetSystem takes an optional `ResetData` buffer holding a null-terminated UCS-2 description of the reset, followed
+//! by a vendor GUID for platform-specific resets. [`reset_data`] builds that buffer from a [`ResetType`] and a `&str`.
+//!
+use alloc::vec::Vec;
+
+use r_efi::efi;
+
+/// Type of reset to perform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetType {
+    /// System-wide reset of all processors and devices (`EfiResetCold`).
+    Cold,
+    /// System-wide initialization where processors are reset but memory may be preserved (`EfiResetWarm`).
+    Warm,
+    /// Power off the system (`EfiResetShutdown`).
+    Shutdown,
+    /// Reset defined by the vendor GUID placed after the description in the reset data (`EfiResetPlatformSpecific`).
+    PlatformSpecific(efi::Guid),
+}
+
+impl From<ResetType> for efi::ResetType {
+    fn from(reset_type: ResetType) -> Self {
+        match reset_type {
+            ResetType::Cold => efi::RESET_COLD,
+            ResetType::Warm => efi::RESET_WARM,
+            ResetType::Shutdown => efi::RESET_SHUTDOWN,
+            ResetType::PlatformSpecific(_) => efi::RESET_PLATFORM_SPECIFIC,
+        }
+    }
+}
+
+/// Build the `ResetData` buffer passed to ResetSystem.
+///
+/// `message` is encoded as a null-terminated UCS-2 string. Characters outside of the Basic Multilingual Plane are
+/// replaced with U+FFFD, and the message is cut at the first null character. For platform-specific resets, the vendor
+/// GUID is appended after the string (an empty string is used if there is no message). Returns an empty buffer if
+/// there is nothing to pass.
+pub fn reset_data(reset_type: ResetType, message: Option<&str>) -> Vec<u8> {
+    let guid = match reset_type {
+        ResetType::PlatformSpecific(guid) => Some(guid),
+        _ => None,
+    };
+    if message.is_none() && guid.is_none() {
+        return Vec::new();
+    }
+
+    let mut data = Vec::new();
+    for c in message.unwrap_or_default().chars().take_while(|&c| c != '\0') {
+        let c = u16::try_from(c as u32).unwrap_or(char::REPLACEMENT_CHARACTER as u16);
+        data.extend_from_slice(&c.to_le_bytes());
+    }
+    data.extend_from_slice(&0u16.to_le_bytes());
+    if let Some(guid) = guid {
+        data.extend_from_slice(guid.as_bytes());
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_GUID: efi::Guid =
+        efi::Guid::from_fields(0x4c8a0b71, 0x5b1f, 0x4b3e, 0x9a, 0x0e, &[0x52, 0x7b, 0x06, 0x1d, 0x4f, 0x10]);
+
+    #[test]
+    fn test_reset_type_conversion() {
+        assert_eq!(efi::ResetType::from(ResetType::Cold), efi::RESET_COLD);
+        assert_eq!(efi::ResetType::from(ResetType::Warm), efi::RESET_WARM);
+        assert_eq!(efi::ResetType::from(ResetType::Shutdown), efi::RESET_SHUTDOWN);
+        assert_eq!(efi::ResetType::from(ResetType::PlatformSpecific(TEST_GUID)), efi::RESET_PLATFORM_SPECIFIC);
+    }
+
+    #[test]
+    fn test_reset_data() {
+        assert!(reset_data(ResetType::Cold, None).is_empty());
+        assert_eq!(reset_data(ResetType::Warm, Some("Hi")), [b'H', 0, b'i', 0, 0, 0]);
+        assert_eq!(reset_data(ResetType::Warm, Some("A\0B")), [b'A', 0, 0, 0]);
+        assert_eq!(reset_data(ResetType::Shutdown, Some("\u{1F600}")), [0xFD, 0xFF, 0, 0]);
+
+        let data = reset_data(ResetType::PlatformSpecific(TEST_GUID), None);
+        assert_eq!(data[..2], [0, 0]);
+        assert_eq!(data[2..], *TEST_GUID.as_bytes());
+
+        let data = reset_data(ResetType::PlatformSpecific(TEST_GUID), Some("X"));
+        assert_eq!(data[..4], [b'X', 0, 0, 0]);
+        assert_eq!(data[4..], *TEST_GUID.as_bytes());
+    }
+}
@@ -0,0 +1,103 @@
+//! Marker for APIs and data that remain usable after ExitBootServices.
+//!
+//! Once the OS calls ExitBootServices, boot services and every boot services allocation (including the pool backing
+//! `alloc` collections) are gone. Code that runs at runtime, such as notification functions registered for
+//! `EVT_SIGNAL_VIRTUAL_ADDRESS_CHANGE` or runtime driver entry points, must only touch types implementing
+//! [`RuntimeSafe`]. APIs that hand data to runtime code bound it with `RuntimeSafe`, so passing a boot-phase-only type
+//! is rejected at compile time.
+//!
+use r_efi::efi;
+
+use crate::{
+    reset_services::ResetType,
+    time_services::{Daylight, EfiTime, TimeCapabilities, TimeZone},
+    variable_services::VariableAttributes,
+};
+
+/// Marker for types that may be used after ExitBootServices.
+///
+/// # Safety
+/// Implementors must not call boot services and must not own or point to boot services memory, either directly or
+/// through the types they contain. Memory they point to must be allocated as a runtime memory type.
+///
+/// # Example
+/// ```compile_fail
+/// use runtime_services::{virtual_address::VirtualAddressFixups, StandardRuntimeServices};
+///
+/// static RUNTIME_SERVICES: StandardRuntimeServices = StandardRuntimeServices::new_uninit();
+/// static FIXUPS: VirtualAddressFixups<1> = VirtualAddressFixups::new(&RUNTIME_SERVICES);
+/// // `Vec` storage comes from boot services pool and does not survive ExitBootServices.
+/// static mut LOG: *mut Vec<u8> = core::ptr::null_mut();
+///
+/// unsafe { FIXUPS.register(core::ptr::addr_of_mut!(LOG)) }.unwrap();
+/// ```
+pub unsafe trait RuntimeSafe {}
+
+macro_rules! impl_runtime_safe {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            // SAFETY: plain data, or types that only reference the runtime services table.
+            unsafe impl RuntimeSafe for $ty {}
+        )*
+    };
+}
+
+impl_runtime_safe!(
+    (),
+    bool,
+    char,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    efi::Guid,
+    efi::Status,
+    efi::Time,
+    EfiTime,
+    TimeCapabilities,
+    TimeZone,
+    Daylight,
+    ResetType,
+    VariableAttributes,
+);
+
+// SAFETY: containers of runtime-safe types without their own allocation are runtime safe.
+unsafe impl<T: RuntimeSafe, const N: usize> RuntimeSafe for [T; N] {}
+// SAFETY: see above.
+unsafe impl<T: RuntimeSafe> RuntimeSafe for [T] {}
+// SAFETY: see above.
+unsafe impl<T: RuntimeSafe> RuntimeSafe for Option<T> {}
+// SAFETY: atomics and cells are plain data.
+unsafe impl<T: RuntimeSafe> RuntimeSafe for core::cell::Cell<T> {}
+// SAFETY: see above.
+unsafe impl RuntimeSafe for core::sync::atomic::AtomicBool {}
+// SAFETY: see above.
+unsafe impl RuntimeSafe for core::sync::atomic::AtomicU32 {}
+// SAFETY: see above.
+unsafe impl RuntimeSafe for core::sync::atomic::AtomicU64 {}
+// SAFETY: see above.
+unsafe impl RuntimeSafe for core::sync::atomic::AtomicUsize {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_runtime_safe<T: RuntimeSafe + ?Sized>() {}
+
+    #[test]
+    fn test_runtime_safe_types() {
+        assert_runtime_safe::<EfiTime>();
+        assert_runtime_safe::<Option<u32>>();
+        assert_runtime_safe::<[efi::Guid; 4]>();
+        assert_runtime_safe::<[u16]>();
+        assert_runtime_safe::<core::cell::Cell<bool>>();
+    }
+}
@@ -0,0 +1,172 @@
+//! Interned UCS-2 strings.
+//!
+//! Drivers that repeatedly access the same variables otherwise convert and allocate the same UCS-2 names on every
+//! call. [`StringTable`] converts each distinct string once and stores all of them in a single fixed-size allocation,
+//! so the returned strings stay valid (and at the same address) for the lifetime of the table.
+//!
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::{
+    cell::{Cell, RefCell},
+    ptr::NonNull,
+    slice,
+};
+
+use r_efi::efi;
+
+/// Table of interned, null-terminated UCS-2 strings backed by a single allocation.
+///
+/// # Example
+/// ```
+/// use runtime_services::string_table::StringTable;
+///
+/// let table = StringTable::with_capacity(64);
+/// let boot_order = table.intern("BootOrder").unwrap();
+/// let timeout = table.intern("Timeout").unwrap();
+///
+/// // Interning the same string again returns the existing entry.
+/// assert_eq!(table.intern("BootOrder").unwrap().as_ptr(), boot_order.as_ptr());
+/// assert_eq!(timeout.last(), Some(&0));
+/// ```
+pub struct StringTable {
+    block: NonNull<u16>,
+    capacity: usize,
+    used: Cell<usize>,
+    // (offset, length including the null terminator) of each interned string.
+    entries: RefCell<Vec<(usize, usize)>>,
+}
+
+impl StringTable {
+    /// Create a new table able to hold `capacity` UCS-2 characters, null terminators included.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let block = Box::into_raw(vec![0u16; capacity].into_boxed_slice());
+        // SAFETY: `Box::into_raw` never returns a null pointer.
+        let block = unsafe { NonNull::new_unchecked(block as *mut u16) };
+        Self { block, capacity, used: Cell::new(0), entries: RefCell::new(Vec::new()) }
+    }
+
+    /// Return the interned, null-terminated UCS-2 form of `string`, adding it to the table if needed.
+    ///
+    /// Returns `efi::Status::INVALID_PARAMETER` if `string` contains a null character or characters outside of the
+    /// Basic Multilingual Plane, and `efi::Status::OUT_OF_RESOURCES` if the table is full.
+    pub fn intern(&self, string: &str) -> Result<&[u16], efi::Status> {
+        let mut ucs2 = Vec::with_capacity(string.len() + 1);
+        for c in string.chars() {
+            match u16::try_from(c as u32) {
+                Ok(0) | Err(_) => return Err(efi::Status::INVALID_PARAMETER),
+                Ok(c) => ucs2.push(c),
+            }
+        }
+        ucs2.push(0);
+        self.intern_ucs2(&ucs2)
+    }
+
+    /// Return the interned copy of the null-terminated UCS-2 string `string`, adding it to the table if needed.
+    ///
+    /// Returns `efi::Status::INVALID_PARAMETER` if `string` is not null-terminated or contains an embedded null, and
+    /// `efi::Status::OUT_OF_RESOURCES` if the table is full.
+    pub fn intern_ucs2(&self, string: &[u16]) -> Result<&[u16], efi::Status> {
+        if string.iter().position(|&c| c == 0) != Some(string.len().wrapping_sub(1)) {
+            return Err(efi::Status::INVALID_PARAMETER);
+        }
+        if let Some(interned) = self.get_ucs2(string) {
+            return Ok(interned);
+        }
+
+        let offset = self.used.get();
+        if string.len() > self.capacity - offset {
+            return Err(efi::Status::OUT_OF_RESOURCES);
+        }
+        // SAFETY: the destination range is in bounds and lies past every slice handed out so far, so it is not aliased.
+        unsafe { self.block.as_ptr().add(offset).copy_from_nonoverlapping(string.as_ptr(), string.len()) };
+        self.used.set(offset + string.len());
+        self.entries.borrow_mut().push((offset, string.len()));
+
+        Ok(self.entry(offset, string.len()))
+    }
+
+    /// Return the interned UCS-2 form of `string` if it is already present in the table.
+    pub fn get(&self, string: &str) -> Option<&[u16]> {
+        self.find(|entry| entry.iter().map(|&c| u32::from(c)).eq(string.chars().map(|c| c as u32).chain([0])))
+    }
+
+    /// Return the interned copy of the null-terminated UCS-2 string `string` if it is present in the table.
+    pub fn get_ucs2(&self, string: &[u16]) -> Option<&[u16]> {
+        self.find(|entry| entry == string)
+    }
+
+    /// Return the number of strings in the table.
+    pub fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+
+    /// Return true if the table holds no strings.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the number of UCS-2 characters still available in the table.
+    pub fn remaining(&self) -> usize {
+        self.capacity - self.used.get()
+    }
+
+    fn find(&self, mut predicate: impl FnMut(&[u16]) -> bool) -> Option<&[u16]> {
+        let entries = self.entries.borrow();
+        entries.iter().map(|&(offset, len)| self.entry(offset, len)).find(|entry| predicate(entry))
+    }
+
+    fn entry(&self, offset: usize, len: usize) -> &[u16] {
+        // SAFETY: entries are in bounds and never written again once interned.
+        unsafe { slice::from_raw_parts(self.block.as_ptr().add(offset), len) }
+    }
+}
+
+impl Drop for StringTable {
+    fn drop(&mut self) {
+        // SAFETY: `block` was created from a boxed slice of `capacity` elements in `with_capacity`.
+        drop(unsafe { Box::from_raw(slice::from_raw_parts_mut(self.block.as_ptr(), self.capacity)) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let table = StringTable::with_capacity(32);
+        assert!(table.is_empty());
+
+        let test = table.intern("Test").unwrap();
+        assert_eq!(test, [0x54, 0x65, 0x73, 0x74, 0x00]);
+        let other = table.intern_ucs2(&[0x4F, 0x00]).unwrap();
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.remaining(), 32 - 5 - 2);
+
+        // Interning an existing string returns the original entry without using more space.
+        assert_eq!(table.intern("Test").unwrap().as_ptr(), test.as_ptr());
+        assert_eq!(table.intern("O").unwrap().as_ptr(), other.as_ptr());
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get("Test").map(<[u16]>::as_ptr), Some(test.as_ptr()));
+        assert_eq!(table.get_ucs2(&[0x4F, 0x00]).map(<[u16]>::as_ptr), Some(other.as_ptr()));
+        assert_eq!(table.get("Tes"), None);
+
+        // Earlier entries are unaffected by later ones.
+        table.intern("Another").unwrap();
+        assert_eq!(test, [0x54, 0x65, 0x73, 0x74, 0x00]);
+    }
+
+    #[test]
+    fn test_intern_errors() {
+        let table = StringTable::with_capacity(7);
+        assert_eq!(table.intern("\u{1F600}"), Err(efi::Status::INVALID_PARAMETER));
+        assert_eq!(table.intern("A\0B"), Err(efi::Status::INVALID_PARAMETER));
+        assert_eq!(table.intern_ucs2(&[0x41]), Err(efi::Status::INVALID_PARAMETER));
+        assert_eq!(table.intern_ucs2(&[0x41, 0x00, 0x42, 0x00]), Err(efi::Status::INVALID_PARAMETER));
+        assert_eq!(table.intern_ucs2(&[]), Err(efi::Status::INVALID_PARAMETER));
+
+        table.intern("Test").unwrap();
+        assert_eq!(table.intern("More"), Err(efi::Status::OUT_OF_RESOURCES));
+        assert_eq!(table.intern("M").unwrap(), [0x4D, 0x00]);
+        assert_eq!(table.remaining(), 0);
+    }
+}
@@ -0,0 +1,223 @@
+//! Typed access to the UEFI real-time clock.
+//!
+//! [`EfiTime`] mirrors `efi::Time` with the time zone and daylight fields decoded, and validates every field against
+//! the ranges allowed by the UEFI specification when converting from or to the raw structure.
+//!
+use bitflags::bitflags;
+use r_efi::efi;
+
+bitflags! {
+    /// Daylight saving time state of an [`EfiTime`] (`EFI_TIME_ADJUST_DAYLIGHT`, `EFI_TIME_IN_DAYLIGHT`).
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct Daylight: u8 {
+        const ADJUST_DAYLIGHT = efi::TIME_ADJUST_DAYLIGHT;
+        const IN_DAYLIGHT = efi::TIME_IN_DAYLIGHT;
+    }
+}
+
+/// Time zone of an [`EfiTime`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TimeZone {
+    /// The time is local time (`EFI_UNSPECIFIED_TIMEZONE`).
+    Unspecified,
+    /// Offset from UTC in minutes, in the range -1440 to 1440.
+    ///
+    /// Follows the UEFI convention: local time is the UTC time *minus* the offset.
+    Offset(i16),
+}
+
+/// A validated UEFI time.
+///
+/// # Example
+/// ```
+/// use runtime_services::time_services::{Daylight, EfiTime, TimeZone};
+///
+/// let time = EfiTime {
+///     year: 2024,
+///     month: 2,
+///     day: 29,
+///     hour: 13,
+///     minute: 30,
+///     second: 0,
+///     nanosecond: 0,
+///     time_zone: TimeZone::Offset(480),
+///     daylight: Daylight::empty(),
+/// };
+/// assert!(time.is_valid());
+///
+/// let raw: r_efi::efi::Time = time.try_into().unwrap();
+/// assert_eq!(EfiTime::try_from(raw), Ok(time));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EfiTime {
+    /// 1900 - 9999
+    pub year: u16,
+    /// 1 - 12
+    pub month: u8,
+    /// 1 - 31, bounded by the number of days in the month.
+    pub day: u8,
+    /// 0 - 23
+    pub hour: u8,
+    /// 0 - 59
+    pub minute: u8,
+    /// 0 - 59
+    pub second: u8,
+    /// 0 - 999,999,999
+    pub nanosecond: u32,
+    /// Time zone of the time.
+    pub time_zone: TimeZone,
+    /// Daylight saving time state.
+    pub daylight: Daylight,
+}
+
+impl EfiTime {
+    /// Return true if every field is in the range allowed by the UEFI specification.
+    pub fn is_valid(&self) -> bool {
+        (1900..=9999).contains(&self.year)
+            && (1..=12).contains(&self.month)
+            && (1..=days_in_month(self.year, self.month)).contains(&self.day)
+            && self.hour <= 23
+            && self.minute <= 59
+            && self.second <= 59
+            && self.nanosecond <= 999_999_999
+            && match self.time_zone {
+                TimeZone::Unspecified => true,
+                TimeZone::Offset(offset) => (-1440..=1440).contains(&offset),
+            }
+    }
+}
+
+impl TryFrom<efi::Time> for EfiTime {
+    type Error = efi::Status;
+
+    /// Returns `efi::Status::INVALID_PARAMETER` if any field is out of range or unknown daylight bits are set.
+    fn try_from(time: efi::Time) -> Result<Self, Self::Error> {
+        let time = EfiTime {
+            year: time.year,
+            month: time.month,
+            day: time.day,
+            hour: time.hour,
+            minute: time.minute,
+            second: time.second,
+            nanosecond: time.nanosecond,
+            time_zone: match time.timezone {
+                efi::UNSPECIFIED_TIMEZONE => TimeZone::Unspecified,
+                offset => TimeZone::Offset(offset),
+            },
+            daylight: Daylight::from_bits(time.daylight).ok_or(efi::Status::INVALID_PARAMETER)?,
+        };
+        if time.is_valid() {
+            Ok(time)
+        } else {
+            Err(efi::Status::INVALID_PARAMETER)
+        }
+    }
+}
+
+impl TryFrom<EfiTime> for efi::Time {
+    type Error = efi::Status;
+
+    /// Returns `efi::Status::INVALID_PARAMETER` if any field is out of range.
+    fn try_from(time: EfiTime) -> Result<Self, Self::Error> {
+        if !time.is_valid() {
+            return Err(efi::Status::INVALID_PARAMETER);
+        }
+        Ok(efi::Time {
+            year: time.year,
+            month: time.month,
+            day: time.day,
+            hour: time.hour,
+            minute: time.minute,
+            second: time.second,
+            pad1: 0,
+            nanosecond: time.nanosecond,
+            timezone: match time.time_zone {
+                TimeZone::Unspecified => efi::UNSPECIFIED_TIMEZONE,
+                TimeZone::Offset(offset) => offset,
+            },
+            daylight: time.daylight.bits(),
+            pad2: 0,
+        })
+    }
+}
+
+/// Capabilities of the real-time clock, as reported by GetTime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeCapabilities {
+    /// Resolution of the clock in counts per second.
+    pub resolution: u32,
+    /// Accuracy of the clock in parts per million (units of 1e-6).
+    pub accuracy: u32,
+    /// True if setting the time clears the time below the resolution reporting level.
+    pub sets_to_zero: bool,
+}
+
+impl From<efi::TimeCapabilities> for TimeCapabilities {
+    fn from(capabilities: efi::TimeCapabilities) -> Self {
+        Self {
+            resolution: capabilities.resolution,
+            accuracy: capabilities.accuracy,
+            sets_to_zero: capabilities.sets_to_zero.into(),
+        }
+    }
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_TIME: EfiTime = EfiTime {
+        year: 2024,
+        month: 2,
+        day: 29,
+        hour: 23,
+        minute: 59,
+        second: 59,
+        nanosecond: 999_999_999,
+        time_zone: TimeZone::Offset(-1440),
+        daylight: Daylight::ADJUST_DAYLIGHT,
+    };
+
+    #[test]
+    fn test_validation() {
+        assert!(TEST_TIME.is_valid());
+        assert!(EfiTime { time_zone: TimeZone::Unspecified, ..TEST_TIME }.is_valid());
+        assert!(!EfiTime { year: 1899, ..TEST_TIME }.is_valid());
+        assert!(!EfiTime { month: 13, ..TEST_TIME }.is_valid());
+        assert!(!EfiTime { month: 0, ..TEST_TIME }.is_valid());
+        assert!(!EfiTime { day: 0, ..TEST_TIME }.is_valid());
+        assert!(!EfiTime { year: 2023, ..TEST_TIME }.is_valid());
+        assert!(!EfiTime { year: 1900, ..TEST_TIME }.is_valid());
+        assert!(EfiTime { year: 2000, ..TEST_TIME }.is_valid());
+        assert!(!EfiTime { month: 4, day: 31, ..TEST_TIME }.is_valid());
+        assert!(!EfiTime { hour: 24, ..TEST_TIME }.is_valid());
+        assert!(!EfiTime { minute: 60, ..TEST_TIME }.is_valid());
+        assert!(!EfiTime { second: 60, ..TEST_TIME }.is_valid());
+        assert!(!EfiTime { nanosecond: 1_000_000_000, ..TEST_TIME }.is_valid());
+        assert!(!EfiTime { time_zone: TimeZone::Offset(1441), ..TEST_TIME }.is_valid());
+    }
+
+    #[test]
+    fn test_efi_time_conversions() {
+        let raw = efi::Time::try_from(TEST_TIME).unwrap();
+        assert_eq!(raw.timezone, -1440);
+        assert_eq!(raw.daylight, efi::TIME_ADJUST_DAYLIGHT);
+        assert_eq!(EfiTime::try_from(raw), Ok(TEST_TIME));
+
+        let unspecified = efi::Time { timezone: efi::UNSPECIFIED_TIMEZONE, ..raw };
+        assert_eq!(EfiTime::try_from(unspecified).map(|time| time.time_zone), Ok(TimeZone::Unspecified));
+
+        assert_eq!(EfiTime::try_from(efi::Time { daylight: 0x04, ..raw }), Err(efi::Status::INVALID_PARAMETER));
+        assert_eq!(EfiTime::try_from(efi::Time { month: 0, ..raw }), Err(efi::Status::INVALID_PARAMETER));
+        assert_eq!(efi::Time::try_from(EfiTime { day: 30, ..TEST_TIME }).err(), Some(efi::Status::INVALID_PARAMETER));
+    }
+}
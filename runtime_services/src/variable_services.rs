@@ -0,0 +1,364 @@
+//! Typed access to UEFI variable data.
+//!
+//! Variable payloads are plain byte buffers. [`FromVariable`] describes how a payload is decoded into a Rust type so
+//! that [`RuntimeServices::get_variable`](crate::RuntimeServices::get_variable) can return typed values directly.
+//! [`ToVariable`] and [`VariableBuilder`] cover the opposite direction. [`VariableNames`] enumerates the variables
+//! present in the store.
+//!
+use alloc::{boxed::Box, vec, vec::Vec};
+
+use bitflags::bitflags;
+use guid::{guid, ZERO};
+use r_efi::efi;
+
+use crate::RuntimeServices;
+
+/// Vendor GUID for the architecturally defined variables (`EFI_GLOBAL_VARIABLE`).
+pub const GLOBAL_VARIABLE: efi::Guid = guid!("8BE4DF61-93CA-11D2-AA0D-00E098032B8C");
+
+bitflags! {
+    /// Attributes of a UEFI variable (`EFI_VARIABLE_*`).
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct VariableAttributes: u32 {
+        const NON_VOLATILE = efi::VARIABLE_NON_VOLATILE;
+        const BOOTSERVICE_ACCESS = efi::VARIABLE_BOOTSERVICE_ACCESS;
+        const RUNTIME_ACCESS = efi::VARIABLE_RUNTIME_ACCESS;
+        const HARDWARE_ERROR_RECORD = efi::VARIABLE_HARDWARE_ERROR_RECORD;
+        const AUTHENTICATED_WRITE_ACCESS = efi::VARIABLE_AUTHENTICATED_WRITE_ACCESS;
+        const TIME_BASED_AUTHENTICATED_WRITE_ACCESS = efi::VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS;
+        const APPEND_WRITE = efi::VARIABLE_APPEND_WRITE;
+        const ENHANCED_AUTHENTICATED_ACCESS = efi::VARIABLE_ENHANCED_AUTHENTICATED_ACCESS;
+    }
+}
+
+impl VariableAttributes {
+    /// Non-volatile, accessible during boot services only.
+    pub const NV_BS: Self = Self::NON_VOLATILE.union(Self::BOOTSERVICE_ACCESS);
+    /// Volatile, accessible during boot services and at runtime.
+    pub const BS_RT: Self = Self::BOOTSERVICE_ACCESS.union(Self::RUNTIME_ACCESS);
+    /// Non-volatile, accessible during boot services and at runtime, as most architectural variables.
+    pub const NV_BS_RT: Self = Self::NV_BS.union(Self::RUNTIME_ACCESS);
+    /// [`Self::NV_BS_RT`] with time-based authenticated writes, as the Secure Boot variables.
+    pub const NV_BS_RT_AT: Self = Self::NV_BS_RT.union(Self::TIME_BASED_AUTHENTICATED_WRITE_ACCESS);
+
+    /// Check that the attributes can be passed to SetVariable.
+    ///
+    /// Returns `efi::Status::INVALID_PARAMETER` for undefined bits, runtime access without boot services access,
+    /// hardware error records that are not non-volatile with boot services and runtime access, and writes requesting
+    /// more than one authentication scheme. No attributes at all is valid and deletes the variable.
+    pub fn validate(self) -> Result<(), efi::Status> {
+        let authentication = [
+            Self::AUTHENTICATED_WRITE_ACCESS,
+            Self::TIME_BASED_AUTHENTICATED_WRITE_ACCESS,
+            Self::ENHANCED_AUTHENTICATED_ACCESS,
+        ];
+        let is_valid = Self::from_bits(self.bits()).is_some()
+            && (!self.contains(Self::RUNTIME_ACCESS) || self.contains(Self::BOOTSERVICE_ACCESS))
+            && (!self.contains(Self::HARDWARE_ERROR_RECORD) || self.contains(Self::NV_BS_RT))
+            && authentication.iter().filter(|&&scheme| self.contains(scheme)).count() <= 1;
+        if is_valid {
+            Ok(())
+        } else {
+            Err(efi::Status::INVALID_PARAMETER)
+        }
+    }
+}
+
+/// A type that can be decoded from the contents of a UEFI variable.
+///
+/// # Example
+/// ```
+/// use r_efi::efi;
+/// use runtime_services::FromVariable;
+///
+/// struct BootTimeout(u16);
+///
+/// impl FromVariable for BootTimeout {
+///     fn from_variable(data: &[u8]) -> Result<Self, efi::Status> {
+///         u16::from_variable(data).map(BootTimeout)
+///     }
+/// }
+/// ```
+pub trait FromVariable: Sized {
+    /// Decode the variable contents in `data`.
+    ///
+    /// Returns `efi::Status::BAD_BUFFER_SIZE` if `data` does not have the size expected by the type.
+    fn from_variable(data: &[u8]) -> Result<Self, efi::Status>;
+}
+
+macro_rules! impl_from_variable_for_integer {
+    ($($int:ty),*) => {
+        $(
+            impl FromVariable for $int {
+                fn from_variable(data: &[u8]) -> Result<Self, efi::Status> {
+                    Ok(<$int>::from_le_bytes(data.try_into().map_err(|_| efi::Status::BAD_BUFFER_SIZE)?))
+                }
+            }
+        )*
+    };
+}
+
+impl_from_variable_for_integer!(u8, u16, u32, u64);
+
+impl FromVariable for bool {
+    fn from_variable(data: &[u8]) -> Result<Self, efi::Status> {
+        match data {
+            [value] => Ok(*value != 0),
+            _ => Err(efi::Status::BAD_BUFFER_SIZE),
+        }
+    }
+}
+
+impl<const N: usize> FromVariable for [u8; N] {
+    fn from_variable(data: &[u8]) -> Result<Self, efi::Status> {
+        data.try_into().map_err(|_| efi::Status::BAD_BUFFER_SIZE)
+    }
+}
+
+impl FromVariable for Vec<u8> {
+    fn from_variable(data: &[u8]) -> Result<Self, efi::Status> {
+        Ok(data.to_vec())
+    }
+}
+
+impl FromVariable for Box<[u8]> {
+    fn from_variable(data: &[u8]) -> Result<Self, efi::Status> {
+        Ok(data.into())
+    }
+}
+
+/// A type that can be encoded as the contents of a UEFI variable.
+pub trait ToVariable {
+    /// Encode the value as variable contents.
+    fn to_variable(&self) -> Vec<u8>;
+}
+
+macro_rules! impl_to_variable_for_integer {
+    ($($int:ty),*) => {
+        $(
+            impl ToVariable for $int {
+                fn to_variable(&self) -> Vec<u8> {
+                    self.to_le_bytes().to_vec()
+                }
+            }
+        )*
+    };
+}
+
+impl_to_variable_for_integer!(u8, u16, u32, u64);
+
+impl ToVariable for bool {
+    fn to_variable(&self) -> Vec<u8> {
+        vec![*self as u8]
+    }
+}
+
+impl ToVariable for [u8] {
+    fn to_variable(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
+impl<const N: usize> ToVariable for [u8; N] {
+    fn to_variable(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
+impl ToVariable for Vec<u8> {
+    fn to_variable(&self) -> Vec<u8> {
+        self.clone()
+    }
+}
+
+/// Builder for writing or deleting a UEFI variable.
+///
+/// # Example
+/// ```no_run
+/// use r_efi::efi;
+/// use runtime_services::{StandardRuntimeServices, VariableBuilder, GLOBAL_VARIABLE};
+///
+/// fn set_boot_timeout(runtime_services: &StandardRuntimeServices, timeout: u16) -> Result<(), efi::Status> {
+///     // "Timeout" as a null-terminated UCS-2 string.
+///     let name = [0x54, 0x69, 0x6D, 0x65, 0x6F, 0x75, 0x74, 0x00];
+///     VariableBuilder::new(runtime_services, &name, &GLOBAL_VARIABLE)
+///         .non_volatile()
+///         .bootservice_access()
+///         .runtime_access()
+///         .set(&timeout)
+/// }
+/// ```
+pub struct VariableBuilder<'a, R: RuntimeServices> {
+    runtime_services: &'a R,
+    name: &'a [u16],
+    namespace: &'a efi::Guid,
+    attributes: VariableAttributes,
+}
+
+impl<'a, R: RuntimeServices> VariableBuilder<'a, R> {
+    /// Create a builder for the variable `name` in the `namespace` vendor GUID, with no attributes set.
+    ///
+    /// `name` must be a null-terminated UCS-2 string.
+    pub fn new(runtime_services: &'a R, name: &'a [u16], namespace: &'a efi::Guid) -> Self {
+        Self { runtime_services, name, namespace, attributes: VariableAttributes::empty() }
+    }
+
+    /// Add `attributes` to the attributes of the variable.
+    pub fn attributes(mut self, attributes: VariableAttributes) -> Self {
+        self.attributes |= attributes;
+        self
+    }
+
+    /// Store the variable in non-volatile storage.
+    pub fn non_volatile(self) -> Self {
+        self.attributes(VariableAttributes::NON_VOLATILE)
+    }
+
+    /// Make the variable accessible during boot services.
+    pub fn bootservice_access(self) -> Self {
+        self.attributes(VariableAttributes::BOOTSERVICE_ACCESS)
+    }
+
+    /// Make the variable accessible after ExitBootServices.
+    pub fn runtime_access(self) -> Self {
+        self.attributes(VariableAttributes::RUNTIME_ACCESS)
+    }
+
+    /// Mark the write as a time-based authenticated write. The payload must start with the authentication descriptor.
+    pub fn time_based_authenticated_write_access(self) -> Self {
+        self.attributes(VariableAttributes::TIME_BASED_AUTHENTICATED_WRITE_ACCESS)
+    }
+
+    /// Append the payload to the existing variable contents instead of replacing them.
+    pub fn append_write(self) -> Self {
+        self.attributes(VariableAttributes::APPEND_WRITE)
+    }
+
+    /// Encode `value` and write it to the variable.
+    pub fn set<T: ToVariable + ?Sized>(self, value: &T) -> Result<(), efi::Status> {
+        self.runtime_services.set_variable(self.name, self.namespace, self.attributes, value)
+    }
+
+    /// Delete the variable by writing it with a zero-sized payload.
+    ///
+    /// Authenticated variables cannot be deleted this way; they require a signed, empty payload written with
+    /// [`Self::set`] instead.
+    pub fn delete(self) -> Result<(), efi::Status> {
+        let attributes = self.attributes - VariableAttributes::APPEND_WRITE;
+        self.runtime_services.set_variable_bytes(self.name, self.namespace, attributes, &[])
+    }
+}
+
+/// Iterator over the variable store, created by [`RuntimeServices::variables`](crate::RuntimeServices::variables).
+///
+/// Each item is the null-terminated UCS-2 name of a variable along with its vendor GUID. The name buffer is grown as
+/// needed while walking the store. If the firmware reports an error other than the end of the enumeration, the error
+/// is yielded once and the iteration stops.
+pub struct VariableNames<'a, R: RuntimeServices> {
+    runtime_services: &'a R,
+    name: Vec<u16>,
+    namespace: efi::Guid,
+    is_done: bool,
+}
+
+impl<'a, R: RuntimeServices> VariableNames<'a, R> {
+    pub(crate) fn new(runtime_services: &'a R) -> Self {
+        // An empty name starts the enumeration. The initial capacity fits most variable names in a single call.
+        let name = vec![0; 64];
+        Self { runtime_services, name, namespace: ZERO, is_done: false }
+    }
+}
+
+impl<R: RuntimeServices> Iterator for VariableNames<'_, R> {
+    type Item = Result<(Vec<u16>, efi::Guid), efi::Status>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_done {
+            return None;
+        }
+        loop {
+            match self.runtime_services.get_next_variable_name_into(&mut self.name, &mut self.namespace) {
+                Ok(()) => {
+                    let Some(len) = self.name.iter().position(|&c| c == 0) else {
+                        self.is_done = true;
+                        return Some(Err(efi::Status::COMPROMISED_DATA));
+                    };
+                    return Some(Ok((self.name[..=len].to_vec(), self.namespace)));
+                }
+                // The previous name is preserved at the start of the buffer, so it can be grown in place.
+                Err((efi::Status::BUFFER_TOO_SMALL, len)) if len > self.name.len() => self.name.resize(len, 0),
+                Err((efi::Status::NOT_FOUND, _)) => {
+                    self.is_done = true;
+                    return None;
+                }
+                Err((status, _)) => {
+                    self.is_done = true;
+                    return Some(Err(status));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_attributes() {
+        for attributes in [
+            VariableAttributes::empty(),
+            VariableAttributes::NV_BS,
+            VariableAttributes::BS_RT,
+            VariableAttributes::NV_BS_RT,
+            VariableAttributes::NV_BS_RT_AT | VariableAttributes::APPEND_WRITE,
+            VariableAttributes::NV_BS_RT | VariableAttributes::HARDWARE_ERROR_RECORD,
+        ] {
+            assert_eq!(attributes.validate(), Ok(()), "{attributes:?}");
+        }
+        for attributes in [
+            VariableAttributes::RUNTIME_ACCESS,
+            VariableAttributes::NV_BS | VariableAttributes::HARDWARE_ERROR_RECORD,
+            VariableAttributes::NV_BS_RT_AT | VariableAttributes::ENHANCED_AUTHENTICATED_ACCESS,
+            VariableAttributes::from_bits_retain(0x100) | VariableAttributes::NV_BS,
+        ] {
+            assert_eq!(attributes.validate(), Err(efi::Status::INVALID_PARAMETER), "{attributes:?}");
+        }
+    }
+
+    #[test]
+    fn test_from_variable_integers() {
+        assert_eq!(u8::from_variable(&[0x12]), Ok(0x12));
+        assert_eq!(u16::from_variable(&[0x34, 0x12]), Ok(0x1234));
+        assert_eq!(u32::from_variable(&[0x78, 0x56, 0x34, 0x12]), Ok(0x12345678));
+        assert_eq!(u64::from_variable(&[1, 0, 0, 0, 0, 0, 0, 0]), Ok(1));
+        assert_eq!(u32::from_variable(&[0x78, 0x56]), Err(efi::Status::BAD_BUFFER_SIZE));
+        assert_eq!(u16::from_variable(&[]), Err(efi::Status::BAD_BUFFER_SIZE));
+    }
+
+    #[test]
+    fn test_from_variable_bool() {
+        assert_eq!(bool::from_variable(&[0]), Ok(false));
+        assert_eq!(bool::from_variable(&[1]), Ok(true));
+        assert_eq!(bool::from_variable(&[0xFF]), Ok(true));
+        assert_eq!(bool::from_variable(&[1, 0]), Err(efi::Status::BAD_BUFFER_SIZE));
+    }
+
+    #[test]
+    fn test_to_variable() {
+        assert_eq!(0x1234u16.to_variable(), [0x34, 0x12]);
+        assert_eq!(0x12345678u32.to_variable(), [0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(true.to_variable(), [1]);
+        assert_eq!([1u8, 2, 3].to_variable(), [1, 2, 3]);
+        assert_eq!(vec![4u8, 5].to_variable(), [4, 5]);
+        assert_eq!(u32::from_variable(&7u32.to_variable()), Ok(7));
+    }
+
+    #[test]
+    fn test_from_variable_byte_buffers() {
+        assert_eq!(<[u8; 3]>::from_variable(&[1, 2, 3]), Ok([1, 2, 3]));
+        assert_eq!(<[u8; 3]>::from_variable(&[1, 2]), Err(efi::Status::BAD_BUFFER_SIZE));
+        assert_eq!(Vec::<u8>::from_variable(&[1, 2]), Ok(vec![1, 2]));
+        assert_eq!(Box::<[u8]>::from_variable(&[]), Ok(Box::from([])));
+    }
+}
@@ -0,0 +1,241 @@
+//! Virtual address change support for runtime drivers.
+//!
+//! When the OS calls SetVirtualAddressMap, every runtime driver must convert the physical pointers it will use at
+//! runtime into virtual pointers, from an `EVT_SIGNAL_VIRTUAL_ADDRESS_CHANGE` notification. [`VirtualAddressFixups`]
+//! keeps a fixed-size registry of the pointers to convert and applies the conversion automatically once its event is
+//! registered with [`VirtualAddressFixups::register_event`].
+//!
+//! [`RuntimeCallback`] runs a function of the driver from the same notification, with a context that must be
+//! [`RuntimeSafe`].
+//!
+use core::{
+    ffi::c_void,
+    ptr,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+
+use r_efi::efi;
+
+use crate::{runtime_safe::RuntimeSafe, RuntimeServices, StandardRuntimeServices};
+
+/// Registry of up to `N` pointers converted to virtual addresses when SetVirtualAddressMap is called.
+///
+/// The pointer to the runtime services table held by the associated [`StandardRuntimeServices`] is converted last,
+/// so that the instance remains usable at runtime.
+///
+/// # Example
+/// ```no_run
+/// use r_efi::efi;
+/// use runtime_services::{virtual_address::VirtualAddressFixups, StandardRuntimeServices};
+///
+/// static RUNTIME_SERVICES: StandardRuntimeServices = StandardRuntimeServices::new_uninit();
+/// static FIXUPS: VirtualAddressFixups<4> = VirtualAddressFixups::new(&RUNTIME_SERVICES);
+/// static mut MAILBOX: *mut u32 = core::ptr::null_mut();
+///
+/// pub extern "efiapi" fn efi_main(_image_handle: efi::Handle, system_table: *const efi::SystemTable) -> efi::Status {
+///     let system_table = unsafe { &*system_table };
+///     RUNTIME_SERVICES.initialize(unsafe { &*system_table.runtime_services });
+///     unsafe { FIXUPS.register(core::ptr::addr_of_mut!(MAILBOX)) }.unwrap();
+///     FIXUPS.register_event(unsafe { &*system_table.boot_services }).unwrap();
+///     efi::Status::SUCCESS
+/// }
+/// ```
+pub struct VirtualAddressFixups<const N: usize> {
+    runtime_services: &'static StandardRuntimeServices<'static>,
+    pointers: [AtomicPtr<*mut c_void>; N],
+    len: AtomicUsize,
+}
+
+impl<const N: usize> VirtualAddressFixups<N> {
+    /// Create an empty registry converting pointers through `runtime_services`.
+    pub const fn new(runtime_services: &'static StandardRuntimeServices<'static>) -> Self {
+        Self { runtime_services, pointers: [const { AtomicPtr::new(ptr::null_mut()) }; N], len: AtomicUsize::new(0) }
+    }
+
+    /// Register the pointer stored at `pointer` for conversion.
+    ///
+    /// Returns `efi::Status::OUT_OF_RESOURCES` if the registry is full.
+    ///
+    /// # Safety
+    /// `pointer` must remain valid for writes until SetVirtualAddressMap is called, and nothing may access it while
+    /// the conversion runs.
+    pub unsafe fn register<T: RuntimeSafe>(&self, pointer: *mut *mut T) -> Result<(), efi::Status> {
+        let index = self.len.fetch_add(1, Ordering::SeqCst);
+        if index >= N {
+            self.len.fetch_sub(1, Ordering::SeqCst);
+            return Err(efi::Status::OUT_OF_RESOURCES);
+        }
+        self.pointers[index].store(pointer as *mut *mut c_void, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Return the number of registered pointers.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst).min(N)
+    }
+
+    /// Return true if no pointers are registered.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Convert every registered pointer, then the runtime services table pointer, to virtual addresses.
+    ///
+    /// Every conversion is attempted, and the first error encountered is returned.
+    ///
+    /// # Safety
+    /// Must only be called from an `EVT_SIGNAL_VIRTUAL_ADDRESS_CHANGE` notification, and only once.
+    pub unsafe fn apply(&self) -> Result<(), efi::Status> {
+        let mut result = Ok(());
+        for pointer in &self.pointers[..self.len()] {
+            let pointer = pointer.load(Ordering::SeqCst);
+            if pointer.is_null() {
+                continue;
+            }
+            result = result.and(self.runtime_services.convert_pointer(&mut *pointer));
+        }
+        result.and(self.runtime_services.convert_to_virtual())
+    }
+
+    /// Create the `EVT_SIGNAL_VIRTUAL_ADDRESS_CHANGE` event that calls [`Self::apply`].
+    ///
+    /// The event is never closed, since it must stay registered until SetVirtualAddressMap is called.
+    pub fn register_event(&'static self, boot_services: &efi::BootServices) -> Result<efi::Event, efi::Status> {
+        extern "efiapi" fn notify<const N: usize>(_event: efi::Event, context: *mut c_void) {
+            // SAFETY: the context is the `&'static self` given to `register_event`, and the firmware signals this
+            // event exactly once, from SetVirtualAddressMap.
+            let fixups = unsafe { &*(context as *const VirtualAddressFixups<N>) };
+            // There is no way to report a failure from within SetVirtualAddressMap.
+            let _ = unsafe { fixups.apply() };
+        }
+
+        let mut event = ptr::null_mut();
+        let status = (boot_services.create_event)(
+            efi::EVT_SIGNAL_VIRTUAL_ADDRESS_CHANGE,
+            efi::TPL_NOTIFY,
+            Some(notify::<N>),
+            self as *const Self as *mut c_void,
+            &mut event,
+        );
+
+        if status.is_error() {
+            Err(status)
+        } else {
+            Ok(event)
+        }
+    }
+}
+
+/// Function called with a [`RuntimeSafe`] context when SetVirtualAddressMap is called.
+///
+/// The callback and its context live in a `static`, so nothing is allocated from boot services memory.
+///
+/// # Example
+/// ```no_run
+/// use core::sync::atomic::{AtomicBool, Ordering};
+/// use r_efi::efi;
+/// use runtime_services::virtual_address::RuntimeCallback;
+///
+/// static VIRTUAL_MODE: RuntimeCallback<AtomicBool> =
+///     RuntimeCallback::new(AtomicBool::new(false), |virtual_mode| virtual_mode.store(true, Ordering::SeqCst));
+///
+/// fn install(boot_services: &efi::BootServices) -> Result<(), efi::Status> {
+///     VIRTUAL_MODE.register_event(boot_services)?;
+///     Ok(())
+/// }
+/// ```
+///
+/// Contexts owning boot services memory are rejected:
+/// ```compile_fail
+/// use runtime_services::virtual_address::RuntimeCallback;
+///
+/// static LOG: RuntimeCallback<Vec<u8>> = RuntimeCallback::new(Vec::new(), |_log| ());
+/// ```
+pub struct RuntimeCallback<T: RuntimeSafe + Sync> {
+    context: T,
+    callback: fn(&T),
+}
+
+impl<T: RuntimeSafe + Sync> RuntimeCallback<T> {
+    /// Create a callback calling `callback` with `context`.
+    pub const fn new(context: T, callback: fn(&T)) -> Self {
+        Self { context, callback }
+    }
+
+    /// Return the context passed to the callback.
+    pub fn context(&self) -> &T {
+        &self.context
+    }
+
+    /// Create the `EVT_SIGNAL_VIRTUAL_ADDRESS_CHANGE` event that calls the callback.
+    ///
+    /// The event is never closed, since it must stay registered until SetVirtualAddressMap is called.
+    pub fn register_event(&'static self, boot_services: &efi::BootServices) -> Result<efi::Event, efi::Status> {
+        let mut event = ptr::null_mut();
+        let status = (boot_services.create_event)(
+            efi::EVT_SIGNAL_VIRTUAL_ADDRESS_CHANGE,
+            efi::TPL_NOTIFY,
+            Some(Self::notify),
+            self as *const Self as *mut c_void,
+            &mut event,
+        );
+
+        if status.is_error() {
+            Err(status)
+        } else {
+            Ok(event)
+        }
+    }
+
+    extern "efiapi" fn notify(_event: efi::Event, context: *mut c_void) {
+        // SAFETY: the context is the `&'static self` given to `register_event`.
+        let this = unsafe { &*(context as *const Self) };
+        (this.callback)(&this.context);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tests::{mock_efi_runtime_services, VIRTUAL_ADDRESS_OFFSET};
+
+    #[test]
+    fn test_apply() {
+        let efi_runtime_services: &'static efi::RuntimeServices = Box::leak(Box::new(mock_efi_runtime_services()));
+        let runtime_services: &'static StandardRuntimeServices =
+            Box::leak(Box::new(StandardRuntimeServices::new_uninit()));
+        runtime_services.initialize(efi_runtime_services);
+        let fixups = VirtualAddressFixups::<2>::new(runtime_services);
+        assert!(fixups.is_empty());
+
+        let mut first = 0x1000 as *mut u32;
+        let mut second = ptr::null_mut::<u64>();
+        let mut third = 0x3000 as *mut u8;
+        unsafe {
+            fixups.register(&mut first).unwrap();
+            fixups.register(&mut second).unwrap();
+            assert_eq!(fixups.register(&mut third), Err(efi::Status::OUT_OF_RESOURCES));
+        }
+        assert_eq!(fixups.len(), 2);
+
+        unsafe { fixups.apply() }.unwrap();
+        assert_eq!(first as usize, 0x1000 + VIRTUAL_ADDRESS_OFFSET);
+        assert!(second.is_null());
+        assert_eq!(third as usize, 0x3000);
+        assert_eq!(
+            runtime_services.efi_runtime_services.load(Ordering::SeqCst) as usize,
+            efi_runtime_services as *const efi::RuntimeServices as usize + VIRTUAL_ADDRESS_OFFSET
+        );
+    }
+
+    #[test]
+    fn test_runtime_callback() {
+        static CALLBACK: RuntimeCallback<AtomicUsize> =
+            RuntimeCallback::new(AtomicUsize::new(0), |count| _ = count.fetch_add(1, Ordering::SeqCst));
+
+        RuntimeCallback::<AtomicUsize>::notify(ptr::null_mut(), &CALLBACK as *const _ as *mut c_void);
+        RuntimeCallback::<AtomicUsize>::notify(ptr::null_mut(), &CALLBACK as *const _ as *mut c_void);
+        assert_eq!(CALLBACK.context().load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,292 @@
+//! `#[repr(C)]` bridges for sharing Rust traits between separately built images.
+//!
+//! The Rust ABI is not stable across separately compiled images, so a trait object cannot be handed to another image
+//! directly. [`abi_bridge!`](crate::abi_bridge) generates a `#[repr(C)]` vtable with `efiapi` function pointers for a
+//! trait, which can be installed as the interface of a UEFI protocol by one image and used as an implementation of
+//! the same trait by another.
+//!
+//! Every bridge starts with a [`BridgeHeader`] carrying a version and the size of the vtable. Methods may only be
+//! appended to a bridge, together with a version bump, so that consumers built against an older version keep working
+//! with newer producers.
+//!
+use core::{ffi::c_void, mem};
+
+use r_efi::efi;
+
+/// Header at the start of every bridge generated by [`abi_bridge!`](crate::abi_bridge).
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BridgeHeader {
+    /// Version of the bridge definition used by the producer.
+    pub version: u32,
+    /// Size in bytes of the producer's bridge, header included.
+    pub size: u32,
+}
+
+impl BridgeHeader {
+    /// Create the header for a bridge of type `B` at the given version.
+    pub const fn new<B>(version: u32) -> Self {
+        Self { version, size: mem::size_of::<B>() as u32 }
+    }
+}
+
+/// Error returned when an interface cannot be used as a bridge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BridgeError {
+    /// The interface pointer is null or not aligned for the bridge.
+    InvalidInterface,
+    /// The producer implements an older version of the bridge than the consumer requires.
+    IncompatibleVersion {
+        /// Version provided by the producer.
+        found: u32,
+        /// Minimum version required by the consumer.
+        required: u32,
+    },
+    /// The producer's bridge is smaller than the bridge layout expected by the consumer.
+    InvalidSize,
+}
+
+impl From<BridgeError> for efi::Status {
+    fn from(error: BridgeError) -> Self {
+        match error {
+            BridgeError::InvalidInterface => efi::Status::INVALID_PARAMETER,
+            BridgeError::IncompatibleVersion { .. } | BridgeError::InvalidSize => efi::Status::INCOMPATIBLE_VERSION,
+        }
+    }
+}
+
+/// Bridge generated by [`abi_bridge!`](crate::abi_bridge!).
+///
+/// # Safety
+/// Implementors must start with a [`BridgeHeader`]. Implemented by [`abi_bridge!`](crate::abi_bridge!) only.
+pub unsafe trait Bridge: Sized {
+    /// Version of the bridge definition.
+    const VERSION: u32;
+
+    /// Use the protocol interface at `interface` as a bridge, after checking its version and size.
+    ///
+    /// # Safety
+    /// `interface` must be null or point to a bridge of this type built by an image using a compatible definition of
+    /// it, and the bridge must remain valid for `'a`.
+    unsafe fn from_interface<'a>(interface: *const c_void) -> Result<&'a Self, BridgeError>;
+}
+
+/// Validate that `interface` points to a bridge of type `B` compatible with `version`.
+///
+/// # Safety
+/// `interface` must be null or point to a readable [`BridgeHeader`].
+#[doc(hidden)]
+pub unsafe fn check_interface<B>(interface: *const c_void, version: u32) -> Result<(), BridgeError> {
+    if interface.is_null() || !interface.cast::<B>().is_aligned() {
+        return Err(BridgeError::InvalidInterface);
+    }
+    let header = *interface.cast::<BridgeHeader>();
+    if header.version < version {
+        return Err(BridgeError::IncompatibleVersion { found: header.version, required: version });
+    }
+    if (header.size as usize) < mem::size_of::<B>() {
+        return Err(BridgeError::InvalidSize);
+    }
+    Ok(())
+}
+
+/// Generate a `#[repr(C)]` bridge that exposes a Rust trait through `efiapi` function pointers.
+///
+/// The generated bridge type provides:
+/// - `new(implementation)` to build the bridge from a `'static` implementation of the trait, to be installed as a
+///   protocol interface.
+/// - `unsafe from_interface(interface)` to validate a protocol interface located in another image and use it as a
+///   bridge.
+/// - An implementation of the trait itself, forwarding every call through the function pointers.
+///
+/// Only `&self` methods are supported, and their argument and return types must be FFI-safe.
+///
+/// # Example
+/// ```
+/// use core::{cell::Cell, ffi::c_void};
+/// use mu_rust_helpers::abi_bridge;
+///
+/// pub trait Counter {
+///     fn add(&self, amount: u32) -> u32;
+///     fn reset(&self);
+/// }
+///
+/// abi_bridge! {
+///     /// Bridge for the [`Counter`] trait.
+///     pub struct CounterBridge: Counter, version 1 {
+///         fn add(&self, amount: u32) -> u32;
+///         fn reset(&self);
+///     }
+/// }
+///
+/// struct SimpleCounter(Cell<u32>);
+///
+/// impl Counter for SimpleCounter {
+///     fn add(&self, amount: u32) -> u32 {
+///         self.0.set(self.0.get() + amount);
+///         self.0.get()
+///     }
+///
+///     fn reset(&self) {
+///         self.0.set(0);
+///     }
+/// }
+///
+/// // Producer image: build the bridge and install it as a protocol interface.
+/// let counter: &'static SimpleCounter = Box::leak(Box::new(SimpleCounter(Cell::new(0))));
+/// let bridge: &'static CounterBridge = Box::leak(Box::new(CounterBridge::new(counter)));
+/// let interface = bridge as *const CounterBridge as *const c_void;
+///
+/// // Consumer image: validate the located interface and use it through the trait.
+/// let counter: &dyn Counter = unsafe { CounterBridge::from_interface(interface) }.unwrap();
+/// assert_eq!(counter.add(2), 2);
+/// ```
+#[macro_export]
+macro_rules! abi_bridge {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $bridge:ident: $trait:path, version $version:literal {
+            $(fn $method:ident(&self $(, $arg:ident: $arg_ty:ty)* $(,)?) $(-> $ret:ty)?;)*
+        }
+    ) => {
+        $(#[$attr])*
+        #[repr(C)]
+        $vis struct $bridge {
+            /// Version and size of the bridge.
+            pub header: $crate::abi_bridge::BridgeHeader,
+            this: *const core::ffi::c_void,
+            $($method: extern "efiapi" fn(*const core::ffi::c_void $(, $arg_ty)*) $(-> $ret)?,)*
+        }
+
+        impl $bridge {
+            /// Version of the bridge definition.
+            pub const VERSION: u32 = $version;
+
+            /// Create a bridge forwarding every call to `implementation`.
+            pub fn new<T: $trait + 'static>(implementation: &'static T) -> Self {
+                $(
+                    extern "efiapi" fn $method<T: $trait>(this: *const core::ffi::c_void $(, $arg: $arg_ty)*) $(-> $ret)? {
+                        // SAFETY: `this` is the `&'static T` given to `new`.
+                        let this = unsafe { &*(this as *const T) };
+                        <T as $trait>::$method(this $(, $arg)*)
+                    }
+                )*
+                Self {
+                    header: $crate::abi_bridge::BridgeHeader::new::<Self>(Self::VERSION),
+                    this: implementation as *const T as *const core::ffi::c_void,
+                    $($method: $method::<T>,)*
+                }
+            }
+
+            /// Use the protocol interface at `interface` as a bridge, after checking its version and size.
+            ///
+            /// # Safety
+            /// `interface` must be null or point to a bridge built with [`Self::new`] by an image using a compatible
+            /// definition of this bridge, and the bridge must remain valid for `'a`.
+            pub unsafe fn from_interface<'a>(
+                interface: *const core::ffi::c_void,
+            ) -> Result<&'a Self, $crate::abi_bridge::BridgeError> {
+                $crate::abi_bridge::check_interface::<Self>(interface, Self::VERSION)?;
+                Ok(&*(interface as *const Self))
+            }
+        }
+
+        // SAFETY: the bridge is `#[repr(C)]` and starts with its header.
+        unsafe impl $crate::abi_bridge::Bridge for $bridge {
+            const VERSION: u32 = $version;
+
+            unsafe fn from_interface<'a>(
+                interface: *const core::ffi::c_void,
+            ) -> Result<&'a Self, $crate::abi_bridge::BridgeError> {
+                $bridge::from_interface(interface)
+            }
+        }
+
+        impl $trait for $bridge {
+            $(
+                fn $method(&self $(, $arg: $arg_ty)*) $(-> $ret)? {
+                    (self.$method)(self.this $(, $arg)*)
+                }
+            )*
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::{cell::Cell, mem};
+
+    trait Counter {
+        fn add(&self, amount: u32, times: u32) -> u32;
+        fn get(&self) -> u32;
+        fn reset(&self);
+    }
+
+    abi_bridge! {
+        struct CounterBridge: Counter, version 2 {
+            fn add(&self, amount: u32, times: u32) -> u32;
+            fn get(&self) -> u32;
+            fn reset(&self);
+        }
+    }
+
+    struct TestCounter(Cell<u32>);
+
+    impl Counter for TestCounter {
+        fn add(&self, amount: u32, times: u32) -> u32 {
+            self.0.set(self.0.get() + amount * times);
+            self.0.get()
+        }
+
+        fn get(&self) -> u32 {
+            self.0.get()
+        }
+
+        fn reset(&self) {
+            self.0.set(0);
+        }
+    }
+
+    fn interface(bridge: &CounterBridge) -> *const c_void {
+        bridge as *const CounterBridge as *const c_void
+    }
+
+    #[test]
+    fn test_bridge_round_trip() {
+        let counter: &'static TestCounter = Box::leak(Box::new(TestCounter(Cell::new(1))));
+        let bridge = CounterBridge::new(counter);
+        assert_eq!(bridge.header, BridgeHeader { version: 2, size: mem::size_of::<CounterBridge>() as u32 });
+
+        let consumer: &dyn Counter = unsafe { CounterBridge::from_interface(interface(&bridge)) }.unwrap();
+        assert_eq!(consumer.add(3, 2), 7);
+        assert_eq!(consumer.get(), 7);
+        consumer.reset();
+        assert_eq!(counter.get(), 0);
+    }
+
+    #[test]
+    fn test_bridge_compatibility_checks() {
+        let counter: &'static TestCounter = Box::leak(Box::new(TestCounter(Cell::new(0))));
+        let mut bridge = CounterBridge::new(counter);
+
+        assert_eq!(
+            unsafe { CounterBridge::from_interface(core::ptr::null()) }.err(),
+            Some(BridgeError::InvalidInterface)
+        );
+
+        // Newer producers are accepted, older ones are not.
+        bridge.header.version = 3;
+        assert!(unsafe { CounterBridge::from_interface(interface(&bridge)) }.is_ok());
+        bridge.header.version = 1;
+        assert_eq!(
+            unsafe { CounterBridge::from_interface(interface(&bridge)) }.err(),
+            Some(BridgeError::IncompatibleVersion { found: 1, required: 2 })
+        );
+
+        bridge.header.version = 2;
+        bridge.header.size -= 1;
+        assert_eq!(unsafe { CounterBridge::from_interface(interface(&bridge)) }.err(), Some(BridgeError::InvalidSize));
+    }
+}
@@ -0,0 +1,292 @@
+//! ACPI System Description Table protocol.
+//!
+//! The PI `EFI_ACPI_SDT_PROTOCOL` gives access to the ACPI tables installed by the platform. [`AcpiSdt`] enumerates
+//! them, and the tables it returns can be patched in place, e.g. with
+//! [`aml::patch_name_integer`](crate::aml::patch_name_integer), as long as the checksum is updated afterwards.
+//!
+use core::{ffi::c_void, marker::PhantomData, mem, ptr, slice};
+
+use r_efi::efi;
+
+use crate::protocol;
+
+/// `EFI_ACPI_SDT_PROTOCOL_GUID`.
+pub const PROTOCOL_GUID: efi::Guid =
+    efi::Guid::from_fields(0xeb97088e, 0xcfdf, 0x49c6, 0xbe, 0x4b, &[0xd9, 0x06, 0xa5, 0xb2, 0x0e, 0x86]);
+
+/// Bitmask of ACPI versions.
+pub type TableVersion = u32;
+
+/// No ACPI version.
+pub const TABLE_VERSION_NONE: TableVersion = 1 << 0;
+/// ACPI 1.0b.
+pub const TABLE_VERSION_1_0B: TableVersion = 1 << 1;
+/// ACPI 2.0.
+pub const TABLE_VERSION_2_0: TableVersion = 1 << 2;
+/// ACPI 3.0.
+pub const TABLE_VERSION_3_0: TableVersion = 1 << 3;
+/// ACPI 4.0.
+pub const TABLE_VERSION_4_0: TableVersion = 1 << 4;
+/// ACPI 5.0.
+pub const TABLE_VERSION_5_0: TableVersion = 1 << 5;
+
+/// Handle of an object of the ACPI namespace.
+pub type AcpiHandle = *mut c_void;
+
+/// Type of the data returned by `get_option`.
+pub type DataType = u32;
+
+/// Header common to all ACPI System Description Tables.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct SdtHeader {
+    /// Table signature, e.g. `"SSDT"`.
+    pub signature: [u8; 4],
+    /// Length of the table in bytes, header included.
+    pub length: u32,
+    /// Revision of the table.
+    pub revision: u8,
+    /// Checksum of the whole table, which must sum to zero.
+    pub checksum: u8,
+    /// OEM identifier.
+    pub oem_id: [u8; 6],
+    /// OEM table identifier.
+    pub oem_table_id: [u8; 8],
+    /// OEM revision.
+    pub oem_revision: u32,
+    /// Vendor ID of the tool that created the table.
+    pub creator_id: u32,
+    /// Revision of the tool that created the table.
+    pub creator_revision: u32,
+}
+
+const _: () = assert!(mem::size_of::<SdtHeader>() == 36);
+
+/// Offset of the checksum in the table header.
+const CHECKSUM_OFFSET: usize = 9;
+
+/// Function called when a table is installed or removed.
+pub type NotificationFn = extern "efiapi" fn(*mut SdtHeader, TableVersion, usize) -> efi::Status;
+
+pub type GetAcpiTable = extern "efiapi" fn(usize, *mut *mut SdtHeader, *mut TableVersion, *mut usize) -> efi::Status;
+pub type RegisterNotify = extern "efiapi" fn(efi::Boolean, NotificationFn) -> efi::Status;
+pub type Open = extern "efiapi" fn(*mut c_void, *mut AcpiHandle) -> efi::Status;
+pub type OpenSdt = extern "efiapi" fn(usize, *mut AcpiHandle) -> efi::Status;
+pub type Close = extern "efiapi" fn(AcpiHandle) -> efi::Status;
+pub type GetChild = extern "efiapi" fn(AcpiHandle, *mut AcpiHandle) -> efi::Status;
+pub type GetOption =
+    extern "efiapi" fn(AcpiHandle, usize, *mut DataType, *mut *const c_void, *mut usize) -> efi::Status;
+pub type SetOption = extern "efiapi" fn(AcpiHandle, usize, *const c_void, usize) -> efi::Status;
+pub type FindPath = extern "efiapi" fn(AcpiHandle, *mut c_void, *mut AcpiHandle) -> efi::Status;
+
+/// `EFI_ACPI_SDT_PROTOCOL`.
+#[repr(C)]
+pub struct Protocol {
+    pub acceptable_table_versions: TableVersion,
+    pub get_acpi_table: GetAcpiTable,
+    pub register_notify: RegisterNotify,
+    pub open: Open,
+    pub open_sdt: OpenSdt,
+    pub close: Close,
+    pub get_child: GetChild,
+    pub get_option: GetOption,
+    pub set_option: SetOption,
+    pub find_path: FindPath,
+}
+
+// SAFETY: `PROTOCOL_GUID` identifies the ACPI SDT protocol, whose interface is `Protocol`.
+unsafe impl protocol::Protocol for Protocol {
+    const GUID: efi::Guid = PROTOCOL_GUID;
+}
+
+/// Wrapper around the ACPI SDT protocol.
+#[derive(Clone, Copy)]
+pub struct AcpiSdt<'a> {
+    protocol: &'a Protocol,
+}
+
+impl<'a> AcpiSdt<'a> {
+    /// Create a wrapper around `protocol`.
+    pub fn new(protocol: &'a Protocol) -> Self {
+        Self { protocol }
+    }
+
+    /// Locate the protocol.
+    pub fn locate(boot_services: &'a efi::BootServices) -> Result<Self, efi::Status> {
+        let mut guid = PROTOCOL_GUID;
+        let mut interface = ptr::null_mut();
+        let status = (boot_services.locate_protocol)(&mut guid, ptr::null_mut(), &mut interface);
+        if status.is_error() {
+            return Err(status);
+        }
+        // SAFETY: the firmware installs a valid protocol structure with this GUID, which stays installed while boot
+        // services are available.
+        unsafe { (interface as *const Protocol).as_ref() }.map(Self::new).ok_or(efi::Status::NOT_FOUND)
+    }
+
+    /// Return the ACPI versions supported by the protocol.
+    pub fn acceptable_table_versions(&self) -> TableVersion {
+        self.protocol.acceptable_table_versions
+    }
+
+    /// Return the installed table at `index`, if there is one.
+    pub fn get(&self, index: usize) -> Option<InstalledTable<'a>> {
+        let mut header = ptr::null_mut();
+        let mut version = 0;
+        let mut key = 0;
+        let status = (self.protocol.get_acpi_table)(index, &mut header, &mut version, &mut key);
+        if status.is_error() || header.is_null() {
+            return None;
+        }
+        Some(InstalledTable { header, version, key, _lifetime_marker: PhantomData })
+    }
+
+    /// Return an iterator over the installed tables.
+    pub fn tables(&self) -> impl Iterator<Item = InstalledTable<'a>> + '_ {
+        (0..).map_while(|index| self.get(index))
+    }
+
+    /// Return the first installed table with `signature`, e.g. `*b"DSDT"`.
+    pub fn find(&self, signature: [u8; 4]) -> Option<InstalledTable<'a>> {
+        self.tables().find(|table| table.signature() == signature)
+    }
+}
+
+/// Table installed by the platform, as returned by [`AcpiSdt`].
+#[derive(Debug)]
+pub struct InstalledTable<'a> {
+    header: *mut SdtHeader,
+    version: TableVersion,
+    key: usize,
+    _lifetime_marker: PhantomData<&'a mut SdtHeader>,
+}
+
+impl InstalledTable<'_> {
+    /// Return the table header.
+    pub fn header(&self) -> SdtHeader {
+        // SAFETY: the protocol returns pointers to installed tables, which start with a header.
+        unsafe { self.header.read_unaligned() }
+    }
+
+    /// Return the table signature.
+    pub fn signature(&self) -> [u8; 4] {
+        self.header().signature
+    }
+
+    /// Return the ACPI versions the table was installed for.
+    pub fn version(&self) -> TableVersion {
+        self.version
+    }
+
+    /// Return the key identifying the table, e.g. to uninstall it with the ACPI table protocol.
+    pub fn key(&self) -> usize {
+        self.key
+    }
+
+    /// Return the whole table, header included.
+    pub fn as_bytes(&self) -> &[u8] {
+        // SAFETY: installed tables are `length` bytes long.
+        unsafe { slice::from_raw_parts(self.header as *const u8, self.header().length as usize) }
+    }
+
+    /// Return the whole table for patching, header included.
+    ///
+    /// # Safety
+    /// The table must not be in use by anything else, e.g. by the firmware AML interpreter, while it is modified.
+    /// [`update_checksum`] must be called once the patching is done.
+    pub unsafe fn as_bytes_mut(&mut self) -> &mut [u8] {
+        slice::from_raw_parts_mut(self.header as *mut u8, self.header().length as usize)
+    }
+}
+
+/// Update the checksum of `table` so that its bytes sum to zero.
+///
+/// Tables shorter than a header are left untouched.
+pub fn update_checksum(table: &mut [u8]) {
+    if table.len() < mem::size_of::<SdtHeader>() {
+        return;
+    }
+    table[CHECKSUM_OFFSET] = 0;
+    let sum = table.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    table[CHECKSUM_OFFSET] = sum.wrapping_neg();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::{mock_efi_boot_services, unimplemented_service};
+
+    fn table(signature: &[u8; 4], length: usize) -> Vec<u8> {
+        let mut table = vec![0u8; length];
+        table[..4].copy_from_slice(signature);
+        table[4..8].copy_from_slice(&(length as u32).to_le_bytes());
+        table[36..].fill(0x5a);
+        update_checksum(&mut table);
+        table
+    }
+
+    std::thread_local! {
+        static TABLES: std::cell::RefCell<Vec<Vec<u8>>> =
+            std::cell::RefCell::new(vec![table(b"FACP", 64), table(b"SSDT", 48)]);
+    }
+
+    extern "efiapi" fn get_acpi_table(
+        index: usize,
+        table: *mut *mut SdtHeader,
+        version: *mut TableVersion,
+        key: *mut usize,
+    ) -> efi::Status {
+        TABLES.with(|tables| match tables.borrow_mut().get_mut(index) {
+            Some(found) => {
+                unsafe {
+                    *table = found.as_mut_ptr() as *mut SdtHeader;
+                    *version = TABLE_VERSION_2_0;
+                    *key = 0x100 + index;
+                }
+                efi::Status::SUCCESS
+            }
+            None => efi::Status::NOT_FOUND,
+        })
+    }
+
+    fn protocol() -> Protocol {
+        Protocol {
+            acceptable_table_versions: TABLE_VERSION_1_0B | TABLE_VERSION_2_0,
+            get_acpi_table,
+            register_notify: unimplemented_service(),
+            open: unimplemented_service(),
+            open_sdt: unimplemented_service(),
+            close: unimplemented_service(),
+            get_child: unimplemented_service(),
+            get_option: unimplemented_service(),
+            set_option: unimplemented_service(),
+            find_path: unimplemented_service(),
+        }
+    }
+
+    #[test]
+    fn test_tables() {
+        let protocol = protocol();
+        let sdt = AcpiSdt::new(&protocol);
+        assert_eq!(sdt.acceptable_table_versions(), TABLE_VERSION_1_0B | TABLE_VERSION_2_0);
+        let signatures: Vec<[u8; 4]> = sdt.tables().map(|table| table.signature()).collect();
+        assert_eq!(signatures, [*b"FACP", *b"SSDT"]);
+
+        let mut ssdt = sdt.find(*b"SSDT").unwrap();
+        assert_eq!((ssdt.key(), ssdt.version(), ssdt.as_bytes().len()), (0x101, TABLE_VERSION_2_0, 48));
+        assert_eq!(ssdt.as_bytes().iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)), 0);
+
+        let bytes = unsafe { ssdt.as_bytes_mut() };
+        bytes[40] = 0x11;
+        update_checksum(bytes);
+        assert_eq!(ssdt.as_bytes().iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)), 0);
+        assert!(sdt.find(*b"DSDT").is_none());
+    }
+
+    #[test]
+    fn test_locate() {
+        let boot_services = mock_efi_boot_services();
+        assert!(matches!(AcpiSdt::locate(&boot_services), Err(efi::Status::UNSUPPORTED)));
+    }
+}
@@ -0,0 +1,925 @@
+//! Owned page allocations.
+//!
+//! AllocatePages returns a physical address and a page count that must be handed back to FreePages on every path.
+//! [`PageBox`] owns such an allocation, gives typed access to its contents and frees the pages when dropped.
+//! [`PageAllocation`] owns untyped pages, e.g. a DMA buffer or a range at a fixed address for legacy code.
+//! [`ArenaAllocator`] carves many small allocations out of page ranges and frees them all at once.
+//! [`with_pool_buffer`] and [`with_pages`] lend a temporary buffer to a closure and free it however the closure
+//! returns.
+//!
+use alloc::vec::Vec;
+use core::{
+    alloc::Layout,
+    cell::{Cell, RefCell},
+    fmt,
+    marker::PhantomData,
+    mem,
+    ops::{Deref, DerefMut},
+    ptr::{self, NonNull},
+    slice,
+};
+
+use r_efi::efi;
+
+use crate::units::{ByteCount, PageCount, PhysicalAddress, UEFI_PAGE_SIZE};
+
+/// Constraint on the address of a page allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocType {
+    /// Any address.
+    AnyPages,
+    /// An address whose last byte is at or below the given address, e.g. below 4 GiB for 32-bit DMA.
+    MaxAddress(PhysicalAddress),
+    /// Exactly the given page-aligned address.
+    Address(PhysicalAddress),
+}
+
+impl AllocType {
+    /// Any address whose last byte is below 4 GiB, e.g. for 32-bit DMA.
+    pub const fn below_4gb() -> Self {
+        Self::MaxAddress(PhysicalAddress::new(0xffff_ffff))
+    }
+
+    fn to_efi(self) -> (efi::AllocateType, efi::PhysicalAddress) {
+        match self {
+            Self::AnyPages => (efi::ALLOCATE_ANY_PAGES, 0),
+            Self::MaxAddress(address) => (efi::ALLOCATE_MAX_ADDRESS, address.get()),
+            Self::Address(address) => (efi::ALLOCATE_ADDRESS, address.get()),
+        }
+    }
+}
+
+/// Value stored in pages allocated with AllocatePages, freed when dropped.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::allocation::{AllocType, PageBox};
+/// use r_efi::efi;
+///
+/// fn dma_buffer(boot_services: &efi::BootServices) -> Result<PageBox<'_, [u8]>, efi::Status> {
+///     PageBox::new_slice(boot_services, 0, 0x3000, efi::BOOT_SERVICES_DATA, AllocType::below_4gb())
+/// }
+/// ```
+pub struct PageBox<'a, T: ?Sized> {
+    boot_services: &'a efi::BootServices,
+    value: NonNull<T>,
+    pages: PageCount,
+    _owned: PhantomData<T>,
+}
+
+impl<'a, T> PageBox<'a, T> {
+    /// Move `value` into pages of `memory_type` allocated as requested by `alloc_type`.
+    ///
+    /// Returns `efi::Status::INVALID_PARAMETER` if `T` must be aligned beyond a page.
+    pub fn new(
+        boot_services: &'a efi::BootServices,
+        value: T,
+        memory_type: impl Into<efi::MemoryType>,
+        alloc_type: AllocType,
+    ) -> Result<Self, efi::Status> {
+        let (pointer, pages) = allocate::<T>(boot_services, 1, memory_type.into(), alloc_type)?;
+        // SAFETY: the pages hold at least one suitably aligned `T`.
+        unsafe { pointer.write(value) };
+        Ok(Self { boot_services, value: pointer, pages, _owned: PhantomData })
+    }
+}
+
+impl<'a, T: Clone> PageBox<'a, [T]> {
+    /// Fill pages of `memory_type`, allocated as requested by `alloc_type`, with `len` clones of `value`.
+    ///
+    /// Returns `efi::Status::INVALID_PARAMETER` if `T` must be aligned beyond a page, and
+    /// `efi::Status::BAD_BUFFER_SIZE` if the size of the slice overflows.
+    pub fn new_slice(
+        boot_services: &'a efi::BootServices,
+        value: T,
+        len: usize,
+        memory_type: impl Into<efi::MemoryType>,
+        alloc_type: AllocType,
+    ) -> Result<Self, efi::Status> {
+        let (pointer, pages) = allocate::<T>(boot_services, len, memory_type.into(), alloc_type)?;
+        for index in 0..len {
+            // SAFETY: the pages hold at least `len` suitably aligned `T`. Clone panicking leaks the pages, which is
+            // safe.
+            unsafe { pointer.add(index).write(value.clone()) };
+        }
+        let value = NonNull::slice_from_raw_parts(pointer, len);
+        Ok(Self { boot_services, value, pages, _owned: PhantomData })
+    }
+}
+
+impl<'a, T: ?Sized> PageBox<'a, T> {
+    /// Return the physical address of the allocation.
+    pub fn address(this: &Self) -> PhysicalAddress {
+        PhysicalAddress::new(this.value.as_ptr() as *mut u8 as u64)
+    }
+
+    /// Return the number of pages of the allocation.
+    pub fn pages(this: &Self) -> PageCount {
+        this.pages
+    }
+
+    /// Give up ownership of the pages, e.g. for a runtime allocation that must outlive the driver.
+    pub fn leak(this: Self) -> &'a mut T {
+        let this = mem::ManuallyDrop::new(this);
+        // SAFETY: the value is initialized and is never dropped nor freed.
+        unsafe { &mut *this.value.as_ptr() }
+    }
+}
+
+fn allocate<T>(
+    boot_services: &efi::BootServices,
+    len: usize,
+    memory_type: efi::MemoryType,
+    alloc_type: AllocType,
+) -> Result<(NonNull<T>, PageCount), efi::Status> {
+    if mem::align_of::<T>() as u64 > UEFI_PAGE_SIZE {
+        return Err(efi::Status::INVALID_PARAMETER);
+    }
+    let size = mem::size_of::<T>().checked_mul(len).ok_or(efi::Status::BAD_BUFFER_SIZE)?;
+    // Empty values still get a page, so every box owns a distinct, freeable allocation.
+    let pages = ByteCount::from_usize(size).to_pages_ceil().get().max(1);
+    let pages = PageCount::new(pages);
+    let page_count = pages.to_usize().ok_or(efi::Status::BAD_BUFFER_SIZE)?;
+    let (allocate_type, mut address) = alloc_type.to_efi();
+    let status = (boot_services.allocate_pages)(allocate_type, memory_type, page_count, &mut address);
+    if status.is_error() {
+        return Err(status);
+    }
+    let Some(pointer) = NonNull::new(address as *mut T) else {
+        // Page 0 is valid memory but not a valid Rust pointer.
+        (boot_services.free_pages)(address, page_count);
+        return Err(efi::Status::OUT_OF_RESOURCES);
+    };
+    Ok((pointer, pages))
+}
+
+impl<T: ?Sized> Deref for PageBox<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the value is initialized and owned by the box.
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for PageBox<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the value is initialized and owned by the box.
+        unsafe { self.value.as_mut() }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for PageBox<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized> Drop for PageBox<'_, T> {
+    fn drop(&mut self) {
+        // SAFETY: the value is initialized and is not used after this.
+        unsafe { ptr::drop_in_place(self.value.as_ptr()) };
+        // The page count was checked to fit a `usize` when allocating.
+        (self.boot_services.free_pages)(Self::address(self).get(), self.pages.get() as usize);
+    }
+}
+
+/// Pages allocated with AllocatePages, freed when dropped.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::{
+///     allocation::allocate_pages_at,
+///     units::{PageCount, PhysicalAddress},
+/// };
+/// use r_efi::efi;
+///
+/// fn clear_legacy_region(boot_services: &efi::BootServices) -> Result<(), efi::Status> {
+///     let mut pages = allocate_pages_at(
+///         boot_services,
+///         efi::BOOT_SERVICES_DATA,
+///         PhysicalAddress::new(0x9_0000),
+///         PageCount::new(16),
+///     )?;
+///     pages.as_mut_slice().fill(0);
+///     Ok(())
+/// }
+/// ```
+pub struct PageAllocation<'a> {
+    boot_services: &'a efi::BootServices,
+    address: NonNull<u8>,
+    pages: PageCount,
+}
+
+impl<'a> PageAllocation<'a> {
+    /// Allocate `pages` of `memory_type` as requested by `alloc_type`.
+    ///
+    /// Returns `efi::Status::INVALID_PARAMETER` for an empty allocation or an address that is not page-aligned, and
+    /// the error of AllocatePages otherwise.
+    pub fn new(
+        boot_services: &'a efi::BootServices,
+        memory_type: impl Into<efi::MemoryType>,
+        alloc_type: AllocType,
+        pages: PageCount,
+    ) -> Result<Self, efi::Status> {
+        if pages.get() == 0 {
+            return Err(efi::Status::INVALID_PARAMETER);
+        }
+        if let AllocType::Address(address) = alloc_type {
+            if !address.is_page_aligned() {
+                return Err(efi::Status::INVALID_PARAMETER);
+            }
+        }
+        // The size of the pages must fit the address space for the slice accessors.
+        pages.to_bytes().and_then(|size| size.to_usize()).ok_or(efi::Status::BAD_BUFFER_SIZE)?;
+        let page_count = pages.to_usize().ok_or(efi::Status::BAD_BUFFER_SIZE)?;
+        let (allocate_type, mut address) = alloc_type.to_efi();
+        let status = (boot_services.allocate_pages)(allocate_type, memory_type.into(), page_count, &mut address);
+        if status.is_error() {
+            return Err(status);
+        }
+        let Some(pointer) = NonNull::new(address as *mut u8) else {
+            // Page 0 is valid memory but not a valid Rust pointer.
+            (boot_services.free_pages)(address, page_count);
+            return Err(efi::Status::OUT_OF_RESOURCES);
+        };
+        Ok(Self { boot_services, address: pointer, pages })
+    }
+
+    /// Return a pointer to the first byte of the allocation.
+    pub fn as_ptr(&self) -> NonNull<u8> {
+        self.address
+    }
+
+    /// Return the physical address of the allocation.
+    pub fn address(&self) -> PhysicalAddress {
+        PhysicalAddress::new(self.address.as_ptr() as u64)
+    }
+
+    /// Return the number of pages of the allocation.
+    pub fn pages(&self) -> PageCount {
+        self.pages
+    }
+
+    /// Return the contents of the allocation, as left by the firmware or a previous user.
+    pub fn as_slice(&self) -> &[u8] {
+        // SAFETY: the pages are owned by the allocation and their size was checked to fit a `usize`.
+        unsafe { slice::from_raw_parts(self.address.as_ptr(), self.len()) }
+    }
+
+    /// Return the contents of the allocation mutably.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        // SAFETY: as for `as_slice`, and the allocation is borrowed mutably.
+        unsafe { slice::from_raw_parts_mut(self.address.as_ptr(), self.len()) }
+    }
+
+    /// Give up ownership of the pages and return their address.
+    pub fn leak(self) -> NonNull<u8> {
+        mem::ManuallyDrop::new(self).address
+    }
+
+    fn len(&self) -> usize {
+        (self.pages.get() * UEFI_PAGE_SIZE) as usize
+    }
+}
+
+impl fmt::Debug for PageAllocation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PageAllocation").field("address", &self.address()).field("pages", &self.pages).finish()
+    }
+}
+
+impl Drop for PageAllocation<'_> {
+    fn drop(&mut self) {
+        // The page count was checked to fit a `usize` when allocating.
+        (self.boot_services.free_pages)(self.address().get(), self.pages.get() as usize);
+    }
+}
+
+/// Allocate `pages` of `memory_type` at exactly `address`.
+///
+/// Returns `efi::Status::INVALID_PARAMETER` if `address` is not page-aligned, and `efi::Status::NOT_FOUND` if the
+/// range is not available.
+pub fn allocate_pages_at(
+    boot_services: &efi::BootServices,
+    memory_type: impl Into<efi::MemoryType>,
+    address: PhysicalAddress,
+    pages: PageCount,
+) -> Result<PageAllocation<'_>, efi::Status> {
+    PageAllocation::new(boot_services, memory_type, AllocType::Address(address), pages)
+}
+
+/// Run `f` with a zeroed buffer of `size` bytes of `BootServicesData` pool, freed once `f` returns, successfully or
+/// not.
+///
+/// Returns the error of AllocatePool, or the result of `f`.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::allocation::with_pool_buffer;
+/// use r_efi::efi;
+///
+/// fn checksum_block(
+///     boot_services: &efi::BootServices,
+///     read_block: fn(&mut [u8]) -> efi::Status,
+/// ) -> Result<u8, efi::Status> {
+///     with_pool_buffer(boot_services, 0x200, |buffer| {
+///         let status = read_block(buffer);
+///         if status.is_error() {
+///             return Err(status);
+///         }
+///         Ok(buffer.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)))
+///     })
+/// }
+/// ```
+pub fn with_pool_buffer<R>(
+    boot_services: &efi::BootServices,
+    size: usize,
+    f: impl FnOnce(&mut [u8]) -> Result<R, efi::Status>,
+) -> Result<R, efi::Status> {
+    struct PoolBuffer<'a> {
+        boot_services: &'a efi::BootServices,
+        buffer: NonNull<u8>,
+    }
+
+    impl Drop for PoolBuffer<'_> {
+        fn drop(&mut self) {
+            (self.boot_services.free_pool)(self.buffer.as_ptr().cast());
+        }
+    }
+
+    if size == 0 {
+        return f(&mut []);
+    }
+    let mut buffer = ptr::null_mut();
+    let status = (boot_services.allocate_pool)(efi::BOOT_SERVICES_DATA, size, &mut buffer);
+    if status.is_error() {
+        return Err(status);
+    }
+    let buffer = NonNull::new(buffer.cast::<u8>()).ok_or(efi::Status::OUT_OF_RESOURCES)?;
+    let pool = PoolBuffer { boot_services, buffer };
+    // SAFETY: the pool buffer holds `size` bytes, zeroed before being lent to `f`, and is freed only after `f`
+    // returns.
+    let buffer = unsafe {
+        ptr::write_bytes(pool.buffer.as_ptr(), 0, size);
+        slice::from_raw_parts_mut(pool.buffer.as_ptr(), size)
+    };
+    f(buffer)
+}
+
+/// Run `f` with `pages` of `BootServicesData` at any address, freed once `f` returns, successfully or not.
+///
+/// The contents of the pages are as left by the firmware or a previous user. Returns the error of
+/// [`PageAllocation::new`], or the result of `f`.
+pub fn with_pages<R>(
+    boot_services: &efi::BootServices,
+    pages: PageCount,
+    f: impl FnOnce(&mut [u8]) -> Result<R, efi::Status>,
+) -> Result<R, efi::Status> {
+    let mut allocation = PageAllocation::new(boot_services, efi::BOOT_SERVICES_DATA, AllocType::AnyPages, pages)?;
+    f(allocation.as_mut_slice())
+}
+
+/// Bump allocator serving allocations from page ranges, all freed together.
+///
+/// Each allocation is a pointer increment within the current range, and a new range is allocated from
+/// AllocatePages when it is exhausted. Individual allocations are never freed and their destructors never run, so only
+/// `Copy` values are accepted. Pages are freed by [`Self::reset`] or when the arena is dropped.
+///
+/// An arena built with [`Self::with_capacity`] grabs its pages once, and [`Self::clear`] rewinds it at a phase boundary
+/// such as EndOfDxe while keeping those pages for the next phase. [`Self::allocate`] and [`Self::deallocate`] follow
+/// the shape of `core::alloc::Allocator` for callers written against that API.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::allocation::ArenaAllocator;
+/// use r_efi::efi;
+///
+/// fn collect_signatures(boot_services: &efi::BootServices, tables: &[[u8; 4]]) -> Result<usize, efi::Status> {
+///     let arena = ArenaAllocator::new(boot_services, efi::BOOT_SERVICES_DATA);
+///     let signatures = tables.iter().map(|signature| arena.alloc(*signature)).collect::<Result<Vec<_>, _>>()?;
+///     Ok(signatures.len())
+/// }
+/// ```
+pub struct ArenaAllocator<'a> {
+    boot_services: &'a efi::BootServices,
+    memory_type: efi::MemoryType,
+    chunk_pages: usize,
+    chunks: RefCell<Vec<(efi::PhysicalAddress, usize)>>,
+    next: Cell<usize>,
+    end: Cell<usize>,
+}
+
+impl<'a> ArenaAllocator<'a> {
+    /// Number of pages allocated at a time by [`Self::new`].
+    pub const DEFAULT_CHUNK_PAGES: usize = 16;
+
+    /// Create an arena allocating pages of `memory_type`, [`Self::DEFAULT_CHUNK_PAGES`] at a time.
+    pub fn new(boot_services: &'a efi::BootServices, memory_type: impl Into<efi::MemoryType>) -> Self {
+        Self::with_chunk_pages(boot_services, memory_type, Self::DEFAULT_CHUNK_PAGES)
+    }
+
+    /// Create an arena allocating pages of `memory_type`, `chunk_pages` at a time. Allocations larger than a chunk get
+    /// pages of their own.
+    pub fn with_chunk_pages(
+        boot_services: &'a efi::BootServices,
+        memory_type: impl Into<efi::MemoryType>,
+        chunk_pages: usize,
+    ) -> Self {
+        Self {
+            boot_services,
+            memory_type: memory_type.into(),
+            chunk_pages: chunk_pages.max(1),
+            chunks: RefCell::new(Vec::new()),
+            next: Cell::new(0),
+            end: Cell::new(0),
+        }
+    }
+
+    /// Create an arena allocating pages of `memory_type`, `chunk_pages` at a time, and allocate its first chunk now.
+    ///
+    /// The first chunk is kept by [`Self::clear`], so an arena sized for its workload never calls AllocatePages again.
+    pub fn with_capacity(
+        boot_services: &'a efi::BootServices,
+        memory_type: impl Into<efi::MemoryType>,
+        chunk_pages: usize,
+    ) -> Result<Self, efi::Status> {
+        let arena = Self::with_chunk_pages(boot_services, memory_type, chunk_pages);
+        let start = arena.allocate_chunk(arena.chunk_pages)?;
+        arena.next.set(start);
+        arena.end.set(start + arena.chunk_size()?);
+        Ok(arena)
+    }
+
+    /// Allocate memory for `layout`.
+    ///
+    /// Returns `efi::Status::INVALID_PARAMETER` if `layout` must be aligned beyond a page.
+    pub fn alloc_layout(&self, layout: Layout) -> Result<NonNull<u8>, efi::Status> {
+        if layout.align() as u64 > UEFI_PAGE_SIZE {
+            return Err(efi::Status::INVALID_PARAMETER);
+        }
+        if let Some(start) = self.bump(layout) {
+            // SAFETY: the range lies within a chunk, whose addresses are not null.
+            return Ok(unsafe { NonNull::new_unchecked(start as *mut u8) });
+        }
+
+        let pages =
+            ByteCount::from_usize(layout.size()).to_pages_ceil().to_usize().ok_or(efi::Status::BAD_BUFFER_SIZE)?;
+        let chunk_pages = pages.max(1);
+        let is_dedicated = chunk_pages > self.chunk_pages;
+        let chunk_pages = if is_dedicated { chunk_pages } else { self.chunk_pages };
+        let chunk_size = self.chunk_size()?;
+        let start = self.allocate_chunk(chunk_pages)?;
+        // Keep bumping in the current chunk after a dedicated allocation, it may still have room.
+        if !is_dedicated {
+            self.next.set(start + layout.size());
+            self.end.set(start + chunk_size);
+        }
+        // SAFETY: `allocate_chunk` never returns page 0.
+        Ok(unsafe { NonNull::new_unchecked(start as *mut u8) })
+    }
+
+    /// Allocate memory for `layout`, returning the whole block like `core::alloc::Allocator::allocate`.
+    pub fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, efi::Status> {
+        let pointer = self.alloc_layout(layout)?;
+        Ok(NonNull::slice_from_raw_parts(pointer, layout.size()))
+    }
+
+    /// Give back memory from [`Self::allocate`] or [`Self::alloc_layout`].
+    ///
+    /// Only the most recent allocation in the current chunk is reclaimed; other memory is reclaimed by [`Self::clear`]
+    /// or [`Self::reset`].
+    ///
+    /// # Safety
+    /// `pointer` must have been allocated by this arena with `layout`, and must not be used afterwards.
+    pub unsafe fn deallocate(&self, pointer: NonNull<u8>, layout: Layout) {
+        let start = pointer.as_ptr() as usize;
+        if start.checked_add(layout.size()) == Some(self.next.get()) {
+            self.next.set(start);
+        }
+    }
+
+    fn chunk_size(&self) -> Result<usize, efi::Status> {
+        PageCount::from_usize(self.chunk_pages)
+            .to_bytes()
+            .and_then(ByteCount::to_usize)
+            .ok_or(efi::Status::BAD_BUFFER_SIZE)
+    }
+
+    fn allocate_chunk(&self, pages: usize) -> Result<usize, efi::Status> {
+        let mut address = 0;
+        let status =
+            (self.boot_services.allocate_pages)(efi::ALLOCATE_ANY_PAGES, self.memory_type, pages, &mut address);
+        if status.is_error() {
+            return Err(status);
+        }
+        if address == 0 {
+            // Page 0 is valid memory but not a valid Rust pointer.
+            (self.boot_services.free_pages)(address, pages);
+            return Err(efi::Status::OUT_OF_RESOURCES);
+        }
+        self.chunks.borrow_mut().push((address, pages));
+        Ok(address as usize)
+    }
+
+    fn bump(&self, layout: Layout) -> Option<usize> {
+        if self.next.get() == 0 {
+            return None;
+        }
+        let start = self.next.get().checked_next_multiple_of(layout.align())?;
+        let end = start.checked_add(layout.size())?;
+        if end > self.end.get() {
+            return None;
+        }
+        self.next.set(end);
+        Some(start)
+    }
+
+    /// Move `value` into the arena.
+    pub fn alloc<T: Copy>(&self, value: T) -> Result<&mut T, efi::Status> {
+        let pointer = self.alloc_layout(Layout::new::<T>())?.as_ptr() as *mut T;
+        // SAFETY: the memory is suitably sized and aligned for a `T`, and is not handed out again.
+        unsafe {
+            pointer.write(value);
+            Ok(&mut *pointer)
+        }
+    }
+
+    /// Copy `values` into the arena.
+    pub fn alloc_slice_copy<T: Copy>(&self, values: &[T]) -> Result<&mut [T], efi::Status> {
+        let layout = Layout::for_value(values);
+        let pointer = self.alloc_layout(layout)?.as_ptr() as *mut T;
+        // SAFETY: the memory is suitably sized and aligned for `values`, and is not handed out again.
+        unsafe {
+            pointer.copy_from_nonoverlapping(values.as_ptr(), values.len());
+            Ok(slice::from_raw_parts_mut(pointer, values.len()))
+        }
+    }
+
+    /// Return the number of pages allocated by the arena.
+    pub fn allocated_pages(&self) -> PageCount {
+        PageCount::from_usize(self.chunks.borrow().iter().map(|&(_, pages)| pages).sum())
+    }
+
+    /// Free every allocation, keeping the first regular chunk for reuse.
+    ///
+    /// Meant for phase boundaries: the next phase bumps from the retained chunk without calling AllocatePages.
+    pub fn clear(&mut self) {
+        let chunk_pages = self.chunk_pages;
+        let chunks = self.chunks.get_mut();
+        let kept = chunks.iter().position(|&(_, pages)| pages == chunk_pages).map(|index| chunks.swap_remove(index));
+        for (address, pages) in chunks.drain(..) {
+            (self.boot_services.free_pages)(address, pages);
+        }
+        self.next.set(0);
+        self.end.set(0);
+        if let Some((address, pages)) = kept {
+            chunks.push((address, pages));
+            let start = address as usize;
+            self.next.set(start);
+            // The chunk was allocated with this size, so it cannot overflow.
+            self.end.set(start + pages * UEFI_PAGE_SIZE as usize);
+        }
+    }
+
+    /// Free every allocation.
+    pub fn reset(&mut self) {
+        for (address, pages) in self.chunks.get_mut().drain(..) {
+            (self.boot_services.free_pages)(address, pages);
+        }
+        self.next.set(0);
+        self.end.set(0);
+    }
+}
+
+impl Drop for ArenaAllocator<'_> {
+    fn drop(&mut self) {
+        self.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        alloc::{alloc_zeroed, dealloc, Layout},
+        cell::RefCell,
+        rc::Rc,
+    };
+
+    use crate::{memory_type::MemoryType, test_support::mock_efi_boot_services};
+
+    /// Type, memory type, page count and requested address of an AllocatePages call.
+    type PageRequest = (efi::AllocateType, efi::MemoryType, usize, efi::PhysicalAddress);
+
+    std::thread_local! {
+        /// Live page allocations, with the address granted to each.
+        static ALLOCATIONS: RefCell<Vec<(PageRequest, efi::PhysicalAddress)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn layout(pages: usize) -> Layout {
+        Layout::from_size_align(pages * UEFI_PAGE_SIZE as usize, UEFI_PAGE_SIZE as usize).unwrap()
+    }
+
+    extern "efiapi" fn allocate_pages(
+        allocate_type: efi::AllocateType,
+        memory_type: efi::MemoryType,
+        pages: usize,
+        address: *mut efi::PhysicalAddress,
+    ) -> efi::Status {
+        let requested = unsafe { *address };
+        if allocate_type == efi::ALLOCATE_ADDRESS && requested == 0x1000 {
+            return efi::Status::NOT_FOUND;
+        }
+        // Fixed addresses are backed by an allocation made by the test, except page 0 which is never accessed.
+        if allocate_type != efi::ALLOCATE_ADDRESS {
+            unsafe { *address = alloc_zeroed(layout(pages)) as efi::PhysicalAddress };
+        }
+        let granted = unsafe { *address };
+        ALLOCATIONS.with(|allocations| {
+            allocations.borrow_mut().push(((allocate_type, memory_type, pages, requested), granted))
+        });
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn free_pages(address: efi::PhysicalAddress, pages: usize) -> efi::Status {
+        let index =
+            ALLOCATIONS.with(|allocations| allocations.borrow().iter().position(|&(_, granted)| granted == address));
+        let ((_, _, allocated, _), _) = ALLOCATIONS.with(|allocations| allocations.borrow_mut().remove(index.unwrap()));
+        assert_eq!(pages, allocated);
+        if address != 0 {
+            unsafe { dealloc(address as *mut u8, layout(pages)) };
+        }
+        efi::Status::SUCCESS
+    }
+
+    std::thread_local! {
+        static POOL: RefCell<Vec<(usize, usize)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    extern "efiapi" fn allocate_pool(
+        pool_type: efi::MemoryType,
+        size: usize,
+        buffer: *mut *mut core::ffi::c_void,
+    ) -> efi::Status {
+        assert_eq!(pool_type, efi::BOOT_SERVICES_DATA);
+        let allocation = unsafe { std::alloc::alloc(Layout::from_size_align(size, 8).unwrap()) };
+        // Garbage, as left by a previous user.
+        unsafe { ptr::write_bytes(allocation, 0xa5, size) };
+        POOL.with(|pool| pool.borrow_mut().push((allocation as usize, size)));
+        unsafe { *buffer = allocation.cast() };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn free_pool(buffer: *mut core::ffi::c_void) -> efi::Status {
+        let index = POOL.with(|pool| pool.borrow().iter().position(|&(address, _)| address == buffer as usize));
+        let (_, size) = POOL.with(|pool| pool.borrow_mut().remove(index.unwrap()));
+        unsafe { dealloc(buffer.cast(), Layout::from_size_align(size, 8).unwrap()) };
+        efi::Status::SUCCESS
+    }
+
+    fn boot_services() -> efi::BootServices {
+        efi::BootServices { allocate_pages, free_pages, allocate_pool, free_pool, ..mock_efi_boot_services() }
+    }
+
+    fn allocations() -> Vec<PageRequest> {
+        ALLOCATIONS.with(|allocations| allocations.borrow().iter().map(|&(request, _)| request).collect())
+    }
+
+    #[test]
+    fn test_page_box() {
+        let boot_services = boot_services();
+        let value = Rc::new(5);
+        let mut page_box =
+            PageBox::new(&boot_services, Rc::clone(&value), efi::RUNTIME_SERVICES_DATA, AllocType::AnyPages).unwrap();
+        assert_eq!(**page_box, 5);
+        assert!(PageBox::address(&page_box).is_page_aligned());
+        assert_eq!(PageBox::pages(&page_box), PageCount::new(1));
+        assert_eq!(allocations(), [(efi::ALLOCATE_ANY_PAGES, efi::RUNTIME_SERVICES_DATA, 1, 0)]);
+        *page_box = Rc::new(6);
+        assert_eq!(Rc::strong_count(&value), 1);
+
+        let slice = PageBox::new_slice(
+            &boot_services,
+            Rc::clone(&value),
+            0x201,
+            efi::BOOT_SERVICES_DATA,
+            AllocType::below_4gb(),
+        )
+        .unwrap();
+        assert_eq!(slice.len(), 0x201);
+        assert_eq!(Rc::strong_count(&value), 0x202);
+        assert_eq!(allocations()[1], (efi::ALLOCATE_MAX_ADDRESS, efi::BOOT_SERVICES_DATA, 2, 0xffff_ffff));
+
+        drop(slice);
+        drop(page_box);
+        assert_eq!(Rc::strong_count(&value), 1);
+        assert!(allocations().is_empty());
+    }
+
+    #[test]
+    fn test_errors() {
+        let boot_services = boot_services();
+        assert_eq!(
+            PageBox::new(
+                &boot_services,
+                0u8,
+                efi::BOOT_SERVICES_DATA,
+                AllocType::Address(PhysicalAddress::new(0x1000))
+            )
+            .map(|_| ()),
+            Err(efi::Status::NOT_FOUND)
+        );
+        assert_eq!(
+            PageBox::new_slice(&boot_services, 0u64, usize::MAX, efi::BOOT_SERVICES_DATA, AllocType::AnyPages)
+                .map(|_| ()),
+            Err(efi::Status::BAD_BUFFER_SIZE)
+        );
+
+        #[repr(align(8192))]
+        struct OverAligned;
+        assert!(matches!(
+            PageBox::new(&boot_services, OverAligned, efi::BOOT_SERVICES_DATA, AllocType::AnyPages),
+            Err(efi::Status::INVALID_PARAMETER)
+        ));
+
+        let empty = PageBox::new_slice(&boot_services, 0u8, 0, efi::BOOT_SERVICES_DATA, AllocType::AnyPages).unwrap();
+        assert!(empty.is_empty());
+        let leaked = PageBox::leak(empty);
+        assert_eq!(leaked, &[]);
+
+        // Page 0 cannot be referenced, so it is given back and only the leaked allocation remains.
+        let page_0 = AllocType::Address(PhysicalAddress::new(0));
+        assert_eq!(
+            PageBox::new(&boot_services, 0u8, efi::BOOT_SERVICES_DATA, page_0).map(|_| ()),
+            Err(efi::Status::OUT_OF_RESOURCES)
+        );
+        assert_eq!(
+            PageBox::new_slice(&boot_services, 0u8, 0x10, efi::BOOT_SERVICES_DATA, page_0).map(|_| ()),
+            Err(efi::Status::OUT_OF_RESOURCES)
+        );
+        assert_eq!(allocations().len(), 1);
+    }
+
+    #[test]
+    fn test_page_allocation() {
+        let boot_services = boot_services();
+        let mut pages =
+            PageAllocation::new(&boot_services, efi::BOOT_SERVICES_DATA, AllocType::below_4gb(), PageCount::new(2))
+                .unwrap();
+        assert_eq!(pages.pages(), PageCount::new(2));
+        assert_eq!(pages.address().get(), pages.as_ptr().as_ptr() as u64);
+        pages.as_mut_slice().fill(0x5a);
+        assert_eq!(pages.as_slice().len(), 0x2000);
+        assert_eq!(allocations(), [(efi::ALLOCATE_MAX_ADDRESS, efi::BOOT_SERVICES_DATA, 2, 0xffff_ffff)]);
+        drop(pages);
+        assert!(allocations().is_empty());
+
+        let address = PhysicalAddress::new(unsafe { alloc_zeroed(layout(3)) } as u64);
+        let pages = allocate_pages_at(&boot_services, efi::ACPI_RECLAIM_MEMORY, address, PageCount::new(3)).unwrap();
+        assert_eq!(pages.address(), address);
+        assert_eq!(allocations(), [(efi::ALLOCATE_ADDRESS, efi::ACPI_RECLAIM_MEMORY, 3, address.get())]);
+        drop(pages);
+
+        let oem = MemoryType::oem(0x10).unwrap();
+        let pages = PageAllocation::new(&boot_services, oem, AllocType::AnyPages, PageCount::new(1)).unwrap();
+        assert_eq!(allocations()[0].1, 0x7000_0010);
+        drop(pages);
+
+        let at = |address, pages| {
+            allocate_pages_at(
+                &boot_services,
+                efi::BOOT_SERVICES_DATA,
+                PhysicalAddress::new(address),
+                PageCount::new(pages),
+            )
+            .map(|_| ())
+        };
+        assert_eq!(at(0x1000, 1), Err(efi::Status::NOT_FOUND));
+        assert_eq!(at(0x1800, 1), Err(efi::Status::INVALID_PARAMETER));
+        assert_eq!(at(0x2000, 0), Err(efi::Status::INVALID_PARAMETER));
+        assert!(allocations().is_empty());
+    }
+
+    #[test]
+    fn test_scoped_buffers() {
+        let boot_services = boot_services();
+        let sum = with_pool_buffer(&boot_services, 0x10, |buffer| {
+            assert_eq!(POOL.with(|pool| pool.borrow().len()), 1);
+            buffer[3] = 7;
+            Ok(buffer.iter().map(|&byte| byte as usize).sum::<usize>())
+        });
+        assert_eq!(sum, Ok(7));
+        let result: Result<(), _> = with_pool_buffer(&boot_services, 0x10, |_| Err(efi::Status::DEVICE_ERROR));
+        assert_eq!(result, Err(efi::Status::DEVICE_ERROR));
+        assert_eq!(with_pool_buffer(&boot_services, 0, |buffer| Ok(buffer.len())), Ok(0));
+        assert!(POOL.with(|pool| pool.borrow().is_empty()));
+
+        let len = with_pages(&boot_services, PageCount::new(2), |buffer| {
+            assert_eq!(allocations(), [(efi::ALLOCATE_ANY_PAGES, efi::BOOT_SERVICES_DATA, 2, 0)]);
+            Ok(buffer.len())
+        });
+        assert_eq!(len, Ok(0x2000));
+        let result: Result<(), _> = with_pages(&boot_services, PageCount::new(1), |_| Err(efi::Status::ABORTED));
+        assert_eq!(result, Err(efi::Status::ABORTED));
+        assert_eq!(with_pages(&boot_services, PageCount::new(0), |_| Ok(())), Err(efi::Status::INVALID_PARAMETER));
+        assert!(allocations().is_empty());
+    }
+
+    #[test]
+    fn test_arena() {
+        let boot_services = boot_services();
+        let mut arena = ArenaAllocator::with_chunk_pages(&boot_services, efi::LOADER_DATA, 2);
+        let first = arena.alloc(1u8).unwrap() as *mut u8 as usize;
+        let second = arena.alloc(2u64).unwrap() as *mut u64 as usize;
+        assert_eq!(second, first + 8);
+        let slice = arena.alloc_slice_copy(&[3u16; 4]).unwrap();
+        assert_eq!(slice, [3; 4]);
+        assert_eq!(slice.as_ptr() as usize, second + 8);
+        assert_eq!(arena.allocated_pages(), PageCount::new(2));
+
+        // Too large for a chunk: the allocation gets its own pages and bumping continues in the current chunk.
+        let large = arena.alloc_slice_copy(&[0u8; 0x3001]).unwrap();
+        assert_eq!(large.len(), 0x3001);
+        assert_eq!(arena.alloc(4u8).unwrap() as *mut u8 as usize, second + 16);
+        assert_eq!(arena.allocated_pages(), PageCount::new(6));
+
+        // Exhausting the chunk starts a new one.
+        arena.alloc([0u8; 0x1ff0]).unwrap();
+        assert_eq!(arena.allocated_pages(), PageCount::new(8));
+        assert_eq!(allocations().len(), 3);
+        assert_eq!(
+            arena.alloc_layout(Layout::from_size_align(1, 0x2000).unwrap()),
+            Err(efi::Status::INVALID_PARAMETER)
+        );
+
+        arena.reset();
+        assert_eq!(arena.allocated_pages(), PageCount::new(0));
+        assert!(allocations().is_empty());
+        arena.alloc(5u32).unwrap();
+        drop(arena);
+        assert!(allocations().is_empty());
+    }
+
+    #[test]
+    fn test_arena_phases() {
+        let boot_services = boot_services();
+        let mut arena = ArenaAllocator::with_capacity(&boot_services, efi::LOADER_DATA, 1).unwrap();
+        assert_eq!(allocations().len(), 1);
+        let first = arena.allocate(Layout::new::<u32>()).unwrap();
+        assert_eq!(first.len(), 4);
+        let first = first.cast::<u8>();
+
+        // Only the most recent allocation is given back.
+        let second = arena.alloc_layout(Layout::new::<u32>()).unwrap();
+        unsafe { arena.deallocate(first, Layout::new::<u32>()) };
+        let third = arena.alloc_layout(Layout::new::<u32>()).unwrap();
+        assert_eq!(third.as_ptr() as usize, second.as_ptr() as usize + 4);
+        unsafe { arena.deallocate(third, Layout::new::<u32>()) };
+        unsafe { arena.deallocate(second, Layout::new::<u32>()) };
+        assert_eq!(arena.alloc_layout(Layout::new::<u32>()).unwrap(), second);
+
+        // Clearing keeps the first chunk and bumps from its start again.
+        arena.alloc_slice_copy(&[0u8; 0x2000]).unwrap();
+        arena.alloc([0u8; 0xff9]).unwrap();
+        assert_eq!(allocations().len(), 3);
+        arena.clear();
+        assert_eq!(allocations().len(), 1);
+        assert_eq!(arena.allocated_pages(), PageCount::new(1));
+        assert_eq!(arena.alloc_layout(Layout::new::<u32>()).unwrap(), first);
+        assert_eq!(allocations().len(), 1);
+
+        drop(arena);
+        assert!(allocations().is_empty());
+    }
+
+    #[test]
+    fn test_arena_page_0() {
+        std::thread_local! {
+            static FREED: core::cell::Cell<bool> = const { core::cell::Cell::new(false) };
+        }
+        extern "efiapi" fn allocate_page_0(
+            _allocate_type: efi::AllocateType,
+            _memory_type: efi::MemoryType,
+            _pages: usize,
+            address: *mut efi::PhysicalAddress,
+        ) -> efi::Status {
+            unsafe { *address = 0 };
+            efi::Status::SUCCESS
+        }
+        extern "efiapi" fn free_page_0(address: efi::PhysicalAddress, pages: usize) -> efi::Status {
+            assert_eq!((address, pages), (0, 1));
+            FREED.with(|freed| freed.set(true));
+            efi::Status::SUCCESS
+        }
+
+        let boot_services =
+            efi::BootServices { allocate_pages: allocate_page_0, free_pages: free_page_0, ..mock_efi_boot_services() };
+        let arena = ArenaAllocator::with_chunk_pages(&boot_services, efi::LOADER_DATA, 1);
+        assert_eq!(arena.alloc(1u8).map(|_| ()), Err(efi::Status::OUT_OF_RESOURCES));
+        assert_eq!(arena.allocated_pages(), PageCount::new(0));
+        // The page was given back.
+        assert!(FREED.with(|freed| freed.get()));
+    }
+}
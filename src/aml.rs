@@ -0,0 +1,335 @@
+//! Targeted patching of AML in ACPI definition blocks.
+//!
+//! Platforms often ship a static SSDT whose values are only known at boot, declared with `Name(FOO, 0)` and patched
+//! before the table is installed or once it is found with [`AcpiSdt`](crate::acpi_sdt::AcpiSdt).
+//! [`patch_name_integer`] rewrites the integer of such a declaration in place, given the absolute path of the name,
+//! and updates the table checksum.
+//!
+//! This is not an AML interpreter. Declarations are found in the term list of the table and of the `Scope`, `Device`,
+//! `Processor`, `PowerResource` and `ThermalZone` objects it contains; names local to methods are ignored. Integers
+//! are rewritten with their existing encoding, so the new value must fit in the width the compiler chose.
+//!
+use alloc::vec::Vec;
+use core::mem;
+
+use r_efi::efi;
+
+use crate::acpi_sdt::{update_checksum, SdtHeader};
+
+const ZERO_OP: u8 = 0x00;
+const ONE_OP: u8 = 0x01;
+const NAME_OP: u8 = 0x08;
+const BYTE_PREFIX: u8 = 0x0a;
+const WORD_PREFIX: u8 = 0x0b;
+const DWORD_PREFIX: u8 = 0x0c;
+const QWORD_PREFIX: u8 = 0x0e;
+const SCOPE_OP: u8 = 0x10;
+const METHOD_OP: u8 = 0x14;
+const DUAL_NAME_PREFIX: u8 = 0x2e;
+const MULTI_NAME_PREFIX: u8 = 0x2f;
+const EXT_OP_PREFIX: u8 = 0x5b;
+const ROOT_CHAR: u8 = b'\\';
+const PARENT_PREFIX_CHAR: u8 = b'^';
+const ONES_OP: u8 = 0xff;
+
+// Extended opcodes of the objects with a term list, and the size of their fixed fields following the name.
+const DEVICE_OP: u8 = 0x82;
+const PROCESSOR_OP: u8 = 0x83;
+const POWER_RES_OP: u8 = 0x84;
+const THERMAL_ZONE_OP: u8 = 0x85;
+
+type NameSeg = [u8; 4];
+
+/// Return the integer declared with `Name` at `path`, e.g. `"\\_SB.PCI0.FOO"`.
+///
+/// Returns `efi::Status::INVALID_PARAMETER` if the table or the path is malformed or the object is not an integer, and
+/// `efi::Status::NOT_FOUND` if no declaration of `path` is found.
+pub fn find_name_integer(table: &[u8], path: &str) -> Result<u64, efi::Status> {
+    let (offset, encoding) = locate(table, path)?;
+    Ok(encoding.read(&table[offset..]))
+}
+
+/// Rewrite the integer declared with `Name` at `path`, e.g. `"\\_SB.PCI0.FOO"`, and update the table checksum.
+///
+/// `table` is the whole definition block, header included. Returns the errors of [`find_name_integer`], and
+/// `efi::Status::BAD_BUFFER_SIZE` if `value` does not fit in the existing encoding of the integer.
+pub fn patch_name_integer(table: &mut [u8], path: &str, value: u64) -> Result<(), efi::Status> {
+    let (offset, encoding) = locate(table, path)?;
+    encoding.write(&mut table[offset..], value)?;
+    update_checksum(table);
+    Ok(())
+}
+
+fn locate(table: &[u8], path: &str) -> Result<(usize, Encoding), efi::Status> {
+    let header_size = mem::size_of::<SdtHeader>();
+    let length = table.get(4..8).map(|length| u32::from_le_bytes(length.try_into().unwrap()) as usize);
+    let table = match length {
+        Some(length) if length >= header_size && length <= table.len() => &table[..length],
+        _ => return Err(efi::Status::INVALID_PARAMETER),
+    };
+    let target = parse_path(path).ok_or(efi::Status::INVALID_PARAMETER)?;
+
+    let offset = find_name(table, header_size, table.len(), &[], &target).ok_or(efi::Status::NOT_FOUND)?;
+    let encoding = Encoding::parse(&table[offset..]).ok_or(efi::Status::INVALID_PARAMETER)?;
+    Ok((offset, encoding))
+}
+
+/// Parse an absolute ASL path, padding short segments with `_`.
+fn parse_path(path: &str) -> Option<Vec<NameSeg>> {
+    let path = path.strip_prefix('\\')?;
+    path.split('.')
+        .map(|segment| {
+            let bytes = segment.as_bytes();
+            if bytes.is_empty() || bytes.len() > 4 {
+                return None;
+            }
+            let mut name = [b'_'; 4];
+            name[..bytes.len()].copy_from_slice(bytes);
+            is_name_seg(&name).then_some(name)
+        })
+        .collect()
+}
+
+fn is_name_seg(name: &[u8]) -> bool {
+    name.len() == 4
+        && (name[0].is_ascii_uppercase() || name[0] == b'_')
+        && name[1..].iter().all(|&c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == b'_')
+}
+
+/// Search the term list in `aml[start..end]` for a `Name` declaration of `target`, returning the offset of its data.
+fn find_name(aml: &[u8], start: usize, end: usize, scope: &[NameSeg], target: &[NameSeg]) -> Option<usize> {
+    let mut offset = start;
+    while offset < end {
+        let (op_len, fixed_len) = match aml[offset] {
+            NAME_OP => {
+                if let Some((name, name_len)) = parse_name_string(&aml[offset + 1..end], scope) {
+                    if name == target {
+                        return Some(offset + 1 + name_len);
+                    }
+                    offset += 1 + name_len;
+                    continue;
+                }
+                offset += 1;
+                continue;
+            }
+            SCOPE_OP => (1, 0),
+            METHOD_OP => {
+                // Method bodies are skipped, names declared in them only exist while the method runs.
+                match parse_pkg_length(&aml[offset + 1..end]) {
+                    Some((pkg_length, _)) => offset += 1 + pkg_length,
+                    None => offset += 1,
+                }
+                continue;
+            }
+            EXT_OP_PREFIX => match aml.get(offset + 1) {
+                Some(&(DEVICE_OP | THERMAL_ZONE_OP)) => (2, 0),
+                Some(&PROCESSOR_OP) => (2, 6),
+                Some(&POWER_RES_OP) => (2, 3),
+                _ => {
+                    offset += 1;
+                    continue;
+                }
+            },
+            _ => {
+                offset += 1;
+                continue;
+            }
+        };
+
+        // Object with a term list: Op PkgLength NameString FixedFields TermList.
+        let object = offset + op_len;
+        let Some((pkg_length, pkg_length_len)) = parse_pkg_length(&aml[object..end]) else {
+            offset += 1;
+            continue;
+        };
+        let object_end = object + pkg_length;
+        let Some((name, name_len)) = parse_name_string(&aml[object + pkg_length_len..object_end], scope) else {
+            offset += 1;
+            continue;
+        };
+        let body = object + pkg_length_len + name_len + fixed_len;
+        if body <= object_end {
+            if let Some(found) = find_name(aml, body, object_end, &name, target) {
+                return Some(found);
+            }
+        }
+        offset = object_end;
+    }
+    None
+}
+
+/// Parse a PkgLength, returning the length it encodes (which includes itself) and its own size.
+fn parse_pkg_length(aml: &[u8]) -> Option<(usize, usize)> {
+    let lead = *aml.first()?;
+    let follow = (lead >> 6) as usize;
+    let mut length = if follow == 0 { (lead & 0x3f) as usize } else { (lead & 0x0f) as usize };
+    for index in 0..follow {
+        length |= (*aml.get(1 + index)? as usize) << (4 + 8 * index);
+    }
+    (length > follow && length <= aml.len()).then_some((length, 1 + follow))
+}
+
+/// Parse a NameString, returning the absolute path it resolves to from `scope` and its size.
+fn parse_name_string(aml: &[u8], scope: &[NameSeg]) -> Option<(Vec<NameSeg>, usize)> {
+    let mut offset = 0;
+    let mut path = match aml.first()? {
+        &ROOT_CHAR => {
+            offset += 1;
+            Vec::new()
+        }
+        _ => {
+            let parents = aml.iter().take_while(|&&c| c == PARENT_PREFIX_CHAR).count();
+            offset += parents;
+            scope[..scope.len().checked_sub(parents)?].to_vec()
+        }
+    };
+    let count = match *aml.get(offset)? {
+        0 => {
+            offset += 1;
+            0
+        }
+        DUAL_NAME_PREFIX => {
+            offset += 1;
+            2
+        }
+        MULTI_NAME_PREFIX => {
+            offset += 2;
+            *aml.get(offset - 1)? as usize
+        }
+        _ => 1,
+    };
+    for _ in 0..count {
+        let name = aml.get(offset..offset + 4)?;
+        if !is_name_seg(name) {
+            return None;
+        }
+        path.push(name.try_into().unwrap());
+        offset += 4;
+    }
+    Some((path, offset))
+}
+
+/// Encoding of an integer data object.
+#[derive(Debug, Clone, Copy)]
+enum Encoding {
+    Constant,
+    Prefixed(usize),
+}
+
+impl Encoding {
+    fn parse(aml: &[u8]) -> Option<Self> {
+        let width = match *aml.first()? {
+            ZERO_OP | ONE_OP | ONES_OP => return Some(Self::Constant),
+            BYTE_PREFIX => 1,
+            WORD_PREFIX => 2,
+            DWORD_PREFIX => 4,
+            QWORD_PREFIX => 8,
+            _ => return None,
+        };
+        (aml.len() > width).then_some(Self::Prefixed(width))
+    }
+
+    fn read(self, aml: &[u8]) -> u64 {
+        match self {
+            Self::Constant => match aml[0] {
+                ZERO_OP => 0,
+                ONE_OP => 1,
+                _ => u64::MAX,
+            },
+            Self::Prefixed(width) => {
+                let mut bytes = [0; 8];
+                bytes[..width].copy_from_slice(&aml[1..1 + width]);
+                u64::from_le_bytes(bytes)
+            }
+        }
+    }
+
+    fn write(self, aml: &mut [u8], value: u64) -> Result<(), efi::Status> {
+        match self {
+            Self::Constant => {
+                aml[0] = match value {
+                    0 => ZERO_OP,
+                    1 => ONE_OP,
+                    u64::MAX => ONES_OP,
+                    _ => return Err(efi::Status::BAD_BUFFER_SIZE),
+                };
+            }
+            Self::Prefixed(width) => {
+                if width < 8 && value >> (8 * width) != 0 {
+                    return Err(efi::Status::BAD_BUFFER_SIZE);
+                }
+                aml[1..1 + width].copy_from_slice(&value.to_le_bytes()[..width]);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(op: &[u8], contents: &[u8]) -> Vec<u8> {
+        assert!(contents.len() < 0x3f);
+        let mut aml = op.to_vec();
+        aml.push(contents.len() as u8 + 1);
+        aml.extend_from_slice(contents);
+        aml
+    }
+
+    fn name(name: &[u8], data: &[u8]) -> Vec<u8> {
+        [&[NAME_OP], name, data].concat()
+    }
+
+    fn ssdt() -> Vec<u8> {
+        let method = package(&[METHOD_OP], &[b"_STA".as_slice(), &[0x00], &name(b"BAR_", &[ONE_OP])].concat());
+        let device = package(
+            &[EXT_OP_PREFIX, DEVICE_OP],
+            &[b"PCI0".as_slice(), &name(b"BAR_", &[WORD_PREFIX, 0x34, 0x12]), &method].concat(),
+        );
+        let scope = package(
+            &[SCOPE_OP],
+            &[b"\\_SB_".as_slice(), &name(b"FOO_", &[BYTE_PREFIX, 0x12]), &device, &name(b"ON__", &[ONE_OP])].concat(),
+        );
+        let absolute = name(b"\\\x2f\x03_SB_PCI0BAZ_", &[DWORD_PREFIX, 0x78, 0x56, 0x34, 0x12]);
+        let mut table = [b"SSDT".as_slice(), &[0; 32], &scope, &absolute].concat();
+        let length = table.len() as u32;
+        table[4..8].copy_from_slice(&length.to_le_bytes());
+        update_checksum(&mut table);
+        table
+    }
+
+    fn checksum(table: &[u8]) -> u8 {
+        table.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+    }
+
+    #[test]
+    fn test_find_name_integer() {
+        let table = ssdt();
+        assert_eq!(find_name_integer(&table, "\\_SB.FOO"), Ok(0x12));
+        assert_eq!(find_name_integer(&table, "\\_SB.PCI0.BAR"), Ok(0x1234));
+        assert_eq!(find_name_integer(&table, "\\_SB.PCI0.BAZ_"), Ok(0x12345678));
+        assert_eq!(find_name_integer(&table, "\\_SB.ON"), Ok(1));
+        // Names declared in methods are not part of the namespace.
+        assert_eq!(find_name_integer(&table, "\\_SB.PCI0._STA.BAR"), Err(efi::Status::NOT_FOUND));
+        assert_eq!(find_name_integer(&table, "\\FOO"), Err(efi::Status::NOT_FOUND));
+        assert_eq!(find_name_integer(&table, "_SB.FOO"), Err(efi::Status::INVALID_PARAMETER));
+        assert_eq!(find_name_integer(&table, "\\_SB.FOOBAR"), Err(efi::Status::INVALID_PARAMETER));
+        assert_eq!(find_name_integer(&table[..20], "\\_SB.FOO"), Err(efi::Status::INVALID_PARAMETER));
+    }
+
+    #[test]
+    fn test_patch_name_integer() {
+        let mut table = ssdt();
+        patch_name_integer(&mut table, "\\_SB.PCI0.BAR", 0xbeef).unwrap();
+        patch_name_integer(&mut table, "\\_SB.ON", 0).unwrap();
+        assert_eq!(find_name_integer(&table, "\\_SB.PCI0.BAR"), Ok(0xbeef));
+        assert_eq!(find_name_integer(&table, "\\_SB.ON"), Ok(0));
+        assert_eq!(checksum(&table), 0);
+
+        let original = table.clone();
+        assert_eq!(patch_name_integer(&mut table, "\\_SB.FOO", 0x100), Err(efi::Status::BAD_BUFFER_SIZE));
+        assert_eq!(patch_name_integer(&mut table, "\\_SB.ON", 2), Err(efi::Status::BAD_BUFFER_SIZE));
+        assert_eq!(table, original);
+    }
+}
@@ -0,0 +1,332 @@
+//! Read cache for block devices.
+//!
+//! Parsing file systems or configuration files from a disk issues many small reads, often of the same or neighbouring
+//! blocks, which is slow on media with a high per-request cost such as USB or network disks. [`BlockCache`] serves
+//! byte-granular reads from a fixed number of cached blocks, and on a miss reads ahead the blocks that follow, so that
+//! a sequential walk costs one device request per read-ahead window.
+//!
+//! Devices are accessed through [`BlockDevice`], implemented for the Block I/O protocol by [`BlockIoDevice`].
+//!
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::{
+    cell::{Cell, RefCell},
+    ffi::c_void,
+    marker::PhantomData,
+};
+
+use r_efi::{efi, protocols::block_io};
+
+/// Device read in whole blocks.
+pub trait BlockDevice {
+    /// Return the size of a block in bytes.
+    fn block_size(&self) -> usize;
+
+    /// Return the number of blocks of the device.
+    fn block_count(&self) -> u64;
+
+    /// Read the blocks from `lba` into `buffer`, whose length is a multiple of the block size.
+    fn read_blocks(&self, lba: efi::Lba, buffer: &mut [u8]) -> Result<(), efi::Status>;
+}
+
+/// [`BlockDevice`] backed by the Block I/O protocol.
+pub struct BlockIoDevice<'a> {
+    protocol: *mut block_io::Protocol,
+    _lifetime_marker: PhantomData<&'a block_io::Protocol>,
+}
+
+impl BlockIoDevice<'_> {
+    /// Create a device reading through `protocol`.
+    ///
+    /// # Safety
+    /// `protocol` must point to a valid protocol structure for the lifetime of the device.
+    pub unsafe fn new(protocol: *mut block_io::Protocol) -> Self {
+        Self { protocol, _lifetime_marker: PhantomData }
+    }
+
+    fn media(&self) -> block_io::Media {
+        // SAFETY: the protocol is valid, and its media stays valid with it.
+        unsafe { *(*self.protocol).media }
+    }
+}
+
+impl BlockDevice for BlockIoDevice<'_> {
+    fn block_size(&self) -> usize {
+        self.media().block_size as usize
+    }
+
+    fn block_count(&self) -> u64 {
+        let media = self.media();
+        if media.media_present {
+            media.last_block + 1
+        } else {
+            0
+        }
+    }
+
+    fn read_blocks(&self, lba: efi::Lba, buffer: &mut [u8]) -> Result<(), efi::Status> {
+        let media_id = self.media().media_id;
+        // SAFETY: the protocol is valid for the lifetime of the device.
+        let read_blocks = unsafe { (*self.protocol).read_blocks };
+        let status = read_blocks(self.protocol, media_id, lba, buffer.len(), buffer.as_mut_ptr() as *mut c_void);
+        if status.is_error() {
+            return Err(status);
+        }
+        Ok(())
+    }
+}
+
+struct CachedBlock {
+    lba: efi::Lba,
+    data: Box<[u8]>,
+    last_used: u64,
+}
+
+/// Least-recently-used cache of the blocks of a device, with read-ahead.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::block_cache::{BlockCache, BlockDevice};
+/// use r_efi::efi;
+///
+/// fn read_config<D: BlockDevice>(device: D, offset: u64) -> Result<[u8; 64], efi::Status> {
+///     let cache = BlockCache::new(device, 64).read_ahead(8);
+///     let mut header = [0; 64];
+///     cache.read(offset, &mut header)?;
+///     Ok(header)
+/// }
+/// ```
+pub struct BlockCache<D: BlockDevice> {
+    device: D,
+    capacity: usize,
+    read_ahead: usize,
+    blocks: RefCell<Vec<CachedBlock>>,
+    clock: Cell<u64>,
+    hits: Cell<u64>,
+    misses: Cell<u64>,
+}
+
+impl<D: BlockDevice> BlockCache<D> {
+    /// Cache up to `capacity` blocks of `device`, at least one, without read-ahead.
+    pub fn new(device: D, capacity: usize) -> Self {
+        Self {
+            device,
+            capacity: capacity.max(1),
+            read_ahead: 0,
+            blocks: RefCell::new(Vec::new()),
+            clock: Cell::new(0),
+            hits: Cell::new(0),
+            misses: Cell::new(0),
+        }
+    }
+
+    /// Read up to `blocks` more blocks after each missed block, bounded by the capacity of the cache.
+    pub fn read_ahead(mut self, blocks: usize) -> Self {
+        self.read_ahead = blocks;
+        self
+    }
+
+    /// Return the device.
+    pub fn device(&self) -> &D {
+        &self.device
+    }
+
+    /// Read `buffer.len()` bytes from `offset`.
+    ///
+    /// Returns `efi::Status::INVALID_PARAMETER` if the range extends past the end of the device, and the error of the
+    /// device if a block cannot be read.
+    pub fn read(&self, offset: u64, buffer: &mut [u8]) -> Result<(), efi::Status> {
+        let block_size = self.device.block_size() as u64;
+        if block_size == 0 {
+            return Err(efi::Status::NO_MEDIA);
+        }
+        let end = offset.checked_add(buffer.len() as u64).ok_or(efi::Status::INVALID_PARAMETER)?;
+        if end > self.device.block_count().saturating_mul(block_size) {
+            return Err(efi::Status::INVALID_PARAMETER);
+        }
+
+        let mut position = offset;
+        for chunk in buffer.chunks_mut(block_size as usize) {
+            // Chunks are not aligned on blocks, so a chunk may span two blocks.
+            let mut filled = 0;
+            while filled < chunk.len() {
+                let lba = position / block_size;
+                let start = (position % block_size) as usize;
+                let len = (block_size as usize - start).min(chunk.len() - filled);
+                self.with_block(lba, |data| chunk[filled..filled + len].copy_from_slice(&data[start..start + len]))?;
+                filled += len;
+                position += len as u64;
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop every cached block, e.g. after the device was written to or its media changed.
+    pub fn invalidate(&self) {
+        self.blocks.borrow_mut().clear();
+    }
+
+    /// Return the number of block lookups served from the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits.get()
+    }
+
+    /// Return the number of block lookups that read the device.
+    pub fn misses(&self) -> u64 {
+        self.misses.get()
+    }
+
+    fn with_block(&self, lba: efi::Lba, f: impl FnOnce(&[u8])) -> Result<(), efi::Status> {
+        let now = self.clock.get() + 1;
+        self.clock.set(now);
+        let mut blocks = self.blocks.borrow_mut();
+        if let Some(block) = blocks.iter_mut().find(|block| block.lba == lba) {
+            self.hits.set(self.hits.get() + 1);
+            block.last_used = now;
+            f(&block.data);
+            return Ok(());
+        }
+        self.misses.set(self.misses.get() + 1);
+
+        // Read the missed block along with the following ones that are not cached yet.
+        let block_size = self.device.block_size();
+        let window = (self.read_ahead + 1).min(self.capacity) as u64;
+        let count = (1..window)
+            .take_while(|&index| {
+                let next = lba + index;
+                next < self.device.block_count() && !blocks.iter().any(|block| block.lba == next)
+            })
+            .count()
+            + 1;
+        let mut data = vec![0; count * block_size];
+        self.device.read_blocks(lba, &mut data)?;
+        f(&data[..block_size]);
+
+        for (index, data) in data.chunks_exact(block_size).enumerate() {
+            let block = CachedBlock { lba: lba + index as u64, data: data.into(), last_used: now };
+            if blocks.len() < self.capacity {
+                blocks.push(block);
+            } else if let Some(oldest) = blocks.iter_mut().min_by_key(|block| block.last_used) {
+                *oldest = block;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::unimplemented_service;
+
+    /// Device of 16 blocks of 8 bytes, where each byte holds its offset.
+    struct MemoryDevice {
+        reads: RefCell<Vec<(efi::Lba, usize)>>,
+    }
+
+    impl BlockDevice for MemoryDevice {
+        fn block_size(&self) -> usize {
+            8
+        }
+
+        fn block_count(&self) -> u64 {
+            16
+        }
+
+        fn read_blocks(&self, lba: efi::Lba, buffer: &mut [u8]) -> Result<(), efi::Status> {
+            self.reads.borrow_mut().push((lba, buffer.len() / 8));
+            for (index, byte) in buffer.iter_mut().enumerate() {
+                *byte = (lba as usize * 8 + index) as u8;
+            }
+            Ok(())
+        }
+    }
+
+    fn cache(capacity: usize, read_ahead: usize) -> BlockCache<MemoryDevice> {
+        BlockCache::new(MemoryDevice { reads: RefCell::new(Vec::new()) }, capacity).read_ahead(read_ahead)
+    }
+
+    fn reads(cache: &BlockCache<MemoryDevice>) -> Vec<(efi::Lba, usize)> {
+        cache.device().reads.take()
+    }
+
+    #[test]
+    fn test_read() {
+        let cache = cache(4, 2);
+        let mut buffer = [0; 10];
+        cache.read(5, &mut buffer).unwrap();
+        assert_eq!(buffer, [5, 6, 7, 8, 9, 10, 11, 12, 13, 14]);
+        // Block 0 is read along with two blocks ahead.
+        assert_eq!(reads(&cache), [(0, 3)]);
+        assert_eq!((cache.hits(), cache.misses()), (2, 1));
+
+        cache.read(20, &mut buffer).unwrap();
+        assert_eq!(buffer[0], 20);
+        assert_eq!(reads(&cache), [(3, 3)]);
+
+        cache.invalidate();
+        cache.read(0, &mut buffer[..1]).unwrap();
+        assert_eq!(reads(&cache), [(0, 3)]);
+
+        assert_eq!(cache.read(127, &mut [0; 2]), Err(efi::Status::INVALID_PARAMETER));
+        cache.read(127, &mut [0; 1]).unwrap();
+    }
+
+    #[test]
+    fn test_eviction() {
+        let cache = cache(2, 0);
+        let mut byte = [0];
+        for lba in [0u64, 1, 0, 2, 0, 1] {
+            cache.read(lba * 8, &mut byte).unwrap();
+            assert_eq!(byte[0], lba as u8 * 8);
+        }
+        // Block 1 is evicted by block 2, block 0 being used more recently.
+        assert_eq!(reads(&cache), [(0, 1), (1, 1), (2, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn test_block_io_device() {
+        extern "efiapi" fn read_blocks(
+            _this: *mut block_io::Protocol,
+            media_id: u32,
+            lba: efi::Lba,
+            size: usize,
+            buffer: *mut c_void,
+        ) -> efi::Status {
+            assert_eq!(media_id, 3);
+            let buffer = unsafe { core::slice::from_raw_parts_mut(buffer as *mut u8, size) };
+            for (index, block) in buffer.chunks_mut(512).enumerate() {
+                block.fill(lba as u8 + index as u8);
+            }
+            efi::Status::SUCCESS
+        }
+        let media = block_io::Media {
+            media_id: 3,
+            removable_media: false,
+            media_present: true,
+            logical_partition: false,
+            read_only: true,
+            write_caching: false,
+            block_size: 512,
+            io_align: 1,
+            last_block: 7,
+            lowest_aligned_lba: 0,
+            logical_blocks_per_physical_block: 1,
+            optimal_transfer_length_granularity: 0,
+        };
+        let mut protocol = block_io::Protocol {
+            revision: block_io::REVISION3,
+            media: &media,
+            reset: unimplemented_service(),
+            read_blocks,
+            write_blocks: unimplemented_service(),
+            flush_blocks: unimplemented_service(),
+        };
+        let device = unsafe { BlockIoDevice::new(&mut protocol) };
+        assert_eq!((device.block_size(), device.block_count()), (512, 8));
+        let cache = BlockCache::new(device, 4).read_ahead(3);
+        let mut buffer = [0; 4];
+        cache.read(3 * 512 - 2, &mut buffer).unwrap();
+        assert_eq!(buffer, [2, 2, 3, 3]);
+    }
+}
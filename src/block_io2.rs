@@ -0,0 +1,576 @@
+//! Block I/O 2 protocol.
+//!
+//! The UEFI `EFI_BLOCK_IO2_PROTOCOL` starts block transfers that complete in the background, signaling the event of a
+//! token when done, so that several transfers can overlap, e.g. to load a large image from several disks at once.
+//! [`BlockIo2`] starts transfers on owned buffers, which are handed back on completion either as the output of a
+//! [`Transfer`] future, or to a closure.
+//!
+//! Transfer futures complete by polling their token event, like other UEFI completion sources; see the `executor`
+//! crate for a scheduler that polls waiting tasks again from its idle hook.
+//!
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    cell::Cell,
+    ffi::c_void,
+    future::Future,
+    marker::PhantomData,
+    mem::{self, ManuallyDrop},
+    pin::Pin,
+    ptr,
+    task::{Context, Poll},
+};
+
+use r_efi::{efi, protocols::block_io};
+
+use crate::protocol;
+
+/// `EFI_BLOCK_IO2_PROTOCOL_GUID`.
+pub const PROTOCOL_GUID: efi::Guid =
+    efi::Guid::from_fields(0xa77b2472, 0xe282, 0x4e9f, 0xa2, 0x45, &[0xc2, 0xc0, 0xe2, 0x7b, 0xbc, 0xc1]);
+
+/// `EFI_BLOCK_IO2_TOKEN`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Token {
+    /// Event signaled when the transfer completes.
+    pub event: efi::Event,
+    /// Status of the completed transfer.
+    pub transaction_status: efi::Status,
+}
+
+/// `EFI_BLOCK_RESET_EX`: reset the device, with extended verification if requested.
+pub type ResetEx = extern "efiapi" fn(*mut Protocol, efi::Boolean) -> efi::Status;
+/// `EFI_BLOCK_READ_EX`: start reading blocks from an LBA into a buffer, signaling the token when done.
+pub type ReadBlocksEx = extern "efiapi" fn(*mut Protocol, u32, efi::Lba, *mut Token, usize, *mut c_void) -> efi::Status;
+/// `EFI_BLOCK_WRITE_EX`: start writing a buffer to blocks from an LBA, signaling the token when done.
+pub type WriteBlocksEx =
+    extern "efiapi" fn(*mut Protocol, u32, efi::Lba, *mut Token, usize, *mut c_void) -> efi::Status;
+/// `EFI_BLOCK_FLUSH_EX`: start flushing cached writes to the device, signaling the token when done.
+pub type FlushBlocksEx = extern "efiapi" fn(*mut Protocol, *mut Token) -> efi::Status;
+
+/// `EFI_BLOCK_IO2_PROTOCOL`.
+#[repr(C)]
+pub struct Protocol {
+    /// Media the protocol gives access to, shared with Block I/O.
+    pub media: *const block_io::Media,
+    /// Reset the device.
+    pub reset: ResetEx,
+    /// Start reading blocks.
+    pub read_blocks_ex: ReadBlocksEx,
+    /// Start writing blocks.
+    pub write_blocks_ex: WriteBlocksEx,
+    /// Start flushing cached writes.
+    pub flush_blocks_ex: FlushBlocksEx,
+}
+
+// SAFETY: `PROTOCOL_GUID` identifies the Block I/O 2 protocol, whose interface is `Protocol`.
+unsafe impl protocol::Protocol for Protocol {
+    const GUID: efi::Guid = PROTOCOL_GUID;
+}
+
+/// Callback receiving the buffer of a completed transfer, or the error it failed with.
+type Completion = Box<dyn FnOnce(Result<Vec<u8>, efi::Status>)>;
+
+/// Transfer completing in the background, handed to the event notification function.
+///
+/// CloseEvent is copied out of the boot services table, since the transfer may outlive the borrow of the table.
+struct PendingCallback {
+    close_event: efi::BootCloseEvent,
+    token: Token,
+    buffer: Vec<u8>,
+    completion: Completion,
+}
+
+#[derive(Clone, Copy)]
+enum Operation {
+    Read(efi::Lba),
+    Write(efi::Lba),
+    Flush,
+}
+
+/// Wrapper around the Block I/O 2 protocol.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::block_io2::BlockIo2;
+/// use r_efi::efi;
+///
+/// async fn read_header(disk: &BlockIo2<'_>) -> Result<Vec<u8>, efi::Status> {
+///     let block_size = disk.media().block_size as usize;
+///     disk.read_blocks(0, vec![0; block_size])?.await
+/// }
+/// ```
+pub struct BlockIo2<'a> {
+    boot_services: &'a efi::BootServices,
+    protocol: *mut Protocol,
+    _lifetime_marker: PhantomData<&'a Protocol>,
+}
+
+impl<'a> BlockIo2<'a> {
+    /// Create a wrapper around `protocol`.
+    ///
+    /// # Safety
+    /// `protocol` must point to a valid protocol structure for the lifetime of the wrapper.
+    pub unsafe fn new(boot_services: &'a efi::BootServices, protocol: *mut Protocol) -> Self {
+        Self { boot_services, protocol, _lifetime_marker: PhantomData }
+    }
+
+    /// Return the media the protocol gives access to.
+    pub fn media(&self) -> block_io::Media {
+        // SAFETY: the protocol is valid, and its media stays valid with it.
+        unsafe { *(*self.protocol).media }
+    }
+
+    /// Start reading the blocks from `lba` into `buffer`, whose length must be a multiple of the block size.
+    ///
+    /// Returns the error of ReadBlocksEx if the transfer cannot be started, e.g. `efi::Status::BAD_BUFFER_SIZE`.
+    pub fn read_blocks(&self, lba: efi::Lba, buffer: Vec<u8>) -> Result<Transfer<'a>, efi::Status> {
+        Transfer::start(self.boot_services, self.protocol, Operation::Read(lba), buffer)
+    }
+
+    /// Start writing `buffer` to the blocks from `lba`.
+    pub fn write_blocks(&self, lba: efi::Lba, buffer: Vec<u8>) -> Result<Transfer<'a>, efi::Status> {
+        Transfer::start(self.boot_services, self.protocol, Operation::Write(lba), buffer)
+    }
+
+    /// Start flushing the writes that completed so far. The transfer resolves to an empty buffer.
+    pub fn flush_blocks(&self) -> Result<Transfer<'a>, efi::Status> {
+        Transfer::start(self.boot_services, self.protocol, Operation::Flush, Vec::new())
+    }
+
+    /// Start reading the blocks from `lba` into `buffer`, calling `completion` at `efi::TPL_CALLBACK` once done.
+    pub fn read_blocks_with(
+        &self,
+        lba: efi::Lba,
+        buffer: Vec<u8>,
+        completion: impl FnOnce(Result<Vec<u8>, efi::Status>) + 'static,
+    ) -> Result<(), efi::Status> {
+        self.start_with(Operation::Read(lba), buffer, Box::new(completion))
+    }
+
+    /// Start writing `buffer` to the blocks from `lba`, calling `completion` at `efi::TPL_CALLBACK` once done.
+    pub fn write_blocks_with(
+        &self,
+        lba: efi::Lba,
+        buffer: Vec<u8>,
+        completion: impl FnOnce(Result<Vec<u8>, efi::Status>) + 'static,
+    ) -> Result<(), efi::Status> {
+        self.start_with(Operation::Write(lba), buffer, Box::new(completion))
+    }
+
+    fn start_with(&self, operation: Operation, buffer: Vec<u8>, completion: Completion) -> Result<(), efi::Status> {
+        extern "efiapi" fn complete(event: efi::Event, context: *mut c_void) {
+            // SAFETY: the context is the pending transfer leaked by `start_with`, whose event is signaled once.
+            let pending = unsafe { Box::from_raw(context as *mut PendingCallback) };
+            (pending.close_event)(event);
+            let status = pending.token.transaction_status;
+            (pending.completion)(if status.is_error() { Err(status) } else { Ok(pending.buffer) });
+        }
+
+        let boot_services = self.boot_services;
+        let pending = Box::into_raw(Box::new(PendingCallback {
+            close_event: boot_services.close_event,
+            token: Token { event: ptr::null_mut(), transaction_status: efi::Status::NOT_READY },
+            buffer,
+            completion,
+        }));
+        // SAFETY: the pending transfer is owned here until the transfer starts, then by the notification function.
+        let status = unsafe {
+            let status = (boot_services.create_event)(
+                efi::EVT_NOTIFY_SIGNAL,
+                efi::TPL_CALLBACK,
+                Some(complete),
+                pending as *mut c_void,
+                &mut (*pending).token.event,
+            );
+            if status.is_error() {
+                drop(Box::from_raw(pending));
+                return Err(status);
+            }
+            start(self.protocol, operation, &mut (*pending).token, &mut (*pending).buffer)
+        };
+        if status.is_error() {
+            // SAFETY: the transfer did not start, so the event is never signaled and the pending transfer is still
+            // owned here.
+            let pending = unsafe { Box::from_raw(pending) };
+            (boot_services.close_event)(pending.token.event);
+            return Err(status);
+        }
+        Ok(())
+    }
+}
+
+/// Start `operation` with `token`.
+///
+/// # Safety
+/// `protocol` must be valid, and `token` and `buffer` must stay valid until the token event is signaled.
+unsafe fn start(protocol: *mut Protocol, operation: Operation, token: *mut Token, buffer: &mut Vec<u8>) -> efi::Status {
+    let media_id = (*(*protocol).media).media_id;
+    let data = buffer.as_mut_ptr() as *mut c_void;
+    match operation {
+        Operation::Read(lba) => ((*protocol).read_blocks_ex)(protocol, media_id, lba, token, buffer.len(), data),
+        Operation::Write(lba) => ((*protocol).write_blocks_ex)(protocol, media_id, lba, token, buffer.len(), data),
+        Operation::Flush => ((*protocol).flush_blocks_ex)(protocol, token),
+    }
+}
+
+/// Transfer in progress, resolving to its buffer once complete.
+///
+/// Dropping a transfer that is still in progress polls it for up to a second, since the device may still access the
+/// token and buffer. If it does not complete in time, the token, its event and the buffer are leaked. Polling rather
+/// than WaitForEvent lets transfers be dropped above `efi::TPL_APPLICATION`. Forgetting a transfer leaks the buffer.
+pub struct Transfer<'a> {
+    boot_services: &'a efi::BootServices,
+    // Only dropped once the device is done with it.
+    token: ManuallyDrop<Box<Token>>,
+    buffer: Option<Vec<u8>>,
+    is_signaled: Cell<bool>,
+}
+
+impl<'a> Transfer<'a> {
+    fn start(
+        boot_services: &'a efi::BootServices,
+        protocol: *mut Protocol,
+        operation: Operation,
+        buffer: Vec<u8>,
+    ) -> Result<Self, efi::Status> {
+        let mut token = Box::new(Token { event: ptr::null_mut(), transaction_status: efi::Status::NOT_READY });
+        let status = (boot_services.create_event)(0, efi::TPL_CALLBACK, None, ptr::null_mut(), &mut token.event);
+        if status.is_error() {
+            return Err(status);
+        }
+        let token = ManuallyDrop::new(token);
+        let mut transfer = Self { boot_services, token, buffer: Some(buffer), is_signaled: Cell::new(false) };
+        let token = &mut **transfer.token as *mut Token;
+        let buffer = transfer.buffer.as_mut().unwrap();
+        // SAFETY: the token and buffer are owned by the transfer, which waits for completion before releasing them.
+        let status = unsafe { start(protocol, operation, token, buffer) };
+        if status.is_error() {
+            // Nothing is in progress, so the buffer can be released right away.
+            transfer.buffer = None;
+            (boot_services.close_event)(transfer.token.event);
+            return Err(status);
+        }
+        Ok(transfer)
+    }
+
+    /// Return true once the transfer completed.
+    pub fn is_complete(&self) -> bool {
+        if self.buffer.is_some() && !self.is_signaled.get() {
+            // Checking the event clears its signaled state, so the completion is latched.
+            let is_signaled = (self.boot_services.check_event)(self.token.event) == efi::Status::SUCCESS;
+            self.is_signaled.set(is_signaled);
+        }
+        self.buffer.is_none() || self.is_signaled.get()
+    }
+
+    /// Wait for the transfer to complete.
+    ///
+    /// Must be called at `efi::TPL_APPLICATION`.
+    pub fn wait(mut self) -> Result<Vec<u8>, efi::Status> {
+        self.wait_for_event();
+        self.finish()
+    }
+
+    fn wait_for_event(&mut self) {
+        if !self.is_complete() {
+            let mut index = 0;
+            (self.boot_services.wait_for_event)(1, &mut self.token.event, &mut index);
+        }
+    }
+
+    /// Release the completed transfer, returning its buffer or its error.
+    fn finish(&mut self) -> Result<Vec<u8>, efi::Status> {
+        let buffer = self.buffer.take().expect("transfer already finished");
+        (self.boot_services.close_event)(self.token.event);
+        let status = self.token.transaction_status;
+        if status.is_error() {
+            Err(status)
+        } else {
+            Ok(buffer)
+        }
+    }
+}
+
+impl Future for Transfer<'_> {
+    type Output = Result<Vec<u8>, efi::Status>;
+
+    fn poll(self: Pin<&mut Self>, _context: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if this.is_complete() {
+            Poll::Ready(this.finish())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Transfer<'_> {
+    /// Interval between checks of a transfer being dropped, in microseconds.
+    const DROP_POLL_INTERVAL: usize = 10;
+    /// Number of checks of a transfer being dropped before it is leaked, for a total of a second.
+    const DROP_POLL_COUNT: usize = 100_000;
+}
+
+impl Drop for Transfer<'_> {
+    fn drop(&mut self) {
+        if self.buffer.is_some() {
+            let mut polls = 0;
+            while !self.is_complete() {
+                if polls == Self::DROP_POLL_COUNT {
+                    // The device may still access the token and buffer, and signal the event.
+                    mem::forget(self.buffer.take());
+                    return;
+                }
+                (self.boot_services.stall)(Self::DROP_POLL_INTERVAL);
+                polls += 1;
+            }
+            let _ = self.finish();
+        }
+        // SAFETY: the transfer is complete or never started, so the device no longer uses the token.
+        unsafe { ManuallyDrop::drop(&mut self.token) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        cell::{Cell, RefCell},
+        rc::Rc,
+        sync::Arc,
+        task::{Wake, Waker},
+    };
+
+    use crate::test_support::mock_efi_boot_services;
+
+    #[derive(Default)]
+    struct MockState {
+        /// Events created, with their notification function and context, and whether they are signaled.
+        events: Vec<(Option<(efi::EventNotify, usize)>, bool)>,
+        closed: Vec<usize>,
+        /// Transfers started, as their token, LBA and buffer.
+        transfers: Vec<(usize, efi::Lba, usize, usize)>,
+        /// Number of calls to Stall, and whether the device stopped completing transfers.
+        stalls: usize,
+        is_stuck: bool,
+    }
+
+    std::thread_local! {
+        static STATE: RefCell<MockState> = RefCell::new(MockState::default());
+    }
+
+    extern "efiapi" fn create_event(
+        _event_type: u32,
+        _tpl: efi::Tpl,
+        notify: Option<efi::EventNotify>,
+        context: *mut c_void,
+        event: *mut efi::Event,
+    ) -> efi::Status {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            state.events.push((notify.map(|notify| (notify, context as usize)), false));
+            // Events are numbered from 1 in creation order.
+            unsafe { *event = state.events.len() as efi::Event };
+        });
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn check_event(event: efi::Event) -> efi::Status {
+        STATE.with(|state| {
+            let signaled = &mut state.borrow_mut().events[event as usize - 1].1;
+            if core::mem::take(signaled) {
+                efi::Status::SUCCESS
+            } else {
+                efi::Status::NOT_READY
+            }
+        })
+    }
+
+    extern "efiapi" fn wait_for_event(count: usize, events: *mut efi::Event, index: *mut usize) -> efi::Status {
+        assert_eq!(count, 1);
+        unsafe { *index = 0 };
+        // The mock device completes pending transfers as soon as they are waited for.
+        complete(unsafe { *events } as usize, efi::Status::SUCCESS);
+        check_event(unsafe { *events });
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn stall(_microseconds: usize) -> efi::Status {
+        let event = STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            state.stalls += 1;
+            let transfer = state.transfers.first().filter(|_| !state.is_stuck);
+            transfer.map(|&(token, ..)| unsafe { (*(token as *mut Token)).event } as usize)
+        });
+        // The mock device completes the oldest transfer while the caller stalls.
+        if let Some(event) = event {
+            complete(event, efi::Status::SUCCESS);
+        }
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn close_event(event: efi::Event) -> efi::Status {
+        STATE.with(|state| state.borrow_mut().closed.push(event as usize));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn read_blocks_ex(
+        _this: *mut Protocol,
+        media_id: u32,
+        lba: efi::Lba,
+        token: *mut Token,
+        size: usize,
+        buffer: *mut c_void,
+    ) -> efi::Status {
+        assert_eq!(media_id, 7);
+        if size % 512 != 0 {
+            return efi::Status::BAD_BUFFER_SIZE;
+        }
+        STATE.with(|state| state.borrow_mut().transfers.push((token as usize, lba, size, buffer as usize)));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn write_blocks_ex(
+        _this: *mut Protocol,
+        _media_id: u32,
+        _lba: efi::Lba,
+        _token: *mut Token,
+        _size: usize,
+        _buffer: *mut c_void,
+    ) -> efi::Status {
+        efi::Status::WRITE_PROTECTED
+    }
+
+    extern "efiapi" fn flush_blocks_ex(_this: *mut Protocol, token: *mut Token) -> efi::Status {
+        STATE.with(|state| state.borrow_mut().transfers.push((token as usize, 0, 0, 0)));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn reset(_this: *mut Protocol, _extended_verification: efi::Boolean) -> efi::Status {
+        efi::Status::SUCCESS
+    }
+
+    /// Complete the transfer whose token event is `event`: fill its buffer with the LBA, set its status and signal
+    /// the event, running its notification function if it has one.
+    fn complete(event: usize, status: efi::Status) {
+        let (transfer, notify) = STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let position = state
+                .transfers
+                .iter()
+                .position(|&(token, ..)| unsafe { (*(token as *mut Token)).event } as usize == event);
+            let transfer = position.map(|position| state.transfers.remove(position));
+            let (notify, signaled) = &mut state.events[event - 1];
+            *signaled = notify.is_none();
+            (transfer, *notify)
+        });
+        let Some((token, lba, size, buffer)) = transfer else {
+            return;
+        };
+        unsafe {
+            ptr::write_bytes(buffer as *mut u8, lba as u8, size);
+            (*(token as *mut Token)).transaction_status = status;
+        }
+        if let Some((notify, context)) = notify {
+            notify(event as efi::Event, context as *mut c_void);
+        }
+    }
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    fn media() -> block_io::Media {
+        block_io::Media {
+            media_id: 7,
+            removable_media: false,
+            media_present: true,
+            logical_partition: false,
+            read_only: false,
+            write_caching: false,
+            block_size: 512,
+            io_align: 1,
+            last_block: 0x3ff,
+            lowest_aligned_lba: 0,
+            logical_blocks_per_physical_block: 1,
+            optimal_transfer_length_granularity: 0,
+        }
+    }
+
+    fn boot_services() -> efi::BootServices {
+        efi::BootServices { create_event, check_event, wait_for_event, close_event, stall, ..mock_efi_boot_services() }
+    }
+
+    #[test]
+    fn test_transfer_future() {
+        let boot_services = boot_services();
+        let media = media();
+        let mut protocol = Protocol { media: &media, reset, read_blocks_ex, write_blocks_ex, flush_blocks_ex };
+        let disk = unsafe { BlockIo2::new(&boot_services, &mut protocol) };
+        assert_eq!(disk.media().block_size, 512);
+
+        // Two reads in flight, completing out of order.
+        let mut first = disk.read_blocks(2, vec![0; 512]).unwrap();
+        let mut second = disk.read_blocks(5, vec![0; 1024]).unwrap();
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut context = Context::from_waker(&waker);
+        assert!(Pin::new(&mut first).poll(&mut context).is_pending());
+        complete(second.token.event as usize, efi::Status::SUCCESS);
+        assert!(second.is_complete());
+        let Poll::Ready(Ok(buffer)) = Pin::new(&mut second).poll(&mut context) else { panic!("read not complete") };
+        assert_eq!(buffer, [5; 1024]);
+        complete(first.token.event as usize, efi::Status::DEVICE_ERROR);
+        assert_eq!(Pin::new(&mut first).poll(&mut context), Poll::Ready(Err(efi::Status::DEVICE_ERROR)));
+
+        assert_eq!(disk.read_blocks(0, vec![0; 100]).err(), Some(efi::Status::BAD_BUFFER_SIZE));
+        assert_eq!(disk.write_blocks(0, vec![0; 512]).err(), Some(efi::Status::WRITE_PROTECTED));
+        assert_eq!(disk.flush_blocks().unwrap().wait(), Ok(Vec::new()));
+
+        // Dropping a transfer in progress polls it until it completes.
+        drop(disk.read_blocks(9, vec![0; 512]).unwrap());
+        STATE.with(|state| {
+            let state = state.borrow();
+            assert!(state.transfers.is_empty());
+            assert_eq!(state.closed, [2, 1, 3, 4, 5, 6]);
+            assert_eq!(state.stalls, 1);
+        });
+
+        // A transfer that never completes is leaked rather than released under the device.
+        STATE.with(|state| state.borrow_mut().is_stuck = true);
+        drop(disk.read_blocks(10, vec![0; 512]).unwrap());
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            assert_eq!(state.transfers.len(), 1);
+            assert_eq!(state.closed, [2, 1, 3, 4, 5, 6]);
+            assert_eq!(state.stalls, 1 + Transfer::DROP_POLL_COUNT);
+            state.transfers.clear();
+        });
+    }
+
+    #[test]
+    fn test_completion_callback() {
+        let boot_services = boot_services();
+        let media = media();
+        let mut protocol = Protocol { media: &media, reset, read_blocks_ex, write_blocks_ex, flush_blocks_ex };
+        let disk = unsafe { BlockIo2::new(&boot_services, &mut protocol) };
+
+        let result = Rc::new(Cell::new(None));
+        let completed = Rc::clone(&result);
+        disk.read_blocks_with(3, vec![0; 512], move |buffer| completed.set(Some(buffer.map(|buffer| buffer[0]))))
+            .unwrap();
+        assert_eq!(result.get(), None);
+        complete(1, efi::Status::SUCCESS);
+        assert_eq!(result.get(), Some(Ok(3)));
+        assert_eq!(Rc::strong_count(&result), 1);
+
+        let completed = Rc::clone(&result);
+        let status = disk.write_blocks_with(3, vec![0; 512], move |_| completed.set(None));
+        assert_eq!(status, Err(efi::Status::WRITE_PROTECTED));
+        assert_eq!(Rc::strong_count(&result), 1);
+        assert_eq!(STATE.with(|state| state.borrow().closed.clone()), [1, 2]);
+    }
+}
@@ -0,0 +1,284 @@
+//! Timing of driver Start and ConnectController calls during boot.
+//!
+//! Finding the driver slowing down boot usually takes a performance build of the firmware. [`BootTimings`] samples
+//! the `EFI_TIMESTAMP_PROTOCOL` counter around driver Start functions and ConnectController calls, and reports the
+//! accumulated time per driver or controller, typically at ReadyToBoot.
+//!
+use alloc::vec::Vec;
+use core::{cell::RefCell, ptr, time::Duration};
+
+use r_efi::{
+    efi,
+    protocols::{device_path, timestamp},
+};
+
+use crate::{
+    connect,
+    event::{self, GroupEvent},
+};
+
+/// Kind of call measured by a [`DriverTiming`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// Start function of a driver binding. The handle is the driver image handle.
+    Start,
+    /// ConnectController. The handle is the controller.
+    Connect,
+}
+
+/// Time accumulated by the calls for one handle and [`Phase`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DriverTiming {
+    /// Driver image handle or controller, depending on `phase`.
+    pub handle: efi::Handle,
+    /// Kind of call measured.
+    pub phase: Phase,
+    /// Number of calls measured.
+    pub calls: usize,
+    /// Total time of the calls.
+    pub total: Duration,
+    /// Time of the longest call.
+    pub longest: Duration,
+}
+
+/// Recorder of driver Start and ConnectController timings, based on `EFI_TIMESTAMP_PROTOCOL`.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::boot_timing::{BootTimings, DriverTiming};
+/// use r_efi::efi;
+///
+/// fn connect_all(
+///     boot_services: &'static efi::BootServices,
+///     controllers: &[efi::Handle],
+///     print: fn(&DriverTiming),
+/// ) -> Result<(), efi::Status> {
+///     // Reporting at ReadyToBoot needs a recorder that lives until then.
+///     let timings: &'static BootTimings = Box::leak(Box::new(BootTimings::new(boot_services)?));
+///     for &controller in controllers {
+///         // Failures to connect are expected for controllers without a driver.
+///         let _ = timings.connect_controller(controller, &[], None, true);
+///     }
+///     // Print the five slowest at ReadyToBoot.
+///     let _report = timings.report_at_ready_to_boot(move |report| report.iter().take(5).for_each(print))?;
+///     // ...
+///     Ok(())
+/// }
+/// ```
+pub struct BootTimings<'a> {
+    boot_services: &'a efi::BootServices,
+    timestamp: &'a timestamp::Protocol,
+    properties: timestamp::Properties,
+    timings: RefCell<Vec<DriverTiming>>,
+}
+
+impl<'a> BootTimings<'a> {
+    /// Create a recorder using the timestamp protocol of the platform.
+    ///
+    /// Returns `efi::Status::NOT_FOUND` if the platform does not provide `EFI_TIMESTAMP_PROTOCOL`, and
+    /// `efi::Status::UNSUPPORTED` if the timestamp counter does not tick.
+    pub fn new(boot_services: &'a efi::BootServices) -> Result<Self, efi::Status> {
+        let mut interface = ptr::null_mut();
+        let status = (boot_services.locate_protocol)(
+            &timestamp::PROTOCOL_GUID as *const _ as *mut _,
+            ptr::null_mut(),
+            &mut interface,
+        );
+        if status.is_error() {
+            return Err(status);
+        }
+        if interface.is_null() {
+            return Err(efi::Status::NOT_FOUND);
+        }
+        // SAFETY: the interface of an installed timestamp protocol is never uninstalled.
+        let timestamp = unsafe { &*(interface as *const timestamp::Protocol) };
+        let mut properties = timestamp::Properties { frequency: 0, end_value: 0 };
+        let status = (timestamp.get_properties)(&mut properties);
+        if status.is_error() {
+            return Err(status);
+        }
+        if properties.frequency == 0 || properties.end_value == 0 {
+            return Err(efi::Status::UNSUPPORTED);
+        }
+        Ok(Self { boot_services, timestamp, properties, timings: RefCell::new(Vec::new()) })
+    }
+
+    /// Run `start`, the Start function of `driver`, and record how long it took.
+    ///
+    /// Typically called from a wrapper installed in place of the Start function of a driver binding.
+    pub fn measure_start<R>(&self, driver: efi::Handle, start: impl FnOnce() -> R) -> R {
+        self.measure(driver, Phase::Start, start)
+    }
+
+    /// Connect `controller` as [`connect::connect_controller`] does, and record how long it took.
+    pub fn connect_controller(
+        &self,
+        controller: efi::Handle,
+        drivers: &[efi::Handle],
+        remaining_device_path: Option<&device_path::Protocol>,
+        recursive: bool,
+    ) -> Result<(), efi::Status> {
+        self.measure(controller, Phase::Connect, || {
+            connect::connect_controller(self.boot_services, controller, drivers, remaining_device_path, recursive)
+        })
+    }
+
+    /// Return the timings recorded so far, slowest first.
+    pub fn report(&self) -> Vec<DriverTiming> {
+        let mut report = self.timings.borrow().clone();
+        report.sort_by(|a, b| b.total.cmp(&a.total));
+        report
+    }
+
+    /// Call `callback` with [`Self::report`] at ReadyToBoot.
+    ///
+    /// The recorder must be `'static`, since the returned registration may be leaked with `mem::forget`.
+    pub fn report_at_ready_to_boot(
+        &'static self,
+        mut callback: impl FnMut(&[DriverTiming]) + 'static,
+    ) -> Result<GroupEvent<'a>, efi::Status> {
+        event::on_ready_to_boot(self.boot_services, move || callback(&self.report()))
+    }
+
+    fn measure<R>(&self, handle: efi::Handle, phase: Phase, call: impl FnOnce() -> R) -> R {
+        let start = (self.timestamp.get_timestamp)();
+        let result = call();
+        let end = (self.timestamp.get_timestamp)();
+        self.record(handle, phase, self.elapsed(start, end));
+        result
+    }
+
+    fn elapsed(&self, start: u64, end: u64) -> Duration {
+        // The counter wraps to zero after its end value.
+        let ticks = if end >= start {
+            end - start
+        } else {
+            (self.properties.end_value - start).saturating_add(end).saturating_add(1)
+        };
+        let nanos = ticks as u128 * 1_000_000_000 / self.properties.frequency as u128;
+        Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
+    }
+
+    fn record(&self, handle: efi::Handle, phase: Phase, elapsed: Duration) {
+        // A measured call may itself connect controllers, e.g. from a recursive Start function.
+        let Ok(mut timings) = self.timings.try_borrow_mut() else {
+            return;
+        };
+        match timings.iter_mut().find(|timing| timing.handle == handle && timing.phase == phase) {
+            Some(timing) => {
+                timing.calls += 1;
+                timing.total += elapsed;
+                timing.longest = timing.longest.max(elapsed);
+            }
+            None => timings.push(DriverTiming { handle, phase, calls: 1, total: elapsed, longest: elapsed }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::{cell::Cell, ffi::c_void};
+
+    use crate::test_support::mock_efi_boot_services;
+
+    std::thread_local! {
+        static NOW: Cell<u64> = const { Cell::new(0) };
+    }
+
+    // 1MHz counter wrapping after 0xffff.
+    extern "efiapi" fn get_timestamp() -> u64 {
+        NOW.with(|now| now.get())
+    }
+
+    extern "efiapi" fn get_properties(properties: *mut timestamp::Properties) -> efi::Status {
+        unsafe { *properties = timestamp::Properties { frequency: 1_000_000, end_value: 0xffff } };
+        efi::Status::SUCCESS
+    }
+
+    static TIMESTAMP: timestamp::Protocol = timestamp::Protocol { get_timestamp, get_properties };
+
+    extern "efiapi" fn locate_protocol(
+        protocol: *mut efi::Guid,
+        _registration: *mut c_void,
+        interface: *mut *mut c_void,
+    ) -> efi::Status {
+        assert_eq!(unsafe { *protocol }, timestamp::PROTOCOL_GUID);
+        unsafe { *interface = &TIMESTAMP as *const _ as *mut c_void };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn connect_controller(
+        controller: efi::Handle,
+        _drivers: *mut efi::Handle,
+        _remaining_device_path: *mut efi::protocols::device_path::Protocol,
+        _recursive: efi::Boolean,
+    ) -> efi::Status {
+        NOW.with(|now| now.set(now.get() + 300));
+        if controller as usize == 0xbad {
+            return efi::Status::NOT_FOUND;
+        }
+        efi::Status::SUCCESS
+    }
+
+    fn advance(ticks: u64) {
+        NOW.with(|now| now.set((now.get() + ticks) % 0x10000));
+    }
+
+    #[test]
+    fn test_timings() {
+        let boot_services = efi::BootServices { locate_protocol, connect_controller, ..mock_efi_boot_services() };
+        let timings = BootTimings::new(&boot_services).unwrap();
+        let (driver, controller) = (0xd1 as efi::Handle, 0xc1 as efi::Handle);
+
+        let status = timings.measure_start(driver, || {
+            advance(100);
+            efi::Status::SUCCESS
+        });
+        assert_eq!(status, efi::Status::SUCCESS);
+        // Wraps past the end value of the counter.
+        NOW.with(|now| now.set(0xfff0));
+        timings.measure_start(driver, || advance(0x30));
+        timings.connect_controller(controller, &[], None, false).unwrap();
+        assert_eq!(timings.connect_controller(0xbad as efi::Handle, &[], None, false), Err(efi::Status::NOT_FOUND));
+
+        let report = timings.report();
+        assert_eq!(report.len(), 3);
+        assert_eq!(
+            report[0],
+            DriverTiming {
+                handle: controller,
+                phase: Phase::Connect,
+                calls: 1,
+                total: Duration::from_micros(300),
+                longest: Duration::from_micros(300),
+            }
+        );
+        assert_eq!(report[1].handle as usize, 0xbad);
+        assert_eq!(
+            report[2],
+            DriverTiming {
+                handle: driver,
+                phase: Phase::Start,
+                calls: 2,
+                total: Duration::from_micros(148),
+                longest: Duration::from_micros(100),
+            }
+        );
+    }
+
+    #[test]
+    fn test_missing_protocol() {
+        extern "efiapi" fn locate_protocol(
+            _protocol: *mut efi::Guid,
+            _registration: *mut c_void,
+            _interface: *mut *mut c_void,
+        ) -> efi::Status {
+            efi::Status::NOT_FOUND
+        }
+
+        let boot_services = efi::BootServices { locate_protocol, ..mock_efi_boot_services() };
+        assert_eq!(BootTimings::new(&boot_services).err(), Some(efi::Status::NOT_FOUND));
+    }
+}
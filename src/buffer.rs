@@ -0,0 +1,91 @@
+//! The two-call buffer pattern.
+//!
+//! Many UEFI services are called once to learn the size of their output and again with a buffer that large. The size
+//! may grow between the calls, e.g. when a handle is installed by a notification function, so the second call can
+//! fail in the same way. [`get_with_growing_buffer`] runs that loop once for all of them.
+//!
+use alloc::{vec, vec::Vec};
+
+use r_efi::efi;
+
+/// Call `call` with a buffer grown until it is large enough, and return the part of the buffer that was filled.
+///
+/// New buffer elements are initialized to `fill`, e.g. `0` or `ptr::null_mut()`.
+/// `call` fills the buffer it is given and returns the number of elements written. When the buffer is too small, it
+/// returns `efi::Status::BUFFER_TOO_SMALL` along with the required number of elements. The first call receives an empty
+/// buffer. Any other error is returned as is.
+///
+/// # Example
+/// ```no_run
+/// use core::{mem, ptr};
+///
+/// use mu_rust_helpers::buffer::get_with_growing_buffer;
+/// use r_efi::efi;
+///
+/// fn all_handles(boot_services: &efi::BootServices) -> Result<Vec<efi::Handle>, efi::Status> {
+///     get_with_growing_buffer(ptr::null_mut(), |buffer: &mut [efi::Handle]| {
+///         let mut size = mem::size_of_val(buffer);
+///         let status = (boot_services.locate_handle)(
+///             efi::ALL_HANDLES,
+///             ptr::null_mut(),
+///             ptr::null_mut(),
+///             &mut size,
+///             buffer.as_mut_ptr(),
+///         );
+///         let len = size / mem::size_of::<efi::Handle>();
+///         if status.is_error() {
+///             return Err((status, len));
+///         }
+///         Ok(len)
+///     })
+/// }
+/// ```
+pub fn get_with_growing_buffer<T: Copy>(
+    fill: T,
+    mut call: impl FnMut(&mut [T]) -> Result<usize, (efi::Status, usize)>,
+) -> Result<Vec<T>, efi::Status> {
+    let mut buffer = Vec::new();
+    loop {
+        match call(&mut buffer) {
+            Ok(len) => {
+                buffer.truncate(len);
+                return Ok(buffer);
+            }
+            // A firmware asking for a buffer no larger than the one it rejected would loop forever.
+            Err((efi::Status::BUFFER_TOO_SMALL, required)) if required > buffer.len() => {
+                buffer = vec![fill; required];
+            }
+            Err((status, _)) => return Err(status),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_with_growing_buffer() {
+        // The data grows once between the size query and the second call.
+        let mut data = vec![1u16, 2, 3];
+        let mut calls = 0;
+        let result = get_with_growing_buffer(0, |buffer: &mut [u16]| {
+            calls += 1;
+            if calls == 2 {
+                data.push(4);
+            }
+            if buffer.len() < data.len() {
+                return Err((efi::Status::BUFFER_TOO_SMALL, data.len()));
+            }
+            buffer[..data.len()].copy_from_slice(&data);
+            Ok(data.len())
+        });
+        assert_eq!(result, Ok(vec![1, 2, 3, 4]));
+        assert_eq!(calls, 3);
+
+        let result = get_with_growing_buffer(0u8, |_| Err((efi::Status::NOT_FOUND, 0)));
+        assert_eq!(result, Err(efi::Status::NOT_FOUND));
+        let result = get_with_growing_buffer(0u8, |_| Err((efi::Status::BUFFER_TOO_SMALL, 0)));
+        assert_eq!(result, Err(efi::Status::BUFFER_TOO_SMALL));
+    }
+}
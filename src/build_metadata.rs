@@ -0,0 +1,159 @@
+//! Build metadata embedded in firmware images.
+//!
+//! [`embed_build_metadata!`](crate::embed_build_metadata) places a [`BuildMetadata`] record with the crate name,
+//! version and git commit in a dedicated `.mubld` section of the image. [`BuildMetadata::find`] locates the record in
+//! the memory of any loaded image, e.g. from the `ImageBase` and `ImageSize` of its LoadedImage protocol, which helps
+//! identify exactly which build of a component is running on a given system.
+//!
+use core::{mem, ptr, str};
+
+/// Build metadata record embedded in an image.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuildMetadata {
+    /// [`BuildMetadata::SIGNATURE`].
+    pub signature: [u8; 8],
+    /// Size of the record in bytes.
+    pub size: u32,
+    /// Reserved, must be zero.
+    pub reserved: u32,
+    crate_name: [u8; 64],
+    version: [u8; 32],
+    git_commit: [u8; 48],
+}
+
+impl BuildMetadata {
+    /// `"MUBLDINF"`.
+    pub const SIGNATURE: [u8; 8] = *b"MUBLDINF";
+
+    /// Create a record. Values are truncated to the size of their field.
+    pub const fn new(crate_name: &str, version: &str, git_commit: &str) -> Self {
+        Self {
+            signature: Self::SIGNATURE,
+            size: mem::size_of::<Self>() as u32,
+            reserved: 0,
+            crate_name: to_field(crate_name),
+            version: to_field(version),
+            git_commit: to_field(git_commit),
+        }
+    }
+
+    /// Return the name of the crate the image was built from.
+    pub fn crate_name(&self) -> &str {
+        from_field(&self.crate_name)
+    }
+
+    /// Return the version of the crate the image was built from.
+    pub fn version(&self) -> &str {
+        from_field(&self.version)
+    }
+
+    /// Return the git commit the image was built from, or an empty string if it was not provided.
+    pub fn git_commit(&self) -> &str {
+        from_field(&self.git_commit)
+    }
+
+    /// Search the memory of a loaded image for a build metadata record.
+    pub fn find(image: &[u8]) -> Option<Self> {
+        let record_size = mem::size_of::<Self>();
+        // Records are emitted with the alignment of the struct, so only aligned offsets need to be checked.
+        let alignment = mem::align_of::<Self>();
+        let first = image.as_ptr().align_offset(alignment);
+        (first..image.len().checked_sub(record_size)? + 1).step_by(alignment).find_map(|offset| {
+            let bytes = &image[offset..offset + record_size];
+            if bytes[..Self::SIGNATURE.len()] != Self::SIGNATURE {
+                return None;
+            }
+            // SAFETY: `bytes` holds `size_of::<Self>()` bytes, and every bit pattern is a valid record.
+            let record = unsafe { ptr::read_unaligned(bytes.as_ptr() as *const Self) };
+            (record.size as usize == record_size).then_some(record)
+        })
+    }
+}
+
+const fn to_field<const N: usize>(value: &str) -> [u8; N] {
+    let bytes = value.as_bytes();
+    let mut len = if bytes.len() < N { bytes.len() } else { N - 1 };
+    // Do not split a UTF-8 sequence when truncating.
+    while len < bytes.len() && len > 0 && bytes[len] & 0xC0 == 0x80 {
+        len -= 1;
+    }
+    let mut field = [0; N];
+    let mut index = 0;
+    while index < len {
+        field[index] = bytes[index];
+        index += 1;
+    }
+    field
+}
+
+fn from_field(field: &[u8]) -> &str {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    match str::from_utf8(&field[..len]) {
+        Ok(value) => value,
+        Err(error) => str::from_utf8(&field[..error.valid_up_to()]).unwrap_or_default(),
+    }
+}
+
+/// Embed a [`BuildMetadata`] record for the current crate in the `.mubld` section of the image.
+///
+/// The git commit is taken from the `MU_BUILD_GIT_COMMIT` environment variable at build time, if it is set.
+///
+/// # Example
+/// ```
+/// mu_rust_helpers::embed_build_metadata!();
+/// ```
+#[macro_export]
+macro_rules! embed_build_metadata {
+    () => {
+        #[used]
+        #[link_section = ".mubld"]
+        static MU_BUILD_METADATA: $crate::build_metadata::BuildMetadata =
+            $crate::build_metadata::BuildMetadata::new(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), {
+                match option_env!("MU_BUILD_GIT_COMMIT") {
+                    Some(commit) => commit,
+                    None => "",
+                }
+            });
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    embed_build_metadata!();
+
+    #[test]
+    fn test_embedded_metadata() {
+        assert_eq!(MU_BUILD_METADATA.crate_name(), "mu_rust_helpers");
+        assert_eq!(MU_BUILD_METADATA.version(), env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn test_truncation() {
+        let metadata = BuildMetadata::new(&"x".repeat(100), "1.0.0-\u{e9}\u{e9}", "");
+        assert_eq!(metadata.crate_name(), "x".repeat(63));
+        assert_eq!(metadata.version(), "1.0.0-\u{e9}\u{e9}");
+        assert_eq!(metadata.git_commit(), "");
+
+        let metadata = BuildMetadata::new("crate", "", &"\u{e9}".repeat(40));
+        assert_eq!(metadata.git_commit(), "\u{e9}".repeat(23));
+    }
+
+    #[test]
+    fn test_find() {
+        let metadata = BuildMetadata::new("driver", "3.1.4", "0123abcd");
+        let record = unsafe {
+            core::slice::from_raw_parts(&metadata as *const BuildMetadata as *const u8, mem::size_of::<BuildMetadata>())
+        };
+        let mut image = vec![0u32; 256];
+        let bytes = unsafe { core::slice::from_raw_parts_mut(image.as_mut_ptr() as *mut u8, image.len() * 4) };
+        bytes[200..200 + record.len()].copy_from_slice(record);
+
+        assert_eq!(BuildMetadata::find(bytes), Some(metadata));
+        assert_eq!(BuildMetadata::find(bytes).unwrap().git_commit(), "0123abcd");
+        assert!(BuildMetadata::find(&bytes[..100]).is_none());
+        assert!(BuildMetadata::find(&[]).is_none());
+    }
+}
@@ -0,0 +1,423 @@
+//! Typed lookup of configuration tables.
+//!
+//! Configuration tables are published in the system table as a GUID and an untyped pointer. Types implementing
+//! [`ConfigTable`] associate the GUID with the layout of the table it identifies, so that
+//! [`StandardSystemTable::find_config_table`](crate::system_table::StandardSystemTable::find_config_table) can return
+//! a typed reference.
+//!
+use core::{ffi::c_void, mem, ptr, slice};
+
+use r_efi::efi;
+
+/// A configuration table layout identified by a vendor GUID.
+///
+/// # Safety
+/// Every configuration table published with `GUID` must start with a valid instance of the implementing type.
+pub unsafe trait ConfigTable {
+    /// Vendor GUID of the table in the system table.
+    const GUID: efi::Guid;
+}
+
+/// EFI System Resource Table GUID.
+pub const ESRT_TABLE_GUID: efi::Guid =
+    efi::Guid::from_fields(0xb122a263, 0x3661, 0x4f68, 0x99, 0x29, &[0x78, 0xf8, 0xb0, 0xd6, 0x21, 0x80]);
+
+/// ACPI 2.0+ Root System Description Pointer.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct AcpiRsdp {
+    /// `"RSD PTR "`.
+    pub signature: [u8; 8],
+    /// Checksum of the first 20 bytes.
+    pub checksum: u8,
+    /// OEM identifier.
+    pub oem_id: [u8; 6],
+    /// ACPI revision of the structure; 2 or above for ACPI 2.0+.
+    pub revision: u8,
+    /// Physical address of the RSDT.
+    pub rsdt_address: u32,
+    /// Length of the structure in bytes.
+    pub length: u32,
+    /// Physical address of the XSDT.
+    pub xsdt_address: u64,
+    /// Checksum of the whole structure.
+    pub extended_checksum: u8,
+    /// Reserved.
+    pub reserved: [u8; 3],
+}
+
+// SAFETY: `ACPI_20_TABLE_GUID` identifies an ACPI 2.0+ RSDP.
+unsafe impl ConfigTable for AcpiRsdp {
+    const GUID: efi::Guid = efi::ACPI_20_TABLE_GUID;
+}
+
+/// SMBIOS 2.x (32-bit) entry point structure.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct SmbiosEntryPoint {
+    /// `"_SM_"`.
+    pub anchor: [u8; 4],
+    /// Checksum of the entry point structure.
+    pub checksum: u8,
+    /// Length of the entry point structure.
+    pub length: u8,
+    /// SMBIOS major version.
+    pub major_version: u8,
+    /// SMBIOS minor version.
+    pub minor_version: u8,
+    /// Size of the largest SMBIOS structure.
+    pub max_structure_size: u16,
+    /// Revision of the entry point structure.
+    pub entry_point_revision: u8,
+    /// Revision-specific data.
+    pub formatted_area: [u8; 5],
+    /// `"_DMI_"`.
+    pub intermediate_anchor: [u8; 5],
+    /// Checksum of the intermediate entry point structure.
+    pub intermediate_checksum: u8,
+    /// Total length of the structure table.
+    pub table_length: u16,
+    /// 32-bit physical address of the structure table.
+    pub table_address: u32,
+    /// Number of structures in the structure table.
+    pub number_of_structures: u16,
+    /// SMBIOS revision in BCD.
+    pub bcd_revision: u8,
+}
+
+// SAFETY: `SMBIOS_TABLE_GUID` identifies an SMBIOS 2.x entry point.
+unsafe impl ConfigTable for SmbiosEntryPoint {
+    const GUID: efi::Guid = efi::SMBIOS_TABLE_GUID;
+}
+
+/// SMBIOS 3.x (64-bit) entry point structure.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct Smbios3EntryPoint {
+    /// `"_SM3_"`.
+    pub anchor: [u8; 5],
+    /// Checksum of the entry point structure.
+    pub checksum: u8,
+    /// Length of the entry point structure.
+    pub length: u8,
+    /// SMBIOS major version.
+    pub major_version: u8,
+    /// SMBIOS minor version.
+    pub minor_version: u8,
+    /// SMBIOS docrev.
+    pub docrev: u8,
+    /// Revision of the entry point structure.
+    pub entry_point_revision: u8,
+    /// Reserved.
+    pub reserved: u8,
+    /// Maximum size of the structure table.
+    pub table_maximum_size: u32,
+    /// 64-bit physical address of the structure table.
+    pub table_address: u64,
+}
+
+// SAFETY: `SMBIOS3_TABLE_GUID` identifies an SMBIOS 3.x entry point.
+unsafe impl ConfigTable for Smbios3EntryPoint {
+    const GUID: efi::Guid = efi::SMBIOS3_TABLE_GUID;
+}
+
+/// Flattened device tree header. Every field is stored big-endian.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceTree {
+    /// `0xd00dfeed`.
+    pub magic: u32,
+    /// Total size of the device tree blob.
+    pub total_size: u32,
+    /// Offset of the structure block.
+    pub off_dt_struct: u32,
+    /// Offset of the strings block.
+    pub off_dt_strings: u32,
+    /// Offset of the memory reservation block.
+    pub off_mem_rsvmap: u32,
+    /// Version of the device tree format.
+    pub version: u32,
+    /// Lowest version the blob is backwards compatible with.
+    pub last_comp_version: u32,
+    /// Physical ID of the boot processor.
+    pub boot_cpuid_phys: u32,
+    /// Size of the strings block.
+    pub size_dt_strings: u32,
+    /// Size of the structure block.
+    pub size_dt_struct: u32,
+}
+
+impl DeviceTree {
+    /// Magic value at the start of a device tree blob.
+    pub const MAGIC: u32 = 0xd00dfeed;
+
+    /// Return true if the header starts with the device tree magic value.
+    pub fn is_valid(&self) -> bool {
+        u32::from_be(self.magic) == Self::MAGIC
+    }
+
+    /// Return the total size of the device tree blob in bytes.
+    pub fn total_size(&self) -> usize {
+        u32::from_be(self.total_size) as usize
+    }
+}
+
+// SAFETY: `DTB_TABLE_GUID` identifies a flattened device tree blob.
+unsafe impl ConfigTable for DeviceTree {
+    const GUID: efi::Guid = efi::DTB_TABLE_GUID;
+}
+
+/// EFI System Resource Table header, followed by `fw_resource_count` [`EsrtEntry`] structures.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Esrt {
+    /// Number of entries in the table.
+    pub fw_resource_count: u32,
+    /// Number of entries the table can hold without reallocation.
+    pub fw_resource_count_max: u32,
+    /// Version of the entry structure.
+    pub fw_resource_version: u64,
+}
+
+impl Esrt {
+    /// Entry structure version described by [`EsrtEntry`].
+    pub const RESOURCE_VERSION: u64 = 1;
+
+    /// Return the entries following the header.
+    ///
+    /// # Safety
+    /// `self` must be the header of an ESRT, followed by `fw_resource_count` entries.
+    pub unsafe fn entries(&self) -> &[EsrtEntry] {
+        let entries = (self as *const Self).add(1) as *const EsrtEntry;
+        slice::from_raw_parts(entries, self.fw_resource_count as usize)
+    }
+}
+
+// SAFETY: `ESRT_TABLE_GUID` identifies an ESRT.
+unsafe impl ConfigTable for Esrt {
+    const GUID: efi::Guid = ESRT_TABLE_GUID;
+}
+
+/// Entry of the EFI System Resource Table describing one updatable firmware resource.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct EsrtEntry {
+    /// GUID identifying the firmware resource.
+    pub fw_class: efi::Guid,
+    /// Type of the firmware resource.
+    pub fw_type: u32,
+    /// Current firmware version.
+    pub fw_version: u32,
+    /// Lowest firmware version an update may install.
+    pub lowest_supported_fw_version: u32,
+    /// Capsule flags required to update the resource.
+    pub capsule_flags: u32,
+    /// Version of the last update attempt.
+    pub last_attempt_version: u32,
+    /// Status of the last update attempt.
+    pub last_attempt_status: u32,
+}
+
+// SAFETY: `MEMORY_ATTRIBUTES_TABLE_GUID` identifies a memory attributes table.
+unsafe impl ConfigTable for efi::MemoryAttributesTable {
+    const GUID: efi::Guid = efi::MEMORY_ATTRIBUTES_TABLE_GUID;
+}
+
+/// Guard returned by [`install_configuration_table_guarded`] that removes the table when dropped.
+#[must_use = "the configuration table is removed when the guard is dropped"]
+pub struct ConfigTableGuard<'a> {
+    boot_services: &'a efi::BootServices,
+    guid: efi::Guid,
+}
+
+impl ConfigTableGuard<'_> {
+    /// Return the GUID of the guarded table.
+    pub fn guid(&self) -> &efi::Guid {
+        &self.guid
+    }
+
+    /// Keep the table installed after the guard is gone.
+    pub fn leak(self) {
+        mem::forget(self);
+    }
+}
+
+impl Drop for ConfigTableGuard<'_> {
+    fn drop(&mut self) {
+        // Removal only fails if the table is no longer installed, which leaves nothing to clean up.
+        let _ = (self.boot_services.install_configuration_table)(&mut self.guid, ptr::null_mut());
+    }
+}
+
+/// Install `table` as the configuration table `guid`, returning a guard that removes it when dropped.
+///
+/// # Safety
+/// `table` must point to a table of the layout associated with `guid`, and remain valid until the guard is dropped, or
+/// for as long as the table stays installed if the guard is leaked.
+///
+/// # Example
+/// ```no_run
+/// use core::ffi::c_void;
+/// use mu_rust_helpers::config_table::{install_configuration_table_guarded, AcpiRsdp, ConfigTable};
+/// use r_efi::efi;
+///
+/// fn with_test_rsdp(boot_services: &efi::BootServices, rsdp: &mut AcpiRsdp) -> Result<(), efi::Status> {
+///     let _guard = unsafe {
+///         install_configuration_table_guarded(boot_services, &AcpiRsdp::GUID, rsdp as *mut AcpiRsdp as *mut c_void)
+///     }?;
+///     // Run the code under test; the table is removed on every return path.
+///     Ok(())
+/// }
+/// ```
+pub unsafe fn install_configuration_table_guarded<'a>(
+    boot_services: &'a efi::BootServices,
+    guid: &efi::Guid,
+    table: *mut c_void,
+) -> Result<ConfigTableGuard<'a>, efi::Status> {
+    let mut guid = *guid;
+    let status = (boot_services.install_configuration_table)(&mut guid, table);
+    if status.is_error() {
+        Err(status)
+    } else {
+        Ok(ConfigTableGuard { boot_services, guid })
+    }
+}
+
+const _: () = assert!(mem::size_of::<AcpiRsdp>() == 36);
+const _: () = assert!(mem::size_of::<SmbiosEntryPoint>() == 31);
+const _: () = assert!(mem::size_of::<Smbios3EntryPoint>() == 24);
+const _: () = assert!(mem::size_of::<EsrtEntry>() == 40);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+
+    use crate::{
+        system_table::{tests::mock_efi_system_table, StandardSystemTable},
+        test_support::mock_efi_boot_services,
+    };
+
+    #[repr(C)]
+    struct TestEsrt {
+        header: Esrt,
+        entries: [EsrtEntry; 2],
+    }
+
+    #[test]
+    fn test_find_config_table() {
+        let rsdp = AcpiRsdp {
+            signature: *b"RSD PTR ",
+            checksum: 0,
+            oem_id: *b"MUOEM ",
+            revision: 2,
+            rsdt_address: 0,
+            length: 36,
+            xsdt_address: 0x1234_5000,
+            extended_checksum: 0,
+            reserved: [0; 3],
+        };
+        let entry = EsrtEntry {
+            fw_class: efi::Guid::from_fields(0x1, 0x2, 0x3, 0x4, 0x5, &[0x6; 6]),
+            fw_type: 1,
+            fw_version: 2,
+            lowest_supported_fw_version: 1,
+            capsule_flags: 0,
+            last_attempt_version: 0,
+            last_attempt_status: 0,
+        };
+        let esrt = TestEsrt {
+            header: Esrt {
+                fw_resource_count: 2,
+                fw_resource_count_max: 2,
+                fw_resource_version: Esrt::RESOURCE_VERSION,
+            },
+            entries: [entry, EsrtEntry { fw_version: 3, ..entry }],
+        };
+        let mut configuration_table = [
+            efi::ConfigurationTable {
+                vendor_guid: AcpiRsdp::GUID,
+                vendor_table: &rsdp as *const AcpiRsdp as *mut c_void,
+            },
+            efi::ConfigurationTable { vendor_guid: Esrt::GUID, vendor_table: &esrt as *const TestEsrt as *mut c_void },
+            efi::ConfigurationTable { vendor_guid: DeviceTree::GUID, vendor_table: core::ptr::null_mut() },
+        ];
+        let efi_system_table = mock_efi_system_table(&mut configuration_table);
+        let system_table = StandardSystemTable::new(&efi_system_table);
+
+        let found = system_table.find_config_table::<AcpiRsdp>().unwrap();
+        assert_eq!(found.signature, *b"RSD PTR ");
+        assert_eq!({ found.xsdt_address }, 0x1234_5000);
+
+        let found = system_table.find_config_table::<Esrt>().unwrap();
+        let versions: Vec<u32> = unsafe { found.entries() }.iter().map(|entry| entry.fw_version).collect();
+        assert_eq!(versions, [2, 3]);
+
+        assert!(system_table.find_config_table::<DeviceTree>().is_none());
+        assert!(system_table.find_config_table::<Smbios3EntryPoint>().is_none());
+        assert_eq!(system_table.find_config_table_by_guid(&Esrt::GUID), Some(&esrt as *const TestEsrt as *mut c_void));
+    }
+
+    #[test]
+    fn test_device_tree_header() {
+        let header = DeviceTree {
+            magic: DeviceTree::MAGIC.to_be(),
+            total_size: 0x200u32.to_be(),
+            off_dt_struct: 0,
+            off_dt_strings: 0,
+            off_mem_rsvmap: 0,
+            version: 17u32.to_be(),
+            last_comp_version: 16u32.to_be(),
+            boot_cpuid_phys: 0,
+            size_dt_strings: 0,
+            size_dt_struct: 0,
+        };
+        assert!(header.is_valid());
+        assert_eq!(header.total_size(), 0x200);
+        assert!(!DeviceTree { magic: DeviceTree::MAGIC, ..header }.is_valid());
+    }
+
+    std::thread_local! {
+        static INSTALLED_TABLES: RefCell<Vec<(efi::Guid, usize)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    extern "efiapi" fn install_configuration_table(guid: *mut efi::Guid, table: *mut c_void) -> efi::Status {
+        let guid = unsafe { *guid };
+        INSTALLED_TABLES.with(|tables| {
+            let mut tables = tables.borrow_mut();
+            let existing = tables.iter().position(|(installed, _)| *installed == guid);
+            match (existing, table.is_null()) {
+                (Some(index), true) => drop(tables.remove(index)),
+                (None, true) => return efi::Status::NOT_FOUND,
+                (Some(index), false) => tables[index].1 = table as usize,
+                (None, false) => tables.push((guid, table as usize)),
+            }
+            efi::Status::SUCCESS
+        })
+    }
+
+    #[test]
+    fn test_install_configuration_table_guarded() {
+        let boot_services = efi::BootServices { install_configuration_table, ..mock_efi_boot_services() };
+        let installed = || INSTALLED_TABLES.with(|tables| tables.borrow().clone());
+
+        let guard =
+            unsafe { install_configuration_table_guarded(&boot_services, &Esrt::GUID, 0x1000 as *mut c_void) }.unwrap();
+        assert_eq!(guard.guid(), &Esrt::GUID);
+        assert_eq!(installed(), [(Esrt::GUID, 0x1000)]);
+        drop(guard);
+        assert!(installed().is_empty());
+
+        unsafe { install_configuration_table_guarded(&boot_services, &Esrt::GUID, 0x2000 as *mut c_void) }
+            .unwrap()
+            .leak();
+        assert_eq!(installed(), [(Esrt::GUID, 0x2000)]);
+
+        let boot_services = mock_efi_boot_services();
+        assert_eq!(
+            unsafe { install_configuration_table_guarded(&boot_services, &Esrt::GUID, 0x3000 as *mut c_void) }.err(),
+            Some(efi::Status::UNSUPPORTED)
+        );
+    }
+}
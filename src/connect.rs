@@ -0,0 +1,320 @@
+//! Controller connection honoring driver override policy.
+//!
+//! ConnectController tries drivers in order of priority: the driver images passed by the caller first, then those
+//! returned by `EFI_PLATFORM_DRIVER_OVERRIDE_PROTOCOL` and by the `EFI_BUS_SPECIFIC_DRIVER_OVERRIDE_PROTOCOL` of the
+//! controller. [`override_drivers`] collects the orderings of both protocols, so a boot manager can log or filter
+//! them, and [`connect_controller_with_overrides`] connects a controller with them.
+//!
+//! [`connect_controller`] and [`disconnect_controller`] wrap the raw services, building the null-terminated driver
+//! list and passing the optional arguments as null pointers.
+//!
+use alloc::vec::Vec;
+use core::ptr;
+
+use r_efi::{
+    efi,
+    protocols::{bus_specific_driver_override, device_path, platform_driver_override},
+};
+
+/// Collect the driver images returned by `get_driver` until it reports `efi::Status::NOT_FOUND`.
+fn collect_drivers(
+    mut get_driver: impl FnMut(&mut efi::Handle) -> efi::Status,
+) -> Result<Vec<efi::Handle>, efi::Status> {
+    let mut drivers = Vec::new();
+    let mut driver = ptr::null_mut();
+    loop {
+        match get_driver(&mut driver) {
+            efi::Status::NOT_FOUND => return Ok(drivers),
+            status if status.is_error() => return Err(status),
+            // Guard against a protocol returning the same driver forever.
+            _ if drivers.contains(&driver) => return Ok(drivers),
+            _ => drivers.push(driver),
+        }
+    }
+}
+
+/// Return the driver images the platform driver override protocol selects for `controller`, by decreasing priority.
+///
+/// Returns an empty list if the platform has no override protocol.
+pub fn platform_override_drivers(
+    boot_services: &efi::BootServices,
+    controller: efi::Handle,
+) -> Result<Vec<efi::Handle>, efi::Status> {
+    let mut guid = platform_driver_override::PROTOCOL_GUID;
+    let mut interface = ptr::null_mut();
+    let status = (boot_services.locate_protocol)(&mut guid, ptr::null_mut(), &mut interface);
+    if status == efi::Status::NOT_FOUND {
+        return Ok(Vec::new());
+    }
+    if status.is_error() {
+        return Err(status);
+    }
+    if interface.is_null() {
+        return Ok(Vec::new());
+    }
+    let protocol = interface as *mut platform_driver_override::Protocol;
+    // SAFETY: the firmware installs a valid protocol structure with this GUID.
+    let get_driver = unsafe { (*protocol).get_driver };
+    collect_drivers(|driver| get_driver(protocol, controller, driver))
+}
+
+/// Return the driver images the bus driver of `controller` selects for it, by decreasing priority.
+///
+/// Returns an empty list if the controller has no bus specific driver override protocol.
+pub fn bus_specific_override_drivers(
+    boot_services: &efi::BootServices,
+    controller: efi::Handle,
+) -> Result<Vec<efi::Handle>, efi::Status> {
+    let mut guid = bus_specific_driver_override::PROTOCOL_GUID;
+    let mut interface = ptr::null_mut();
+    let status = (boot_services.handle_protocol)(controller, &mut guid, &mut interface);
+    if status == efi::Status::UNSUPPORTED {
+        return Ok(Vec::new());
+    }
+    if status.is_error() {
+        return Err(status);
+    }
+    if interface.is_null() {
+        return Ok(Vec::new());
+    }
+    let protocol = interface as *mut bus_specific_driver_override::Protocol;
+    // SAFETY: the protocol is installed on the controller by its bus driver.
+    let get_driver = unsafe { (*protocol).get_driver };
+    collect_drivers(|driver| get_driver(protocol, driver))
+}
+
+/// Return the driver images selected for `controller` by the platform, then by its bus driver, without duplicates.
+pub fn override_drivers(
+    boot_services: &efi::BootServices,
+    controller: efi::Handle,
+) -> Result<Vec<efi::Handle>, efi::Status> {
+    let mut drivers = platform_override_drivers(boot_services, controller)?;
+    for driver in bus_specific_override_drivers(boot_services, controller)? {
+        if !drivers.contains(&driver) {
+            drivers.push(driver);
+        }
+    }
+    Ok(drivers)
+}
+
+/// Connect `drivers` to `controller`, trying them before any other driver, and its children too if `recursive`.
+///
+/// If `drivers` is empty, the firmware picks the drivers on its own. `remaining_device_path` asks bus drivers to
+/// create only the child it designates, or no child if it is an end node; with `None`, they create all their
+/// children.
+pub fn connect_controller(
+    boot_services: &efi::BootServices,
+    controller: efi::Handle,
+    drivers: &[efi::Handle],
+    remaining_device_path: Option<&device_path::Protocol>,
+    recursive: bool,
+) -> Result<(), efi::Status> {
+    // The list of driver images is terminated by a null handle.
+    let mut list: Vec<efi::Handle> = drivers.iter().copied().chain([ptr::null_mut()]).collect();
+    let list = if drivers.is_empty() { ptr::null_mut() } else { list.as_mut_ptr() };
+    // ConnectController only reads the remaining device path.
+    let remaining_device_path =
+        remaining_device_path.map_or(ptr::null_mut(), |path| path as *const device_path::Protocol as *mut _);
+    let status = (boot_services.connect_controller)(controller, list, remaining_device_path, recursive.into());
+    if status.is_error() {
+        return Err(status);
+    }
+    Ok(())
+}
+
+/// Disconnect `driver` from `controller`, or every driver if `None`, undoing [`connect_controller`].
+///
+/// With `child`, only that child of the controller is destroyed, and the drivers stay connected to the controller.
+pub fn disconnect_controller(
+    boot_services: &efi::BootServices,
+    controller: efi::Handle,
+    driver: Option<efi::Handle>,
+    child: Option<efi::Handle>,
+) -> Result<(), efi::Status> {
+    let status = (boot_services.disconnect_controller)(
+        controller,
+        driver.unwrap_or(ptr::null_mut()),
+        child.unwrap_or(ptr::null_mut()),
+    );
+    if status.is_error() {
+        return Err(status);
+    }
+    Ok(())
+}
+
+/// Connect `controller` with the drivers returned by [`override_drivers`] first, and its children too if
+/// `recursive`.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::{
+///     connect::connect_controller_with_overrides,
+///     handles::{locate_handles, HandleSearch},
+/// };
+/// use r_efi::efi;
+///
+/// fn connect_all(boot_services: &efi::BootServices) -> Result<(), efi::Status> {
+///     for handle in locate_handles(boot_services, HandleSearch::AllHandles)? {
+///         // Handles without a matching driver cannot be connected.
+///         let _ = connect_controller_with_overrides(boot_services, handle, true);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub fn connect_controller_with_overrides(
+    boot_services: &efi::BootServices,
+    controller: efi::Handle,
+    recursive: bool,
+) -> Result<(), efi::Status> {
+    let drivers = override_drivers(boot_services, controller)?;
+    connect_controller(boot_services, controller, &drivers, None, recursive)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::{cell::RefCell, ffi::c_void};
+
+    use crate::test_support::{mock_efi_boot_services, unimplemented_service};
+
+    /// Controller, driver list, remaining device path and recursive flag of a ConnectController call.
+    type Connection = (efi::Handle, Vec<efi::Handle>, usize, bool);
+
+    std::thread_local! {
+        static CONNECTED: RefCell<Vec<Connection>> = const { RefCell::new(Vec::new()) };
+        static DISCONNECTED: RefCell<Vec<(efi::Handle, usize, usize)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    const CONTROLLER: efi::Handle = 0xc0 as efi::Handle;
+
+    fn next(list: &[usize], driver: *mut efi::Handle) -> efi::Status {
+        let current = unsafe { *driver } as usize;
+        let index = list.iter().position(|&handle| handle == current).map_or(0, |index| index + 1);
+        match list.get(index) {
+            Some(&next) => {
+                unsafe { *driver = next as efi::Handle };
+                efi::Status::SUCCESS
+            }
+            None => efi::Status::NOT_FOUND,
+        }
+    }
+
+    extern "efiapi" fn platform_get_driver(
+        _this: *mut platform_driver_override::Protocol,
+        controller: efi::Handle,
+        driver: *mut efi::Handle,
+    ) -> efi::Status {
+        assert_eq!(controller, CONTROLLER);
+        next(&[0xa, 0xb], driver)
+    }
+
+    extern "efiapi" fn bus_get_driver(
+        _this: *mut bus_specific_driver_override::Protocol,
+        driver: *mut efi::Handle,
+    ) -> efi::Status {
+        next(&[0xb, 0xc], driver)
+    }
+
+    extern "efiapi" fn locate_protocol(
+        protocol: *mut efi::Guid,
+        _registration: *mut c_void,
+        interface: *mut *mut c_void,
+    ) -> efi::Status {
+        assert_eq!(unsafe { *protocol }, platform_driver_override::PROTOCOL_GUID);
+        let protocol = Box::leak(Box::new(platform_driver_override::Protocol {
+            get_driver: platform_get_driver,
+            get_driver_path: unimplemented_service(),
+            driver_loaded: unimplemented_service(),
+        }));
+        unsafe { *interface = protocol as *mut _ as *mut c_void };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn handle_protocol(
+        handle: efi::Handle,
+        protocol: *mut efi::Guid,
+        interface: *mut *mut c_void,
+    ) -> efi::Status {
+        assert_eq!((handle, unsafe { *protocol }), (CONTROLLER, bus_specific_driver_override::PROTOCOL_GUID));
+        let protocol = Box::leak(Box::new(bus_specific_driver_override::Protocol { get_driver: bus_get_driver }));
+        unsafe { *interface = protocol as *mut _ as *mut c_void };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn connect_controller(
+        controller: efi::Handle,
+        drivers: *mut efi::Handle,
+        remaining_device_path: *mut device_path::Protocol,
+        recursive: efi::Boolean,
+    ) -> efi::Status {
+        let mut list = Vec::new();
+        while !drivers.is_null() && !unsafe { *drivers.add(list.len()) }.is_null() {
+            list.push(unsafe { *drivers.add(list.len()) });
+        }
+        let entry = (controller, list, remaining_device_path as usize, recursive.into());
+        CONNECTED.with(|connected| connected.borrow_mut().push(entry));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn disconnect_controller(
+        controller: efi::Handle,
+        driver: efi::Handle,
+        child: efi::Handle,
+    ) -> efi::Status {
+        DISCONNECTED.with(|disconnected| disconnected.borrow_mut().push((controller, driver as usize, child as usize)));
+        efi::Status::SUCCESS
+    }
+
+    #[test]
+    fn test_override_drivers() {
+        let boot_services =
+            efi::BootServices { locate_protocol, handle_protocol, connect_controller, ..mock_efi_boot_services() };
+        let handles = |list: &[usize]| list.iter().map(|&handle| handle as efi::Handle).collect::<Vec<_>>();
+        assert_eq!(platform_override_drivers(&boot_services, CONTROLLER), Ok(handles(&[0xa, 0xb])));
+        assert_eq!(bus_specific_override_drivers(&boot_services, CONTROLLER), Ok(handles(&[0xb, 0xc])));
+        assert_eq!(override_drivers(&boot_services, CONTROLLER), Ok(handles(&[0xa, 0xb, 0xc])));
+
+        connect_controller_with_overrides(&boot_services, CONTROLLER, true).unwrap();
+        super::connect_controller(&boot_services, CONTROLLER, &[], None, false).unwrap();
+        assert_eq!(
+            CONNECTED.with(|connected| connected.take()),
+            [(CONTROLLER, handles(&[0xa, 0xb, 0xc]), 0, true), (CONTROLLER, Vec::new(), 0, false)]
+        );
+    }
+
+    #[test]
+    fn test_connect_remaining_device_path() {
+        let boot_services = efi::BootServices { connect_controller, ..mock_efi_boot_services() };
+        // An end node: connect the controller without creating any child.
+        let end = device_path::Protocol {
+            r#type: device_path::TYPE_END,
+            sub_type: device_path::End::SUBTYPE_ENTIRE,
+            length: [4, 0],
+        };
+        super::connect_controller(&boot_services, CONTROLLER, &[0xd as efi::Handle], Some(&end), false).unwrap();
+        assert_eq!(
+            CONNECTED.with(|connected| connected.take()),
+            [(CONTROLLER, vec![0xd as efi::Handle], &end as *const _ as usize, false)]
+        );
+    }
+
+    #[test]
+    fn test_disconnect_controller() {
+        let boot_services = efi::BootServices { disconnect_controller, ..mock_efi_boot_services() };
+        super::disconnect_controller(&boot_services, CONTROLLER, None, None).unwrap();
+        super::disconnect_controller(&boot_services, CONTROLLER, Some(0xd as efi::Handle), Some(0xe as efi::Handle))
+            .unwrap();
+        assert_eq!(DISCONNECTED.with(|disconnected| disconnected.take()), [(CONTROLLER, 0, 0), (CONTROLLER, 0xd, 0xe)]);
+
+        let boot_services = mock_efi_boot_services();
+        assert_eq!(super::disconnect_controller(&boot_services, CONTROLLER, None, None), Err(efi::Status::UNSUPPORTED));
+    }
+
+    #[test]
+    fn test_no_override_protocols() {
+        let boot_services = mock_efi_boot_services();
+        assert_eq!(bus_specific_override_drivers(&boot_services, CONTROLLER), Ok(Vec::new()));
+        assert_eq!(platform_override_drivers(&boot_services, CONTROLLER), Err(efi::Status::UNSUPPORTED));
+    }
+}
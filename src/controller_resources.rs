@@ -0,0 +1,476 @@
+//! Resources of a driver managing a controller.
+//!
+//! A DriverBinding `Start` opens protocols, creates child handles, events and buffers, and must undo all of it if a
+//! later step fails, then again in `Stop`. [`ControllerResources`] records each resource as it is acquired and
+//! releases them in reverse order, so both paths share the same teardown.
+//!
+use alloc::{boxed::Box, vec::Vec};
+use core::{ffi::c_void, ptr};
+
+use r_efi::efi;
+
+use crate::{
+    open_protocol::OpenProtocolAttributes,
+    units::{PageCount, PhysicalAddress},
+};
+
+enum Resource<'a> {
+    Protocol { handle: efi::Handle, protocol: efi::Guid, controller: efi::Handle },
+    Interface { handle: efi::Handle, protocol: efi::Guid, interface: *mut c_void },
+    Event(efi::Event),
+    Pages { address: efi::PhysicalAddress, pages: usize },
+    Pool(*mut c_void),
+    Custom(Box<dyn FnOnce() -> Result<(), efi::Status> + 'a>),
+}
+
+/// Resources acquired for a controller, released in reverse order.
+///
+/// Resources are released by [`Self::release`], or when dropped. A driver keeps the resources of a started controller
+/// in its private context until `Stop`.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::{controller_resources::ControllerResources, open_protocol::OpenProtocolAttributes};
+/// use r_efi::{efi, protocols::block_io};
+///
+/// fn start<'a>(
+///     boot_services: &'a efi::BootServices,
+///     driver_handle: efi::Handle,
+///     controller: efi::Handle,
+/// ) -> Result<ControllerResources<'a>, efi::Status> {
+///     let mut resources = ControllerResources::new(boot_services, driver_handle, controller);
+///     // Everything opened so far is closed if a later step fails and `resources` is dropped.
+///     let _block_io = resources.open_protocol(&block_io::PROTOCOL_GUID, OpenProtocolAttributes::BY_DRIVER)?;
+///     let _buffer = resources.allocate_pool(efi::BOOT_SERVICES_DATA, 0x200)?;
+///     Ok(resources)
+/// }
+/// ```
+pub struct ControllerResources<'a> {
+    boot_services: &'a efi::BootServices,
+    agent_handle: efi::Handle,
+    controller_handle: efi::Handle,
+    resources: Vec<Resource<'a>>,
+}
+
+impl<'a> ControllerResources<'a> {
+    /// Create an empty set of resources for `controller_handle`, managed by the driver `agent_handle`.
+    pub fn new(
+        boot_services: &'a efi::BootServices,
+        agent_handle: efi::Handle,
+        controller_handle: efi::Handle,
+    ) -> Self {
+        Self { boot_services, agent_handle, controller_handle, resources: Vec::new() }
+    }
+
+    /// Return the controller handle.
+    pub fn controller_handle(&self) -> efi::Handle {
+        self.controller_handle
+    }
+
+    /// Open `protocol` on the controller with `attributes`, usually [`OpenProtocolAttributes::BY_DRIVER`].
+    pub fn open_protocol(
+        &mut self,
+        protocol: &efi::Guid,
+        attributes: OpenProtocolAttributes,
+    ) -> Result<*mut c_void, efi::Status> {
+        self.open(self.controller_handle, protocol, self.controller_handle, attributes)
+    }
+
+    /// Open `protocol` on the controller on behalf of `child`, with [`OpenProtocolAttributes::BY_CHILD_CONTROLLER`].
+    pub fn open_protocol_by_child(
+        &mut self,
+        protocol: &efi::Guid,
+        child: efi::Handle,
+    ) -> Result<*mut c_void, efi::Status> {
+        self.open(self.controller_handle, protocol, child, OpenProtocolAttributes::BY_CHILD_CONTROLLER)
+    }
+
+    fn open(
+        &mut self,
+        handle: efi::Handle,
+        protocol: &efi::Guid,
+        controller: efi::Handle,
+        attributes: OpenProtocolAttributes,
+    ) -> Result<*mut c_void, efi::Status> {
+        let mut guid = *protocol;
+        let mut interface = ptr::null_mut();
+        let status = (self.boot_services.open_protocol)(
+            handle,
+            &mut guid,
+            &mut interface,
+            self.agent_handle,
+            controller,
+            attributes.get(),
+        );
+        if status.is_error() {
+            return Err(status);
+        }
+        // Protocols opened to get or test them are not tracked by the firmware, so there is nothing to close.
+        if attributes.needs_close() {
+            self.resources.push(Resource::Protocol { handle, protocol: guid, controller });
+        }
+        Ok(interface)
+    }
+
+    /// Install `interface` for `protocol` on `handle`, or on a new child handle if `handle` is null.
+    ///
+    /// Returns the handle the protocol was installed on. The interface must stay valid until it is uninstalled.
+    pub fn install_protocol(
+        &mut self,
+        handle: efi::Handle,
+        protocol: &efi::Guid,
+        interface: *mut c_void,
+    ) -> Result<efi::Handle, efi::Status> {
+        let mut handle = handle;
+        let mut guid = *protocol;
+        let status =
+            (self.boot_services.install_protocol_interface)(&mut handle, &mut guid, efi::NATIVE_INTERFACE, interface);
+        if status.is_error() {
+            return Err(status);
+        }
+        self.resources.push(Resource::Interface { handle, protocol: guid, interface });
+        Ok(handle)
+    }
+
+    /// Create an event, closed on release.
+    pub fn create_event(
+        &mut self,
+        event_type: u32,
+        tpl: efi::Tpl,
+        notify: Option<efi::EventNotify>,
+        context: *mut c_void,
+    ) -> Result<efi::Event, efi::Status> {
+        let mut event = ptr::null_mut();
+        let status = (self.boot_services.create_event)(event_type, tpl, notify, context, &mut event);
+        if status.is_error() {
+            return Err(status);
+        }
+        self.resources.push(Resource::Event(event));
+        Ok(event)
+    }
+
+    /// Allocate `pages` of `memory_type` at any address, freed on release.
+    ///
+    /// Returns `efi::Status::OUT_OF_RESOURCES` if the pages were allocated at address 0, which are freed.
+    pub fn allocate_pages(
+        &mut self,
+        memory_type: impl Into<efi::MemoryType>,
+        pages: PageCount,
+    ) -> Result<PhysicalAddress, efi::Status> {
+        let pages = pages.to_usize().ok_or(efi::Status::BAD_BUFFER_SIZE)?;
+        let mut address = 0;
+        let status =
+            (self.boot_services.allocate_pages)(efi::ALLOCATE_ANY_PAGES, memory_type.into(), pages, &mut address);
+        if status.is_error() {
+            return Err(status);
+        }
+        if address == 0 {
+            // Page 0 is valid memory but not a valid Rust pointer.
+            (self.boot_services.free_pages)(address, pages);
+            return Err(efi::Status::OUT_OF_RESOURCES);
+        }
+        self.resources.push(Resource::Pages { address, pages });
+        Ok(PhysicalAddress::new(address))
+    }
+
+    /// Allocate `size` bytes of pool of `memory_type`, freed on release.
+    ///
+    /// Returns `efi::Status::OUT_OF_RESOURCES` if AllocatePool succeeds without returning a buffer.
+    pub fn allocate_pool(
+        &mut self,
+        memory_type: impl Into<efi::MemoryType>,
+        size: usize,
+    ) -> Result<*mut c_void, efi::Status> {
+        let mut buffer = ptr::null_mut();
+        let status = (self.boot_services.allocate_pool)(memory_type.into(), size, &mut buffer);
+        if status.is_error() {
+            return Err(status);
+        }
+        if buffer.is_null() {
+            return Err(efi::Status::OUT_OF_RESOURCES);
+        }
+        self.resources.push(Resource::Pool(buffer));
+        Ok(buffer)
+    }
+
+    /// Run `release` on release, in order with the other resources, e.g. to quiesce the device.
+    pub fn defer(&mut self, release: impl FnOnce() -> Result<(), efi::Status> + 'a) {
+        self.resources.push(Resource::Custom(Box::new(release)));
+    }
+
+    /// Release every resource, in reverse order of acquisition.
+    ///
+    /// Every resource is released even if some fail, and the first failure is returned. A protocol interface that
+    /// cannot be uninstalled, e.g. because another driver still has it open, is kept along with the resources acquired
+    /// before it, and `efi::Status::ACCESS_DENIED` is returned so `Stop` can fail and be retried.
+    pub fn release(&mut self) -> Result<(), efi::Status> {
+        let mut result = Ok(());
+        while let Some(resource) = self.resources.pop() {
+            if let Err(status) = self.release_one(resource) {
+                if result.is_ok() {
+                    result = Err(status);
+                }
+                if status == efi::Status::ACCESS_DENIED {
+                    break;
+                }
+            }
+        }
+        result
+    }
+
+    fn release_one(&mut self, resource: Resource<'a>) -> Result<(), efi::Status> {
+        let boot_services = self.boot_services;
+        let status = match resource {
+            Resource::Protocol { handle, mut protocol, controller } => {
+                (boot_services.close_protocol)(handle, &mut protocol, self.agent_handle, controller)
+            }
+            Resource::Interface { handle, mut protocol, interface } => {
+                let status = (boot_services.uninstall_protocol_interface)(handle, &mut protocol, interface);
+                if status.is_error() {
+                    self.resources.push(Resource::Interface { handle, protocol, interface });
+                    return Err(efi::Status::ACCESS_DENIED);
+                }
+                status
+            }
+            Resource::Event(event) => (boot_services.close_event)(event),
+            Resource::Pages { address, pages } => (boot_services.free_pages)(address, pages),
+            Resource::Pool(buffer) => (boot_services.free_pool)(buffer),
+            Resource::Custom(release) => return release(),
+        };
+        if status.is_error() {
+            return Err(status);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ControllerResources<'_> {
+    fn drop(&mut self) {
+        // Failures cannot be reported from a destructor. Resources that could not be released are leaked.
+        let _ = self.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{cell::RefCell, format, string::String};
+
+    use crate::test_support::mock_efi_boot_services;
+
+    const TEST_PROTOCOL: efi::Guid =
+        efi::Guid::from_fields(0x2f8b6a2c, 0x7d3e, 0x4a51, 0x8e, 0x6f, &[0x1a, 0x2b, 0x3c, 0x4d, 0x5e, 0x6f]);
+    const AGENT: usize = 0xa6;
+    const CONTROLLER: usize = 0xc0;
+    const CHILD: usize = 0xc1;
+
+    std::thread_local! {
+        static CALLS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+        static UNINSTALL_STATUS: RefCell<efi::Status> = const { RefCell::new(efi::Status::SUCCESS) };
+    }
+
+    fn log(call: String) {
+        CALLS.with(|calls| calls.borrow_mut().push(call));
+    }
+
+    fn take_calls() -> Vec<String> {
+        CALLS.with(|calls| calls.take())
+    }
+
+    extern "efiapi" fn open_protocol(
+        handle: efi::Handle,
+        _protocol: *mut efi::Guid,
+        interface: *mut *mut c_void,
+        agent: efi::Handle,
+        controller: efi::Handle,
+        attributes: u32,
+    ) -> efi::Status {
+        assert_eq!(agent as usize, AGENT);
+        if attributes == efi::OPEN_PROTOCOL_EXCLUSIVE {
+            return efi::Status::ACCESS_DENIED;
+        }
+        log(format!("open {:#x} {:#x} {attributes:#x}", handle as usize, controller as usize));
+        unsafe { *interface = 0x1f as *mut c_void };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn close_protocol(
+        handle: efi::Handle,
+        _protocol: *mut efi::Guid,
+        agent: efi::Handle,
+        controller: efi::Handle,
+    ) -> efi::Status {
+        assert_eq!(agent as usize, AGENT);
+        log(format!("close {:#x} {:#x}", handle as usize, controller as usize));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn install_protocol_interface(
+        handle: *mut efi::Handle,
+        _protocol: *mut efi::Guid,
+        _interface_type: efi::InterfaceType,
+        _interface: *mut c_void,
+    ) -> efi::Status {
+        unsafe { *handle = CHILD as efi::Handle };
+        log(format!("install {CHILD:#x}"));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn uninstall_protocol_interface(
+        handle: efi::Handle,
+        _protocol: *mut efi::Guid,
+        _interface: *mut c_void,
+    ) -> efi::Status {
+        let status = UNINSTALL_STATUS.with(|status| *status.borrow());
+        log(format!("uninstall {:#x} {}", handle as usize, status.is_error()));
+        status
+    }
+
+    extern "efiapi" fn create_event(
+        _event_type: u32,
+        _tpl: efi::Tpl,
+        _notify: Option<efi::EventNotify>,
+        _context: *mut c_void,
+        event: *mut efi::Event,
+    ) -> efi::Status {
+        unsafe { *event = 0xe7 as efi::Event };
+        log(String::from("create_event"));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn close_event(_event: efi::Event) -> efi::Status {
+        log(String::from("close_event"));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn allocate_pool(
+        _pool_type: efi::MemoryType,
+        _size: usize,
+        buffer: *mut *mut c_void,
+    ) -> efi::Status {
+        unsafe { *buffer = 0xb0 as *mut c_void };
+        log(String::from("allocate_pool"));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn allocate_pages(
+        _allocate_type: efi::AllocateType,
+        _memory_type: efi::MemoryType,
+        pages: usize,
+        address: *mut efi::PhysicalAddress,
+    ) -> efi::Status {
+        // Model firmware handing out page 0 for single pages.
+        unsafe { *address = if pages == 1 { 0 } else { 0x10000 } };
+        log(format!("allocate_pages {pages}"));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn free_pages(address: efi::PhysicalAddress, pages: usize) -> efi::Status {
+        log(format!("free_pages {address:#x} {pages}"));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn allocate_null_pool(
+        _pool_type: efi::MemoryType,
+        _size: usize,
+        buffer: *mut *mut c_void,
+    ) -> efi::Status {
+        unsafe { *buffer = ptr::null_mut() };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn free_pool(_buffer: *mut c_void) -> efi::Status {
+        log(String::from("free_pool"));
+        efi::Status::SUCCESS
+    }
+
+    fn boot_services() -> efi::BootServices {
+        efi::BootServices {
+            open_protocol,
+            close_protocol,
+            install_protocol_interface,
+            uninstall_protocol_interface,
+            create_event,
+            close_event,
+            allocate_pages,
+            free_pages,
+            allocate_pool,
+            free_pool,
+            ..mock_efi_boot_services()
+        }
+    }
+
+    fn start(resources: &mut ControllerResources) -> Result<(), efi::Status> {
+        resources.open_protocol(&TEST_PROTOCOL, OpenProtocolAttributes::BY_DRIVER)?;
+        resources.open_protocol(&TEST_PROTOCOL, OpenProtocolAttributes::GET_PROTOCOL)?;
+        resources.allocate_pool(efi::BOOT_SERVICES_DATA, 0x10)?;
+        let child = resources.install_protocol(ptr::null_mut(), &TEST_PROTOCOL, 0x1f as *mut c_void)?;
+        resources.open_protocol_by_child(&TEST_PROTOCOL, child)?;
+        resources.create_event(0, efi::TPL_CALLBACK, None, ptr::null_mut())?;
+        resources.defer(|| {
+            log(String::from("quiesce"));
+            Ok(())
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn test_release_in_reverse_order() {
+        let boot_services = boot_services();
+        let mut resources = ControllerResources::new(&boot_services, AGENT as efi::Handle, CONTROLLER as efi::Handle);
+        start(&mut resources).unwrap();
+        assert_eq!(
+            resources.open_protocol(&TEST_PROTOCOL, OpenProtocolAttributes::EXCLUSIVE),
+            Err(efi::Status::ACCESS_DENIED)
+        );
+        take_calls();
+
+        assert_eq!(resources.release(), Ok(()));
+        assert_eq!(
+            take_calls(),
+            ["quiesce", "close_event", "close 0xc0 0xc1", "uninstall 0xc1 false", "free_pool", "close 0xc0 0xc0"]
+        );
+        drop(resources);
+        assert!(take_calls().is_empty());
+    }
+
+    #[test]
+    fn test_child_still_in_use() {
+        let boot_services = boot_services();
+        let mut resources = ControllerResources::new(&boot_services, AGENT as efi::Handle, CONTROLLER as efi::Handle);
+        start(&mut resources).unwrap();
+        take_calls();
+
+        UNINSTALL_STATUS.with(|status| *status.borrow_mut() = efi::Status::ACCESS_DENIED);
+        assert_eq!(resources.release(), Err(efi::Status::ACCESS_DENIED));
+        assert_eq!(take_calls(), ["quiesce", "close_event", "close 0xc0 0xc1", "uninstall 0xc1 true"]);
+
+        UNINSTALL_STATUS.with(|status| *status.borrow_mut() = efi::Status::SUCCESS);
+        drop(resources);
+        assert_eq!(take_calls(), ["uninstall 0xc1 false", "free_pool", "close 0xc0 0xc0"]);
+    }
+
+    #[test]
+    fn test_invalid_allocations() {
+        let boot_services = boot_services();
+        let mut resources = ControllerResources::new(&boot_services, AGENT as efi::Handle, CONTROLLER as efi::Handle);
+        assert_eq!(
+            resources.allocate_pages(efi::BOOT_SERVICES_DATA, PageCount::new(1)),
+            Err(efi::Status::OUT_OF_RESOURCES)
+        );
+        assert_eq!(take_calls(), ["allocate_pages 1", "free_pages 0x0 1"]);
+        assert_eq!(
+            resources.allocate_pages(efi::BOOT_SERVICES_DATA, PageCount::new(2)),
+            Ok(PhysicalAddress::new(0x10000))
+        );
+        drop(resources);
+        assert_eq!(take_calls(), ["allocate_pages 2", "free_pages 0x10000 2"]);
+
+        let boot_services = efi::BootServices { allocate_pool: allocate_null_pool, ..boot_services };
+        let mut resources = ControllerResources::new(&boot_services, AGENT as efi::Handle, CONTROLLER as efi::Handle);
+        assert_eq!(resources.allocate_pool(efi::BOOT_SERVICES_DATA, 0x10), Err(efi::Status::OUT_OF_RESOURCES));
+        drop(resources);
+        assert!(take_calls().is_empty());
+    }
+}
@@ -0,0 +1,308 @@
+//! Images deferred by the security policy.
+//!
+//! When the platform security policy cannot authorize an image until a user has authenticated, LoadImage fails with
+//! `efi::Status::SECURITY_VIOLATION` and the image is recorded by `EFI_DEFERRED_IMAGE_LOAD_PROTOCOL`. Once the user is
+//! authenticated, the boot manager loads and starts the deferred images again: [`deferred_images`] enumerates them
+//! and [`dispatch_deferred_images`] re-dispatches them.
+//!
+use alloc::vec::Vec;
+use core::{ffi::c_void, ptr, slice};
+
+use r_efi::{efi, protocols::device_path};
+
+use crate::{
+    handles::{locate_handles, HandleSearch},
+    image::{start_image_decoded, ImageError},
+    protocol,
+};
+
+/// `EFI_DEFERRED_IMAGE_LOAD_PROTOCOL_GUID`.
+pub const PROTOCOL_GUID: efi::Guid =
+    efi::Guid::from_fields(0x15853d7c, 0x3ddf, 0x43e0, 0xa1, 0xcb, &[0xeb, 0xf8, 0x5b, 0x8f, 0x87, 0x2c]);
+
+pub type GetImageInfo = extern "efiapi" fn(
+    *mut Protocol,
+    usize,
+    *mut *mut device_path::Protocol,
+    *mut *mut c_void,
+    *mut usize,
+    *mut efi::Boolean,
+) -> efi::Status;
+
+/// `EFI_DEFERRED_IMAGE_LOAD_PROTOCOL`.
+#[repr(C)]
+pub struct Protocol {
+    pub get_image_info: GetImageInfo,
+}
+
+// SAFETY: `PROTOCOL_GUID` identifies the deferred image load protocol, whose interface is `Protocol`.
+unsafe impl protocol::Protocol for Protocol {
+    const GUID: efi::Guid = PROTOCOL_GUID;
+}
+
+/// Image whose loading was deferred, as recorded by the deferred image load protocol.
+///
+/// The device path and the image buffer belong to the protocol, which keeps them until the image is loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeferredImage {
+    /// Device path of the image.
+    pub device_path: *mut device_path::Protocol,
+    /// Image buffer, null if only the device path is known.
+    pub image: *mut c_void,
+    /// Size of the image buffer in bytes.
+    pub image_size: usize,
+    /// True if the image was deferred while the boot manager was loading a boot option.
+    pub boot_option: bool,
+}
+
+impl DeferredImage {
+    /// Return the image buffer, if the protocol recorded it.
+    ///
+    /// # Safety
+    /// The image must not have been loaded since it was enumerated, which lets the protocol free the buffer.
+    pub unsafe fn image(&self) -> Option<&[u8]> {
+        (!self.image.is_null()).then(|| slice::from_raw_parts(self.image as *const u8, self.image_size))
+    }
+}
+
+/// Deferred image and the status it returned once started, or the error of LoadImage or StartImage.
+pub type DispatchOutcome = (DeferredImage, Result<efi::Status, ImageError>);
+
+/// Return the images deferred by every instance of the deferred image load protocol.
+///
+/// Returns an empty list if the protocol is not installed.
+pub fn deferred_images(boot_services: &efi::BootServices) -> Result<Vec<DeferredImage>, efi::Status> {
+    let mut images = Vec::new();
+    for handle in locate_handles(boot_services, HandleSearch::ByProtocol(&PROTOCOL_GUID))? {
+        let mut guid = PROTOCOL_GUID;
+        let mut interface = ptr::null_mut();
+        let status = (boot_services.handle_protocol)(handle, &mut guid, &mut interface);
+        if status.is_error() {
+            return Err(status);
+        }
+        let protocol = interface as *mut Protocol;
+        for index in 0.. {
+            let mut image = DeferredImage {
+                device_path: ptr::null_mut(),
+                image: ptr::null_mut(),
+                image_size: 0,
+                boot_option: false,
+            };
+            let mut boot_option = efi::Boolean::FALSE;
+            // SAFETY: the protocol is installed on the handle returned by LocateHandle.
+            let status = unsafe {
+                ((*protocol).get_image_info)(
+                    protocol,
+                    index,
+                    &mut image.device_path,
+                    &mut image.image,
+                    &mut image.image_size,
+                    &mut boot_option,
+                )
+            };
+            // The protocol reports `NOT_FOUND` past its last image.
+            if status == efi::Status::NOT_FOUND {
+                break;
+            }
+            if status.is_error() {
+                return Err(status);
+            }
+            image.boot_option = boot_option.into();
+            images.push(image);
+        }
+    }
+    Ok(images)
+}
+
+/// Load and start the deferred images for which `filter` returns true, as children of `parent_image`.
+///
+/// Call this once the user is authenticated, so that the security policy allows the images. Images deferred while
+/// loading a boot option are usually left to the boot manager, which boots the option again. Returns the outcome of
+/// each dispatched image, in order: the status returned by the image, or the error of LoadImage or StartImage. Drivers
+/// among the images must then be connected, e.g. with [`crate::connect::connect_controller_with_overrides`].
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::deferred_image::dispatch_deferred_images;
+/// use r_efi::efi;
+///
+/// fn on_user_authenticated(boot_services: &efi::BootServices, image_handle: efi::Handle) -> Result<(), efi::Status> {
+///     for (image, result) in dispatch_deferred_images(boot_services, image_handle, |image| !image.boot_option)? {
+///         if let Err(error) = result {
+///             // Report the image that still failed.
+///             let _ = (image.device_path, error);
+///         }
+///     }
+///     Ok(())
+/// }
+/// ```
+pub fn dispatch_deferred_images(
+    boot_services: &efi::BootServices,
+    parent_image: efi::Handle,
+    mut filter: impl FnMut(&DeferredImage) -> bool,
+) -> Result<Vec<DispatchOutcome>, efi::Status> {
+    let images = deferred_images(boot_services)?;
+    let mut outcomes = Vec::new();
+    for image in images.into_iter().filter(|image| filter(image)) {
+        let mut handle = ptr::null_mut();
+        let status = (boot_services.load_image)(
+            efi::Boolean::FALSE,
+            parent_image,
+            image.device_path,
+            image.image,
+            image.image_size,
+            &mut handle,
+        );
+        let result = if status.is_error() {
+            Err(ImageError { status, message: Default::default(), payload: Vec::new() })
+        } else {
+            start_image_decoded(boot_services, handle)
+        };
+        outcomes.push((image, result));
+    }
+    Ok(outcomes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::{cell::RefCell, mem};
+
+    use crate::test_support::mock_efi_boot_services;
+
+    std::thread_local! {
+        static LOADED: RefCell<Vec<(usize, usize, usize)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    const DEFERRED: efi::Handle = 0xdef as efi::Handle;
+    const PARENT: efi::Handle = 0x9a as efi::Handle;
+
+    /// Three images: a driver with its buffer, an application known by its path only, and a boot option.
+    extern "efiapi" fn get_image_info(
+        _this: *mut Protocol,
+        index: usize,
+        device_path: *mut *mut device_path::Protocol,
+        image: *mut *mut c_void,
+        image_size: *mut usize,
+        boot_option: *mut efi::Boolean,
+    ) -> efi::Status {
+        let (path, buffer, size, is_boot_option) = match index {
+            0 => (0x100, 0x1000, 0x200, false),
+            1 => (0x200, 0, 0, false),
+            2 => (0x300, 0x3000, 0x400, true),
+            _ => return efi::Status::NOT_FOUND,
+        };
+        unsafe {
+            *device_path = path as *mut device_path::Protocol;
+            *image = buffer as *mut c_void;
+            *image_size = size;
+            *boot_option = is_boot_option.into();
+        }
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn locate_handle(
+        search_type: efi::LocateSearchType,
+        protocol: *mut efi::Guid,
+        _key: *mut c_void,
+        size: *mut usize,
+        buffer: *mut efi::Handle,
+    ) -> efi::Status {
+        assert_eq!((search_type, unsafe { *protocol }), (efi::BY_PROTOCOL, PROTOCOL_GUID));
+        unsafe {
+            if *size < mem::size_of::<efi::Handle>() {
+                *size = mem::size_of::<efi::Handle>();
+                return efi::Status::BUFFER_TOO_SMALL;
+            }
+            *size = mem::size_of::<efi::Handle>();
+            *buffer = DEFERRED;
+        }
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn handle_protocol(
+        handle: efi::Handle,
+        _protocol: *mut efi::Guid,
+        interface: *mut *mut c_void,
+    ) -> efi::Status {
+        assert_eq!(handle, DEFERRED);
+        let protocol = Box::leak(Box::new(Protocol { get_image_info }));
+        unsafe { *interface = protocol as *mut Protocol as *mut c_void };
+        efi::Status::SUCCESS
+    }
+
+    /// Images without a buffer are not found on their device.
+    extern "efiapi" fn load_image(
+        boot_policy: efi::Boolean,
+        parent: efi::Handle,
+        device_path: *mut device_path::Protocol,
+        source: *mut c_void,
+        source_size: usize,
+        image: *mut efi::Handle,
+    ) -> efi::Status {
+        assert_eq!((bool::from(boot_policy), parent), (false, PARENT));
+        LOADED.with(|loaded| loaded.borrow_mut().push((device_path as usize, source as usize, source_size)));
+        if source.is_null() {
+            return efi::Status::NOT_FOUND;
+        }
+        unsafe { *image = 0x1a6e as efi::Handle };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn start_image(image: efi::Handle, size: *mut usize, data: *mut *mut u16) -> efi::Status {
+        assert_eq!(image, 0x1a6e as efi::Handle);
+        unsafe {
+            *size = 0;
+            *data = ptr::null_mut();
+        }
+        efi::Status::SUCCESS
+    }
+
+    fn boot_services() -> efi::BootServices {
+        efi::BootServices { locate_handle, handle_protocol, load_image, start_image, ..mock_efi_boot_services() }
+    }
+
+    #[test]
+    fn test_deferred_images() {
+        let images = deferred_images(&boot_services()).unwrap();
+        assert_eq!(
+            images.iter().map(|image| (image.device_path as usize, image.boot_option)).collect::<Vec<_>>(),
+            [(0x100, false), (0x200, false), (0x300, true)]
+        );
+        assert!(unsafe { images[1].image() }.is_none());
+
+        let boot_services = efi::BootServices { locate_handle: unsupported_locate_handle, ..mock_efi_boot_services() };
+        assert_eq!(deferred_images(&boot_services), Err(efi::Status::UNSUPPORTED));
+    }
+
+    extern "efiapi" fn unsupported_locate_handle(
+        _search_type: efi::LocateSearchType,
+        _protocol: *mut efi::Guid,
+        _key: *mut c_void,
+        _size: *mut usize,
+        _buffer: *mut efi::Handle,
+    ) -> efi::Status {
+        efi::Status::UNSUPPORTED
+    }
+
+    #[test]
+    fn test_dispatch_deferred_images() {
+        let outcomes = dispatch_deferred_images(&boot_services(), PARENT, |image| !image.boot_option).unwrap();
+        assert_eq!(
+            outcomes.iter().map(|(image, result)| (image.device_path as usize, result.clone())).collect::<Vec<_>>(),
+            [
+                (0x100, Ok(efi::Status::SUCCESS)),
+                (
+                    0x200,
+                    Err(ImageError {
+                        status: efi::Status::NOT_FOUND,
+                        message: Default::default(),
+                        payload: Vec::new()
+                    })
+                ),
+            ]
+        );
+        assert_eq!(LOADED.with(|loaded| loaded.take()), [(0x100, 0x1000, 0x200), (0x200, 0, 0)]);
+    }
+}
@@ -0,0 +1,582 @@
+//! Driver Health protocol.
+//!
+//! Drivers of controllers that can need a repair or configuration before they are usable, such as RAID or storage
+//! controllers, report their health with `EFI_DRIVER_HEALTH_PROTOCOL` on their driver image handle.
+//! [`InstalledDriverHealth::install`] implements the protocol from a [`DriverHealthProvider`], and [`DriverHealth`]
+//! lets a boot manager query the health of a controller and drive its repair.
+//!
+use alloc::{boxed::Box, vec::Vec};
+use core::{ffi::c_void, marker::PhantomData, mem, mem::ManuallyDrop, ptr};
+
+use r_efi::efi;
+
+use crate::protocol;
+
+/// `EFI_DRIVER_HEALTH_PROTOCOL_GUID`.
+pub const PROTOCOL_GUID: efi::Guid =
+    efi::Guid::from_fields(0x2a534210, 0x9280, 0x41d8, 0xae, 0x79, &[0xca, 0xda, 0x01, 0xa2, 0xb1, 0x27]);
+
+/// `EfiDriverHealthStatusHealthy`.
+pub const STATUS_HEALTHY: u32 = 0;
+/// `EfiDriverHealthStatusRepairRequired`.
+pub const STATUS_REPAIR_REQUIRED: u32 = 1;
+/// `EfiDriverHealthStatusConfigurationRequired`.
+pub const STATUS_CONFIGURATION_REQUIRED: u32 = 2;
+/// `EfiDriverHealthStatusFailed`.
+pub const STATUS_FAILED: u32 = 3;
+/// `EfiDriverHealthStatusReconnectRequired`.
+pub const STATUS_RECONNECT_REQUIRED: u32 = 4;
+/// `EfiDriverHealthStatusRebootRequired`.
+pub const STATUS_REBOOT_REQUIRED: u32 = 5;
+
+/// `EFI_DRIVER_HEALTH_HII_MESSAGE`. Message lists end with an entry whose HII handle is null.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct HiiMessage {
+    pub hii_handle: efi::Handle,
+    pub string_id: u16,
+    pub message_code: u64,
+}
+
+pub type RepairNotify = extern "efiapi" fn(usize, usize) -> efi::Status;
+pub type GetHealthStatus = extern "efiapi" fn(
+    *mut Protocol,
+    efi::Handle,
+    efi::Handle,
+    *mut u32,
+    *mut *mut HiiMessage,
+    *mut efi::Handle,
+) -> efi::Status;
+pub type Repair = extern "efiapi" fn(*mut Protocol, efi::Handle, efi::Handle, Option<RepairNotify>) -> efi::Status;
+
+/// `EFI_DRIVER_HEALTH_PROTOCOL`.
+#[repr(C)]
+pub struct Protocol {
+    pub get_health_status: GetHealthStatus,
+    pub repair: Repair,
+}
+
+// SAFETY: `PROTOCOL_GUID` identifies the driver health protocol, whose interface is `Protocol`.
+unsafe impl protocol::Protocol for Protocol {
+    const GUID: efi::Guid = PROTOCOL_GUID;
+}
+
+/// Health of a driver or of one of its controllers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthStatus {
+    /// The controller is usable.
+    Healthy,
+    /// The controller must be repaired with [`DriverHealth::repair`].
+    RepairRequired,
+    /// The controller must be configured through the HII form returned in the report.
+    ConfigurationRequired,
+    /// The controller cannot be repaired.
+    Failed,
+    /// The controller must be disconnected and connected again.
+    ReconnectRequired,
+    /// The platform must be reset.
+    RebootRequired,
+}
+
+impl HealthStatus {
+    /// Convert a raw `EFI_DRIVER_HEALTH_STATUS`, returning `None` for unknown values.
+    pub fn from_raw(status: u32) -> Option<Self> {
+        Some(match status {
+            STATUS_HEALTHY => Self::Healthy,
+            STATUS_REPAIR_REQUIRED => Self::RepairRequired,
+            STATUS_CONFIGURATION_REQUIRED => Self::ConfigurationRequired,
+            STATUS_FAILED => Self::Failed,
+            STATUS_RECONNECT_REQUIRED => Self::ReconnectRequired,
+            STATUS_REBOOT_REQUIRED => Self::RebootRequired,
+            _ => return None,
+        })
+    }
+
+    /// Return the raw `EFI_DRIVER_HEALTH_STATUS`.
+    pub fn into_raw(self) -> u32 {
+        match self {
+            Self::Healthy => STATUS_HEALTHY,
+            Self::RepairRequired => STATUS_REPAIR_REQUIRED,
+            Self::ConfigurationRequired => STATUS_CONFIGURATION_REQUIRED,
+            Self::Failed => STATUS_FAILED,
+            Self::ReconnectRequired => STATUS_RECONNECT_REQUIRED,
+            Self::RebootRequired => STATUS_REBOOT_REQUIRED,
+        }
+    }
+}
+
+/// Message describing the health of a controller, as an HII string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Message {
+    /// HII package list holding the string.
+    pub hii_handle: efi::Handle,
+    /// String in the package list.
+    pub string_id: u16,
+    /// Vendor specific code of the message.
+    pub code: u64,
+}
+
+/// Health of a driver or of one of its controllers, with the messages to show to the user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthReport {
+    /// Health status.
+    pub status: HealthStatus,
+    /// Messages to show to the user, in order.
+    pub messages: Vec<Message>,
+    /// HII package list of the form configuring the controller, when the status is
+    /// [`HealthStatus::ConfigurationRequired`].
+    pub form: Option<efi::Handle>,
+}
+
+impl HealthReport {
+    /// Create a report without messages or form.
+    pub fn new(status: HealthStatus) -> Self {
+        Self { status, messages: Vec::new(), form: None }
+    }
+}
+
+/// Health reporting of a driver, installed with [`InstalledDriverHealth::install`].
+pub trait DriverHealthProvider {
+    /// Return the health of `child` of `controller`, of `controller` if `child` is `None`, or of the driver and all
+    /// its controllers if both are `None`.
+    fn health_status(
+        &self,
+        controller: Option<efi::Handle>,
+        child: Option<efi::Handle>,
+    ) -> Result<HealthReport, efi::Status>;
+
+    /// Repair `child` of `controller`, or `controller` if `child` is `None`.
+    ///
+    /// `progress` reports the number of completed steps out of the total number of steps.
+    fn repair(
+        &self,
+        controller: efi::Handle,
+        child: Option<efi::Handle>,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<(), efi::Status>;
+}
+
+#[repr(C)]
+struct Instance<'a, P> {
+    // Must be the first field: the protocol pointer passed by callers is cast back to the instance.
+    protocol: Protocol,
+    boot_services: &'a efi::BootServices,
+    provider: P,
+}
+
+/// Driver health protocol installed from a [`DriverHealthProvider`], uninstalled when dropped.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::driver_health::{DriverHealthProvider, HealthReport, HealthStatus, InstalledDriverHealth};
+/// use r_efi::efi;
+///
+/// struct Raid;
+///
+/// impl DriverHealthProvider for Raid {
+///     fn health_status(
+///         &self,
+///         _controller: Option<efi::Handle>,
+///         _child: Option<efi::Handle>,
+///     ) -> Result<HealthReport, efi::Status> {
+///         Ok(HealthReport::new(HealthStatus::RepairRequired))
+///     }
+///
+///     fn repair(
+///         &self,
+///         _controller: efi::Handle,
+///         _child: Option<efi::Handle>,
+///         progress: &mut dyn FnMut(usize, usize),
+///     ) -> Result<(), efi::Status> {
+///         // Rebuild the array.
+///         progress(1, 1);
+///         Ok(())
+///     }
+/// }
+///
+/// fn install(boot_services: &'static efi::BootServices, image: efi::Handle) -> Result<(), efi::Status> {
+///     let health = InstalledDriverHealth::install(boot_services, image, Raid)?;
+///     // The driver reports its health for as long as it is loaded.
+///     core::mem::forget(health);
+///     Ok(())
+/// }
+/// ```
+pub struct InstalledDriverHealth<'a, P> {
+    handle: efi::Handle,
+    instance: ManuallyDrop<Box<Instance<'a, P>>>,
+}
+
+impl<'a, P: DriverHealthProvider> InstalledDriverHealth<'a, P> {
+    /// Install the protocol on the driver image `handle`, implemented by `provider`.
+    pub fn install(
+        boot_services: &'a efi::BootServices,
+        handle: efi::Handle,
+        provider: P,
+    ) -> Result<Self, efi::Status> {
+        let mut instance = Box::new(Instance {
+            protocol: Protocol { get_health_status: get_health_status::<P>, repair: repair::<P> },
+            boot_services,
+            provider,
+        });
+        let mut handle = handle;
+        let mut guid = PROTOCOL_GUID;
+        let interface = &mut instance.protocol as *mut Protocol as *mut c_void;
+        let status =
+            (boot_services.install_protocol_interface)(&mut handle, &mut guid, efi::NATIVE_INTERFACE, interface);
+        if status.is_error() {
+            return Err(status);
+        }
+        Ok(Self { handle, instance: ManuallyDrop::new(instance) })
+    }
+
+    /// Return the handle the protocol is installed on.
+    pub fn handle(&self) -> efi::Handle {
+        self.handle
+    }
+
+    /// Return the protocol, e.g. to query it with [`DriverHealth`].
+    pub fn protocol(&mut self) -> *mut Protocol {
+        &mut self.instance.protocol
+    }
+}
+
+impl<P> Drop for InstalledDriverHealth<'_, P> {
+    fn drop(&mut self) {
+        let mut guid = PROTOCOL_GUID;
+        let interface = &mut self.instance.protocol as *mut Protocol as *mut c_void;
+        // If a consumer still has the protocol open, the instance cannot be freed safely.
+        if !(self.instance.boot_services.uninstall_protocol_interface)(self.handle, &mut guid, interface).is_error() {
+            // SAFETY: the instance is no longer reachable from the protocol database.
+            unsafe { ManuallyDrop::drop(&mut self.instance) };
+        }
+    }
+}
+
+fn optional_handle(handle: efi::Handle) -> Option<efi::Handle> {
+    (!handle.is_null()).then_some(handle)
+}
+
+extern "efiapi" fn get_health_status<P: DriverHealthProvider>(
+    protocol: *mut Protocol,
+    controller: efi::Handle,
+    child: efi::Handle,
+    health_status: *mut u32,
+    message_list: *mut *mut HiiMessage,
+    form_hii_handle: *mut efi::Handle,
+) -> efi::Status {
+    if health_status.is_null() || (controller.is_null() && !child.is_null()) {
+        return efi::Status::INVALID_PARAMETER;
+    }
+    // SAFETY: callers pass the protocol installed by `InstalledDriverHealth::install`, the first field of an instance.
+    let instance = unsafe { &*(protocol as *const Instance<P>) };
+    let report = match instance.provider.health_status(optional_handle(controller), optional_handle(child)) {
+        Ok(report) => report,
+        Err(status) => return status,
+    };
+
+    if !message_list.is_null() {
+        let mut messages = ptr::null_mut();
+        if !report.messages.is_empty() {
+            // The caller frees the list with FreePool.
+            let size = (report.messages.len() + 1) * mem::size_of::<HiiMessage>();
+            let mut buffer = ptr::null_mut();
+            if (instance.boot_services.allocate_pool)(efi::BOOT_SERVICES_DATA, size, &mut buffer).is_error() {
+                return efi::Status::OUT_OF_RESOURCES;
+            }
+            messages = buffer as *mut HiiMessage;
+            let terminator = HiiMessage { hii_handle: ptr::null_mut(), string_id: 0, message_code: 0 };
+            let entries = report.messages.iter().map(|message| HiiMessage {
+                hii_handle: message.hii_handle,
+                string_id: message.string_id,
+                message_code: message.code,
+            });
+            for (index, entry) in entries.chain([terminator]).enumerate() {
+                // SAFETY: the pool buffer holds one entry per message and the terminator.
+                unsafe { messages.add(index).write(entry) };
+            }
+        }
+        // SAFETY: checked for null above.
+        unsafe { *message_list = messages };
+    }
+    if !form_hii_handle.is_null() {
+        // SAFETY: checked for null above.
+        unsafe { *form_hii_handle = report.form.unwrap_or(ptr::null_mut()) };
+    }
+    // SAFETY: checked for null above.
+    unsafe { *health_status = report.status.into_raw() };
+    efi::Status::SUCCESS
+}
+
+extern "efiapi" fn repair<P: DriverHealthProvider>(
+    protocol: *mut Protocol,
+    controller: efi::Handle,
+    child: efi::Handle,
+    repair_notify: Option<RepairNotify>,
+) -> efi::Status {
+    if controller.is_null() {
+        return efi::Status::INVALID_PARAMETER;
+    }
+    // SAFETY: see `get_health_status`.
+    let instance = unsafe { &*(protocol as *const Instance<P>) };
+    let mut progress = |value: usize, limit: usize| {
+        if let Some(notify) = repair_notify {
+            notify(value, limit);
+        }
+    };
+    match instance.provider.repair(controller, optional_handle(child), &mut progress) {
+        Ok(()) => efi::Status::SUCCESS,
+        Err(status) => status,
+    }
+}
+
+/// Driver health protocol of a driver, used to query the health of its controllers and repair them.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::driver_health::{DriverHealth, HealthStatus};
+/// use r_efi::efi;
+///
+/// fn repair_controller(health: &DriverHealth, controller: efi::Handle) -> Result<(), efi::Status> {
+///     match health.repair_if_required(controller, None, None)?.status {
+///         HealthStatus::Healthy => Ok(()),
+///         _ => Err(efi::Status::DEVICE_ERROR),
+///     }
+/// }
+/// ```
+pub struct DriverHealth<'a> {
+    boot_services: &'a efi::BootServices,
+    protocol: *mut Protocol,
+    _lifetime_marker: PhantomData<&'a Protocol>,
+}
+
+impl<'a> DriverHealth<'a> {
+    /// Create a wrapper around `protocol`, freeing message lists with `boot_services`.
+    ///
+    /// # Safety
+    /// `protocol` must point to a valid protocol structure for the lifetime of the wrapper.
+    pub unsafe fn new(boot_services: &'a efi::BootServices, protocol: *mut Protocol) -> Self {
+        Self { boot_services, protocol, _lifetime_marker: PhantomData }
+    }
+
+    /// Return the health of `child` of `controller`, of `controller` if `child` is `None`, or of the driver and all
+    /// its controllers if both are `None`.
+    ///
+    /// Returns `efi::Status::UNSUPPORTED` if the status reported by the driver is unknown.
+    pub fn health_status(
+        &self,
+        controller: Option<efi::Handle>,
+        child: Option<efi::Handle>,
+    ) -> Result<HealthReport, efi::Status> {
+        let mut status = 0;
+        let mut list = ptr::null_mut();
+        let mut form = ptr::null_mut();
+        // SAFETY: the protocol is valid for the lifetime of the wrapper.
+        let get_health_status = unsafe { (*self.protocol).get_health_status };
+        let result = get_health_status(
+            self.protocol,
+            controller.unwrap_or(ptr::null_mut()),
+            child.unwrap_or(ptr::null_mut()),
+            &mut status,
+            &mut list,
+            &mut form,
+        );
+        if result.is_error() {
+            return Err(result);
+        }
+
+        let mut messages = Vec::new();
+        if !list.is_null() {
+            // SAFETY: the driver returns a pool allocated list terminated by an entry with a null HII handle.
+            unsafe {
+                let mut entry = list;
+                while !(*entry).hii_handle.is_null() {
+                    let HiiMessage { hii_handle, string_id, message_code } = *entry;
+                    messages.push(Message { hii_handle, string_id, code: message_code });
+                    entry = entry.add(1);
+                }
+            }
+            (self.boot_services.free_pool)(list as *mut c_void);
+        }
+        let status = HealthStatus::from_raw(status).ok_or(efi::Status::UNSUPPORTED)?;
+        Ok(HealthReport { status, messages, form: optional_handle(form) })
+    }
+
+    /// Repair `child` of `controller`, or `controller` if `child` is `None`, reporting the progress to `notify`.
+    pub fn repair(
+        &self,
+        controller: efi::Handle,
+        child: Option<efi::Handle>,
+        notify: Option<RepairNotify>,
+    ) -> Result<(), efi::Status> {
+        // SAFETY: the protocol is valid for the lifetime of the wrapper.
+        let repair = unsafe { (*self.protocol).repair };
+        let status = repair(self.protocol, controller, child.unwrap_or(ptr::null_mut()), notify);
+        if status.is_error() {
+            return Err(status);
+        }
+        Ok(())
+    }
+
+    /// Repair `child` of `controller`, or `controller` if `child` is `None`, if the driver reports that it requires a
+    /// repair, and return its health afterwards.
+    ///
+    /// Reconnecting the controller or resetting the platform, when the returned report requires it, is left to the
+    /// caller.
+    pub fn repair_if_required(
+        &self,
+        controller: efi::Handle,
+        child: Option<efi::Handle>,
+        notify: Option<RepairNotify>,
+    ) -> Result<HealthReport, efi::Status> {
+        let report = self.health_status(Some(controller), child)?;
+        if report.status != HealthStatus::RepairRequired {
+            return Ok(report);
+        }
+        self.repair(controller, child, notify)?;
+        self.health_status(Some(controller), child)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::cell::{Cell, RefCell};
+    use std::alloc::{alloc, Layout};
+
+    use crate::test_support::mock_efi_boot_services;
+
+    std::thread_local! {
+        static INSTALLED: RefCell<Option<*mut c_void>> = const { RefCell::new(None) };
+        static FREED: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+        static PROGRESS: RefCell<Vec<(usize, usize)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    const IMAGE: efi::Handle = 0x1a6e as efi::Handle;
+    const CONTROLLER: efi::Handle = 0xc0 as efi::Handle;
+    const HII: efi::Handle = 0x4111 as efi::Handle;
+
+    extern "efiapi" fn install_protocol_interface(
+        handle: *mut efi::Handle,
+        protocol: *mut efi::Guid,
+        interface_type: efi::InterfaceType,
+        interface: *mut c_void,
+    ) -> efi::Status {
+        assert_eq!(
+            (unsafe { *handle }, unsafe { *protocol }, interface_type),
+            (IMAGE, PROTOCOL_GUID, efi::NATIVE_INTERFACE)
+        );
+        INSTALLED.with(|installed| *installed.borrow_mut() = Some(interface));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn uninstall_protocol_interface(
+        handle: efi::Handle,
+        _protocol: *mut efi::Guid,
+        interface: *mut c_void,
+    ) -> efi::Status {
+        assert_eq!(handle, IMAGE);
+        INSTALLED.with(|installed| assert_eq!(installed.borrow_mut().take(), Some(interface)));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn allocate_pool(
+        _pool_type: efi::MemoryType,
+        size: usize,
+        buffer: *mut *mut c_void,
+    ) -> efi::Status {
+        unsafe { *buffer = alloc(Layout::from_size_align(size, 8).unwrap()) as *mut c_void };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn free_pool(buffer: *mut c_void) -> efi::Status {
+        FREED.with(|freed| freed.borrow_mut().push(buffer as usize));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn repair_notify(value: usize, limit: usize) -> efi::Status {
+        PROGRESS.with(|progress| progress.borrow_mut().push((value, limit)));
+        efi::Status::SUCCESS
+    }
+
+    /// Controller that requires one repair, then a configuration.
+    struct Raid {
+        repaired: Cell<bool>,
+    }
+
+    impl DriverHealthProvider for Raid {
+        fn health_status(
+            &self,
+            controller: Option<efi::Handle>,
+            child: Option<efi::Handle>,
+        ) -> Result<HealthReport, efi::Status> {
+            assert_eq!((controller, child), (Some(CONTROLLER), None));
+            if !self.repaired.get() {
+                return Ok(HealthReport::new(HealthStatus::RepairRequired));
+            }
+            Ok(HealthReport {
+                status: HealthStatus::ConfigurationRequired,
+                messages: alloc::vec![
+                    Message { hii_handle: HII, string_id: 3, code: 0x10 },
+                    Message { hii_handle: HII, string_id: 4, code: 0x20 },
+                ],
+                form: Some(HII),
+            })
+        }
+
+        fn repair(
+            &self,
+            controller: efi::Handle,
+            child: Option<efi::Handle>,
+            progress: &mut dyn FnMut(usize, usize),
+        ) -> Result<(), efi::Status> {
+            assert_eq!((controller, child), (CONTROLLER, None));
+            progress(1, 2);
+            progress(2, 2);
+            self.repaired.set(true);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_health_status_values() {
+        for raw in 0..6 {
+            assert_eq!(HealthStatus::from_raw(raw).map(HealthStatus::into_raw), Some(raw));
+        }
+        assert_eq!(HealthStatus::from_raw(6), None);
+    }
+
+    #[test]
+    fn test_install_and_repair() {
+        let boot_services = efi::BootServices {
+            install_protocol_interface,
+            uninstall_protocol_interface,
+            allocate_pool,
+            free_pool,
+            ..mock_efi_boot_services()
+        };
+        let mut installed =
+            InstalledDriverHealth::install(&boot_services, IMAGE, Raid { repaired: Cell::new(false) }).unwrap();
+        assert_eq!(installed.handle(), IMAGE);
+        let protocol = installed.protocol();
+        assert_eq!(INSTALLED.with(|installed| *installed.borrow()), Some(protocol as *mut c_void));
+
+        let health = unsafe { DriverHealth::new(&boot_services, protocol) };
+        assert_eq!(health.health_status(Some(CONTROLLER), None), Ok(HealthReport::new(HealthStatus::RepairRequired)));
+        assert_eq!(health.repair(ptr::null_mut(), None, None), Err(efi::Status::INVALID_PARAMETER));
+        assert_eq!(health.health_status(None, Some(CONTROLLER)), Err(efi::Status::INVALID_PARAMETER));
+
+        let report = health.repair_if_required(CONTROLLER, None, Some(repair_notify)).unwrap();
+        assert_eq!(report.status, HealthStatus::ConfigurationRequired);
+        assert_eq!(
+            report.messages.iter().map(|message| (message.string_id, message.code)).collect::<Vec<_>>(),
+            [(3, 0x10), (4, 0x20)]
+        );
+        assert_eq!(report.form, Some(HII));
+        assert_eq!(PROGRESS.with(|progress| progress.take()), [(1, 2), (2, 2)]);
+        assert_eq!(FREED.with(|freed| freed.borrow().len()), 1);
+
+        drop(installed);
+        assert_eq!(INSTALLED.with(|installed| *installed.borrow()), None);
+    }
+}
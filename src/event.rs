@@ -0,0 +1,1093 @@
+//! Event creation.
+//!
+//! CreateEvent and CreateEventEx combine an event type, a TPL, a notification function with its context, and
+//! optionally an event group, and only some combinations are valid: notification types need a function, a function
+//! needs a notification type, and a group event is signaled by the group rather than by a timer. [`EventBuilder`]
+//! spells these out one by one and tracks them in its type, so that invalid combinations do not compile:
+//!
+//! ```compile_fail
+//! use mu_rust_helpers::event::EventBuilder;
+//! use r_efi::efi;
+//!
+//! // A timer event cannot join a group.
+//! let builder = EventBuilder::new().timer().group(&efi::EVENT_GROUP_READY_TO_BOOT);
+//! ```
+//!
+//! ```compile_fail
+//! use mu_rust_helpers::event::EventBuilder;
+//! use r_efi::efi;
+//!
+//! // A notification type without a function cannot be created.
+//! fn create(boot_services: &efi::BootServices) {
+//!     let event = EventBuilder::new().notify_signal().create(boot_services);
+//! }
+//! ```
+//!
+//! The created event is typed after its kind as well: only a [`TimerEvent`] or a timer [`SignalEvent`] can be armed,
+//! and WaitForEvent, through [`wait_for_event`], only accepts events without a notification function run on signal:
+//!
+//! ```compile_fail
+//! use core::time::Duration;
+//! use mu_rust_helpers::event::EventBuilder;
+//! use r_efi::efi;
+//!
+//! // An event that is not a timer cannot be armed.
+//! fn arm(boot_services: &efi::BootServices) {
+//!     let event = EventBuilder::new().create(boot_services).unwrap();
+//!     event.set_timer(Duration::from_millis(1), false);
+//! }
+//! ```
+//!
+//! ```compile_fail
+//! use core::ffi::c_void;
+//! use mu_rust_helpers::event::{wait_for_event, EventBuilder};
+//! use r_efi::efi;
+//!
+//! extern "efiapi" fn notify(_event: efi::Event, _context: *mut c_void) {}
+//!
+//! // An event notified on signal cannot be waited for.
+//! fn wait(boot_services: &efi::BootServices) {
+//!     let event = EventBuilder::new().notify_signal().callback(notify, core::ptr::null_mut()).create(boot_services);
+//!     wait_for_event(boot_services, &[&event.unwrap()]);
+//! }
+//! ```
+//!
+//! A context handed over as a `Box` with [`EventBuilder::callback_owned`] is owned by the created event, and freed
+//! after the event is closed instead of being leaked.
+//!
+//! Most events are notifications of one of the event groups defined by the specification. [`GroupEvent`] runs a Rust
+//! closure when its group is signaled, and [`on_ready_to_boot`] and its siblings pick the TPL suited to each group.
+//! With the `executor` feature, `EventFuture` resolves in an async task when its event is signaled.
+//!
+#[cfg(feature = "executor")]
+use alloc::rc::Rc;
+use alloc::{boxed::Box, vec::Vec};
+#[cfg(feature = "executor")]
+use core::{
+    cell::Cell,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use core::{cell::RefCell, ffi::c_void, marker::PhantomData, mem, ops::Deref, ptr, time::Duration};
+
+use r_efi::efi;
+
+/// Signaled when ExitBootServices is called, after [`GROUP_BEFORE_EXIT_BOOT_SERVICES`].
+pub const GROUP_EXIT_BOOT_SERVICES: efi::Guid = efi::EVENT_GROUP_EXIT_BOOT_SERVICES;
+/// Signaled when ExitBootServices is called, before the memory map is final.
+pub const GROUP_BEFORE_EXIT_BOOT_SERVICES: efi::Guid = efi::EVENT_GROUP_BEFORE_EXIT_BOOT_SERVICES;
+/// Signaled when SetVirtualAddressMap is called, to convert runtime pointers.
+pub const GROUP_VIRTUAL_ADDRESS_CHANGE: efi::Guid = efi::EVENT_GROUP_VIRTUAL_ADDRESS_CHANGE;
+/// Signaled by the boot manager before it attempts to boot an option.
+pub const GROUP_READY_TO_BOOT: efi::Guid = efi::EVENT_GROUP_READY_TO_BOOT;
+/// Signaled by the boot manager right after [`GROUP_READY_TO_BOOT`].
+pub const GROUP_AFTER_READY_TO_BOOT: efi::Guid = efi::EVENT_GROUP_AFTER_READY_TO_BOOT;
+/// Signaled when ResetSystem is called, before the platform is reset.
+pub const GROUP_RESET_SYSTEM: efi::Guid = efi::EVENT_GROUP_RESET_SYSTEM;
+
+/// [`EventBuilder`] state: neither a timer nor in a group.
+pub struct Plain;
+/// [`EventBuilder`] state: timer event.
+pub struct Timer;
+/// [`EventBuilder`] state: member of an event group.
+pub struct Grouped;
+/// [`EventBuilder`] state: no notification.
+pub struct NoNotify;
+/// [`EventBuilder`] state: notification type `M` set, function still missing.
+pub struct NeedsCallback<M = Signal>(PhantomData<M>);
+/// [`EventBuilder`] state: notification type `M` and function set.
+pub struct WithCallback<M = Signal>(PhantomData<M>);
+/// Notification type: run the function when the event is signaled.
+pub struct Signal;
+/// Notification type: run the function while the event is waited for or checked.
+pub struct Wait;
+
+/// [`EventBuilder`] state that can create an event, and the type of that event.
+pub trait BuilderState {
+    /// Type of the created event.
+    type Event<'a>;
+
+    #[doc(hidden)]
+    fn wrap(event: Event<'_>) -> Self::Event<'_>;
+}
+
+macro_rules! builder_state {
+    ($($kind:ty, $notify:ty => $event:ident;)*) => {
+        $(
+            impl BuilderState for EventBuilder<$kind, $notify> {
+                type Event<'a> = $event<'a>;
+
+                fn wrap(event: Event<'_>) -> $event<'_> {
+                    $event(event)
+                }
+            }
+        )*
+    };
+}
+
+builder_state! {
+    Plain, NoNotify => WaitEvent;
+    Grouped, NoNotify => WaitEvent;
+    Timer, NoNotify => TimerEvent;
+    Plain, WithCallback<Wait> => WaitEvent;
+    Grouped, WithCallback<Wait> => WaitEvent;
+    Timer, WithCallback<Wait> => TimerEvent;
+}
+
+impl<K> BuilderState for EventBuilder<K, WithCallback<Signal>> {
+    type Event<'a> = SignalEvent<'a, K>;
+
+    fn wrap(event: Event<'_>) -> SignalEvent<'_, K> {
+        SignalEvent { event, _kind: PhantomData }
+    }
+}
+
+/// Builder of events, see the [module documentation](self).
+///
+/// # Example
+/// ```no_run
+/// use core::ffi::c_void;
+/// use mu_rust_helpers::event::{Event, EventBuilder};
+/// use r_efi::efi;
+///
+/// extern "efiapi" fn on_ready_to_boot(_event: efi::Event, _context: *mut c_void) {
+///     // Lock down the platform.
+/// }
+///
+/// fn register(boot_services: &efi::BootServices) -> Result<Event<'_>, efi::Status> {
+///     EventBuilder::new()
+///         .notify_signal()
+///         .tpl(efi::TPL_CALLBACK)
+///         .callback(on_ready_to_boot, core::ptr::null_mut())
+///         .group(&efi::EVENT_GROUP_READY_TO_BOOT)
+///         .create(boot_services)
+///         .map(|event| event.into_inner())
+/// }
+/// ```
+pub struct EventBuilder<K = Plain, N = NoNotify> {
+    event_type: u32,
+    tpl: efi::Tpl,
+    notify: Option<efi::EventNotify>,
+    context: *mut c_void,
+    group: Option<efi::Guid>,
+    owned: Option<OwnedContext>,
+    _state: PhantomData<(K, N)>,
+}
+
+/// Boxed context owned by an event, freed once the event is closed.
+struct OwnedContext {
+    pointer: *mut c_void,
+    free: unsafe fn(*mut c_void),
+}
+
+impl OwnedContext {
+    fn new<T: 'static>(context: Box<T>) -> Self {
+        unsafe fn free<T>(pointer: *mut c_void) {
+            drop(Box::from_raw(pointer as *mut T));
+        }
+        Self { pointer: Box::into_raw(context) as *mut c_void, free: free::<T> }
+    }
+}
+
+impl Drop for OwnedContext {
+    fn drop(&mut self) {
+        // SAFETY: the pointer comes from `Box::into_raw` in `OwnedContext::new`, with the matching `free`.
+        unsafe { (self.free)(self.pointer) };
+    }
+}
+
+impl EventBuilder {
+    /// Start building an event with no timer, group nor notification, at `efi::TPL_CALLBACK`.
+    pub const fn new() -> Self {
+        Self {
+            event_type: 0,
+            tpl: efi::TPL_CALLBACK,
+            notify: None,
+            context: ptr::null_mut(),
+            group: None,
+            owned: None,
+            _state: PhantomData,
+        }
+    }
+}
+
+impl Default for EventBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, N> EventBuilder<K, N> {
+    /// Set the TPL at which the notification function runs.
+    pub fn tpl(mut self, tpl: efi::Tpl) -> Self {
+        self.tpl = tpl;
+        self
+    }
+
+    fn into_state<K2, N2>(mut self) -> EventBuilder<K2, N2> {
+        EventBuilder {
+            event_type: self.event_type,
+            tpl: self.tpl,
+            notify: self.notify,
+            context: self.context,
+            group: self.group,
+            owned: self.owned.take(),
+            _state: PhantomData,
+        }
+    }
+
+    /// Create the event.
+    pub fn create(self, boot_services: &efi::BootServices) -> Result<<Self as BuilderState>::Event<'_>, efi::Status>
+    where
+        Self: BuilderState,
+    {
+        self.build(boot_services).map(Self::wrap)
+    }
+
+    fn build(mut self, boot_services: &efi::BootServices) -> Result<Event<'_>, efi::Status> {
+        let mut event = ptr::null_mut();
+        let status = match self.group {
+            Some(group) => (boot_services.create_event_ex)(
+                self.event_type,
+                self.tpl,
+                self.notify,
+                self.context,
+                &group,
+                &mut event,
+            ),
+            None => (boot_services.create_event)(self.event_type, self.tpl, self.notify, self.context, &mut event),
+        };
+        // On failure, an owned context is freed along with the builder.
+        if status.is_error() {
+            return Err(status);
+        }
+        Ok(Event { boot_services, event, owned: self.owned.take() })
+    }
+}
+
+impl<N> EventBuilder<Plain, N> {
+    /// Make the event a timer, armed with [`TimerEvent::set_timer`] or [`SignalEvent::set_timer`].
+    pub fn timer(mut self) -> EventBuilder<Timer, N> {
+        self.event_type |= efi::EVT_TIMER;
+        self.into_state()
+    }
+
+    /// Add the event to `group`, so that it is signaled along with every other member, e.g.
+    /// `efi::EVENT_GROUP_READY_TO_BOOT`.
+    pub fn group(mut self, group: &efi::Guid) -> EventBuilder<Grouped, N> {
+        self.group = Some(*group);
+        self.into_state()
+    }
+}
+
+impl<K> EventBuilder<K, NoNotify> {
+    /// Run the notification function when the event is signaled.
+    pub fn notify_signal(mut self) -> EventBuilder<K, NeedsCallback<Signal>> {
+        self.event_type |= efi::EVT_NOTIFY_SIGNAL;
+        self.into_state()
+    }
+
+    /// Run the notification function while the event is waited for or checked, until it is signaled.
+    pub fn notify_wait(mut self) -> EventBuilder<K, NeedsCallback<Wait>> {
+        self.event_type |= efi::EVT_NOTIFY_WAIT;
+        self.into_state()
+    }
+}
+
+impl<K, M> EventBuilder<K, NeedsCallback<M>> {
+    /// Set the notification function and the context it is called with.
+    pub fn callback(mut self, notify: efi::EventNotify, context: *mut c_void) -> EventBuilder<K, WithCallback<M>> {
+        self.notify = Some(notify);
+        self.context = context;
+        self.into_state()
+    }
+
+    /// Set the notification function and the context it is called with, owned by the created event.
+    ///
+    /// The context is dropped after the event is closed, so the notification function can no longer run. It is
+    /// leaked by [`Event::into_raw`] or `mem::forget`, so it must not borrow anything.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use core::cell::Cell;
+    /// use mu_rust_helpers::event::{EventBuilder, SignalEvent};
+    /// use r_efi::efi;
+    ///
+    /// extern "efiapi" fn count(_event: efi::Event, counter: *mut Cell<u32>) {
+    ///     // SAFETY: the counter is owned by the event, and lives until the event is closed.
+    ///     let counter = unsafe { &*counter };
+    ///     counter.set(counter.get() + 1);
+    /// }
+    ///
+    /// fn counting_event(boot_services: &efi::BootServices) -> Result<SignalEvent<'_>, efi::Status> {
+    ///     EventBuilder::new().notify_signal().callback_owned(count, Box::new(Cell::new(0))).create(boot_services)
+    /// }
+    /// ```
+    pub fn callback_owned<T: 'static>(
+        mut self,
+        notify: extern "efiapi" fn(efi::Event, *mut T),
+        context: Box<T>,
+    ) -> EventBuilder<K, WithCallback<M>> {
+        let owned = OwnedContext::new(context);
+        // SAFETY: `*mut T` and `*mut c_void` have the same ABI, and the context passed to `notify` is a `*mut T`.
+        self.notify =
+            Some(unsafe { mem::transmute::<extern "efiapi" fn(efi::Event, *mut T), efi::EventNotify>(notify) });
+        self.context = owned.pointer;
+        self.owned = Some(owned);
+        self.into_state()
+    }
+}
+
+/// Event closed when dropped.
+pub struct Event<'a> {
+    boot_services: &'a efi::BootServices,
+    event: efi::Event,
+    owned: Option<OwnedContext>,
+}
+
+impl Event<'_> {
+    /// Return the raw event.
+    pub fn as_raw(&self) -> efi::Event {
+        self.event
+    }
+
+    /// Give up ownership of the event, e.g. for a notification that must outlive the driver.
+    ///
+    /// An owned context is leaked along with the event.
+    pub fn into_raw(mut self) -> efi::Event {
+        mem::forget(self.owned.take());
+        mem::ManuallyDrop::new(self).event
+    }
+
+    /// Signal the event, or every event of its group.
+    pub fn signal(&self) -> Result<(), efi::Status> {
+        let status = (self.boot_services.signal_event)(self.event);
+        if status.is_error() {
+            return Err(status);
+        }
+        Ok(())
+    }
+
+    /// Return true if the event is signaled, clearing its signaled state.
+    pub fn check(&self) -> bool {
+        (self.boot_services.check_event)(self.event) == efi::Status::SUCCESS
+    }
+
+    fn set_timer(&self, delay: Duration, periodic: bool) -> Result<(), efi::Status> {
+        let timer_type = if periodic { efi::TIMER_PERIODIC } else { efi::TIMER_RELATIVE };
+        let status = (self.boot_services.set_timer)(self.event, timer_type, timer_ticks(delay));
+        if status.is_error() {
+            return Err(status);
+        }
+        Ok(())
+    }
+
+    fn cancel_timer(&self) -> Result<(), efi::Status> {
+        let status = (self.boot_services.set_timer)(self.event, efi::TIMER_CANCEL, 0);
+        if status.is_error() {
+            return Err(status);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Event<'_> {
+    fn drop(&mut self) {
+        (self.boot_services.close_event)(self.event);
+        // The notification function cannot run anymore, so the context can go.
+        drop(self.owned.take());
+    }
+}
+
+/// Return `delay` in the 100ns units of SetTimer, rounded up.
+///
+/// The result is at least one tick, since a zero delay would cancel the timer.
+pub(crate) fn timer_ticks(delay: Duration) -> u64 {
+    delay.as_nanos().div_ceil(100).clamp(1, u64::MAX as u128) as u64
+}
+
+/// Event that is neither a timer nor notified on signal, which can be waited for.
+pub struct WaitEvent<'a>(Event<'a>);
+
+/// Timer event that is not notified on signal, which can be waited for.
+pub struct TimerEvent<'a>(Event<'a>);
+
+/// Event notified on signal, of kind `K`: [`Plain`], [`Grouped`] or [`Timer`]. It cannot be waited for.
+pub struct SignalEvent<'a, K = Plain> {
+    event: Event<'a>,
+    _kind: PhantomData<K>,
+}
+
+impl<'a> WaitEvent<'a> {
+    /// Return the untyped event.
+    pub fn into_inner(self) -> Event<'a> {
+        self.0
+    }
+
+    /// Give up ownership of the event, see [`Event::into_raw`].
+    pub fn into_raw(self) -> efi::Event {
+        self.into_inner().into_raw()
+    }
+}
+
+impl<'a> TimerEvent<'a> {
+    /// Arm the timer to fire once after `delay`, or every `delay` if `periodic`, rounded up to the 100ns resolution
+    /// of timers.
+    pub fn set_timer(&self, delay: Duration, periodic: bool) -> Result<(), efi::Status> {
+        self.0.set_timer(delay, periodic)
+    }
+
+    /// Cancel the timer.
+    pub fn cancel_timer(&self) -> Result<(), efi::Status> {
+        self.0.cancel_timer()
+    }
+
+    /// Return the untyped event.
+    pub fn into_inner(self) -> Event<'a> {
+        self.0
+    }
+
+    /// Give up ownership of the event, see [`Event::into_raw`].
+    pub fn into_raw(self) -> efi::Event {
+        self.into_inner().into_raw()
+    }
+}
+
+impl<'a, K> SignalEvent<'a, K> {
+    /// Return the untyped event.
+    pub fn into_inner(self) -> Event<'a> {
+        self.event
+    }
+
+    /// Give up ownership of the event, see [`Event::into_raw`].
+    pub fn into_raw(self) -> efi::Event {
+        self.into_inner().into_raw()
+    }
+}
+
+impl SignalEvent<'_, Timer> {
+    /// Arm the timer to fire once after `delay`, or every `delay` if `periodic`, rounded up to the 100ns resolution
+    /// of timers.
+    pub fn set_timer(&self, delay: Duration, periodic: bool) -> Result<(), efi::Status> {
+        self.event.set_timer(delay, periodic)
+    }
+
+    /// Cancel the timer.
+    pub fn cancel_timer(&self) -> Result<(), efi::Status> {
+        self.event.cancel_timer()
+    }
+}
+
+impl<'a> Deref for WaitEvent<'a> {
+    type Target = Event<'a>;
+
+    fn deref(&self) -> &Event<'a> {
+        &self.0
+    }
+}
+
+impl<'a> Deref for TimerEvent<'a> {
+    type Target = Event<'a>;
+
+    fn deref(&self) -> &Event<'a> {
+        &self.0
+    }
+}
+
+impl<'a, K> Deref for SignalEvent<'a, K> {
+    type Target = Event<'a>;
+
+    fn deref(&self) -> &Event<'a> {
+        &self.event
+    }
+}
+
+/// Event accepted by WaitForEvent, i.e. without a notification function run on signal.
+pub trait WaitableEvent {
+    /// Return the raw event to wait for.
+    fn wait_handle(&self) -> efi::Event;
+}
+
+impl WaitableEvent for WaitEvent<'_> {
+    fn wait_handle(&self) -> efi::Event {
+        self.as_raw()
+    }
+}
+
+impl WaitableEvent for TimerEvent<'_> {
+    fn wait_handle(&self) -> efi::Event {
+        self.as_raw()
+    }
+}
+
+/// Raw events, e.g. `wait_for_key` of a console, are trusted to be waitable.
+impl WaitableEvent for efi::Event {
+    fn wait_handle(&self) -> efi::Event {
+        *self
+    }
+}
+
+/// Wait until one of `events` is signaled, and return its index.
+///
+/// Like WaitForEvent, this must be called at `efi::TPL_APPLICATION`.
+pub fn wait_for_event(boot_services: &efi::BootServices, events: &[&dyn WaitableEvent]) -> Result<usize, efi::Status> {
+    let mut wait_list: Vec<efi::Event> = events.iter().map(|event| event.wait_handle()).collect();
+    let mut index = 0;
+    let status = (boot_services.wait_for_event)(wait_list.len(), wait_list.as_mut_ptr(), &mut index);
+    if status.is_error() {
+        return Err(status);
+    }
+    Ok(index)
+}
+
+/// Outcome of [`wait_with_timeout`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitResult {
+    /// The event at this index was signaled.
+    Signaled(usize),
+    /// No event was signaled before the timeout.
+    TimedOut,
+}
+
+/// Wait until one of `events` is signaled, or until `timeout` elapses.
+///
+/// WaitForEvent has no timeout, so a temporary timer event is added to the events waited for. Like WaitForEvent,
+/// this must be called at `efi::TPL_APPLICATION`.
+///
+/// # Example
+/// ```no_run
+/// use core::time::Duration;
+/// use mu_rust_helpers::event::{wait_with_timeout, WaitResult};
+/// use r_efi::efi;
+///
+/// fn wait_for_key(system_table: &efi::SystemTable) -> Result<bool, efi::Status> {
+///     // SAFETY: the system table points to valid boot services and console input.
+///     let (boot_services, input) = unsafe { (&*system_table.boot_services, &*system_table.con_in) };
+///     let result = wait_with_timeout(boot_services, &[&input.wait_for_key], Duration::from_secs(5))?;
+///     Ok(result == WaitResult::Signaled(0))
+/// }
+/// ```
+pub fn wait_with_timeout(
+    boot_services: &efi::BootServices,
+    events: &[&dyn WaitableEvent],
+    timeout: Duration,
+) -> Result<WaitResult, efi::Status> {
+    let timer = EventBuilder::new().timer().create(boot_services)?;
+    timer.set_timer(timeout, false)?;
+    let wait_list: Vec<&dyn WaitableEvent> = events.iter().copied().chain([&timer as &dyn WaitableEvent]).collect();
+    let index = wait_for_event(boot_services, &wait_list)?;
+    if index == events.len() {
+        return Ok(WaitResult::TimedOut);
+    }
+    Ok(WaitResult::Signaled(index))
+}
+
+type Callback = RefCell<Box<dyn FnMut()>>;
+
+/// Member of an event group running a closure when the group is signaled, removed from the group when dropped.
+///
+/// # Example
+/// ```no_run
+/// use core::sync::atomic::{AtomicBool, Ordering};
+/// use mu_rust_helpers::event;
+/// use r_efi::efi;
+///
+/// static LOCKED: AtomicBool = AtomicBool::new(false);
+///
+/// fn lock_on_ready_to_boot(boot_services: &'static efi::BootServices) -> Result<(), efi::Status> {
+///     // The closure owns everything it uses, so the registration can be leaked.
+///     let registration = event::on_ready_to_boot(boot_services, || LOCKED.store(true, Ordering::Relaxed))?;
+///     // The closure runs every time the boot manager signals the group.
+///     core::mem::forget(registration);
+///     Ok(())
+/// }
+/// ```
+pub struct GroupEvent<'a> {
+    // Declared first so that the event is closed before the closure it calls is dropped.
+    event: Event<'a>,
+    _callback: Box<Callback>,
+}
+
+impl<'a> GroupEvent<'a> {
+    /// Add an event to `group`, calling `callback` at `tpl` when the group is signaled.
+    ///
+    /// The closure must not borrow anything, since the registration may be leaked with `mem::forget`.
+    pub fn new(
+        boot_services: &'a efi::BootServices,
+        group: &efi::Guid,
+        tpl: efi::Tpl,
+        callback: impl FnMut() + 'static,
+    ) -> Result<Self, efi::Status> {
+        Self::register(boot_services, Some(group), tpl, Box::new(callback))
+    }
+
+    /// Create the event, in `group` if there is one, or signaled at ExitBootServices otherwise.
+    fn register(
+        boot_services: &'a efi::BootServices,
+        group: Option<&efi::Guid>,
+        tpl: efi::Tpl,
+        callback: Box<dyn FnMut()>,
+    ) -> Result<Self, efi::Status> {
+        extern "efiapi" fn notify(_event: efi::Event, context: *mut c_void) {
+            // SAFETY: the context is the boxed callback owned by the `GroupEvent`, which closes this event before
+            // dropping it.
+            let callback = unsafe { &*(context as *const Callback) };
+            // A callback signaling its own group is not run again.
+            if let Ok(mut callback) = callback.try_borrow_mut() {
+                callback();
+            }
+        }
+
+        let callback: Box<Callback> = Box::new(RefCell::new(callback));
+        let context = &*callback as *const Callback as *mut c_void;
+        let builder = EventBuilder::new().notify_signal().tpl(tpl).callback(notify, context);
+        let event = match group {
+            Some(group) => builder.group(group).create(boot_services)?.into_inner(),
+            None => {
+                let mut builder = builder;
+                builder.event_type |= efi::EVT_SIGNAL_EXIT_BOOT_SERVICES;
+                builder.create(boot_services)?.into_inner()
+            }
+        };
+        Ok(Self { event, _callback: callback })
+    }
+
+    /// Return the event, e.g. to signal the group.
+    pub fn event(&self) -> &Event<'a> {
+        &self.event
+    }
+}
+
+/// Call `callback` at `efi::TPL_CALLBACK` when the boot manager is about to boot an option.
+pub fn on_ready_to_boot(
+    boot_services: &efi::BootServices,
+    callback: impl FnMut() + 'static,
+) -> Result<GroupEvent<'_>, efi::Status> {
+    GroupEvent::new(boot_services, &GROUP_READY_TO_BOOT, efi::TPL_CALLBACK, callback)
+}
+
+/// Call `callback` at `efi::TPL_CALLBACK` after every ReadyToBoot notification has run.
+pub fn on_after_ready_to_boot(
+    boot_services: &efi::BootServices,
+    callback: impl FnMut() + 'static,
+) -> Result<GroupEvent<'_>, efi::Status> {
+    GroupEvent::new(boot_services, &GROUP_AFTER_READY_TO_BOOT, efi::TPL_CALLBACK, callback)
+}
+
+/// Call `callback` at `efi::TPL_CALLBACK` when ExitBootServices is called, while boot services can still allocate
+/// memory.
+pub fn on_before_exit_boot_services(
+    boot_services: &efi::BootServices,
+    callback: impl FnMut() + 'static,
+) -> Result<GroupEvent<'_>, efi::Status> {
+    GroupEvent::new(boot_services, &GROUP_BEFORE_EXIT_BOOT_SERVICES, efi::TPL_CALLBACK, callback)
+}
+
+/// Call `callback` at `efi::TPL_CALLBACK` when ExitBootServices is called, e.g. to stop the DMA of a device.
+///
+/// The event is created with `efi::EVT_SIGNAL_EXIT_BOOT_SERVICES` rather than joining [`GROUP_EXIT_BOOT_SERVICES`],
+/// which also works with firmware that predates CreateEventEx. Running at `efi::TPL_CALLBACK` rather than
+/// `efi::TPL_NOTIFY` lets the notifications of other drivers complete before `callback` runs.
+///
+/// The memory map is final: `callback` must not allocate or free memory, nor use other boot services. It runs again
+/// if the OS loader retries ExitBootServices after a failure.
+pub fn on_exit_boot_services(
+    boot_services: &efi::BootServices,
+    callback: impl FnMut() + 'static,
+) -> Result<GroupEvent<'_>, efi::Status> {
+    GroupEvent::register(boot_services, None, efi::TPL_CALLBACK, Box::new(callback))
+}
+
+/// Call `callback` at `efi::TPL_NOTIFY` when SetVirtualAddressMap is called.
+///
+/// Boot services are gone by then: the closure and everything it uses must live in runtime memory.
+pub fn on_virtual_address_change(
+    boot_services: &efi::BootServices,
+    callback: impl FnMut() + 'static,
+) -> Result<GroupEvent<'_>, efi::Status> {
+    GroupEvent::new(boot_services, &GROUP_VIRTUAL_ADDRESS_CHANGE, efi::TPL_NOTIFY, callback)
+}
+
+/// Call `callback` at `efi::TPL_CALLBACK` when ResetSystem is called, before the platform is reset.
+pub fn on_reset_system(
+    boot_services: &efi::BootServices,
+    callback: impl FnMut() + 'static,
+) -> Result<GroupEvent<'_>, efi::Status> {
+    GroupEvent::new(boot_services, &GROUP_RESET_SYSTEM, efi::TPL_CALLBACK, callback)
+}
+
+/// State shared between an event future and the notification function of its event.
+#[cfg(feature = "executor")]
+#[derive(Default)]
+pub(crate) struct WakeSlot {
+    signaled: Cell<bool>,
+    waker: RefCell<Option<Waker>>,
+}
+
+#[cfg(feature = "executor")]
+impl WakeSlot {
+    /// Record that the event was signaled and wake the waiting task, from a notification at `efi::TPL_CALLBACK`.
+    pub(crate) fn wake(&self) {
+        self.signaled.set(true);
+        // Tasks register their waker at `TPL_CALLBACK`, so the slot is never borrowed here.
+        if let Some(waker) = self.waker.try_borrow_mut().ok().and_then(|mut waker| waker.take()) {
+            waker.wake();
+        }
+    }
+
+    /// Return ready if the event was signaled, or register the waker of `cx` to be woken when it is.
+    pub(crate) fn poll_signaled(&self, boot_services: &efi::BootServices, cx: &mut Context<'_>) -> Poll<()> {
+        // The notification function runs at `TPL_CALLBACK`, so it cannot preempt this.
+        let tpl = (boot_services.raise_tpl)(efi::TPL_CALLBACK);
+        let poll = if self.signaled.get() {
+            Poll::Ready(())
+        } else {
+            *self.waker.borrow_mut() = Some(cx.waker().clone());
+            Poll::Pending
+        };
+        (boot_services.restore_tpl)(tpl);
+        poll
+    }
+}
+
+/// Future resolving once its event, or its event group, is signaled.
+///
+/// The event notification runs at `efi::TPL_CALLBACK` and wakes the waiting task. Tasks must poll the future below
+/// `TPL_CALLBACK`, which it raises the TPL to while it registers the waker.
+///
+/// # Example
+/// ```no_run
+/// use executor::{Priority, Scheduler};
+/// use mu_rust_helpers::event::EventFuture;
+/// use r_efi::efi;
+///
+/// fn run(boot_services: &efi::BootServices) -> Result<(), efi::Status> {
+///     let ready_to_boot = EventFuture::group(boot_services, &efi::EVENT_GROUP_READY_TO_BOOT)?;
+///     let mut scheduler = Scheduler::new();
+///     scheduler.spawn(Priority::Normal, async move {
+///         ready_to_boot.await;
+///         // Stop background work before the OS loader starts.
+///     });
+///     scheduler.run();
+///     Ok(())
+/// }
+/// ```
+#[cfg(feature = "executor")]
+pub struct EventFuture<'a> {
+    event: Event<'a>,
+    slot: Rc<WakeSlot>,
+}
+
+#[cfg(feature = "executor")]
+impl<'a> EventFuture<'a> {
+    /// Create an event resolving the future when signaled, e.g. by a service given [`Self::event`] as completion
+    /// event.
+    pub fn new(boot_services: &'a efi::BootServices) -> Result<Self, efi::Status> {
+        let slot = Rc::new(WakeSlot::default());
+        let event = EventBuilder::new()
+            .notify_signal()
+            .callback_owned(Self::notify, Box::new(slot.clone()))
+            .create(boot_services)?
+            .into_inner();
+        Ok(Self { event, slot })
+    }
+
+    /// Add an event to `group`, resolving the future when the group is signaled.
+    pub fn group(boot_services: &'a efi::BootServices, group: &efi::Guid) -> Result<Self, efi::Status> {
+        let slot = Rc::new(WakeSlot::default());
+        let event = EventBuilder::new()
+            .group(group)
+            .notify_signal()
+            .callback_owned(Self::notify, Box::new(slot.clone()))
+            .create(boot_services)?
+            .into_inner();
+        Ok(Self { event, slot })
+    }
+
+    /// Return the event, to be signaled by the completion source.
+    pub fn event(&self) -> efi::Event {
+        self.event.as_raw()
+    }
+
+    extern "efiapi" fn notify(_event: efi::Event, slot: *mut Rc<WakeSlot>) {
+        // SAFETY: the slot is owned by the event, and lives until the event is closed.
+        unsafe { &*slot }.wake();
+    }
+}
+
+#[cfg(feature = "executor")]
+impl Future for EventFuture<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        self.slot.poll_signaled(self.event.boot_services, cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+
+    use crate::test_support::mock_efi_boot_services;
+
+    /// Event created by the mocks: type, TPL, notification function, context and group.
+    type Created = (u32, efi::Tpl, Option<usize>, usize, Option<efi::Guid>);
+
+    std::thread_local! {
+        static CREATED: RefCell<Vec<Created>> = const { RefCell::new(Vec::new()) };
+        static TIMERS: RefCell<Vec<(efi::TimerDelay, u64)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    extern "efiapi" fn create_event(
+        event_type: u32,
+        tpl: efi::Tpl,
+        notify: Option<efi::EventNotify>,
+        context: *mut c_void,
+        event: *mut efi::Event,
+    ) -> efi::Status {
+        CREATED.with(|created| {
+            created.borrow_mut().push((event_type, tpl, notify.map(|notify| notify as usize), context as usize, None))
+        });
+        unsafe { *event = 0x10 as efi::Event };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn create_event_ex(
+        event_type: u32,
+        tpl: efi::Tpl,
+        notify: Option<efi::EventNotify>,
+        context: *const c_void,
+        group: *const efi::Guid,
+        event: *mut efi::Event,
+    ) -> efi::Status {
+        let group = unsafe { *group };
+        CREATED.with(|created| {
+            created.borrow_mut().push((
+                event_type,
+                tpl,
+                notify.map(|notify| notify as usize),
+                context as usize,
+                Some(group),
+            ))
+        });
+        unsafe { *event = 0x20 as efi::Event };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn set_timer(event: efi::Event, delay: efi::TimerDelay, period: u64) -> efi::Status {
+        assert_eq!(event as usize, 0x10);
+        TIMERS.with(|timers| timers.borrow_mut().push((delay, period)));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn notify(_event: efi::Event, _context: *mut c_void) {}
+
+    fn boot_services() -> efi::BootServices {
+        efi::BootServices { create_event, create_event_ex, set_timer, ..mock_efi_boot_services() }
+    }
+
+    #[test]
+    fn test_event_builder() {
+        let boot_services = boot_services();
+        let timer = EventBuilder::new().timer().create(&boot_services).unwrap();
+        timer.set_timer(Duration::from_micros(10), true).unwrap();
+        timer.cancel_timer().unwrap();
+        assert_eq!(TIMERS.with(|timers| timers.take()), [(efi::TIMER_PERIODIC, 100), (efi::TIMER_CANCEL, 0)]);
+        let timer = timer.into_raw();
+        assert_eq!(timer as usize, 0x10);
+
+        let group = EventBuilder::new()
+            .notify_signal()
+            .tpl(efi::TPL_NOTIFY)
+            .callback(notify, 0x30 as *mut c_void)
+            .group(&efi::EVENT_GROUP_READY_TO_BOOT)
+            .create(&boot_services)
+            .unwrap()
+            .into_raw();
+        assert_eq!(group as usize, 0x20);
+
+        let wait = EventBuilder::new().notify_wait().callback(notify, ptr::null_mut()).timer();
+        wait.create(&boot_services).unwrap().into_raw();
+
+        assert_eq!(
+            CREATED.with(|created| created.take()),
+            [
+                (efi::EVT_TIMER, efi::TPL_CALLBACK, None, 0, None),
+                (
+                    efi::EVT_NOTIFY_SIGNAL,
+                    efi::TPL_NOTIFY,
+                    Some(notify as usize),
+                    0x30,
+                    Some(efi::EVENT_GROUP_READY_TO_BOOT)
+                ),
+                (efi::EVT_NOTIFY_WAIT | efi::EVT_TIMER, efi::TPL_CALLBACK, Some(notify as usize), 0, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_event_close_on_drop() {
+        std::thread_local! {
+            static CLOSED: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+        }
+        extern "efiapi" fn close_event(event: efi::Event) -> efi::Status {
+            CLOSED.with(|closed| closed.borrow_mut().push(event as usize));
+            efi::Status::SUCCESS
+        }
+
+        let boot_services = efi::BootServices { close_event, ..boot_services() };
+        let event = EventBuilder::new().create(&boot_services).unwrap();
+        assert_eq!(event.signal(), Err(efi::Status::UNSUPPORTED));
+        assert!(!event.check());
+        drop(event);
+        assert_eq!(CLOSED.with(|closed| closed.take()), [0x10]);
+    }
+
+    #[test]
+    fn test_owned_context() {
+        std::thread_local! {
+            static DROPPED: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+        }
+        struct Context(&'static str);
+        impl Drop for Context {
+            fn drop(&mut self) {
+                DROPPED.with(|dropped| dropped.borrow_mut().push(self.0));
+            }
+        }
+        extern "efiapi" fn close_event(_event: efi::Event) -> efi::Status {
+            DROPPED.with(|dropped| dropped.borrow_mut().push("closed"));
+            efi::Status::SUCCESS
+        }
+        extern "efiapi" fn notify(_event: efi::Event, context: *mut Context) {
+            assert_eq!(unsafe { &*context }.0, "event");
+        }
+
+        let boot_services = efi::BootServices { close_event, ..boot_services() };
+        let event = EventBuilder::new()
+            .notify_signal()
+            .callback_owned(notify, Box::new(Context("event")))
+            .create(&boot_services)
+            .unwrap();
+        let (_, _, raw_notify, context, _) = CREATED.with(|created| created.take()).pop().unwrap();
+        let raw_notify = unsafe { core::mem::transmute::<usize, efi::EventNotify>(raw_notify.unwrap()) };
+        raw_notify(event.as_raw(), context as *mut c_void);
+        assert!(DROPPED.with(|dropped| dropped.borrow().is_empty()));
+        drop(event);
+        assert_eq!(DROPPED.with(|dropped| dropped.take()), ["closed", "event"]);
+
+        // Dropped with a builder that never creates the event, and leaked with a raw event.
+        drop(EventBuilder::new().notify_signal().callback_owned(notify, Box::new(Context("builder"))));
+        let event = EventBuilder::new().notify_signal().callback_owned(notify, Box::new(Context("raw")));
+        event.create(&boot_services).unwrap().into_raw();
+        assert_eq!(DROPPED.with(|dropped| dropped.take()), ["builder"]);
+        CREATED.with(|created| created.take());
+    }
+
+    #[test]
+    fn test_wait_with_timeout() {
+        std::thread_local! {
+            static SIGNALED: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+        }
+        extern "efiapi" fn wait_for_event(count: usize, events: *mut efi::Event, index: *mut usize) -> efi::Status {
+            let events = unsafe { core::slice::from_raw_parts(events, count) };
+            assert_eq!(events.iter().map(|&event| event as usize).collect::<Vec<_>>(), [0x31, 0x32, 0x10]);
+            unsafe { *index = SIGNALED.with(|signaled| signaled.get()) };
+            efi::Status::SUCCESS
+        }
+
+        let boot_services = efi::BootServices { wait_for_event, ..boot_services() };
+        let (key, timer) = (0x31 as efi::Event, 0x32 as efi::Event);
+        let events: [&dyn WaitableEvent; 2] = [&key, &timer];
+        SIGNALED.with(|signaled| signaled.set(1));
+        assert_eq!(wait_with_timeout(&boot_services, &events, Duration::from_millis(1)), Ok(WaitResult::Signaled(1)));
+        SIGNALED.with(|signaled| signaled.set(2));
+        assert_eq!(wait_with_timeout(&boot_services, &events, Duration::from_millis(1)), Ok(WaitResult::TimedOut));
+        assert_eq!(TIMERS.with(|timers| timers.take()), [(efi::TIMER_RELATIVE, 10_000), (efi::TIMER_RELATIVE, 10_000)]);
+        CREATED.with(|created| created.take());
+    }
+
+    #[test]
+    fn test_group_event() {
+        let boot_services = boot_services();
+        let count = std::rc::Rc::new(std::cell::Cell::new(0));
+        let counter = count.clone();
+        let registration = on_virtual_address_change(&boot_services, move || counter.set(counter.get() + 1)).unwrap();
+        let (event_type, tpl, notify, context, group) = CREATED.with(|created| created.borrow_mut().pop().unwrap());
+        assert_eq!(
+            (event_type, tpl, group),
+            (efi::EVT_NOTIFY_SIGNAL, efi::TPL_NOTIFY, Some(GROUP_VIRTUAL_ADDRESS_CHANGE))
+        );
+        assert_eq!(registration.event().as_raw() as usize, 0x20);
+
+        let notify = unsafe { core::mem::transmute::<usize, efi::EventNotify>(notify.unwrap()) };
+        notify(registration.event().as_raw(), context as *mut c_void);
+        notify(registration.event().as_raw(), context as *mut c_void);
+        drop(registration);
+        assert_eq!(count.get(), 2);
+
+        drop(on_ready_to_boot(&boot_services, || ()).unwrap());
+        let (_, tpl, _, _, group) = CREATED.with(|created| created.borrow_mut().pop().unwrap());
+        assert_eq!((tpl, group), (efi::TPL_CALLBACK, Some(GROUP_READY_TO_BOOT)));
+    }
+
+    #[test]
+    fn test_on_exit_boot_services() {
+        let boot_services = boot_services();
+        let quiesced = std::rc::Rc::new(std::cell::Cell::new(false));
+        let flag = quiesced.clone();
+        let registration = on_exit_boot_services(&boot_services, move || flag.set(true)).unwrap();
+        let (event_type, tpl, notify, context, group) = CREATED.with(|created| created.borrow_mut().pop().unwrap());
+        assert_eq!(
+            (event_type, tpl, group),
+            (efi::EVT_NOTIFY_SIGNAL | efi::EVT_SIGNAL_EXIT_BOOT_SERVICES, efi::TPL_CALLBACK, None)
+        );
+        assert_eq!(registration.event().as_raw() as usize, 0x10);
+
+        let notify = unsafe { core::mem::transmute::<usize, efi::EventNotify>(notify.unwrap()) };
+        notify(registration.event().as_raw(), context as *mut c_void);
+        drop(registration);
+        assert!(quiesced.get());
+    }
+
+    #[cfg(feature = "executor")]
+    #[test]
+    fn test_event_future() {
+        use executor::{Priority, Scheduler};
+        use std::{cell::Cell, rc::Rc};
+
+        std::thread_local! {
+            static TPL: Cell<efi::Tpl> = const { Cell::new(efi::TPL_APPLICATION) };
+        }
+        extern "efiapi" fn raise_tpl(tpl: efi::Tpl) -> efi::Tpl {
+            TPL.with(|current| current.replace(tpl))
+        }
+        extern "efiapi" fn restore_tpl(tpl: efi::Tpl) {
+            TPL.with(|current| current.set(tpl))
+        }
+
+        let boot_services = efi::BootServices { raise_tpl, restore_tpl, ..boot_services() };
+        let future = EventFuture::group(&boot_services, &efi::EVENT_GROUP_READY_TO_BOOT).unwrap();
+        assert_eq!(future.event() as usize, 0x20);
+        let (event_type, tpl, notify, context, group) = CREATED.with(|created| created.borrow_mut().pop().unwrap());
+        assert_eq!((event_type, tpl, group), (efi::EVT_NOTIFY_SIGNAL, efi::TPL_CALLBACK, Some(GROUP_READY_TO_BOOT)));
+
+        let done = Rc::new(Cell::new(false));
+        let finished = done.clone();
+        let mut scheduler = Scheduler::new();
+        scheduler.spawn(Priority::Normal, async move {
+            future.await;
+            finished.set(true);
+        });
+        assert!(scheduler.run_once());
+        // The task is not polled again until the group is signaled.
+        assert!(!scheduler.run_once());
+        let notify = unsafe { core::mem::transmute::<usize, efi::EventNotify>(notify.unwrap()) };
+        notify(0x20 as efi::Event, context as *mut c_void);
+        assert!(scheduler.run_once());
+        assert!(done.get() && scheduler.is_empty());
+        assert_eq!(TPL.with(|tpl| tpl.get()), efi::TPL_APPLICATION);
+    }
+}
@@ -0,0 +1,240 @@
+//! File path handling consistent with the FAT file system driver.
+//!
+//! UEFI file paths use `\` as separator and are resolved by the FAT driver case-insensitively, ignoring trailing
+//! spaces and periods in each component. [`normalize`] produces the canonical form of a path so that two paths naming
+//! the same file compare equal with [`eq_ignore_case`], [`glob_match`] matches paths against wildcard patterns, and
+//! [`ShortName`] converts names to and from the 8.3 form stored in FAT directory entries.
+//!
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+use r_efi::efi;
+
+/// Path separator.
+pub const SEPARATOR: char = '\\';
+
+/// Characters that may not appear in a long file name, besides control characters.
+const INVALID_CHARS: &[char] = &['"', '*', '/', ':', '<', '>', '?', '\\', '|'];
+
+/// Return the canonical form of `path`.
+///
+/// `/` is accepted as a separator, repeated and trailing separators are removed, `.` and `..` components are
+/// resolved and trailing spaces and periods are trimmed from every component. Absolute paths keep their leading `\`.
+///
+/// Returns `efi::Status::INVALID_PARAMETER` if a component contains a character that is not valid in a file name, or
+/// if `..` goes above the root of an absolute path.
+pub fn normalize(path: &str) -> Result<String, efi::Status> {
+    normalize_with(path, is_valid_name)
+}
+
+fn normalize_with(path: &str, is_valid_name: fn(&str) -> bool) -> Result<String, efi::Status> {
+    let is_absolute = path.starts_with(['\\', '/']);
+    let mut components: Vec<&str> = Vec::new();
+    for component in path.split(['\\', '/']) {
+        match component {
+            "" | "." => (),
+            ".." => match components.last() {
+                Some(&last) if last != ".." => {
+                    components.pop();
+                }
+                _ if is_absolute => return Err(efi::Status::INVALID_PARAMETER),
+                _ => components.push(".."),
+            },
+            _ => {
+                let component = component.trim_end_matches([' ', '.']);
+                if component.is_empty() || !is_valid_name(component) {
+                    return Err(efi::Status::INVALID_PARAMETER);
+                }
+                components.push(component);
+            }
+        }
+    }
+
+    let mut normalized = String::with_capacity(path.len());
+    if is_absolute {
+        normalized.push(SEPARATOR);
+    }
+    for (index, component) in components.iter().enumerate() {
+        if index > 0 {
+            normalized.push(SEPARATOR);
+        }
+        normalized.push_str(component);
+    }
+    Ok(normalized)
+}
+
+/// Return true if `a` and `b` name the same file, see [`normalize`]. Invalid paths never compare equal.
+pub fn eq_ignore_case(a: &str, b: &str) -> bool {
+    match (normalize(a), normalize(b)) {
+        (Ok(a), Ok(b)) => a.chars().flat_map(char::to_uppercase).eq(b.chars().flat_map(char::to_uppercase)),
+        _ => false,
+    }
+}
+
+/// Return true if `path` matches the glob `pattern`, e.g. `\EFI\*\*.efi`.
+///
+/// Both are normalized first, and compared case-insensitively like [`eq_ignore_case`]. In a component, `*` matches any
+/// sequence of characters and `?` any single character. A `**` component matches any number of components. Invalid
+/// paths or patterns never match.
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let is_valid_pattern = |name: &str| is_valid_name(&name.replace(['*', '?'], "_"));
+    let (Ok(pattern), Ok(path)) = (normalize_with(pattern, is_valid_pattern), normalize(path)) else {
+        return false;
+    };
+    let is_absolute = |path: &str| path.starts_with(SEPARATOR);
+    if is_absolute(&pattern) != is_absolute(&path) {
+        return false;
+    }
+    let components = |path: &str| -> Vec<Vec<char>> {
+        path.split(SEPARATOR)
+            .filter(|component| !component.is_empty())
+            .map(|component| component.chars().flat_map(char::to_uppercase).collect())
+            .collect()
+    };
+    match_components(&components(&pattern), &components(&path))
+}
+
+fn match_components(pattern: &[Vec<char>], path: &[Vec<char>]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first[..] == ['*', '*'] => {
+            (0..=path.len()).any(|skipped| match_components(rest, &path[skipped..]))
+        }
+        Some((first, rest)) => {
+            path.split_first().is_some_and(|(name, path)| match_name(first, name) && match_components(rest, path))
+        }
+    }
+}
+
+fn match_name(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skipped| match_name(rest, &name[skipped..])),
+        Some((&c, rest)) => name.split_first().is_some_and(|(&n, name)| (c == '?' || c == n) && match_name(rest, name)),
+    }
+}
+
+/// Return true if `name` is a valid long file name component.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().count() <= 255 && !name.chars().any(|c| c < ' ' || INVALID_CHARS.contains(&c))
+}
+
+/// Name in the 8.3 form stored in FAT directory entries: 8 characters of base name and 3 of extension, uppercase and
+/// padded with spaces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ShortName([u8; 11]);
+
+impl ShortName {
+    /// Characters valid in a short name, besides letters and digits.
+    const SPECIAL_CHARS: &'static [u8] = b"!#$%&'()-@^_`{}~";
+
+    /// Convert `name` to its short form, if it has one.
+    ///
+    /// Only names made of at most 8 characters, optionally followed by a period and at most 3 characters, all of them
+    /// ASCII letters, digits or characters valid in short names, can be converted. Lowercase letters are converted to
+    /// uppercase.
+    pub fn new(name: &str) -> Option<Self> {
+        let (base, extension) = match name.rsplit_once('.') {
+            Some((base, extension)) => (base, extension),
+            None => (name, ""),
+        };
+        if base.is_empty() || base.len() > 8 || extension.len() > 3 {
+            return None;
+        }
+        let mut short_name = [b' '; 11];
+        let (base_field, extension_field) = short_name.split_at_mut(8);
+        for (field, part) in [(base_field, base), (extension_field, extension)] {
+            for (c, &byte) in field.iter_mut().zip(part.as_bytes()) {
+                if !byte.is_ascii_alphanumeric() && !Self::SPECIAL_CHARS.contains(&byte) {
+                    return None;
+                }
+                *c = byte.to_ascii_uppercase();
+            }
+        }
+        Some(Self(short_name))
+    }
+
+    /// Create a short name from the 11 bytes of a directory entry.
+    pub const fn from_bytes(bytes: [u8; 11]) -> Self {
+        Self(bytes)
+    }
+
+    /// Return the 11 bytes stored in a directory entry.
+    pub fn as_bytes(&self) -> &[u8; 11] {
+        &self.0
+    }
+}
+
+impl fmt::Display for ShortName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let trim = |field: &[u8]| {
+            let len = field.iter().rposition(|&c| c != b' ').map_or(0, |index| index + 1);
+            String::from_utf8_lossy(&field[..len]).into_owned()
+        };
+        let (base, extension) = (trim(&self.0[..8]), trim(&self.0[8..]));
+        if extension.is_empty() {
+            write!(f, "{base}")
+        } else {
+            write!(f, "{base}.{extension}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("\\EFI\\Boot\\bootx64.efi").unwrap(), "\\EFI\\Boot\\bootx64.efi");
+        assert_eq!(normalize("/EFI//Boot/").unwrap(), "\\EFI\\Boot");
+        assert_eq!(normalize("\\EFI\\.\\Vendor\\..\\Boot\\.\\x.efi").unwrap(), "\\EFI\\Boot\\x.efi");
+        assert_eq!(normalize("EFI\\Boot.. \\file. ").unwrap(), "EFI\\Boot\\file");
+        assert_eq!(normalize("..\\..\\x").unwrap(), "..\\..\\x");
+        assert_eq!(normalize("a\\..\\..").unwrap(), "..");
+        assert_eq!(normalize("\\").unwrap(), "\\");
+        assert_eq!(normalize("").unwrap(), "");
+
+        assert_eq!(normalize("\\..\\x"), Err(efi::Status::INVALID_PARAMETER));
+        assert_eq!(normalize("\\EFI\\a:b"), Err(efi::Status::INVALID_PARAMETER));
+        assert_eq!(normalize("\\EFI\\a\u{7}"), Err(efi::Status::INVALID_PARAMETER));
+        assert_eq!(normalize("\\EFI\\ . "), Err(efi::Status::INVALID_PARAMETER));
+    }
+
+    #[test]
+    fn test_eq_ignore_case() {
+        assert!(eq_ignore_case("\\EFI\\BOOT\\BOOTX64.EFI", "/efi/boot/bootx64.efi"));
+        assert!(eq_ignore_case("\\efi\\\u{e9}t\u{e9}\\", "\\EFI\\\u{c9}T\u{c9}"));
+        assert!(!eq_ignore_case("\\EFI\\Boot", "EFI\\Boot"));
+        assert!(!eq_ignore_case("\\a|b", "\\a|b"));
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("\\EFI\\*\\*.efi", "\\EFI\\Boot\\BOOTX64.EFI"));
+        assert!(glob_match("\\EFI\\*\\*.efi", "/efi/vendor/grub.efi"));
+        assert!(!glob_match("\\EFI\\*\\*.efi", "\\EFI\\Boot\\Fonts\\x.efi"));
+        assert!(!glob_match("\\EFI\\*\\*.efi", "\\EFI\\Boot\\bootx64.efi.bak"));
+        assert!(glob_match("\\EFI\\**\\*.efi", "\\EFI\\Boot\\Fonts\\x.efi"));
+        assert!(glob_match("\\EFI\\**\\*.efi", "\\EFI\\x.efi"));
+        assert!(glob_match("\\EFI\\Boot\\boot???.efi", "\\EFI\\Boot\\bootx64.efi"));
+        assert!(!glob_match("\\EFI\\Boot\\boot???.efi", "\\EFI\\Boot\\bootaa64.efi"));
+        assert!(!glob_match("EFI\\*.efi", "\\EFI\\x.efi"));
+    }
+
+    #[test]
+    fn test_short_name() {
+        let name = ShortName::new("bootx64.efi").unwrap();
+        assert_eq!(name.as_bytes(), b"BOOTX64 EFI");
+        assert_eq!(name.to_string(), "BOOTX64.EFI");
+        assert_eq!(ShortName::new("README").unwrap().to_string(), "README");
+        assert_eq!(ShortName::from_bytes(*b"A       B  ").to_string(), "A.B");
+
+        assert!(ShortName::new("toolongname.efi").is_none());
+        assert!(ShortName::new("file.json").is_none());
+        assert!(ShortName::new("two.dots.x").is_none());
+        assert!(ShortName::new("sp ace.txt").is_none());
+        assert!(ShortName::new(".efi").is_none());
+        assert!(ShortName::new("\u{e9}.txt").is_none());
+    }
+}
@@ -0,0 +1,205 @@
+//! Checked views of buffers handed out by firmware.
+//!
+//! Firmware returns data as a raw pointer and a length: configuration tables, handle buffers, protocol-owned
+//! arrays. Building a slice from those with `core::slice::from_raw_parts` is undefined behavior if the pointer is null
+//! or misaligned, or if the length overflows the address space. [`FirmwareSlice`] and [`FirmwareRef`] validate those
+//! properties once at construction and then only offer safe accessors.
+//!
+use core::{fmt, mem, ops::Deref, ptr::NonNull, slice};
+
+use r_efi::efi;
+
+/// Validated view of `len` elements of type `T` provided by firmware.
+///
+/// # Example
+/// ```
+/// use mu_rust_helpers::firmware_slice::FirmwareSlice;
+///
+/// let handles = [1usize, 2, 3];
+/// // `handles` stands in for a buffer and count returned by firmware.
+/// let handles = unsafe { FirmwareSlice::new(handles.as_ptr(), handles.len()) }.unwrap();
+/// assert_eq!(handles.get(2), Some(&3));
+/// assert_eq!(handles.get(3), None);
+///
+/// let empty = unsafe { FirmwareSlice::<usize>::new(core::ptr::null(), 0) }.unwrap();
+/// assert!(empty.is_empty());
+/// ```
+#[derive(Clone, Copy)]
+pub struct FirmwareSlice<'a, T> {
+    slice: &'a [T],
+}
+
+impl<'a, T> FirmwareSlice<'a, T> {
+    /// Create a view of `len` elements starting at `data`.
+    ///
+    /// A null `data` is accepted for an empty buffer. Returns `efi::Status::INVALID_PARAMETER` if `data` is null with a
+    /// non-zero `len` or is not aligned for `T`, and `efi::Status::BAD_BUFFER_SIZE` if the buffer overflows the address
+    /// space.
+    ///
+    /// # Safety
+    /// If validation succeeds, `data` must point to `len` initialized elements of `T` that remain valid and unmodified
+    /// for `'a`.
+    pub unsafe fn new(data: *const T, len: usize) -> Result<Self, efi::Status> {
+        if data.is_null() {
+            return match len {
+                0 => Ok(Self { slice: &[] }),
+                _ => Err(efi::Status::INVALID_PARAMETER),
+            };
+        }
+        if !data.is_aligned() {
+            return Err(efi::Status::INVALID_PARAMETER);
+        }
+        let size = len.checked_mul(mem::size_of::<T>()).ok_or(efi::Status::BAD_BUFFER_SIZE)?;
+        if size > isize::MAX as usize || (data as usize).checked_add(size).is_none() {
+            return Err(efi::Status::BAD_BUFFER_SIZE);
+        }
+        Ok(Self { slice: slice::from_raw_parts(data, len) })
+    }
+
+    /// Create a view of a buffer of `size` bytes holding elements of type `T`.
+    ///
+    /// Returns `efi::Status::BAD_BUFFER_SIZE` if `size` is not a multiple of the size of `T`, in addition to the
+    /// errors returned by [`Self::new`].
+    ///
+    /// # Safety
+    /// Same as [`Self::new`].
+    pub unsafe fn from_byte_size(data: *const T, size: usize) -> Result<Self, efi::Status> {
+        let element_size = mem::size_of::<T>();
+        if element_size == 0 || size % element_size != 0 {
+            return Err(efi::Status::BAD_BUFFER_SIZE);
+        }
+        Self::new(data, size / element_size)
+    }
+
+    /// Return the elements as a slice.
+    pub fn as_slice(&self) -> &'a [T] {
+        self.slice
+    }
+
+    /// Return the view of `len` elements starting at `offset`, or `None` if it is out of bounds.
+    pub fn subslice(&self, offset: usize, len: usize) -> Option<FirmwareSlice<'a, T>> {
+        let end = offset.checked_add(len)?;
+        self.slice.get(offset..end).map(|slice| FirmwareSlice { slice })
+    }
+}
+
+impl<T> Default for FirmwareSlice<'_, T> {
+    fn default() -> Self {
+        Self { slice: &[] }
+    }
+}
+
+impl<T> Deref for FirmwareSlice<'_, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.slice
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for FirmwareSlice<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.slice.fmt(f)
+    }
+}
+
+impl<'a, T> IntoIterator for FirmwareSlice<'a, T> {
+    type Item = &'a T;
+    type IntoIter = slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.slice.iter()
+    }
+}
+
+/// Validated reference to a single `T` provided by firmware.
+#[derive(Clone, Copy)]
+pub struct FirmwareRef<'a, T> {
+    value: &'a T,
+}
+
+impl<'a, T> FirmwareRef<'a, T> {
+    /// Create a reference to the `T` at `data`.
+    ///
+    /// Returns `efi::Status::INVALID_PARAMETER` if `data` is null or not aligned for `T`.
+    ///
+    /// # Safety
+    /// If validation succeeds, `data` must point to an initialized `T` that remains valid and unmodified for `'a`.
+    pub unsafe fn new(data: *const T) -> Result<Self, efi::Status> {
+        let data = NonNull::new(data as *mut T).ok_or(efi::Status::INVALID_PARAMETER)?;
+        if !data.as_ptr().is_aligned() {
+            return Err(efi::Status::INVALID_PARAMETER);
+        }
+        Ok(Self { value: data.as_ref() })
+    }
+
+    /// Return the underlying reference.
+    pub fn get(&self) -> &'a T {
+        self.value
+    }
+}
+
+impl<T> Deref for FirmwareRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for FirmwareRef<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::ptr;
+
+    #[test]
+    fn test_firmware_slice() {
+        let data = [1u32, 2, 3, 4];
+        let view = unsafe { FirmwareSlice::new(data.as_ptr(), data.len()) }.unwrap();
+        assert_eq!(view.len(), 4);
+        assert_eq!(view.as_slice(), &data);
+        assert_eq!(view.into_iter().sum::<u32>(), 10);
+        assert_eq!(view.subslice(1, 2).unwrap().as_slice(), &[2, 3]);
+        assert!(view.subslice(3, 2).is_none());
+        assert!(view.subslice(usize::MAX, 2).is_none());
+
+        let view = unsafe { FirmwareSlice::from_byte_size(data.as_ptr(), 8) }.unwrap();
+        assert_eq!(view.as_slice(), &[1, 2]);
+    }
+
+    #[test]
+    fn test_firmware_slice_validation() {
+        assert!(unsafe { FirmwareSlice::<u32>::new(ptr::null(), 0) }.unwrap().is_empty());
+        assert_eq!(unsafe { FirmwareSlice::<u32>::new(ptr::null(), 1) }.err(), Some(efi::Status::INVALID_PARAMETER));
+
+        let data = [0u32; 2];
+        let misaligned = (data.as_ptr() as *const u8).wrapping_add(1) as *const u32;
+        assert_eq!(unsafe { FirmwareSlice::new(misaligned, 1) }.err(), Some(efi::Status::INVALID_PARAMETER));
+
+        assert_eq!(unsafe { FirmwareSlice::new(data.as_ptr(), usize::MAX) }.err(), Some(efi::Status::BAD_BUFFER_SIZE));
+        assert_eq!(
+            unsafe { FirmwareSlice::new((usize::MAX - 3) as *const u32, 2) }.err(),
+            Some(efi::Status::BAD_BUFFER_SIZE)
+        );
+        assert_eq!(
+            unsafe { FirmwareSlice::from_byte_size(data.as_ptr(), 6) }.err(),
+            Some(efi::Status::BAD_BUFFER_SIZE)
+        );
+    }
+
+    #[test]
+    fn test_firmware_ref() {
+        let value = 0x1234u64;
+        let reference = unsafe { FirmwareRef::new(&value) }.unwrap();
+        assert_eq!(*reference, 0x1234);
+        assert_eq!(*reference.get(), 0x1234);
+        assert_eq!(unsafe { FirmwareRef::<u64>::new(ptr::null()) }.err(), Some(efi::Status::INVALID_PARAMETER));
+    }
+}
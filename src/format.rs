@@ -0,0 +1,251 @@
+//! Allocation-free formatting.
+//!
+//! `core::fmt` already formats numbers with any width, padding and radix, but drivers often run where no allocator is
+//! available, or must not allocate in the middle of a failure. [`FormatBuf`] collects formatted text in a fixed
+//! buffer, and [`DisplayGuid`] and [`HexDump`] format GUIDs and memory the way firmware debug output usually shows
+//! them.
+//!
+use core::{fmt, str};
+
+use r_efi::efi;
+
+/// Text formatted into a fixed buffer of `N` bytes.
+///
+/// Text that does not fit is dropped, and the write fails, so that `write!` stops early; what fit remains available.
+///
+/// # Example
+/// ```
+/// use core::fmt::Write;
+/// use mu_rust_helpers::format::FormatBuf;
+///
+/// let mut buffer = FormatBuf::<32>::new();
+/// write!(buffer, "BAR{} at {:#010x}", 2, 0xfe00_0000u32).unwrap();
+/// assert_eq!(buffer.as_str(), "BAR2 at 0xfe000000");
+/// ```
+#[derive(Clone)]
+pub struct FormatBuf<const N: usize> {
+    buffer: [u8; N],
+    len: usize,
+    is_truncated: bool,
+}
+
+impl<const N: usize> FormatBuf<N> {
+    /// Create an empty buffer.
+    pub const fn new() -> Self {
+        Self { buffer: [0; N], len: 0, is_truncated: false }
+    }
+
+    /// Return the formatted text.
+    pub fn as_str(&self) -> &str {
+        // SAFETY: only whole `str` prefixes ending on a character boundary are copied into the buffer.
+        unsafe { str::from_utf8_unchecked(&self.buffer[..self.len]) }
+    }
+
+    /// Return the length of the formatted text in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Return true if no text was formatted.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Return true if text was dropped because the buffer was full.
+    pub fn is_truncated(&self) -> bool {
+        self.is_truncated
+    }
+
+    /// Discard the formatted text.
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.is_truncated = false;
+    }
+}
+
+impl<const N: usize> Default for FormatBuf<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> fmt::Write for FormatBuf<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let available = N - self.len;
+        let mut len = s.len().min(available);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.buffer[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        if len < s.len() {
+            self.is_truncated = true;
+            return Err(fmt::Error);
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize> fmt::Display for FormatBuf<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl<const N: usize> fmt::Debug for FormatBuf<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+/// Format `args` into `buffer`, returning the formatted text.
+///
+/// Returns `efi::Status::BUFFER_TOO_SMALL` if the text does not fit.
+pub fn format_into<'a>(buffer: &'a mut [u8], args: fmt::Arguments) -> Result<&'a str, efi::Status> {
+    struct SliceWriter<'a> {
+        buffer: &'a mut [u8],
+        len: usize,
+    }
+
+    impl fmt::Write for SliceWriter<'_> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len.checked_add(s.len()).filter(|&end| end <= self.buffer.len()).ok_or(fmt::Error)?;
+            self.buffer[self.len..end].copy_from_slice(s.as_bytes());
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    let mut writer = SliceWriter { buffer, len: 0 };
+    fmt::write(&mut writer, args).map_err(|_| efi::Status::BUFFER_TOO_SMALL)?;
+    let SliceWriter { buffer, len } = writer;
+    // SAFETY: only whole `str`s were copied into the buffer.
+    Ok(unsafe { str::from_utf8_unchecked(&buffer[..len]) })
+}
+
+/// GUID displayed in the registry format used by firmware debug output, e.g.
+/// `8BE4DF61-93CA-11D2-AA0D-00E098032B8C`.
+#[derive(Debug, Clone, Copy)]
+pub struct DisplayGuid<'a>(pub &'a efi::Guid);
+
+impl fmt::Display for DisplayGuid<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (time_low, time_mid, time_hi, clk_seq_hi, clk_seq_low, node) = self.0.as_fields();
+        write!(f, "{time_low:08X}-{time_mid:04X}-{time_hi:04X}-{clk_seq_hi:02X}{clk_seq_low:02X}-")?;
+        node.iter().try_for_each(|byte| write!(f, "{byte:02X}"))
+    }
+}
+
+/// Memory displayed as a hex dump of 16 bytes per line, with offsets and an ASCII pane.
+///
+/// ```text
+/// 00000000  48 65 6c 6c 6f 2c 20 55  45 46 49 21 00 01 02 03  |Hello, UEFI!....|
+/// 00000010  04 05                                             |..|
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct HexDump<'a> {
+    data: &'a [u8],
+    base: u64,
+}
+
+impl<'a> HexDump<'a> {
+    /// Dump `data`, with offsets counted from zero.
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, base: 0 }
+    }
+
+    /// Count offsets from `base`, e.g. the address of the data.
+    pub fn base(mut self, base: u64) -> Self {
+        self.base = base;
+        self
+    }
+}
+
+impl fmt::Display for HexDump<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, line) in self.data.chunks(16).enumerate() {
+            write!(f, "{:08x} ", self.base.wrapping_add(index as u64 * 16))?;
+            for column in 0..16 {
+                if column == 8 {
+                    f.write_str(" ")?;
+                }
+                match line.get(column) {
+                    Some(byte) => write!(f, " {byte:02x}")?,
+                    None => f.write_str("   ")?,
+                }
+            }
+            f.write_str("  |")?;
+            for &byte in line {
+                let c = if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' };
+                fmt::Write::write_char(f, c)?;
+            }
+            f.write_str("|\n")?;
+        }
+        Ok(())
+    }
+}
+
+/// Write a hex dump of `data` to `writer`, see [`HexDump`].
+///
+/// # Example
+/// ```
+/// use mu_rust_helpers::format::{hexdump, FormatBuf};
+///
+/// let mut buffer = FormatBuf::<128>::new();
+/// hexdump(&mut buffer, b"MZ\x90\x00").unwrap();
+/// assert_eq!(
+///     buffer.as_str(),
+///     "00000000  4d 5a 90 00                                       |MZ..|\n"
+/// );
+/// ```
+pub fn hexdump(writer: &mut impl fmt::Write, data: &[u8]) -> fmt::Result {
+    write!(writer, "{}", HexDump::new(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::fmt::Write;
+
+    #[test]
+    fn test_format_buf() {
+        let mut buffer = FormatBuf::<8>::new();
+        assert!(buffer.is_empty());
+        write!(buffer, "{:>4}", 42).unwrap();
+        assert_eq!(buffer.as_str(), "  42");
+        // Multi-byte characters are never split.
+        assert!(buffer.write_str("-\u{e9}\u{e9}").is_err());
+        assert_eq!((buffer.as_str(), buffer.len(), buffer.is_truncated()), ("  42-\u{e9}", 7, true));
+        assert_eq!(format!("{buffer:?}"), "\"  42-\u{e9}\"");
+        buffer.clear();
+        assert!(!buffer.is_truncated() && buffer.is_empty());
+    }
+
+    #[test]
+    fn test_format_into() {
+        let mut buffer = [0u8; 10];
+        assert_eq!(format_into(&mut buffer, format_args!("{:#x}", 0xbeefu16)), Ok("0xbeef"));
+        assert_eq!(format_into(&mut buffer, format_args!("{:#018x}", 1u64)), Err(efi::Status::BUFFER_TOO_SMALL));
+    }
+
+    #[test]
+    fn test_display_guid() {
+        let guid =
+            efi::Guid::from_fields(0x8be4df61, 0x93ca, 0x11d2, 0xaa, 0x0d, &[0x00, 0xe0, 0x98, 0x03, 0x2b, 0x8c]);
+        assert_eq!(DisplayGuid(&guid).to_string(), "8BE4DF61-93CA-11D2-AA0D-00E098032B8C");
+    }
+
+    #[test]
+    fn test_hexdump() {
+        let data: Vec<u8> = b"Hello, UEFI!".iter().copied().chain(0..6).collect();
+        assert_eq!(
+            HexDump::new(&data).base(0xfee0_0000).to_string(),
+            "fee00000  48 65 6c 6c 6f 2c 20 55  45 46 49 21 00 01 02 03  |Hello, UEFI!....|\n\
+             fee00010  04 05                                             |..|\n"
+        );
+        let mut output = String::new();
+        hexdump(&mut output, &[]).unwrap();
+        assert!(output.is_empty());
+    }
+}
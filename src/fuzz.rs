@@ -0,0 +1,219 @@
+//! Fuzzing entry points for host builds, enabled with the `fuzz` feature.
+//!
+//! Every function takes the input of a fuzzer, feeds it to one of the parsers of this crate, and checks the invariants
+//! that hold for any input. They are deterministic and only panic when an invariant is broken, which the fuzzer
+//! reports as a crash. Entry points taking more than a byte slice take an input type implementing `Arbitrary`, which
+//! `cargo fuzz` builds from the raw fuzzer data; the `fuzz` directory of the repository holds one target per entry
+//! point:
+//!
+//! ```ignore
+//! #![no_main]
+//! libfuzzer_sys::fuzz_target!(|input: mu_rust_helpers::fuzz::AmlInput| mu_rust_helpers::fuzz::aml(input));
+//! ```
+//!
+//! The entry points cover the byte parsers this crate has: AML name lookup and patching, user information records,
+//! FAT paths and the little-endian reader.
+//!
+use alloc::format;
+
+use arbitrary::{Arbitrary, Unstructured};
+use r_efi::efi;
+
+use crate::{aml, fat_path, le_cursor::LeReader, user_auth::UserInfoRecord};
+
+/// Input of [`aml`].
+#[derive(Debug, Clone, Copy)]
+pub struct AmlInput<'a> {
+    /// Name path to look up, e.g. `\_SB.FOO_`.
+    pub path: &'a str,
+    /// Definition block, header included.
+    pub table: &'a [u8],
+}
+
+/// Input of [`fat_path`].
+#[derive(Debug, Clone, Copy)]
+pub struct FatPathInput<'a> {
+    /// Path to normalize and match.
+    pub path: &'a str,
+    /// Pattern to match the path against.
+    pub pattern: &'a str,
+}
+
+impl<'a> Arbitrary<'a> for AmlInput<'a> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self { path: u.arbitrary()?, table: u.arbitrary()? })
+    }
+
+    fn arbitrary_take_rest(mut u: Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self { path: u.arbitrary()?, table: u.take_rest() })
+    }
+}
+
+impl<'a> Arbitrary<'a> for FatPathInput<'a> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self { path: u.arbitrary()?, pattern: u.arbitrary()? })
+    }
+}
+
+impl<'a> Arbitrary<'a> for UserInfoRecord {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self {
+            credential: efi::Guid::from_bytes(&u.arbitrary()?),
+            info_type: u.arbitrary()?,
+            attributes: u.arbitrary()?,
+            data: u.arbitrary()?,
+        })
+    }
+}
+
+/// Look up and patch an AML integer.
+pub fn aml(input: AmlInput<'_>) {
+    let Ok(value) = aml::find_name_integer(input.table, input.path) else {
+        return;
+    };
+    // Writing back the value read must succeed and leave it unchanged.
+    let mut patched = input.table.to_vec();
+    assert_eq!(aml::patch_name_integer(&mut patched, input.path, value), Ok(()));
+    assert_eq!(aml::find_name_integer(&patched, input.path), Ok(value));
+}
+
+/// Parse a user information record, which must survive a round trip.
+pub fn user_info_record(data: &[u8]) {
+    let Some(record) = UserInfoRecord::from_bytes(data) else {
+        return;
+    };
+    let bytes = record.to_bytes().expect("a parsed record fits its header");
+    assert_eq!(UserInfoRecord::from_bytes(&bytes), Some(record));
+}
+
+/// Encode a user information record, which must parse back unchanged.
+pub fn user_info_record_encode(record: UserInfoRecord) {
+    let Ok(bytes) = record.to_bytes() else {
+        return;
+    };
+    assert_eq!(UserInfoRecord::from_bytes(&bytes), Some(record));
+}
+
+/// Normalize and match file paths.
+pub fn fat_path(input: FatPathInput<'_>) {
+    let FatPathInput { path, pattern } = input;
+    if let Ok(normalized) = fat_path::normalize(path) {
+        assert_eq!(fat_path::normalize(&normalized).as_ref(), Ok(&normalized));
+        assert!(fat_path::eq_ignore_case(path, &normalized));
+    }
+    fat_path::glob_match(pattern, path);
+    if let Some(short_name) = fat_path::ShortName::new(path) {
+        let _ = format!("{short_name}");
+    }
+}
+
+/// Read little-endian values. Each input byte selects the next read, on the bytes that follow.
+pub fn le_reader(data: &[u8]) {
+    let mut reader = LeReader::new(data);
+    while let Ok(op) = reader.read_u8() {
+        let position = reader.position();
+        let result = match op % 8 {
+            0 => reader.read_u16().map(drop),
+            1 => reader.read_u32().map(drop),
+            2 => reader.read_u64().map(drop),
+            3 => reader.read_guid().map(drop),
+            4 => reader.read_ucs2().map(drop),
+            5 => reader.read_array::<3>().map(drop),
+            6 => reader.skip((op / 8) as usize),
+            _ => reader.read_bytes((op / 8) as usize).map(drop),
+        };
+        // Failed reads leave the cursor unchanged.
+        if result.is_err() {
+            assert_eq!(reader.position(), position);
+        }
+        assert_eq!(reader.position() + reader.remaining().len(), data.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic pseudo-random inputs, with `seeds` and mutations of them.
+    fn corpus(seeds: &[&[u8]]) -> Vec<Vec<u8>> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let mut inputs: Vec<Vec<u8>> = seeds.iter().map(|seed| seed.to_vec()).collect();
+        for _ in 0..500 {
+            let len = (next() % 96) as usize;
+            inputs.push((0..len).map(|_| next() as u8).collect());
+        }
+        for seed in seeds {
+            for len in 0..=seed.len() {
+                inputs.push(seed[..len].to_vec());
+            }
+            for _ in 0..500 {
+                let mut input = seed.to_vec();
+                let index = (next() as usize) % input.len().max(1);
+                if let Some(byte) = input.get_mut(index) {
+                    *byte = next() as u8;
+                }
+                inputs.push(input);
+            }
+        }
+        inputs
+    }
+
+    /// Build an input the way `cargo fuzz` does, from the raw fuzzer data.
+    fn arbitrary_input<'a, T: Arbitrary<'a>>(data: &'a [u8]) -> Option<T> {
+        T::arbitrary_take_rest(Unstructured::new(data)).ok()
+    }
+
+    #[test]
+    fn test_aml() {
+        let name = [&[0x08][..], b"FOO_", &[0x0b, 0x34, 0x12]].concat();
+        let mut table = [b"SSDT".as_slice(), &[0; 32], &name].concat();
+        table[4] = table.len() as u8;
+        for input in corpus(&[&table]) {
+            aml(AmlInput { path: "\\FOO", table: &input });
+            aml(AmlInput { path: "FOO_", table: &input });
+            if let Some(input) = arbitrary_input(&input) {
+                aml(input);
+            }
+        }
+    }
+
+    #[test]
+    fn test_user_info_record() {
+        let record = UserInfoRecord {
+            credential: crate::user_auth::CLASS_PASSWORD_GUID,
+            info_type: 1,
+            attributes: 2,
+            data: [7; 5].into(),
+        };
+        for input in corpus(&[&record.to_bytes().unwrap()]) {
+            user_info_record(&input);
+            if let Some(record) = arbitrary_input(&input) {
+                user_info_record_encode(record);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fat_path() {
+        let seeds = [("\\EFI\\..\\Boot\\bootx64.efi. ", "*\\BOOT\\*.EFI"), ("a/./b/../c", "?/c")];
+        for (path, pattern) in seeds {
+            fat_path(FatPathInput { path, pattern });
+        }
+        for input in corpus(&[b"\\EFI\\..\\Boot\\bootx64.efi. *\\BOOT\\*.EFI", b"a/./b/../c?/c"]) {
+            if let Some(input) = arbitrary_input(&input) {
+                fat_path(input);
+            }
+        }
+    }
+
+    #[test]
+    fn test_le_reader() {
+        corpus(&[&[0xf8, 1, 2, 3, 4, 0x04, b'A', 0, 0, 0, 0x03]]).iter().for_each(|input| le_reader(input));
+    }
+}
@@ -0,0 +1,213 @@
+//! Names of well-known GUIDs, for diagnostics.
+//!
+//! [`guid_name`] maps the GUIDs of the UEFI protocols and configuration tables defined by `r-efi`, those owned by this
+//! crate and a few PI ones to readable names. Platforms add their own GUIDs with [`register`], without allocating.
+//!
+use core::{
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
+};
+
+use r_efi::{efi, hii, protocols, vendor};
+
+/// Table of GUID names registered with [`register`].
+///
+/// # Example
+/// ```
+/// use mu_rust_helpers::guid_name::{guid_name, register, GuidNameTable};
+/// use r_efi::efi;
+///
+/// const PLATFORM_CONFIG_GUID: efi::Guid =
+///     efi::Guid::from_fields(0x6fd7a8b5, 0x31c2, 0x4f0e, 0x8d, 0x4a, &[0x1b, 0x2c, 0x3d, 0x4e, 0x5f, 0x60]);
+/// static PLATFORM_NAMES: GuidNameTable = GuidNameTable::new(&[(PLATFORM_CONFIG_GUID, "PlatformConfig")]);
+///
+/// register(&PLATFORM_NAMES);
+/// assert_eq!(guid_name(&PLATFORM_CONFIG_GUID), Some("PlatformConfig"));
+/// ```
+pub struct GuidNameTable {
+    names: &'static [(efi::Guid, &'static str)],
+    registered: AtomicBool,
+    next: AtomicPtr<GuidNameTable>,
+}
+
+impl GuidNameTable {
+    /// Create a table of `names`.
+    pub const fn new(names: &'static [(efi::Guid, &'static str)]) -> Self {
+        Self { names, registered: AtomicBool::new(false), next: AtomicPtr::new(ptr::null_mut()) }
+    }
+
+    fn find(&self, guid: &efi::Guid) -> Option<&'static str> {
+        self.names.iter().find(|(known, _)| known == guid).map(|&(_, name)| name)
+    }
+}
+
+static REGISTERED: AtomicPtr<GuidNameTable> = AtomicPtr::new(ptr::null_mut());
+
+/// Add the names of `table` to those returned by [`guid_name`].
+///
+/// Registered names take precedence over the built-in ones, and later registrations over earlier ones. Registering a
+/// table again has no effect.
+pub fn register(table: &'static GuidNameTable) {
+    if table.registered.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let table_ptr = table as *const GuidNameTable as *mut GuidNameTable;
+    let mut head = REGISTERED.load(Ordering::SeqCst);
+    loop {
+        table.next.store(head, Ordering::SeqCst);
+        match REGISTERED.compare_exchange(head, table_ptr, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_) => return,
+            Err(current) => head = current,
+        }
+    }
+}
+
+/// Return the name of `guid`, if it is known.
+pub fn guid_name(guid: &efi::Guid) -> Option<&'static str> {
+    let mut table = REGISTERED.load(Ordering::SeqCst);
+    // SAFETY: only `'static` tables are linked in the list, and links are never removed.
+    while let Some(current) = unsafe { table.as_ref() } {
+        if let Some(name) = current.find(guid) {
+            return Some(name);
+        }
+        table = current.next.load(Ordering::SeqCst);
+    }
+    KNOWN_GUIDS.find(guid)
+}
+
+const fn guid(time_low: u32, time_mid: u16, time_hi: u16, clk_seq_hi: u8, clk_seq_low: u8, node: [u8; 6]) -> efi::Guid {
+    efi::Guid::from_fields(time_low, time_mid, time_hi, clk_seq_hi, clk_seq_low, &node)
+}
+
+static KNOWN_GUIDS: GuidNameTable = GuidNameTable::new(&[
+    // UEFI protocols.
+    (protocols::absolute_pointer::PROTOCOL_GUID, "AbsolutePointer"),
+    (protocols::block_io::PROTOCOL_GUID, "BlockIo"),
+    (protocols::bus_specific_driver_override::PROTOCOL_GUID, "BusSpecificDriverOverride"),
+    (protocols::debug_support::PROTOCOL_GUID, "DebugSupport"),
+    (protocols::debugport::PROTOCOL_GUID, "DebugPort"),
+    (protocols::decompress::PROTOCOL_GUID, "Decompress"),
+    (protocols::device_path::PROTOCOL_GUID, "DevicePath"),
+    (protocols::device_path_from_text::PROTOCOL_GUID, "DevicePathFromText"),
+    (protocols::device_path_to_text::PROTOCOL_GUID, "DevicePathToText"),
+    (protocols::device_path_utilities::PROTOCOL_GUID, "DevicePathUtilities"),
+    (protocols::disk_io::PROTOCOL_GUID, "DiskIo"),
+    (protocols::disk_io2::PROTOCOL_GUID, "DiskIo2"),
+    (protocols::driver_binding::PROTOCOL_GUID, "DriverBinding"),
+    (protocols::driver_diagnostics2::PROTOCOL_GUID, "DriverDiagnostics2"),
+    (protocols::driver_family_override::PROTOCOL_GUID, "DriverFamilyOverride"),
+    (protocols::graphics_output::PROTOCOL_GUID, "GraphicsOutput"),
+    (protocols::hii_database::PROTOCOL_GUID, "HiiDatabase"),
+    (protocols::hii_font::PROTOCOL_GUID, "HiiFont"),
+    (protocols::hii_font_ex::PROTOCOL_GUID, "HiiFontEx"),
+    (protocols::hii_package_list::PROTOCOL_GUID, "HiiPackageList"),
+    (protocols::hii_string::PROTOCOL_GUID, "HiiString"),
+    (protocols::ip4::PROTOCOL_GUID, "Ip4"),
+    (protocols::ip4::SERVICE_BINDING_PROTOCOL_GUID, "Ip4ServiceBinding"),
+    (protocols::ip6::PROTOCOL_GUID, "Ip6"),
+    (protocols::ip6::SERVICE_BINDING_PROTOCOL_GUID, "Ip6ServiceBinding"),
+    (protocols::load_file::PROTOCOL_GUID, "LoadFile"),
+    (protocols::load_file2::PROTOCOL_GUID, "LoadFile2"),
+    (protocols::loaded_image::PROTOCOL_GUID, "LoadedImage"),
+    (protocols::loaded_image_device_path::PROTOCOL_GUID, "LoadedImageDevicePath"),
+    (protocols::managed_network::PROTOCOL_GUID, "ManagedNetwork"),
+    (protocols::managed_network::SERVICE_BINDING_PROTOCOL_GUID, "ManagedNetworkServiceBinding"),
+    (protocols::memory_attribute::PROTOCOL_GUID, "MemoryAttribute"),
+    (protocols::mp_services::PROTOCOL_GUID, "MpServices"),
+    (protocols::pci_io::PROTOCOL_GUID, "PciIo"),
+    (protocols::platform_driver_override::PROTOCOL_GUID, "PlatformDriverOverride"),
+    (protocols::rng::PROTOCOL_GUID, "Rng"),
+    (protocols::shell::PROTOCOL_GUID, "Shell"),
+    (protocols::shell_dynamic_command::PROTOCOL_GUID, "ShellDynamicCommand"),
+    (protocols::shell_parameters::PROTOCOL_GUID, "ShellParameters"),
+    (protocols::simple_file_system::PROTOCOL_GUID, "SimpleFileSystem"),
+    (protocols::simple_network::PROTOCOL_GUID, "SimpleNetwork"),
+    (protocols::simple_text_input::PROTOCOL_GUID, "SimpleTextInput"),
+    (protocols::simple_text_input_ex::PROTOCOL_GUID, "SimpleTextInputEx"),
+    (protocols::simple_text_output::PROTOCOL_GUID, "SimpleTextOutput"),
+    (protocols::tcp4::PROTOCOL_GUID, "Tcp4"),
+    (protocols::tcp4::SERVICE_BINDING_PROTOCOL_GUID, "Tcp4ServiceBinding"),
+    (protocols::tcp6::PROTOCOL_GUID, "Tcp6"),
+    (protocols::tcp6::SERVICE_BINDING_PROTOCOL_GUID, "Tcp6ServiceBinding"),
+    (protocols::timestamp::PROTOCOL_GUID, "Timestamp"),
+    (protocols::udp4::PROTOCOL_GUID, "Udp4"),
+    (protocols::udp4::SERVICE_BINDING_PROTOCOL_GUID, "Udp4ServiceBinding"),
+    (protocols::udp6::PROTOCOL_GUID, "Udp6"),
+    (protocols::udp6::SERVICE_BINDING_PROTOCOL_GUID, "Udp6ServiceBinding"),
+    (vendor::intel::console_control::PROTOCOL_GUID, "ConsoleControl"),
+    (crate::acpi_sdt::PROTOCOL_GUID, "AcpiSdt"),
+    (crate::block_io2::PROTOCOL_GUID, "BlockIo2"),
+    (crate::deferred_image::PROTOCOL_GUID, "DeferredImageLoad"),
+    (crate::driver_health::PROTOCOL_GUID, "DriverHealth"),
+    (crate::kms::PROTOCOL_GUID, "Kms"),
+    (crate::user_auth::CREDENTIAL2_PROTOCOL_GUID, "UserCredential2"),
+    (crate::user_auth::MANAGER_PROTOCOL_GUID, "UserManager"),
+    // Configuration tables.
+    (efi::ACPI_10_TABLE_GUID, "Acpi10Table"),
+    (efi::ACPI_20_TABLE_GUID, "Acpi20Table"),
+    (efi::CONFORMANCE_PROFILES_TABLE_GUID, "ConformanceProfilesTable"),
+    (efi::DTB_TABLE_GUID, "DtbTable"),
+    (efi::JSON_CAPSULE_DATA_TABLE_GUID, "JsonCapsuleDataTable"),
+    (efi::JSON_CAPSULE_RESULT_TABLE_GUID, "JsonCapsuleResultTable"),
+    (efi::JSON_CONFIG_DATA_TABLE_GUID, "JsonConfigDataTable"),
+    (efi::MEMORY_ATTRIBUTES_TABLE_GUID, "MemoryAttributesTable"),
+    (efi::MPS_TABLE_GUID, "MpsTable"),
+    (efi::PROPERTIES_TABLE_GUID, "PropertiesTable"),
+    (efi::RT_PROPERTIES_TABLE_GUID, "RtPropertiesTable"),
+    (efi::SAL_SYSTEM_TABLE_GUID, "SalSystemTable"),
+    (efi::SMBIOS_TABLE_GUID, "SmbiosTable"),
+    (efi::SMBIOS3_TABLE_GUID, "Smbios3Table"),
+    (crate::config_table::ESRT_TABLE_GUID, "EsrtTable"),
+    (crate::interop_registry::INTEROP_REGISTRY_GUID, "InteropRegistry"),
+    (crate::preserved_region::PRESERVED_REGION_GUID, "PreservedRegion"),
+    (guid(0x05ad34ba, 0x6f02, 0x4214, 0x95, 0x2e, [0x4d, 0xa0, 0x39, 0x8e, 0x2b, 0xb9]), "DxeServicesTable"),
+    (guid(0x7739f24c, 0x93d7, 0x11d4, 0x9a, 0x3a, [0x00, 0x90, 0x27, 0x3f, 0xc1, 0x4d]), "HobList"),
+    (guid(0x49152e77, 0x1ada, 0x4764, 0xb7, 0xa2, [0x7a, 0xfe, 0xfe, 0xd9, 0x5e, 0x8b]), "DebugImageInfoTable"),
+    // Variable namespaces and other GUIDs.
+    (guid(0x8be4df61, 0x93ca, 0x11d2, 0xaa, 0x0d, [0x00, 0xe0, 0x98, 0x03, 0x2b, 0x8c]), "GlobalVariable"),
+    (guid(0xd719b2cb, 0x3d3a, 0x4596, 0xa3, 0xbc, [0xda, 0xd0, 0x0e, 0x67, 0x65, 0x6f]), "ImageSecurityDatabase"),
+    (efi::HARDWARE_ERROR_VARIABLE_GUID, "HardwareErrorVariable"),
+    (efi::CAPSULE_REPORT_GUID, "CapsuleReport"),
+    (efi::CONFORMANCE_PROFILES_UEFI_SPEC_GUID, "ConformanceProfilesUefiSpec"),
+    (hii::STANDARD_FORM_GUID, "HiiStandardForm"),
+    (protocols::hii_database::SET_KEYBOARD_LAYOUT_EVENT_GUID, "SetKeyboardLayoutEvent"),
+    (protocols::rng::ALGORITHM_SP800_90_HASH_256_GUID, "RngAlgorithmSp80090Hash256"),
+    (protocols::rng::ALGORITHM_SP800_90_HMAC_256_GUID, "RngAlgorithmSp80090Hmac256"),
+    (protocols::rng::ALGORITHM_SP800_90_CTR_256_GUID, "RngAlgorithmSp80090Ctr256"),
+    (protocols::rng::ALGORITHM_X9_31_3DES_GUID, "RngAlgorithmX9313Des"),
+    (protocols::rng::ALGORITHM_X9_31_AES_GUID, "RngAlgorithmX931Aes"),
+]);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLATFORM_GUID: efi::Guid = guid(0x1c0ffee1, 0x2222, 0x4333, 0x84, 0x44, [0x55; 6]);
+
+    static FIRST: GuidNameTable = GuidNameTable::new(&[(PLATFORM_GUID, "First")]);
+    static SECOND: GuidNameTable =
+        GuidNameTable::new(&[(PLATFORM_GUID, "Second"), (efi::SMBIOS3_TABLE_GUID, "PlatformSmbios")]);
+
+    #[test]
+    fn test_guid_name() {
+        assert_eq!(guid_name(&protocols::loaded_image::PROTOCOL_GUID), Some("LoadedImage"));
+        assert_eq!(guid_name(&efi::ACPI_20_TABLE_GUID), Some("Acpi20Table"));
+        assert_eq!(guid_name(&guid(0, 0, 0, 0, 0, [0; 6])), None);
+
+        let names = KNOWN_GUIDS.names;
+        for (index, (guid, name)) in names.iter().enumerate() {
+            assert!(names[..index].iter().all(|(other, _)| other != guid), "{name} is listed twice");
+        }
+    }
+
+    #[test]
+    fn test_register() {
+        register(&FIRST);
+        assert_eq!(guid_name(&PLATFORM_GUID), Some("First"));
+        register(&SECOND);
+        register(&FIRST);
+        assert_eq!(guid_name(&PLATFORM_GUID), Some("Second"));
+        assert_eq!(guid_name(&efi::SMBIOS3_TABLE_GUID), Some("PlatformSmbios"));
+        assert_eq!(guid_name(&efi::SMBIOS_TABLE_GUID), Some("SmbiosTable"));
+    }
+}
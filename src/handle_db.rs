@@ -0,0 +1,299 @@
+//! Handle database dump.
+//!
+//! [`HandleDatabase::snapshot`] records every handle with the protocols installed on it, and optionally who has each
+//! protocol open. The snapshot renders through `Display` with protocols named by
+//! [`guid_name`](crate::guid_name::guid_name), which is usually the quickest way to see why a driver did not bind to
+//! a controller.
+//!
+use alloc::vec::Vec;
+use core::{ffi::c_void, fmt, ptr, slice};
+
+use r_efi::efi;
+
+use crate::{
+    format::DisplayGuid,
+    guid_name::guid_name,
+    handles::{locate_handles, HandleSearch},
+    open_protocol::OpenProtocolAttributes,
+};
+
+/// Record of an opening of a protocol, as returned by OpenProtocolInformation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenInfo {
+    /// Image that opened the protocol.
+    pub agent_handle: efi::Handle,
+    /// Controller the protocol was opened for, null if it was not opened by a driver.
+    pub controller_handle: efi::Handle,
+    /// `efi::OPEN_PROTOCOL_*` attributes of the opening, as reported by the firmware. See [`OpenProtocolAttributes`].
+    pub attributes: u32,
+    /// Number of times the protocol was opened with these handles and attributes.
+    pub open_count: u32,
+}
+
+/// Protocol installed on a handle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolEntry {
+    /// GUID of the protocol.
+    pub guid: efi::Guid,
+    /// Openings of the protocol, if they were requested in the snapshot.
+    pub open_info: Option<Vec<OpenInfo>>,
+}
+
+/// Handle of the database with its protocols.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandleEntry {
+    /// The handle.
+    pub handle: efi::Handle,
+    /// Protocols installed on the handle, in the order returned by ProtocolsPerHandle.
+    pub protocols: Vec<ProtocolEntry>,
+}
+
+/// Snapshot of the handle database.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::handle_db::HandleDatabase;
+/// use r_efi::efi;
+///
+/// fn dump(boot_services: &efi::BootServices) -> Result<(), efi::Status> {
+///     let database = HandleDatabase::snapshot_with_open_info(boot_services)?;
+///     assert!(!database.to_string().is_empty());
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandleDatabase {
+    handles: Vec<HandleEntry>,
+}
+
+impl HandleDatabase {
+    /// Record every handle with the protocols installed on it.
+    pub fn snapshot(boot_services: &efi::BootServices) -> Result<Self, efi::Status> {
+        Self::take(boot_services, false)
+    }
+
+    /// Record every handle with the protocols installed on it and their openings.
+    pub fn snapshot_with_open_info(boot_services: &efi::BootServices) -> Result<Self, efi::Status> {
+        Self::take(boot_services, true)
+    }
+
+    fn take(boot_services: &efi::BootServices, open_info: bool) -> Result<Self, efi::Status> {
+        let handles = locate_handles(boot_services, HandleSearch::AllHandles)?;
+        let handles = handles
+            .into_iter()
+            .map(|handle| {
+                let protocols = protocols_per_handle(boot_services, handle)?
+                    .into_iter()
+                    .map(|guid| {
+                        let open_info = open_info.then(|| open_protocol_information(boot_services, handle, guid));
+                        Ok(ProtocolEntry { guid, open_info: open_info.transpose()? })
+                    })
+                    .collect::<Result<_, efi::Status>>()?;
+                Ok(HandleEntry { handle, protocols })
+            })
+            .collect::<Result<_, efi::Status>>()?;
+        Ok(Self { handles })
+    }
+
+    /// Return the recorded handles.
+    pub fn handles(&self) -> &[HandleEntry] {
+        &self.handles
+    }
+
+    /// Return the handles on which `protocol` is installed.
+    pub fn handles_with(&self, protocol: &efi::Guid) -> impl Iterator<Item = &HandleEntry> + '_ {
+        let protocol = *protocol;
+        self.handles.iter().filter(move |entry| entry.protocols.iter().any(|p| p.guid == protocol))
+    }
+}
+
+impl fmt::Display for HandleDatabase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in &self.handles {
+            writeln!(f, "Handle {:p}", entry.handle)?;
+            for protocol in &entry.protocols {
+                match guid_name(&protocol.guid) {
+                    Some(name) => writeln!(f, "  {name}")?,
+                    None => writeln!(f, "  {}", DisplayGuid(&protocol.guid))?,
+                }
+                for open in protocol.open_info.iter().flatten() {
+                    write!(f, "    opened by {:p}", open.agent_handle)?;
+                    if !open.controller_handle.is_null() {
+                        write!(f, " for {:p}", open.controller_handle)?;
+                    }
+                    match OpenProtocolAttributes::new(open.attributes) {
+                        Some(attributes) => write!(f, " {attributes}")?,
+                        None => write!(f, " {:#x}", open.attributes)?,
+                    }
+                    writeln!(f, " x{}", open.open_count)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn protocols_per_handle(boot_services: &efi::BootServices, handle: efi::Handle) -> Result<Vec<efi::Guid>, efi::Status> {
+    let mut buffer = ptr::null_mut();
+    let mut count = 0;
+    let status = (boot_services.protocols_per_handle)(handle, &mut buffer, &mut count);
+    if status.is_error() {
+        return Err(status);
+    }
+    if buffer.is_null() {
+        return Ok(Vec::new());
+    }
+    // SAFETY: ProtocolsPerHandle returns `count` protocol GUID pointers in a pool buffer owned by the caller.
+    let guids = unsafe { slice::from_raw_parts(buffer, count).iter().map(|&guid| *guid).collect() };
+    (boot_services.free_pool)(buffer as *mut c_void);
+    Ok(guids)
+}
+
+fn open_protocol_information(
+    boot_services: &efi::BootServices,
+    handle: efi::Handle,
+    mut protocol: efi::Guid,
+) -> Result<Vec<OpenInfo>, efi::Status> {
+    let mut buffer = ptr::null_mut();
+    let mut count = 0;
+    let status = (boot_services.open_protocol_information)(handle, &mut protocol, &mut buffer, &mut count);
+    if status.is_error() {
+        return Err(status);
+    }
+    if buffer.is_null() {
+        return Ok(Vec::new());
+    }
+    // SAFETY: OpenProtocolInformation returns `count` entries in a pool buffer owned by the caller.
+    let entries = unsafe { slice::from_raw_parts(buffer, count) }
+        .iter()
+        .map(|entry| OpenInfo {
+            agent_handle: entry.agent_handle,
+            controller_handle: entry.controller_handle,
+            attributes: entry.attributes,
+            open_count: entry.open_count,
+        })
+        .collect();
+    (boot_services.free_pool)(buffer as *mut c_void);
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use r_efi::protocols;
+    use std::{
+        alloc::{alloc, dealloc, Layout},
+        boxed::Box,
+        cell::Cell,
+        mem,
+        string::ToString,
+    };
+
+    use crate::{
+        handles::tests::{locate_handle, TEST_PROTOCOL},
+        test_support::mock_efi_boot_services,
+    };
+
+    std::thread_local! {
+        static LIVE_POOLS: Cell<isize> = const { Cell::new(0) };
+    }
+
+    fn pool<T: Copy>(values: &[T]) -> *mut T {
+        let buffer = unsafe { alloc(Layout::from_size_align(8 + mem::size_of_val(values), 8).unwrap()) as *mut usize };
+        unsafe {
+            buffer.write(mem::size_of_val(values));
+            let data = buffer.add(1) as *mut T;
+            data.copy_from_nonoverlapping(values.as_ptr(), values.len());
+            LIVE_POOLS.with(|pools| pools.set(pools.get() + 1));
+            data
+        }
+    }
+
+    extern "efiapi" fn free_pool(buffer: *mut c_void) -> efi::Status {
+        unsafe {
+            let allocation = (buffer as *mut usize).sub(1);
+            dealloc(allocation as *mut u8, Layout::from_size_align(allocation.read() + 8, 8).unwrap());
+        }
+        LIVE_POOLS.with(|pools| pools.set(pools.get() - 1));
+        efi::Status::SUCCESS
+    }
+
+    // Matches the mock handle database of `handles`: handles 2 and 4 also support `TEST_PROTOCOL`.
+    extern "efiapi" fn protocols_per_handle(
+        handle: efi::Handle,
+        buffer: *mut *mut *mut efi::Guid,
+        count: *mut usize,
+    ) -> efi::Status {
+        let loaded_image: &'static mut efi::Guid = Box::leak(Box::new(protocols::loaded_image::PROTOCOL_GUID));
+        let test_protocol: &'static mut efi::Guid = Box::leak(Box::new(TEST_PROTOCOL));
+        let guids: Vec<*mut efi::Guid> = match handle as usize {
+            2 | 4 => vec![loaded_image, test_protocol],
+            _ => vec![loaded_image],
+        };
+        unsafe {
+            *buffer = pool(&guids);
+            *count = guids.len();
+        }
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn open_protocol_information(
+        handle: efi::Handle,
+        protocol: *mut efi::Guid,
+        buffer: *mut *mut efi::OpenProtocolInformationEntry,
+        count: *mut usize,
+    ) -> efi::Status {
+        let entries: Vec<_> = if handle as usize == 2 && unsafe { *protocol } == TEST_PROTOCOL {
+            vec![efi::OpenProtocolInformationEntry {
+                agent_handle: 0x8 as efi::Handle,
+                controller_handle: 0x2 as efi::Handle,
+                attributes: efi::OPEN_PROTOCOL_BY_DRIVER | efi::OPEN_PROTOCOL_EXCLUSIVE,
+                open_count: 1,
+            }]
+        } else {
+            Vec::new()
+        };
+        unsafe {
+            *buffer = if entries.is_empty() { ptr::null_mut() } else { pool(&entries) };
+            *count = entries.len();
+        }
+        efi::Status::SUCCESS
+    }
+
+    fn boot_services() -> efi::BootServices {
+        efi::BootServices {
+            locate_handle,
+            protocols_per_handle,
+            open_protocol_information,
+            free_pool,
+            ..mock_efi_boot_services()
+        }
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let boot_services = boot_services();
+        let database = HandleDatabase::snapshot(&boot_services).unwrap();
+        assert_eq!(database.handles().len(), 4);
+        assert!(database.handles().iter().flat_map(|entry| &entry.protocols).all(|p| p.open_info.is_none()));
+        let with_test: Vec<_> = database.handles_with(&TEST_PROTOCOL).map(|entry| entry.handle as usize).collect();
+        assert_eq!(with_test, [2, 4]);
+        assert_eq!(LIVE_POOLS.with(|pools| pools.get()), 0);
+    }
+
+    #[test]
+    fn test_display() {
+        let boot_services = boot_services();
+        let database = HandleDatabase::snapshot_with_open_info(&boot_services).unwrap();
+        let dump = database.to_string();
+        let handle_2 = format!("Handle {:p}\n", 2 as efi::Handle);
+        let expected = format!(
+            "{handle_2}  LoadedImage\n  0B6E5233-A65C-44C9-9407-D9AB83BFC8BD\n    opened by {:p} for {:p} BY_DRIVER|EXCLUSIVE x1\n",
+            0x8 as efi::Handle, 0x2 as efi::Handle
+        );
+        assert!(dump.contains(&expected), "{dump}");
+        assert_eq!(dump.lines().filter(|line| line.starts_with("Handle")).count(), 4);
+        assert_eq!(LIVE_POOLS.with(|pools| pools.get()), 0);
+    }
+}
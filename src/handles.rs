@@ -0,0 +1,246 @@
+//! Handle database queries.
+//!
+//! [`iter_handles`] yields the handles matching a search as a [`HandleIter`], calling LocateHandle as it is advanced.
+//! [`locate_handles`] collects them into a `Vec`. Buffers are sized with
+//! [`get_with_growing_buffer`](crate::buffer::get_with_growing_buffer) so handles installed between the calls are not
+//! missed. A search matching no handle yields no handle rather than `efi::Status::NOT_FOUND`.
+//!
+use alloc::vec::{self, Vec};
+use core::{ffi::c_void, iter::FusedIterator, mem, ptr};
+
+use r_efi::efi;
+
+use crate::buffer::get_with_growing_buffer;
+
+/// Handle search of [`iter_handles`] and [`locate_handles`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandleSearch<'a> {
+    /// Every handle in the handle database.
+    AllHandles,
+    /// The handles supporting a protocol.
+    ByProtocol(&'a efi::Guid),
+    /// The handles on which a protocol was installed since the last search with the registration key returned by
+    /// RegisterProtocolNotify.
+    ByRegisterNotify(*mut c_void),
+}
+
+/// Iterator over the handles matching a [`HandleSearch`], returned by [`iter_handles`].
+///
+/// Nothing is searched until the iterator is first advanced. `AllHandles` and `ByProtocol` searches then read every
+/// matching handle at once, while LocateHandle returns a single handle per `ByRegisterNotify` search, so that search is
+/// repeated on every call to `next` until it reports `efi::Status::NOT_FOUND`. The iterator ends after the last handle
+/// or the first error.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::handles::{iter_handles, HandleSearch};
+/// use r_efi::{efi, protocols::block_io};
+///
+/// fn first_block_device(boot_services: &efi::BootServices) -> Result<Option<efi::Handle>, efi::Status> {
+///     iter_handles(boot_services, HandleSearch::ByProtocol(&block_io::PROTOCOL_GUID)).next().transpose()
+/// }
+/// ```
+pub struct HandleIter<'a> {
+    boot_services: &'a efi::BootServices,
+    search: HandleSearch<'a>,
+    buffered: Option<vec::IntoIter<efi::Handle>>,
+    done: bool,
+}
+
+impl Iterator for HandleIter<'_> {
+    type Item = Result<efi::Handle, efi::Status>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let next = match self.search {
+            HandleSearch::AllHandles => self.next_buffered(efi::ALL_HANDLES, None),
+            HandleSearch::ByProtocol(protocol) => self.next_buffered(efi::BY_PROTOCOL, Some(*protocol)),
+            HandleSearch::ByRegisterNotify(key) => registered_handle(self.boot_services, key).transpose(),
+        };
+        self.done = !matches!(next, Some(Ok(_)));
+        next
+    }
+}
+
+impl FusedIterator for HandleIter<'_> {}
+
+impl HandleIter<'_> {
+    fn next_buffered(
+        &mut self,
+        search_type: efi::LocateSearchType,
+        protocol: Option<efi::Guid>,
+    ) -> Option<Result<efi::Handle, efi::Status>> {
+        let handles = match self.buffered.as_mut() {
+            Some(handles) => handles,
+            None => match buffered_handles(self.boot_services, search_type, protocol) {
+                Ok(handles) => self.buffered.insert(handles.into_iter()),
+                Err(status) => return Some(Err(status)),
+            },
+        };
+        handles.next().map(Ok)
+    }
+}
+
+/// Return an iterator over the handles matching `search`.
+pub fn iter_handles<'a>(boot_services: &'a efi::BootServices, search: HandleSearch<'a>) -> HandleIter<'a> {
+    HandleIter { boot_services, search, buffered: None, done: false }
+}
+
+/// Return the handles matching `search`, or an empty list if no handle matches.
+///
+/// Every handle installed since the last `ByRegisterNotify` search is returned, as with [`iter_handles`].
+pub fn locate_handles(
+    boot_services: &efi::BootServices,
+    search: HandleSearch,
+) -> Result<Vec<efi::Handle>, efi::Status> {
+    iter_handles(boot_services, search).collect()
+}
+
+fn buffered_handles(
+    boot_services: &efi::BootServices,
+    search_type: efi::LocateSearchType,
+    mut protocol: Option<efi::Guid>,
+) -> Result<Vec<efi::Handle>, efi::Status> {
+    let protocol = protocol.as_mut().map_or(ptr::null_mut(), |protocol| protocol as *mut efi::Guid);
+    let handles = get_with_growing_buffer(ptr::null_mut(), |buffer: &mut [efi::Handle]| {
+        let mut size = mem::size_of_val(buffer);
+        let status =
+            (boot_services.locate_handle)(search_type, protocol, ptr::null_mut(), &mut size, buffer.as_mut_ptr());
+        let len = size / mem::size_of::<efi::Handle>();
+        if status.is_error() {
+            return Err((status, len));
+        }
+        Ok(len)
+    });
+    match handles {
+        Err(efi::Status::NOT_FOUND) => Ok(Vec::new()),
+        handles => handles,
+    }
+}
+
+fn registered_handle(boot_services: &efi::BootServices, key: *mut c_void) -> Result<Option<efi::Handle>, efi::Status> {
+    let mut handle = ptr::null_mut();
+    let mut size = mem::size_of::<efi::Handle>();
+    match (boot_services.locate_handle)(efi::BY_REGISTER_NOTIFY, ptr::null_mut(), key, &mut size, &mut handle) {
+        efi::Status::NOT_FOUND => Ok(None),
+        status if status.is_error() => Err(status),
+        _ => Ok(Some(handle)),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use core::cell::RefCell;
+
+    use crate::test_support::mock_efi_boot_services;
+
+    pub(crate) const TEST_PROTOCOL: efi::Guid =
+        efi::Guid::from_fields(0x0b6e5233, 0xa65c, 0x44c9, 0x94, 0x07, &[0xd9, 0xab, 0x83, 0xbf, 0xc8, 0xbd]);
+
+    /// Mock handle database: handles 1 to 4, of which 2 and 4 support `TEST_PROTOCOL`.
+    pub(crate) extern "efiapi" fn locate_handle(
+        search_type: efi::LocateSearchType,
+        protocol: *mut efi::Guid,
+        _key: *mut c_void,
+        size: *mut usize,
+        buffer: *mut efi::Handle,
+    ) -> efi::Status {
+        let handles: &[usize] = match search_type {
+            efi::ALL_HANDLES => &[1, 2, 3, 4],
+            efi::BY_PROTOCOL if unsafe { *protocol } == TEST_PROTOCOL => &[2, 4],
+            _ => return efi::Status::NOT_FOUND,
+        };
+        let required = mem::size_of_val(handles);
+        unsafe {
+            if *size < required {
+                *size = required;
+                return efi::Status::BUFFER_TOO_SMALL;
+            }
+            *size = required;
+            for (index, &handle) in handles.iter().enumerate() {
+                *buffer.add(index) = handle as efi::Handle;
+            }
+        }
+        efi::Status::SUCCESS
+    }
+
+    #[test]
+    fn test_locate_handles() {
+        let boot_services = efi::BootServices { locate_handle, ..mock_efi_boot_services() };
+        let handles = |search| {
+            locate_handles(&boot_services, search)
+                .map(|handles| handles.iter().map(|&h| h as usize).collect::<Vec<_>>())
+        };
+        assert_eq!(handles(HandleSearch::AllHandles), Ok(vec![1, 2, 3, 4]));
+        assert_eq!(handles(HandleSearch::ByProtocol(&TEST_PROTOCOL)), Ok(vec![2, 4]));
+        assert_eq!(handles(HandleSearch::ByProtocol(&efi::Guid::from_bytes(&[0; 16]))), Ok(vec![]));
+    }
+
+    std::thread_local! {
+        static REGISTERED: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// Return the handles of `REGISTERED` one per call, as LocateHandle does for `ByRegisterNotify` searches.
+    extern "efiapi" fn locate_registered_handle(
+        search_type: efi::LocateSearchType,
+        _protocol: *mut efi::Guid,
+        key: *mut c_void,
+        size: *mut usize,
+        buffer: *mut efi::Handle,
+    ) -> efi::Status {
+        assert_eq!((search_type, key as usize), (efi::BY_REGISTER_NOTIFY, 0x6e7));
+        assert_eq!(unsafe { *size }, mem::size_of::<efi::Handle>());
+        if REGISTERED.with(|registered| registered.borrow().is_empty()) {
+            return efi::Status::NOT_FOUND;
+        }
+        unsafe { *buffer = REGISTERED.with(|registered| registered.borrow_mut().remove(0)) as efi::Handle };
+        efi::Status::SUCCESS
+    }
+
+    #[test]
+    fn test_locate_registered_handles() {
+        let boot_services = efi::BootServices { locate_handle: locate_registered_handle, ..mock_efi_boot_services() };
+        let search = HandleSearch::ByRegisterNotify(0x6e7 as *mut c_void);
+        REGISTERED.with(|registered| registered.borrow_mut().extend([5, 6, 7]));
+        let handles = locate_handles(&boot_services, search).unwrap();
+        assert_eq!(handles, [5 as efi::Handle, 6 as efi::Handle, 7 as efi::Handle]);
+        assert_eq!(locate_handles(&boot_services, search), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_iter_registered_handles() {
+        let boot_services = efi::BootServices { locate_handle: locate_registered_handle, ..mock_efi_boot_services() };
+        let search = HandleSearch::ByRegisterNotify(0x6e7 as *mut c_void);
+        REGISTERED.with(|registered| registered.borrow_mut().push(5));
+        let mut handles = iter_handles(&boot_services, search);
+        assert_eq!(handles.next(), Some(Ok(5 as efi::Handle)));
+        // Handles installed while iterating are yielded too.
+        REGISTERED.with(|registered| registered.borrow_mut().push(6));
+        assert_eq!(handles.next(), Some(Ok(6 as efi::Handle)));
+        assert_eq!(handles.next(), None);
+        // The iterator is fused once the search reported NOT_FOUND.
+        REGISTERED.with(|registered| registered.borrow_mut().push(7));
+        assert_eq!(handles.next(), None);
+        REGISTERED.with(|registered| registered.borrow_mut().clear());
+    }
+
+    #[test]
+    fn test_iter_handles_error() {
+        let boot_services = mock_efi_boot_services();
+        let mut handles = iter_handles(&boot_services, HandleSearch::ByProtocol(&TEST_PROTOCOL));
+        assert_eq!(handles.next(), Some(Err(efi::Status::UNSUPPORTED)));
+        assert_eq!(handles.next(), None);
+    }
+
+    #[test]
+    fn test_locate_handles_error() {
+        let boot_services = mock_efi_boot_services();
+        assert_eq!(locate_handles(&boot_services, HandleSearch::AllHandles), Err(efi::Status::UNSUPPORTED));
+        let search = HandleSearch::ByRegisterNotify(0x6e7 as *mut c_void);
+        assert_eq!(locate_handles(&boot_services, search), Err(efi::Status::UNSUPPORTED));
+    }
+}
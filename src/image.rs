@@ -0,0 +1,245 @@
+//! Image exit data.
+//!
+//! An image exiting with an error may return a null-terminated UCS-2 description, optionally followed by binary data,
+//! in a buffer allocated from pool that its caller frees. [`exit_with_message`] builds that buffer from a `&str`, and
+//! [`start_image_decoded`] turns the buffer returned by StartImage back into a [`String`].
+//!
+use alloc::{string::String, vec::Vec};
+use core::{ffi::c_void, fmt, mem, ptr, slice};
+
+use r_efi::efi;
+
+use crate::ucs2;
+
+/// Exit the image `image_handle` with `status`, passing `message` as exit data if `status` is an error.
+///
+/// Characters that cannot be represented in UCS-2 are replaced with `char::REPLACEMENT_CHARACTER`, and the message is
+/// cut at the first null character. If the exit data cannot be allocated, the image exits without it.
+///
+/// Only returns if Exit fails, with its status.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::image::exit_with_message;
+/// use r_efi::efi;
+///
+/// fn abort(boot_services: &efi::BootServices, image_handle: efi::Handle) -> efi::Status {
+///     exit_with_message(boot_services, image_handle, efi::Status::NOT_FOUND, "No bootable device found.")
+/// }
+/// ```
+pub fn exit_with_message(
+    boot_services: &efi::BootServices,
+    image_handle: efi::Handle,
+    status: efi::Status,
+    message: &str,
+) -> efi::Status {
+    // Exit data is only defined for errors.
+    let exit_data = if status.is_error() { allocate_exit_data(boot_services, message) } else { None };
+    let (data_size, data) = exit_data.unwrap_or((0, ptr::null_mut()));
+    let exit_status = (boot_services.exit)(image_handle, status, data_size, data);
+    // The image is still running, so the exit data was not handed over.
+    if !data.is_null() {
+        (boot_services.free_pool)(data as *mut c_void);
+    }
+    exit_status
+}
+
+fn allocate_exit_data(boot_services: &efi::BootServices, message: &str) -> Option<(usize, *mut u16)> {
+    let message = message.split('\0').next().unwrap_or_default();
+    let len = message.chars().count() + 1;
+    let size = len * mem::size_of::<u16>();
+    let mut buffer = ptr::null_mut();
+    if (boot_services.allocate_pool)(efi::BOOT_SERVICES_DATA, size, &mut buffer).is_error() || buffer.is_null() {
+        return None;
+    }
+
+    let data = buffer as *mut u16;
+    let ucs2 = message.chars().map(|c| u16::try_from(c as u32).unwrap_or(char::REPLACEMENT_CHARACTER as u16));
+    for (index, c) in ucs2.chain([0]).enumerate() {
+        // SAFETY: the pool buffer holds `len` characters and pool allocations are 8-byte aligned.
+        unsafe { data.add(index).write(c) };
+    }
+    Some((size, data))
+}
+
+/// Error returned by an image started with [`start_image_decoded`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageError {
+    /// Status returned by the image.
+    pub status: efi::Status,
+    /// Description returned by the image, empty if it returned no exit data.
+    pub message: String,
+    /// Binary data following the description in the exit data.
+    pub payload: Vec<u8>,
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "status {:#x}", self.status.as_usize())?;
+        if !self.message.is_empty() {
+            write!(f, ": {}", self.message)?;
+        }
+        Ok(())
+    }
+}
+
+/// Start the image `image_handle`, decoding its exit data if it returns an error.
+///
+/// Returns the status of the image if it is not an error, warnings included. The exit data is freed in every case.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::image::start_image_decoded;
+/// use r_efi::efi;
+///
+/// fn boot(boot_services: &efi::BootServices, image_handle: efi::Handle) -> Option<String> {
+///     // Return the description of the failure, to be shown in the boot menu.
+///     start_image_decoded(boot_services, image_handle).err().map(|error| error.to_string())
+/// }
+/// ```
+pub fn start_image_decoded(
+    boot_services: &efi::BootServices,
+    image_handle: efi::Handle,
+) -> Result<efi::Status, ImageError> {
+    let mut data_size = 0;
+    let mut data = ptr::null_mut();
+    let status = (boot_services.start_image)(image_handle, &mut data_size, &mut data);
+    let exit_data = if data.is_null() {
+        &[][..]
+    } else {
+        // SAFETY: StartImage returns `data_size` bytes of exit data at `data`, allocated from pool.
+        unsafe { slice::from_raw_parts(data as *const u8, data_size) }
+    };
+    let result = if status.is_error() { Err(decode_exit_data(status, exit_data)) } else { Ok(status) };
+    if !data.is_null() {
+        (boot_services.free_pool)(data as *mut c_void);
+    }
+    result
+}
+
+fn decode_exit_data(status: efi::Status, exit_data: &[u8]) -> ImageError {
+    let message: Vec<u16> = exit_data
+        .chunks_exact(mem::size_of::<u16>())
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|&c| c != 0)
+        .collect();
+    // Without a null terminator, the whole buffer is the description.
+    let payload_offset = ((message.len() + 1) * mem::size_of::<u16>()).min(exit_data.len());
+    ImageError { status, message: ucs2::decode(&message).collect(), payload: exit_data[payload_offset..].to_vec() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        alloc::{alloc, dealloc, Layout},
+        cell::RefCell,
+        slice,
+    };
+
+    use crate::test_support::mock_efi_boot_services;
+
+    std::thread_local! {
+        static EXITS: RefCell<Vec<(efi::Status, Option<Vec<u16>>)>> = const { RefCell::new(Vec::new()) };
+        static FREED: RefCell<usize> = const { RefCell::new(0) };
+    }
+
+    // Pool allocations store their size in front of the buffer so they can be freed.
+    extern "efiapi" fn allocate_pool(
+        _pool_type: efi::MemoryType,
+        size: usize,
+        buffer: *mut *mut c_void,
+    ) -> efi::Status {
+        unsafe {
+            let allocation = alloc(Layout::from_size_align(size + 8, 8).unwrap()) as *mut usize;
+            allocation.write(size);
+            *buffer = allocation.add(1) as *mut c_void;
+        }
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn free_pool(buffer: *mut c_void) -> efi::Status {
+        unsafe {
+            let allocation = (buffer as *mut usize).sub(1);
+            dealloc(allocation as *mut u8, Layout::from_size_align(allocation.read() + 8, 8).unwrap());
+        }
+        FREED.with(|freed| *freed.borrow_mut() += 1);
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn exit(
+        _image_handle: efi::Handle,
+        status: efi::Status,
+        data_size: usize,
+        data: *mut u16,
+    ) -> efi::Status {
+        let data = (!data.is_null()).then(|| unsafe { slice::from_raw_parts(data, data_size / 2) }.to_vec());
+        EXITS.with(|exits| exits.borrow_mut().push((status, data)));
+        // Model an image that is not the one currently running.
+        efi::Status::INVALID_PARAMETER
+    }
+
+    extern "efiapi" fn start_image(
+        image_handle: efi::Handle,
+        data_size: *mut usize,
+        data: *mut *mut u16,
+    ) -> efi::Status {
+        let (status, exit_data): (_, &[u8]) = match image_handle as usize {
+            1 => (efi::Status::WARN_UNKNOWN_GLYPH, &[]),
+            2 => (efi::Status::LOAD_ERROR, b"N\0o\0\0\0\xde\xad"),
+            3 => (efi::Status::ABORTED, b"=\xd8"),
+            _ => (efi::Status::NOT_FOUND, &[]),
+        };
+        if !exit_data.is_empty() {
+            let mut buffer = ptr::null_mut();
+            allocate_pool(efi::BOOT_SERVICES_DATA, exit_data.len(), &mut buffer);
+            unsafe {
+                (buffer as *mut u8).copy_from_nonoverlapping(exit_data.as_ptr(), exit_data.len());
+                *data_size = exit_data.len();
+                *data = buffer as *mut u16;
+            }
+        }
+        status
+    }
+
+    fn boot_services() -> efi::BootServices {
+        efi::BootServices { allocate_pool, free_pool, exit, start_image, ..mock_efi_boot_services() }
+    }
+
+    #[test]
+    fn test_exit_with_message() {
+        let boot_services = boot_services();
+        let image_handle = 0x1a6e as efi::Handle;
+        assert_eq!(
+            exit_with_message(&boot_services, image_handle, efi::Status::ABORTED, "Bad \u{1f4be}\0ignored"),
+            efi::Status::INVALID_PARAMETER
+        );
+        exit_with_message(&boot_services, image_handle, efi::Status::SUCCESS, "Done");
+
+        let exits = EXITS.with(|exits| exits.borrow().clone());
+        let expected: Vec<u16> = "Bad \u{fffd}\0".encode_utf16().collect();
+        assert_eq!(exits, [(efi::Status::ABORTED, Some(expected)), (efi::Status::SUCCESS, None)]);
+        assert_eq!(FREED.with(|freed| *freed.borrow()), 1);
+    }
+
+    #[test]
+    fn test_start_image_decoded() {
+        let boot_services = boot_services();
+        let start = |handle: usize| start_image_decoded(&boot_services, handle as efi::Handle);
+        assert_eq!(start(1), Ok(efi::Status::WARN_UNKNOWN_GLYPH));
+
+        let error = start(2).unwrap_err();
+        assert_eq!(
+            error,
+            ImageError { status: efi::Status::LOAD_ERROR, message: "No".into(), payload: vec![0xde, 0xad] }
+        );
+        assert_eq!(error.to_string(), "status 0x8000000000000001: No");
+
+        // An unpaired surrogate without a null terminator.
+        let error = start(3).unwrap_err();
+        assert_eq!((error.message.as_str(), error.payload.len()), ("\u{fffd}", 0));
+        assert_eq!(start(4).unwrap_err().to_string(), "status 0x800000000000000e");
+        assert_eq!(FREED.with(|freed| *freed.borrow()), 2);
+    }
+}
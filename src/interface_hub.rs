@@ -0,0 +1,288 @@
+//! Private interfaces shared between cooperating Rust images.
+//!
+//! Rust drivers built from the same sources may want to share richer interfaces than a C ABI protocol, without
+//! defining and publishing a protocol GUID for each. A [`PrivateInterfaceHub`] holds bridges generated by
+//! [`abi_bridge!`](crate::abi_bridge!), each registered under a GUID, and installs a single protocol owned by this
+//! crate on the image handle of the producer. Consumers find a bridge with [`locate_interface`] and use it through its
+//! trait.
+//!
+use alloc::{boxed::Box, vec::Vec};
+use core::{cell::RefCell, ffi::c_void, ptr};
+
+use r_efi::efi;
+
+use crate::{
+    abi_bridge,
+    abi_bridge::{Bridge, BridgeError},
+};
+
+/// GUID of the protocol installed by [`PrivateInterfaceHub::install`].
+pub const PROTOCOL_GUID: efi::Guid =
+    efi::Guid::from_fields(0x57400553, 0x9287, 0x4ebc, 0x8a, 0xed, &[0x13, 0x16, 0x75, 0xee, 0x38, 0x04]);
+
+/// Directory of the interfaces registered with a hub.
+pub trait InterfaceDirectory {
+    /// Return the bridge registered under `guid`, or null if there is none.
+    fn find(&self, guid: &efi::Guid) -> *const c_void;
+}
+
+abi_bridge! {
+    /// Interface of the [`PROTOCOL_GUID`] protocol.
+    pub struct Protocol: InterfaceDirectory, version 1 {
+        fn find(&self, guid: &efi::Guid) -> *const c_void;
+    }
+}
+
+// SAFETY: `PROTOCOL_GUID` is owned by this crate and only installed with a `Protocol`.
+unsafe impl crate::protocol::Protocol for Protocol {
+    const GUID: efi::Guid = PROTOCOL_GUID;
+}
+
+/// Bridges shared by an image, registered by GUID.
+///
+/// # Example
+/// ```no_run
+/// use core::cell::Cell;
+/// use mu_rust_helpers::{abi_bridge, interface_hub::{self, PrivateInterfaceHub}};
+/// use r_efi::efi;
+///
+/// pub trait Counter {
+///     fn add(&self, amount: u32) -> u32;
+/// }
+///
+/// abi_bridge! {
+///     pub struct CounterBridge: Counter, version 1 {
+///         fn add(&self, amount: u32) -> u32;
+///     }
+/// }
+///
+/// pub const COUNTER_GUID: efi::Guid =
+///     efi::Guid::from_fields(0x1c0a6e8f, 0x4d4b, 0x4b52, 0x9a, 0x3e, &[0x51, 0x0d, 0x2f, 0x6c, 0x7e, 0x11]);
+///
+/// struct SimpleCounter(Cell<u32>);
+///
+/// impl Counter for SimpleCounter {
+///     fn add(&self, amount: u32) -> u32 {
+///         self.0.set(self.0.get() + amount);
+///         self.0.get()
+///     }
+/// }
+///
+/// // Producer image.
+/// fn share(boot_services: &efi::BootServices, image: efi::Handle) -> Result<(), efi::Status> {
+///     let counter: &'static SimpleCounter = Box::leak(Box::new(SimpleCounter(Cell::new(0))));
+///     let hub: &'static PrivateInterfaceHub = Box::leak(Box::new(PrivateInterfaceHub::new()));
+///     hub.register(COUNTER_GUID, Box::leak(Box::new(CounterBridge::new(counter))))?;
+///     hub.install(boot_services, image)
+/// }
+///
+/// // Consumer image.
+/// fn count(boot_services: &efi::BootServices, producer: efi::Handle) -> Result<u32, efi::Status> {
+///     let counter: &dyn Counter =
+///         unsafe { interface_hub::locate_interface::<CounterBridge>(boot_services, Some(producer), &COUNTER_GUID) }?;
+///     Ok(counter.add(1))
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct PrivateInterfaceHub {
+    interfaces: RefCell<Vec<(efi::Guid, *const c_void)>>,
+}
+
+impl PrivateInterfaceHub {
+    /// Create an empty hub.
+    pub const fn new() -> Self {
+        Self { interfaces: RefCell::new(Vec::new()) }
+    }
+
+    /// Share `bridge` under `guid`. Bridges may be registered before or after the hub is installed.
+    ///
+    /// Returns `efi::Status::ALREADY_STARTED` if a bridge is already registered under `guid`.
+    pub fn register<B: Bridge>(&self, guid: efi::Guid, bridge: &'static B) -> Result<(), efi::Status> {
+        let mut interfaces = self.interfaces.borrow_mut();
+        if interfaces.iter().any(|(registered, _)| *registered == guid) {
+            return Err(efi::Status::ALREADY_STARTED);
+        }
+        interfaces.push((guid, bridge as *const B as *const c_void));
+        Ok(())
+    }
+
+    /// Install the hub protocol on the image `handle`.
+    ///
+    /// The protocol is never uninstalled, since consumers may keep using the bridges for as long as the image is
+    /// loaded.
+    pub fn install(&'static self, boot_services: &efi::BootServices, handle: efi::Handle) -> Result<(), efi::Status> {
+        let protocol = Box::into_raw(Box::new(Protocol::new(self)));
+        let mut handle = handle;
+        let mut guid = PROTOCOL_GUID;
+        let status = (boot_services.install_protocol_interface)(
+            &mut handle,
+            &mut guid,
+            efi::NATIVE_INTERFACE,
+            protocol as *mut c_void,
+        );
+        if status.is_error() {
+            // SAFETY: the protocol was not installed, so nothing else refers to it.
+            drop(unsafe { Box::from_raw(protocol) });
+            return Err(status);
+        }
+        Ok(())
+    }
+}
+
+impl InterfaceDirectory for PrivateInterfaceHub {
+    fn find(&self, guid: &efi::Guid) -> *const c_void {
+        // A registration in progress, interrupted by a notification looking up an interface, hides every interface.
+        let Ok(interfaces) = self.interfaces.try_borrow() else {
+            return ptr::null();
+        };
+        interfaces.iter().find(|(registered, _)| registered == guid).map_or(ptr::null(), |&(_, bridge)| bridge)
+    }
+}
+
+/// Return the bridge registered under `guid` with the hub installed on `handle`, or with any hub if `handle` is
+/// `None`.
+///
+/// Returns `efi::Status::NOT_FOUND` if no bridge is registered under `guid`, and `efi::Status::INCOMPATIBLE_VERSION` if
+/// the hub or the bridge is older than this image expects.
+///
+/// # Safety
+/// The bridge registered under `guid` must be a `B`, and the producer image must not be unloaded while the bridge is
+/// in use.
+pub unsafe fn locate_interface<B: Bridge>(
+    boot_services: &efi::BootServices,
+    handle: Option<efi::Handle>,
+    guid: &efi::Guid,
+) -> Result<&'static B, efi::Status> {
+    let mut protocol_guid = PROTOCOL_GUID;
+    let mut interface = ptr::null_mut();
+    let status = match handle {
+        Some(handle) => (boot_services.handle_protocol)(handle, &mut protocol_guid, &mut interface),
+        None => (boot_services.locate_protocol)(&mut protocol_guid, ptr::null_mut(), &mut interface),
+    };
+    if status.is_error() {
+        return Err(status);
+    }
+    let hub = <Protocol as Bridge>::from_interface(interface)?;
+    match B::from_interface(hub.find(guid)) {
+        Err(BridgeError::InvalidInterface) => Err(efi::Status::NOT_FOUND),
+        result => Ok(result?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::cell::Cell;
+    use std::cell::RefCell;
+
+    use crate::test_support::mock_efi_boot_services;
+
+    trait Greeter {
+        fn greet(&self, times: u32) -> u32;
+    }
+
+    abi_bridge! {
+        struct GreeterBridge: Greeter, version 2 {
+            fn greet(&self, times: u32) -> u32;
+        }
+    }
+
+    struct TestGreeter(Cell<u32>);
+
+    impl Greeter for TestGreeter {
+        fn greet(&self, times: u32) -> u32 {
+            self.0.set(self.0.get() + times);
+            self.0.get()
+        }
+    }
+
+    const GREETER_GUID: efi::Guid =
+        efi::Guid::from_fields(0x1c0a6e8f, 0x4d4b, 0x4b52, 0x9a, 0x3e, &[0x51, 0x0d, 0x2f, 0x6c, 0x7e, 0x11]);
+    const OTHER_GUID: efi::Guid =
+        efi::Guid::from_fields(0x1c0a6e8f, 0x4d4b, 0x4b52, 0x9a, 0x3e, &[0x51, 0x0d, 0x2f, 0x6c, 0x7e, 0x12]);
+
+    std::thread_local! {
+        /// Protocols installed, as their handle, GUID and interface.
+        static INSTALLED: RefCell<Vec<(usize, efi::Guid, usize)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    extern "efiapi" fn install_protocol_interface(
+        handle: *mut efi::Handle,
+        guid: *mut efi::Guid,
+        interface_type: efi::InterfaceType,
+        interface: *mut c_void,
+    ) -> efi::Status {
+        assert_eq!(interface_type, efi::NATIVE_INTERFACE);
+        let entry = unsafe { (*handle as usize, *guid, interface as usize) };
+        INSTALLED.with(|installed| installed.borrow_mut().push(entry));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn handle_protocol(
+        handle: efi::Handle,
+        guid: *mut efi::Guid,
+        interface: *mut *mut c_void,
+    ) -> efi::Status {
+        let guid = unsafe { *guid };
+        INSTALLED.with(|installed| {
+            match installed.borrow().iter().find(|entry| (entry.0, entry.1) == (handle as usize, guid)) {
+                Some(&(_, _, found)) => {
+                    unsafe { *interface = found as *mut c_void };
+                    efi::Status::SUCCESS
+                }
+                None => efi::Status::UNSUPPORTED,
+            }
+        })
+    }
+
+    extern "efiapi" fn locate_protocol(
+        guid: *mut efi::Guid,
+        _registration: *mut c_void,
+        interface: *mut *mut c_void,
+    ) -> efi::Status {
+        let guid = unsafe { *guid };
+        INSTALLED.with(|installed| match installed.borrow().iter().find(|entry| entry.1 == guid) {
+            Some(&(_, _, found)) => {
+                unsafe { *interface = found as *mut c_void };
+                efi::Status::SUCCESS
+            }
+            None => efi::Status::NOT_FOUND,
+        })
+    }
+
+    #[test]
+    fn test_hub() {
+        let boot_services = efi::BootServices {
+            install_protocol_interface,
+            handle_protocol,
+            locate_protocol,
+            ..mock_efi_boot_services()
+        };
+        let image = 0x10 as efi::Handle;
+        let locate = |handle, guid| unsafe { locate_interface::<GreeterBridge>(&boot_services, handle, guid) };
+        assert_eq!(locate(None, &GREETER_GUID).err(), Some(efi::Status::NOT_FOUND));
+
+        let greeter: &'static TestGreeter = Box::leak(Box::new(TestGreeter(Cell::new(0))));
+        let bridge: &'static GreeterBridge = Box::leak(Box::new(GreeterBridge::new(greeter)));
+        let hub: &'static PrivateInterfaceHub = Box::leak(Box::new(PrivateInterfaceHub::new()));
+        hub.install(&boot_services, image).unwrap();
+        assert_eq!(INSTALLED.with(|installed| installed.borrow()[0].1), PROTOCOL_GUID);
+
+        // Bridges registered after the hub is installed are found.
+        assert_eq!(locate(Some(image), &GREETER_GUID).err(), Some(efi::Status::NOT_FOUND));
+        hub.register(GREETER_GUID, bridge).unwrap();
+        assert_eq!(hub.register(GREETER_GUID, bridge), Err(efi::Status::ALREADY_STARTED));
+        let consumer: &dyn Greeter = locate(Some(image), &GREETER_GUID).unwrap();
+        assert_eq!(consumer.greet(2), 2);
+        assert_eq!(locate(None, &GREETER_GUID).unwrap().greet(3), 5);
+        assert_eq!(locate(Some(image), &OTHER_GUID).err(), Some(efi::Status::NOT_FOUND));
+        assert_eq!(locate(Some(0x20 as efi::Handle), &GREETER_GUID).err(), Some(efi::Status::UNSUPPORTED));
+
+        // Bridges older than the consumer expects are rejected.
+        let mut old_bridge = GreeterBridge::new(greeter);
+        old_bridge.header.version = 1;
+        hub.register(OTHER_GUID, Box::leak(Box::new(old_bridge))).unwrap();
+        assert_eq!(locate(Some(image), &OTHER_GUID).err(), Some(efi::Status::INCOMPATIBLE_VERSION));
+    }
+}
@@ -0,0 +1,235 @@
+//! Registry for coordinating separately built images.
+//!
+//! Images built with this crate may want to coordinate, for example to share a single logger instead of each
+//! installing their own. The [`InteropRegistry`] is a configuration table owned by this crate where every component
+//! advertises a GUID identifying it, a version and feature flags. Components publish an entry with [`advertise`] and
+//! discover each other with [`lookup`].
+//!
+use core::{ffi::c_void, mem, ptr};
+
+use r_efi::efi;
+
+use crate::{config_table::ConfigTable, system_table::StandardSystemTable};
+
+/// GUID of the interop registry configuration table.
+pub const INTEROP_REGISTRY_GUID: efi::Guid =
+    efi::Guid::from_fields(0x3892eea8, 0x1108, 0x4148, 0x87, 0xc6, &[0x9f, 0xae, 0xbb, 0x89, 0x3e, 0x6b]);
+
+/// Component entry of the [`InteropRegistry`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InteropEntry {
+    /// GUID identifying the component.
+    pub component: efi::Guid,
+    /// Version of the component.
+    pub version: u32,
+    /// Reserved, must be zero.
+    pub reserved: u32,
+    /// Component-defined feature flags.
+    pub features: u64,
+}
+
+/// Interop registry configuration table.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct InteropRegistry {
+    /// [`InteropRegistry::SIGNATURE`].
+    pub signature: u32,
+    /// [`InteropRegistry::REVISION`].
+    pub revision: u32,
+    /// Number of valid entries.
+    pub count: u32,
+    /// Number of entries the table can hold, [`InteropRegistry::CAPACITY`] for this revision.
+    pub capacity: u32,
+    /// Component entries, of which the first `count` are valid.
+    pub entries: [InteropEntry; InteropRegistry::CAPACITY],
+}
+
+// SAFETY: `INTEROP_REGISTRY_GUID` is owned by this crate and only published with an `InteropRegistry`.
+unsafe impl ConfigTable for InteropRegistry {
+    const GUID: efi::Guid = INTEROP_REGISTRY_GUID;
+}
+
+impl InteropRegistry {
+    /// `"MURH"`.
+    pub const SIGNATURE: u32 = u32::from_le_bytes(*b"MURH");
+    /// Revision of the table layout.
+    pub const REVISION: u32 = 1;
+    /// Number of entries of the table.
+    pub const CAPACITY: usize = 32;
+
+    /// Create an empty registry.
+    pub const fn new() -> Self {
+        const EMPTY: InteropEntry = InteropEntry {
+            component: efi::Guid::from_fields(0, 0, 0, 0, 0, &[0; 6]),
+            version: 0,
+            reserved: 0,
+            features: 0,
+        };
+        Self {
+            signature: Self::SIGNATURE,
+            revision: Self::REVISION,
+            count: 0,
+            capacity: Self::CAPACITY as u32,
+            entries: [EMPTY; Self::CAPACITY],
+        }
+    }
+
+    /// Return true if the header matches the layout of this revision.
+    pub fn is_valid(&self) -> bool {
+        self.signature == Self::SIGNATURE
+            && self.revision == Self::REVISION
+            && self.capacity as usize == Self::CAPACITY
+            && self.count <= self.capacity
+    }
+
+    /// Return the valid entries.
+    pub fn entries(&self) -> &[InteropEntry] {
+        &self.entries[..(self.count as usize).min(Self::CAPACITY)]
+    }
+
+    /// Return the entry of `component`, if it is registered.
+    pub fn find(&self, component: &efi::Guid) -> Option<&InteropEntry> {
+        self.entries().iter().find(|entry| entry.component == *component)
+    }
+
+    /// Add `entry`, replacing the entry of the same component if there is one.
+    ///
+    /// Returns `efi::Status::OUT_OF_RESOURCES` if the registry is full.
+    pub fn insert(&mut self, entry: InteropEntry) -> Result<(), efi::Status> {
+        let count = (self.count as usize).min(Self::CAPACITY);
+        if let Some(existing) = self.entries[..count].iter_mut().find(|existing| existing.component == entry.component)
+        {
+            *existing = entry;
+            return Ok(());
+        }
+        if count == Self::CAPACITY {
+            return Err(efi::Status::OUT_OF_RESOURCES);
+        }
+        self.entries[count] = entry;
+        self.count += 1;
+        Ok(())
+    }
+}
+
+impl Default for InteropRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Advertise `component` with its `version` and `features` in the registry, publishing the registry if needed.
+///
+/// Returns `efi::Status::UNSUPPORTED` if boot services are no longer available, `efi::Status::INCOMPATIBLE_VERSION`
+/// if a registry with an unknown layout is already published, and `efi::Status::OUT_OF_RESOURCES` if the registry is
+/// full.
+pub fn advertise(
+    system_table: &StandardSystemTable,
+    component: &efi::Guid,
+    version: u32,
+    features: u64,
+) -> Result<(), efi::Status> {
+    let boot_services = system_table.boot_services().ok_or(efi::Status::UNSUPPORTED)?;
+    let entry = InteropEntry { component: *component, version, reserved: 0, features };
+
+    // Keep notification functions from observing a partially updated registry.
+    let tpl = (boot_services.raise_tpl)(efi::TPL_NOTIFY);
+    let result = match system_table.find_config_table_by_guid(&INTEROP_REGISTRY_GUID) {
+        Some(registry) => {
+            // SAFETY: the registry is published by this crate, and is only updated at TPL_NOTIFY.
+            let registry = unsafe { &mut *(registry as *mut InteropRegistry) };
+            if registry.is_valid() {
+                registry.insert(entry)
+            } else {
+                Err(efi::Status::INCOMPATIBLE_VERSION)
+            }
+        }
+        None => publish(boot_services, entry),
+    };
+    (boot_services.restore_tpl)(tpl);
+    result
+}
+
+fn publish(boot_services: &efi::BootServices, entry: InteropEntry) -> Result<(), efi::Status> {
+    let mut buffer = ptr::null_mut::<c_void>();
+    let status = (boot_services.allocate_pool)(efi::BOOT_SERVICES_DATA, mem::size_of::<InteropRegistry>(), &mut buffer);
+    if status.is_error() {
+        return Err(status);
+    }
+
+    let registry = buffer as *mut InteropRegistry;
+    // SAFETY: `allocate_pool` returned a buffer large enough for the registry, aligned to 8 bytes.
+    unsafe {
+        registry.write(InteropRegistry::new());
+        (*registry).insert(entry)?;
+    }
+
+    let mut guid = INTEROP_REGISTRY_GUID;
+    let status = (boot_services.install_configuration_table)(&mut guid, buffer);
+    if status.is_error() {
+        (boot_services.free_pool)(buffer);
+        return Err(status);
+    }
+    Ok(())
+}
+
+/// Return the entry advertised by `component`, if any.
+pub fn lookup(system_table: &StandardSystemTable, component: &efi::Guid) -> Option<InteropEntry> {
+    let registry = system_table.find_config_table::<InteropRegistry>()?;
+    if !registry.is_valid() {
+        return None;
+    }
+    registry.find(component).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::system_table::tests::mock_efi_system_table;
+
+    const LOGGER: efi::Guid =
+        efi::Guid::from_fields(0x4f3a5e2d, 0x2b1c, 0x4d0e, 0x9f, 0x8a, &[0x7b, 0x6c, 0x5d, 0x4e, 0x3f, 0x20]);
+
+    fn component(index: u32) -> efi::Guid {
+        efi::Guid::from_fields(index, 0, 0, 0, 0, &[0; 6])
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut registry = InteropRegistry::new();
+        assert!(registry.is_valid());
+        assert!(registry.entries().is_empty());
+
+        registry.insert(InteropEntry { component: LOGGER, version: 1, reserved: 0, features: 0x1 }).unwrap();
+        registry.insert(InteropEntry { component: LOGGER, version: 2, reserved: 0, features: 0x3 }).unwrap();
+        assert_eq!(registry.entries().len(), 1);
+        assert_eq!(registry.find(&LOGGER).map(|entry| (entry.version, entry.features)), Some((2, 0x3)));
+
+        for index in 1..InteropRegistry::CAPACITY as u32 {
+            registry
+                .insert(InteropEntry { component: component(index), version: 1, reserved: 0, features: 0 })
+                .unwrap();
+        }
+        assert_eq!(
+            registry.insert(InteropEntry { component: component(0x100), version: 1, reserved: 0, features: 0 }),
+            Err(efi::Status::OUT_OF_RESOURCES)
+        );
+    }
+
+    #[test]
+    fn test_lookup() {
+        let mut registry = InteropRegistry::new();
+        registry.insert(InteropEntry { component: LOGGER, version: 3, reserved: 0, features: 0 }).unwrap();
+        let mut configuration_table = [efi::ConfigurationTable {
+            vendor_guid: INTEROP_REGISTRY_GUID,
+            vendor_table: &mut registry as *mut InteropRegistry as *mut c_void,
+        }];
+        let efi_system_table = mock_efi_system_table(&mut configuration_table);
+        let system_table = StandardSystemTable::new(&efi_system_table);
+
+        assert_eq!(lookup(&system_table, &LOGGER).map(|entry| entry.version), Some(3));
+        assert!(lookup(&system_table, &component(1)).is_none());
+        assert_eq!(advertise(&system_table, &LOGGER, 4, 0), Err(efi::Status::UNSUPPORTED));
+    }
+}
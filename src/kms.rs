@@ -0,0 +1,543 @@
+//! Key Management Service protocol.
+//!
+//! The UEFI `EFI_KMS_PROTOCOL` gives access to a key management service, e.g. to provision keys during manufacturing.
+//! [`Kms`] wraps it with a typed [`Client`] identity and [`KeyDescriptor`]s whose value buffers are checked against
+//! the size of their key format, since the service writes key values without being told the buffer size.
+//!
+//! Key attributes and client data are not wrapped.
+//!
+use core::{ffi::c_void, marker::PhantomData, ptr, slice};
+
+use r_efi::efi;
+
+use crate::protocol;
+
+/// `EFI_KMS_PROTOCOL_GUID`.
+pub const PROTOCOL_GUID: efi::Guid =
+    efi::Guid::from_fields(0xec3a978d, 0x7c4e, 0x48fa, 0x9a, 0xbe, &[0x6a, 0xd9, 0x1c, 0xc8, 0xf8, 0x11]);
+
+/// `EFI_KMS_FORMAT_GENERIC_128_GUID`: 128-bit key of unspecified use.
+pub const FORMAT_GENERIC_128_GUID: efi::Guid =
+    efi::Guid::from_fields(0xec8a3d69, 0x6ddf, 0x4108, 0x94, 0x76, &[0x73, 0x37, 0xfc, 0x52, 0x21, 0x36]);
+/// `EFI_KMS_FORMAT_GENERIC_256_GUID`: 256-bit key of unspecified use.
+pub const FORMAT_GENERIC_256_GUID: efi::Guid =
+    efi::Guid::from_fields(0x70f64793, 0xc323, 0x4261, 0xac, 0x2c, &[0xd8, 0x76, 0xf2, 0x7c, 0x53, 0x45]);
+/// `EFI_KMS_FORMAT_AESXTS_128_GUID`: AES-XTS key pair of 2 × 128 bits.
+pub const FORMAT_AESXTS_128_GUID: efi::Guid =
+    efi::Guid::from_fields(0x4776e33f, 0xdb47, 0x479a, 0xa2, 0x5f, &[0xa1, 0xcd, 0x0a, 0xfa, 0xb3, 0x8b]);
+/// `EFI_KMS_FORMAT_AESXTS_256_GUID`: AES-XTS key pair of 2 × 256 bits.
+pub const FORMAT_AESXTS_256_GUID: efi::Guid =
+    efi::Guid::from_fields(0xdc7e8613, 0xc4bb, 0x4db0, 0x84, 0x62, &[0x13, 0x51, 0x13, 0x57, 0xab, 0xe2]);
+
+/// Return the size in bytes of the key values of `format`, if the format is known.
+pub fn key_size(format: &efi::Guid) -> Option<usize> {
+    [
+        (FORMAT_GENERIC_128_GUID, 16),
+        (FORMAT_GENERIC_256_GUID, 32),
+        (FORMAT_AESXTS_128_GUID, 32),
+        (FORMAT_AESXTS_256_GUID, 64),
+    ]
+    .into_iter()
+    .find_map(|(known, size)| (known == *format).then_some(size))
+}
+
+/// No data.
+pub const DATA_TYPE_NONE: u8 = 0;
+/// Binary data.
+pub const DATA_TYPE_BINARY: u8 = 1;
+/// ASCII string.
+pub const DATA_TYPE_ASCII: u8 = 2;
+/// UCS-2 string.
+pub const DATA_TYPE_UNICODE: u8 = 4;
+/// UTF-8 string.
+pub const DATA_TYPE_UTF8: u8 = 8;
+
+/// `EFI_KMS_CLIENT_INFO`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ClientInfo {
+    pub client_id_size: u16,
+    pub client_id: *mut c_void,
+    pub client_name_type: u8,
+    pub client_name_count: u8,
+    pub client_name: *mut c_void,
+}
+
+/// `EFI_KMS_KEY_DESCRIPTOR`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct RawKeyDescriptor {
+    pub key_identifier_size: u8,
+    pub key_identifier: *mut c_void,
+    pub key_format: efi::Guid,
+    pub key_value: *mut c_void,
+    pub key_status: efi::Status,
+}
+
+/// `EFI_KMS_KEY_ATTRIBUTE`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct KeyAttribute {
+    pub key_attribute_identifier_type: u8,
+    pub key_attribute_identifier_count: u16,
+    pub key_attribute_identifier: *mut c_void,
+    pub key_attribute_instance: u16,
+    pub key_attribute_type: u16,
+    pub key_attribute_value_size: u16,
+    pub key_attribute_value: *mut c_void,
+    pub key_attribute_status: efi::Status,
+}
+
+pub type GetServiceStatus = extern "efiapi" fn(*mut Protocol) -> efi::Status;
+pub type RegisterClient =
+    extern "efiapi" fn(*mut Protocol, *mut ClientInfo, *mut usize, *mut *mut c_void) -> efi::Status;
+pub type KeyOperation = extern "efiapi" fn(
+    *mut Protocol,
+    *mut ClientInfo,
+    *mut u16,
+    *mut RawKeyDescriptor,
+    *mut usize,
+    *mut *mut c_void,
+) -> efi::Status;
+pub type KeyAttributesOperation = extern "efiapi" fn(
+    *mut Protocol,
+    *mut ClientInfo,
+    u8,
+    *mut c_void,
+    *mut u16,
+    *mut KeyAttribute,
+    *mut usize,
+    *mut *mut c_void,
+) -> efi::Status;
+pub type GetKeyByAttributes = extern "efiapi" fn(
+    *mut Protocol,
+    *mut ClientInfo,
+    *mut usize,
+    *mut KeyAttribute,
+    *mut usize,
+    *mut RawKeyDescriptor,
+    *mut usize,
+    *mut *mut c_void,
+) -> efi::Status;
+
+/// `EFI_KMS_PROTOCOL`.
+#[repr(C)]
+pub struct Protocol {
+    pub get_service_status: GetServiceStatus,
+    pub register_client: RegisterClient,
+    pub create_key: KeyOperation,
+    pub get_key: KeyOperation,
+    pub add_key: KeyOperation,
+    pub delete_key: KeyOperation,
+    pub get_key_attributes: KeyAttributesOperation,
+    pub add_key_attributes: KeyAttributesOperation,
+    pub delete_key_attributes: KeyAttributesOperation,
+    pub get_key_by_attributes: GetKeyByAttributes,
+    pub protocol_version: u32,
+    pub service_id: efi::Guid,
+    pub service_name: *mut u16,
+    pub service_version: u32,
+    pub service_available: efi::Boolean,
+    pub client_id_supported: efi::Boolean,
+    pub client_id_required: efi::Boolean,
+    pub client_id_max_size: u16,
+    pub client_name_string_types: u8,
+    pub client_name_required: efi::Boolean,
+    pub client_name_max_count: u16,
+    pub client_data_supported: efi::Boolean,
+    pub client_data_max_size: usize,
+    pub key_id_variable_len_supported: efi::Boolean,
+    pub key_id_max_size: usize,
+    pub key_formats_count: usize,
+    pub key_formats: *mut efi::Guid,
+    pub key_attributes_supported: efi::Boolean,
+    pub key_attribute_id_string_types: u8,
+    pub key_attribute_id_max_count: u16,
+    pub key_attributes_count: usize,
+    pub key_attributes: *mut KeyAttribute,
+}
+
+// SAFETY: `PROTOCOL_GUID` identifies the key management service protocol, whose interface is `Protocol`.
+unsafe impl protocol::Protocol for Protocol {
+    const GUID: efi::Guid = PROTOCOL_GUID;
+}
+
+/// Name a client gives to the service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientName<'a> {
+    /// No name.
+    None,
+    /// Binary name.
+    Binary(&'a [u8]),
+    /// ASCII name, without terminator.
+    Ascii(&'a [u8]),
+    /// UCS-2 name, without terminator.
+    Unicode(&'a [u16]),
+    /// UTF-8 name.
+    Utf8(&'a str),
+}
+
+/// Identity of a client of the service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Client<'a> {
+    /// Client identifier, empty if the client has none.
+    pub id: &'a [u8],
+    /// Client name.
+    pub name: ClientName<'a>,
+}
+
+impl Client<'_> {
+    /// Return the raw client information.
+    ///
+    /// Returns `efi::Status::INVALID_PARAMETER` if the identifier or the name is too long for the protocol.
+    fn to_raw(self) -> Result<ClientInfo, efi::Status> {
+        let (client_name_type, count, client_name) = match self.name {
+            ClientName::None => (DATA_TYPE_NONE, 0, ptr::null()),
+            ClientName::Binary(name) => (DATA_TYPE_BINARY, name.len(), name.as_ptr() as *const c_void),
+            ClientName::Ascii(name) => (DATA_TYPE_ASCII, name.len(), name.as_ptr() as *const c_void),
+            ClientName::Unicode(name) => (DATA_TYPE_UNICODE, name.len(), name.as_ptr() as *const c_void),
+            ClientName::Utf8(name) => (DATA_TYPE_UTF8, name.len(), name.as_ptr() as *const c_void),
+        };
+        Ok(ClientInfo {
+            client_id_size: self.id.len().try_into().map_err(|_| efi::Status::INVALID_PARAMETER)?,
+            client_id: if self.id.is_empty() { ptr::null_mut() } else { self.id.as_ptr() as *mut c_void },
+            client_name_type,
+            client_name_count: count.try_into().map_err(|_| efi::Status::INVALID_PARAMETER)?,
+            // The service only reads the name.
+            client_name: client_name as *mut c_void,
+        })
+    }
+}
+
+/// Key passed to and returned by the service.
+///
+/// The per-key status reported by the service is available through [`Self::status`] after each operation.
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct KeyDescriptor<'a> {
+    raw: RawKeyDescriptor,
+    _buffers: PhantomData<(&'a [u8], &'a mut [u8])>,
+}
+
+impl<'a> KeyDescriptor<'a> {
+    /// Describe the key `id` of `format`, whose value is read from or written to `value`.
+    ///
+    /// Returns `efi::Status::UNSUPPORTED` if the format is not known to [`key_size`], and
+    /// `efi::Status::INVALID_PARAMETER` if `value` does not have the size of the format or `id` is longer than 255
+    /// bytes.
+    pub fn new(id: &'a [u8], format: efi::Guid, value: &'a mut [u8]) -> Result<Self, efi::Status> {
+        let size = key_size(&format).ok_or(efi::Status::UNSUPPORTED)?;
+        if value.len() != size {
+            return Err(efi::Status::INVALID_PARAMETER);
+        }
+        // SAFETY: `value` has the size of the key format.
+        unsafe { Self::with_format(id, format, value) }
+    }
+
+    /// Describe the key `id` of `format`, which may not be known to [`key_size`].
+    ///
+    /// Returns `efi::Status::INVALID_PARAMETER` if `id` is longer than 255 bytes.
+    ///
+    /// # Safety
+    /// `value` must be at least as large as the key values of `format`.
+    pub unsafe fn with_format(id: &'a [u8], format: efi::Guid, value: &'a mut [u8]) -> Result<Self, efi::Status> {
+        Ok(Self {
+            raw: RawKeyDescriptor {
+                key_identifier_size: id.len().try_into().map_err(|_| efi::Status::INVALID_PARAMETER)?,
+                // The service only reads the identifier, which the caller provides for every operation.
+                key_identifier: id.as_ptr() as *mut c_void,
+                key_format: format,
+                key_value: value.as_mut_ptr() as *mut c_void,
+                key_status: efi::Status::NOT_READY,
+            },
+            _buffers: PhantomData,
+        })
+    }
+
+    /// Return the key format.
+    pub fn format(&self) -> efi::Guid {
+        self.raw.key_format
+    }
+
+    /// Return the status of the last operation on this key, `efi::Status::NOT_READY` before the first one.
+    pub fn status(&self) -> efi::Status {
+        self.raw.key_status
+    }
+}
+
+/// Wrapper around the KMS protocol.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::kms::{self, Client, ClientName, KeyDescriptor, Kms};
+/// use r_efi::efi;
+///
+/// fn provision_key(boot_services: &efi::BootServices, key: &mut [u8; 32]) -> Result<(), efi::Status> {
+///     let kms = Kms::locate(boot_services)?;
+///     let client = Client { id: b"factory-01", name: ClientName::Ascii(b"Provisioning") };
+///     kms.register_client(&client)?;
+///     let mut keys = [KeyDescriptor::new(b"disk-0", kms::FORMAT_AESXTS_128_GUID, key)?];
+///     kms.add_keys(&client, &mut keys)
+/// }
+/// ```
+pub struct Kms<'a> {
+    protocol: *mut Protocol,
+    _lifetime_marker: PhantomData<&'a Protocol>,
+}
+
+impl<'a> Kms<'a> {
+    /// Create a wrapper around `protocol`.
+    ///
+    /// # Safety
+    /// `protocol` must point to a valid protocol structure for the lifetime of the wrapper.
+    pub unsafe fn new(protocol: *mut Protocol) -> Self {
+        Self { protocol, _lifetime_marker: PhantomData }
+    }
+
+    /// Locate the protocol.
+    pub fn locate(boot_services: &'a efi::BootServices) -> Result<Self, efi::Status> {
+        let mut guid = PROTOCOL_GUID;
+        let mut interface = ptr::null_mut();
+        let status = (boot_services.locate_protocol)(&mut guid, ptr::null_mut(), &mut interface);
+        if status.is_error() {
+            return Err(status);
+        }
+        if interface.is_null() {
+            return Err(efi::Status::NOT_FOUND);
+        }
+        // SAFETY: the firmware installs a valid protocol structure with this GUID, which stays installed while boot
+        // services are available.
+        Ok(unsafe { Self::new(interface as *mut Protocol) })
+    }
+
+    fn protocol(&self) -> &Protocol {
+        // SAFETY: the protocol is valid for the lifetime of the wrapper.
+        unsafe { &*self.protocol }
+    }
+
+    /// Return the GUID identifying the service.
+    pub fn service_id(&self) -> efi::Guid {
+        self.protocol().service_id
+    }
+
+    /// Return the version of the service.
+    pub fn service_version(&self) -> u32 {
+        self.protocol().service_version
+    }
+
+    /// Return true if the service was available when the protocol was installed, see [`Self::service_status`].
+    pub fn service_available(&self) -> bool {
+        self.protocol().service_available.into()
+    }
+
+    /// Return the key formats supported by the service.
+    pub fn key_formats(&self) -> &[efi::Guid] {
+        let protocol = self.protocol();
+        if protocol.key_formats.is_null() {
+            return &[];
+        }
+        // SAFETY: the protocol describes `key_formats_count` supported formats.
+        unsafe { slice::from_raw_parts(protocol.key_formats, protocol.key_formats_count) }
+    }
+
+    /// Check that the service is currently available.
+    pub fn service_status(&self) -> Result<(), efi::Status> {
+        result((self.protocol().get_service_status)(self.protocol))
+    }
+
+    /// Register `client` with the service.
+    pub fn register_client(&self, client: &Client) -> Result<(), efi::Status> {
+        let mut info = client.to_raw()?;
+        result((self.protocol().register_client)(self.protocol, &mut info, ptr::null_mut(), ptr::null_mut()))
+    }
+
+    /// Have the service generate a value for each of `keys`, written to their value buffers.
+    pub fn create_keys(&self, client: &Client, keys: &mut [KeyDescriptor]) -> Result<(), efi::Status> {
+        self.key_operation(self.protocol().create_key, client, keys)
+    }
+
+    /// Retrieve the value of each of `keys` into their value buffers.
+    pub fn get_keys(&self, client: &Client, keys: &mut [KeyDescriptor]) -> Result<(), efi::Status> {
+        self.key_operation(self.protocol().get_key, client, keys)
+    }
+
+    /// Store each of `keys` with the value in their value buffers.
+    pub fn add_keys(&self, client: &Client, keys: &mut [KeyDescriptor]) -> Result<(), efi::Status> {
+        self.key_operation(self.protocol().add_key, client, keys)
+    }
+
+    /// Delete each of `keys` from the service.
+    pub fn delete_keys(&self, client: &Client, keys: &mut [KeyDescriptor]) -> Result<(), efi::Status> {
+        self.key_operation(self.protocol().delete_key, client, keys)
+    }
+
+    /// Run `operation` on `keys`; the status of each key is updated even if the operation fails.
+    fn key_operation(
+        &self,
+        operation: KeyOperation,
+        client: &Client,
+        keys: &mut [KeyDescriptor],
+    ) -> Result<(), efi::Status> {
+        let mut info = client.to_raw()?;
+        let mut count: u16 = keys.len().try_into().map_err(|_| efi::Status::INVALID_PARAMETER)?;
+        // `KeyDescriptor` is a transparent wrapper around the raw descriptor, so the slice is passed as is.
+        let descriptors = keys.as_mut_ptr() as *mut RawKeyDescriptor;
+        result(operation(self.protocol, &mut info, &mut count, descriptors, ptr::null_mut(), ptr::null_mut()))
+    }
+}
+
+fn result(status: efi::Status) -> Result<(), efi::Status> {
+    if status.is_error() {
+        Err(status)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::{mock_efi_boot_services, unimplemented_service};
+
+    extern "efiapi" fn get_service_status(_this: *mut Protocol) -> efi::Status {
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn register_client(
+        _this: *mut Protocol,
+        client: *mut ClientInfo,
+        client_data_size: *mut usize,
+        client_data: *mut *mut c_void,
+    ) -> efi::Status {
+        assert!(client_data_size.is_null() && client_data.is_null());
+        let client = unsafe { *client };
+        let id = unsafe { slice::from_raw_parts(client.client_id as *const u8, client.client_id_size as usize) };
+        let name =
+            unsafe { slice::from_raw_parts(client.client_name as *const u16, client.client_name_count as usize) };
+        assert_eq!((id, client.client_name_type), (&b"tool"[..], DATA_TYPE_UNICODE));
+        assert_eq!(String::from_utf16(name).unwrap(), "Line 3");
+        efi::Status::SUCCESS
+    }
+
+    /// Fill each key with the length of its identifier. Generic 256-bit keys are not supported.
+    extern "efiapi" fn get_key(
+        _this: *mut Protocol,
+        _client: *mut ClientInfo,
+        count: *mut u16,
+        keys: *mut RawKeyDescriptor,
+        _client_data_size: *mut usize,
+        _client_data: *mut *mut c_void,
+    ) -> efi::Status {
+        let keys = unsafe { slice::from_raw_parts_mut(keys, *count as usize) };
+        let mut status = efi::Status::SUCCESS;
+        for key in keys {
+            key.key_status = match key_size(&key.key_format) {
+                Some(size) if key.key_format != FORMAT_GENERIC_256_GUID => {
+                    unsafe { ptr::write_bytes(key.key_value as *mut u8, key.key_identifier_size, size) };
+                    efi::Status::SUCCESS
+                }
+                _ => {
+                    status = efi::Status::UNSUPPORTED;
+                    efi::Status::UNSUPPORTED
+                }
+            };
+        }
+        status
+    }
+
+    fn protocol(key_formats: &mut [efi::Guid]) -> Protocol {
+        let key_operation = unimplemented_service();
+        let key_attributes_operation = unimplemented_service();
+        Protocol {
+            get_service_status,
+            register_client,
+            create_key: key_operation,
+            get_key,
+            add_key: key_operation,
+            delete_key: key_operation,
+            get_key_attributes: key_attributes_operation,
+            add_key_attributes: key_attributes_operation,
+            delete_key_attributes: key_attributes_operation,
+            get_key_by_attributes: unimplemented_service(),
+            protocol_version: 0x00020040,
+            service_id: PROTOCOL_GUID,
+            service_name: ptr::null_mut(),
+            service_version: 3,
+            service_available: efi::Boolean::TRUE,
+            client_id_supported: efi::Boolean::TRUE,
+            client_id_required: efi::Boolean::FALSE,
+            client_id_max_size: 16,
+            client_name_string_types: DATA_TYPE_ASCII | DATA_TYPE_UNICODE,
+            client_name_required: efi::Boolean::FALSE,
+            client_name_max_count: 32,
+            client_data_supported: efi::Boolean::FALSE,
+            client_data_max_size: 0,
+            key_id_variable_len_supported: efi::Boolean::TRUE,
+            key_id_max_size: 255,
+            key_formats_count: key_formats.len(),
+            key_formats: key_formats.as_mut_ptr(),
+            key_attributes_supported: efi::Boolean::FALSE,
+            key_attribute_id_string_types: DATA_TYPE_NONE,
+            key_attribute_id_max_count: 0,
+            key_attributes_count: 0,
+            key_attributes: ptr::null_mut(),
+        }
+    }
+
+    #[test]
+    fn test_service() {
+        let mut formats = [FORMAT_GENERIC_128_GUID, FORMAT_AESXTS_256_GUID];
+        let mut protocol = protocol(&mut formats);
+        let kms = unsafe { Kms::new(&mut protocol) };
+        assert_eq!((kms.service_id(), kms.service_version()), (PROTOCOL_GUID, 3));
+        assert!(kms.service_available());
+        assert_eq!(kms.key_formats(), [FORMAT_GENERIC_128_GUID, FORMAT_AESXTS_256_GUID]);
+        assert_eq!(kms.service_status(), Ok(()));
+
+        let name: Vec<u16> = "Line 3".encode_utf16().collect();
+        let client = Client { id: b"tool", name: ClientName::Unicode(&name) };
+        assert_eq!(kms.register_client(&client), Ok(()));
+        let too_long = [0u8; 0x10000];
+        assert_eq!(
+            kms.register_client(&Client { id: &too_long, name: ClientName::None }),
+            Err(efi::Status::INVALID_PARAMETER)
+        );
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut formats = [];
+        let mut protocol = protocol(&mut formats);
+        let kms = unsafe { Kms::new(&mut protocol) };
+        let client = Client { id: &[], name: ClientName::Ascii(b"tool") };
+
+        let mut xts = [0u8; 32];
+        let mut generic = [0u8; 32];
+        let mut keys = [
+            KeyDescriptor::new(b"disk", FORMAT_AESXTS_128_GUID, &mut xts).unwrap(),
+            KeyDescriptor::new(b"other", FORMAT_GENERIC_256_GUID, &mut generic).unwrap(),
+        ];
+        assert_eq!(keys[0].status(), efi::Status::NOT_READY);
+        assert_eq!(kms.get_keys(&client, &mut keys), Err(efi::Status::UNSUPPORTED));
+        assert_eq!((keys[0].status(), keys[1].status()), (efi::Status::SUCCESS, efi::Status::UNSUPPORTED));
+        assert_eq!(keys[1].format(), FORMAT_GENERIC_256_GUID);
+        assert_eq!(xts, [4; 32]);
+        assert_eq!(kms.add_keys(&client, &mut []), Err(efi::Status::UNSUPPORTED));
+
+        let mut short = [0u8; 16];
+        assert_eq!(
+            KeyDescriptor::new(b"", FORMAT_AESXTS_128_GUID, &mut short).unwrap_err(),
+            efi::Status::INVALID_PARAMETER
+        );
+        let unknown = efi::Guid::from_fields(1, 2, 3, 4, 5, &[6; 6]);
+        assert_eq!(KeyDescriptor::new(b"", unknown, &mut short).unwrap_err(), efi::Status::UNSUPPORTED);
+        assert!(unsafe { KeyDescriptor::with_format(b"", unknown, &mut short) }.is_ok());
+    }
+
+    #[test]
+    fn test_locate() {
+        let boot_services = mock_efi_boot_services();
+        assert!(matches!(Kms::locate(&boot_services), Err(efi::Status::UNSUPPORTED)));
+    }
+}
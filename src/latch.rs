@@ -0,0 +1,183 @@
+//! Rendezvous of asynchronous callbacks.
+//!
+//! Waiting for several drivers to report readiness usually means a counter shared by their notification functions,
+//! and the last one to decrement it running the follow-up work at whatever TPL it happens to be at. [`EventLatch`]
+//! counts the arrivals atomically and signals an event when the last one arrives, so the continuation always runs
+//! from the event notification at `efi::TPL_CALLBACK`.
+//!
+use alloc::boxed::Box;
+use core::{
+    cell::RefCell,
+    ffi::c_void,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use r_efi::efi;
+
+use crate::event::{EventBuilder, SignalEvent};
+
+type Continuation = RefCell<Option<Box<dyn FnOnce()>>>;
+
+/// Latch running a continuation once a number of arrivals have been counted.
+///
+/// [`EventLatch::arrive`] may be called at any TPL up to `efi::TPL_HIGH_LEVEL`. The continuation runs once, from an
+/// event notification at `efi::TPL_CALLBACK`, after the last arrival. It does not run if the latch is dropped first.
+///
+/// # Example
+/// ```no_run
+/// use std::rc::Rc;
+/// use mu_rust_helpers::{latch::EventLatch, protocol_notify::ProtocolNotify};
+/// use r_efi::{efi, protocols::{block_io, simple_file_system}};
+///
+/// fn when_storage_ready(boot_services: &'static efi::BootServices, connect: fn()) -> Result<(), efi::Status> {
+///     let latch = Rc::new(EventLatch::new(boot_services, 2, connect)?);
+///     // The two notifications run at different TPLs.
+///     let block_io_guid = &block_io::PROTOCOL_GUID;
+///     let block_io_latch = latch.clone();
+///     let _block_io = ProtocolNotify::with_callback(boot_services, block_io_guid, efi::TPL_CALLBACK, move |_| {
+///         block_io_latch.arrive();
+///     })?;
+///     let file_system_guid = &simple_file_system::PROTOCOL_GUID;
+///     let _file_system = ProtocolNotify::with_callback(boot_services, file_system_guid, efi::TPL_NOTIFY, move |_| {
+///         latch.arrive();
+///     })?;
+///     // ...
+///     Ok(())
+/// }
+/// ```
+pub struct EventLatch<'a> {
+    // Declared first so that the event is closed before the continuation it runs is dropped.
+    event: SignalEvent<'a>,
+    _continuation: Box<Continuation>,
+    remaining: AtomicUsize,
+}
+
+impl<'a> EventLatch<'a> {
+    /// Create a latch running `continuation` after `count` arrivals, or as soon as possible if `count` is zero.
+    ///
+    /// The continuation must not borrow anything, since the latch may be leaked with `mem::forget` and run it later.
+    pub fn new(
+        boot_services: &'a efi::BootServices,
+        count: usize,
+        continuation: impl FnOnce() + 'static,
+    ) -> Result<Self, efi::Status> {
+        extern "efiapi" fn released(_event: efi::Event, context: *mut c_void) {
+            // SAFETY: the context is the boxed continuation owned by the `EventLatch`, which closes this event before
+            // dropping it.
+            let continuation = unsafe { &*(context as *const Continuation) };
+            let continuation = continuation.try_borrow_mut().ok().and_then(|mut continuation| continuation.take());
+            if let Some(continuation) = continuation {
+                continuation();
+            }
+        }
+
+        let continuation: Box<Continuation> = Box::new(RefCell::new(Some(Box::new(continuation) as Box<dyn FnOnce()>)));
+        let context = &*continuation as *const Continuation as *mut c_void;
+        let event = EventBuilder::new()
+            .notify_signal()
+            .tpl(efi::TPL_CALLBACK)
+            .callback(released, context)
+            .create(boot_services)?;
+        let latch = Self { event, _continuation: continuation, remaining: AtomicUsize::new(count) };
+        if count == 0 {
+            latch.event.signal()?;
+        }
+        Ok(latch)
+    }
+
+    /// Count one arrival, and return true if it was the last one.
+    ///
+    /// Arrivals after the last one are ignored and return false.
+    pub fn arrive(&self) -> bool {
+        let released =
+            self.remaining.fetch_update(Ordering::AcqRel, Ordering::Acquire, |remaining| remaining.checked_sub(1))
+                == Ok(1);
+        if released {
+            // Signaling a valid event cannot fail.
+            let _ = self.event.signal();
+        }
+        released
+    }
+
+    /// Return the number of arrivals still expected.
+    pub fn remaining(&self) -> usize {
+        self.remaining.load(Ordering::Acquire)
+    }
+
+    /// Return true once the last arrival has been counted. The continuation may not have run yet.
+    pub fn is_released(&self) -> bool {
+        self.remaining() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use alloc::rc::Rc;
+    use core::cell::Cell;
+
+    use crate::test_support::mock_efi_boot_services;
+
+    std::thread_local! {
+        static NOTIFY: Cell<Option<(efi::EventNotify, usize)>> = const { Cell::new(None) };
+        static CLOSED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    extern "efiapi" fn create_event(
+        event_type: u32,
+        tpl: efi::Tpl,
+        notify: Option<efi::EventNotify>,
+        context: *mut c_void,
+        event: *mut efi::Event,
+    ) -> efi::Status {
+        assert_eq!((event_type, tpl), (efi::EVT_NOTIFY_SIGNAL, efi::TPL_CALLBACK));
+        NOTIFY.with(|cell| cell.set(Some((notify.unwrap(), context as usize))));
+        unsafe { *event = 0x1a7 as efi::Event };
+        efi::Status::SUCCESS
+    }
+
+    // Runs the notification right away, as if the TPL was dropped below TPL_CALLBACK.
+    extern "efiapi" fn signal_event(event: efi::Event) -> efi::Status {
+        assert_eq!(event as usize, 0x1a7);
+        let (notify, context) = NOTIFY.with(|cell| cell.get()).unwrap();
+        notify(event, context as *mut c_void);
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn close_event(event: efi::Event) -> efi::Status {
+        assert_eq!(event as usize, 0x1a7);
+        CLOSED.with(|closed| closed.set(closed.get() + 1));
+        efi::Status::SUCCESS
+    }
+
+    #[test]
+    fn test_latch() {
+        let boot_services = efi::BootServices { create_event, signal_event, close_event, ..mock_efi_boot_services() };
+        let runs = Rc::new(Cell::new(0));
+        let counter = runs.clone();
+        let latch = EventLatch::new(&boot_services, 3, move || counter.set(counter.get() + 1)).unwrap();
+        assert!(!latch.arrive());
+        assert!(!latch.arrive());
+        assert_eq!((latch.remaining(), latch.is_released(), runs.get()), (1, false, 0));
+        assert!(latch.arrive());
+        assert_eq!((latch.remaining(), latch.is_released(), runs.get()), (0, true, 1));
+        // Late arrivals neither wrap the count nor run the continuation again.
+        assert!(!latch.arrive());
+        assert_eq!((latch.remaining(), runs.get()), (0, 1));
+
+        drop(latch);
+        assert_eq!(CLOSED.with(|closed| closed.get()), 1);
+    }
+
+    #[test]
+    fn test_zero_count() {
+        let boot_services = efi::BootServices { create_event, signal_event, close_event, ..mock_efi_boot_services() };
+        let ran = Rc::new(Cell::new(false));
+        let flag = ran.clone();
+        let latch = EventLatch::new(&boot_services, 0, move || flag.set(true)).unwrap();
+        assert!(ran.get());
+        assert!(latch.is_released());
+        assert!(!latch.arrive());
+    }
+}
@@ -0,0 +1,244 @@
+//! Little-endian cursors over byte buffers.
+//!
+//! Firmware structures such as load options, signature lists, GPT headers and SMBIOS records are little-endian and
+//! often unaligned. [`LeReader`] and [`LeWriter`] decode and encode them field by field with bounds checks, returning
+//! an error instead of panicking on truncated or oversized data.
+//!
+use alloc::{string::String, vec::Vec};
+use core::mem;
+
+use r_efi::efi;
+
+use crate::ucs2;
+
+/// Cursor reading little-endian values from a byte slice.
+///
+/// Reads past the end of the slice return `efi::Status::BAD_BUFFER_SIZE` and leave the cursor unchanged.
+///
+/// # Example
+/// ```
+/// use mu_rust_helpers::le_cursor::LeReader;
+///
+/// let mut reader = LeReader::new(&[0x01, 0x00, 0x00, 0x00, 0x34, 0x12]);
+/// assert_eq!(reader.read_u32(), Ok(1));
+/// assert_eq!(reader.read_u16(), Ok(0x1234));
+/// assert!(reader.is_empty());
+/// ```
+#[derive(Debug, Clone)]
+pub struct LeReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> LeReader<'a> {
+    /// Create a reader at the start of `bytes`.
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    /// Return the offset of the next read.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Return the bytes that have not been read.
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.bytes[self.position..]
+    }
+
+    /// Return true if every byte has been read.
+    pub fn is_empty(&self) -> bool {
+        self.remaining().is_empty()
+    }
+
+    /// Read the next `len` bytes.
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], efi::Status> {
+        let bytes = self.remaining().get(..len).ok_or(efi::Status::BAD_BUFFER_SIZE)?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    /// Skip the next `len` bytes.
+    pub fn skip(&mut self, len: usize) -> Result<(), efi::Status> {
+        self.read_bytes(len).map(|_| ())
+    }
+
+    /// Read the next `N` bytes as an array.
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], efi::Status> {
+        Ok(self.read_bytes(N)?.try_into().unwrap())
+    }
+
+    /// Read a `u8`.
+    pub fn read_u8(&mut self) -> Result<u8, efi::Status> {
+        self.read_array().map(u8::from_le_bytes)
+    }
+
+    /// Read a little-endian `u16`.
+    pub fn read_u16(&mut self) -> Result<u16, efi::Status> {
+        self.read_array().map(u16::from_le_bytes)
+    }
+
+    /// Read a little-endian `u32`.
+    pub fn read_u32(&mut self) -> Result<u32, efi::Status> {
+        self.read_array().map(u32::from_le_bytes)
+    }
+
+    /// Read a little-endian `u64`.
+    pub fn read_u64(&mut self) -> Result<u64, efi::Status> {
+        self.read_array().map(u64::from_le_bytes)
+    }
+
+    /// Read a GUID in its EFI binary layout.
+    pub fn read_guid(&mut self) -> Result<efi::Guid, efi::Status> {
+        self.read_array().map(|bytes| efi::Guid::from_bytes(&bytes))
+    }
+
+    /// Read a null-terminated UCS-2 string, consuming its terminator.
+    ///
+    /// Returns `efi::Status::BAD_BUFFER_SIZE` if there is no terminator. Unpaired surrogates are replaced with
+    /// `char::REPLACEMENT_CHARACTER`.
+    pub fn read_ucs2(&mut self) -> Result<String, efi::Status> {
+        let remaining = self.remaining();
+        let len = remaining
+            .chunks_exact(mem::size_of::<u16>())
+            .position(|c| c == [0, 0])
+            .ok_or(efi::Status::BAD_BUFFER_SIZE)?;
+        let bytes = self.read_bytes((len + 1) * mem::size_of::<u16>())?;
+        let chars: Vec<u16> = bytes.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+        Ok(ucs2::decode(&chars).collect())
+    }
+}
+
+/// Cursor writing little-endian values into a byte slice.
+///
+/// Writes past the end of the slice return `efi::Status::BUFFER_TOO_SMALL` and leave the cursor unchanged.
+///
+/// # Example
+/// ```
+/// use mu_rust_helpers::le_cursor::LeWriter;
+///
+/// let mut buffer = [0; 6];
+/// let mut writer = LeWriter::new(&mut buffer);
+/// writer.write_u32(1).unwrap();
+/// writer.write_u16(0x1234).unwrap();
+/// assert_eq!(buffer, [0x01, 0x00, 0x00, 0x00, 0x34, 0x12]);
+/// ```
+#[derive(Debug)]
+pub struct LeWriter<'a> {
+    bytes: &'a mut [u8],
+    position: usize,
+}
+
+impl<'a> LeWriter<'a> {
+    /// Create a writer at the start of `bytes`.
+    pub fn new(bytes: &'a mut [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    /// Return the offset of the next write, which is the number of bytes written.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Return the bytes written so far.
+    pub fn written(&self) -> &[u8] {
+        &self.bytes[..self.position]
+    }
+
+    /// Return the number of bytes that can still be written.
+    pub fn remaining_len(&self) -> usize {
+        self.bytes.len() - self.position
+    }
+
+    /// Write `bytes`.
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), efi::Status> {
+        let end = self.position.checked_add(bytes.len()).ok_or(efi::Status::BUFFER_TOO_SMALL)?;
+        self.bytes.get_mut(self.position..end).ok_or(efi::Status::BUFFER_TOO_SMALL)?.copy_from_slice(bytes);
+        self.position = end;
+        Ok(())
+    }
+
+    /// Write a `u8`.
+    pub fn write_u8(&mut self, value: u8) -> Result<(), efi::Status> {
+        self.write_bytes(&value.to_le_bytes())
+    }
+
+    /// Write a little-endian `u16`.
+    pub fn write_u16(&mut self, value: u16) -> Result<(), efi::Status> {
+        self.write_bytes(&value.to_le_bytes())
+    }
+
+    /// Write a little-endian `u32`.
+    pub fn write_u32(&mut self, value: u32) -> Result<(), efi::Status> {
+        self.write_bytes(&value.to_le_bytes())
+    }
+
+    /// Write a little-endian `u64`.
+    pub fn write_u64(&mut self, value: u64) -> Result<(), efi::Status> {
+        self.write_bytes(&value.to_le_bytes())
+    }
+
+    /// Write a GUID in its EFI binary layout.
+    pub fn write_guid(&mut self, guid: &efi::Guid) -> Result<(), efi::Status> {
+        self.write_bytes(guid.as_bytes())
+    }
+
+    /// Write `string` as a null-terminated UCS-2 string.
+    ///
+    /// Returns `efi::Status::INVALID_PARAMETER` if `string` cannot be represented in UCS-2, without writing anything.
+    pub fn write_ucs2(&mut self, string: &str) -> Result<(), efi::Status> {
+        if string.chars().any(|c| c == '\0' || c as u32 > 0xffff) {
+            return Err(efi::Status::INVALID_PARAMETER);
+        }
+        let size = (string.chars().count() + 1) * mem::size_of::<u16>();
+        if size > self.remaining_len() {
+            return Err(efi::Status::BUFFER_TOO_SMALL);
+        }
+        string.chars().map(|c| c as u16).chain([0]).try_for_each(|c| self.write_u16(c))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUID: efi::Guid =
+        efi::Guid::from_fields(0x8be4df61, 0x93ca, 0x11d2, 0xaa, 0x0d, &[0x00, 0xe0, 0x98, 0x03, 0x2b, 0x8c]);
+
+    #[test]
+    fn test_round_trip() {
+        let mut buffer = [0xff; 40];
+        let mut writer = LeWriter::new(&mut buffer);
+        writer.write_u8(7).unwrap();
+        writer.write_u64(0x0102_0304_0506_0708).unwrap();
+        writer.write_guid(&GUID).unwrap();
+        writer.write_ucs2("Boot\u{e9}").unwrap();
+        assert_eq!(writer.position(), 37);
+        assert_eq!(writer.write_u32(0), Err(efi::Status::BUFFER_TOO_SMALL));
+        assert_eq!(writer.write_ucs2("x"), Err(efi::Status::BUFFER_TOO_SMALL));
+        assert_eq!(writer.write_ucs2("\u{1f4be}"), Err(efi::Status::INVALID_PARAMETER));
+        assert_eq!(writer.remaining_len(), 3);
+        assert_eq!(&writer.written()[1..3], [0x08, 0x07]);
+
+        let mut reader = LeReader::new(&buffer);
+        assert_eq!(reader.read_u8(), Ok(7));
+        assert_eq!(reader.read_u64(), Ok(0x0102_0304_0506_0708));
+        assert_eq!(reader.read_guid(), Ok(GUID));
+        assert_eq!(reader.read_ucs2().as_deref(), Ok("Boot\u{e9}"));
+        assert_eq!(reader.position(), 37);
+        assert_eq!(reader.read_u32(), Err(efi::Status::BAD_BUFFER_SIZE));
+        assert_eq!(reader.read_u16(), Ok(0xffff));
+        assert_eq!(reader.remaining(), [0xff]);
+    }
+
+    #[test]
+    fn test_truncated_string() {
+        let mut reader = LeReader::new(&[b'A', 0, b'B', 0, 0]);
+        assert_eq!(reader.read_ucs2(), Err(efi::Status::BAD_BUFFER_SIZE));
+        assert_eq!(reader.position(), 0);
+        reader.skip(2).unwrap();
+        assert_eq!(reader.read_bytes(4), Err(efi::Status::BAD_BUFFER_SIZE));
+        assert_eq!(reader.read_bytes(3), Ok(&[b'B', 0, 0][..]));
+        assert!(reader.is_empty());
+    }
+}
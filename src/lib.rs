@@ -2,7 +2,60 @@
 
 extern crate alloc;
 
+pub mod abi_bridge;
+pub mod acpi_sdt;
+pub mod allocation;
+pub mod aml;
+pub mod block_cache;
+pub mod block_io2;
+pub mod boot_timing;
+pub mod buffer;
+pub mod build_metadata;
+pub mod config_table;
+pub mod connect;
+pub mod controller_resources;
+pub mod deferred_image;
+pub mod driver_health;
+pub mod event;
+pub mod fat_path;
+pub mod firmware_slice;
+pub mod format;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
+pub mod guid_name;
+pub mod handle_db;
+pub mod handles;
+pub mod image;
+pub mod interface_hub;
+pub mod interop_registry;
+pub mod kms;
+pub mod latch;
+pub mod le_cursor;
 pub mod macros;
+pub mod mem_services;
+pub mod memory_map;
+pub mod memory_type;
+pub mod open_protocol;
+pub mod polling_driver;
+pub mod preserved_region;
+pub mod protocol;
+pub mod protocol_assert;
+pub mod protocol_cache;
+pub mod protocol_notify;
+pub mod retry;
+pub mod shell_command;
+pub mod system_table;
+#[cfg(test)]
+pub(crate) mod test_support;
+pub mod timer;
+pub mod ucs2;
+pub mod units;
+pub mod user_auth;
+pub mod watchdog;
+pub mod work_queue;
+
+#[cfg(feature = "executor")]
+pub use executor;
 
 #[cfg(feature = "guid")]
 pub use guid;
@@ -12,3 +65,9 @@ pub use uefi_decompress;
 
 #[cfg(feature = "perf_timer")]
 pub use perf_timer;
+
+#[cfg(feature = "runtime_services")]
+pub use runtime_services;
+
+#[cfg(feature = "sync")]
+pub use sync;
@@ -0,0 +1,112 @@
+//! Bulk memory operations through the CopyMem and SetMem boot services.
+//!
+//! Firmware may implement CopyMem and SetMem with hardware acceleration or tuned assembly, which can be much faster
+//! than a byte loop for large buffers. These helpers apply them to typed slices.
+//!
+use core::{ffi::c_void, mem};
+
+use r_efi::efi;
+
+/// Copy `source` into `destination` with CopyMem.
+///
+/// Returns `efi::Status::INVALID_PARAMETER` if the slices have different lengths.
+pub fn copy_mem_slice<T: Copy>(
+    boot_services: &efi::BootServices,
+    destination: &mut [T],
+    source: &[T],
+) -> Result<(), efi::Status> {
+    if destination.len() != source.len() {
+        return Err(efi::Status::INVALID_PARAMETER);
+    }
+    if destination.is_empty() {
+        return Ok(());
+    }
+    (boot_services.copy_mem)(
+        destination.as_mut_ptr() as *mut c_void,
+        source.as_ptr() as *mut c_void,
+        mem::size_of_val(source),
+    );
+    Ok(())
+}
+
+/// Fill `destination` with `value`.
+///
+/// Byte slices are filled with SetMem. Other types are filled by writing `value` once and copying the filled prefix
+/// with CopyMem, doubling it until the slice is full.
+pub fn fill_mem<T: Copy>(boot_services: &efi::BootServices, destination: &mut [T], value: T) {
+    let Some(first) = destination.first_mut() else {
+        return;
+    };
+    let size = mem::size_of::<T>();
+    if size == 1 {
+        // SAFETY: `T` is a single byte with no padding, and `value` is initialized.
+        let byte = unsafe { *(&value as *const T as *const u8) };
+        (boot_services.set_mem)(destination.as_mut_ptr() as *mut c_void, destination.len(), byte);
+        return;
+    }
+
+    *first = value;
+    let mut filled = 1;
+    while filled < destination.len() {
+        let count = filled.min(destination.len() - filled);
+        let base = destination.as_mut_ptr();
+        // SAFETY: both ranges are in bounds and do not overlap: the source is the first `count` elements, already
+        // filled, and the destination starts past the filled prefix.
+        (boot_services.copy_mem)(unsafe { base.add(filled) } as *mut c_void, base as *mut c_void, count * size);
+        filled += count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::Cell;
+
+    use crate::test_support::mock_efi_boot_services;
+
+    std::thread_local! {
+        static COPIES: Cell<usize> = const { Cell::new(0) };
+    }
+
+    extern "efiapi" fn copy_mem(destination: *mut c_void, source: *mut c_void, length: usize) {
+        COPIES.with(|copies| copies.set(copies.get() + 1));
+        unsafe { core::ptr::copy(source as *const u8, destination as *mut u8, length) };
+    }
+
+    extern "efiapi" fn set_mem(buffer: *mut c_void, size: usize, value: u8) {
+        unsafe { core::ptr::write_bytes(buffer as *mut u8, value, size) };
+    }
+
+    fn boot_services() -> efi::BootServices {
+        efi::BootServices { copy_mem, set_mem, ..mock_efi_boot_services() }
+    }
+
+    #[test]
+    fn test_copy_mem_slice() {
+        let boot_services = boot_services();
+        let source = [1u32, 2, 3, 4];
+        let mut destination = [0u32; 4];
+        copy_mem_slice(&boot_services, &mut destination, &source).unwrap();
+        assert_eq!(destination, source);
+        assert_eq!(copy_mem_slice(&boot_services, &mut destination[..3], &source), Err(efi::Status::INVALID_PARAMETER));
+        copy_mem_slice::<u32>(&boot_services, &mut [], &[]).unwrap();
+    }
+
+    #[test]
+    fn test_fill_mem() {
+        let boot_services = boot_services();
+        let mut bytes = [0u8; 7];
+        fill_mem(&boot_services, &mut bytes, 0xa5);
+        assert_eq!(bytes, [0xa5; 7]);
+        assert_eq!(COPIES.with(|copies| copies.get()), 0);
+
+        let mut values = [(0u16, 0u64); 11];
+        fill_mem(&boot_services, &mut values, (0x1234, 0xdead_beef));
+        assert_eq!(values, [(0x1234, 0xdead_beef); 11]);
+        // 1 + 1 + 2 + 4 + 3 elements.
+        assert_eq!(COPIES.with(|copies| copies.get()), 4);
+
+        fill_mem(&boot_services, &mut [0u64; 0], 1);
+    }
+}
@@ -0,0 +1,570 @@
+//! Memory map snapshots that outlive ExitBootServices.
+//!
+//! Loaders need the memory map exactly when boot services go away: its key is passed to ExitBootServices, and its
+//! descriptors are handed to the OS afterwards. A map stored in pool memory may be reclaimed at that point, so
+//! [`OwnedMemoryMap`] stores the descriptors in caller-provided storage, or in `LoaderData` pages that remain owned by
+//! the loader after ExitBootServices.
+//!
+//! Descriptors are read using the descriptor size reported by the firmware, which may be larger than
+//! `efi::MemoryDescriptor`. [`exit_boot_services`] performs the GetMemoryMap/ExitBootServices sequence and returns
+//! the final map. [`OwnedMemoryMap::diff`] compares two snapshots, e.g. to find the allocations of a driver.
+//!
+use alloc::vec::Vec;
+use core::{
+    fmt, mem,
+    ops::{Deref, DerefMut},
+    ptr, slice,
+};
+
+use r_efi::efi;
+
+use crate::{
+    memory_type::MemoryType,
+    units::{ByteCount, PageCount, PhysicalAddress},
+};
+
+/// Snapshot of the memory map stored in memory owned by the caller.
+#[derive(Debug)]
+pub struct OwnedMemoryMap<'a> {
+    buffer: &'a mut [u8],
+    size: usize,
+    map_key: usize,
+    descriptor_size: usize,
+    descriptor_version: u32,
+}
+
+impl<'a> OwnedMemoryMap<'a> {
+    /// Read the current memory map into `buffer`.
+    ///
+    /// On failure, the status is returned along with the map size reported by the firmware, which is the required
+    /// buffer size when the status is `efi::Status::BUFFER_TOO_SMALL`. Returns `efi::Status::INCOMPATIBLE_VERSION` if
+    /// the firmware descriptors are smaller than `efi::MemoryDescriptor`.
+    pub fn new(boot_services: &efi::BootServices, buffer: &'a mut [u8]) -> Result<Self, (efi::Status, usize)> {
+        let mut size = buffer.len();
+        let mut map_key = 0;
+        let mut descriptor_size = 0;
+        let mut descriptor_version = 0;
+        let status = (boot_services.get_memory_map)(
+            &mut size,
+            buffer.as_mut_ptr() as *mut efi::MemoryDescriptor,
+            &mut map_key,
+            &mut descriptor_size,
+            &mut descriptor_version,
+        );
+        if status.is_error() {
+            return Err((status, size));
+        }
+        if descriptor_size < mem::size_of::<efi::MemoryDescriptor>() || size > buffer.len() {
+            return Err((efi::Status::INCOMPATIBLE_VERSION, size));
+        }
+        Ok(Self { buffer, size, map_key, descriptor_size, descriptor_version })
+    }
+
+    /// Return the key identifying this snapshot, to be passed to ExitBootServices.
+    pub fn map_key(&self) -> usize {
+        self.map_key
+    }
+
+    /// Return the size in bytes of each descriptor.
+    pub fn descriptor_size(&self) -> usize {
+        self.descriptor_size
+    }
+
+    /// Return the version of the descriptors.
+    pub fn descriptor_version(&self) -> u32 {
+        self.descriptor_version
+    }
+
+    /// Return the descriptors as raw bytes, e.g. to hand them to the OS.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer[..self.size]
+    }
+
+    /// Return the number of descriptors.
+    pub fn len(&self) -> usize {
+        self.size / self.descriptor_size
+    }
+
+    /// Return true if the map has no descriptors.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the descriptor at `index`.
+    pub fn get(&self, index: usize) -> Option<efi::MemoryDescriptor> {
+        if index >= self.len() {
+            return None;
+        }
+        let descriptor = &self.buffer[index * self.descriptor_size..][..mem::size_of::<efi::MemoryDescriptor>()];
+        // SAFETY: the slice holds a descriptor written by the firmware, which may not be aligned.
+        Some(unsafe { ptr::read_unaligned(descriptor.as_ptr() as *const efi::MemoryDescriptor) })
+    }
+
+    /// Return the descriptor of the region containing `address`, if any.
+    pub fn find(&self, address: PhysicalAddress) -> Option<efi::MemoryDescriptor> {
+        self.iter().find(|descriptor| {
+            let start = PhysicalAddress::new(descriptor.physical_start);
+            let size = PageCount::new(descriptor.number_of_pages).to_bytes();
+            // Regions ending beyond the address space are only bounded by it.
+            let contains_end = match size.and_then(|size| start.checked_add(size)) {
+                Some(end) => address < end,
+                None => true,
+            };
+            address >= start && contains_end
+        })
+    }
+
+    /// Return an iterator over the descriptors.
+    pub fn iter(&self) -> impl Iterator<Item = efi::MemoryDescriptor> + '_ {
+        (0..self.len()).filter_map(|index| self.get(index))
+    }
+
+    /// Return an iterator over the descriptors that allows modifying them, e.g. to set the virtual addresses or
+    /// attributes before SetVirtualAddressMap.
+    ///
+    /// Changes are written back to the map when each [`DescriptorMut`] is dropped.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = DescriptorMut<'_>> + '_ {
+        let descriptor_size = self.descriptor_size;
+        self.buffer[..self.size].chunks_exact_mut(descriptor_size).map(DescriptorMut::new)
+    }
+
+    /// Compare two snapshots, e.g. taken before and after loading a driver.
+    ///
+    /// Descriptors are matched by their physical start address.
+    pub fn diff(old: &OwnedMemoryMap, new: &OwnedMemoryMap) -> MemoryMapDiff {
+        let mut old_descriptors: Vec<_> = old.iter().collect();
+        let mut new_descriptors: Vec<_> = new.iter().collect();
+        old_descriptors.sort_unstable_by_key(|descriptor| descriptor.physical_start);
+        new_descriptors.sort_unstable_by_key(|descriptor| descriptor.physical_start);
+
+        let mut changes = Vec::new();
+        let mut old_descriptors = old_descriptors.into_iter().peekable();
+        let mut new_descriptors = new_descriptors.into_iter().peekable();
+        loop {
+            let change = match (old_descriptors.peek(), new_descriptors.peek()) {
+                (None, None) => break,
+                (Some(_), None) => MemoryMapChange::Removed(old_descriptors.next().unwrap()),
+                (None, Some(_)) => MemoryMapChange::Added(new_descriptors.next().unwrap()),
+                (Some(old), Some(new)) if old.physical_start < new.physical_start => {
+                    MemoryMapChange::Removed(old_descriptors.next().unwrap())
+                }
+                (Some(old), Some(new)) if old.physical_start > new.physical_start => {
+                    MemoryMapChange::Added(new_descriptors.next().unwrap())
+                }
+                (Some(_), Some(_)) => {
+                    let (old, new) = (old_descriptors.next().unwrap(), new_descriptors.next().unwrap());
+                    if (old.r#type, old.number_of_pages, old.attribute, old.virtual_start)
+                        == (new.r#type, new.number_of_pages, new.attribute, new.virtual_start)
+                    {
+                        continue;
+                    }
+                    MemoryMapChange::Changed { old, new }
+                }
+            };
+            changes.push(change);
+        }
+        MemoryMapDiff { changes }
+    }
+
+    fn into_buffer(self) -> &'a mut [u8] {
+        self.buffer
+    }
+}
+
+/// Mutable access to a descriptor of an [`OwnedMemoryMap`].
+///
+/// Descriptors may not be aligned in the map, so this holds a copy of the descriptor that is written back on drop.
+pub struct DescriptorMut<'a> {
+    bytes: &'a mut [u8],
+    descriptor: efi::MemoryDescriptor,
+}
+
+impl<'a> DescriptorMut<'a> {
+    fn new(bytes: &'a mut [u8]) -> Self {
+        // SAFETY: the map only hands out chunks of at least `size_of::<MemoryDescriptor>()` bytes.
+        let descriptor = unsafe { ptr::read_unaligned(bytes.as_ptr() as *const efi::MemoryDescriptor) };
+        Self { bytes, descriptor }
+    }
+}
+
+impl Deref for DescriptorMut<'_> {
+    type Target = efi::MemoryDescriptor;
+
+    fn deref(&self) -> &Self::Target {
+        &self.descriptor
+    }
+}
+
+impl DerefMut for DescriptorMut<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.descriptor
+    }
+}
+
+impl Drop for DescriptorMut<'_> {
+    fn drop(&mut self) {
+        // SAFETY: see `DescriptorMut::new`.
+        unsafe { ptr::write_unaligned(self.bytes.as_mut_ptr() as *mut efi::MemoryDescriptor, self.descriptor) };
+    }
+}
+
+/// Difference between two memory map snapshots, see [`OwnedMemoryMap::diff`].
+///
+/// The `Display` rendering lists one change per line, suitable for serial logs.
+#[derive(Debug, Clone)]
+pub struct MemoryMapDiff {
+    changes: Vec<MemoryMapChange>,
+}
+
+impl MemoryMapDiff {
+    /// Return the changes, ordered by physical address.
+    pub fn changes(&self) -> &[MemoryMapChange] {
+        &self.changes
+    }
+
+    /// Return true if the snapshots are identical.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl fmt::Display for MemoryMapDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.changes {
+            match change {
+                MemoryMapChange::Added(descriptor) => writeln!(f, "+ {}", DisplayDescriptor(descriptor))?,
+                MemoryMapChange::Removed(descriptor) => writeln!(f, "- {}", DisplayDescriptor(descriptor))?,
+                MemoryMapChange::Changed { old, new } => {
+                    writeln!(f, "~ {} -> {}", DisplayDescriptor(old), DisplayDescriptor(new))?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Change of a region between two memory map snapshots.
+#[derive(Debug, Clone, Copy)]
+pub enum MemoryMapChange {
+    /// A region that only exists in the new snapshot.
+    Added(efi::MemoryDescriptor),
+    /// A region that only exists in the old snapshot.
+    Removed(efi::MemoryDescriptor),
+    /// A region starting at the same address in both snapshots, with a different size, type or attributes.
+    Changed { old: efi::MemoryDescriptor, new: efi::MemoryDescriptor },
+}
+
+struct DisplayDescriptor<'a>(&'a efi::MemoryDescriptor);
+
+impl fmt::Display for DisplayDescriptor<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let descriptor = self.0;
+        let size = PageCount::new(descriptor.number_of_pages).to_bytes().unwrap_or(ByteCount::new(u64::MAX));
+        let end = descriptor.physical_start.wrapping_add(size.get());
+        write!(f, "[{:#018x}-{:#018x}) ", descriptor.physical_start, end.wrapping_sub(1))?;
+        match MemoryType::new(descriptor.r#type) {
+            Some(memory_type) => write!(f, "{memory_type}")?,
+            None => write!(f, "{:#010x}", descriptor.r#type)?,
+        }
+        write!(f, " pages={:#x} attr={:#x}", descriptor.number_of_pages, descriptor.attribute)
+    }
+}
+
+impl OwnedMemoryMap<'static> {
+    /// Read the current memory map into newly allocated `LoaderData` pages, which stay valid after ExitBootServices.
+    ///
+    /// The pages are never freed by the snapshot.
+    pub fn from_pages(boot_services: &efi::BootServices) -> Result<Self, efi::Status> {
+        let mut required = match Self::new(boot_services, &mut []) {
+            Ok(map) => return Ok(map),
+            Err((efi::Status::BUFFER_TOO_SMALL, required)) => required,
+            Err((status, _)) => return Err(status),
+        };
+
+        loop {
+            // Allocating the pages may split a region of the map, so leave room for a few more descriptors.
+            let size = ByteCount::from(required)
+                .checked_add(ByteCount::from(4 * mem::size_of::<efi::MemoryDescriptor>()))
+                .ok_or(efi::Status::BAD_BUFFER_SIZE)?;
+            let pages = size.to_pages_ceil();
+            let (Some(pages), Some(buffer_size)) = (pages.to_usize(), pages.to_bytes().and_then(ByteCount::to_usize))
+            else {
+                return Err(efi::Status::BAD_BUFFER_SIZE);
+            };
+            let mut address = 0;
+            let status = (boot_services.allocate_pages)(efi::ALLOCATE_ANY_PAGES, efi::LOADER_DATA, pages, &mut address);
+            if status.is_error() {
+                return Err(status);
+            }
+            if address == 0 {
+                // Page 0 is valid memory but not a valid Rust pointer.
+                (boot_services.free_pages)(address, pages);
+                return Err(efi::Status::OUT_OF_RESOURCES);
+            }
+
+            // SAFETY: the pages were just allocated for exclusive use by this snapshot and are never freed.
+            let buffer = unsafe { slice::from_raw_parts_mut(address as *mut u8, buffer_size) };
+            match Self::new(boot_services, buffer) {
+                Ok(map) => return Ok(map),
+                Err((efi::Status::BUFFER_TOO_SMALL, size)) => {
+                    (boot_services.free_pages)(address, pages);
+                    required = size;
+                }
+                Err((status, _)) => {
+                    (boot_services.free_pages)(address, pages);
+                    return Err(status);
+                }
+            }
+        }
+    }
+}
+
+/// Exit boot services, returning the memory map they were exited with.
+///
+/// The map is stored in `LoaderData` pages allocated up front. ExitBootServices fails if the map changed since it was
+/// read, in which case the map is read again into the same pages, without allocating, and the call is retried.
+/// Nothing else may run between the calls, so this must be called at `TPL_APPLICATION` with no other work pending.
+///
+/// On failure, the status of the last call is returned. Boot services may already be partially shut down if
+/// ExitBootServices failed, in which case only GetMemoryMap and ExitBootServices may be used afterwards.
+pub fn exit_boot_services(
+    boot_services: &efi::BootServices,
+    image_handle: efi::Handle,
+) -> Result<OwnedMemoryMap<'static>, efi::Status> {
+    const MAX_ATTEMPTS: usize = 8;
+
+    let mut map = OwnedMemoryMap::from_pages(boot_services)?;
+    let mut attempt = 1;
+    loop {
+        let status = (boot_services.exit_boot_services)(image_handle, map.map_key());
+        if !status.is_error() {
+            return Ok(map);
+        }
+        // INVALID_PARAMETER reports a stale map key. Anything else will not be fixed by refreshing the map.
+        if status != efi::Status::INVALID_PARAMETER || attempt == MAX_ATTEMPTS {
+            return Err(status);
+        }
+        attempt += 1;
+        map = OwnedMemoryMap::new(boot_services, map.into_buffer()).map_err(|(status, _)| status)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        alloc::{alloc_zeroed, Layout},
+        cell::Cell,
+    };
+
+    use crate::{test_support::mock_efi_boot_services, units::UEFI_PAGE_SIZE};
+
+    const TEST_DESCRIPTOR_SIZE: usize = 48;
+
+    std::thread_local! {
+        static MAP_KEY: Cell<usize> = const { Cell::new(0x42) };
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn test_descriptor(index: u64) -> efi::MemoryDescriptor {
+        efi::MemoryDescriptor {
+            r#type: efi::CONVENTIONAL_MEMORY,
+            physical_start: index * 0x10000,
+            virtual_start: 0,
+            number_of_pages: index + 1,
+            attribute: efi::MEMORY_WB,
+        }
+    }
+
+    extern "efiapi" fn get_memory_map(
+        size: *mut usize,
+        map: *mut efi::MemoryDescriptor,
+        map_key: *mut usize,
+        descriptor_size: *mut usize,
+        descriptor_version: *mut u32,
+    ) -> efi::Status {
+        let required = 3 * TEST_DESCRIPTOR_SIZE;
+        unsafe {
+            *descriptor_size = TEST_DESCRIPTOR_SIZE;
+            *descriptor_version = efi::MEMORY_DESCRIPTOR_VERSION;
+            if *size < required {
+                *size = required;
+                return efi::Status::BUFFER_TOO_SMALL;
+            }
+            *size = required;
+            *map_key = MAP_KEY.with(|key| key.get());
+            for index in 0..3 {
+                let descriptor = (map as *mut u8).add(index * TEST_DESCRIPTOR_SIZE) as *mut efi::MemoryDescriptor;
+                descriptor.write_unaligned(test_descriptor(index as u64));
+            }
+        }
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn allocate_pages(
+        _allocate_type: efi::AllocateType,
+        memory_type: efi::MemoryType,
+        pages: usize,
+        address: *mut efi::PhysicalAddress,
+    ) -> efi::Status {
+        assert_eq!(memory_type, efi::LOADER_DATA);
+        ALLOCATIONS.with(|allocations| allocations.set(allocations.get() + 1));
+        let size = PageCount::from_usize(pages).to_bytes().and_then(ByteCount::to_usize).unwrap();
+        let layout = Layout::from_size_align(size, UEFI_PAGE_SIZE as usize).unwrap();
+        unsafe { *address = alloc_zeroed(layout) as efi::PhysicalAddress };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn exit_boot_services(image_handle: efi::Handle, map_key: usize) -> efi::Status {
+        assert_eq!(image_handle as usize, 0x1a6e);
+        MAP_KEY.with(|key| {
+            if map_key != key.get() {
+                return efi::Status::INVALID_PARAMETER;
+            }
+            // Model a map that changes once more after it was first read.
+            if key.get() == 0x42 {
+                key.set(0x43);
+                return efi::Status::INVALID_PARAMETER;
+            }
+            efi::Status::SUCCESS
+        })
+    }
+
+    #[test]
+    fn test_caller_buffer() {
+        let boot_services = efi::BootServices { get_memory_map, ..mock_efi_boot_services() };
+
+        let mut small = [0u8; 64];
+        assert_eq!(
+            OwnedMemoryMap::new(&boot_services, &mut small).err(),
+            Some((efi::Status::BUFFER_TOO_SMALL, 3 * TEST_DESCRIPTOR_SIZE))
+        );
+
+        // Odd offset to exercise unaligned descriptor reads.
+        let mut buffer = [0u8; 256];
+        let map = OwnedMemoryMap::new(&boot_services, &mut buffer[1..]).unwrap();
+        assert_eq!(map.map_key(), 0x42);
+        assert_eq!(map.descriptor_size(), TEST_DESCRIPTOR_SIZE);
+        assert_eq!(map.descriptor_version(), efi::MEMORY_DESCRIPTOR_VERSION);
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.as_bytes().len(), 3 * TEST_DESCRIPTOR_SIZE);
+        let pages: Vec<u64> = map.iter().map(|descriptor| descriptor.number_of_pages).collect();
+        assert_eq!(pages, [1, 2, 3]);
+        assert_eq!(map.get(2).map(|descriptor| descriptor.physical_start), Some(0x20000));
+        assert!(map.get(3).is_none());
+        assert_eq!(map.find(PhysicalAddress::new(0x11fff)).map(|descriptor| descriptor.number_of_pages), Some(2));
+        assert_eq!(map.find(PhysicalAddress::new(0x20000)).map(|descriptor| descriptor.number_of_pages), Some(3));
+        assert!(map.find(PhysicalAddress::new(0x12000)).is_none());
+    }
+
+    #[test]
+    fn test_iter_mut() {
+        let boot_services = efi::BootServices { get_memory_map, ..mock_efi_boot_services() };
+        let mut buffer = [0xa5u8; 256];
+        let mut map = OwnedMemoryMap::new(&boot_services, &mut buffer[3..]).unwrap();
+        for mut descriptor in map.iter_mut() {
+            descriptor.virtual_start = descriptor.physical_start + 0x8000_0000;
+            descriptor.attribute |= efi::MEMORY_RUNTIME;
+        }
+        assert!(map.iter().all(|descriptor| descriptor.virtual_start == descriptor.physical_start + 0x8000_0000));
+        assert!(map.iter().all(|descriptor| descriptor.attribute == efi::MEMORY_WB | efi::MEMORY_RUNTIME));
+        // Bytes past the descriptor definition are left untouched.
+        let padding = mem::size_of::<efi::MemoryDescriptor>()..TEST_DESCRIPTOR_SIZE;
+        assert!(map.as_bytes()[padding].iter().all(|&byte| byte == 0xa5));
+    }
+
+    #[test]
+    fn test_diff() {
+        let boot_services = efi::BootServices { get_memory_map, ..mock_efi_boot_services() };
+        let mut old_buffer = [0u8; 256];
+        let old = OwnedMemoryMap::new(&boot_services, &mut old_buffer).unwrap();
+        let mut new_buffer = [0u8; 256];
+        let mut new = OwnedMemoryMap::new(&boot_services, &mut new_buffer).unwrap();
+        assert!(OwnedMemoryMap::diff(&old, &new).is_empty());
+
+        // Allocate the first page of region 1, and move region 2.
+        for (index, mut descriptor) in new.iter_mut().enumerate() {
+            match index {
+                1 => {
+                    descriptor.r#type = efi::BOOT_SERVICES_DATA;
+                    descriptor.number_of_pages = 1;
+                }
+                2 => descriptor.physical_start = 0x30000,
+                _ => (),
+            }
+        }
+        let diff = OwnedMemoryMap::diff(&old, &new);
+        let changes: Vec<_> = diff
+            .changes()
+            .iter()
+            .map(|change| match change {
+                MemoryMapChange::Added(descriptor) => ('+', descriptor.physical_start),
+                MemoryMapChange::Removed(descriptor) => ('-', descriptor.physical_start),
+                MemoryMapChange::Changed { old, .. } => ('~', old.physical_start),
+            })
+            .collect();
+        assert_eq!(changes, [('~', 0x10000), ('-', 0x20000), ('+', 0x30000)]);
+        assert_eq!(
+            diff.to_string().lines().next(),
+            Some(
+                "~ [0x0000000000010000-0x0000000000011fff) Conventional pages=0x2 attr=0x8 -> \
+                 [0x0000000000010000-0x0000000000010fff) BootServicesData pages=0x1 attr=0x8"
+            )
+        );
+        assert_eq!(diff.to_string().lines().count(), 3);
+    }
+
+    #[test]
+    fn test_from_pages() {
+        let boot_services = efi::BootServices { get_memory_map, allocate_pages, ..mock_efi_boot_services() };
+        let map = OwnedMemoryMap::from_pages(&boot_services).unwrap();
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get(0).map(|descriptor| descriptor.physical_start), Some(0));
+
+        let boot_services = mock_efi_boot_services();
+        assert_eq!(OwnedMemoryMap::from_pages(&boot_services).err(), Some(efi::Status::UNSUPPORTED));
+    }
+
+    #[test]
+    fn test_from_pages_at_zero() {
+        std::thread_local! {
+            static FREED: Cell<bool> = const { Cell::new(false) };
+        }
+        extern "efiapi" fn allocate_pages(
+            _allocate_type: efi::AllocateType,
+            _memory_type: efi::MemoryType,
+            _pages: usize,
+            address: *mut efi::PhysicalAddress,
+        ) -> efi::Status {
+            unsafe { *address = 0 };
+            efi::Status::SUCCESS
+        }
+        extern "efiapi" fn free_pages(address: efi::PhysicalAddress, _pages: usize) -> efi::Status {
+            assert_eq!(address, 0);
+            FREED.with(|freed| freed.set(true));
+            efi::Status::SUCCESS
+        }
+
+        let boot_services =
+            efi::BootServices { get_memory_map, allocate_pages, free_pages, ..mock_efi_boot_services() };
+        assert_eq!(OwnedMemoryMap::from_pages(&boot_services).err(), Some(efi::Status::OUT_OF_RESOURCES));
+        assert!(FREED.with(|freed| freed.get()));
+    }
+
+    #[test]
+    fn test_exit_boot_services() {
+        let boot_services =
+            efi::BootServices { get_memory_map, allocate_pages, exit_boot_services, ..mock_efi_boot_services() };
+        let map = super::exit_boot_services(&boot_services, 0x1a6e as efi::Handle).unwrap();
+        assert_eq!(map.map_key(), 0x43);
+        assert_eq!(map.len(), 3);
+        assert_eq!(ALLOCATIONS.with(|allocations| allocations.get()), 1);
+
+        let boot_services = efi::BootServices { get_memory_map, allocate_pages, ..mock_efi_boot_services() };
+        assert_eq!(
+            super::exit_boot_services(&boot_services, 0x1a6e as efi::Handle).err(),
+            Some(efi::Status::UNSUPPORTED)
+        );
+    }
+}
@@ -0,0 +1,196 @@
+//! Memory types, including OEM and OS-defined ones.
+//!
+//! Besides the types defined by the specification, the memory type space reserves `0x70000000..=0x7fffffff` for OEM
+//! types, e.g. platform carve-outs, and `0x80000000..=0xffffffff` for OS loaders. Every other value is rejected by
+//! AllocatePages and AllocatePool. [`MemoryType`] can only hold a valid type, and is accepted wherever the allocation
+//! helpers of this crate take a memory type.
+//!
+use core::fmt;
+
+use r_efi::efi;
+
+/// Range of the memory type space a [`MemoryType`] belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemoryTypeRange {
+    /// Types defined by the UEFI specification.
+    Standard,
+    /// Types reserved for OEMs, `0x70000000..=0x7fffffff`.
+    Oem,
+    /// Types reserved for OS loaders, `0x80000000..=0xffffffff`.
+    Os,
+}
+
+/// Memory type valid for AllocatePages and AllocatePool.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::{
+///     allocation::allocate_pages_at,
+///     memory_type::MemoryType,
+///     units::{PageCount, PhysicalAddress},
+/// };
+/// use r_efi::efi;
+///
+/// // Platform-defined type for the trace buffer carve-out.
+/// const TRACE_BUFFER: MemoryType = MemoryType::oem(0x10).unwrap();
+///
+/// fn reserve_trace_buffer(boot_services: &efi::BootServices) -> Result<(), efi::Status> {
+///     let address = PhysicalAddress::new(0x8000_0000);
+///     let pages = allocate_pages_at(boot_services, TRACE_BUFFER, address, PageCount::new(64))?;
+///     pages.leak();
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct MemoryType(efi::MemoryType);
+
+impl MemoryType {
+    /// `efi::LOADER_CODE`.
+    pub const LOADER_CODE: Self = Self(efi::LOADER_CODE);
+    /// `efi::LOADER_DATA`.
+    pub const LOADER_DATA: Self = Self(efi::LOADER_DATA);
+    /// `efi::BOOT_SERVICES_CODE`.
+    pub const BOOT_SERVICES_CODE: Self = Self(efi::BOOT_SERVICES_CODE);
+    /// `efi::BOOT_SERVICES_DATA`.
+    pub const BOOT_SERVICES_DATA: Self = Self(efi::BOOT_SERVICES_DATA);
+    /// `efi::RUNTIME_SERVICES_CODE`.
+    pub const RUNTIME_SERVICES_CODE: Self = Self(efi::RUNTIME_SERVICES_CODE);
+    /// `efi::RUNTIME_SERVICES_DATA`.
+    pub const RUNTIME_SERVICES_DATA: Self = Self(efi::RUNTIME_SERVICES_DATA);
+    /// `efi::ACPI_RECLAIM_MEMORY`.
+    pub const ACPI_RECLAIM_MEMORY: Self = Self(efi::ACPI_RECLAIM_MEMORY);
+    /// `efi::ACPI_MEMORY_NVS`.
+    pub const ACPI_MEMORY_NVS: Self = Self(efi::ACPI_MEMORY_NVS);
+
+    /// First OEM-defined type.
+    pub const OEM_START: efi::MemoryType = 0x7000_0000;
+    /// First OS-defined type.
+    pub const OS_START: efi::MemoryType = 0x8000_0000;
+
+    /// Return the memory type `raw`, or `None` if it is neither a type defined by the specification, nor in the OEM or
+    /// OS ranges.
+    pub const fn new(raw: efi::MemoryType) -> Option<Self> {
+        if raw <= efi::UNACCEPTED_MEMORY_TYPE || raw >= Self::OEM_START {
+            Some(Self(raw))
+        } else {
+            None
+        }
+    }
+
+    /// Return the OEM-defined type at `offset` from [`Self::OEM_START`], or `None` if it is out of the OEM range.
+    pub const fn oem(offset: u32) -> Option<Self> {
+        if offset < Self::OS_START - Self::OEM_START {
+            Some(Self(Self::OEM_START + offset))
+        } else {
+            None
+        }
+    }
+
+    /// Return the OS-defined type at `offset` from [`Self::OS_START`], or `None` if it is out of the OS range.
+    pub const fn os(offset: u32) -> Option<Self> {
+        match Self::OS_START.checked_add(offset) {
+            Some(raw) => Some(Self(raw)),
+            None => None,
+        }
+    }
+
+    /// Return the raw memory type.
+    pub const fn get(self) -> efi::MemoryType {
+        self.0
+    }
+
+    /// Return the range the type belongs to.
+    pub const fn range(self) -> MemoryTypeRange {
+        if self.0 >= Self::OS_START {
+            MemoryTypeRange::Os
+        } else if self.0 >= Self::OEM_START {
+            MemoryTypeRange::Oem
+        } else {
+            MemoryTypeRange::Standard
+        }
+    }
+
+    /// Return the name of a type defined by the specification, e.g. `"BootServicesData"`.
+    pub const fn name(self) -> Option<&'static str> {
+        Some(match self.0 {
+            efi::RESERVED_MEMORY_TYPE => "Reserved",
+            efi::LOADER_CODE => "LoaderCode",
+            efi::LOADER_DATA => "LoaderData",
+            efi::BOOT_SERVICES_CODE => "BootServicesCode",
+            efi::BOOT_SERVICES_DATA => "BootServicesData",
+            efi::RUNTIME_SERVICES_CODE => "RuntimeServicesCode",
+            efi::RUNTIME_SERVICES_DATA => "RuntimeServicesData",
+            efi::CONVENTIONAL_MEMORY => "Conventional",
+            efi::UNUSABLE_MEMORY => "Unusable",
+            efi::ACPI_RECLAIM_MEMORY => "AcpiReclaim",
+            efi::ACPI_MEMORY_NVS => "AcpiNvs",
+            efi::MEMORY_MAPPED_IO => "MemoryMappedIo",
+            efi::MEMORY_MAPPED_IO_PORT_SPACE => "MemoryMappedIoPortSpace",
+            efi::PAL_CODE => "PalCode",
+            efi::PERSISTENT_MEMORY => "Persistent",
+            efi::UNACCEPTED_MEMORY_TYPE => "Unaccepted",
+            _ => return None,
+        })
+    }
+}
+
+impl From<MemoryType> for efi::MemoryType {
+    fn from(memory_type: MemoryType) -> Self {
+        memory_type.0
+    }
+}
+
+impl TryFrom<efi::MemoryType> for MemoryType {
+    type Error = efi::Status;
+
+    /// Returns `efi::Status::INVALID_PARAMETER` for a value outside of the memory type space.
+    fn try_from(raw: efi::MemoryType) -> Result<Self, efi::Status> {
+        Self::new(raw).ok_or(efi::Status::INVALID_PARAMETER)
+    }
+}
+
+impl fmt::Display for MemoryType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.name(), self.range()) {
+            (Some(name), _) => write!(f, "{name}"),
+            (None, MemoryTypeRange::Oem) => write!(f, "Oem({:#x})", self.0 - Self::OEM_START),
+            (None, _) => write!(f, "Os({:#x})", self.0 - Self::OS_START),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_validation() {
+        assert_eq!(MemoryType::new(efi::BOOT_SERVICES_DATA), Some(MemoryType::BOOT_SERVICES_DATA));
+        assert_eq!(
+            MemoryType::new(efi::UNACCEPTED_MEMORY_TYPE).map(MemoryType::range),
+            Some(MemoryTypeRange::Standard)
+        );
+        assert_eq!(MemoryType::new(efi::UNACCEPTED_MEMORY_TYPE + 1), None);
+        assert_eq!(MemoryType::new(0x6fff_ffff), None);
+        assert_eq!(MemoryType::try_from(0x1234), Err(efi::Status::INVALID_PARAMETER));
+        assert_eq!(MemoryType::new(0x7000_0001), MemoryType::oem(1));
+        assert_eq!(MemoryType::new(u32::MAX), MemoryType::os(0x7fff_ffff));
+        assert_eq!(MemoryType::oem(0x1000_0000), None);
+        assert_eq!(MemoryType::os(0x8000_0000), None);
+    }
+
+    #[test]
+    fn test_ranges_and_names() {
+        let oem = MemoryType::oem(0x10).unwrap();
+        assert_eq!((oem.get(), oem.range()), (0x7000_0010, MemoryTypeRange::Oem));
+        assert_eq!(oem.to_string(), "Oem(0x10)");
+        let os = MemoryType::os(0).unwrap();
+        assert_eq!((efi::MemoryType::from(os), os.range()), (0x8000_0000, MemoryTypeRange::Os));
+        assert_eq!(os.to_string(), "Os(0x0)");
+        assert_eq!(MemoryType::RUNTIME_SERVICES_DATA.to_string(), "RuntimeServicesData");
+        assert_eq!(oem.name(), None);
+    }
+}
@@ -0,0 +1,132 @@
+//! Attributes of OpenProtocol.
+//!
+//! OpenProtocol takes its attributes as a bit mask, but only accepts a handful of the combinations it can express:
+//! one of the `efi::OPEN_PROTOCOL_*` values, or `BY_DRIVER` together with `EXCLUSIVE`. Any other combination is
+//! rejected with `efi::Status::INVALID_PARAMETER`. [`OpenProtocolAttributes`] can only hold an accepted combination,
+//! and is taken wherever the helpers of this crate open a protocol.
+//!
+use core::fmt;
+
+use r_efi::efi;
+
+/// Attributes accepted by OpenProtocol.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::{controller_resources::ControllerResources, open_protocol::OpenProtocolAttributes};
+/// use r_efi::{efi, protocols::block_io};
+///
+/// fn open_block_io(resources: &mut ControllerResources) -> Result<*mut block_io::Protocol, efi::Status> {
+///     let interface = resources.open_protocol(&block_io::PROTOCOL_GUID, OpenProtocolAttributes::BY_DRIVER_EXCLUSIVE)?;
+///     Ok(interface as *mut block_io::Protocol)
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct OpenProtocolAttributes(u32);
+
+impl OpenProtocolAttributes {
+    /// `efi::OPEN_PROTOCOL_BY_HANDLE_PROTOCOL`.
+    pub const BY_HANDLE_PROTOCOL: Self = Self(efi::OPEN_PROTOCOL_BY_HANDLE_PROTOCOL);
+    /// `efi::OPEN_PROTOCOL_GET_PROTOCOL`.
+    pub const GET_PROTOCOL: Self = Self(efi::OPEN_PROTOCOL_GET_PROTOCOL);
+    /// `efi::OPEN_PROTOCOL_TEST_PROTOCOL`.
+    pub const TEST_PROTOCOL: Self = Self(efi::OPEN_PROTOCOL_TEST_PROTOCOL);
+    /// `efi::OPEN_PROTOCOL_BY_CHILD_CONTROLLER`.
+    pub const BY_CHILD_CONTROLLER: Self = Self(efi::OPEN_PROTOCOL_BY_CHILD_CONTROLLER);
+    /// `efi::OPEN_PROTOCOL_BY_DRIVER`.
+    pub const BY_DRIVER: Self = Self(efi::OPEN_PROTOCOL_BY_DRIVER);
+    /// `efi::OPEN_PROTOCOL_EXCLUSIVE`.
+    pub const EXCLUSIVE: Self = Self(efi::OPEN_PROTOCOL_EXCLUSIVE);
+    /// `efi::OPEN_PROTOCOL_BY_DRIVER | efi::OPEN_PROTOCOL_EXCLUSIVE`.
+    pub const BY_DRIVER_EXCLUSIVE: Self = Self(efi::OPEN_PROTOCOL_BY_DRIVER | efi::OPEN_PROTOCOL_EXCLUSIVE);
+
+    /// Return the attributes `raw`, or `None` if OpenProtocol does not accept the combination.
+    pub const fn new(raw: u32) -> Option<Self> {
+        match raw {
+            efi::OPEN_PROTOCOL_BY_HANDLE_PROTOCOL
+            | efi::OPEN_PROTOCOL_GET_PROTOCOL
+            | efi::OPEN_PROTOCOL_TEST_PROTOCOL
+            | efi::OPEN_PROTOCOL_BY_CHILD_CONTROLLER
+            | efi::OPEN_PROTOCOL_BY_DRIVER
+            | efi::OPEN_PROTOCOL_EXCLUSIVE => Some(Self(raw)),
+            _ if raw == Self::BY_DRIVER_EXCLUSIVE.0 => Some(Self(raw)),
+            _ => None,
+        }
+    }
+
+    /// Return the raw attributes.
+    pub const fn get(self) -> u32 {
+        self.0
+    }
+
+    /// Return true if the opening is recorded in the handle database and must be matched by a CloseProtocol.
+    ///
+    /// Openings with `GET_PROTOCOL` or `TEST_PROTOCOL` are not recorded.
+    pub const fn needs_close(self) -> bool {
+        !matches!(self.0, efi::OPEN_PROTOCOL_GET_PROTOCOL | efi::OPEN_PROTOCOL_TEST_PROTOCOL)
+    }
+
+    /// Return the name of the attributes, e.g. `"BY_DRIVER|EXCLUSIVE"`.
+    pub const fn name(self) -> &'static str {
+        match self.0 {
+            efi::OPEN_PROTOCOL_BY_HANDLE_PROTOCOL => "BY_HANDLE_PROTOCOL",
+            efi::OPEN_PROTOCOL_GET_PROTOCOL => "GET_PROTOCOL",
+            efi::OPEN_PROTOCOL_TEST_PROTOCOL => "TEST_PROTOCOL",
+            efi::OPEN_PROTOCOL_BY_CHILD_CONTROLLER => "BY_CHILD_CONTROLLER",
+            efi::OPEN_PROTOCOL_BY_DRIVER => "BY_DRIVER",
+            efi::OPEN_PROTOCOL_EXCLUSIVE => "EXCLUSIVE",
+            _ => "BY_DRIVER|EXCLUSIVE",
+        }
+    }
+}
+
+impl From<OpenProtocolAttributes> for u32 {
+    fn from(attributes: OpenProtocolAttributes) -> Self {
+        attributes.0
+    }
+}
+
+impl TryFrom<u32> for OpenProtocolAttributes {
+    type Error = efi::Status;
+
+    /// Returns `efi::Status::INVALID_PARAMETER` for a combination OpenProtocol does not accept.
+    fn try_from(raw: u32) -> Result<Self, efi::Status> {
+        Self::new(raw).ok_or(efi::Status::INVALID_PARAMETER)
+    }
+}
+
+impl fmt::Display for OpenProtocolAttributes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_validation() {
+        assert_eq!(OpenProtocolAttributes::new(efi::OPEN_PROTOCOL_BY_DRIVER), Some(OpenProtocolAttributes::BY_DRIVER));
+        assert_eq!(
+            OpenProtocolAttributes::new(efi::OPEN_PROTOCOL_BY_DRIVER | efi::OPEN_PROTOCOL_EXCLUSIVE),
+            Some(OpenProtocolAttributes::BY_DRIVER_EXCLUSIVE)
+        );
+        assert_eq!(OpenProtocolAttributes::new(0), None);
+        assert_eq!(OpenProtocolAttributes::new(efi::OPEN_PROTOCOL_GET_PROTOCOL | efi::OPEN_PROTOCOL_BY_DRIVER), None);
+        assert_eq!(OpenProtocolAttributes::new(0x100), None);
+        assert_eq!(OpenProtocolAttributes::try_from(0x6), Err(efi::Status::INVALID_PARAMETER));
+        assert_eq!(u32::from(OpenProtocolAttributes::EXCLUSIVE), efi::OPEN_PROTOCOL_EXCLUSIVE);
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(OpenProtocolAttributes::GET_PROTOCOL.to_string(), "GET_PROTOCOL");
+        assert_eq!(OpenProtocolAttributes::BY_DRIVER_EXCLUSIVE.to_string(), "BY_DRIVER|EXCLUSIVE");
+        assert!(OpenProtocolAttributes::BY_CHILD_CONTROLLER.needs_close());
+        assert!(!OpenProtocolAttributes::TEST_PROTOCOL.needs_close());
+    }
+}
@@ -0,0 +1,273 @@
+//! Drivers polling their hardware periodically.
+//!
+//! Hardware that cannot raise interrupts, such as thermal sensors or embedded controllers, is serviced from a periodic
+//! timer. [`Poller`] owns a periodic timer event running a closure. [`PollingDriver`] keeps one poller per controller,
+//! so that the Start and Stop functions of a driver binding reduce to [`PollingDriver::start`] and
+//! [`PollingDriver::stop`].
+//!
+use alloc::{boxed::Box, rc::Rc, vec::Vec};
+use core::{cell::RefCell, time::Duration};
+
+use r_efi::efi;
+
+use crate::timer::Timer;
+
+/// Closure run periodically from a timer event, stopped when dropped.
+///
+/// The closure must not borrow anything, since the poller may be leaked with `mem::forget` and keep running it.
+///
+/// # Example
+/// ```no_run
+/// use core::{cell::Cell, time::Duration};
+/// use std::rc::Rc;
+/// use mu_rust_helpers::polling_driver::Poller;
+/// use r_efi::efi;
+///
+/// fn sample_temperature(boot_services: &efi::BootServices, read_sensor: fn() -> u32) -> Result<(), efi::Status> {
+///     let hottest = Rc::new(Cell::new(0));
+///     let sampled = hottest.clone();
+///     let poller = Poller::new(boot_services, Duration::from_millis(500), efi::TPL_CALLBACK, move || {
+///         sampled.set(sampled.get().max(read_sensor()));
+///     })?;
+///     // The sensor is sampled every 500ms for as long as `poller` is alive.
+///     drop(poller);
+///     Ok(())
+/// }
+/// ```
+pub struct Poller<'a> {
+    timer: Timer<'a>,
+    work: Rc<RefCell<dyn FnMut()>>,
+}
+
+impl<'a> Poller<'a> {
+    /// Run `work` at `tpl` every `period`, starting one period from now.
+    ///
+    /// The period is rounded up to the 100ns resolution of timer events.
+    pub fn new(
+        boot_services: &'a efi::BootServices,
+        period: Duration,
+        tpl: efi::Tpl,
+        work: impl FnMut() + 'static,
+    ) -> Result<Self, efi::Status> {
+        let work: Rc<RefCell<dyn FnMut()>> = Rc::new(RefCell::new(work));
+        let timer_work = work.clone();
+        let tick = move || {
+            // Skip the tick if the work is already running, e.g. when it is run directly with `Poller::poll_now`.
+            if let Ok(mut work) = timer_work.try_borrow_mut() {
+                work();
+            }
+        };
+        let timer = Timer::new(boot_services, period, true, tpl, Box::new(tick))?;
+        Ok(Self { timer, work })
+    }
+
+    /// Run the work now, e.g. to take a first sample without waiting for a period.
+    ///
+    /// Must not be called from the work itself.
+    pub fn poll_now(&self) {
+        (self.work.borrow_mut())();
+    }
+
+    /// Return the timer event.
+    pub fn event(&self) -> efi::Event {
+        self.timer.event().as_raw()
+    }
+}
+
+/// Per-controller pollers of a driver.
+///
+/// # Example
+/// ```no_run
+/// use core::time::Duration;
+/// use mu_rust_helpers::polling_driver::PollingDriver;
+/// use r_efi::efi;
+///
+/// struct EcDriver<'a> {
+///     polling: PollingDriver<'a>,
+/// }
+///
+/// impl<'a> EcDriver<'a> {
+///     fn new(boot_services: &'a efi::BootServices) -> Self {
+///         Self { polling: PollingDriver::new(boot_services, Duration::from_millis(100)) }
+///     }
+///
+///     // Called from the Start function of the driver binding, once the controller is opened.
+///     fn start(&mut self, controller: efi::Handle, service_ec: fn(efi::Handle)) -> Result<(), efi::Status> {
+///         self.polling.start(controller, move || service_ec(controller))
+///     }
+///
+///     // Called from the Stop function of the driver binding, before the controller is closed.
+///     fn stop(&mut self, controller: efi::Handle) -> Result<(), efi::Status> {
+///         self.polling.stop(controller)
+///     }
+/// }
+/// ```
+pub struct PollingDriver<'a> {
+    boot_services: &'a efi::BootServices,
+    period: Duration,
+    tpl: efi::Tpl,
+    pollers: Vec<(efi::Handle, Poller<'a>)>,
+}
+
+impl<'a> PollingDriver<'a> {
+    /// Create a driver polling each started controller every `period` at `efi::TPL_CALLBACK`.
+    pub fn new(boot_services: &'a efi::BootServices, period: Duration) -> Self {
+        Self { boot_services, period, tpl: efi::TPL_CALLBACK, pollers: Vec::new() }
+    }
+
+    /// Set the TPL at which the work runs, e.g. `efi::TPL_NOTIFY` for work that must preempt callbacks.
+    pub fn tpl(mut self, tpl: efi::Tpl) -> Self {
+        self.tpl = tpl;
+        self
+    }
+
+    /// Start running `work` periodically for `controller`.
+    ///
+    /// Returns `efi::Status::ALREADY_STARTED` if the controller is already polled.
+    pub fn start(&mut self, controller: efi::Handle, work: impl FnMut() + 'static) -> Result<(), efi::Status> {
+        if self.is_started(controller) {
+            return Err(efi::Status::ALREADY_STARTED);
+        }
+        let poller = Poller::new(self.boot_services, self.period, self.tpl, work)?;
+        self.pollers.push((controller, poller));
+        Ok(())
+    }
+
+    /// Stop polling `controller`.
+    ///
+    /// Returns `efi::Status::NOT_STARTED` if the controller is not polled.
+    pub fn stop(&mut self, controller: efi::Handle) -> Result<(), efi::Status> {
+        let index =
+            self.pollers.iter().position(|(handle, _)| *handle == controller).ok_or(efi::Status::NOT_STARTED)?;
+        self.pollers.swap_remove(index);
+        Ok(())
+    }
+
+    /// Return true if `controller` is polled.
+    pub fn is_started(&self, controller: efi::Handle) -> bool {
+        self.pollers.iter().any(|(handle, _)| *handle == controller)
+    }
+
+    /// Return the controllers being polled.
+    pub fn controllers(&self) -> Vec<efi::Handle> {
+        self.pollers.iter().map(|(handle, _)| *handle).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::ffi::c_void;
+    use std::cell::Cell;
+
+    use crate::test_support::mock_efi_boot_services;
+
+    #[derive(Default)]
+    struct MockState {
+        events: Vec<(efi::EventNotify, usize)>,
+        timers: Vec<(usize, efi::TimerDelay, u64)>,
+        closed: Vec<usize>,
+    }
+
+    std::thread_local! {
+        static STATE: RefCell<MockState> = RefCell::new(MockState::default());
+    }
+
+    extern "efiapi" fn create_event(
+        event_type: u32,
+        tpl: efi::Tpl,
+        notify: Option<efi::EventNotify>,
+        context: *mut c_void,
+        event: *mut efi::Event,
+    ) -> efi::Status {
+        assert_eq!((event_type, tpl), (efi::EVT_TIMER | efi::EVT_NOTIFY_SIGNAL, efi::TPL_CALLBACK));
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            state.events.push((notify.unwrap(), context as usize));
+            // Events are numbered from 1 in creation order.
+            unsafe { *event = state.events.len() as efi::Event };
+        });
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn set_timer(event: efi::Event, delay: efi::TimerDelay, period: u64) -> efi::Status {
+        STATE.with(|state| state.borrow_mut().timers.push((event as usize, delay, period)));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn close_event(event: efi::Event) -> efi::Status {
+        STATE.with(|state| state.borrow_mut().closed.push(event as usize));
+        efi::Status::SUCCESS
+    }
+
+    fn boot_services() -> efi::BootServices {
+        efi::BootServices { create_event, set_timer, close_event, ..mock_efi_boot_services() }
+    }
+
+    /// Signal the timer event `event` as the firmware would, unless it was closed.
+    fn tick(event: usize) {
+        let (notify, context, closed) = STATE.with(|state| {
+            let state = state.borrow();
+            let (notify, context) = state.events[event - 1];
+            (notify, context, state.closed.contains(&event))
+        });
+        if !closed {
+            notify(event as efi::Event, context as *mut c_void);
+        }
+    }
+
+    #[test]
+    fn test_poller() {
+        let boot_services = boot_services();
+        let count = Rc::new(Cell::new(0));
+        let counter = count.clone();
+        let poller = Poller::new(&boot_services, Duration::from_millis(5), efi::TPL_CALLBACK, move || {
+            counter.set(counter.get() + 1)
+        })
+        .unwrap();
+        let event = poller.event() as usize;
+        assert_eq!(STATE.with(|state| state.borrow().timers.clone()), [(event, efi::TIMER_PERIODIC, 50_000)]);
+
+        tick(event);
+        tick(event);
+        poller.poll_now();
+        assert_eq!(count.get(), 3);
+
+        drop(poller);
+        tick(event);
+        assert_eq!(count.get(), 3);
+        assert_eq!(STATE.with(|state| state.borrow().closed.clone()), [event]);
+    }
+
+    #[test]
+    fn test_polling_driver() {
+        let boot_services = boot_services();
+        let serviced = Rc::new(RefCell::new(Vec::new()));
+        let mut driver = PollingDriver::new(&boot_services, Duration::ZERO);
+        let first = 0x10 as efi::Handle;
+        let second = 0x20 as efi::Handle;
+        for controller in [first, second] {
+            let serviced = Rc::clone(&serviced);
+            driver.start(controller, move || serviced.borrow_mut().push(controller)).unwrap();
+        }
+        assert_eq!(driver.start(first, || ()), Err(efi::Status::ALREADY_STARTED));
+        assert_eq!(driver.controllers(), [first, second]);
+        // Zero periods are rounded up to a single tick.
+        assert!(STATE.with(|state| state.borrow().timers.iter().all(|timer| timer.2 == 1)));
+
+        tick(2);
+        tick(1);
+        assert_eq!(*serviced.borrow(), [second, first]);
+
+        driver.stop(first).unwrap();
+        assert_eq!(driver.stop(first), Err(efi::Status::NOT_STARTED));
+        assert!(!driver.is_started(first) && driver.is_started(second));
+        tick(1);
+        assert_eq!(serviced.borrow().len(), 2);
+
+        drop(driver);
+        assert_eq!(STATE.with(|state| state.borrow().closed.clone()), [1, 2]);
+        assert_eq!(Rc::strong_count(&serviced), 1);
+    }
+}
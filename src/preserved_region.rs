@@ -0,0 +1,366 @@
+//! Memory region preserved across warm reset.
+//!
+//! On platforms that do not clear memory on warm reset, a boot can leave data for the next one, e.g. a counter of
+//! failed boots to detect crash loops, or hints that shorten the next boot. [`PreservedRegion::reserve`] allocates the
+//! region at a fixed address as reserved memory, so that neither firmware nor the OS reuses it, and publishes it as a
+//! configuration table so that later stages can find it. The region starts with a [`PreservedRegionHeader`] holding a
+//! signature, a revision and a CRC32 of the data, telling data written by the previous boot apart from whatever the
+//! memory holds after a cold boot.
+//!
+//! The CRC32 is computed with the CalculateCrc32 boot service, so the region is only read and written before
+//! ExitBootServices.
+//!
+//! The region must be at the same address on every boot. Keeping the address out of the memory the platform clears
+//! or hands out early, e.g. through the GCD memory space map, is left to the platform.
+//!
+//! # Example
+//! ```no_run
+//! use mu_rust_helpers::{
+//!     preserved_region::PreservedRegion,
+//!     units::{PageCount, PhysicalAddress},
+//! };
+//! use r_efi::efi;
+//!
+//! fn count_boot_attempt(boot_services: &efi::BootServices) -> Result<u32, efi::Status> {
+//!     let mut region = PreservedRegion::reserve(boot_services, PhysicalAddress::new(0x7f00_0000), PageCount::new(1))?;
+//!     let attempts = match region.data() {
+//!         Some(&[a, b, c, d, ..]) => u32::from_le_bytes([a, b, c, d]) + 1,
+//!         _ => 1,
+//!     };
+//!     region.write(&attempts.to_le_bytes())?;
+//!     Ok(attempts)
+//! }
+//! ```
+//!
+use core::{ffi::c_void, mem, ptr, slice};
+
+use r_efi::efi;
+
+use crate::{
+    config_table::ConfigTable,
+    units::{PageCount, PhysicalAddress},
+};
+
+/// GUID of the preserved region configuration table.
+pub const PRESERVED_REGION_GUID: efi::Guid =
+    efi::Guid::from_fields(0x6b1f3c0e, 0x52a4, 0x4d7b, 0xa3, 0x19, &[0x0e, 0x8d, 0x5c, 0x27, 0xf4, 0x61]);
+
+/// Header at the start of a [`PreservedRegion`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PreservedRegionHeader {
+    /// [`PreservedRegionHeader::SIGNATURE`].
+    pub signature: u64,
+    /// [`PreservedRegionHeader::REVISION`].
+    pub revision: u32,
+    /// Size of the header in bytes.
+    pub header_size: u32,
+    /// Number of bytes available for data after the header.
+    pub capacity: u32,
+    /// Number of valid data bytes.
+    pub data_size: u32,
+    /// CRC32 of the valid data bytes.
+    pub crc32: u32,
+    /// Number of writes since the region was first initialized.
+    pub sequence: u32,
+}
+
+// SAFETY: `PRESERVED_REGION_GUID` is owned by this crate and only published with a `PreservedRegionHeader`.
+unsafe impl ConfigTable for PreservedRegionHeader {
+    const GUID: efi::Guid = PRESERVED_REGION_GUID;
+}
+
+impl PreservedRegionHeader {
+    /// `"MUPRSRVD"`.
+    pub const SIGNATURE: u64 = u64::from_le_bytes(*b"MUPRSRVD");
+    /// Revision of the header layout.
+    pub const REVISION: u32 = 1;
+    /// Size of the header in bytes.
+    pub const SIZE: usize = mem::size_of::<Self>();
+}
+
+/// Region of memory whose contents are kept across warm reset.
+#[derive(Debug)]
+pub struct PreservedRegion<'a> {
+    calculate_crc32: efi::BootCalculateCrc32,
+    memory: &'a mut [u8],
+}
+
+impl<'a> PreservedRegion<'a> {
+    /// Use `memory` as a preserved region, keeping the data it holds.
+    ///
+    /// Returns `efi::Status::BAD_BUFFER_SIZE` if `memory` cannot hold the header, or more than 4 GiB of data.
+    pub fn new(boot_services: &efi::BootServices, memory: &'a mut [u8]) -> Result<Self, efi::Status> {
+        check_size(memory.len())?;
+        Ok(Self { calculate_crc32: boot_services.calculate_crc32, memory })
+    }
+
+    /// Number of bytes available for data.
+    pub fn capacity(&self) -> usize {
+        self.memory.len() - PreservedRegionHeader::SIZE
+    }
+
+    /// Return the header if it is valid and matches the data it describes.
+    pub fn header(&self) -> Option<PreservedRegionHeader> {
+        // SAFETY: `new` checked that the memory holds a header; `read_unaligned` has no alignment requirement.
+        let header = unsafe { ptr::read_unaligned(self.memory.as_ptr() as *const PreservedRegionHeader) };
+        let valid = header.signature == PreservedRegionHeader::SIGNATURE
+            && header.revision == PreservedRegionHeader::REVISION
+            && header.header_size as usize == PreservedRegionHeader::SIZE
+            && header.capacity as usize == self.capacity()
+            && header.data_size <= header.capacity;
+        if !valid {
+            return None;
+        }
+        let data = &self.memory[PreservedRegionHeader::SIZE..][..header.data_size as usize];
+        (crc32(self.calculate_crc32, data).ok()? == header.crc32).then_some(header)
+    }
+
+    /// Return the data of the last write, or `None` if the region holds no valid data, e.g. after a cold boot.
+    pub fn data(&self) -> Option<&[u8]> {
+        let header = self.header()?;
+        Some(&self.memory[PreservedRegionHeader::SIZE..][..header.data_size as usize])
+    }
+
+    /// Replace the data of the region with `data`.
+    ///
+    /// Returns `efi::Status::BUFFER_TOO_SMALL` if `data` does not fit in the region.
+    pub fn write(&mut self, data: &[u8]) -> Result<(), efi::Status> {
+        if data.len() > self.capacity() {
+            return Err(efi::Status::BUFFER_TOO_SMALL);
+        }
+        let crc32 = crc32(self.calculate_crc32, data)?;
+        let sequence = self.header().map_or(0, |header| header.sequence.wrapping_add(1));
+
+        // Invalidate the header first, so that a reset during the update does not leave torn data behind as valid.
+        self.invalidate();
+        self.memory[PreservedRegionHeader::SIZE..][..data.len()].copy_from_slice(data);
+        let header = PreservedRegionHeader {
+            signature: PreservedRegionHeader::SIGNATURE,
+            revision: PreservedRegionHeader::REVISION,
+            header_size: PreservedRegionHeader::SIZE as u32,
+            capacity: self.capacity() as u32,
+            data_size: data.len() as u32,
+            crc32,
+            sequence,
+        };
+        // SAFETY: `new` checked that the memory holds a header; `write_unaligned` has no alignment requirement.
+        unsafe { ptr::write_unaligned(self.memory.as_mut_ptr() as *mut PreservedRegionHeader, header) };
+        Ok(())
+    }
+
+    /// Discard the data of the region, so that the next boot finds none.
+    pub fn invalidate(&mut self) {
+        self.memory[..mem::size_of::<u64>()].fill(0);
+    }
+}
+
+impl PreservedRegion<'static> {
+    /// Reserve `pages` at `address` and publish them as the preserved region configuration table.
+    ///
+    /// The pages are allocated as `efi::RESERVED_MEMORY_TYPE` and never freed. Data written by the previous boot is
+    /// kept and available through [`data`](Self::data).
+    ///
+    /// Returns `efi::Status::INVALID_PARAMETER` if `address` is 0 or not page aligned, the error of AllocatePages if the
+    /// pages are not available, e.g. `efi::Status::NOT_FOUND`, and the error of InstallConfigurationTable if the table
+    /// cannot be published, in which case the pages are freed.
+    pub fn reserve(
+        boot_services: &efi::BootServices,
+        address: PhysicalAddress,
+        pages: PageCount,
+    ) -> Result<Self, efi::Status> {
+        // Page 0 is valid memory but not a valid Rust pointer.
+        if address.get() == 0 || !address.is_page_aligned() {
+            return Err(efi::Status::INVALID_PARAMETER);
+        }
+        let (Some(page_count), Some(size)) = (pages.to_usize(), pages.to_bytes().and_then(|size| size.to_usize()))
+        else {
+            return Err(efi::Status::BAD_BUFFER_SIZE);
+        };
+        // Checked before publishing the table, which would otherwise describe a region that is not returned.
+        check_size(size)?;
+
+        let mut allocated = address.get();
+        let status = (boot_services.allocate_pages)(
+            efi::ALLOCATE_ADDRESS,
+            efi::RESERVED_MEMORY_TYPE,
+            page_count,
+            &mut allocated,
+        );
+        if status.is_error() {
+            return Err(status);
+        }
+
+        let mut guid = PRESERVED_REGION_GUID;
+        let status = (boot_services.install_configuration_table)(&mut guid, allocated as *mut _);
+        if status.is_error() {
+            (boot_services.free_pages)(allocated, page_count);
+            return Err(status);
+        }
+
+        // SAFETY: the pages were allocated above and are never freed, so no other code owns them.
+        let memory = unsafe { slice::from_raw_parts_mut(allocated as *mut u8, size) };
+        Ok(Self { calculate_crc32: boot_services.calculate_crc32, memory })
+    }
+}
+
+fn check_size(size: usize) -> Result<(), efi::Status> {
+    match size.checked_sub(PreservedRegionHeader::SIZE) {
+        Some(capacity) if u32::try_from(capacity).is_ok() => Ok(()),
+        _ => Err(efi::Status::BAD_BUFFER_SIZE),
+    }
+}
+
+fn crc32(calculate_crc32: efi::BootCalculateCrc32, data: &[u8]) -> Result<u32, efi::Status> {
+    // CalculateCrc32 rejects empty data.
+    if data.is_empty() {
+        return Ok(0);
+    }
+    let mut crc32 = 0;
+    // CalculateCrc32 takes a mutable pointer but only reads the data.
+    let status = calculate_crc32(data.as_ptr() as *mut c_void, data.len(), &mut crc32);
+    if status.is_error() {
+        return Err(status);
+    }
+    Ok(crc32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        alloc::{alloc_zeroed, Layout},
+        cell::RefCell,
+    };
+
+    use crate::{test_support::mock_efi_boot_services, units::UEFI_PAGE_SIZE};
+
+    std::thread_local! {
+        static CALLS: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+    }
+
+    extern "efiapi" fn allocate_pages(
+        allocate_type: efi::AllocateType,
+        memory_type: efi::MemoryType,
+        _pages: usize,
+        _address: *mut efi::PhysicalAddress,
+    ) -> efi::Status {
+        assert_eq!((allocate_type, memory_type), (efi::ALLOCATE_ADDRESS, efi::RESERVED_MEMORY_TYPE));
+        CALLS.with(|calls| calls.borrow_mut().push("allocate_pages"));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn free_pages(_address: efi::PhysicalAddress, _pages: usize) -> efi::Status {
+        CALLS.with(|calls| calls.borrow_mut().push("free_pages"));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn install_configuration_table(guid: *mut efi::Guid, _table: *mut c_void) -> efi::Status {
+        assert_eq!(unsafe { *guid }, PRESERVED_REGION_GUID);
+        CALLS.with(|calls| calls.borrow_mut().push("install_configuration_table"));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn install_configuration_table_failure(_guid: *mut efi::Guid, _table: *mut c_void) -> efi::Status {
+        efi::Status::OUT_OF_RESOURCES
+    }
+
+    extern "efiapi" fn calculate_crc32(data: *mut c_void, size: usize, crc32: *mut u32) -> efi::Status {
+        assert_ne!(size, 0);
+        let data = unsafe { slice::from_raw_parts(data as *const u8, size) };
+        let crc = !data.iter().fold(!0u32, |crc, &byte| {
+            (0..8).fold(crc ^ u32::from(byte), |crc, _| (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg()))
+        });
+        unsafe { *crc32 = crc };
+        efi::Status::SUCCESS
+    }
+
+    fn calls() -> Vec<&'static str> {
+        CALLS.with(|calls| calls.take())
+    }
+
+    #[test]
+    fn test_write_and_read_back() {
+        let boot_services = efi::BootServices { calculate_crc32, ..mock_efi_boot_services() };
+        let mut memory = [0xa5u8; 64];
+        let mut region = PreservedRegion::new(&boot_services, &mut memory).unwrap();
+        assert_eq!(region.capacity(), 64 - PreservedRegionHeader::SIZE);
+        assert_eq!(region.data(), None);
+
+        region.write(b"first").unwrap();
+        region.write(b"second").unwrap();
+        assert_eq!(region.data(), Some(&b"second"[..]));
+        assert_eq!(region.header().unwrap().sequence, 1);
+        assert_eq!(region.write(&[0; 64]), Err(efi::Status::BUFFER_TOO_SMALL));
+
+        // Data survives re-opening the memory, as on the next boot.
+        let region = PreservedRegion::new(&boot_services, &mut memory).unwrap();
+        assert_eq!(region.data(), Some(&b"second"[..]));
+    }
+
+    #[test]
+    fn test_corrupted_data_is_rejected() {
+        let boot_services = efi::BootServices { calculate_crc32, ..mock_efi_boot_services() };
+        let mut memory = [0u8; 64];
+        PreservedRegion::new(&boot_services, &mut memory).unwrap().write(b"data").unwrap();
+        memory[PreservedRegionHeader::SIZE + 1] ^= 1;
+        let mut region = PreservedRegion::new(&boot_services, &mut memory).unwrap();
+        assert_eq!(region.data(), None);
+
+        // The sequence restarts once the data is lost.
+        region.write(b"data").unwrap();
+        assert_eq!(region.header().unwrap().sequence, 0);
+        region.invalidate();
+        assert_eq!(region.data(), None);
+        region.write(&[]).unwrap();
+        assert_eq!(region.data(), Some(&[][..]));
+
+        assert_eq!(PreservedRegion::new(&boot_services, &mut [0; 8]).unwrap_err(), efi::Status::BAD_BUFFER_SIZE);
+    }
+
+    #[test]
+    fn test_reserve() {
+        let layout = Layout::from_size_align(UEFI_PAGE_SIZE as usize, UEFI_PAGE_SIZE as usize).unwrap();
+        // Leaked, as the region is never freed.
+        let address = PhysicalAddress::new(unsafe { alloc_zeroed(layout) } as u64);
+
+        let boot_services = efi::BootServices {
+            allocate_pages,
+            free_pages,
+            install_configuration_table,
+            calculate_crc32,
+            ..mock_efi_boot_services()
+        };
+        let mut region = PreservedRegion::reserve(&boot_services, address, PageCount::new(1)).unwrap();
+        assert_eq!(region.capacity(), UEFI_PAGE_SIZE as usize - PreservedRegionHeader::SIZE);
+        region.write(b"hint").unwrap();
+        assert_eq!(calls(), ["allocate_pages", "install_configuration_table"]);
+
+        let region = PreservedRegion::reserve(&boot_services, address, PageCount::new(1)).unwrap();
+        assert_eq!(region.data(), Some(&b"hint"[..]));
+        calls();
+
+        let boot_services =
+            efi::BootServices { install_configuration_table: install_configuration_table_failure, ..boot_services };
+        assert_eq!(
+            PreservedRegion::reserve(&boot_services, address, PageCount::new(1)).unwrap_err(),
+            efi::Status::OUT_OF_RESOURCES
+        );
+        assert_eq!(calls(), ["allocate_pages", "free_pages"]);
+        assert_eq!(
+            PreservedRegion::reserve(&boot_services, PhysicalAddress::new(0x1001), PageCount::new(1)).unwrap_err(),
+            efi::Status::INVALID_PARAMETER
+        );
+        assert_eq!(
+            PreservedRegion::reserve(&boot_services, PhysicalAddress::new(0), PageCount::new(1)).unwrap_err(),
+            efi::Status::INVALID_PARAMETER
+        );
+        // More than 4 GiB of data is rejected before anything is allocated or published.
+        assert_eq!(
+            PreservedRegion::reserve(&boot_services, address, PageCount::new(0x10_0001)).unwrap_err(),
+            efi::Status::BAD_BUFFER_SIZE
+        );
+        assert!(calls().is_empty());
+    }
+}
@@ -0,0 +1,44 @@
+//! Protocols identified by type.
+//!
+//! Protocol services take the GUID of a protocol and return an untyped interface pointer. Types implementing
+//! [`Protocol`] associate the GUID with the layout of the interface it identifies, so that helpers such as
+//! [`ProtocolNotify`](crate::protocol_notify::ProtocolNotify) can be typed by protocol rather than take a GUID.
+//!
+use r_efi::{efi, protocols};
+
+/// A protocol interface layout identified by a GUID.
+///
+/// # Safety
+/// Every interface installed with `GUID` must be a valid instance of the implementing type.
+pub unsafe trait Protocol {
+    /// GUID of the protocol in the handle database.
+    const GUID: efi::Guid;
+}
+
+macro_rules! impl_protocol {
+    ($($module:ident),* $(,)?) => {
+        $(
+            // SAFETY: `PROTOCOL_GUID` identifies the interface defined next to it by r-efi.
+            unsafe impl Protocol for protocols::$module::Protocol {
+                const GUID: efi::Guid = protocols::$module::PROTOCOL_GUID;
+            }
+        )*
+    };
+}
+
+impl_protocol!(
+    block_io,
+    bus_specific_driver_override,
+    device_path,
+    disk_io,
+    driver_binding,
+    graphics_output,
+    loaded_image,
+    pci_io,
+    platform_driver_override,
+    rng,
+    simple_file_system,
+    simple_text_input,
+    simple_text_output,
+    timestamp,
+);
@@ -0,0 +1,272 @@
+//! Compile-time checks of protocol interface definitions.
+//!
+//! Protocol interfaces are shared with firmware and other images built from C, so a Rust definition whose layout
+//! drifts from the specification, or two definitions sharing a GUID, only show up as corruption at runtime.
+//! [`static_assert_protocol!`](crate::static_assert_protocol) turns these mistakes into build errors.
+//!
+use core::mem;
+
+use r_efi::efi;
+
+use crate::{acpi_sdt, block_io2, deferred_image, driver_health, kms, user_auth};
+
+/// Return the size and alignment of the field selected by `field`.
+#[doc(hidden)]
+pub const fn field_layout<T, F>(_field: fn(&T) -> &F) -> (usize, usize) {
+    (mem::size_of::<F>(), mem::align_of::<F>())
+}
+
+/// Round `offset` up to a multiple of `align`.
+#[doc(hidden)]
+pub const fn align_up(offset: usize, align: usize) -> usize {
+    offset.div_ceil(align) * align
+}
+
+/// Return true if no two GUIDs of `guids` are equal.
+#[doc(hidden)]
+pub const fn guids_unique(guids: &[efi::Guid]) -> bool {
+    let mut i = 0;
+    while i < guids.len() {
+        let mut j = i + 1;
+        while j < guids.len() {
+            let (a, b) = (guids[i].as_bytes(), guids[j].as_bytes());
+            let mut k = 0;
+            while k < a.len() && a[k] == b[k] {
+                k += 1;
+            }
+            if k == a.len() {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Check protocol interface definitions at compile time.
+///
+/// For every protocol, the build fails if:
+/// - a field is not at the offset `#[repr(C)]` would give it, taking the fields in the order listed, or the fields
+///   do not cover the whole interface. Rust cannot observe the `repr` attribute itself, so this is what guarantees
+///   that the interface matches its C definition.
+/// - the interface does not have the expected `size` or `align`, when given.
+///
+/// The build also fails if two protocols share a GUID, so every protocol defined by a crate should be listed in a
+/// single invocation.
+///
+/// # Example
+/// ```
+/// use core::mem::size_of;
+/// use mu_rust_helpers::static_assert_protocol;
+/// use r_efi::efi;
+///
+/// const PROTOCOL_GUID: efi::Guid =
+///     efi::Guid::from_fields(0x2d1f0e3c, 0x5a4b, 0x4c6d, 0x8e, 0x7f, &[0x90, 0xa1, 0xb2, 0xc3, 0xd4, 0xe5]);
+///
+/// #[repr(C)]
+/// pub struct Protocol {
+///     pub revision: u32,
+///     pub get_value: extern "efiapi" fn(*mut Protocol, *mut u64) -> efi::Status,
+/// }
+///
+/// static_assert_protocol! {
+///     Protocol {
+///         guid: PROTOCOL_GUID,
+///         size: 2 * size_of::<usize>(),
+///         fields: [revision, get_value],
+///     },
+/// }
+/// ```
+///
+/// Reordering the fields of `Protocol` in the example without `#[repr(C)]` is allowed to move `revision` after
+/// `get_value`, which fails the build instead of the first call through the interface.
+#[macro_export]
+macro_rules! static_assert_protocol {
+    (
+        $(
+            $protocol:path {
+                guid: $guid:expr,
+                $(size: $size:expr,)?
+                $(align: $align:expr,)?
+                fields: [$($field:ident),* $(,)?] $(,)?
+            }
+        ),* $(,)?
+    ) => {
+        const _: () = {
+            $(
+                {
+                    let mut end = 0;
+                    $(
+                        let (size, align) = $crate::protocol_assert::field_layout(|protocol: &$protocol| &protocol.$field);
+                        let offset = $crate::protocol_assert::align_up(end, align);
+                        assert!(
+                            ::core::mem::offset_of!($protocol, $field) == offset,
+                            concat!(stringify!($protocol), "::", stringify!($field), " is not at its #[repr(C)] offset"),
+                        );
+                        end = offset + size;
+                    )*
+                    assert!(
+                        $crate::protocol_assert::align_up(end, ::core::mem::align_of::<$protocol>())
+                            == ::core::mem::size_of::<$protocol>(),
+                        concat!(stringify!($protocol), " has bytes not covered by the listed fields"),
+                    );
+                    $(
+                        assert!(
+                            ::core::mem::size_of::<$protocol>() == $size,
+                            concat!(stringify!($protocol), " does not have the expected size"),
+                        );
+                    )?
+                    $(
+                        assert!(
+                            ::core::mem::align_of::<$protocol>() == $align,
+                            concat!(stringify!($protocol), " does not have the expected alignment"),
+                        );
+                    )?
+                }
+            )*
+            assert!($crate::protocol_assert::guids_unique(&[$($guid),*]), "two protocols share a GUID");
+        };
+    };
+}
+
+static_assert_protocol! {
+    acpi_sdt::Protocol {
+        guid: acpi_sdt::PROTOCOL_GUID,
+        size: 10 * mem::size_of::<usize>(),
+        align: mem::align_of::<usize>(),
+        fields: [
+            acceptable_table_versions,
+            get_acpi_table,
+            register_notify,
+            open,
+            open_sdt,
+            close,
+            get_child,
+            get_option,
+            set_option,
+            find_path,
+        ],
+    },
+    block_io2::Protocol {
+        guid: block_io2::PROTOCOL_GUID,
+        size: 5 * mem::size_of::<usize>(),
+        align: mem::align_of::<usize>(),
+        fields: [media, reset, read_blocks_ex, write_blocks_ex, flush_blocks_ex],
+    },
+    deferred_image::Protocol {
+        guid: deferred_image::PROTOCOL_GUID,
+        size: mem::size_of::<usize>(),
+        align: mem::align_of::<usize>(),
+        fields: [get_image_info],
+    },
+    driver_health::Protocol {
+        guid: driver_health::PROTOCOL_GUID,
+        size: 2 * mem::size_of::<usize>(),
+        align: mem::align_of::<usize>(),
+        fields: [get_health_status, repair],
+    },
+    kms::Protocol {
+        guid: kms::PROTOCOL_GUID,
+        fields: [
+            get_service_status,
+            register_client,
+            create_key,
+            get_key,
+            add_key,
+            delete_key,
+            get_key_attributes,
+            add_key_attributes,
+            delete_key_attributes,
+            get_key_by_attributes,
+            protocol_version,
+            service_id,
+            service_name,
+            service_version,
+            service_available,
+            client_id_supported,
+            client_id_required,
+            client_id_max_size,
+            client_name_string_types,
+            client_name_required,
+            client_name_max_count,
+            client_data_supported,
+            client_data_max_size,
+            key_id_variable_len_supported,
+            key_id_max_size,
+            key_formats_count,
+            key_formats,
+            key_attributes_supported,
+            key_attribute_id_string_types,
+            key_attribute_id_max_count,
+            key_attributes_count,
+            key_attributes,
+        ],
+    },
+    user_auth::Credential2Protocol {
+        guid: user_auth::CREDENTIAL2_PROTOCOL_GUID,
+        fields: [
+            identifier,
+            r#type,
+            enroll,
+            form,
+            tile,
+            title,
+            user,
+            select,
+            deselect,
+            default,
+            get_info,
+            get_next_info,
+            capabilities,
+            delete,
+        ],
+    },
+    user_auth::ManagerProtocol {
+        guid: user_auth::MANAGER_PROTOCOL_GUID,
+        size: 11 * mem::size_of::<usize>(),
+        align: mem::align_of::<usize>(),
+        fields: [
+            create,
+            delete,
+            get_next,
+            current,
+            identify,
+            find,
+            notify,
+            get_info,
+            set_info,
+            delete_info,
+            get_next_info,
+        ],
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guids_unique() {
+        let guids = [acpi_sdt::PROTOCOL_GUID, kms::PROTOCOL_GUID, driver_health::PROTOCOL_GUID];
+        assert!(guids_unique(&guids));
+        assert!(guids_unique(&[]));
+        assert!(!guids_unique(&[guids[0], guids[1], guids[2], guids[1]]));
+    }
+
+    #[test]
+    fn test_field_layout() {
+        #[repr(C)]
+        struct Header {
+            revision: u32,
+            flags: u8,
+            length: u64,
+        }
+
+        assert_eq!(field_layout(|header: &Header| &header.flags), (1, 1));
+        assert_eq!(field_layout(|header: &Header| &header.length), (8, mem::align_of::<u64>()));
+        assert_eq!(align_up(5, 8), 8);
+        assert_eq!(align_up(16, 8), 16);
+        assert_eq!(align_up(0, 4), 0);
+    }
+}
@@ -0,0 +1,250 @@
+//! Memoized LocateProtocol.
+//!
+//! Code on hot paths, such as per-request crypto or status code routing, often locates the same protocols over and
+//! over. [`ProtocolCache`] remembers the result of LocateProtocol per GUID, and forgets it when a new instance of the
+//! protocol is installed, using a [`ProtocolNotify`] registration.
+//!
+use alloc::{rc::Rc, vec::Vec};
+use core::{
+    cell::{Cell, RefCell},
+    ffi::c_void,
+    ptr,
+};
+
+use r_efi::efi;
+
+use crate::protocol_notify::ProtocolNotify;
+
+struct Entry<'a> {
+    guid: efi::Guid,
+    result: Option<Result<*mut c_void, efi::Status>>,
+    is_stale: Rc<Cell<bool>>,
+    _notify: ProtocolNotify<'a>,
+}
+
+/// Cache of LocateProtocol results.
+///
+/// UEFI does not notify protocol uninstallation, so code that uninstalls a cached protocol, or knows it was
+/// uninstalled, must call [`Self::invalidate`].
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::protocol_cache::ProtocolCache;
+/// use r_efi::{efi, protocols::rng};
+///
+/// fn random_u64(cache: &ProtocolCache) -> Result<u64, efi::Status> {
+///     let rng = cache.locate(&rng::PROTOCOL_GUID)? as *mut rng::Protocol;
+///     let mut value = 0u64;
+///     // SAFETY: LocateProtocol returned an RNG protocol instance.
+///     let status = unsafe {
+///         ((*rng).get_rng)(rng, core::ptr::null_mut(), 8, &mut value as *mut u64 as *mut u8)
+///     };
+///     if status.is_error() {
+///         return Err(status);
+///     }
+///     Ok(value)
+/// }
+/// ```
+pub struct ProtocolCache<'a> {
+    boot_services: &'a efi::BootServices,
+    entries: RefCell<Vec<Entry<'a>>>,
+}
+
+impl<'a> ProtocolCache<'a> {
+    /// Create an empty cache.
+    pub fn new(boot_services: &'a efi::BootServices) -> Self {
+        Self { boot_services, entries: RefCell::new(Vec::new()) }
+    }
+
+    /// Return the first instance of `protocol`, as LocateProtocol does.
+    ///
+    /// Failures, such as `efi::Status::NOT_FOUND`, are cached as well until the protocol is installed. If the
+    /// installation notification cannot be registered, the result is returned without being cached.
+    pub fn locate(&self, protocol: &efi::Guid) -> Result<*mut c_void, efi::Status> {
+        let mut entries = self.entries.borrow_mut();
+        let index = match entries.iter().position(|entry| entry.guid == *protocol) {
+            Some(index) => index,
+            None => {
+                // Register before locating, so an installation in between is not missed.
+                let is_stale = Rc::new(Cell::new(true));
+                let flag = Rc::clone(&is_stale);
+                let notify =
+                    ProtocolNotify::with_callback(self.boot_services, protocol, efi::TPL_CALLBACK, move |_| {
+                        flag.set(true)
+                    });
+                let Ok(notify) = notify else {
+                    return self.locate_uncached(protocol);
+                };
+                entries.push(Entry { guid: *protocol, result: None, is_stale, _notify: notify });
+                entries.len() - 1
+            }
+        };
+
+        let entry = &mut entries[index];
+        if entry.is_stale.replace(false) {
+            entry.result = None;
+        }
+        *entry.result.get_or_insert_with(|| self.locate_uncached(protocol))
+    }
+
+    /// Forget the cached result for `protocol`, e.g. after uninstalling it.
+    pub fn invalidate(&self, protocol: &efi::Guid) {
+        if let Some(entry) = self.entries.borrow().iter().find(|entry| entry.guid == *protocol) {
+            entry.is_stale.set(true);
+        }
+    }
+
+    /// Forget every cached result, keeping the installation notifications registered.
+    pub fn clear(&self) {
+        self.entries.borrow().iter().for_each(|entry| entry.is_stale.set(true));
+    }
+
+    fn locate_uncached(&self, protocol: &efi::Guid) -> Result<*mut c_void, efi::Status> {
+        let mut guid = *protocol;
+        let mut interface = ptr::null_mut();
+        let status = (self.boot_services.locate_protocol)(&mut guid, ptr::null_mut(), &mut interface);
+        if status.is_error() {
+            return Err(status);
+        }
+        Ok(interface)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{handles::tests::TEST_PROTOCOL, test_support::mock_efi_boot_services};
+
+    #[derive(Default)]
+    struct MockState {
+        interface: Option<usize>,
+        new_handles: usize,
+        locate_calls: usize,
+        notify: Option<(efi::EventNotify, usize)>,
+        closed: usize,
+    }
+
+    std::thread_local! {
+        static STATE: RefCell<MockState> = RefCell::new(MockState::default());
+    }
+
+    extern "efiapi" fn locate_protocol(
+        protocol: *mut efi::Guid,
+        _registration: *mut c_void,
+        interface: *mut *mut c_void,
+    ) -> efi::Status {
+        assert_eq!(unsafe { *protocol }, TEST_PROTOCOL);
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            state.locate_calls += 1;
+            match state.interface {
+                Some(address) => {
+                    unsafe { *interface = address as *mut c_void };
+                    efi::Status::SUCCESS
+                }
+                None => efi::Status::NOT_FOUND,
+            }
+        })
+    }
+
+    extern "efiapi" fn create_event(
+        _event_type: u32,
+        _tpl: efi::Tpl,
+        notify: Option<efi::EventNotify>,
+        context: *mut c_void,
+        event: *mut efi::Event,
+    ) -> efi::Status {
+        STATE.with(|state| state.borrow_mut().notify = notify.map(|notify| (notify, context as usize)));
+        unsafe { *event = 0xe7 as efi::Event };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn register_protocol_notify(
+        _protocol: *mut efi::Guid,
+        _event: efi::Event,
+        key: *mut *mut c_void,
+    ) -> efi::Status {
+        unsafe { *key = 0x6e7 as *mut c_void };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn locate_handle(
+        _search_type: efi::LocateSearchType,
+        _protocol: *mut efi::Guid,
+        _key: *mut c_void,
+        _size: *mut usize,
+        buffer: *mut efi::Handle,
+    ) -> efi::Status {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            if state.new_handles == 0 {
+                return efi::Status::NOT_FOUND;
+            }
+            state.new_handles -= 1;
+            unsafe { *buffer = 1 as efi::Handle };
+            efi::Status::SUCCESS
+        })
+    }
+
+    extern "efiapi" fn close_event(_event: efi::Event) -> efi::Status {
+        STATE.with(|state| state.borrow_mut().closed += 1);
+        efi::Status::SUCCESS
+    }
+
+    fn install(address: usize) {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            state.interface = Some(address);
+            state.new_handles += 1;
+        });
+        let (notify, context) = STATE.with(|state| state.borrow().notify).unwrap();
+        notify(0xe7 as efi::Event, context as *mut c_void);
+    }
+
+    fn locate_calls() -> usize {
+        STATE.with(|state| state.borrow().locate_calls)
+    }
+
+    #[test]
+    fn test_locate() {
+        let boot_services = efi::BootServices {
+            locate_protocol,
+            create_event,
+            register_protocol_notify,
+            locate_handle,
+            close_event,
+            ..mock_efi_boot_services()
+        };
+        let cache = ProtocolCache::new(&boot_services);
+        assert_eq!(cache.locate(&TEST_PROTOCOL), Err(efi::Status::NOT_FOUND));
+        assert_eq!(cache.locate(&TEST_PROTOCOL), Err(efi::Status::NOT_FOUND));
+        assert_eq!(locate_calls(), 1);
+
+        install(0x1000);
+        assert_eq!(cache.locate(&TEST_PROTOCOL), Ok(0x1000 as *mut c_void));
+        assert_eq!(cache.locate(&TEST_PROTOCOL), Ok(0x1000 as *mut c_void));
+        assert_eq!(locate_calls(), 2);
+
+        cache.invalidate(&TEST_PROTOCOL);
+        STATE.with(|state| state.borrow_mut().interface = Some(0x2000));
+        assert_eq!(cache.locate(&TEST_PROTOCOL), Ok(0x2000 as *mut c_void));
+        cache.clear();
+        assert_eq!(cache.locate(&TEST_PROTOCOL), Ok(0x2000 as *mut c_void));
+        assert_eq!(locate_calls(), 4);
+
+        drop(cache);
+        assert_eq!(STATE.with(|state| state.borrow().closed), 1);
+    }
+
+    #[test]
+    fn test_uncached() {
+        // Without protocol notifications, every call locates the protocol.
+        let boot_services = efi::BootServices { locate_protocol, ..mock_efi_boot_services() };
+        let cache = ProtocolCache::new(&boot_services);
+        STATE.with(|state| state.borrow_mut().interface = Some(0x3000));
+        assert_eq!(cache.locate(&TEST_PROTOCOL), Ok(0x3000 as *mut c_void));
+        assert_eq!(cache.locate(&TEST_PROTOCOL), Ok(0x3000 as *mut c_void));
+        assert_eq!(locate_calls(), 2);
+    }
+}
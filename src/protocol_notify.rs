@@ -0,0 +1,494 @@
+//! Notification of protocol installations.
+//!
+//! Being told about new instances of a protocol takes three calls that must be wired together: create an event,
+//! register it with RegisterProtocolNotify, then call LocateHandle with `ByRegisterNotify` until it reports
+//! `NOT_FOUND` to collect every handle installed since the last call. [`ProtocolNotify`] owns all three.
+//!
+//! A notifier is typed by the [`Protocol`] it watches, or created from a raw GUID for protocols without a Rust type.
+//! With the `executor` feature, `ProtocolInstalledStream` yields the new handles to an async task instead.
+//!
+use alloc::{boxed::Box, vec::Vec};
+#[cfg(feature = "executor")]
+use alloc::{collections::VecDeque, rc::Rc};
+use core::{
+    cell::{Cell, RefCell},
+    ffi::c_void,
+    marker::PhantomData,
+    mem, ptr,
+};
+#[cfg(feature = "executor")]
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+#[cfg(feature = "executor")]
+use futures_core::Stream;
+use r_efi::efi;
+
+use crate::protocol::Protocol;
+
+type Callback = RefCell<Box<dyn FnMut(efi::Handle)>>;
+
+/// State reached by the notification function, which must not borrow the boot services table since the
+/// subscription may be leaked.
+struct Registration {
+    locate_handle: efi::BootLocateHandle,
+    key: Cell<*mut c_void>,
+    callback: Option<Callback>,
+}
+
+impl Registration {
+    fn new(boot_services: &efi::BootServices, callback: Option<Callback>) -> Box<Self> {
+        Box::new(Self { locate_handle: boot_services.locate_handle, key: Cell::new(ptr::null_mut()), callback })
+    }
+
+    fn next_handle(&self) -> Option<efi::Handle> {
+        let key = self.key.get();
+        if key.is_null() {
+            return None;
+        }
+        let mut handle = ptr::null_mut();
+        let mut size = mem::size_of::<efi::Handle>();
+        let status = (self.locate_handle)(efi::BY_REGISTER_NOTIFY, ptr::null_mut(), key, &mut size, &mut handle);
+        // `ByRegisterNotify` returns one handle per call and `NOT_FOUND` once every new handle has been returned.
+        (!status.is_error()).then_some(handle)
+    }
+}
+
+/// Subscription to the installation of protocol `P`.
+///
+/// `P` defaults to `c_void` for subscriptions created from a raw GUID with [`ProtocolNotify::new`] and
+/// [`ProtocolNotify::with_callback`]. The subscription is cancelled when dropped.
+///
+/// # Example
+/// ```no_run
+/// use std::{cell::Cell, rc::Rc};
+/// use mu_rust_helpers::protocol_notify::ProtocolNotify;
+/// use r_efi::{efi, protocols::block_io};
+///
+/// fn count_block_devices(boot_services: &efi::BootServices, count: Rc<Cell<usize>>) -> Result<(), efi::Status> {
+///     // The callback owns what it uses, since the firmware calls it for as long as the subscription is alive.
+///     let callback = move |_| count.set(count.get() + 1);
+///     let notify: ProtocolNotify<block_io::Protocol> =
+///         ProtocolNotify::subscribe_with_callback(boot_services, efi::TPL_CALLBACK, callback)?;
+///     // Block devices are counted for as long as `notify` is alive.
+///     drop(notify);
+///     Ok(())
+/// }
+/// ```
+pub struct ProtocolNotify<'a, P = c_void> {
+    boot_services: &'a efi::BootServices,
+    event: efi::Event,
+    registration: Box<Registration>,
+    _protocol: PhantomData<fn() -> P>,
+}
+
+impl<'a> ProtocolNotify<'a> {
+    /// Subscribe to installations of `protocol`, to be collected with [`Self::drain_new_handles`].
+    pub fn new(boot_services: &'a efi::BootServices, protocol: &efi::Guid) -> Result<Self, efi::Status> {
+        let registration = Registration::new(boot_services, None);
+        Self::register(boot_services, registration, protocol, 0, efi::TPL_APPLICATION, None)
+    }
+
+    /// Subscribe to installations of `protocol`, calling `callback` at `tpl` for every new handle.
+    ///
+    /// Handles that already carry the protocol are not reported. The callback must not borrow anything, since the
+    /// subscription may be leaked with `mem::forget`.
+    pub fn with_callback(
+        boot_services: &'a efi::BootServices,
+        protocol: &efi::Guid,
+        tpl: efi::Tpl,
+        callback: impl FnMut(efi::Handle) + 'static,
+    ) -> Result<Self, efi::Status> {
+        Self::register_callback(boot_services, protocol, tpl, callback)
+    }
+}
+
+impl<'a, P: Protocol> ProtocolNotify<'a, P> {
+    /// Subscribe to installations of `P`, to be collected with [`Self::drain_new_handles`].
+    pub fn subscribe(boot_services: &'a efi::BootServices) -> Result<Self, efi::Status> {
+        let registration = Registration::new(boot_services, None);
+        Self::register(boot_services, registration, &P::GUID, 0, efi::TPL_APPLICATION, None)
+    }
+
+    /// Subscribe to installations of `P`, calling `callback` at `tpl` for every new handle.
+    ///
+    /// Handles that already carry the protocol are not reported. The callback must not borrow anything, since the
+    /// subscription may be leaked with `mem::forget`.
+    pub fn subscribe_with_callback(
+        boot_services: &'a efi::BootServices,
+        tpl: efi::Tpl,
+        callback: impl FnMut(efi::Handle) + 'static,
+    ) -> Result<Self, efi::Status> {
+        Self::register_callback(boot_services, &P::GUID, tpl, callback)
+    }
+}
+
+impl<'a, P> ProtocolNotify<'a, P> {
+    fn register_callback(
+        boot_services: &'a efi::BootServices,
+        protocol: &efi::Guid,
+        tpl: efi::Tpl,
+        callback: impl FnMut(efi::Handle) + 'static,
+    ) -> Result<Self, efi::Status> {
+        extern "efiapi" fn notify(_event: efi::Event, context: *mut c_void) {
+            // SAFETY: the context is the boxed registration owned by the `ProtocolNotify`, which closes this event
+            // before dropping it.
+            let registration = unsafe { &*(context as *const Registration) };
+            let Some(callback) = registration.callback.as_ref() else {
+                return;
+            };
+            while let Some(handle) = registration.next_handle() {
+                (callback.borrow_mut())(handle);
+            }
+        }
+
+        let callback: Box<dyn FnMut(efi::Handle)> = Box::new(callback);
+        let registration = Registration::new(boot_services, Some(RefCell::new(callback)));
+        Self::register(boot_services, registration, protocol, efi::EVT_NOTIFY_SIGNAL, tpl, Some(notify))
+    }
+
+    fn register(
+        boot_services: &'a efi::BootServices,
+        registration: Box<Registration>,
+        protocol: &efi::Guid,
+        event_type: u32,
+        tpl: efi::Tpl,
+        notify: Option<efi::EventNotify>,
+    ) -> Result<Self, efi::Status> {
+        let mut event = ptr::null_mut();
+        let context = &*registration as *const Registration as *mut c_void;
+        let status = (boot_services.create_event)(event_type, tpl, notify, context, &mut event);
+        if status.is_error() {
+            return Err(status);
+        }
+
+        let mut protocol = *protocol;
+        let mut key = ptr::null_mut();
+        let status = (boot_services.register_protocol_notify)(&mut protocol, event, &mut key);
+        if status.is_error() {
+            (boot_services.close_event)(event);
+            return Err(status);
+        }
+        registration.key.set(key);
+        Ok(Self { boot_services, event, registration, _protocol: PhantomData })
+    }
+
+    /// Return every handle on which the protocol was installed since the last call.
+    ///
+    /// Handles are also consumed by the callback, if there is one.
+    pub fn drain_new_handles(&self) -> Vec<efi::Handle> {
+        core::iter::from_fn(|| self.registration.next_handle()).collect()
+    }
+
+    /// Return the event signaled when the protocol is installed, e.g. to wait for it.
+    pub fn event(&self) -> efi::Event {
+        self.event
+    }
+}
+
+impl<P> Drop for ProtocolNotify<'_, P> {
+    fn drop(&mut self) {
+        // Closing the event also cancels the protocol notification registration.
+        (self.boot_services.close_event)(self.event);
+    }
+}
+
+/// Handles collected by the notification function of a [`ProtocolInstalledStream`], and the task waiting for them.
+#[cfg(feature = "executor")]
+#[derive(Default)]
+struct StreamSlot {
+    handles: RefCell<VecDeque<efi::Handle>>,
+    waker: RefCell<Option<Waker>>,
+}
+
+/// Stream of the handles on which protocol `P` is installed.
+///
+/// The handles are collected at `efi::TPL_CALLBACK` by the notification function of a [`ProtocolNotify`], which
+/// wakes the task waiting on the stream. Handles that already carry the protocol when the stream is created are not
+/// yielded. The stream never ends; the registration is cancelled when it is dropped.
+///
+/// Tasks must poll the stream below `TPL_CALLBACK`, which it raises the TPL to while it takes a handle or registers
+/// the waker.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::protocol_notify::ProtocolInstalledStream;
+/// use r_efi::{efi, protocols::block_io};
+///
+/// async fn mount_new_disks(boot_services: &efi::BootServices, mount: fn(efi::Handle)) -> Result<(), efi::Status> {
+///     let mut disks = ProtocolInstalledStream::<block_io::Protocol>::new(boot_services)?;
+///     loop {
+///         mount(disks.next_handle().await);
+///     }
+/// }
+/// ```
+#[cfg(feature = "executor")]
+pub struct ProtocolInstalledStream<'a, P> {
+    notify: ProtocolNotify<'a, P>,
+    slot: Rc<StreamSlot>,
+}
+
+#[cfg(feature = "executor")]
+impl<'a, P: Protocol> ProtocolInstalledStream<'a, P> {
+    /// Start watching installations of `P`.
+    pub fn new(boot_services: &'a efi::BootServices) -> Result<Self, efi::Status> {
+        let slot = Rc::new(StreamSlot::default());
+        let collected = slot.clone();
+        let notify = ProtocolNotify::subscribe_with_callback(boot_services, efi::TPL_CALLBACK, move |handle| {
+            collected.handles.borrow_mut().push_back(handle);
+            if let Some(waker) = collected.waker.borrow_mut().take() {
+                waker.wake();
+            }
+        })?;
+        Ok(Self { notify, slot })
+    }
+}
+
+#[cfg(feature = "executor")]
+impl<'a, P> ProtocolInstalledStream<'a, P> {
+    /// Return the next handle on which the protocol was installed, or register the waker of `cx` to be woken when
+    /// there is one.
+    pub fn poll_next_handle(&self, cx: &mut Context<'_>) -> Poll<efi::Handle> {
+        let boot_services = self.notify.boot_services;
+        // The notification function runs at `TPL_CALLBACK`, so it cannot preempt this.
+        let tpl = (boot_services.raise_tpl)(efi::TPL_CALLBACK);
+        let poll = match self.slot.handles.borrow_mut().pop_front() {
+            Some(handle) => Poll::Ready(handle),
+            None => {
+                *self.slot.waker.borrow_mut() = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        };
+        (boot_services.restore_tpl)(tpl);
+        poll
+    }
+
+    /// Return a future resolving to the next handle on which the protocol is installed.
+    pub fn next_handle(&mut self) -> NextHandle<'_, 'a, P> {
+        NextHandle { stream: self }
+    }
+}
+
+#[cfg(feature = "executor")]
+impl<P> Stream for ProtocolInstalledStream<'_, P> {
+    type Item = efi::Handle;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<efi::Handle>> {
+        self.poll_next_handle(cx).map(Some)
+    }
+}
+
+/// Future returned by [`ProtocolInstalledStream::next_handle`].
+#[cfg(feature = "executor")]
+pub struct NextHandle<'s, 'a, P> {
+    stream: &'s mut ProtocolInstalledStream<'a, P>,
+}
+
+#[cfg(feature = "executor")]
+impl<P> Future for NextHandle<'_, '_, P> {
+    type Output = efi::Handle;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<efi::Handle> {
+        self.stream.poll_next_handle(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{collections::VecDeque, rc::Rc};
+
+    use crate::test_support::mock_efi_boot_services;
+
+    const TEST_PROTOCOL: efi::Guid =
+        efi::Guid::from_fields(0x7c1e4a3b, 0x6d2f, 0x4e8a, 0x91, 0x5c, &[0x3b, 0x2a, 0x19, 0x08, 0xf7, 0xe6]);
+    const TEST_EVENT: usize = 0xe7e7;
+
+    struct TestProtocol;
+
+    // SAFETY: the tests never access the interface.
+    unsafe impl Protocol for TestProtocol {
+        const GUID: efi::Guid = TEST_PROTOCOL;
+    }
+    const TEST_KEY: usize = 0x6e7;
+
+    #[derive(Default)]
+    struct MockState {
+        notify: Option<(efi::EventNotify, usize)>,
+        pending: VecDeque<efi::Handle>,
+        closed: Vec<efi::Event>,
+        tpl: efi::Tpl,
+    }
+
+    std::thread_local! {
+        static STATE: RefCell<MockState> = RefCell::new(MockState::default());
+    }
+
+    extern "efiapi" fn create_event(
+        _event_type: u32,
+        _tpl: efi::Tpl,
+        notify: Option<efi::EventNotify>,
+        context: *mut c_void,
+        event: *mut efi::Event,
+    ) -> efi::Status {
+        STATE.with(|state| state.borrow_mut().notify = notify.map(|notify| (notify, context as usize)));
+        unsafe { *event = TEST_EVENT as efi::Event };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn register_protocol_notify(
+        protocol: *mut efi::Guid,
+        event: efi::Event,
+        key: *mut *mut c_void,
+    ) -> efi::Status {
+        assert_eq!(unsafe { *protocol }, TEST_PROTOCOL);
+        assert_eq!(event, TEST_EVENT as efi::Event);
+        unsafe { *key = TEST_KEY as *mut c_void };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn locate_handle(
+        search_type: efi::LocateSearchType,
+        _protocol: *mut efi::Guid,
+        key: *mut c_void,
+        size: *mut usize,
+        buffer: *mut efi::Handle,
+    ) -> efi::Status {
+        assert_eq!((search_type, key as usize), (efi::BY_REGISTER_NOTIFY, TEST_KEY));
+        assert_eq!(unsafe { *size }, mem::size_of::<efi::Handle>());
+        match STATE.with(|state| state.borrow_mut().pending.pop_front()) {
+            Some(handle) => {
+                unsafe { *buffer = handle };
+                efi::Status::SUCCESS
+            }
+            None => efi::Status::NOT_FOUND,
+        }
+    }
+
+    extern "efiapi" fn close_event(event: efi::Event) -> efi::Status {
+        STATE.with(|state| state.borrow_mut().closed.push(event));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn raise_tpl(tpl: efi::Tpl) -> efi::Tpl {
+        STATE.with(|state| mem::replace(&mut state.borrow_mut().tpl, tpl))
+    }
+
+    extern "efiapi" fn restore_tpl(tpl: efi::Tpl) {
+        STATE.with(|state| state.borrow_mut().tpl = tpl)
+    }
+
+    fn boot_services() -> efi::BootServices {
+        efi::BootServices {
+            create_event,
+            register_protocol_notify,
+            locate_handle,
+            close_event,
+            raise_tpl,
+            restore_tpl,
+            ..mock_efi_boot_services()
+        }
+    }
+
+    fn install(handles: &[usize]) {
+        STATE.with(|state| state.borrow_mut().pending.extend(handles.iter().map(|&handle| handle as efi::Handle)));
+    }
+
+    #[test]
+    fn test_drain_new_handles() {
+        let boot_services = boot_services();
+        let notify = ProtocolNotify::new(&boot_services, &TEST_PROTOCOL).unwrap();
+        assert_eq!(notify.event(), TEST_EVENT as efi::Event);
+        assert!(notify.drain_new_handles().is_empty());
+
+        install(&[1, 2, 3]);
+        assert_eq!(notify.drain_new_handles(), [1 as efi::Handle, 2 as efi::Handle, 3 as efi::Handle]);
+        assert!(notify.drain_new_handles().is_empty());
+
+        drop(notify);
+        assert_eq!(STATE.with(|state| state.borrow().closed.clone()), [TEST_EVENT as efi::Event]);
+    }
+
+    #[test]
+    fn test_callback() {
+        let boot_services = boot_services();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let collected = seen.clone();
+        let notify = ProtocolNotify::with_callback(&boot_services, &TEST_PROTOCOL, efi::TPL_CALLBACK, move |handle| {
+            collected.borrow_mut().push(handle as usize)
+        })
+        .unwrap();
+
+        install(&[4, 5]);
+        let (notify_fn, context) = STATE.with(|state| state.borrow().notify).unwrap();
+        notify_fn(notify.event(), context as *mut c_void);
+        assert_eq!(*seen.borrow(), [4, 5]);
+        drop(notify);
+    }
+
+    #[test]
+    fn test_typed_subscription() {
+        let boot_services = boot_services();
+        let notify = ProtocolNotify::<TestProtocol>::subscribe(&boot_services).unwrap();
+        install(&[6, 7]);
+        assert_eq!(notify.drain_new_handles(), [6 as efi::Handle, 7 as efi::Handle]);
+        drop(notify);
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let collected = seen.clone();
+        let notify: ProtocolNotify<TestProtocol> =
+            ProtocolNotify::subscribe_with_callback(&boot_services, efi::TPL_CALLBACK, move |handle| {
+                collected.borrow_mut().push(handle as usize)
+            })
+            .unwrap();
+        install(&[8]);
+        let (notify_fn, context) = STATE.with(|state| state.borrow().notify).unwrap();
+        notify_fn(notify.event(), context as *mut c_void);
+        assert_eq!(*seen.borrow(), [8]);
+    }
+
+    #[cfg(feature = "executor")]
+    #[test]
+    fn test_protocol_installed_stream() {
+        use executor::{Priority, Scheduler};
+
+        STATE.with(|state| state.borrow_mut().tpl = efi::TPL_APPLICATION);
+        let boot_services = boot_services();
+        let handles = RefCell::new(Vec::new());
+        let mut scheduler = Scheduler::new();
+        let mut stream = ProtocolInstalledStream::<TestProtocol>::new(&boot_services).unwrap();
+        let handles = &handles;
+        scheduler.spawn(Priority::Normal, async move {
+            for _ in 0..3 {
+                let handle = stream.next_handle().await;
+                handles.borrow_mut().push(handle as usize);
+            }
+        });
+
+        assert!(scheduler.run_once());
+        assert!(!scheduler.run_once());
+        install(&[1, 2]);
+        let (notify_fn, context) = STATE.with(|state| state.borrow().notify).unwrap();
+        notify_fn(TEST_EVENT as efi::Event, context as *mut c_void);
+        assert!(scheduler.run_once());
+        assert_eq!(*handles.borrow(), [1, 2]);
+        assert!(!scheduler.run_once());
+        install(&[3]);
+        notify_fn(TEST_EVENT as efi::Event, context as *mut c_void);
+        assert!(scheduler.run_once());
+        assert!(scheduler.is_empty());
+        assert_eq!(*handles.borrow(), [1, 2, 3]);
+        assert_eq!(STATE.with(|state| state.borrow().tpl), efi::TPL_APPLICATION);
+        assert_eq!(STATE.with(|state| state.borrow().closed.clone()), [TEST_EVENT as efi::Event]);
+    }
+
+    #[test]
+    fn test_create_event_failure() {
+        let boot_services = mock_efi_boot_services();
+        assert!(matches!(ProtocolNotify::new(&boot_services, &TEST_PROTOCOL), Err(efi::Status::UNSUPPORTED)));
+    }
+}
@@ -0,0 +1,274 @@
+//! Deadlines and retries with exponential backoff.
+//!
+//! Device, network and variable code all need the same loop: try an operation, wait a little longer after each
+//! failure, and give up once a deadline passes. [`Deadline`] tracks the time limit with a timer event, and
+//! [`retry_with_backoff`] runs the loop according to a [`BackoffPolicy`].
+//!
+use core::{cell::Cell, time::Duration};
+
+use r_efi::efi;
+
+use crate::event::{EventBuilder, TimerEvent};
+
+/// Point in time after which an operation should be abandoned.
+///
+/// The deadline is a one-shot timer event, so it keeps running while the caller stalls or waits for other events.
+pub struct Deadline<'a> {
+    event: Option<TimerEvent<'a>>,
+    is_expired: Cell<bool>,
+}
+
+impl<'a> Deadline<'a> {
+    /// Create a deadline expiring `duration` from now, rounded up to the 100ns resolution of timer events.
+    pub fn after(boot_services: &'a efi::BootServices, duration: Duration) -> Result<Self, efi::Status> {
+        let event = EventBuilder::new().timer().create(boot_services)?;
+        event.set_timer(duration, false)?;
+        Ok(Self { event: Some(event), is_expired: Cell::new(false) })
+    }
+
+    /// Create a deadline that never expires.
+    pub fn never(_boot_services: &'a efi::BootServices) -> Self {
+        Self { event: None, is_expired: Cell::new(false) }
+    }
+
+    /// Return true if the deadline has passed.
+    pub fn is_expired(&self) -> bool {
+        if !self.is_expired.get() {
+            // Checking the event resets it once signaled, so the expiry is latched.
+            self.is_expired.set(self.event.as_ref().is_some_and(|event| event.check()));
+        }
+        self.is_expired.get()
+    }
+
+    /// Return the timer event signaled when the deadline expires, e.g. to wait for it along with other events.
+    ///
+    /// Returns `None` for deadlines that never expire.
+    pub fn event(&self) -> Option<efi::Event> {
+        self.event.as_ref().map(|event| event.as_raw())
+    }
+}
+
+/// Policy of [`retry_with_backoff`].
+///
+/// The delay between attempts starts at `initial_delay` and is multiplied after every failure, up to `max_delay`.
+#[derive(Debug, Clone, Copy)]
+pub struct BackoffPolicy {
+    initial_delay: Duration,
+    max_delay: Duration,
+    multiplier: u32,
+    max_attempts: Option<u32>,
+    should_retry: fn(efi::Status) -> bool,
+}
+
+impl BackoffPolicy {
+    /// Create a policy doubling the delay from `initial_delay` up to `max_delay`, retrying every error until the
+    /// deadline.
+    pub const fn new(initial_delay: Duration, max_delay: Duration) -> Self {
+        Self { initial_delay, max_delay, multiplier: 2, max_attempts: None, should_retry: |_| true }
+    }
+
+    /// Multiply the delay by `multiplier` after each failure. A multiplier of 1 keeps a constant delay.
+    pub const fn multiplier(mut self, multiplier: u32) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Give up after `max_attempts` attempts, even if the deadline has not passed.
+    pub const fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
+    /// Only retry errors for which `should_retry` returns true; others are returned immediately.
+    pub const fn retry_if(mut self, should_retry: fn(efi::Status) -> bool) -> Self {
+        self.should_retry = should_retry;
+        self
+    }
+}
+
+impl Default for BackoffPolicy {
+    /// 1ms doubling up to 100ms.
+    fn default() -> Self {
+        Self::new(Duration::from_millis(1), Duration::from_millis(100))
+    }
+}
+
+/// Run `operation` until it succeeds, stalling between attempts according to `policy`.
+///
+/// Once `deadline` has passed, or the maximum number of attempts is reached, the error of the last attempt is
+/// returned. The operation is always attempted at least once.
+///
+/// # Example
+/// ```no_run
+/// use core::time::Duration;
+///
+/// use mu_rust_helpers::retry::{retry_with_backoff, BackoffPolicy, Deadline};
+/// use r_efi::efi;
+///
+/// fn wait_for_link(boot_services: &efi::BootServices, link_up: impl Fn() -> bool) -> Result<(), efi::Status> {
+///     let deadline = Deadline::after(boot_services, Duration::from_secs(5))?;
+///     let policy = BackoffPolicy::default().retry_if(|status| status == efi::Status::NOT_READY);
+///     retry_with_backoff(boot_services, &policy, &deadline, || {
+///         if link_up() {
+///             Ok(())
+///         } else {
+///             Err(efi::Status::NOT_READY)
+///         }
+///     })
+/// }
+/// ```
+pub fn retry_with_backoff<T>(
+    boot_services: &efi::BootServices,
+    policy: &BackoffPolicy,
+    deadline: &Deadline,
+    mut operation: impl FnMut() -> Result<T, efi::Status>,
+) -> Result<T, efi::Status> {
+    let mut delay = policy.initial_delay.min(policy.max_delay);
+    let mut attempts = 0u32;
+    loop {
+        let status = match operation() {
+            Ok(value) => return Ok(value),
+            Err(status) => status,
+        };
+        attempts = attempts.saturating_add(1);
+        if !(policy.should_retry)(status)
+            || policy.max_attempts.is_some_and(|max_attempts| attempts >= max_attempts)
+            || deadline.is_expired()
+        {
+            return Err(status);
+        }
+
+        let microseconds = delay.as_micros().min(usize::MAX as u128) as usize;
+        (boot_services.stall)(microseconds);
+        delay = delay.saturating_mul(policy.multiplier).min(policy.max_delay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{cell::RefCell, ffi::c_void};
+
+    use crate::test_support::mock_efi_boot_services;
+
+    const TEST_EVENT: usize = 0xdead11;
+
+    #[derive(Default)]
+    struct MockState {
+        timer: Option<(efi::TimerDelay, u64)>,
+        checks_until_expired: Option<usize>,
+        stalls: Vec<usize>,
+        closed: Vec<efi::Event>,
+    }
+
+    std::thread_local! {
+        static STATE: RefCell<MockState> = RefCell::new(MockState::default());
+    }
+
+    extern "efiapi" fn create_event(
+        event_type: u32,
+        _tpl: efi::Tpl,
+        notify: Option<efi::EventNotify>,
+        _context: *mut c_void,
+        event: *mut efi::Event,
+    ) -> efi::Status {
+        assert_eq!(event_type, efi::EVT_TIMER);
+        assert!(notify.is_none());
+        unsafe { *event = TEST_EVENT as efi::Event };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn set_timer(event: efi::Event, delay: efi::TimerDelay, ticks: u64) -> efi::Status {
+        assert_eq!(event, TEST_EVENT as efi::Event);
+        STATE.with(|state| state.borrow_mut().timer = Some((delay, ticks)));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn check_event(_event: efi::Event) -> efi::Status {
+        STATE.with(|state| match &mut state.borrow_mut().checks_until_expired {
+            Some(0) => efi::Status::SUCCESS,
+            Some(checks) => {
+                *checks -= 1;
+                efi::Status::NOT_READY
+            }
+            None => efi::Status::NOT_READY,
+        })
+    }
+
+    extern "efiapi" fn stall(microseconds: usize) -> efi::Status {
+        STATE.with(|state| state.borrow_mut().stalls.push(microseconds));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn close_event(event: efi::Event) -> efi::Status {
+        STATE.with(|state| state.borrow_mut().closed.push(event));
+        efi::Status::SUCCESS
+    }
+
+    fn boot_services() -> efi::BootServices {
+        efi::BootServices { create_event, set_timer, check_event, stall, close_event, ..mock_efi_boot_services() }
+    }
+
+    fn stalls() -> Vec<usize> {
+        STATE.with(|state| state.borrow().stalls.clone())
+    }
+
+    #[test]
+    fn test_deadline() {
+        let boot_services = boot_services();
+        STATE.with(|state| state.borrow_mut().checks_until_expired = Some(1));
+        let deadline = Deadline::after(&boot_services, Duration::from_nanos(250)).unwrap();
+        assert_eq!(STATE.with(|state| state.borrow().timer), Some((efi::TIMER_RELATIVE, 3)));
+        assert!(!deadline.is_expired());
+        assert!(deadline.is_expired());
+        assert!(deadline.is_expired());
+        drop(deadline);
+        assert_eq!(STATE.with(|state| state.borrow().closed.clone()), [TEST_EVENT as efi::Event]);
+
+        let never = Deadline::never(&boot_services);
+        assert!(!never.is_expired());
+        assert!(never.event().is_none());
+    }
+
+    #[test]
+    fn test_retry_until_success() {
+        let boot_services = boot_services();
+        let deadline = Deadline::never(&boot_services);
+        let policy = BackoffPolicy::new(Duration::from_micros(100), Duration::from_micros(500)).multiplier(3);
+        let mut attempts = 0;
+        let result = retry_with_backoff(&boot_services, &policy, &deadline, || {
+            attempts += 1;
+            if attempts < 5 {
+                Err(efi::Status::NOT_READY)
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(result, Ok(5));
+        assert_eq!(stalls(), [100, 300, 500, 500]);
+    }
+
+    #[test]
+    fn test_retry_limits() {
+        let boot_services = boot_services();
+        let never = Deadline::never(&boot_services);
+        let policy = BackoffPolicy::default().max_attempts(3);
+        let result: Result<(), _> = retry_with_backoff(&boot_services, &policy, &never, || Err(efi::Status::TIMEOUT));
+        assert_eq!(result, Err(efi::Status::TIMEOUT));
+        assert_eq!(stalls(), [1000, 2000]);
+
+        let policy = BackoffPolicy::default().retry_if(|status| status == efi::Status::NOT_READY);
+        let result: Result<(), _> =
+            retry_with_backoff(&boot_services, &policy, &never, || Err(efi::Status::DEVICE_ERROR));
+        assert_eq!(result, Err(efi::Status::DEVICE_ERROR));
+        assert_eq!(stalls().len(), 2);
+
+        STATE.with(|state| state.borrow_mut().checks_until_expired = Some(2));
+        let deadline = Deadline::after(&boot_services, Duration::from_millis(10)).unwrap();
+        let result: Result<(), _> =
+            retry_with_backoff(&boot_services, &BackoffPolicy::default(), &deadline, || Err(efi::Status::NOT_READY));
+        assert_eq!(result, Err(efi::Status::NOT_READY));
+        assert_eq!(stalls().len(), 4);
+    }
+}
@@ -0,0 +1,313 @@
+//! UEFI Shell commands implemented by drivers.
+//!
+//! The shell adds a command for every installed `EFI_SHELL_DYNAMIC_COMMAND_PROTOCOL`. [`DynamicCommand::install`]
+//! implements the protocol from a Rust closure, converting the UCS-2 command line into [`Arguments`], so diagnostics
+//! commands can ship as regular drivers.
+//!
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::{cell::RefCell, ffi::c_void, mem, mem::ManuallyDrop, ptr, slice};
+
+use r_efi::{
+    efi,
+    protocols::{shell, shell_dynamic_command, shell_parameters},
+};
+
+use crate::{system_table::StandardSystemTable, ucs2};
+
+/// Arguments of a shell command, without the command name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Arguments {
+    args: Vec<String>,
+}
+
+impl Arguments {
+    /// Create arguments from already split strings.
+    pub fn new(args: Vec<String>) -> Self {
+        Self { args }
+    }
+
+    /// Return all arguments, in order.
+    pub fn as_slice(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Return true if `flag`, e.g. `-v`, is present. Flags are compared case-insensitively, as the shell does.
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.args.iter().any(|arg| arg.eq_ignore_ascii_case(flag))
+    }
+
+    /// Return the argument following `option`, e.g. the file name in `-o log.txt`.
+    pub fn value(&self, option: &str) -> Option<&str> {
+        let index = self.args.iter().position(|arg| arg.eq_ignore_ascii_case(option))?;
+        self.args.get(index + 1).map(String::as_str)
+    }
+
+    /// Return the arguments that do not start with `-`. Values of options are included.
+    pub fn positional(&self) -> impl Iterator<Item = &str> {
+        self.args.iter().map(String::as_str).filter(|arg| !arg.starts_with('-'))
+    }
+}
+
+type HandlerFn = dyn FnMut(&Arguments, &StandardSystemTable) -> efi::Status;
+type Handler = RefCell<Box<HandlerFn>>;
+type GetHelpFn = extern "efiapi" fn(*mut shell_dynamic_command::Protocol, *mut u8) -> *mut u16;
+
+// The protocol must be the first field, so the instance can be found from the protocol pointer given to the handler.
+// The instance stays reachable from the protocol database if the command is leaked, so it borrows nothing.
+#[repr(C)]
+struct Instance {
+    protocol: shell_dynamic_command::Protocol,
+    allocate_pool: efi::BootAllocatePool,
+    name: Vec<u16>,
+    help: Vec<u16>,
+    handler: Handler,
+}
+
+/// Shell command installed as a dynamic command protocol, uninstalled when dropped.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::shell_command::DynamicCommand;
+/// use r_efi::efi;
+///
+/// fn install_command(boot_services: &'static efi::BootServices) -> Result<(), efi::Status> {
+///     let command = DynamicCommand::install(boot_services, "hello", "Say hello.\r\n", |args, _system_table| {
+///         if args.has_flag("-q") {
+///             return efi::Status::SUCCESS;
+///         }
+///         efi::Status::SUCCESS
+///     })?;
+///     // The command stays available for as long as the driver is loaded.
+///     core::mem::forget(command);
+///     Ok(())
+/// }
+/// ```
+pub struct DynamicCommand<'a> {
+    boot_services: &'a efi::BootServices,
+    handle: efi::Handle,
+    instance: ManuallyDrop<Box<Instance>>,
+}
+
+impl<'a> DynamicCommand<'a> {
+    /// Install the command `name` on a new handle, calling `handler` when it runs.
+    ///
+    /// `help` is returned by the shell `help` command, whatever the requested language. The exit status of the
+    /// command is the status returned by `handler`. Returns `efi::Status::INVALID_PARAMETER` if `name` or `help`
+    /// cannot be represented in UCS-2.
+    ///
+    /// The handler must not borrow anything, since the command may be leaked with `mem::forget` and keep running it.
+    pub fn install(
+        boot_services: &'a efi::BootServices,
+        name: &str,
+        help: &str,
+        handler: impl FnMut(&Arguments, &StandardSystemTable) -> efi::Status + 'static,
+    ) -> Result<Self, efi::Status> {
+        let encode = |string: &str| {
+            let mut buffer = alloc::vec![0; string.chars().count() + 1];
+            ucs2::encode_into(string, &mut buffer)?;
+            Ok::<_, efi::Status>(buffer)
+        };
+        let name = encode(name)?;
+        let help = encode(help)?;
+
+        let handler: Box<HandlerFn> = Box::new(handler);
+        let mut instance = Box::new(Instance {
+            protocol: shell_dynamic_command::Protocol {
+                command_name: ptr::null_mut(),
+                handler: handle_command,
+                // SAFETY: r-efi declares GetHelp as returning a status, but it returns a string pointer. Both are
+                // returned in the same register.
+                get_help: unsafe { mem::transmute::<GetHelpFn, shell_dynamic_command::CommandGetHelp>(get_help) },
+            },
+            allocate_pool: boot_services.allocate_pool,
+            name,
+            help,
+            handler: RefCell::new(handler),
+        });
+        // The name buffer is heap allocated, so the pointer stays valid when the instance is moved into place.
+        instance.protocol.command_name = instance.name.as_mut_ptr();
+
+        let mut handle = ptr::null_mut();
+        let mut guid = shell_dynamic_command::PROTOCOL_GUID;
+        let interface = &mut instance.protocol as *mut shell_dynamic_command::Protocol as *mut c_void;
+        let status =
+            (boot_services.install_protocol_interface)(&mut handle, &mut guid, efi::NATIVE_INTERFACE, interface);
+        if status.is_error() {
+            return Err(status);
+        }
+        Ok(Self { boot_services, handle, instance: ManuallyDrop::new(instance) })
+    }
+
+    /// Return the handle the protocol is installed on.
+    pub fn handle(&self) -> efi::Handle {
+        self.handle
+    }
+}
+
+impl Drop for DynamicCommand<'_> {
+    fn drop(&mut self) {
+        let mut guid = shell_dynamic_command::PROTOCOL_GUID;
+        let interface = &mut self.instance.protocol as *mut shell_dynamic_command::Protocol as *mut c_void;
+        // If the shell still has the protocol open, the instance cannot be freed safely.
+        if !(self.boot_services.uninstall_protocol_interface)(self.handle, &mut guid, interface).is_error() {
+            // SAFETY: the instance is no longer reachable from the protocol database.
+            unsafe { ManuallyDrop::drop(&mut self.instance) };
+        }
+    }
+}
+
+/// Return the arguments of the shell parameters, without the command name.
+///
+/// # Safety
+/// `parameters` must point to a valid shell parameters protocol.
+unsafe fn arguments(parameters: *const shell_parameters::Protocol) -> Arguments {
+    let Some(parameters) = parameters.as_ref() else {
+        return Arguments::default();
+    };
+    if parameters.argv.is_null() {
+        return Arguments::default();
+    }
+    let argv = slice::from_raw_parts(parameters.argv, parameters.argc);
+    Arguments::new(argv.iter().skip(1).map(|&arg| ucs2::decode(ucs2::from_ptr(arg)).collect()).collect())
+}
+
+extern "efiapi" fn handle_command(
+    protocol: *mut shell_dynamic_command::Protocol,
+    system_table: *mut efi::SystemTable,
+    parameters: *mut shell_parameters::Protocol,
+    _shell: *mut shell::Protocol,
+) -> efi::Status {
+    // SAFETY: the shell passes the protocol installed by `DynamicCommand::install`, the first field of an instance.
+    let instance = unsafe { &*(protocol as *const Instance) };
+    // SAFETY: the shell passes the system table and the parameters of the command line.
+    let (Some(system_table), arguments) = (unsafe { system_table.as_ref() }, unsafe { arguments(parameters) }) else {
+        return efi::Status::INVALID_PARAMETER;
+    };
+    let Ok(mut handler) = instance.handler.try_borrow_mut() else {
+        // The command was started again from its own handler, e.g. through the shell protocol.
+        return efi::Status::ACCESS_DENIED;
+    };
+    handler(&arguments, &StandardSystemTable::new(system_table))
+}
+
+extern "efiapi" fn get_help(protocol: *mut shell_dynamic_command::Protocol, _language: *mut u8) -> *mut u16 {
+    // SAFETY: see `handle_command`.
+    let instance = unsafe { &*(protocol as *const Instance) };
+    // The shell frees the returned string with FreePool.
+    let size = mem::size_of_val(instance.help.as_slice());
+    let mut buffer = ptr::null_mut();
+    if (instance.allocate_pool)(efi::BOOT_SERVICES_DATA, size, &mut buffer).is_error() {
+        return ptr::null_mut();
+    }
+    let help = buffer as *mut u16;
+    // SAFETY: the pool buffer holds `size` bytes.
+    unsafe { help.copy_from_nonoverlapping(instance.help.as_ptr(), instance.help.len()) };
+    help
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        alloc::{alloc, Layout},
+        rc::Rc,
+    };
+
+    use crate::{system_table::tests::mock_efi_system_table, test_support::mock_efi_boot_services};
+
+    std::thread_local! {
+        static INSTALLED: RefCell<Option<*mut c_void>> = const { RefCell::new(None) };
+    }
+
+    extern "efiapi" fn install_protocol_interface(
+        handle: *mut efi::Handle,
+        protocol: *mut efi::Guid,
+        interface_type: efi::InterfaceType,
+        interface: *mut c_void,
+    ) -> efi::Status {
+        assert_eq!(
+            (unsafe { *protocol }, interface_type),
+            (shell_dynamic_command::PROTOCOL_GUID, efi::NATIVE_INTERFACE)
+        );
+        INSTALLED.with(|installed| *installed.borrow_mut() = Some(interface));
+        unsafe { *handle = 0x5e11 as efi::Handle };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn uninstall_protocol_interface(
+        handle: efi::Handle,
+        _protocol: *mut efi::Guid,
+        interface: *mut c_void,
+    ) -> efi::Status {
+        assert_eq!(handle, 0x5e11 as efi::Handle);
+        INSTALLED.with(|installed| assert_eq!(installed.borrow_mut().take(), Some(interface)));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn allocate_pool(
+        _pool_type: efi::MemoryType,
+        size: usize,
+        buffer: *mut *mut c_void,
+    ) -> efi::Status {
+        unsafe { *buffer = alloc(Layout::from_size_align(size, 8).unwrap()) as *mut c_void };
+        efi::Status::SUCCESS
+    }
+
+    fn ucs2_vec(string: &str) -> Vec<u16> {
+        string.encode_utf16().chain([0]).collect()
+    }
+
+    #[test]
+    fn test_arguments() {
+        let args = Arguments::new(["-V", "-o", "log.txt", "fs0:"].map(String::from).to_vec());
+        assert!(args.has_flag("-v"));
+        assert!(!args.has_flag("-q"));
+        assert_eq!(args.value("-O"), Some("log.txt"));
+        assert_eq!(args.value("fs0:"), None);
+        assert_eq!(args.positional().collect::<Vec<_>>(), ["log.txt", "fs0:"]);
+    }
+
+    #[test]
+    fn test_dynamic_command() {
+        let boot_services = efi::BootServices {
+            install_protocol_interface,
+            uninstall_protocol_interface,
+            allocate_pool,
+            ..mock_efi_boot_services()
+        };
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let calls = seen.clone();
+        let command = DynamicCommand::install(&boot_services, "hello", "Say hello.", move |args, system_table| {
+            calls.borrow_mut().push((args.as_slice().to_vec(), system_table.firmware_revision()));
+            efi::Status::WARN_UNKNOWN_GLYPH
+        })
+        .unwrap();
+        assert_eq!(command.handle(), 0x5e11 as efi::Handle);
+
+        let protocol = INSTALLED.with(|installed| installed.borrow().unwrap()) as *mut shell_dynamic_command::Protocol;
+        let name = unsafe { ucs2::from_ptr((*protocol).command_name) };
+        assert!(ucs2::decode(name).eq("hello".chars()));
+
+        let help = unsafe { ((*protocol).get_help)(protocol, ptr::null_mut()) };
+        let help = unsafe { ucs2::from_ptr(help.as_usize() as *const u16) };
+        assert!(ucs2::decode(help).eq("Say hello.".chars()));
+
+        let mut args = [ucs2_vec("hello"), ucs2_vec("-v"), ucs2_vec("\u{e9}t\u{e9}")];
+        let mut argv: Vec<*mut u16> = args.iter_mut().map(|arg| arg.as_mut_ptr()).collect();
+        let mut parameters = shell_parameters::Protocol {
+            argv: argv.as_mut_ptr(),
+            argc: argv.len(),
+            std_in: ptr::null_mut(),
+            std_out: ptr::null_mut(),
+            std_err: ptr::null_mut(),
+        };
+        let mut system_table = mock_efi_system_table(&mut []);
+        let status = unsafe { ((*protocol).handler)(protocol, &mut system_table, &mut parameters, ptr::null_mut()) };
+        assert_eq!(status, efi::Status::WARN_UNKNOWN_GLYPH);
+
+        drop(command);
+        assert!(INSTALLED.with(|installed| installed.borrow().is_none()));
+        assert_eq!(*seen.borrow(), [(vec![String::from("-v"), String::from("\u{e9}t\u{e9}")], 0x10000)]);
+    }
+}
@@ -0,0 +1,434 @@
+//! UEFI system table support.
+//!
+//! [`StandardSystemTable`] wraps the `efi::SystemTable` passed to an image entry point and exposes typed accessors to
+//! its consoles, firmware information, service tables and configuration tables. [`StandardSystemTable::uefi_version`]
+//! and [`StandardSystemTable::supports`] let code adapt to the firmware it runs on without comparing raw revisions.
+//!
+use alloc::string::String;
+use core::{
+    ffi::c_void,
+    fmt,
+    marker::PhantomData,
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use r_efi::{
+    efi,
+    protocols::{memory_attribute, rng, simple_text_input, simple_text_output, timestamp},
+};
+
+use crate::{
+    config_table::ConfigTable,
+    firmware_slice::{FirmwareRef, FirmwareSlice},
+    ucs2,
+};
+
+/// Version of the UEFI specification, e.g. 2.3.1.
+///
+/// Versions compare in release order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UefiVersion {
+    /// Major version.
+    pub major: u16,
+    /// Minor version.
+    pub minor: u16,
+    /// Errata version, e.g. 1 in 2.3.1.
+    pub patch: u16,
+}
+
+impl UefiVersion {
+    /// UEFI 2.0, which added CreateEventEx, UpdateCapsule and QueryVariableInfo.
+    pub const V2_0: Self = Self::new(2, 0, 0);
+    /// UEFI 2.3.1, which added time-based authenticated variables.
+    pub const V2_3_1: Self = Self::new(2, 3, 1);
+    /// UEFI 2.8, which added the runtime properties table.
+    pub const V2_8: Self = Self::new(2, 8, 0);
+
+    /// Create a version.
+    pub const fn new(major: u16, minor: u16, patch: u16) -> Self {
+        Self { major, minor, patch }
+    }
+
+    /// Decode a table header revision: the major version in the upper 16 bits, and the minor and errata versions as
+    /// tens and units of the lower 16 bits.
+    pub const fn from_revision(revision: u32) -> Self {
+        let minor = (revision & 0xffff) as u16;
+        Self::new((revision >> 16) as u16, minor / 10, minor % 10)
+    }
+
+    /// Return the version encoded as a table header revision.
+    pub const fn revision(self) -> u32 {
+        (self.major as u32) << 16 | (self.minor as u32 * 10 + self.patch as u32)
+    }
+}
+
+impl fmt::Display for UefiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)?;
+        if self.patch != 0 {
+            write!(f, ".{}", self.patch)?;
+        }
+        Ok(())
+    }
+}
+
+/// Optional firmware feature probed with [`StandardSystemTable::supports`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// CreateEventEx and event groups, from UEFI 2.0.
+    EventGroups,
+    /// UpdateCapsule and QueryCapsuleCapabilities, from UEFI 2.0.
+    CapsuleUpdate,
+    /// QueryVariableInfo, from UEFI 2.0.
+    QueryVariableInfo,
+    /// Time-based authenticated variables, from UEFI 2.3.1.
+    TimeBasedAuthenticatedVariables,
+    /// `EFI_MEMORY_ATTRIBUTES_TABLE`, published by the firmware.
+    MemoryAttributesTable,
+    /// `EFI_RT_PROPERTIES_TABLE`, published by the firmware.
+    RuntimePropertiesTable,
+    /// `EFI_MEMORY_ATTRIBUTE_PROTOCOL`, installed by the firmware.
+    MemoryAttributeProtocol,
+    /// `EFI_RNG_PROTOCOL`, installed by the firmware.
+    Rng,
+    /// `EFI_TIMESTAMP_PROTOCOL`, installed by the firmware.
+    Timestamp,
+}
+
+/// Wrapper around the firmware-provided `efi::SystemTable`.
+///
+/// The console and boot services accessors return `None` once the corresponding pointers are cleared, which firmware
+/// does when ExitBootServices is called.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::system_table::StandardSystemTable;
+/// use r_efi::efi;
+///
+/// pub static SYSTEM_TABLE: StandardSystemTable = StandardSystemTable::new_uninit();
+///
+/// pub extern "efiapi" fn efi_main(_image_handle: efi::Handle, system_table: *const efi::SystemTable) -> efi::Status {
+///     SYSTEM_TABLE.initialize(unsafe { &*system_table });
+///     let _vendor = SYSTEM_TABLE.firmware_vendor();
+///     let _runtime_services = SYSTEM_TABLE.runtime_services();
+///     efi::Status::SUCCESS
+/// }
+/// ```
+#[derive(Debug)]
+pub struct StandardSystemTable<'a> {
+    efi_system_table: AtomicPtr<efi::SystemTable>,
+    _lifetime_marker: PhantomData<&'a efi::SystemTable>,
+}
+
+// SAFETY: the system table is only read through this wrapper, and UEFI services are only used from the boot processor.
+unsafe impl Sync for StandardSystemTable<'_> {}
+// SAFETY: see above.
+unsafe impl Send for StandardSystemTable<'_> {}
+
+impl<'a> StandardSystemTable<'a> {
+    /// Create a new StandardSystemTable from the firmware-provided table.
+    pub const fn new(efi_system_table: &'a efi::SystemTable) -> Self {
+        // The table is only ever read through this pointer. The cast is needed because `AtomicPtr` stores `*mut T`.
+        let efi_system_table = efi_system_table as *const efi::SystemTable as *mut efi::SystemTable;
+        Self { efi_system_table: AtomicPtr::new(efi_system_table), _lifetime_marker: PhantomData }
+    }
+
+    /// Create a new StandardSystemTable that must be initialized with [`Self::initialize`] before use.
+    pub const fn new_uninit() -> Self {
+        Self { efi_system_table: AtomicPtr::new(ptr::null_mut()), _lifetime_marker: PhantomData }
+    }
+
+    /// Initialize the StandardSystemTable with the firmware-provided table.
+    ///
+    /// Calls made after the first successful initialization are ignored.
+    pub fn initialize(&'a self, efi_system_table: &'a efi::SystemTable) {
+        let efi_system_table = efi_system_table as *const efi::SystemTable as *mut efi::SystemTable;
+        let _ = self.efi_system_table.compare_exchange(
+            ptr::null_mut(),
+            efi_system_table,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        );
+    }
+
+    /// Return true if the StandardSystemTable has been initialized.
+    pub fn is_init(&self) -> bool {
+        !self.efi_system_table.load(Ordering::SeqCst).is_null()
+    }
+
+    /// Return the underlying `efi::SystemTable`.
+    ///
+    /// # Panic
+    /// This function will panic if the StandardSystemTable has not been initialized.
+    pub fn as_efi_system_table(&self) -> &efi::SystemTable {
+        let efi_system_table = self.efi_system_table.load(Ordering::SeqCst);
+        // SAFETY: a non-null pointer always originates from a reference with lifetime 'a.
+        unsafe { efi_system_table.as_ref() }.expect("System table is not initialized.")
+    }
+
+    /// Return the revision of the UEFI specification the system table conforms to.
+    pub fn revision(&self) -> u32 {
+        self.as_efi_system_table().hdr.revision
+    }
+
+    /// Return the firmware vendor string, with invalid UCS-2 replaced by U+FFFD.
+    pub fn firmware_vendor(&self) -> String {
+        // SAFETY: the firmware vendor is a null-terminated string that lives as long as the system table.
+        let vendor = unsafe { ucs2::from_ptr(self.as_efi_system_table().firmware_vendor) };
+        ucs2::decode(vendor).collect()
+    }
+
+    /// Return the vendor-specific firmware revision.
+    pub fn firmware_revision(&self) -> u32 {
+        self.as_efi_system_table().firmware_revision
+    }
+
+    /// Return the version of the UEFI specification the system table conforms to.
+    pub fn uefi_version(&self) -> UefiVersion {
+        UefiVersion::from_revision(self.revision())
+    }
+
+    /// Return true if the firmware provides `feature`.
+    ///
+    /// Services are probed from the specification version, tables from the configuration tables, and protocols with
+    /// LocateProtocol. Protocols are reported as missing after ExitBootServices.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use mu_rust_helpers::system_table::{Feature, StandardSystemTable};
+    ///
+    /// fn protect_code_pages(system_table: &StandardSystemTable) {
+    ///     if !system_table.supports(Feature::MemoryAttributeProtocol) {
+    ///         // Firmware older than UEFI 2.10: leave the pages writable.
+    ///         return;
+    ///     }
+    /// }
+    /// ```
+    pub fn supports(&self, feature: Feature) -> bool {
+        let (version, table, protocol) = match feature {
+            Feature::EventGroups | Feature::CapsuleUpdate | Feature::QueryVariableInfo => {
+                (Some(UefiVersion::V2_0), None, None)
+            }
+            Feature::TimeBasedAuthenticatedVariables => (Some(UefiVersion::V2_3_1), None, None),
+            Feature::MemoryAttributesTable => (None, Some(efi::MEMORY_ATTRIBUTES_TABLE_GUID), None),
+            Feature::RuntimePropertiesTable => (None, Some(efi::RT_PROPERTIES_TABLE_GUID), None),
+            Feature::MemoryAttributeProtocol => (None, None, Some(memory_attribute::PROTOCOL_GUID)),
+            Feature::Rng => (None, None, Some(rng::PROTOCOL_GUID)),
+            Feature::Timestamp => (None, None, Some(timestamp::PROTOCOL_GUID)),
+        };
+        if version.is_some_and(|version| self.uefi_version() < version) {
+            return false;
+        }
+        if table.is_some_and(|table| self.find_config_table_by_guid(&table).is_none()) {
+            return false;
+        }
+        match protocol {
+            Some(mut protocol) => self.boot_services().is_some_and(|boot_services| {
+                let mut interface = ptr::null_mut();
+                !(boot_services.locate_protocol)(&mut protocol, ptr::null_mut(), &mut interface).is_error()
+            }),
+            None => true,
+        }
+    }
+
+    /// Return the console input handle and protocol, if available.
+    pub fn con_in(&self) -> Option<(efi::Handle, &simple_text_input::Protocol)> {
+        let system_table = self.as_efi_system_table();
+        // SAFETY: the firmware either clears the pointer or keeps it pointing to a valid protocol.
+        unsafe { system_table.con_in.as_ref() }.map(|protocol| (system_table.console_in_handle, protocol))
+    }
+
+    /// Return the console output handle and protocol, if available.
+    pub fn con_out(&self) -> Option<(efi::Handle, &simple_text_output::Protocol)> {
+        let system_table = self.as_efi_system_table();
+        // SAFETY: see `con_in`.
+        unsafe { system_table.con_out.as_ref() }.map(|protocol| (system_table.console_out_handle, protocol))
+    }
+
+    /// Return the standard error handle and protocol, if available.
+    pub fn std_err(&self) -> Option<(efi::Handle, &simple_text_output::Protocol)> {
+        let system_table = self.as_efi_system_table();
+        // SAFETY: see `con_in`.
+        unsafe { system_table.std_err.as_ref() }.map(|protocol| (system_table.standard_error_handle, protocol))
+    }
+
+    /// Return the boot services table, or `None` after ExitBootServices.
+    pub fn boot_services(&self) -> Option<&efi::BootServices> {
+        // SAFETY: see `con_in`.
+        unsafe { self.as_efi_system_table().boot_services.as_ref() }
+    }
+
+    /// Return the runtime services table.
+    pub fn runtime_services(&self) -> &efi::RuntimeServices {
+        // SAFETY: the runtime services pointer is always valid.
+        unsafe { &*self.as_efi_system_table().runtime_services }
+    }
+
+    /// Return the configuration tables.
+    ///
+    /// Installing or removing a configuration table may reallocate the array, so the returned view must not be held
+    /// across those operations.
+    pub fn configuration_tables(&self) -> FirmwareSlice<'_, efi::ConfigurationTable> {
+        let system_table = self.as_efi_system_table();
+        // SAFETY: the firmware keeps `number_of_table_entries` entries at `configuration_table`.
+        unsafe { FirmwareSlice::new(system_table.configuration_table, system_table.number_of_table_entries) }
+            .unwrap_or_else(|_| FirmwareSlice::default())
+    }
+
+    /// Return the pointer published for the configuration table `guid`, if any.
+    pub fn find_config_table_by_guid(&self, guid: &efi::Guid) -> Option<*mut c_void> {
+        self.configuration_tables().iter().find(|table| table.vendor_guid == *guid).map(|table| table.vendor_table)
+    }
+
+    /// Return the configuration table of type `T`, if it is published and its pointer is valid.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use mu_rust_helpers::{config_table::AcpiRsdp, system_table::StandardSystemTable};
+    ///
+    /// fn xsdt_address(system_table: &StandardSystemTable) -> Option<u64> {
+    ///     system_table.find_config_table::<AcpiRsdp>().map(|rsdp| rsdp.xsdt_address)
+    /// }
+    /// ```
+    pub fn find_config_table<T: ConfigTable>(&self) -> Option<&T> {
+        let table = self.find_config_table_by_guid(&T::GUID)?;
+        // SAFETY: `ConfigTable` guarantees that tables published with `T::GUID` start with a `T`.
+        unsafe { FirmwareRef::new(table as *const T) }.ok().map(|table| table.get())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use crate::test_support::mock_efi_boot_services;
+
+    const TEST_VENDOR: &[u16] = &[b'M' as u16, b'u' as u16, 0xD800, 0];
+
+    pub(crate) fn mock_efi_system_table(configuration_table: &mut [efi::ConfigurationTable]) -> efi::SystemTable {
+        efi::SystemTable {
+            hdr: efi::TableHeader {
+                signature: efi::SYSTEM_TABLE_SIGNATURE,
+                revision: efi::SYSTEM_TABLE_REVISION,
+                header_size: core::mem::size_of::<efi::SystemTable>() as u32,
+                crc32: 0,
+                reserved: 0,
+            },
+            firmware_vendor: TEST_VENDOR.as_ptr() as *mut u16,
+            firmware_revision: 0x10000,
+            console_in_handle: 1 as efi::Handle,
+            con_in: ptr::null_mut(),
+            console_out_handle: ptr::null_mut(),
+            con_out: ptr::null_mut(),
+            standard_error_handle: ptr::null_mut(),
+            std_err: ptr::null_mut(),
+            runtime_services: ptr::null_mut(),
+            boot_services: ptr::null_mut(),
+            number_of_table_entries: configuration_table.len(),
+            configuration_table: configuration_table.as_mut_ptr(),
+        }
+    }
+
+    extern "efiapi" fn mock_reset(_this: *mut simple_text_input::Protocol, _extended: efi::Boolean) -> efi::Status {
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn mock_read_key_stroke(
+        _this: *mut simple_text_input::Protocol,
+        _key: *mut simple_text_input::InputKey,
+    ) -> efi::Status {
+        efi::Status::NOT_READY
+    }
+
+    #[test]
+    fn test_initialize() {
+        let mut configuration_table = [];
+        let efi_system_table = mock_efi_system_table(&mut configuration_table);
+        let system_table = StandardSystemTable::new_uninit();
+        assert!(!system_table.is_init());
+        system_table.initialize(&efi_system_table);
+        assert!(system_table.is_init());
+        assert_eq!(system_table.revision(), efi::SYSTEM_TABLE_REVISION);
+    }
+
+    #[test]
+    #[should_panic = "System table is not initialized."]
+    fn test_uninit_panics() {
+        StandardSystemTable::new_uninit().firmware_revision();
+    }
+
+    #[test]
+    fn test_accessors() {
+        let mut configuration_table = [efi::ConfigurationTable {
+            vendor_guid: efi::Guid::from_fields(0x1, 0x2, 0x3, 0x4, 0x5, &[0x6; 6]),
+            vendor_table: 0x1000 as *mut c_void,
+        }];
+        let mut efi_system_table = mock_efi_system_table(&mut configuration_table);
+        let mut con_in = simple_text_input::Protocol {
+            reset: mock_reset,
+            read_key_stroke: mock_read_key_stroke,
+            wait_for_key: ptr::null_mut(),
+        };
+        efi_system_table.con_in = &mut con_in;
+        let system_table = StandardSystemTable::new(&efi_system_table);
+
+        assert_eq!(system_table.firmware_vendor(), "Mu\u{FFFD}");
+        assert_eq!(system_table.firmware_revision(), 0x10000);
+        assert_eq!(system_table.con_in().map(|(handle, _)| handle), Some(1 as efi::Handle));
+        assert!(system_table.con_out().is_none());
+        assert!(system_table.std_err().is_none());
+        assert!(system_table.boot_services().is_none());
+
+        let tables = system_table.configuration_tables();
+        assert_eq!(tables.len(), 1);
+        assert_eq!(tables[0].vendor_table, 0x1000 as *mut c_void);
+    }
+
+    #[test]
+    fn test_uefi_version() {
+        let version = UefiVersion::from_revision(efi::SYSTEM_TABLE_REVISION_2_31);
+        assert_eq!(version, UefiVersion::V2_3_1);
+        assert_eq!(version.revision(), efi::SYSTEM_TABLE_REVISION_2_31);
+        assert_eq!(version.to_string(), "2.3.1");
+        assert_eq!(UefiVersion::from_revision(efi::SYSTEM_TABLE_REVISION_2_70).to_string(), "2.7");
+        assert!(UefiVersion::V2_3_1 < UefiVersion::from_revision(efi::SYSTEM_TABLE_REVISION_2_40));
+        assert!(UefiVersion::V2_8 > UefiVersion::from_revision(efi::SYSTEM_TABLE_REVISION_2_70));
+    }
+
+    #[test]
+    fn test_supports() {
+        extern "efiapi" fn locate_protocol(
+            protocol: *mut efi::Guid,
+            _registration: *mut c_void,
+            interface: *mut *mut c_void,
+        ) -> efi::Status {
+            if unsafe { *protocol } != rng::PROTOCOL_GUID {
+                return efi::Status::NOT_FOUND;
+            }
+            unsafe { *interface = 0x2a9 as *mut c_void };
+            efi::Status::SUCCESS
+        }
+
+        let mut configuration_table = [efi::ConfigurationTable {
+            vendor_guid: efi::MEMORY_ATTRIBUTES_TABLE_GUID,
+            vendor_table: 0x1000 as *mut c_void,
+        }];
+        let mut efi_system_table = mock_efi_system_table(&mut configuration_table);
+        efi_system_table.hdr.revision = efi::SYSTEM_TABLE_REVISION_2_30;
+        let system_table = StandardSystemTable::new(&efi_system_table);
+        assert_eq!(system_table.uefi_version(), UefiVersion::new(2, 3, 0));
+        assert!(system_table.supports(Feature::EventGroups));
+        assert!(!system_table.supports(Feature::TimeBasedAuthenticatedVariables));
+        assert!(system_table.supports(Feature::MemoryAttributesTable));
+        assert!(!system_table.supports(Feature::RuntimePropertiesTable));
+        // Boot services are gone.
+        assert!(!system_table.supports(Feature::Rng));
+
+        let mut boot_services = efi::BootServices { locate_protocol, ..mock_efi_boot_services() };
+        efi_system_table.boot_services = &mut boot_services;
+        let system_table = StandardSystemTable::new(&efi_system_table);
+        assert!(system_table.supports(Feature::Rng));
+        assert!(!system_table.supports(Feature::Timestamp));
+    }
+}
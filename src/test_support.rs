@@ -0,0 +1,79 @@
+//! Fixtures shared by the unit tests.
+//!
+//! Most tests exercise a helper against a boot services table where only the services under test are mocked.
+//! [`mock_efi_boot_services`] supplies the rest as stubs returning `efi::Status::UNSUPPORTED`, and
+//! [`unimplemented_service`] does the same for individual protocol members.
+//!
+
+use core::ptr;
+
+use r_efi::efi;
+
+extern "efiapi" fn unimplemented_stub() -> efi::Status {
+    efi::Status::UNSUPPORTED
+}
+
+/// Return a pointer to a stub returning `efi::Status::UNSUPPORTED`, for services a test does not use.
+pub(crate) fn unimplemented_service<F: Copy>() -> F {
+    let stub: extern "efiapi" fn() -> efi::Status = unimplemented_stub;
+    // SAFETY: every firmware service is an `efiapi` function pointer returning a pointer-sized value or nothing, and
+    // the stub ignores its arguments.
+    unsafe { core::mem::transmute_copy(&stub) }
+}
+
+/// Return a boot services table where every service returns `efi::Status::UNSUPPORTED`.
+pub(crate) fn mock_efi_boot_services() -> efi::BootServices {
+    efi::BootServices {
+        hdr: efi::TableHeader {
+            signature: efi::BOOT_SERVICES_SIGNATURE,
+            revision: efi::BOOT_SERVICES_REVISION,
+            header_size: core::mem::size_of::<efi::BootServices>() as u32,
+            crc32: 0,
+            reserved: 0,
+        },
+        raise_tpl: unimplemented_service(),
+        restore_tpl: unimplemented_service(),
+        allocate_pages: unimplemented_service(),
+        free_pages: unimplemented_service(),
+        get_memory_map: unimplemented_service(),
+        allocate_pool: unimplemented_service(),
+        free_pool: unimplemented_service(),
+        create_event: unimplemented_service(),
+        set_timer: unimplemented_service(),
+        wait_for_event: unimplemented_service(),
+        signal_event: unimplemented_service(),
+        close_event: unimplemented_service(),
+        check_event: unimplemented_service(),
+        install_protocol_interface: unimplemented_service(),
+        reinstall_protocol_interface: unimplemented_service(),
+        uninstall_protocol_interface: unimplemented_service(),
+        handle_protocol: unimplemented_service(),
+        reserved: ptr::null_mut(),
+        register_protocol_notify: unimplemented_service(),
+        locate_handle: unimplemented_service(),
+        locate_device_path: unimplemented_service(),
+        install_configuration_table: unimplemented_service(),
+        load_image: unimplemented_service(),
+        start_image: unimplemented_service(),
+        exit: unimplemented_service(),
+        unload_image: unimplemented_service(),
+        exit_boot_services: unimplemented_service(),
+        get_next_monotonic_count: unimplemented_service(),
+        stall: unimplemented_service(),
+        set_watchdog_timer: unimplemented_service(),
+        connect_controller: unimplemented_service(),
+        disconnect_controller: unimplemented_service(),
+        open_protocol: unimplemented_service(),
+        close_protocol: unimplemented_service(),
+        open_protocol_information: unimplemented_service(),
+        protocols_per_handle: unimplemented_service(),
+        locate_handle_buffer: unimplemented_service(),
+        locate_protocol: unimplemented_service(),
+        install_multiple_protocol_interfaces: unimplemented_service(),
+        uninstall_multiple_protocol_interfaces: unimplemented_service(),
+        calculate_crc32: unimplemented_service(),
+        copy_mem: unimplemented_service(),
+        set_mem: unimplemented_service(),
+        create_event_ex: unimplemented_service(),
+    }
+}
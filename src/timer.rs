@@ -0,0 +1,286 @@
+//! Timers running a closure.
+//!
+//! A timer notification takes three calls to boot services: create a timer event with a notification function, arm
+//! it with SetTimer in units of 100ns, and close it once it is no longer wanted, making sure the function cannot run
+//! after the data it uses is gone. [`Timer`] does all three around a Rust closure.
+//!
+//! With the `executor` feature, `TimerFuture` resolves in an async task after a delay.
+//!
+use alloc::boxed::Box;
+#[cfg(feature = "executor")]
+use alloc::rc::Rc;
+use core::{cell::RefCell, ffi::c_void, time::Duration};
+#[cfg(feature = "executor")]
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use r_efi::efi;
+
+#[cfg(feature = "executor")]
+use crate::event::WakeSlot;
+use crate::event::{self, Event, EventBuilder, SignalEvent};
+
+type Callback = RefCell<Box<dyn FnMut()>>;
+
+/// Closure run once after a delay, or periodically, from a timer event. The timer is cancelled when dropped.
+///
+/// The closure must not borrow anything, since the timer may be leaked with `mem::forget` and keep running it.
+///
+/// # Example
+/// ```no_run
+/// use core::time::Duration;
+/// use mu_rust_helpers::timer::Timer;
+/// use r_efi::efi;
+///
+/// fn blink(boot_services: &efi::BootServices, toggle_led: fn()) -> Result<(), efi::Status> {
+///     let timer = Timer::periodic(boot_services, Duration::from_millis(250), toggle_led)?;
+///     // The LED blinks until the timer is cancelled or dropped.
+///     timer.cancel()
+/// }
+/// ```
+pub struct Timer<'a> {
+    // Declared first so that the event is closed before the closure it calls is dropped.
+    event: SignalEvent<'a, event::Timer>,
+    _callback: Box<Callback>,
+    delay: Duration,
+    periodic: bool,
+}
+
+impl<'a> Timer<'a> {
+    /// Run `callback` at `efi::TPL_CALLBACK` once, `delay` from now.
+    ///
+    /// The delay is rounded up to the 100ns resolution of timer events.
+    pub fn one_shot(
+        boot_services: &'a efi::BootServices,
+        delay: Duration,
+        callback: impl FnMut() + 'static,
+    ) -> Result<Self, efi::Status> {
+        Self::new(boot_services, delay, false, efi::TPL_CALLBACK, Box::new(callback))
+    }
+
+    /// Run `callback` at `efi::TPL_CALLBACK` every `interval`, starting one interval from now.
+    ///
+    /// The interval is rounded up to the 100ns resolution of timer events.
+    pub fn periodic(
+        boot_services: &'a efi::BootServices,
+        interval: Duration,
+        callback: impl FnMut() + 'static,
+    ) -> Result<Self, efi::Status> {
+        Self::new(boot_services, interval, true, efi::TPL_CALLBACK, Box::new(callback))
+    }
+
+    /// Run `callback` at `tpl` once `delay` from now, or every `delay` if `periodic`.
+    pub(crate) fn new(
+        boot_services: &'a efi::BootServices,
+        delay: Duration,
+        periodic: bool,
+        tpl: efi::Tpl,
+        callback: Box<dyn FnMut()>,
+    ) -> Result<Self, efi::Status> {
+        extern "efiapi" fn expired(_event: efi::Event, context: *mut c_void) {
+            // SAFETY: the context is the boxed callback owned by the `Timer`, which closes this event before dropping
+            // it.
+            let callback = unsafe { &*(context as *const Callback) };
+            // Skip the expiration if the callback is still running from the previous one.
+            if let Ok(mut callback) = callback.try_borrow_mut() {
+                callback();
+            }
+        }
+
+        let callback: Box<Callback> = Box::new(RefCell::new(callback));
+        let context = &*callback as *const Callback as *mut c_void;
+        let event =
+            EventBuilder::new().timer().notify_signal().tpl(tpl).callback(expired, context).create(boot_services)?;
+        let timer = Self { event, _callback: callback, delay, periodic };
+        timer.restart()?;
+        Ok(timer)
+    }
+
+    /// Cancel the timer, so the callback is not run until the timer is restarted.
+    pub fn cancel(&self) -> Result<(), efi::Status> {
+        self.event.cancel_timer()
+    }
+
+    /// Arm the timer again with its delay or interval, counted from now.
+    pub fn restart(&self) -> Result<(), efi::Status> {
+        self.event.set_timer(self.delay, self.periodic)
+    }
+
+    /// Return true if the timer runs its callback periodically.
+    pub fn is_periodic(&self) -> bool {
+        self.periodic
+    }
+
+    /// Return the timer event.
+    pub fn event(&self) -> &Event<'a> {
+        &self.event
+    }
+}
+
+/// Future resolving after a delay.
+///
+/// The timer notification runs at `efi::TPL_CALLBACK` and wakes the waiting task. Tasks must poll the future below
+/// `TPL_CALLBACK`, which it raises the TPL to while it registers the waker.
+#[cfg(feature = "executor")]
+pub struct TimerFuture<'a> {
+    boot_services: &'a efi::BootServices,
+    _timer: Timer<'a>,
+    slot: Rc<WakeSlot>,
+}
+
+#[cfg(feature = "executor")]
+impl<'a> TimerFuture<'a> {
+    /// Resolve `delay` from now, rounded up to the 100ns resolution of timer events.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use core::time::Duration;
+    /// use mu_rust_helpers::timer::TimerFuture;
+    /// use r_efi::efi;
+    ///
+    /// async fn blink(boot_services: &efi::BootServices, toggle_led: fn()) -> Result<(), efi::Status> {
+    ///     loop {
+    ///         toggle_led();
+    ///         TimerFuture::after(boot_services, Duration::from_millis(500))?.await;
+    ///     }
+    /// }
+    /// ```
+    pub fn after(boot_services: &'a efi::BootServices, delay: Duration) -> Result<Self, efi::Status> {
+        let slot = Rc::new(WakeSlot::default());
+        let expired = slot.clone();
+        let timer = Timer::one_shot(boot_services, delay, move || expired.wake())?;
+        Ok(Self { boot_services, _timer: timer, slot })
+    }
+}
+
+#[cfg(feature = "executor")]
+impl Future for TimerFuture<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        self.slot.poll_signaled(self.boot_services, cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use alloc::{rc::Rc, vec::Vec};
+    use core::cell::Cell;
+
+    use crate::test_support::mock_efi_boot_services;
+
+    std::thread_local! {
+        static NOTIFY: Cell<Option<(efi::EventNotify, usize)>> = const { Cell::new(None) };
+        static TIMERS: RefCell<Vec<(efi::TimerDelay, u64)>> = const { RefCell::new(Vec::new()) };
+        static CLOSED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    extern "efiapi" fn create_event(
+        event_type: u32,
+        tpl: efi::Tpl,
+        notify: Option<efi::EventNotify>,
+        context: *mut c_void,
+        event: *mut efi::Event,
+    ) -> efi::Status {
+        assert_eq!((event_type, tpl), (efi::EVT_TIMER | efi::EVT_NOTIFY_SIGNAL, efi::TPL_CALLBACK));
+        NOTIFY.with(|cell| cell.set(Some((notify.unwrap(), context as usize))));
+        unsafe { *event = 0x7e as efi::Event };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn set_timer(event: efi::Event, delay: efi::TimerDelay, period: u64) -> efi::Status {
+        assert_eq!(event as usize, 0x7e);
+        TIMERS.with(|timers| timers.borrow_mut().push((delay, period)));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn close_event(event: efi::Event) -> efi::Status {
+        assert_eq!(event as usize, 0x7e);
+        CLOSED.with(|closed| closed.set(closed.get() + 1));
+        efi::Status::SUCCESS
+    }
+
+    fn expire() {
+        let (notify, context) = NOTIFY.with(|cell| cell.get()).unwrap();
+        notify(0x7e as efi::Event, context as *mut c_void);
+    }
+
+    #[test]
+    fn test_one_shot() {
+        let boot_services = efi::BootServices { create_event, set_timer, close_event, ..mock_efi_boot_services() };
+        let count = Rc::new(Cell::new(0));
+        let counter = count.clone();
+        let timer =
+            Timer::one_shot(&boot_services, Duration::from_nanos(1_550), move || counter.set(counter.get() + 1))
+                .unwrap();
+        assert!(!timer.is_periodic());
+        expire();
+        timer.restart().unwrap();
+        expire();
+        timer.cancel().unwrap();
+        assert_eq!(count.get(), 2);
+        assert_eq!(
+            TIMERS.with(|timers| timers.take()),
+            [(efi::TIMER_RELATIVE, 16), (efi::TIMER_RELATIVE, 16), (efi::TIMER_CANCEL, 0)]
+        );
+
+        drop(timer);
+        assert_eq!(CLOSED.with(|closed| closed.get()), 1);
+    }
+
+    #[test]
+    fn test_periodic() {
+        let boot_services = efi::BootServices { create_event, set_timer, close_event, ..mock_efi_boot_services() };
+        let timer = Timer::periodic(&boot_services, Duration::ZERO, || ()).unwrap();
+        assert!(timer.is_periodic());
+        assert_eq!(timer.event().as_raw() as usize, 0x7e);
+        // A zero interval would cancel the timer.
+        assert_eq!(TIMERS.with(|timers| timers.take()), [(efi::TIMER_PERIODIC, 1)]);
+    }
+
+    #[cfg(feature = "executor")]
+    #[test]
+    fn test_timer_future() {
+        use executor::{Priority, Scheduler};
+
+        std::thread_local! {
+            static TPL: Cell<efi::Tpl> = const { Cell::new(efi::TPL_APPLICATION) };
+        }
+        extern "efiapi" fn raise_tpl(tpl: efi::Tpl) -> efi::Tpl {
+            TPL.with(|current| current.replace(tpl))
+        }
+        extern "efiapi" fn restore_tpl(tpl: efi::Tpl) {
+            TPL.with(|current| current.set(tpl))
+        }
+
+        let boot_services = efi::BootServices {
+            create_event,
+            set_timer,
+            close_event,
+            raise_tpl,
+            restore_tpl,
+            ..mock_efi_boot_services()
+        };
+        let done = Cell::new(false);
+        let mut scheduler = Scheduler::new();
+        let timer = TimerFuture::after(&boot_services, Duration::from_micros(5)).unwrap();
+        scheduler.spawn(Priority::Normal, async {
+            timer.await;
+            done.set(true);
+        });
+        assert_eq!(TIMERS.with(|timers| timers.take()), [(efi::TIMER_RELATIVE, 50)]);
+
+        assert!(scheduler.run_once());
+        // The task is not polled again until the timer fires.
+        assert!(!scheduler.run_once());
+        expire();
+        assert!(scheduler.run_once());
+        assert!(done.get() && scheduler.is_empty());
+        assert_eq!(TPL.with(|tpl| tpl.get()), efi::TPL_APPLICATION);
+    }
+}
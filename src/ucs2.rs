@@ -0,0 +1,301 @@
+//! Allocation-free UCS-2 string conversion.
+//!
+//! UEFI strings are null-terminated UCS-2. The helpers in this module convert to and from Rust strings without
+//! allocating: [`encode_into`] writes into a caller-provided buffer, [`Ucs2Buf`] carries its own fixed-size buffer,
+//! and [`decode`] produces the characters lazily. They remain usable where no allocator is available, e.g. in PEI
+//! before permanent memory is installed.
+//!
+//! Strings returned by UEFI services in pool memory, such as driver names or the exit data of images, are owned by
+//! [`PoolUcs2Str`], which frees them when dropped.
+//!
+use core::{
+    char,
+    fmt::{self, Write},
+    ptr::NonNull,
+    slice,
+};
+
+use r_efi::efi;
+
+/// Convert `string` into a null-terminated UCS-2 string stored in `buffer`, returning the used part of the buffer,
+/// null terminator included.
+///
+/// Returns `efi::Status::INVALID_PARAMETER` if `string` contains a null character or characters outside of the Basic
+/// Multilingual Plane, and `efi::Status::BUFFER_TOO_SMALL` if `buffer` cannot hold the converted string.
+pub fn encode_into<'a>(string: &str, buffer: &'a mut [u16]) -> Result<&'a [u16], efi::Status> {
+    let mut len = 0;
+    for c in string.chars() {
+        let c = match u16::try_from(c as u32) {
+            Ok(0) | Err(_) => return Err(efi::Status::INVALID_PARAMETER),
+            Ok(c) => c,
+        };
+        *buffer.get_mut(len).ok_or(efi::Status::BUFFER_TOO_SMALL)? = c;
+        len += 1;
+    }
+    *buffer.get_mut(len).ok_or(efi::Status::BUFFER_TOO_SMALL)? = 0;
+    Ok(&buffer[..=len])
+}
+
+/// Return an iterator over the characters of the UCS-2 string `string`, up to the first null character.
+///
+/// Characters that are not valid UCS-2, such as unpaired surrogates, are replaced with `char::REPLACEMENT_CHARACTER`.
+pub fn decode(string: &[u16]) -> impl Iterator<Item = char> + '_ {
+    char::decode_utf16(string.iter().copied().take_while(|&c| c != 0)).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+}
+
+/// Return the null-terminated UCS-2 string at `string`, without its null terminator.
+///
+/// A null pointer is treated as an empty string.
+///
+/// # Safety
+/// `string` must be null or point to a null-terminated UCS-2 string that stays valid and unmodified for `'a`.
+pub unsafe fn from_ptr<'a>(string: *const u16) -> &'a [u16] {
+    if string.is_null() {
+        return &[];
+    }
+    let mut len = 0;
+    while *string.add(len) != 0 {
+        len += 1;
+    }
+    slice::from_raw_parts(string, len)
+}
+
+/// Null-terminated UCS-2 string stored in a buffer of `N` characters, null terminator included.
+///
+/// # Example
+/// ```
+/// use mu_rust_helpers::ucs2::Ucs2Buf;
+///
+/// let name = Ucs2Buf::<16>::new("BootOrder").unwrap();
+/// assert_eq!(name.len(), 9);
+/// assert_eq!(name.as_slice_with_nul().last(), Some(&0));
+/// assert_eq!(name.to_string(), "BootOrder");
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Ucs2Buf<const N: usize> {
+    buffer: [u16; N],
+    len: usize,
+}
+
+impl<const N: usize> Ucs2Buf<N> {
+    /// Convert `string`, see [`encode_into`] for the errors returned.
+    pub fn new(string: &str) -> Result<Self, efi::Status> {
+        let mut buffer = [0; N];
+        let len = encode_into(string, &mut buffer)?.len() - 1;
+        Ok(Self { buffer, len })
+    }
+
+    /// Return the number of characters, null terminator excluded.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Return true if the string has no characters.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Return the characters, null terminator excluded.
+    pub fn as_slice(&self) -> &[u16] {
+        &self.buffer[..self.len]
+    }
+
+    /// Return the characters, null terminator included.
+    pub fn as_slice_with_nul(&self) -> &[u16] {
+        &self.buffer[..=self.len]
+    }
+
+    /// Return a pointer to the null-terminated string, e.g. to pass it to a UEFI service.
+    pub fn as_ptr(&self) -> *const u16 {
+        self.buffer.as_ptr()
+    }
+}
+
+impl<const N: usize> fmt::Display for Ucs2Buf<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        decode(self.as_slice()).try_for_each(|c| f.write_char(c))
+    }
+}
+
+impl<const N: usize> fmt::Debug for Ucs2Buf<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_char('"')?;
+        decode(self.as_slice()).flat_map(char::escape_debug).try_for_each(|c| f.write_char(c))?;
+        f.write_char('"')
+    }
+}
+
+/// Null-terminated UCS-2 string allocated from pool memory by a UEFI service, freed when dropped.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::ucs2::PoolUcs2Str;
+/// use r_efi::efi;
+///
+/// fn print_exit_message(boot_services: &efi::BootServices, exit_data: *mut u16) {
+///     // SAFETY: StartImage returns exit data allocated from pool and starting with a null-terminated string.
+///     if let Some(message) = unsafe { PoolUcs2Str::from_raw(boot_services, exit_data) } {
+///         let message = message.to_string();
+///         // ...
+///     }
+/// }
+/// ```
+pub struct PoolUcs2Str<'a> {
+    boot_services: &'a efi::BootServices,
+    string: NonNull<u16>,
+    len: usize,
+}
+
+impl<'a> PoolUcs2Str<'a> {
+    /// Take ownership of the pool-allocated string at `string`, or return `None` if it is null.
+    ///
+    /// # Safety
+    /// `string` must be null or point to a null-terminated UCS-2 string at the start of a pool allocation, which is
+    /// not used nor freed by anything else.
+    pub unsafe fn from_raw(boot_services: &'a efi::BootServices, string: *mut u16) -> Option<Self> {
+        let string = NonNull::new(string)?;
+        let len = from_ptr(string.as_ptr()).len();
+        Some(Self { boot_services, string, len })
+    }
+
+    /// Return the number of characters, null terminator excluded.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Return true if the string has no characters.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Return the characters, null terminator excluded.
+    ///
+    /// Unlike the [`fmt::Display`] output, the characters are returned as is, including unpaired surrogates.
+    pub fn as_slice(&self) -> &[u16] {
+        // SAFETY: the string is owned and holds `len` characters before its null terminator.
+        unsafe { slice::from_raw_parts(self.string.as_ptr(), self.len) }
+    }
+
+    /// Return the characters, null terminator included.
+    pub fn as_slice_with_nul(&self) -> &[u16] {
+        // SAFETY: as for `as_slice`, the null terminator included.
+        unsafe { slice::from_raw_parts(self.string.as_ptr(), self.len + 1) }
+    }
+
+    /// Return a pointer to the null-terminated string, e.g. to pass it to a UEFI service.
+    pub fn as_ptr(&self) -> *const u16 {
+        self.string.as_ptr()
+    }
+
+    /// Give up ownership of the string, returning the pointer to be freed with FreePool.
+    pub fn into_raw(self) -> *mut u16 {
+        core::mem::ManuallyDrop::new(self).string.as_ptr()
+    }
+}
+
+impl PartialEq<str> for PoolUcs2Str<'_> {
+    fn eq(&self, other: &str) -> bool {
+        self.as_slice().iter().copied().eq(other.encode_utf16())
+    }
+}
+
+impl PartialEq<&str> for PoolUcs2Str<'_> {
+    fn eq(&self, other: &&str) -> bool {
+        *self == **other
+    }
+}
+
+impl fmt::Display for PoolUcs2Str<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        decode(self.as_slice()).try_for_each(|c| f.write_char(c))
+    }
+}
+
+impl fmt::Debug for PoolUcs2Str<'_> {
+    /// Unpaired surrogates are shown as `\u{...}` escapes, so that no character is lost.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_char('"')?;
+        for c in char::decode_utf16(self.as_slice().iter().copied()) {
+            match c {
+                Ok(c) => c.escape_debug().try_for_each(|c| f.write_char(c))?,
+                Err(error) => write!(f, "\\u{{{:x}}}", error.unpaired_surrogate())?,
+            }
+        }
+        f.write_char('"')
+    }
+}
+
+impl Drop for PoolUcs2Str<'_> {
+    fn drop(&mut self) {
+        (self.boot_services.free_pool)(self.string.as_ptr() as *mut _);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+
+    use crate::test_support::mock_efi_boot_services;
+
+    std::thread_local! {
+        static FREED: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+    }
+
+    extern "efiapi" fn free_pool(buffer: *mut core::ffi::c_void) -> efi::Status {
+        FREED.with(|freed| freed.borrow_mut().push(buffer as usize));
+        efi::Status::SUCCESS
+    }
+
+    #[test]
+    fn test_encode_into() {
+        let mut buffer = [0xffff; 8];
+        assert_eq!(encode_into("Boot", &mut buffer), Ok(&[0x42, 0x6f, 0x6f, 0x74, 0][..]));
+        assert_eq!(encode_into("", &mut buffer), Ok(&[0][..]));
+        assert_eq!(encode_into("BootOrder", &mut buffer), Err(efi::Status::BUFFER_TOO_SMALL));
+        assert_eq!(encode_into("Boot\0", &mut buffer), Err(efi::Status::INVALID_PARAMETER));
+        assert_eq!(encode_into("\u{1f600}", &mut buffer), Err(efi::Status::INVALID_PARAMETER));
+        assert_eq!(encode_into("", &mut []), Err(efi::Status::BUFFER_TOO_SMALL));
+    }
+
+    #[test]
+    fn test_decode() {
+        let string = [0x54, 0xe9, 0xd800, 0x74, 0, 0x78];
+        assert!(decode(&string).eq("T\u{e9}\u{fffd}t".chars()));
+        assert!(unsafe { from_ptr(string.as_ptr()) }.eq(&string[..4]));
+        assert!(unsafe { from_ptr(core::ptr::null()) }.is_empty());
+    }
+
+    #[test]
+    fn test_ucs2_buf() {
+        let string = Ucs2Buf::<6>::new("Timer").unwrap();
+        assert_eq!((string.len(), string.is_empty()), (5, false));
+        assert_eq!(string.as_slice_with_nul()[5], 0);
+        assert_eq!(format!("{string:?}"), "\"Timer\"");
+        assert_eq!(Ucs2Buf::<5>::new("Timer"), Err(efi::Status::BUFFER_TOO_SMALL));
+        assert!(Ucs2Buf::<1>::new("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_pool_ucs2_str() {
+        let boot_services = efi::BootServices { free_pool, ..mock_efi_boot_services() };
+        // The mock FreePool only records the pointer, so the string can live on the stack.
+        let mut buffer = [0x4e, 0x49, 0x43, 0xdc00, 0, 0x48, 0x69, 0];
+        let address = buffer.as_mut_ptr() as usize;
+        let string = unsafe { PoolUcs2Str::from_raw(&boot_services, buffer.as_mut_ptr()) }.unwrap();
+        assert_eq!((string.len(), string.is_empty()), (4, false));
+        assert_eq!(string.as_slice_with_nul(), &buffer[..5]);
+        assert_eq!(string.to_string(), "NIC\u{fffd}");
+        assert!(string != "NIC");
+        assert_eq!(format!("{string:?}"), "\"NIC\\u{dc00}\"");
+        drop(string);
+        assert_eq!(FREED.with(|freed| freed.take()), [address]);
+
+        let string = unsafe { PoolUcs2Str::from_raw(&boot_services, buffer[5..].as_mut_ptr()) }.unwrap();
+        assert!(string == "Hi");
+        assert_eq!(string.into_raw() as usize, address + 10);
+        assert!(FREED.with(|freed| freed.take()).is_empty());
+        assert!(unsafe { PoolUcs2Str::from_raw(&boot_services, core::ptr::null_mut()) }.is_none());
+    }
+}
@@ -0,0 +1,316 @@
+//! Checked byte and page counts.
+//!
+//! The raw memory services mix `usize` and `u64` sizes, counted in bytes or in UEFI pages, and a conversion forgotten
+//! or done with the wrong rounding silently allocates the wrong amount of memory. [`ByteCount`] and [`PageCount`]
+//! keep the unit in the type and make every conversion explicit and overflow-checked. [`PhysicalAddress`] and
+//! [`VirtualAddress`] do the same for addresses, which only combine with byte counts.
+//!
+use core::fmt;
+
+/// Size of a UEFI page in bytes.
+pub const UEFI_PAGE_SIZE: u64 = 0x1000;
+
+const UEFI_PAGE_SHIFT: u32 = UEFI_PAGE_SIZE.trailing_zeros();
+
+/// A number of bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ByteCount(u64);
+
+impl ByteCount {
+    /// Create a count of `bytes`.
+    pub const fn new(bytes: u64) -> Self {
+        Self(bytes)
+    }
+
+    /// Create a count of `bytes`, e.g. from the size of a buffer.
+    pub const fn from_usize(bytes: usize) -> Self {
+        Self(bytes as u64)
+    }
+
+    /// Return the number of bytes.
+    pub const fn get(self) -> u64 {
+        self.0
+    }
+
+    /// Return the number of bytes as a `usize`, if it fits.
+    pub fn to_usize(self) -> Option<usize> {
+        usize::try_from(self.0).ok()
+    }
+
+    /// Return the number of pages needed to hold this many bytes.
+    pub const fn to_pages_ceil(self) -> PageCount {
+        PageCount(self.0.div_ceil(UEFI_PAGE_SIZE))
+    }
+
+    /// Return the number of pages holding exactly this many bytes, if it is a multiple of the page size.
+    pub const fn to_pages_exact(self) -> Option<PageCount> {
+        if self.0 % UEFI_PAGE_SIZE == 0 {
+            Some(PageCount(self.0 >> UEFI_PAGE_SHIFT))
+        } else {
+            None
+        }
+    }
+
+    /// Round up to a multiple of `alignment`, which must be a power of two. Returns `None` on overflow or if
+    /// `alignment` is not a power of two.
+    pub const fn align_up(self, alignment: u64) -> Option<Self> {
+        if !alignment.is_power_of_two() {
+            return None;
+        }
+        match self.0.checked_add(alignment - 1) {
+            Some(bytes) => Some(Self(bytes & !(alignment - 1))),
+            None => None,
+        }
+    }
+
+    /// Round down to a multiple of `alignment`, which must be a power of two. Returns `None` if `alignment` is not a
+    /// power of two.
+    pub const fn align_down(self, alignment: u64) -> Option<Self> {
+        if !alignment.is_power_of_two() {
+            return None;
+        }
+        Some(Self(self.0 & !(alignment - 1)))
+    }
+
+    /// Return true if the count is a multiple of `alignment`, which must be a power of two.
+    pub const fn is_aligned(self, alignment: u64) -> bool {
+        alignment.is_power_of_two() && self.0 & (alignment - 1) == 0
+    }
+
+    /// Add `other`, returning `None` on overflow.
+    pub const fn checked_add(self, other: Self) -> Option<Self> {
+        match self.0.checked_add(other.0) {
+            Some(bytes) => Some(Self(bytes)),
+            None => None,
+        }
+    }
+
+    /// Subtract `other`, returning `None` on underflow.
+    pub const fn checked_sub(self, other: Self) -> Option<Self> {
+        match self.0.checked_sub(other.0) {
+            Some(bytes) => Some(Self(bytes)),
+            None => None,
+        }
+    }
+}
+
+impl From<usize> for ByteCount {
+    fn from(bytes: usize) -> Self {
+        Self::from_usize(bytes)
+    }
+}
+
+impl fmt::Display for ByteCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x} bytes", self.0)
+    }
+}
+
+/// A number of UEFI pages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PageCount(u64);
+
+impl PageCount {
+    /// Create a count of `pages`.
+    pub const fn new(pages: u64) -> Self {
+        Self(pages)
+    }
+
+    /// Create a count of `pages`, e.g. as passed to AllocatePages.
+    pub const fn from_usize(pages: usize) -> Self {
+        Self(pages as u64)
+    }
+
+    /// Return the number of pages.
+    pub const fn get(self) -> u64 {
+        self.0
+    }
+
+    /// Return the number of pages as a `usize`, e.g. to pass it to AllocatePages, if it fits.
+    pub fn to_usize(self) -> Option<usize> {
+        usize::try_from(self.0).ok()
+    }
+
+    /// Return the number of bytes in this many pages, or `None` on overflow.
+    pub const fn to_bytes(self) -> Option<ByteCount> {
+        match self.0.checked_mul(UEFI_PAGE_SIZE) {
+            Some(bytes) => Some(ByteCount(bytes)),
+            None => None,
+        }
+    }
+
+    /// Add `other`, returning `None` on overflow.
+    pub const fn checked_add(self, other: Self) -> Option<Self> {
+        match self.0.checked_add(other.0) {
+            Some(pages) => Some(Self(pages)),
+            None => None,
+        }
+    }
+
+    /// Subtract `other`, returning `None` on underflow.
+    pub const fn checked_sub(self, other: Self) -> Option<Self> {
+        match self.0.checked_sub(other.0) {
+            Some(pages) => Some(Self(pages)),
+            None => None,
+        }
+    }
+}
+
+impl fmt::Display for PageCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x} pages", self.0)
+    }
+}
+
+macro_rules! address_type {
+    ($(#[$doc:meta])* $name:ident) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(u64);
+
+        impl $name {
+            /// Create an address.
+            pub const fn new(address: u64) -> Self {
+                Self(address)
+            }
+
+            /// Return the address.
+            pub const fn get(self) -> u64 {
+                self.0
+            }
+
+            /// Return the address as a pointer, if it fits in a `usize`.
+            pub fn as_ptr<T>(self) -> Option<*mut T> {
+                usize::try_from(self.0).ok().map(|address| address as *mut T)
+            }
+
+            /// Add `bytes`, returning `None` on overflow.
+            pub const fn checked_add(self, bytes: ByteCount) -> Option<Self> {
+                match self.0.checked_add(bytes.0) {
+                    Some(address) => Some(Self(address)),
+                    None => None,
+                }
+            }
+
+            /// Subtract `bytes`, returning `None` on underflow.
+            pub const fn checked_sub(self, bytes: ByteCount) -> Option<Self> {
+                match self.0.checked_sub(bytes.0) {
+                    Some(address) => Some(Self(address)),
+                    None => None,
+                }
+            }
+
+            /// Return the number of bytes from `base` to this address, or `None` if `base` is above it.
+            pub const fn offset_from(self, base: Self) -> Option<ByteCount> {
+                match self.0.checked_sub(base.0) {
+                    Some(bytes) => Some(ByteCount(bytes)),
+                    None => None,
+                }
+            }
+
+            /// Round up to a multiple of `alignment`, which must be a power of two. Returns `None` on overflow or if
+            /// `alignment` is not a power of two.
+            pub const fn align_up(self, alignment: u64) -> Option<Self> {
+                match ByteCount(self.0).align_up(alignment) {
+                    Some(address) => Some(Self(address.0)),
+                    None => None,
+                }
+            }
+
+            /// Round down to a multiple of `alignment`, which must be a power of two. Returns `None` if `alignment` is
+            /// not a power of two.
+            pub const fn align_down(self, alignment: u64) -> Option<Self> {
+                match ByteCount(self.0).align_down(alignment) {
+                    Some(address) => Some(Self(address.0)),
+                    None => None,
+                }
+            }
+
+            /// Return true if the address is a multiple of `alignment`, which must be a power of two.
+            pub const fn is_aligned(self, alignment: u64) -> bool {
+                ByteCount(self.0).is_aligned(alignment)
+            }
+
+            /// Return true if the address is a multiple of [`UEFI_PAGE_SIZE`].
+            pub const fn is_page_aligned(self) -> bool {
+                self.is_aligned(UEFI_PAGE_SIZE)
+            }
+        }
+
+        impl From<$name> for u64 {
+            fn from(address: $name) -> Self {
+                address.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{:#x}", self.0)
+            }
+        }
+    };
+}
+
+address_type!(
+    /// A physical address, as returned by AllocatePages and found in the memory map.
+    PhysicalAddress
+);
+
+address_type!(
+    /// A virtual address, as set by SetVirtualAddressMap.
+    VirtualAddress
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_count() {
+        assert_eq!(ByteCount::new(0).to_pages_ceil(), PageCount::new(0));
+        assert_eq!(ByteCount::new(1).to_pages_ceil(), PageCount::new(1));
+        assert_eq!(ByteCount::new(0x2000).to_pages_ceil(), PageCount::new(2));
+        assert_eq!(ByteCount::new(u64::MAX).to_pages_ceil(), PageCount::new(1 << 52));
+        assert_eq!(ByteCount::new(0x3000).to_pages_exact(), Some(PageCount::new(3)));
+        assert_eq!(ByteCount::new(0x3001).to_pages_exact(), None);
+
+        assert_eq!(ByteCount::new(0x1001).align_up(0x1000), Some(ByteCount::new(0x2000)));
+        assert_eq!(ByteCount::new(0x1001).align_down(0x1000), Some(ByteCount::new(0x1000)));
+        assert_eq!(ByteCount::new(u64::MAX).align_up(0x1000), None);
+        assert_eq!(ByteCount::new(0x1000).align_up(0x1800), None);
+        assert!(ByteCount::new(0x4000).is_aligned(0x4000));
+        assert!(!ByteCount::new(0x4000).is_aligned(0x8000));
+
+        assert_eq!(ByteCount::new(u64::MAX).checked_add(ByteCount::new(1)), None);
+        assert_eq!(ByteCount::new(1).checked_sub(ByteCount::new(2)), None);
+        assert_eq!(ByteCount::from(0x10usize).to_usize(), Some(0x10));
+        assert_eq!(ByteCount::new(0x10).to_string(), "0x10 bytes");
+    }
+
+    #[test]
+    fn test_page_count() {
+        assert_eq!(PageCount::new(3).to_bytes(), Some(ByteCount::new(0x3000)));
+        assert_eq!(PageCount::new(1 << 52).to_bytes(), None);
+        assert_eq!(PageCount::new(u64::MAX).checked_add(PageCount::new(1)), None);
+        assert_eq!(PageCount::new(3).checked_sub(PageCount::new(1)), Some(PageCount::new(2)));
+        assert_eq!(PageCount::from_usize(4).to_usize(), Some(4));
+        assert_eq!(PageCount::new(2).to_string(), "0x2 pages");
+    }
+
+    #[test]
+    fn test_addresses() {
+        let address = PhysicalAddress::new(0x1_0000_1234);
+        assert_eq!(address.align_down(UEFI_PAGE_SIZE), Some(PhysicalAddress::new(0x1_0000_1000)));
+        assert_eq!(address.align_up(UEFI_PAGE_SIZE), Some(PhysicalAddress::new(0x1_0000_2000)));
+        assert!(!address.is_page_aligned());
+        assert!(PhysicalAddress::new(0x2000).is_page_aligned());
+        assert_eq!(address.checked_add(ByteCount::new(0x10)), Some(PhysicalAddress::new(0x1_0000_1244)));
+        assert_eq!(PhysicalAddress::new(u64::MAX).checked_add(ByteCount::new(1)), None);
+        assert_eq!(PhysicalAddress::new(0).checked_sub(ByteCount::new(1)), None);
+        assert_eq!(address.offset_from(PhysicalAddress::new(0x1_0000_0000)), Some(ByteCount::new(0x1234)));
+        assert_eq!(PhysicalAddress::new(0).offset_from(address), None);
+        assert_eq!(VirtualAddress::new(0x8000).as_ptr::<u8>(), Some(0x8000 as *mut u8));
+        assert_eq!(u64::from(address), 0x1_0000_1234);
+        assert_eq!(address.to_string(), "0x100001234");
+    }
+}
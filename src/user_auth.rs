@@ -0,0 +1,1022 @@
+//! User identification: credential providers and the user manager.
+//!
+//! Pre-boot authentication is split between credential providers, which implement `EFI_USER_CREDENTIAL2_PROTOCOL`
+//! for one class of credential such as a password or a smart card, and the user manager,
+//! `EFI_USER_MANAGER_PROTOCOL`, which keeps the user profiles and runs the identification. [`InstalledCredential`]
+//! implements a credential provider from a [`CredentialProvider`]. [`UserManager`] and [`UserCredential`] drive the
+//! enrollment and identification flows from the other side.
+//!
+//! Tiles and per-user credential information are not wrapped.
+//!
+use alloc::{boxed::Box, vec::Vec};
+use core::{ffi::c_void, marker::PhantomData, mem, mem::ManuallyDrop, ptr, slice};
+
+use r_efi::efi;
+
+use crate::{buffer::get_with_growing_buffer, protocol::Protocol};
+
+/// `EFI_USER_CREDENTIAL2_PROTOCOL_GUID`.
+pub const CREDENTIAL2_PROTOCOL_GUID: efi::Guid =
+    efi::Guid::from_fields(0xe98adb03, 0xb8b9, 0x4af8, 0xba, 0x20, &[0x26, 0xe9, 0x11, 0x4c, 0xbc, 0xe5]);
+/// `EFI_USER_MANAGER_PROTOCOL_GUID`.
+pub const MANAGER_PROTOCOL_GUID: efi::Guid =
+    efi::Guid::from_fields(0x6fd5b00c, 0xd426, 0x4283, 0x98, 0x87, &[0x6c, 0xf5, 0xcf, 0x1c, 0xb1, 0xfe]);
+
+/// `EFI_USER_CREDENTIAL_CLASS_UNKNOWN`.
+pub const CLASS_UNKNOWN_GUID: efi::Guid =
+    efi::Guid::from_fields(0x5cf32e68, 0x7660, 0x449b, 0x80, 0xe6, &[0x7e, 0xa3, 0x6e, 0x03, 0xf6, 0xa8]);
+/// `EFI_USER_CREDENTIAL_CLASS_PASSWORD`.
+pub const CLASS_PASSWORD_GUID: efi::Guid =
+    efi::Guid::from_fields(0xf8e5058c, 0xccb6, 0x4714, 0xb2, 0x20, &[0x3f, 0x7e, 0x3a, 0x64, 0x0b, 0xd1]);
+/// `EFI_USER_CREDENTIAL_CLASS_SMART_CARD`.
+pub const CLASS_SMART_CARD_GUID: efi::Guid =
+    efi::Guid::from_fields(0x5f03ba33, 0x8c6b, 0x4c24, 0xaa, 0x2e, &[0x14, 0xa2, 0x65, 0x7b, 0xd4, 0x54]);
+/// `EFI_USER_CREDENTIAL_CLASS_FINGERPRINT`.
+pub const CLASS_FINGERPRINT_GUID: efi::Guid =
+    efi::Guid::from_fields(0x32cba21f, 0xf308, 0x4cbc, 0x9a, 0xb5, &[0xf5, 0xa3, 0x69, 0x9f, 0x04, 0x4a]);
+/// `EFI_USER_CREDENTIAL_CLASS_HANDPRINT`.
+pub const CLASS_HANDPRINT_GUID: efi::Guid =
+    efi::Guid::from_fields(0x5917ef16, 0xf723, 0x4bb9, 0xa6, 0x4b, &[0xd8, 0xc5, 0x32, 0xf4, 0xd8, 0xb5]);
+/// `EFI_USER_CREDENTIAL_CLASS_SECURE_CARD`.
+pub const CLASS_SECURE_CARD_GUID: efi::Guid =
+    efi::Guid::from_fields(0x8a6b4a83, 0x42fe, 0x45d2, 0xa2, 0xef, &[0x46, 0xf0, 0x6c, 0x7d, 0x98, 0x52]);
+
+/// `EFI_CREDENTIAL_LOGON_FLAG_AUTO`: the user is identified without interaction, e.g. by an inserted smart card.
+pub const LOGON_FLAG_AUTO: u32 = 0x01;
+/// `EFI_CREDENTIAL_LOGON_FLAG_DEFAULT`: the provider should be selected by default.
+pub const LOGON_FLAG_DEFAULT: u32 = 0x02;
+
+/// `EFI_CREDENTIAL_CAPABILITIES_ENROLL`: the provider can enroll users.
+pub const CAPABILITIES_ENROLL: u64 = 0x01;
+
+/// `EFI_USER_INFO_NAME_RECORD`: user name, as a null-terminated UCS-2 string.
+pub const INFO_NAME_RECORD: u8 = 0x01;
+/// `EFI_USER_INFO_CREATE_DATE_RECORD`.
+pub const INFO_CREATE_DATE_RECORD: u8 = 0x02;
+/// `EFI_USER_INFO_USAGE_DATE_RECORD`.
+pub const INFO_USAGE_DATE_RECORD: u8 = 0x03;
+/// `EFI_USER_INFO_USAGE_COUNT_RECORD`.
+pub const INFO_USAGE_COUNT_RECORD: u8 = 0x04;
+/// `EFI_USER_INFO_IDENTIFIER_RECORD`: [`UserIdentifier`] of the user.
+pub const INFO_IDENTIFIER_RECORD: u8 = 0x05;
+/// `EFI_USER_INFO_CREDENTIAL_TYPE_RECORD`: credential class GUID.
+pub const INFO_CREDENTIAL_TYPE_RECORD: u8 = 0x06;
+/// `EFI_USER_INFO_CREDENTIAL_TYPE_NAME_RECORD`: credential class name, as a null-terminated UCS-2 string.
+pub const INFO_CREDENTIAL_TYPE_NAME_RECORD: u8 = 0x07;
+/// `EFI_USER_INFO_CREDENTIAL_PROVIDER_RECORD`: credential provider identifier.
+pub const INFO_CREDENTIAL_PROVIDER_RECORD: u8 = 0x08;
+/// `EFI_USER_INFO_CREDENTIAL_PROVIDER_NAME_RECORD`: provider name, as a null-terminated UCS-2 string.
+pub const INFO_CREDENTIAL_PROVIDER_NAME_RECORD: u8 = 0x09;
+
+/// `EFI_USER_INFO_STORAGE_PLATFORM_NV`: the record is kept in platform non-volatile storage.
+pub const INFO_STORAGE_PLATFORM_NV: u16 = 0x0002;
+/// `EFI_USER_INFO_PUBLIC`: the record can be read by any user.
+pub const INFO_PUBLIC: u16 = 0x0010;
+/// `EFI_USER_INFO_PRIVATE`: the record can only be read by the user it belongs to.
+pub const INFO_PRIVATE: u16 = 0x0020;
+/// `EFI_USER_INFO_PROTECTED`: the record can only be read by users with the right to manage users.
+pub const INFO_PROTECTED: u16 = 0x0030;
+/// `EFI_USER_INFO_EXCLUSIVE`: only one record of this type can exist.
+pub const INFO_EXCLUSIVE: u16 = 0x0080;
+
+/// `EFI_USER_PROFILE_HANDLE`.
+pub type UserProfile = *mut c_void;
+/// `EFI_USER_INFO_HANDLE`.
+pub type UserInfoHandle = *mut c_void;
+/// `EFI_USER_INFO_IDENTIFIER`: unique identifier of a user.
+pub type UserIdentifier = [u8; 16];
+
+/// `EFI_USER_INFO`, the header of a user information record, followed by its data.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UserInfo {
+    /// Identifier of the credential provider owning the record, or zero for records of the user manager.
+    pub credential: efi::Guid,
+    /// Record type, e.g. [`INFO_NAME_RECORD`].
+    pub info_type: u8,
+    /// Reserved, must be zero.
+    pub reserved1: u8,
+    /// Storage and access attributes, e.g. [`INFO_PUBLIC`].
+    pub info_attribs: u16,
+    /// Size in bytes of the record, header included.
+    pub info_size: u32,
+}
+
+/// `EFI_CREDENTIAL2_ENROLL`, also used for `EFI_CREDENTIAL2_DELETE`: enroll or remove a user profile.
+pub type CredentialEnroll = extern "efiapi" fn(*mut Credential2Protocol, UserProfile) -> efi::Status;
+/// `EFI_CREDENTIAL2_FORM`: return the HII handle, form set and form of the provider's user interface.
+pub type CredentialForm =
+    extern "efiapi" fn(*mut Credential2Protocol, *mut efi::Handle, *mut efi::Guid, *mut u16) -> efi::Status;
+/// `EFI_CREDENTIAL2_TILE`: return the image shown for the provider, scaled to at most the given size.
+pub type CredentialTile =
+    extern "efiapi" fn(*mut Credential2Protocol, *mut usize, *mut usize, *mut efi::Handle, *mut u16) -> efi::Status;
+/// `EFI_CREDENTIAL2_TITLE`: return the HII string naming the provider.
+pub type CredentialTitle = extern "efiapi" fn(*mut Credential2Protocol, *mut efi::Handle, *mut u16) -> efi::Status;
+/// `EFI_CREDENTIAL2_USER`: return the identifier of the user the credential was presented for.
+pub type CredentialUser = extern "efiapi" fn(*mut Credential2Protocol, UserProfile, *mut UserIdentifier) -> efi::Status;
+/// `EFI_CREDENTIAL2_SELECT`, also used for `EFI_CREDENTIAL2_DEFAULT`: select the provider, returning logon flags.
+pub type CredentialSelect = extern "efiapi" fn(*mut Credential2Protocol, *mut u32) -> efi::Status;
+/// `EFI_CREDENTIAL2_DESELECT`: deselect the provider.
+pub type CredentialDeselect = extern "efiapi" fn(*mut Credential2Protocol) -> efi::Status;
+/// `EFI_CREDENTIAL2_GET_INFO`: copy a user information record of the provider.
+pub type CredentialGetInfo =
+    extern "efiapi" fn(*mut Credential2Protocol, UserInfoHandle, *mut UserInfo, *mut usize) -> efi::Status;
+/// `EFI_CREDENTIAL2_GET_NEXT_INFO`: enumerate the user information records of the provider.
+pub type CredentialGetNextInfo = extern "efiapi" fn(*mut Credential2Protocol, *mut UserInfoHandle) -> efi::Status;
+
+/// `EFI_USER_CREDENTIAL2_PROTOCOL`.
+#[repr(C)]
+pub struct Credential2Protocol {
+    /// Identifier of the provider.
+    pub identifier: efi::Guid,
+    /// Credential class GUID, e.g. [`CLASS_PASSWORD_GUID`].
+    pub r#type: efi::Guid,
+    /// Enroll a user.
+    pub enroll: CredentialEnroll,
+    /// Return the user interface form.
+    pub form: CredentialForm,
+    /// Return the tile image.
+    pub tile: CredentialTile,
+    /// Return the title string.
+    pub title: CredentialTitle,
+    /// Return the identified user.
+    pub user: CredentialUser,
+    /// Select the provider.
+    pub select: CredentialSelect,
+    /// Deselect the provider.
+    pub deselect: CredentialDeselect,
+    /// Return the logon flags if the provider were the default.
+    pub default: CredentialSelect,
+    /// Copy a user information record.
+    pub get_info: CredentialGetInfo,
+    /// Enumerate the user information records.
+    pub get_next_info: CredentialGetNextInfo,
+    /// Capabilities of the provider, e.g. [`CAPABILITIES_ENROLL`].
+    pub capabilities: u64,
+    /// Remove the enrollment of a user.
+    pub delete: CredentialEnroll,
+}
+
+// SAFETY: `CREDENTIAL2_PROTOCOL_GUID` identifies the user credential protocol, whose interface is
+// `Credential2Protocol`.
+unsafe impl Protocol for Credential2Protocol {
+    const GUID: efi::Guid = CREDENTIAL2_PROTOCOL_GUID;
+}
+
+/// `EFI_USER_PROFILE_CREATE`, also used for `EFI_USER_PROFILE_GET_NEXT`, `EFI_USER_PROFILE_CURRENT` and
+/// `EFI_USER_PROFILE_IDENTIFY`: return a user profile.
+pub type ProfileCreate = extern "efiapi" fn(*mut ManagerProtocol, *mut UserProfile) -> efi::Status;
+/// `EFI_USER_PROFILE_DELETE`: delete a user profile.
+pub type ProfileDelete = extern "efiapi" fn(*mut ManagerProtocol, UserProfile) -> efi::Status;
+/// `EFI_USER_PROFILE_FIND`: find the next profile with a record matching the given one.
+pub type ProfileFind = extern "efiapi" fn(
+    *mut ManagerProtocol,
+    *mut UserProfile,
+    *mut UserInfoHandle,
+    *const UserInfo,
+    usize,
+) -> efi::Status;
+/// `EFI_USER_PROFILE_NOTIFY`: notify the user manager that a credential provider was installed.
+pub type ProfileNotify = extern "efiapi" fn(*mut ManagerProtocol, efi::Handle) -> efi::Status;
+/// `EFI_USER_PROFILE_GET_INFO`: copy a user information record of a profile.
+pub type ProfileGetInfo =
+    extern "efiapi" fn(*mut ManagerProtocol, UserProfile, UserInfoHandle, *mut UserInfo, *mut usize) -> efi::Status;
+/// `EFI_USER_PROFILE_SET_INFO`: add or replace a user information record of a profile.
+pub type ProfileSetInfo =
+    extern "efiapi" fn(*mut ManagerProtocol, UserProfile, *mut UserInfoHandle, *const UserInfo, usize) -> efi::Status;
+/// `EFI_USER_PROFILE_DELETE_INFO`: delete a user information record of a profile.
+pub type ProfileDeleteInfo = extern "efiapi" fn(*mut ManagerProtocol, UserProfile, UserInfoHandle) -> efi::Status;
+/// `EFI_USER_PROFILE_GET_NEXT_INFO`: enumerate the user information records of a profile.
+pub type ProfileGetNextInfo = extern "efiapi" fn(*mut ManagerProtocol, UserProfile, *mut UserInfoHandle) -> efi::Status;
+
+/// `EFI_USER_MANAGER_PROTOCOL`.
+#[repr(C)]
+pub struct ManagerProtocol {
+    /// Create a user profile.
+    pub create: ProfileCreate,
+    /// Delete a user profile.
+    pub delete: ProfileDelete,
+    /// Enumerate the user profiles.
+    pub get_next: ProfileCreate,
+    /// Return the profile of the current user.
+    pub current: ProfileCreate,
+    /// Identify a user, making their profile the current one.
+    pub identify: ProfileCreate,
+    /// Find a profile by user information record.
+    pub find: ProfileFind,
+    /// Register a newly installed credential provider.
+    pub notify: ProfileNotify,
+    /// Copy a user information record.
+    pub get_info: ProfileGetInfo,
+    /// Add or replace a user information record.
+    pub set_info: ProfileSetInfo,
+    /// Delete a user information record.
+    pub delete_info: ProfileDeleteInfo,
+    /// Enumerate the user information records of a profile.
+    pub get_next_info: ProfileGetNextInfo,
+}
+
+// SAFETY: `MANAGER_PROTOCOL_GUID` identifies the user manager protocol, whose interface is `ManagerProtocol`.
+unsafe impl Protocol for ManagerProtocol {
+    const GUID: efi::Guid = MANAGER_PROTOCOL_GUID;
+}
+
+/// Class of credential a provider handles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CredentialClass {
+    /// Class not defined by the specification.
+    Unknown,
+    /// Password or PIN.
+    Password,
+    /// Smart card.
+    SmartCard,
+    /// Fingerprint reader.
+    Fingerprint,
+    /// Hand geometry reader.
+    Handprint,
+    /// Card with a secure element, e.g. a contactless badge.
+    SecureCard,
+}
+
+impl CredentialClass {
+    const GUIDS: [(Self, efi::Guid); 6] = [
+        (Self::Unknown, CLASS_UNKNOWN_GUID),
+        (Self::Password, CLASS_PASSWORD_GUID),
+        (Self::SmartCard, CLASS_SMART_CARD_GUID),
+        (Self::Fingerprint, CLASS_FINGERPRINT_GUID),
+        (Self::Handprint, CLASS_HANDPRINT_GUID),
+        (Self::SecureCard, CLASS_SECURE_CARD_GUID),
+    ];
+
+    /// Return the class of `guid`, `Unknown` for vendor defined classes.
+    pub fn from_guid(guid: &efi::Guid) -> Self {
+        Self::GUIDS.iter().find_map(|(class, known)| (known == guid).then_some(*class)).unwrap_or(Self::Unknown)
+    }
+
+    /// Return the GUID of the class.
+    pub fn guid(self) -> efi::Guid {
+        Self::GUIDS.iter().find_map(|(class, guid)| (*class == self).then_some(*guid)).unwrap_or(CLASS_UNKNOWN_GUID)
+    }
+}
+
+/// User information record: an [`UserInfo`] header and its data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserInfoRecord {
+    /// Identifier of the credential provider owning the record, or zero for records of the user manager.
+    pub credential: efi::Guid,
+    /// Record type, e.g. [`INFO_NAME_RECORD`].
+    pub info_type: u8,
+    /// Storage and access attributes, e.g. [`INFO_PUBLIC`].
+    pub attributes: u16,
+    /// Record data, without the header.
+    pub data: Vec<u8>,
+}
+
+impl UserInfoRecord {
+    /// Parse a record, returning `None` if `bytes` is shorter than its header or than the size in the header.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < mem::size_of::<UserInfo>() {
+            return None;
+        }
+        // SAFETY: the buffer holds a header, read unaligned.
+        let header = unsafe { (bytes.as_ptr() as *const UserInfo).read_unaligned() };
+        let data = bytes.get(mem::size_of::<UserInfo>()..header.info_size as usize)?;
+        Some(Self {
+            credential: header.credential,
+            info_type: header.info_type,
+            attributes: header.info_attribs,
+            data: data.to_vec(),
+        })
+    }
+
+    /// Return the record as an [`UserInfo`] header followed by its data.
+    ///
+    /// Returns `efi::Status::BAD_BUFFER_SIZE` if the record is too large for the header.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, efi::Status> {
+        let size = mem::size_of::<UserInfo>() + self.data.len();
+        let header = UserInfo {
+            credential: self.credential,
+            info_type: self.info_type,
+            reserved1: 0,
+            info_attribs: self.attributes,
+            info_size: size.try_into().map_err(|_| efi::Status::BAD_BUFFER_SIZE)?,
+        };
+        let mut bytes = Vec::with_capacity(size);
+        // SAFETY: the header is plain data; its padding-free layout is 24 bytes.
+        bytes.extend_from_slice(unsafe {
+            slice::from_raw_parts(&header as *const UserInfo as *const u8, mem::size_of::<UserInfo>())
+        });
+        bytes.extend_from_slice(&self.data);
+        Ok(bytes)
+    }
+}
+
+/// HII string, form or image, identified by its package list and its identifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HiiReference {
+    /// HII package list.
+    pub hii_handle: efi::Handle,
+    /// String or image identifier in the package list.
+    pub id: u16,
+}
+
+/// Credential provider, installed with [`InstalledCredential::install`].
+///
+/// Functions that are not overridden report that the provider does not support them.
+pub trait CredentialProvider {
+    /// Return the string naming the provider in the identification user interface.
+    fn title(&self) -> Result<HiiReference, efi::Status>;
+
+    /// The user selected the provider: check the credential and return the logon flags, e.g. [`LOGON_FLAG_AUTO`].
+    fn select(&self) -> Result<u32, efi::Status>;
+
+    /// Return the identifier of the user owning the credential checked by [`Self::select`], or of `user` if it is
+    /// given and holds the credential.
+    ///
+    /// Returns `efi::Status::NOT_READY` if no credential was checked.
+    fn user(&self, user: Option<UserProfile>) -> Result<UserIdentifier, efi::Status>;
+
+    /// The user selected another provider: forget the credential checked by [`Self::select`].
+    fn deselect(&self) -> Result<(), efi::Status> {
+        Ok(())
+    }
+
+    /// Return the logon flags the provider wants before any user interaction.
+    fn default_logon(&self) -> Result<u32, efi::Status> {
+        Ok(0)
+    }
+
+    /// Return true if the provider can enroll users with [`Self::enroll`].
+    fn can_enroll(&self) -> bool {
+        false
+    }
+
+    /// Enroll a credential for `user`, e.g. by asking for a new password.
+    fn enroll(&self, _user: UserProfile) -> Result<(), efi::Status> {
+        Err(efi::Status::UNSUPPORTED)
+    }
+
+    /// Delete the credential enrolled for `user`.
+    fn delete(&self, _user: UserProfile) -> Result<(), efi::Status> {
+        Err(efi::Status::UNSUPPORTED)
+    }
+
+    /// Return the formset and form the identification user interface shows when the provider is selected.
+    fn form(&self) -> Result<(efi::Handle, efi::Guid, u16), efi::Status> {
+        Err(efi::Status::NOT_FOUND)
+    }
+
+    /// Return the information records of the provider, e.g. its [`INFO_CREDENTIAL_PROVIDER_NAME_RECORD`].
+    fn info(&self) -> Vec<UserInfoRecord> {
+        Vec::new()
+    }
+}
+
+#[repr(C)]
+struct Instance<'a, P> {
+    // Must be the first field: the protocol pointer passed by the user manager is cast back to the instance.
+    protocol: Credential2Protocol,
+    boot_services: &'a efi::BootServices,
+    provider: P,
+}
+
+impl<P> Instance<'_, P> {
+    /// # Safety
+    /// `protocol` must be the protocol of an instance installed by [`InstalledCredential::install`].
+    unsafe fn from_protocol<'b>(protocol: *mut Credential2Protocol) -> &'b Self {
+        &*(protocol as *const Self)
+    }
+}
+
+/// Credential provider installed from a [`CredentialProvider`], uninstalled when dropped.
+///
+/// Tiles are not supported: the user manager shows the title of the provider instead.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::user_auth::{
+///     CredentialClass, CredentialProvider, HiiReference, InstalledCredential, UserIdentifier, UserProfile,
+/// };
+/// use r_efi::efi;
+///
+/// struct Badge {
+///     title: HiiReference,
+/// }
+///
+/// impl CredentialProvider for Badge {
+///     fn title(&self) -> Result<HiiReference, efi::Status> {
+///         Ok(self.title)
+///     }
+///
+///     fn select(&self) -> Result<u32, efi::Status> {
+///         // Read the badge.
+///         Ok(0)
+///     }
+///
+///     fn user(&self, _user: Option<UserProfile>) -> Result<UserIdentifier, efi::Status> {
+///         Err(efi::Status::NOT_READY)
+///     }
+/// }
+///
+/// const BADGE_PROVIDER: efi::Guid = efi::Guid::from_fields(0, 0, 0, 0, 0, &[0; 6]);
+///
+/// fn install(boot_services: &'static efi::BootServices, title: HiiReference) -> Result<(), efi::Status> {
+///     let credential =
+///         InstalledCredential::install(boot_services, BADGE_PROVIDER, CredentialClass::SecureCard, Badge { title })?;
+///     core::mem::forget(credential);
+///     Ok(())
+/// }
+/// ```
+pub struct InstalledCredential<'a, P> {
+    handle: efi::Handle,
+    instance: ManuallyDrop<Box<Instance<'a, P>>>,
+}
+
+impl<'a, P: CredentialProvider + 'static> InstalledCredential<'a, P> {
+    /// Install the credential provider `identifier` of `class` on a new handle, implemented by `provider`.
+    ///
+    /// The provider must not borrow anything: it stays reachable from the protocol database if the installed
+    /// credential is leaked with `mem::forget`, or cannot be uninstalled when dropped.
+    pub fn install(
+        boot_services: &'a efi::BootServices,
+        identifier: efi::Guid,
+        class: CredentialClass,
+        provider: P,
+    ) -> Result<Self, efi::Status> {
+        let capabilities = if provider.can_enroll() { CAPABILITIES_ENROLL } else { 0 };
+        let mut instance = Box::new(Instance {
+            protocol: Credential2Protocol {
+                identifier,
+                r#type: class.guid(),
+                enroll: enroll::<P>,
+                form: form::<P>,
+                tile,
+                title: title::<P>,
+                user: user::<P>,
+                select: select::<P>,
+                deselect: deselect::<P>,
+                default: default::<P>,
+                get_info: get_info::<P>,
+                get_next_info: get_next_info::<P>,
+                capabilities,
+                delete: delete::<P>,
+            },
+            boot_services,
+            provider,
+        });
+        let mut handle = ptr::null_mut();
+        let mut guid = CREDENTIAL2_PROTOCOL_GUID;
+        let interface = &mut instance.protocol as *mut Credential2Protocol as *mut c_void;
+        let status =
+            (boot_services.install_protocol_interface)(&mut handle, &mut guid, efi::NATIVE_INTERFACE, interface);
+        if status.is_error() {
+            return Err(status);
+        }
+        Ok(Self { handle, instance: ManuallyDrop::new(instance) })
+    }
+
+    /// Return the handle the protocol is installed on, e.g. to pass to [`UserManager::notify`].
+    pub fn handle(&self) -> efi::Handle {
+        self.handle
+    }
+
+    /// Return the protocol, e.g. to use it with [`UserCredential`].
+    pub fn protocol(&mut self) -> *mut Credential2Protocol {
+        &mut self.instance.protocol
+    }
+}
+
+impl<P> Drop for InstalledCredential<'_, P> {
+    fn drop(&mut self) {
+        let mut guid = CREDENTIAL2_PROTOCOL_GUID;
+        let interface = &mut self.instance.protocol as *mut Credential2Protocol as *mut c_void;
+        // If the user manager still has the protocol open, the instance cannot be freed safely.
+        if !(self.instance.boot_services.uninstall_protocol_interface)(self.handle, &mut guid, interface).is_error() {
+            // SAFETY: the instance is no longer reachable from the protocol database.
+            unsafe { ManuallyDrop::drop(&mut self.instance) };
+        }
+    }
+}
+
+fn status_of(result: Result<(), efi::Status>) -> efi::Status {
+    match result {
+        Ok(()) => efi::Status::SUCCESS,
+        Err(status) => status,
+    }
+}
+
+/// Write `value` to `out`, returning `efi::Status::INVALID_PARAMETER` if `out` is null.
+fn write_out<T>(out: *mut T, value: Result<T, efi::Status>) -> efi::Status {
+    if out.is_null() {
+        return efi::Status::INVALID_PARAMETER;
+    }
+    // SAFETY: checked for null above; the caller passes a pointer to writable storage.
+    status_of(value.map(|value| unsafe { out.write(value) }))
+}
+
+extern "efiapi" fn enroll<P: CredentialProvider>(protocol: *mut Credential2Protocol, user: UserProfile) -> efi::Status {
+    // SAFETY: the user manager passes the protocol installed by `InstalledCredential::install`.
+    status_of(unsafe { Instance::<P>::from_protocol(protocol) }.provider.enroll(user))
+}
+
+extern "efiapi" fn delete<P: CredentialProvider>(protocol: *mut Credential2Protocol, user: UserProfile) -> efi::Status {
+    // SAFETY: see `enroll`.
+    status_of(unsafe { Instance::<P>::from_protocol(protocol) }.provider.delete(user))
+}
+
+extern "efiapi" fn form<P: CredentialProvider>(
+    protocol: *mut Credential2Protocol,
+    hii: *mut efi::Handle,
+    form_set_id: *mut efi::Guid,
+    form_id: *mut u16,
+) -> efi::Status {
+    if hii.is_null() || form_set_id.is_null() || form_id.is_null() {
+        return efi::Status::INVALID_PARAMETER;
+    }
+    // SAFETY: see `enroll`.
+    let result = unsafe { Instance::<P>::from_protocol(protocol) }.provider.form();
+    // SAFETY: checked for null above.
+    status_of(result.map(|(handle, form_set, form)| unsafe {
+        (*hii, *form_set_id, *form_id) = (handle, form_set, form);
+    }))
+}
+
+extern "efiapi" fn tile(
+    _protocol: *mut Credential2Protocol,
+    _width: *mut usize,
+    _height: *mut usize,
+    _hii: *mut efi::Handle,
+    _image: *mut u16,
+) -> efi::Status {
+    efi::Status::NOT_FOUND
+}
+
+extern "efiapi" fn title<P: CredentialProvider>(
+    protocol: *mut Credential2Protocol,
+    hii: *mut efi::Handle,
+    string: *mut u16,
+) -> efi::Status {
+    if hii.is_null() || string.is_null() {
+        return efi::Status::INVALID_PARAMETER;
+    }
+    // SAFETY: see `enroll`.
+    let result = unsafe { Instance::<P>::from_protocol(protocol) }.provider.title();
+    // SAFETY: checked for null above.
+    status_of(result.map(|title| unsafe { (*hii, *string) = (title.hii_handle, title.id) }))
+}
+
+extern "efiapi" fn user<P: CredentialProvider>(
+    protocol: *mut Credential2Protocol,
+    user: UserProfile,
+    identifier: *mut UserIdentifier,
+) -> efi::Status {
+    // SAFETY: see `enroll`.
+    let instance = unsafe { Instance::<P>::from_protocol(protocol) };
+    write_out(identifier, instance.provider.user((!user.is_null()).then_some(user)))
+}
+
+extern "efiapi" fn select<P: CredentialProvider>(protocol: *mut Credential2Protocol, flags: *mut u32) -> efi::Status {
+    // SAFETY: see `enroll`.
+    write_out(flags, unsafe { Instance::<P>::from_protocol(protocol) }.provider.select())
+}
+
+extern "efiapi" fn deselect<P: CredentialProvider>(protocol: *mut Credential2Protocol) -> efi::Status {
+    // SAFETY: see `enroll`.
+    status_of(unsafe { Instance::<P>::from_protocol(protocol) }.provider.deselect())
+}
+
+extern "efiapi" fn default<P: CredentialProvider>(protocol: *mut Credential2Protocol, flags: *mut u32) -> efi::Status {
+    // SAFETY: see `enroll`.
+    write_out(flags, unsafe { Instance::<P>::from_protocol(protocol) }.provider.default_logon())
+}
+
+// Information handles are the 1-based index of the record in `CredentialProvider::info`.
+
+extern "efiapi" fn get_info<P: CredentialProvider>(
+    protocol: *mut Credential2Protocol,
+    handle: UserInfoHandle,
+    info: *mut UserInfo,
+    info_size: *mut usize,
+) -> efi::Status {
+    if info_size.is_null() {
+        return efi::Status::INVALID_PARAMETER;
+    }
+    // SAFETY: see `enroll`.
+    let records = unsafe { Instance::<P>::from_protocol(protocol) }.provider.info();
+    let Some(record) = (handle as usize).checked_sub(1).and_then(|index| records.get(index)) else {
+        return efi::Status::NOT_FOUND;
+    };
+    let bytes = match record.to_bytes() {
+        Ok(bytes) => bytes,
+        Err(status) => return status,
+    };
+    // SAFETY: checked for null above.
+    let available = unsafe { info_size.replace(bytes.len()) };
+    if available < bytes.len() || info.is_null() {
+        return efi::Status::BUFFER_TOO_SMALL;
+    }
+    // SAFETY: the caller's buffer holds `available` bytes.
+    unsafe { (info as *mut u8).copy_from_nonoverlapping(bytes.as_ptr(), bytes.len()) };
+    efi::Status::SUCCESS
+}
+
+extern "efiapi" fn get_next_info<P: CredentialProvider>(
+    protocol: *mut Credential2Protocol,
+    handle: *mut UserInfoHandle,
+) -> efi::Status {
+    if handle.is_null() {
+        return efi::Status::INVALID_PARAMETER;
+    }
+    // SAFETY: see `enroll`.
+    let count = unsafe { Instance::<P>::from_protocol(protocol) }.provider.info().len();
+    // SAFETY: checked for null above.
+    let next = unsafe { *handle } as usize + 1;
+    if next > count {
+        return efi::Status::NOT_FOUND;
+    }
+    // SAFETY: checked for null above.
+    unsafe { *handle = next as UserInfoHandle };
+    efi::Status::SUCCESS
+}
+
+fn result(status: efi::Status) -> Result<(), efi::Status> {
+    if status.is_error() {
+        Err(status)
+    } else {
+        Ok(())
+    }
+}
+
+/// Credential provider, seen from the user manager or from an enrollment application.
+pub struct UserCredential<'a> {
+    protocol: *mut Credential2Protocol,
+    _lifetime_marker: PhantomData<&'a Credential2Protocol>,
+}
+
+impl UserCredential<'_> {
+    /// Create a wrapper around `protocol`.
+    ///
+    /// # Safety
+    /// `protocol` must point to a valid protocol structure for the lifetime of the wrapper.
+    pub unsafe fn new(protocol: *mut Credential2Protocol) -> Self {
+        Self { protocol, _lifetime_marker: PhantomData }
+    }
+
+    fn protocol(&self) -> &Credential2Protocol {
+        // SAFETY: the protocol is valid for the lifetime of the wrapper.
+        unsafe { &*self.protocol }
+    }
+
+    /// Return the identifier of the provider.
+    pub fn identifier(&self) -> efi::Guid {
+        self.protocol().identifier
+    }
+
+    /// Return the class of credential the provider handles.
+    pub fn class(&self) -> CredentialClass {
+        CredentialClass::from_guid(&self.protocol().r#type)
+    }
+
+    /// Return true if the provider can enroll users.
+    pub fn can_enroll(&self) -> bool {
+        self.protocol().capabilities & CAPABILITIES_ENROLL != 0
+    }
+
+    /// Return the string naming the provider.
+    pub fn title(&self) -> Result<HiiReference, efi::Status> {
+        let mut title = HiiReference { hii_handle: ptr::null_mut(), id: 0 };
+        result((self.protocol().title)(self.protocol, &mut title.hii_handle, &mut title.id))?;
+        Ok(title)
+    }
+
+    /// Enroll a credential for `user`.
+    pub fn enroll(&self, user: UserProfile) -> Result<(), efi::Status> {
+        result((self.protocol().enroll)(self.protocol, user))
+    }
+
+    /// Check the credential, returning the logon flags.
+    pub fn select(&self) -> Result<u32, efi::Status> {
+        let mut flags = 0;
+        result((self.protocol().select)(self.protocol, &mut flags))?;
+        Ok(flags)
+    }
+
+    /// Forget the credential checked by [`Self::select`].
+    pub fn deselect(&self) -> Result<(), efi::Status> {
+        result((self.protocol().deselect)(self.protocol))
+    }
+
+    /// Return the identifier of the user owning the checked credential, or of `user` if it holds the credential.
+    pub fn user(&self, user: Option<UserProfile>) -> Result<UserIdentifier, efi::Status> {
+        let mut identifier = [0; 16];
+        result((self.protocol().user)(self.protocol, user.unwrap_or(ptr::null_mut()), &mut identifier))?;
+        Ok(identifier)
+    }
+
+    /// Return the information records of the provider.
+    pub fn info(&self) -> Result<Vec<UserInfoRecord>, efi::Status> {
+        let mut records = Vec::new();
+        let mut handle = ptr::null_mut();
+        loop {
+            match (self.protocol().get_next_info)(self.protocol, &mut handle) {
+                efi::Status::NOT_FOUND => return Ok(records),
+                status => result(status)?,
+            }
+            let bytes = read_record(|info, size| (self.protocol().get_info)(self.protocol, handle, info, size))?;
+            records.push(UserInfoRecord::from_bytes(&bytes).ok_or(efi::Status::COMPROMISED_DATA)?);
+        }
+    }
+}
+
+/// Read a record with the two-call pattern.
+fn read_record(mut get_info: impl FnMut(*mut UserInfo, *mut usize) -> efi::Status) -> Result<Vec<u8>, efi::Status> {
+    let mut len = 0;
+    // Allocated as `u64` so that the header is aligned.
+    let words = get_with_growing_buffer(0u64, |buffer: &mut [u64]| {
+        let mut size = mem::size_of_val(buffer);
+        let status = get_info(buffer.as_mut_ptr() as *mut UserInfo, &mut size);
+        if status.is_error() {
+            return Err((status, size.div_ceil(mem::size_of::<u64>())));
+        }
+        len = size;
+        Ok(buffer.len())
+    })?;
+    Ok(words.iter().flat_map(|word| word.to_ne_bytes()).take(len).collect())
+}
+
+/// User manager, keeping the user profiles and identifying users.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::user_auth::UserManager;
+/// use r_efi::efi;
+///
+/// fn log_on(boot_services: &efi::BootServices) -> Result<(), efi::Status> {
+///     let manager = UserManager::locate(boot_services)?;
+///     // Runs the identification user interface until a user is identified.
+///     let user = manager.identify()?;
+///     let records = manager.info(user)?;
+///     let _ = records;
+///     Ok(())
+/// }
+/// ```
+pub struct UserManager<'a> {
+    protocol: *mut ManagerProtocol,
+    _lifetime_marker: PhantomData<&'a ManagerProtocol>,
+}
+
+impl<'a> UserManager<'a> {
+    /// Create a wrapper around `protocol`.
+    ///
+    /// # Safety
+    /// `protocol` must point to a valid protocol structure for the lifetime of the wrapper.
+    pub unsafe fn new(protocol: *mut ManagerProtocol) -> Self {
+        Self { protocol, _lifetime_marker: PhantomData }
+    }
+
+    /// Locate the protocol.
+    pub fn locate(boot_services: &'a efi::BootServices) -> Result<Self, efi::Status> {
+        let mut guid = MANAGER_PROTOCOL_GUID;
+        let mut interface = ptr::null_mut();
+        let status = (boot_services.locate_protocol)(&mut guid, ptr::null_mut(), &mut interface);
+        if status.is_error() {
+            return Err(status);
+        }
+        if interface.is_null() {
+            return Err(efi::Status::NOT_FOUND);
+        }
+        // SAFETY: the firmware installs a valid protocol structure with this GUID, which stays installed while boot
+        // services are available.
+        Ok(unsafe { Self::new(interface as *mut ManagerProtocol) })
+    }
+
+    fn protocol(&self) -> &ManagerProtocol {
+        // SAFETY: the protocol is valid for the lifetime of the wrapper.
+        unsafe { &*self.protocol }
+    }
+
+    fn get_profile(&self, service: ProfileCreate) -> Result<UserProfile, efi::Status> {
+        let mut user = ptr::null_mut();
+        result(service(self.protocol, &mut user))?;
+        Ok(user)
+    }
+
+    /// Create a user profile, to be enrolled with [`UserCredential::enroll`].
+    pub fn create(&self) -> Result<UserProfile, efi::Status> {
+        self.get_profile(self.protocol().create)
+    }
+
+    /// Delete `user`.
+    pub fn delete(&self, user: UserProfile) -> Result<(), efi::Status> {
+        result((self.protocol().delete)(self.protocol, user))
+    }
+
+    /// Return the user currently logged on.
+    pub fn current(&self) -> Result<UserProfile, efi::Status> {
+        self.get_profile(self.protocol().current)
+    }
+
+    /// Identify a user, with the credential providers selected by the identification policy.
+    pub fn identify(&self) -> Result<UserProfile, efi::Status> {
+        self.get_profile(self.protocol().identify)
+    }
+
+    /// Return every user profile.
+    pub fn profiles(&self) -> Result<Vec<UserProfile>, efi::Status> {
+        let mut profiles = Vec::new();
+        let mut user = ptr::null_mut();
+        loop {
+            match (self.protocol().get_next)(self.protocol, &mut user) {
+                efi::Status::NOT_FOUND => return Ok(profiles),
+                status => result(status)?,
+            }
+            profiles.push(user);
+        }
+    }
+
+    /// Tell the user manager that the credential provider installed on `handle` was added or changed.
+    pub fn notify(&self, handle: efi::Handle) -> Result<(), efi::Status> {
+        result((self.protocol().notify)(self.protocol, handle))
+    }
+
+    /// Return the information records of `user`, with their handles.
+    pub fn info(&self, user: UserProfile) -> Result<Vec<(UserInfoHandle, UserInfoRecord)>, efi::Status> {
+        let mut records = Vec::new();
+        let mut handle = ptr::null_mut();
+        loop {
+            match (self.protocol().get_next_info)(self.protocol, user, &mut handle) {
+                efi::Status::NOT_FOUND => return Ok(records),
+                status => result(status)?,
+            }
+            let bytes = read_record(|info, size| (self.protocol().get_info)(self.protocol, user, handle, info, size))?;
+            records.push((handle, UserInfoRecord::from_bytes(&bytes).ok_or(efi::Status::COMPROMISED_DATA)?));
+        }
+    }
+
+    /// Add `record` to `user`, or replace the record `handle`, returning the handle of the record.
+    pub fn set_info(
+        &self,
+        user: UserProfile,
+        handle: Option<UserInfoHandle>,
+        record: &UserInfoRecord,
+    ) -> Result<UserInfoHandle, efi::Status> {
+        let bytes = record.to_bytes()?;
+        // Copied to a `u64` buffer so that the header is aligned.
+        let mut aligned = alloc::vec![0u64; bytes.len().div_ceil(mem::size_of::<u64>())];
+        // SAFETY: the aligned buffer holds at least `bytes.len()` bytes.
+        unsafe { (aligned.as_mut_ptr() as *mut u8).copy_from_nonoverlapping(bytes.as_ptr(), bytes.len()) };
+        let mut handle = handle.unwrap_or(ptr::null_mut());
+        let info = aligned.as_ptr() as *const UserInfo;
+        result((self.protocol().set_info)(self.protocol, user, &mut handle, info, bytes.len()))?;
+        Ok(handle)
+    }
+
+    /// Delete the record `handle` of `user`.
+    pub fn delete_info(&self, user: UserProfile, handle: UserInfoHandle) -> Result<(), efi::Status> {
+        result((self.protocol().delete_info)(self.protocol, user, handle))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::cell::{Cell, RefCell};
+
+    use crate::test_support::mock_efi_boot_services;
+
+    std::thread_local! {
+        static INSTALLED: RefCell<Option<*mut c_void>> = const { RefCell::new(None) };
+    }
+
+    const PROVIDER: efi::Guid =
+        efi::Guid::from_fields(0x8e1c6b5d, 0x2a7f, 0x4e3b, 0x9c, 0x04, &[0x51, 0x7a, 0x2d, 0x3e, 0x6f, 0x80]);
+    const HII: efi::Handle = 0x4111 as efi::Handle;
+    const ALICE: UserProfile = 0xa11ce as UserProfile;
+
+    extern "efiapi" fn install_protocol_interface(
+        handle: *mut efi::Handle,
+        protocol: *mut efi::Guid,
+        _interface_type: efi::InterfaceType,
+        interface: *mut c_void,
+    ) -> efi::Status {
+        assert_eq!(unsafe { *protocol }, CREDENTIAL2_PROTOCOL_GUID);
+        INSTALLED.with(|installed| *installed.borrow_mut() = Some(interface));
+        unsafe { *handle = 0xc4ed as efi::Handle };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn uninstall_protocol_interface(
+        _handle: efi::Handle,
+        _protocol: *mut efi::Guid,
+        interface: *mut c_void,
+    ) -> efi::Status {
+        INSTALLED.with(|installed| assert_eq!(installed.borrow_mut().take(), Some(interface)));
+        efi::Status::SUCCESS
+    }
+
+    /// Password provider with a single enrolled user.
+    struct Password {
+        checked: Cell<bool>,
+        enrolled: Cell<Option<UserProfile>>,
+    }
+
+    impl CredentialProvider for Password {
+        fn title(&self) -> Result<HiiReference, efi::Status> {
+            Ok(HiiReference { hii_handle: HII, id: 7 })
+        }
+
+        fn select(&self) -> Result<u32, efi::Status> {
+            self.checked.set(true);
+            Ok(LOGON_FLAG_DEFAULT)
+        }
+
+        fn user(&self, user: Option<UserProfile>) -> Result<UserIdentifier, efi::Status> {
+            match (self.checked.get(), user) {
+                (false, None) => Err(efi::Status::NOT_READY),
+                (_, Some(user)) if Some(user) != self.enrolled.get() => Err(efi::Status::NOT_FOUND),
+                _ => Ok([0xa1; 16]),
+            }
+        }
+
+        fn deselect(&self) -> Result<(), efi::Status> {
+            self.checked.set(false);
+            Ok(())
+        }
+
+        fn can_enroll(&self) -> bool {
+            true
+        }
+
+        fn enroll(&self, user: UserProfile) -> Result<(), efi::Status> {
+            self.enrolled.set(Some(user));
+            Ok(())
+        }
+
+        fn info(&self) -> Vec<UserInfoRecord> {
+            let name = "Password".encode_utf16().chain([0]).flat_map(u16::to_le_bytes).collect();
+            alloc::vec![UserInfoRecord {
+                credential: PROVIDER,
+                info_type: INFO_CREDENTIAL_PROVIDER_NAME_RECORD,
+                attributes: INFO_PUBLIC,
+                data: name,
+            }]
+        }
+    }
+
+    #[test]
+    fn test_credential_class() {
+        for class in [CredentialClass::Password, CredentialClass::SmartCard, CredentialClass::SecureCard] {
+            assert_eq!(CredentialClass::from_guid(&class.guid()), class);
+        }
+        assert_eq!(CredentialClass::from_guid(&PROVIDER), CredentialClass::Unknown);
+    }
+
+    #[test]
+    fn test_user_info_record() {
+        assert_eq!(mem::size_of::<UserInfo>(), 24);
+        let record = UserInfoRecord {
+            credential: PROVIDER,
+            info_type: INFO_IDENTIFIER_RECORD,
+            attributes: INFO_PUBLIC | INFO_EXCLUSIVE,
+            data: alloc::vec![0xa1; 16],
+        };
+        let bytes = record.to_bytes().unwrap();
+        assert_eq!(bytes.len(), 40);
+        assert_eq!(&bytes[18..20], &0x0090u16.to_le_bytes());
+        assert_eq!(&bytes[20..24], &40u32.to_le_bytes());
+        assert_eq!(UserInfoRecord::from_bytes(&bytes), Some(record));
+        assert_eq!(UserInfoRecord::from_bytes(&bytes[..39]), None);
+    }
+
+    #[test]
+    fn test_installed_credential() {
+        let boot_services =
+            efi::BootServices { install_protocol_interface, uninstall_protocol_interface, ..mock_efi_boot_services() };
+        let provider = Password { checked: Cell::new(false), enrolled: Cell::new(None) };
+        let mut installed =
+            InstalledCredential::install(&boot_services, PROVIDER, CredentialClass::Password, provider).unwrap();
+        assert_eq!(installed.handle(), 0xc4ed as efi::Handle);
+
+        let credential = unsafe { UserCredential::new(installed.protocol()) };
+        assert_eq!((credential.identifier(), credential.class()), (PROVIDER, CredentialClass::Password));
+        assert!(credential.can_enroll());
+        assert_eq!(credential.title(), Ok(HiiReference { hii_handle: HII, id: 7 }));
+
+        assert_eq!(credential.user(None), Err(efi::Status::NOT_READY));
+        credential.enroll(ALICE).unwrap();
+        assert_eq!(credential.select(), Ok(LOGON_FLAG_DEFAULT));
+        assert_eq!(credential.user(None), Ok([0xa1; 16]));
+        assert_eq!(credential.user(Some(0xb0b as UserProfile)), Err(efi::Status::NOT_FOUND));
+        credential.deselect().unwrap();
+        assert_eq!(credential.user(None), Err(efi::Status::NOT_READY));
+
+        let records = credential.info().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!((records[0].info_type, records[0].attributes), (INFO_CREDENTIAL_PROVIDER_NAME_RECORD, 0x0010));
+        assert_eq!(records[0].data.len(), 18);
+
+        drop(installed);
+        assert_eq!(INSTALLED.with(|installed| *installed.borrow()), None);
+    }
+}
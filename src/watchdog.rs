@@ -0,0 +1,290 @@
+//! Scoped arming of the watchdog timer.
+//!
+//! Firmware arms a five minute watchdog before starting a boot option, and long-running boot tasks must either finish
+//! in time or keep re-arming it. [`WatchdogGuard`] arms the watchdog for as long as it is alive, and can re-arm it
+//! periodically from a timer event so the task itself does not have to. [`with_watchdog_kicks`] does the same for the
+//! duration of a single operation.
+//!
+use core::{ptr, time::Duration};
+
+use r_efi::efi;
+
+use crate::timer::Timer;
+
+/// Watchdog code used by [`WatchdogGuard::arm`]. Codes up to `0xFFFF` are reserved for the firmware.
+pub const DEFAULT_WATCHDOG_CODE: u64 = 0x10000;
+
+#[derive(Clone, Copy)]
+struct Watchdog {
+    set_watchdog_timer: efi::BootSetWatchdogTimer,
+    timeout: usize,
+    code: u64,
+}
+
+impl Watchdog {
+    fn set(&self, timeout: usize) -> Result<(), efi::Status> {
+        let status = (self.set_watchdog_timer)(timeout, self.code, 0, ptr::null_mut());
+        if status.is_error() {
+            return Err(status);
+        }
+        Ok(())
+    }
+}
+
+/// Watchdog timer armed while the guard is alive, and disarmed when it is dropped.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::watchdog::WatchdogGuard;
+/// use r_efi::efi;
+///
+/// fn apply_update(boot_services: &efi::BootServices) -> Result<(), efi::Status> {
+///     // Reset the platform if the update hangs for more than a minute.
+///     let watchdog = WatchdogGuard::arm(boot_services, 60)?;
+///     for _block in 0..16 {
+///         // Write a block, then give the next one a full minute.
+///         watchdog.pet()?;
+///     }
+///     Ok(())
+/// }
+/// ```
+pub struct WatchdogGuard<'a> {
+    watchdog: Watchdog,
+    pet_timer: Option<Timer<'a>>,
+    restore_timeout: usize,
+}
+
+impl<'a> WatchdogGuard<'a> {
+    /// Arm the watchdog to reset the platform after `timeout` seconds, with [`DEFAULT_WATCHDOG_CODE`].
+    ///
+    /// Returns `efi::Status::INVALID_PARAMETER` if `timeout` is zero, which would disarm the watchdog instead.
+    pub fn arm(boot_services: &'a efi::BootServices, timeout: usize) -> Result<Self, efi::Status> {
+        Self::arm_with_code(boot_services, timeout, DEFAULT_WATCHDOG_CODE)
+    }
+
+    /// Arm the watchdog to reset the platform after `timeout` seconds, logging `code` when it fires.
+    pub fn arm_with_code(boot_services: &'a efi::BootServices, timeout: usize, code: u64) -> Result<Self, efi::Status> {
+        if timeout == 0 {
+            return Err(efi::Status::INVALID_PARAMETER);
+        }
+        let watchdog = Watchdog { set_watchdog_timer: boot_services.set_watchdog_timer, timeout, code };
+        watchdog.set(timeout)?;
+        Ok(Self { watchdog, pet_timer: None, restore_timeout: 0 })
+    }
+
+    /// Arm the watchdog for `timeout` seconds and re-arm it every `timeout / 2` seconds from a timer event.
+    ///
+    /// The timer is notified at `TPL_CALLBACK`, so the watchdog only fires if the platform stays at or above that
+    /// level for too long, e.g. because a notification function hangs. Use [`Self::arm`] to also catch hangs of the
+    /// code holding the guard.
+    ///
+    /// Returns `efi::Status::INVALID_PARAMETER` if `timeout` is zero.
+    pub fn auto_pet(boot_services: &'a efi::BootServices, timeout: usize) -> Result<Self, efi::Status> {
+        Self::arm_periodic(boot_services, timeout, Duration::from_secs(timeout as u64) / 2)
+    }
+
+    fn arm_periodic(
+        boot_services: &'a efi::BootServices,
+        timeout: usize,
+        period: Duration,
+    ) -> Result<Self, efi::Status> {
+        let mut guard = Self::arm(boot_services, timeout)?;
+        let watchdog = guard.watchdog;
+        // There is no one to report a failure to; the watchdog fires eventually if re-arming keeps failing.
+        let pet = move || {
+            let _ = watchdog.set(watchdog.timeout);
+        };
+        guard.pet_timer = Some(Timer::periodic(boot_services, period, pet)?);
+        Ok(guard)
+    }
+
+    /// Re-arm the watchdog for the full timeout.
+    pub fn pet(&self) -> Result<(), efi::Status> {
+        self.watchdog.set(self.watchdog.timeout)
+    }
+
+    /// Return the timeout in seconds.
+    pub fn timeout(&self) -> usize {
+        self.watchdog.timeout
+    }
+}
+
+impl Drop for WatchdogGuard<'_> {
+    fn drop(&mut self) {
+        // Cancel the re-arms before disarming.
+        drop(self.pet_timer.take());
+        // Disarming cannot be reported from a destructor, and the watchdog fires if it keeps failing anyway.
+        let _ = self.watchdog.set(self.restore_timeout);
+    }
+}
+
+/// Run `operation` while re-arming the watchdog every `interval` seconds from a timer event.
+///
+/// Each re-arm gives the watchdog twice the interval, so a missed tick does not reset the platform. UEFI cannot report
+/// the current watchdog timeout, so the caller passes the one to restore afterwards in `previous_timeout`, zero to
+/// leave the watchdog disarmed. It is restored with [`DEFAULT_WATCHDOG_CODE`], even if `operation` panics.
+///
+/// The timer is notified at `TPL_CALLBACK`, so `operation` must run below that level for the re-arms to happen.
+///
+/// Returns `efi::Status::INVALID_PARAMETER` if `interval` is zero or too large, without touching the watchdog.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::watchdog::with_watchdog_kicks;
+/// use r_efi::efi;
+///
+/// fn erase_flash(boot_services: &efi::BootServices) -> Result<(), efi::Status> {
+///     // Firmware arms a five minute watchdog before starting a boot option.
+///     with_watchdog_kicks(boot_services, 30, 300, || {
+///         // Erase the whole device, which may take longer than five minutes.
+///     })
+/// }
+/// ```
+pub fn with_watchdog_kicks<R>(
+    boot_services: &efi::BootServices,
+    interval: usize,
+    previous_timeout: usize,
+    operation: impl FnOnce() -> R,
+) -> Result<R, efi::Status> {
+    // Validated before the watchdog is touched, so that an invalid interval leaves it as it is.
+    let timeout = interval.checked_mul(2).filter(|&timeout| timeout != 0).ok_or(efi::Status::INVALID_PARAMETER)?;
+    let mut guard = match WatchdogGuard::arm_periodic(boot_services, timeout, Duration::from_secs(interval as u64)) {
+        Ok(guard) => guard,
+        Err(status) => {
+            // The watchdog may have been re-armed before the failure.
+            let watchdog = Watchdog {
+                set_watchdog_timer: boot_services.set_watchdog_timer,
+                timeout: 0,
+                code: DEFAULT_WATCHDOG_CODE,
+            };
+            let _ = watchdog.set(previous_timeout);
+            return Err(status);
+        }
+    };
+    guard.restore_timeout = previous_timeout;
+    Ok(operation())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{cell::RefCell, ffi::c_void};
+
+    use crate::test_support::mock_efi_boot_services;
+
+    const TEST_EVENT: usize = 0xd09;
+
+    #[derive(Default)]
+    struct MockState {
+        watchdog: Vec<(usize, u64)>,
+        notify: Option<(efi::EventNotify, usize)>,
+        timer: Option<(efi::TimerDelay, u64)>,
+        closed: Vec<efi::Event>,
+    }
+
+    std::thread_local! {
+        static STATE: RefCell<MockState> = RefCell::new(MockState::default());
+    }
+
+    extern "efiapi" fn set_watchdog_timer(timeout: usize, code: u64, data_size: usize, data: *mut u16) -> efi::Status {
+        assert_eq!((data_size, data), (0, ptr::null_mut()));
+        STATE.with(|state| state.borrow_mut().watchdog.push((timeout, code)));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn create_event(
+        event_type: u32,
+        tpl: efi::Tpl,
+        notify: Option<efi::EventNotify>,
+        context: *mut c_void,
+        event: *mut efi::Event,
+    ) -> efi::Status {
+        assert_eq!((event_type, tpl), (efi::EVT_TIMER | efi::EVT_NOTIFY_SIGNAL, efi::TPL_CALLBACK));
+        STATE.with(|state| state.borrow_mut().notify = notify.map(|notify| (notify, context as usize)));
+        unsafe { *event = TEST_EVENT as efi::Event };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn set_timer(event: efi::Event, delay: efi::TimerDelay, period: u64) -> efi::Status {
+        assert_eq!(event, TEST_EVENT as efi::Event);
+        STATE.with(|state| state.borrow_mut().timer = Some((delay, period)));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn close_event(event: efi::Event) -> efi::Status {
+        STATE.with(|state| state.borrow_mut().closed.push(event));
+        efi::Status::SUCCESS
+    }
+
+    fn watchdog_calls() -> Vec<(usize, u64)> {
+        STATE.with(|state| state.borrow().watchdog.clone())
+    }
+
+    #[test]
+    fn test_arm() {
+        let boot_services = efi::BootServices { set_watchdog_timer, ..mock_efi_boot_services() };
+        let guard = WatchdogGuard::arm(&boot_services, 30).unwrap();
+        assert_eq!(guard.timeout(), 30);
+        guard.pet().unwrap();
+        drop(guard);
+        assert_eq!(
+            watchdog_calls(),
+            [(30, DEFAULT_WATCHDOG_CODE), (30, DEFAULT_WATCHDOG_CODE), (0, DEFAULT_WATCHDOG_CODE)]
+        );
+
+        assert!(matches!(WatchdogGuard::arm(&boot_services, 0), Err(efi::Status::INVALID_PARAMETER)));
+        assert!(matches!(WatchdogGuard::arm(&mock_efi_boot_services(), 30), Err(efi::Status::UNSUPPORTED)));
+    }
+
+    #[test]
+    fn test_auto_pet() {
+        let boot_services =
+            efi::BootServices { set_watchdog_timer, create_event, set_timer, close_event, ..mock_efi_boot_services() };
+        let guard = WatchdogGuard::auto_pet(&boot_services, 10).unwrap();
+        assert_eq!(STATE.with(|state| state.borrow().timer), Some((efi::TIMER_PERIODIC, 50_000_000)));
+
+        let (notify, context) = STATE.with(|state| state.borrow().notify).unwrap();
+        notify(TEST_EVENT as efi::Event, context as *mut c_void);
+        drop(guard);
+        assert_eq!(
+            watchdog_calls(),
+            [(10, DEFAULT_WATCHDOG_CODE), (10, DEFAULT_WATCHDOG_CODE), (0, DEFAULT_WATCHDOG_CODE)]
+        );
+        assert_eq!(STATE.with(|state| state.borrow().closed.clone()), [TEST_EVENT as efi::Event]);
+
+        // A zero timeout would disarm the watchdog; nothing is armed.
+        let result = WatchdogGuard::auto_pet(&boot_services, 0);
+        assert!(matches!(result, Err(efi::Status::INVALID_PARAMETER)));
+        assert_eq!(watchdog_calls().len(), 3);
+    }
+
+    #[test]
+    fn test_with_watchdog_kicks() {
+        let boot_services =
+            efi::BootServices { set_watchdog_timer, create_event, set_timer, close_event, ..mock_efi_boot_services() };
+        let result = with_watchdog_kicks(&boot_services, 30, 300, || {
+            let (notify, context) = STATE.with(|state| state.borrow().notify).unwrap();
+            notify(TEST_EVENT as efi::Event, context as *mut c_void);
+            7
+        });
+        assert_eq!(result, Ok(7));
+        assert_eq!(STATE.with(|state| state.borrow().timer), Some((efi::TIMER_PERIODIC, 300_000_000)));
+        assert_eq!(
+            watchdog_calls(),
+            [(60, DEFAULT_WATCHDOG_CODE), (60, DEFAULT_WATCHDOG_CODE), (300, DEFAULT_WATCHDOG_CODE)]
+        );
+
+        // Without timer events, the watchdog is restored right away.
+        let boot_services = efi::BootServices { set_watchdog_timer, ..mock_efi_boot_services() };
+        assert_eq!(with_watchdog_kicks(&boot_services, 30, 300, || ()), Err(efi::Status::UNSUPPORTED));
+        assert_eq!(
+            watchdog_calls()[3..],
+            [(60, DEFAULT_WATCHDOG_CODE), (0, DEFAULT_WATCHDOG_CODE), (300, DEFAULT_WATCHDOG_CODE)]
+        );
+        // Invalid intervals leave the watchdog alone.
+        assert_eq!(with_watchdog_kicks(&boot_services, 0, 300, || ()), Err(efi::Status::INVALID_PARAMETER));
+        assert_eq!(with_watchdog_kicks(&boot_services, usize::MAX, 300, || ()), Err(efi::Status::INVALID_PARAMETER));
+        assert_eq!(watchdog_calls().len(), 6);
+    }
+}
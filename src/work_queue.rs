@@ -0,0 +1,202 @@
+//! Deferred work drained at `efi::TPL_CALLBACK`.
+//!
+//! Code running at `efi::TPL_NOTIFY`, such as the completion of an asynchronous I/O request, should return quickly
+//! and cannot call most boot services. [`WorkQueue`] lets it queue the rest of its work as a closure, run later from
+//! an event notification at `efi::TPL_CALLBACK`: the "bottom half" of the notification.
+//!
+use alloc::{boxed::Box, collections::VecDeque};
+use core::{cell::RefCell, ffi::c_void};
+
+use r_efi::efi;
+
+use crate::event::{EventBuilder, SignalEvent};
+
+type Job = Box<dyn FnOnce()>;
+
+// Keeps the TPL services rather than the boot services table, which a leaked queue could outlive.
+struct Queue {
+    raise_tpl: efi::BootRaiseTpl,
+    restore_tpl: efi::BootRestoreTpl,
+    // Only borrowed at TPL_NOTIFY, so that a push cannot interrupt another borrow.
+    jobs: RefCell<VecDeque<Job>>,
+}
+
+impl Queue {
+    fn with_jobs<R>(&self, f: impl FnOnce(&mut VecDeque<Job>) -> R) -> R {
+        let tpl = (self.raise_tpl)(efi::TPL_NOTIFY);
+        let result = f(&mut self.jobs.borrow_mut());
+        (self.restore_tpl)(tpl);
+        result
+    }
+}
+
+/// Queue of closures pushed at up to `efi::TPL_NOTIFY` and run in order at `efi::TPL_CALLBACK`.
+///
+/// Closures still queued when the queue is dropped are dropped without being run.
+///
+/// # Example
+/// ```no_run
+/// use mu_rust_helpers::work_queue::WorkQueue;
+/// use r_efi::efi;
+///
+/// fn on_transfer_complete(queue: &WorkQueue<'static>, length: usize, parse_packet: fn(usize)) {
+///     // Running at TPL_NOTIFY: parse the packet once the TPL drops to TPL_CALLBACK.
+///     queue.push(move || parse_packet(length)).unwrap();
+/// }
+/// ```
+pub struct WorkQueue<'a> {
+    // Declared first so that the event is closed before the queue it drains is dropped.
+    event: SignalEvent<'a>,
+    queue: Box<Queue>,
+}
+
+impl<'a> WorkQueue<'a> {
+    /// Create an empty queue.
+    pub fn new(boot_services: &'a efi::BootServices) -> Result<Self, efi::Status> {
+        extern "efiapi" fn drain(_event: efi::Event, context: *mut c_void) {
+            // SAFETY: the context is the boxed queue owned by the `WorkQueue`, which closes this event before dropping
+            // it.
+            let queue = unsafe { &*(context as *const Queue) };
+            // Jobs pushed by a running job, or by a notification interrupting it, run in this pass.
+            while let Some(job) = queue.with_jobs(|jobs| jobs.pop_front()) {
+                job();
+            }
+        }
+
+        let queue = Box::new(Queue {
+            raise_tpl: boot_services.raise_tpl,
+            restore_tpl: boot_services.restore_tpl,
+            jobs: RefCell::new(VecDeque::new()),
+        });
+        let context = &*queue as *const Queue as *mut c_void;
+        let event = EventBuilder::new()
+            .notify_signal()
+            .tpl(efi::TPL_CALLBACK)
+            .callback(drain, context)
+            .create(boot_services)?;
+        Ok(Self { event, queue })
+    }
+
+    /// Queue `job`, to be run once the TPL drops below `efi::TPL_CALLBACK`.
+    ///
+    /// Must be called at or below `efi::TPL_NOTIFY`, the highest TPL at which memory can be allocated. The closure
+    /// must not borrow anything, since the queue may be leaked with `mem::forget` and run it later.
+    pub fn push(&self, job: impl FnOnce() + 'static) -> Result<(), efi::Status> {
+        let job: Job = Box::new(job);
+        self.queue.with_jobs(|jobs| jobs.push_back(job));
+        self.event.signal()
+    }
+
+    /// Return the number of queued closures.
+    pub fn len(&self) -> usize {
+        self.queue.with_jobs(|jobs| jobs.len())
+    }
+
+    /// Return true if no closure is queued.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use alloc::{rc::Rc, vec::Vec};
+    use core::cell::Cell;
+
+    use crate::test_support::mock_efi_boot_services;
+
+    std::thread_local! {
+        static NOTIFY: Cell<Option<(efi::EventNotify, usize)>> = const { Cell::new(None) };
+        static TPL: Cell<efi::Tpl> = const { Cell::new(efi::TPL_APPLICATION) };
+        static SIGNALED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    extern "efiapi" fn create_event(
+        event_type: u32,
+        tpl: efi::Tpl,
+        notify: Option<efi::EventNotify>,
+        context: *mut c_void,
+        event: *mut efi::Event,
+    ) -> efi::Status {
+        assert_eq!((event_type, tpl), (efi::EVT_NOTIFY_SIGNAL, efi::TPL_CALLBACK));
+        NOTIFY.with(|cell| cell.set(Some((notify.unwrap(), context as usize))));
+        unsafe { *event = 0x3b as efi::Event };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn signal_event(event: efi::Event) -> efi::Status {
+        assert_eq!(event as usize, 0x3b);
+        SIGNALED.with(|signaled| signaled.set(signaled.get() + 1));
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn close_event(_event: efi::Event) -> efi::Status {
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn raise_tpl(tpl: efi::Tpl) -> efi::Tpl {
+        TPL.with(|current| current.replace(tpl))
+    }
+
+    extern "efiapi" fn restore_tpl(tpl: efi::Tpl) {
+        TPL.with(|current| current.set(tpl));
+    }
+
+    fn drain() {
+        let (notify, context) = NOTIFY.with(|cell| cell.get()).unwrap();
+        let tpl = TPL.with(|current| current.replace(efi::TPL_CALLBACK));
+        notify(0x3b as efi::Event, context as *mut c_void);
+        TPL.with(|current| current.set(tpl));
+    }
+
+    fn boot_services() -> efi::BootServices {
+        efi::BootServices {
+            create_event,
+            signal_event,
+            close_event,
+            raise_tpl,
+            restore_tpl,
+            ..mock_efi_boot_services()
+        }
+    }
+
+    #[test]
+    fn test_push_and_drain() {
+        let boot_services = boot_services();
+        let order = Rc::new(RefCell::new(Vec::new()));
+        let queue = WorkQueue::new(&boot_services).unwrap();
+        assert!(queue.is_empty());
+
+        for job in [1, 2] {
+            let order = order.clone();
+            queue.push(move || order.borrow_mut().push((job, TPL.with(|tpl| tpl.get())))).unwrap();
+        }
+        assert_eq!(queue.len(), 2);
+        assert_eq!(SIGNALED.with(|signaled| signaled.get()), 2);
+        assert!(order.borrow().is_empty());
+
+        drain();
+        assert!(queue.is_empty());
+        assert_eq!(*order.borrow(), [(1, efi::TPL_CALLBACK), (2, efi::TPL_CALLBACK)]);
+        assert_eq!(TPL.with(|tpl| tpl.get()), efi::TPL_APPLICATION);
+    }
+
+    #[test]
+    fn test_push_from_job() {
+        // A job pushing to its own queue needs the queue to outlive it, as in a driver.
+        let boot_services: &'static efi::BootServices = Box::leak(Box::new(boot_services()));
+        let queue: &'static WorkQueue = Box::leak(Box::new(WorkQueue::new(boot_services).unwrap()));
+        let runs: &'static Cell<usize> = Box::leak(Box::new(Cell::new(0)));
+        queue
+            .push(move || {
+                runs.set(runs.get() + 1);
+                queue.push(move || runs.set(runs.get() + 1)).unwrap();
+            })
+            .unwrap();
+        drain();
+        assert_eq!(runs.get(), 2);
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,18 @@
+//! Synchronization primitives for UEFI.
+//!
+//! UEFI has no threads, but event notification functions preempt the code running at a lower TPL. The primitives in
+//! this crate are safe to use across those TPL boundaries.
+//!
+#![cfg_attr(not(test), no_std)]
+
+pub mod spsc;
+#[cfg(all(test, any(feature = "critical_section", feature = "lock_api")))]
+pub(crate) mod test_support;
+#[cfg(any(feature = "critical_section", feature = "lock_api"))]
+pub mod tpl;
+#[cfg(feature = "critical_section")]
+pub mod tpl_critical_section;
+#[cfg(feature = "lock_api")]
+pub mod tpl_lock;
+
+pub use spsc::SpscQueue;
@@ -0,0 +1,229 @@
+//! Bounded single-producer/single-consumer queue.
+//!
+//! The queue is lock-free: pushing and popping never raise the TPL or spin, so the [`Producer`] can be used from an
+//! event notification function running at a high TPL while the [`Consumer`] drains messages from the main loop.
+//!
+use core::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Bounded single-producer/single-consumer queue holding up to `N` elements.
+///
+/// # Example
+/// ```
+/// use sync::SpscQueue;
+///
+/// let mut queue = SpscQueue::<u32, 4>::new();
+/// let (mut producer, mut consumer) = queue.split();
+///
+/// // In an event notification function:
+/// producer.push(42).unwrap();
+///
+/// // In the main loop:
+/// assert_eq!(consumer.pop(), Some(42));
+/// assert_eq!(consumer.pop(), None);
+/// ```
+pub struct SpscQueue<T, const N: usize> {
+    buffer: [UnsafeCell<MaybeUninit<T>>; N],
+    // Total number of elements popped. Only written by the consumer.
+    head: AtomicUsize,
+    // Total number of elements pushed. Only written by the producer.
+    tail: AtomicUsize,
+}
+
+// SAFETY: the producer and consumer never access the same slot at the same time, and elements are moved between them.
+unsafe impl<T: Send, const N: usize> Sync for SpscQueue<T, N> {}
+
+impl<T, const N: usize> SpscQueue<T, N> {
+    /// Create a new, empty queue.
+    pub const fn new() -> Self {
+        Self {
+            buffer: [const { UnsafeCell::new(MaybeUninit::uninit()) }; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Split the queue into its producer and consumer halves.
+    pub fn split(&mut self) -> (Producer<'_, T, N>, Consumer<'_, T, N>) {
+        (Producer { queue: self }, Consumer { queue: self })
+    }
+
+    /// Return the number of elements in the queue.
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        self.tail.load(Ordering::Acquire).wrapping_sub(head)
+    }
+
+    /// Return true if the queue holds no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return true if the queue cannot accept more elements.
+    pub fn is_full(&self) -> bool {
+        self.len() == N
+    }
+
+    /// Return the maximum number of elements the queue can hold.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    fn slot(&self, index: usize) -> *mut MaybeUninit<T> {
+        self.buffer[index % N].get()
+    }
+}
+
+impl<T, const N: usize> Default for SpscQueue<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for SpscQueue<T, N> {
+    fn drop(&mut self) {
+        let tail = *self.tail.get_mut();
+        let mut head = *self.head.get_mut();
+        while head != tail {
+            // SAFETY: every slot between head and tail holds an element that was pushed but not popped.
+            unsafe { (*self.slot(head)).assume_init_drop() };
+            head = head.wrapping_add(1);
+        }
+    }
+}
+
+/// Producer half of a [`SpscQueue`].
+pub struct Producer<'a, T, const N: usize> {
+    queue: &'a SpscQueue<T, N>,
+}
+
+impl<T, const N: usize> Producer<'_, T, N> {
+    /// Push `value` to the back of the queue.
+    ///
+    /// Returns `value` back if the queue is full.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        let tail = self.queue.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(self.queue.head.load(Ordering::Acquire)) == N {
+            return Err(value);
+        }
+        // SAFETY: the slot is not visible to the consumer until `tail` is published below.
+        unsafe { (*self.queue.slot(tail)).write(value) };
+        self.queue.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Return true if the queue cannot accept more elements.
+    pub fn is_full(&self) -> bool {
+        self.queue.is_full()
+    }
+}
+
+/// Consumer half of a [`SpscQueue`].
+pub struct Consumer<'a, T, const N: usize> {
+    queue: &'a SpscQueue<T, N>,
+}
+
+impl<T, const N: usize> Consumer<'_, T, N> {
+    /// Pop the element at the front of the queue, if any.
+    pub fn pop(&mut self) -> Option<T> {
+        let head = self.queue.head.load(Ordering::Relaxed);
+        if head == self.queue.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        // SAFETY: the producer published this slot and will not reuse it until `head` is advanced below.
+        let value = unsafe { (*self.queue.slot(head)).assume_init_read() };
+        self.queue.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(value)
+    }
+
+    /// Return the number of elements waiting in the queue.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Return true if no elements are waiting in the queue.
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+impl<T, const N: usize> Iterator for Consumer<'_, T, N> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.pop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{rc::Rc, thread};
+
+    #[test]
+    fn test_push_pop() {
+        let mut queue = SpscQueue::<u32, 3>::new();
+        let (mut producer, mut consumer) = queue.split();
+
+        assert_eq!(consumer.pop(), None);
+        assert_eq!(producer.push(1), Ok(()));
+        assert_eq!(producer.push(2), Ok(()));
+        assert_eq!(producer.push(3), Ok(()));
+        assert!(producer.is_full());
+        assert_eq!(producer.push(4), Err(4));
+        assert_eq!(consumer.len(), 3);
+
+        assert_eq!(consumer.pop(), Some(1));
+        assert_eq!(producer.push(4), Ok(()));
+        assert_eq!(consumer.by_ref().collect::<Vec<_>>(), vec![2, 3, 4]);
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn test_drop_remaining_elements() {
+        let element = Rc::new(());
+        {
+            let mut queue = SpscQueue::<Rc<()>, 4>::new();
+            let (mut producer, mut consumer) = queue.split();
+            for _ in 0..3 {
+                producer.push(element.clone()).unwrap();
+            }
+            drop(consumer.pop());
+            assert_eq!(Rc::strong_count(&element), 3);
+        }
+        assert_eq!(Rc::strong_count(&element), 1);
+    }
+
+    #[test]
+    fn test_concurrent_producer_and_consumer() {
+        const COUNT: usize = 10_000;
+        let mut queue = SpscQueue::<usize, 8>::new();
+        let (mut producer, mut consumer) = queue.split();
+
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                for mut value in 0..COUNT {
+                    while let Err(rejected) = producer.push(value) {
+                        value = rejected;
+                        thread::yield_now();
+                    }
+                }
+            });
+
+            let mut expected = 0;
+            while expected < COUNT {
+                match consumer.pop() {
+                    Some(value) => {
+                        assert_eq!(value, expected);
+                        expected += 1;
+                    }
+                    None => thread::yield_now(),
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,99 @@
+//! Mock boot services shared by the tests of the TPL-based primitives.
+//!
+use std::cell::Cell;
+
+use r_efi::efi;
+
+use crate::tpl;
+
+std::thread_local! {
+    static CURRENT_TPL: Cell<efi::Tpl> = const { Cell::new(efi::TPL_APPLICATION) };
+}
+
+extern "efiapi" fn raise_tpl(new_tpl: efi::Tpl) -> efi::Tpl {
+    let old_tpl = CURRENT_TPL.with(|tpl| tpl.replace(new_tpl));
+    assert!(new_tpl >= old_tpl, "TPL lowered by raise_tpl.");
+    old_tpl
+}
+
+extern "efiapi" fn restore_tpl(old_tpl: efi::Tpl) {
+    CURRENT_TPL.with(|tpl| {
+        assert!(old_tpl <= tpl.get(), "TPL raised by restore_tpl.");
+        tpl.set(old_tpl);
+    });
+}
+
+/// Return the TPL of the calling test, which starts at `TPL_APPLICATION`.
+pub(crate) fn current_tpl() -> efi::Tpl {
+    CURRENT_TPL.with(|tpl| tpl.get())
+}
+
+extern "efiapi" fn unimplemented_stub() -> efi::Status {
+    efi::Status::UNSUPPORTED
+}
+
+/// Return a pointer to a stub returning `efi::Status::UNSUPPORTED`, for services the tests do not use.
+fn unimplemented_service<F: Copy>() -> F {
+    let stub: extern "efiapi" fn() -> efi::Status = unimplemented_stub;
+    // SAFETY: every boot service is an `efiapi` function pointer returning a pointer-sized value or nothing, and the
+    // stub ignores its arguments.
+    unsafe { core::mem::transmute_copy(&stub) }
+}
+
+/// Register boot services whose TPL services track the TPL of the calling test, see [`current_tpl`].
+pub(crate) fn init() {
+    let boot_services = efi::BootServices {
+        hdr: efi::TableHeader {
+            signature: efi::BOOT_SERVICES_SIGNATURE,
+            revision: efi::BOOT_SERVICES_REVISION,
+            header_size: core::mem::size_of::<efi::BootServices>() as u32,
+            crc32: 0,
+            reserved: 0,
+        },
+        raise_tpl,
+        restore_tpl,
+        allocate_pages: unimplemented_service(),
+        free_pages: unimplemented_service(),
+        get_memory_map: unimplemented_service(),
+        allocate_pool: unimplemented_service(),
+        free_pool: unimplemented_service(),
+        create_event: unimplemented_service(),
+        set_timer: unimplemented_service(),
+        wait_for_event: unimplemented_service(),
+        signal_event: unimplemented_service(),
+        close_event: unimplemented_service(),
+        check_event: unimplemented_service(),
+        install_protocol_interface: unimplemented_service(),
+        reinstall_protocol_interface: unimplemented_service(),
+        uninstall_protocol_interface: unimplemented_service(),
+        handle_protocol: unimplemented_service(),
+        reserved: core::ptr::null_mut(),
+        register_protocol_notify: unimplemented_service(),
+        locate_handle: unimplemented_service(),
+        locate_device_path: unimplemented_service(),
+        install_configuration_table: unimplemented_service(),
+        load_image: unimplemented_service(),
+        start_image: unimplemented_service(),
+        exit: unimplemented_service(),
+        unload_image: unimplemented_service(),
+        exit_boot_services: unimplemented_service(),
+        get_next_monotonic_count: unimplemented_service(),
+        stall: unimplemented_service(),
+        set_watchdog_timer: unimplemented_service(),
+        connect_controller: unimplemented_service(),
+        disconnect_controller: unimplemented_service(),
+        open_protocol: unimplemented_service(),
+        close_protocol: unimplemented_service(),
+        open_protocol_information: unimplemented_service(),
+        protocols_per_handle: unimplemented_service(),
+        locate_handle_buffer: unimplemented_service(),
+        locate_protocol: unimplemented_service(),
+        install_multiple_protocol_interfaces: unimplemented_service(),
+        uninstall_multiple_protocol_interfaces: unimplemented_service(),
+        calculate_crc32: unimplemented_service(),
+        copy_mem: unimplemented_service(),
+        set_mem: unimplemented_service(),
+        create_event_ex: unimplemented_service(),
+    };
+    tpl::init(Box::leak(Box::new(boot_services)));
+}
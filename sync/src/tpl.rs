@@ -0,0 +1,46 @@
+//! Access to the TPL services shared by the TPL-based primitives.
+//!
+use core::{
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use r_efi::efi;
+
+static BOOT_SERVICES: AtomicPtr<efi::BootServices> = AtomicPtr::new(ptr::null_mut());
+
+/// Register the boot services table used to raise and restore the TPL.
+///
+/// Must be called from the image entry point before any TPL-based primitive is used. Those primitives must not be
+/// used after ExitBootServices, since the TPL services are no longer available then.
+///
+/// # Example
+/// ```no_run
+/// use r_efi::efi;
+///
+/// pub extern "efiapi" fn efi_main(_image_handle: efi::Handle, system_table: *const efi::SystemTable) -> efi::Status {
+///     sync::tpl::init(unsafe { &*(*system_table).boot_services });
+///     efi::Status::SUCCESS
+/// }
+/// ```
+pub fn init(boot_services: &'static efi::BootServices) {
+    BOOT_SERVICES.store(boot_services as *const efi::BootServices as *mut efi::BootServices, Ordering::SeqCst);
+}
+
+/// Return true if [`init`] has been called.
+pub fn is_init() -> bool {
+    !BOOT_SERVICES.load(Ordering::SeqCst).is_null()
+}
+
+pub(crate) fn raise_tpl(tpl: efi::Tpl) -> efi::Tpl {
+    (boot_services().raise_tpl)(tpl)
+}
+
+pub(crate) fn restore_tpl(tpl: efi::Tpl) {
+    (boot_services().restore_tpl)(tpl)
+}
+
+fn boot_services() -> &'static efi::BootServices {
+    // SAFETY: a non-null pointer always originates from the `&'static` reference given to `init`.
+    unsafe { BOOT_SERVICES.load(Ordering::SeqCst).as_ref() }.expect("TPL services used before init.")
+}
@@ -0,0 +1,100 @@
+//! [`critical-section`](https://docs.rs/critical-section) implementation for UEFI.
+//!
+//! Code running at a lower TPL can only be preempted by event notification functions running at a higher TPL, so
+//! raising the TPL to `TPL_HIGH_LEVEL` is enough to make a section of code atomic. This lets ecosystem crates that rely
+//! on `critical-section` (e.g. `heapless`) run inside UEFI without spinlocks.
+//!
+//! Enabling the `critical_section` feature registers this implementation for the whole binary, so it must not be
+//! combined with another `critical-section` implementation.
+//!
+use critical_section::RawRestoreState;
+use r_efi::efi;
+
+use crate::tpl;
+
+/// Register the boot services table used to raise and restore the TPL in critical sections.
+///
+/// Equivalent to [`tpl::init`]. Critical sections must not be entered before this is called, nor after
+/// ExitBootServices.
+///
+/// # Example
+/// ```no_run
+/// use r_efi::efi;
+///
+/// pub extern "efiapi" fn efi_main(_image_handle: efi::Handle, system_table: *const efi::SystemTable) -> efi::Status {
+///     sync::tpl_critical_section::init(unsafe { &*(*system_table).boot_services });
+///     critical_section::with(|_| {
+///         // Runs at TPL_HIGH_LEVEL.
+///     });
+///     efi::Status::SUCCESS
+/// }
+/// ```
+pub fn init(boot_services: &'static efi::BootServices) {
+    tpl::init(boot_services);
+}
+
+struct TplCriticalSection;
+
+critical_section::set_impl!(TplCriticalSection);
+
+// SAFETY: raising the TPL to TPL_HIGH_LEVEL masks every event notification, which is the only source of preemption.
+// Nested critical sections raise to the same level and restore the level they observed, so nesting is sound.
+unsafe impl critical_section::Impl for TplCriticalSection {
+    unsafe fn acquire() -> RawRestoreState {
+        tpl::raise_tpl(efi::TPL_HIGH_LEVEL)
+    }
+
+    unsafe fn release(restore_state: RawRestoreState) {
+        tpl::restore_tpl(restore_state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::{current_tpl, init};
+
+    #[test]
+    fn test_with() {
+        init();
+        let value = critical_section::with(|_| {
+            assert_eq!(current_tpl(), efi::TPL_HIGH_LEVEL);
+            7
+        });
+        assert_eq!(value, 7);
+        assert_eq!(current_tpl(), efi::TPL_APPLICATION);
+    }
+
+    #[test]
+    fn test_nested_sections() {
+        init();
+        critical_section::with(|_| {
+            critical_section::with(|_| assert_eq!(current_tpl(), efi::TPL_HIGH_LEVEL));
+            // Leaving the inner section keeps the TPL it observed on entry.
+            assert_eq!(current_tpl(), efi::TPL_HIGH_LEVEL);
+        });
+        assert_eq!(current_tpl(), efi::TPL_APPLICATION);
+
+        // SAFETY: the sections are released in reverse order of acquisition.
+        unsafe {
+            let outer = critical_section::acquire();
+            let inner = critical_section::acquire();
+            critical_section::release(inner);
+            assert_eq!(current_tpl(), efi::TPL_HIGH_LEVEL);
+            critical_section::release(outer);
+        }
+        assert_eq!(current_tpl(), efi::TPL_APPLICATION);
+    }
+
+    #[test]
+    fn test_restores_entry_tpl() {
+        init();
+        // Entered from an event notification function.
+        let old_tpl = tpl::raise_tpl(efi::TPL_CALLBACK);
+        critical_section::with(|_| assert_eq!(current_tpl(), efi::TPL_HIGH_LEVEL));
+        assert_eq!(current_tpl(), efi::TPL_CALLBACK);
+        tpl::restore_tpl(old_tpl);
+        assert_eq!(current_tpl(), efi::TPL_APPLICATION);
+    }
+}
@@ -0,0 +1,219 @@
+//! [`lock_api`](https://docs.rs/lock_api) raw locks based on the TPL.
+//!
+//! [`RawTplMutex`] and [`RawTplRwLock`] raise the TPL to `TPL_NOTIFY` while they are held, so event notification
+//! functions at or below that level cannot preempt the owner. This lets `lock_api::Mutex`, `lock_api::RwLock` and the
+//! ecosystem types built on them be used in UEFI with TPL-based locking.
+//!
+//! A lock held by interrupted code can never be released while the TPL is raised, so contention is a deadlock.
+//! Blocking acquisitions panic instead of spinning forever; `try_lock` reports the contention. Locks must not be
+//! acquired above `TPL_NOTIFY`, nor after ExitBootServices. [`tpl::init`](crate::tpl::init) must be called first.
+//!
+//! # Example
+//! ```no_run
+//! use sync::tpl_lock::TplMutex;
+//!
+//! static COUNTER: TplMutex<u32> = TplMutex::new(0);
+//!
+//! fn increment() -> u32 {
+//!     let mut counter = COUNTER.lock();
+//!     *counter += 1;
+//!     *counter
+//! }
+//! ```
+//!
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use lock_api::{GuardNoSend, RawMutex, RawRwLock};
+use r_efi::efi;
+
+use crate::tpl;
+
+const TPL_LOCK: efi::Tpl = efi::TPL_NOTIFY;
+const EXCLUSIVE: usize = usize::MAX;
+
+/// Lock state shared by the raw mutex and read-write lock: 0 when free, [`EXCLUSIVE`] when held exclusively, and the
+/// number of readers otherwise.
+struct TplLockState {
+    state: AtomicUsize,
+    previous_tpl: AtomicUsize,
+}
+
+impl TplLockState {
+    const fn new() -> Self {
+        Self { state: AtomicUsize::new(0), previous_tpl: AtomicUsize::new(0) }
+    }
+
+    fn try_lock_exclusive(&self) -> bool {
+        let previous_tpl = tpl::raise_tpl(TPL_LOCK);
+        if self.state.compare_exchange(0, EXCLUSIVE, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+            tpl::restore_tpl(previous_tpl);
+            return false;
+        }
+        self.previous_tpl.store(previous_tpl, Ordering::SeqCst);
+        true
+    }
+
+    fn try_lock_shared(&self) -> bool {
+        let previous_tpl = tpl::raise_tpl(TPL_LOCK);
+        let readers = self.state.load(Ordering::SeqCst);
+        if readers == EXCLUSIVE || readers == EXCLUSIVE - 1 {
+            tpl::restore_tpl(previous_tpl);
+            return false;
+        }
+        // The TPL is raised, so nothing can change the state between the load and the store.
+        self.state.store(readers + 1, Ordering::SeqCst);
+        // Later readers run at TPL_LOCK already. Only the first one observed the TPL to restore on the last unlock.
+        if readers == 0 {
+            self.previous_tpl.store(previous_tpl, Ordering::SeqCst);
+        }
+        true
+    }
+
+    fn unlock_exclusive(&self) {
+        let previous_tpl = self.previous_tpl.load(Ordering::SeqCst);
+        self.state.store(0, Ordering::SeqCst);
+        tpl::restore_tpl(previous_tpl);
+    }
+
+    fn unlock_shared(&self) {
+        let previous_tpl = self.previous_tpl.load(Ordering::SeqCst);
+        if self.state.fetch_sub(1, Ordering::SeqCst) == 1 {
+            tpl::restore_tpl(previous_tpl);
+        }
+    }
+
+    fn is_locked(&self) -> bool {
+        self.state.load(Ordering::SeqCst) != 0
+    }
+}
+
+/// Raw mutex raising the TPL to `TPL_NOTIFY` while held.
+pub struct RawTplMutex(TplLockState);
+
+// SAFETY: the lock state guarantees exclusive ownership, and the guard cannot be sent since the TPL must be restored
+// by the code that raised it.
+unsafe impl RawMutex for RawTplMutex {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = Self(TplLockState::new());
+
+    type GuardMarker = GuardNoSend;
+
+    fn lock(&self) {
+        assert!(self.try_lock(), "TPL mutex is already locked.");
+    }
+
+    fn try_lock(&self) -> bool {
+        self.0.try_lock_exclusive()
+    }
+
+    unsafe fn unlock(&self) {
+        self.0.unlock_exclusive()
+    }
+
+    fn is_locked(&self) -> bool {
+        self.0.is_locked()
+    }
+}
+
+/// Raw read-write lock raising the TPL to `TPL_NOTIFY` while held.
+pub struct RawTplRwLock(TplLockState);
+
+// SAFETY: see `RawTplMutex`. Shared holders only ever coexist with other shared holders.
+unsafe impl RawRwLock for RawTplRwLock {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = Self(TplLockState::new());
+
+    type GuardMarker = GuardNoSend;
+
+    fn lock_shared(&self) {
+        assert!(self.try_lock_shared(), "TPL read-write lock is already locked exclusively.");
+    }
+
+    fn try_lock_shared(&self) -> bool {
+        self.0.try_lock_shared()
+    }
+
+    unsafe fn unlock_shared(&self) {
+        self.0.unlock_shared()
+    }
+
+    fn lock_exclusive(&self) {
+        assert!(self.try_lock_exclusive(), "TPL read-write lock is already locked.");
+    }
+
+    fn try_lock_exclusive(&self) -> bool {
+        self.0.try_lock_exclusive()
+    }
+
+    unsafe fn unlock_exclusive(&self) {
+        self.0.unlock_exclusive()
+    }
+
+    fn is_locked(&self) -> bool {
+        self.0.is_locked()
+    }
+}
+
+/// Mutex raising the TPL to `TPL_NOTIFY` while locked.
+pub type TplMutex<T> = lock_api::Mutex<RawTplMutex, T>;
+/// Guard of a [`TplMutex`].
+pub type TplMutexGuard<'a, T> = lock_api::MutexGuard<'a, RawTplMutex, T>;
+/// Read-write lock raising the TPL to `TPL_NOTIFY` while locked.
+pub type TplRwLock<T> = lock_api::RwLock<RawTplRwLock, T>;
+/// Shared guard of a [`TplRwLock`].
+pub type TplRwLockReadGuard<'a, T> = lock_api::RwLockReadGuard<'a, RawTplRwLock, T>;
+/// Exclusive guard of a [`TplRwLock`].
+pub type TplRwLockWriteGuard<'a, T> = lock_api::RwLockWriteGuard<'a, RawTplRwLock, T>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::{current_tpl, init};
+
+    #[test]
+    fn test_mutex() {
+        init();
+        let mutex = TplMutex::new(1);
+        {
+            let mut guard = mutex.lock();
+            assert_eq!(current_tpl(), efi::TPL_NOTIFY);
+            assert!(mutex.try_lock().is_none());
+            assert_eq!(current_tpl(), efi::TPL_NOTIFY);
+            *guard += 1;
+        }
+        assert_eq!(current_tpl(), efi::TPL_APPLICATION);
+        assert_eq!(*mutex.lock(), 2);
+    }
+
+    #[test]
+    fn test_rw_lock() {
+        init();
+        let lock = TplRwLock::new(5);
+        let first = lock.read();
+        let second = lock.read();
+        assert_eq!(current_tpl(), efi::TPL_NOTIFY);
+        assert!(lock.try_write().is_none());
+        // Guards may be released in any order; the TPL is restored with the last one.
+        drop(first);
+        assert_eq!(current_tpl(), efi::TPL_NOTIFY);
+        drop(second);
+        assert_eq!(current_tpl(), efi::TPL_APPLICATION);
+
+        *lock.write() += 1;
+        assert_eq!(current_tpl(), efi::TPL_APPLICATION);
+        let writer = lock.write();
+        assert!(lock.try_read().is_none());
+        drop(writer);
+        assert_eq!(*lock.read(), 6);
+    }
+
+    #[test]
+    #[should_panic = "TPL mutex is already locked."]
+    fn test_reentrant_lock_panics() {
+        init();
+        let mutex = TplMutex::new(());
+        let _guard = mutex.lock();
+        let _second = mutex.lock();
+    }
+}